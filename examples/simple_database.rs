@@ -87,9 +87,10 @@ impl UserDatabase {
             reserved_space: 0,
             max_payload_fraction: 255,
             min_payload_fraction: 32,
+            ..Default::default()
         };
 
-        let mut db = RQLite::create(db_path, Some(config))?;
+        let db = RQLite::create(db_path, Some(config))?;
 
         // Create the users table
         let users_table = db.create_table()?;
@@ -130,7 +131,7 @@ impl UserDatabase {
     }
 
     /// Insert a new user
-    fn insert_user(&mut self, user: &User) -> io::Result<()> {
+    fn insert_user(&self, user: &User) -> io::Result<()> {
         println!("Inserting user: {} ({})", user.name, user.email);
         
         // Start a transaction for consistency
@@ -221,7 +222,7 @@ impl UserDatabase {
     }
 
     /// Delete a user
-    fn delete_user(&mut self, user_id: i64) -> io::Result<bool> {
+    fn delete_user(&self, user_id: i64) -> io::Result<bool> {
         println!("Deleting user with ID: {}", user_id);
         
         // First, get the user to remove from indexes
@@ -303,7 +304,7 @@ impl UserDatabase {
     let db_path = "example.db";
 
     // Create a new database (or open existing one)
-    let mut db = if std::path::Path::new(db_path).exists() {
+    let db = if std::path::Path::new(db_path).exists() {
         UserDatabase::open(db_path)?
     } else {
         UserDatabase::create(db_path)?
@@ -39,6 +39,10 @@ use std::io::{self, Read, Write};
 pub const HEADER_SIZE: usize = 100;
 /// Magic string that identifies the RQLite file format.
 pub const SQLITE_HEADER_STRING: &[u8; 16] = b"SQLite format 3\0";
+/// Offset in the reserved expansion area of the byte with the RQLite flags.
+const FLAGS_OFFSET: usize = 4;
+/// Flag of the databases whose pages carry a checksum.
+const FLAG_CHECKSUMS: u8 = 0x01;
 
 /// Represents the SQLite database header.
 #[derive(Debug, Clone)]
@@ -291,6 +295,49 @@ impl Header {
     pub fn increment_change_counter(&mut self) {
         self.change_counter = self.change_counter.wrapping_add(1);
     }
+
+    /// Increments the schema cookie.
+    /// Every commit that changes the schema table bumps it, so other connections know to reload their catalog.
+    pub fn increment_schema_cookie(&mut self) {
+        self.schema_cookie = self.schema_cookie.wrapping_add(1);
+    }
+
+    /// Gets the root page of the schema table, where RQLite keeps its catalog, or 0 if there is none yet.
+    /// SQLite always has it at page 1, we keep it in the first four bytes of the reserved expansion area instead.
+    pub fn schema_root(&self) -> u32 {
+        u32::from_be_bytes([
+            self.reserved[0],
+            self.reserved[1],
+            self.reserved[2],
+            self.reserved[3],
+        ])
+    }
+
+    /// Sets the root page of the schema table.
+    ///
+    /// Parameters:
+    /// * `root_page` - Number of the root page.
+    pub fn set_schema_root(&mut self, root_page: u32) {
+        self.reserved[..4].copy_from_slice(&root_page.to_be_bytes());
+    }
+
+    /// Checks if the pages carry a checksum at the end of their reserved space (see `utils::checksum`).
+    /// Like the schema root, the flag lives in the reserved expansion area, in the byte right after it.
+    pub fn has_checksums(&self) -> bool {
+        self.reserved[FLAGS_OFFSET] & FLAG_CHECKSUMS != 0
+    }
+
+    /// Sets or clears the flag of the page checksums.
+    ///
+    /// Parameters:
+    /// * `enabled` - Whether the pages carry a checksum.
+    pub fn set_checksums(&mut self, enabled: bool) {
+        if enabled {
+            self.reserved[FLAGS_OFFSET] |= FLAG_CHECKSUMS;
+        } else {
+            self.reserved[FLAGS_OFFSET] &= !FLAG_CHECKSUMS;
+        }
+    }
 }
 
 // Implementation of Display trait for Header
@@ -404,6 +451,36 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_schema_root() {
+        let mut header = Header::default();
+        assert_eq!(header.schema_root(), 0);
+        header.set_schema_root(0x0102_0304);
+
+        let mut buffer = Vec::new();
+        header.write_to(&mut buffer).unwrap();
+        let read_header = Header::read_from(&mut Cursor::new(buffer)).unwrap();
+        assert_eq!(read_header.schema_root(), 0x0102_0304);
+    }
+
+    #[test]
+    fn test_checksums_flag() {
+        let mut header = Header::default();
+        assert!(!header.has_checksums());
+        header.set_schema_root(u32::MAX);
+        header.set_checksums(true);
+
+        let mut buffer = Vec::new();
+        header.write_to(&mut buffer).unwrap();
+        let mut read_header = Header::read_from(&mut Cursor::new(buffer)).unwrap();
+        assert!(read_header.has_checksums());
+        assert_eq!(read_header.schema_root(), u32::MAX);
+
+        read_header.set_checksums(false);
+        assert!(!read_header.has_checksums());
+        assert_eq!(read_header.schema_root(), u32::MAX);
+    }
+
     #[test]
     fn test_is_valid_page_size() {
         // Valid sizes
@@ -17,11 +17,13 @@
 //! ## Example Usage
 //!
 //! ```rust
+//! # fn main() -> std::io::Result<()> {
+//! # let dir = tempfile::tempdir()?;
 //! use rqlite_engine::{RQLite, Record};
 //! use rqlite_engine::utils::serialization::SqliteValue;
 //!
 //! // Create a new database
-//! let mut db = RQLite::create("test.db", None)?;
+//! let db = RQLite::create(dir.path().join("test.db"), None)?;
 //!
 //! // Create a table
 //! let table_id = db.create_table()?;
@@ -36,22 +38,42 @@
 //! // Find the record
 //! let found = db.table_find(table_id, 1)?;
 //! assert!(found.is_some());
+//! # Ok(())
+//! # }
 //! ```
 
+use std::cell::Cell;
 use std::collections::HashMap;
 use std::io;
 use std::path::Path;
-use std::sync::Arc;
+use std::sync::{Arc, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::time::Duration;
 
 pub mod header;
 pub mod page;
+pub mod schema;
+pub mod sql;
 pub mod storage;
+pub mod transaction;
 pub mod tree;
 pub mod utils;
 
+use header::Header;
+use storage::lock::{busy_error, timeout_handler, LockLevel};
+use sql::ast::Statement;
 use storage::pager::Pager;
+pub use storage::pager::{BackgroundWriter, Synchronous};
+pub use storage::vfs::{MemoryVfs, OsVfs, Vfs};
 use tree::btree::{BTree, TreeType};
-pub use tree::record::Record;
+use tree::bulk::BulkLoader;
+use tree::sequence::SequenceRange;
+use tree::stats::{load_stats, save_stats};
+pub use schema::{Column, IndexSchema, TableSchema};
+pub use sql::{sql_error, Rows, SqlError};
+pub use transaction::Transaction;
+pub use tree::record::{Record, RecordRef, ValueRef};
+pub use tree::sorter::{ExternalSorter, SortOrder};
+pub use tree::stats::{KeySample, TreeStats};
 pub use utils::cmp::KeyValue;
 
 /// Configuration options for the RQLite storage engine.
@@ -63,10 +85,42 @@ pub struct RQLiteConfig {
     pub buffer_pool_size: usize,
     /// Reserved space at the end of each page.
     pub reserved_space: u8,
+    /// Stores a checksum of every page in the last four bytes of its reserved space, and checks it on every read,
+    /// so corruption comes back as an error instead of garbage rows (see `utils::checksum`).
+    /// Needs at least four bytes of `reserved_space`. It is recorded in the header when the database is created,
+    /// and ignored when opening one.
+    pub checksums: bool,
     /// Maximum fraction of a page that can be occupied by a single payload.
     pub max_payload_fraction: u8,
     /// Minimum fraction of a page that must be occupied by a payload when splitting.
     pub min_payload_fraction: u8,
+    /// How long to keep retrying when another process holds a conflicting lock on the file.
+    /// Zero means failing right away with a `Busy` error (see `storage::lock::is_busy`).
+    pub busy_timeout: Duration,
+    /// When to sync the database file, like `PRAGMA synchronous` in SQLite.
+    pub synchronous: Synchronous,
+    /// Lets concurrent commits share a single sync instead of doing one each.
+    /// Every commit is still durable when it returns, but writers no longer wait for each other's syncs.
+    pub group_commit: bool,
+    /// Most bytes of the database file to memory map for reading, like `PRAGMA mmap_size` in SQLite.
+    /// Pages that miss the cache are copied out of the map instead of read with a syscall each.
+    /// Zero, the default, reads everything through the file.
+    pub mmap_size: u64,
+    /// Most pages to read ahead in one go when reads look like a sequential scan (see `Pager::set_read_ahead`).
+    /// The prefetched pages and how many of them were used show up in `Pager::cache_stats`.
+    /// Zero, the default, disables read-ahead.
+    pub read_ahead: u32,
+    /// Runs a thread that writes dirty pages back ahead of time during big transactions,
+    /// so readers rarely have to write a page to get a frame (see `Pager::set_background_writer`).
+    /// `None`, the default, leaves every write to the commit and the evictions.
+    pub background_writer: Option<BackgroundWriter>,
+    /// How full `bulk_load` and `bulk_load_index` make the pages they build, in percent (10 to 100).
+    pub bulk_fill_factor: u8,
+    /// Most bytes of rows kept in memory while sorting them for a bulk load or a `sorter`.
+    /// Past that, sorted runs go to temporary files.
+    pub sort_memory: usize,
+    /// Most sample keys `analyze` keeps in the histogram of each index, like `SQLITE_STAT4_SAMPLES` (up to 255).
+    pub stat_samples: usize,
 }
 
 impl Default for RQLiteConfig {
@@ -75,8 +129,18 @@ impl Default for RQLiteConfig {
             page_size: 4096,
             buffer_pool_size: 1000,
             reserved_space: 0,
-            max_payload_fraction: 255, // 100%
-            min_payload_fraction: 32,  // ~12.5%
+            checksums: false,
+            max_payload_fraction: tree::cell::DEFAULT_MAX_PAYLOAD_FRACTION, // 100%
+            min_payload_fraction: tree::cell::DEFAULT_MIN_PAYLOAD_FRACTION, // ~12.5%
+            busy_timeout: Duration::ZERO,
+            synchronous: Synchronous::Full,
+            group_commit: false,
+            mmap_size: 0,
+            read_ahead: 0,
+            background_writer: None,
+            bulk_fill_factor: tree::bulk::DEFAULT_FILL_FACTOR,
+            sort_memory: tree::sorter::DEFAULT_SORT_MEMORY,
+            stat_samples: tree::stats::DEFAULT_SAMPLES,
         }
     }
 }
@@ -87,458 +151,2334 @@ pub type TableId = u32;
 /// Unique identifier for an index in the database.
 pub type IndexId = u32;
 
+/// Savepoint around each SQL statement that writes (and each bulk load), so a statement that fails undoes its own
+/// changes (see `RQLite::statement`). The name cannot be written in SQL, so it never meets a savepoint of the user.
+const STATEMENT_SAVEPOINT: &str = "\0statement";
+
+thread_local! {
+    /// Set while a `Transaction` guard runs one of its writes on this thread (see `RQLite::guarded`).
+    static GUARDED: Cell<bool> = const { Cell::new(false) };
+}
+
 /// Main entry point for the RQLite storage engine.
 ///
 /// The `RQLite` struct provides a high-level interface for database operations
 /// including table and index management, record insertion/retrieval, and
 /// transaction support.
+///
+/// `RQLite` is a cheap, cloneable handle: every clone talks to the same database
+/// and the handle is `Send + Sync`, so it can be moved into threads or wrapped in an `Arc`.
+/// Writers take the catalog lock exclusively, so the engine serializes them for you.
+/// Readers of other threads never wait for a transaction: they read a snapshot of the last commit
+/// (see `Pager::snapshot`), so they neither block the writer nor see its uncommitted changes.
+///
+/// # Example
+/// ```rust
+/// # fn main() -> std::io::Result<()> {
+/// # let dir = tempfile::tempdir()?;
+/// use rqlite_engine::{RQLite, Record, SqliteValue};
+/// use std::thread;
+///
+/// let db = RQLite::create(dir.path().join("shared.db"), None)?;
+/// let table_id = db.create_table()?;
+///
+/// let workers: Vec<_> = (0..4)
+///     .map(|worker| {
+///         let db = db.clone();
+///         thread::spawn(move || {
+///             let record = Record::with_values(vec![SqliteValue::Integer(worker)]);
+///             db.table_insert(table_id, worker + 1, &record)
+///         })
+///     })
+///     .collect();
+///
+/// for worker in workers {
+///     worker.join().unwrap()?;
+/// }
+/// assert!(db.table_find(table_id, 4)?.is_some());
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone)]
 pub struct RQLite {
+    /// State shared by every handle to the same database.
+    shared: Arc<Shared>,
+}
+
+/// State shared by all the handles of a database.
+struct Shared {
     /// The pager manages page-level operations and caching.
     pager: Arc<Pager>,
+    /// Tables and indexes of the database, behind a readers-writer lock.
+    catalog: RwLock<Catalog>,
+    /// Configuration options for this database instance.
+    config: RQLiteConfig,
+}
+
+/// Catalog of the B-Trees of a database.
+/// It is kept in memory, and every commit writes the trees it created or moved to the schema table,
+/// a table B-Tree whose root is in the database header (see `Header::schema_root`). Its rows are:
+/// - rowid -1: `["sequence", 0, root page]`, the sequence table with the sequences of `create_sequence`
+/// - rowid 0: `["stat", 0, root page]`, the stat table where `analyze` saves its statistics
+/// - rowid 1: `["counters", next table id, next index id]`
+/// - rowid `2 * id`: `["table", id, root page]`, plus the serialized `TableSchema` if the table has columns
+///   (NULL if it only has a name) and the name of the table if it has one
+/// - rowid `2 * id + 1`: `["index", id, root page]`, plus the serialized `IndexSchema` if it was created with SQL
+///
+/// Dropping a table or an index deletes its row.
+/// Every commit that writes to the schema table bumps `Header::schema_cookie`. Another connection that finds
+/// a different cookie when it begins a transaction reloads its catalog (see `refresh`).
+struct Catalog {
     /// Maps table IDs to their corresponding B-Trees.
     tables: HashMap<TableId, BTree>,
     /// Maps index IDs to their corresponding B-Trees.
     indexes: HashMap<IndexId, BTree>,
-    /// Configuration options for this database instance.
-    config: RQLiteConfig,
     /// Counter for generating unique table IDs.
     next_table_id: TableId,
     /// Counter for generating unique index IDs.
     next_index_id: IndexId,
+    /// Columns of the tables created with them.
+    table_schemas: HashMap<TableId, TableSchema>,
+    /// Names of the tables that have one.
+    table_names: HashMap<TableId, String>,
+    /// Tables as of the last commit. Snapshot readers find their roots here.
+    committed_tables: HashMap<TableId, BTree>,
+    /// Indexes as of the last commit.
+    committed_indexes: HashMap<IndexId, BTree>,
+    /// Table columns as of the last commit.
+    committed_table_schemas: HashMap<TableId, TableSchema>,
+    /// Table names as of the last commit.
+    committed_table_names: HashMap<TableId, String>,
+    /// Names and columns of the indexes created with SQL.
+    index_schemas: HashMap<IndexId, IndexSchema>,
+    /// Index names and columns as of the last commit.
+    committed_index_schemas: HashMap<IndexId, IndexSchema>,
+    /// Trees as they were when each savepoint of the transaction was opened, the innermost one last.
+    savepoints: Vec<CatalogSavepoint>,
+    /// The schema table, once the database has one.
+    schema: Option<BTree>,
+    /// The schema table as of the last commit.
+    committed_schema: Option<BTree>,
+    /// ID counters as of the last commit.
+    committed_next_ids: (TableId, IndexId),
+    /// The stat table, once the database has been analyzed.
+    stats: Option<BTree>,
+    /// The stat table as of the last commit.
+    committed_stats: Option<BTree>,
+    /// The sequence table, once a sequence has been created.
+    sequences: Option<BTree>,
+    /// The sequence table as of the last commit.
+    committed_sequences: Option<BTree>,
+    /// Values of the sequences reserved by this connection, by lowercase name (see tree/sequence.rs).
+    sequence_ranges: HashMap<String, SequenceRange>,
+    /// Schema cookie of the header when the schema table was last read or written.
+    schema_cookie: u32,
+}
+
+/// Copy of the catalog taken when a savepoint is opened.
+struct CatalogSavepoint {
+    name: String,
+    tables: HashMap<TableId, BTree>,
+    indexes: HashMap<IndexId, BTree>,
+    table_schemas: HashMap<TableId, TableSchema>,
+    table_names: HashMap<TableId, String>,
+    index_schemas: HashMap<IndexId, IndexSchema>,
+    stats: Option<BTree>,
+    sequences: Option<BTree>,
+}
+
+impl Catalog {
+    /// Creates an empty catalog.
+    fn new() -> Self {
+        Catalog {
+            tables: HashMap::new(),
+            indexes: HashMap::new(),
+            next_table_id: 1,
+            next_index_id: 1,
+            table_schemas: HashMap::new(),
+            table_names: HashMap::new(),
+            committed_tables: HashMap::new(),
+            committed_indexes: HashMap::new(),
+            committed_table_schemas: HashMap::new(),
+            committed_table_names: HashMap::new(),
+            index_schemas: HashMap::new(),
+            committed_index_schemas: HashMap::new(),
+            savepoints: Vec::new(),
+            schema: None,
+            committed_schema: None,
+            committed_next_ids: (1, 1),
+            stats: None,
+            committed_stats: None,
+            sequences: None,
+            committed_sequences: None,
+            sequence_ranges: HashMap::new(),
+            schema_cookie: 0,
+        }
+    }
+
+    /// Loads the catalog from the schema table of a database.
+    ///
+    /// # Errors
+    /// Returns an error if the schema table or one of the trees it lists cannot be read.
+    fn load(pager: &Arc<Pager>, config: &RQLiteConfig) -> io::Result<Self> {
+        // Reading the header under the SHARED lock plays back a hot journal first
+        pager.begin_read()?;
+        let header = pager.get_header();
+        pager.end_read()?;
+        Catalog::read(pager, config, &header?)
+    }
+
+    /// Reads the catalog from the schema table whose root is in the header.
+    ///
+    /// # Errors
+    /// Returns an error if the schema table or one of the trees it lists cannot be read.
+    fn read(pager: &Arc<Pager>, config: &RQLiteConfig, header: &Header) -> io::Result<Self> {
+        let open = |root_page: u32, tree_type: TreeType| {
+            BTree::open(
+                root_page,
+                tree_type,
+                Arc::clone(pager),
+                config.page_size,
+                config.reserved_space,
+                config.max_payload_fraction,
+                config.min_payload_fraction,
+            )
+        };
+
+        let mut catalog = Catalog::new();
+        catalog.schema_cookie = header.schema_cookie;
+        let root_page = header.schema_root();
+        if root_page == 0 {
+            return Ok(catalog);
+        }
+
+        let schema = open(root_page, TreeType::Table)?;
+        if let Some(counters) = schema.find(1)? {
+            catalog.next_table_id = schema_value(&counters, 1)?;
+            catalog.next_index_id = schema_value(&counters, 2)?;
+        }
+        for table_id in 1..catalog.next_table_id {
+            if let Some(row) = schema.find(table_id as i64 * 2)? {
+                let btree = open(schema_value(&row, 2)?, TreeType::Table)?;
+                catalog.tables.insert(table_id, btree);
+                if let Some(SqliteValue::Blob(columns)) = row.get_value(3) {
+                    let table_schema = TableSchema::from_bytes(columns)?;
+                    catalog.table_schemas.insert(table_id, table_schema);
+                }
+                if let Some(SqliteValue::String(name)) = row.get_value(4) {
+                    catalog.table_names.insert(table_id, name.clone());
+                }
+            }
+        }
+        for index_id in 1..catalog.next_index_id {
+            if let Some(row) = schema.find(index_id as i64 * 2 + 1)? {
+                let btree = open(schema_value(&row, 2)?, TreeType::Index)?;
+                catalog.indexes.insert(index_id, btree);
+                if let Some(SqliteValue::Blob(index_schema)) = row.get_value(3) {
+                    let index_schema = IndexSchema::from_bytes(index_schema)?;
+                    catalog.index_schemas.insert(index_id, index_schema);
+                }
+            }
+        }
+        if let Some(row) = schema.find(0)? {
+            catalog.stats = Some(open(schema_value(&row, 2)?, TreeType::Table)?);
+        }
+        if let Some(row) = schema.find(-1)? {
+            catalog.sequences = Some(open(schema_value(&row, 2)?, TreeType::Table)?);
+        }
+        catalog.schema = Some(schema);
+        catalog.commit();
+        Ok(catalog)
+    }
+
+    /// Writes the trees the transaction created or moved to the schema table, creating it the first time,
+    /// deletes the rows of the trees it dropped, and bumps the schema cookie if it did any of that.
+    /// Must be called right before the pager commits.
+    ///
+    /// # Errors
+    /// Returns an error if the schema table or the header cannot be written.
+    fn persist(&mut self, pager: &Arc<Pager>, config: &RQLiteConfig) -> io::Result<()> {
+        let mut rows = Vec::new();
+        for (table_id, btree) in &self.tables {
+            let committed = self.committed_tables.get(table_id).map(BTree::root_page);
+            let table_schema = self.table_schemas.get(table_id);
+            let name = self.table_names.get(table_id);
+            if committed != Some(btree.root_page())
+                || table_schema != self.committed_table_schemas.get(table_id)
+                || name != self.committed_table_names.get(table_id)
+            {
+                let mut row = schema_row("table", *table_id, btree.root_page());
+                match table_schema {
+                    Some(table_schema) => {
+                        row.add_value(SqliteValue::Blob(table_schema.to_bytes()?))
+                    }
+                    None if name.is_some() => row.add_value(SqliteValue::Null),
+                    None => {}
+                }
+                if let Some(name) = name {
+                    row.add_value(SqliteValue::String(name.clone()));
+                }
+                rows.push((*table_id as i64 * 2, row));
+            }
+        }
+        for (index_id, btree) in &self.indexes {
+            let committed = self.committed_indexes.get(index_id).map(BTree::root_page);
+            let index_schema = self.index_schemas.get(index_id);
+            if committed != Some(btree.root_page())
+                || index_schema != self.committed_index_schemas.get(index_id)
+            {
+                let mut row = schema_row("index", *index_id, btree.root_page());
+                if let Some(index_schema) = index_schema {
+                    row.add_value(SqliteValue::Blob(index_schema.to_bytes()?));
+                }
+                rows.push((*index_id as i64 * 2 + 1, row));
+            }
+        }
+        let dropped_tables = self
+            .committed_tables
+            .keys()
+            .filter(|table_id| !self.tables.contains_key(table_id))
+            .map(|table_id| *table_id as i64 * 2);
+        let dropped_indexes = self
+            .committed_indexes
+            .keys()
+            .filter(|index_id| !self.indexes.contains_key(index_id))
+            .map(|index_id| *index_id as i64 * 2 + 1);
+        let dropped: Vec<i64> = dropped_tables.chain(dropped_indexes).collect();
+        if let Some(stats) = &self.stats {
+            if self.committed_stats.as_ref().map(BTree::root_page) != Some(stats.root_page()) {
+                rows.push((0, schema_row("stat", 0, stats.root_page())));
+            }
+        }
+        if let Some(sequences) = &self.sequences {
+            let committed = self.committed_sequences.as_ref().map(BTree::root_page);
+            if committed != Some(sequences.root_page()) {
+                rows.push((-1, schema_row("sequence", 0, sequences.root_page())));
+            }
+        }
+        if (self.next_table_id, self.next_index_id) != self.committed_next_ids {
+            rows.push((
+                1,
+                schema_row("counters", self.next_table_id, self.next_index_id),
+            ));
+        }
+        if rows.is_empty() && dropped.is_empty() {
+            return Ok(());
+        }
+
+        let schema = match &mut self.schema {
+            Some(schema) => schema,
+            None => self.schema.insert(BTree::create(
+                TreeType::Table,
+                Arc::clone(pager),
+                config.page_size,
+                config.reserved_space,
+                config.max_payload_fraction,
+                config.min_payload_fraction,
+            )?),
+        };
+        for rowid in dropped {
+            schema.delete(rowid)?;
+        }
+        for (rowid, row) in rows {
+            schema.delete(rowid)?;
+            schema.insert(rowid, &row)?;
+        }
+
+        // The root of the schema table itself moves when it splits
+        let mut header = pager.get_header()?;
+        header.set_schema_root(schema.root_page());
+        header.increment_schema_cookie();
+        pager.update_header(&header)?;
+        self.schema_cookie = header.schema_cookie;
+        Ok(())
+    }
+
+    /// Reloads the catalog if another connection changed the schema table since we last read or wrote it,
+    /// which the schema cookie tells. Must be called at the start of a write transaction, when the header
+    /// is the latest one and nobody else can change it anymore.
+    ///
+    /// # Errors
+    /// Returns an error if the header or the schema table cannot be read.
+    fn refresh(&mut self, pager: &Arc<Pager>, config: &RQLiteConfig) -> io::Result<()> {
+        let header = pager.get_header()?;
+        if header.schema_cookie != self.schema_cookie {
+            *self = Catalog::read(pager, config, &header)?;
+        }
+        Ok(())
+    }
+
+    /// Gets the B-Tree of a table or an index, or a `NotFound` error.
+    fn tree(&self, tree_type: TreeType, id: u32) -> io::Result<&BTree> {
+        match tree_type {
+            TreeType::Table => self.table(id),
+            TreeType::Index => self.index(id),
+        }
+    }
+
+    /// Gets the B-Tree of a table or an index as of the last commit, or a `NotFound` error.
+    fn committed_tree(&self, tree_type: TreeType, id: u32) -> io::Result<&BTree> {
+        let (trees, kind) = match tree_type {
+            TreeType::Table => (&self.committed_tables, "Table"),
+            TreeType::Index => (&self.committed_indexes, "Index"),
+        };
+        trees.get(&id).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::NotFound, format!("{} {} not found", kind, id))
+        })
+    }
+
+    /// Makes the current trees the committed ones. Must be called right after the pager commits.
+    fn commit(&mut self) {
+        self.committed_tables = self.tables.clone();
+        self.committed_indexes = self.indexes.clone();
+        self.committed_table_schemas = self.table_schemas.clone();
+        self.committed_table_names = self.table_names.clone();
+        self.committed_index_schemas = self.index_schemas.clone();
+        self.committed_schema = self.schema.clone();
+        self.committed_next_ids = (self.next_table_id, self.next_index_id);
+        self.committed_stats = self.stats.clone();
+        self.committed_sequences = self.sequences.clone();
+        for range in self.sequence_ranges.values_mut() {
+            range.uncommitted = false;
+        }
+        self.savepoints.clear();
+    }
+
+    /// Goes back to the committed trees, forgetting the ones created and the roots moved by the transaction.
+    fn rollback(&mut self) {
+        self.tables = self.committed_tables.clone();
+        self.indexes = self.committed_indexes.clone();
+        self.table_schemas = self.committed_table_schemas.clone();
+        self.table_names = self.committed_table_names.clone();
+        self.index_schemas = self.committed_index_schemas.clone();
+        self.schema = self.committed_schema.clone();
+        self.stats = self.committed_stats.clone();
+        self.sequences = self.committed_sequences.clone();
+        self.forget_uncommitted_ranges();
+        self.savepoints.clear();
+    }
+
+    /// Remembers the current trees for a savepoint. Must be called right after the pager opens it.
+    fn savepoint(&mut self, name: &str) {
+        self.savepoints.push(CatalogSavepoint {
+            name: name.to_string(),
+            tables: self.tables.clone(),
+            indexes: self.indexes.clone(),
+            table_schemas: self.table_schemas.clone(),
+            table_names: self.table_names.clone(),
+            index_schemas: self.index_schemas.clone(),
+            stats: self.stats.clone(),
+            sequences: self.sequences.clone(),
+        });
+    }
+
+    /// Finds the innermost savepoint with the given name, the same one the pager uses.
+    fn find_savepoint(&self, name: &str) -> Option<usize> {
+        self.savepoints
+            .iter()
+            .rposition(|savepoint| savepoint.name.eq_ignore_ascii_case(name))
+    }
+
+    /// Forgets a savepoint and the ones opened after it.
+    fn release_savepoint(&mut self, name: &str) {
+        if let Some(position) = self.find_savepoint(name) {
+            self.savepoints.truncate(position);
+        }
+    }
+
+    /// Goes back to the trees of a savepoint, which stays open.
+    fn rollback_to_savepoint(&mut self, name: &str) {
+        if let Some(position) = self.find_savepoint(name) {
+            self.savepoints.truncate(position + 1);
+            self.tables = self.savepoints[position].tables.clone();
+            self.indexes = self.savepoints[position].indexes.clone();
+            self.table_schemas = self.savepoints[position].table_schemas.clone();
+            self.table_names = self.savepoints[position].table_names.clone();
+            self.index_schemas = self.savepoints[position].index_schemas.clone();
+            self.stats = self.savepoints[position].stats.clone();
+            self.sequences = self.savepoints[position].sequences.clone();
+            // Ranges reserved before the savepoint are still reserved, but telling them apart is not worth it,
+            // forgetting a range only leaves a gap
+            self.forget_uncommitted_ranges();
+        }
+    }
+
+    /// Forgets the sequence ranges reserved by the transaction, whose reservation is being rolled back.
+    /// Another connection could reserve the same values, so handing them out would give them twice.
+    fn forget_uncommitted_ranges(&mut self) {
+        self.sequence_ranges.retain(|_, range| !range.uncommitted);
+    }
+
+    /// Gets the B-Tree of a table, or a `NotFound` error.
+    fn table(&self, table_id: TableId) -> io::Result<&BTree> {
+        self.tables.get(&table_id).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::NotFound, format!("Table {} not found", table_id))
+        })
+    }
+
+    /// Gets the B-Tree of a table for modification, or a `NotFound` error.
+    fn table_mut(&mut self, table_id: TableId) -> io::Result<&mut BTree> {
+        self.tables.get_mut(&table_id).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::NotFound, format!("Table {} not found", table_id))
+        })
+    }
+
+    /// Gets the B-Tree of an index, or a `NotFound` error.
+    fn index(&self, index_id: IndexId) -> io::Result<&BTree> {
+        self.indexes.get(&index_id).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::NotFound, format!("Index {} not found", index_id))
+        })
+    }
+
+    /// Gets the B-Tree of an index for modification, or a `NotFound` error.
+    fn index_mut(&mut self, index_id: IndexId) -> io::Result<&mut BTree> {
+        self.indexes.get_mut(&index_id).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::NotFound, format!("Index {} not found", index_id))
+        })
+    }
+
+    /// Gets the columns of a table to change them, or an error if the table does not exist or has no columns.
+    fn table_schema_mut(&mut self, table_id: TableId) -> io::Result<&mut TableSchema> {
+        self.table(table_id)?;
+        self.table_schemas.get_mut(&table_id).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Table {} has no columns", table_id),
+            )
+        })
+    }
+
+    /// Finds a table by name, ignoring ASCII case.
+    fn find_table(&self, name: &str) -> Option<TableId> {
+        self.table_names
+            .iter()
+            .find(|(_, table_name)| table_name.eq_ignore_ascii_case(name))
+            .map(|(table_id, _)| *table_id)
+    }
+
+    /// Finds an index created with SQL by name, ignoring ASCII case.
+    fn find_index(&self, name: &str) -> Option<IndexId> {
+        self.index_schemas
+            .iter()
+            .find(|(_, index_schema)| index_schema.name.eq_ignore_ascii_case(name))
+            .map(|(index_id, _)| *index_id)
+    }
+
+    /// Creates an empty B-Tree with the settings of the database.
+    fn new_tree(
+        pager: &Arc<Pager>,
+        config: &RQLiteConfig,
+        tree_type: TreeType,
+    ) -> io::Result<BTree> {
+        BTree::create(
+            tree_type,
+            Arc::clone(pager),
+            config.page_size,
+            config.reserved_space,
+            config.max_payload_fraction,
+            config.min_payload_fraction,
+        )
+    }
+
+    /// Copies the committed trees into a catalog of their own, read through a snapshot of the last commit.
+    /// Threads without the write transaction run their SQL queries on one, so they see neither the uncommitted
+    /// changes nor the ones committed while the query runs, and do not hold the catalog lock meanwhile.
+    ///
+    /// # Errors
+    /// Returns an error if the snapshot cannot be taken.
+    fn snapshot(&self, pager: &Arc<Pager>) -> io::Result<Catalog> {
+        let pager = Arc::new(pager.snapshot()?);
+        let with_pager = |trees: &HashMap<u32, BTree>| -> HashMap<u32, BTree> {
+            trees
+                .iter()
+                .map(|(id, btree)| (*id, btree.with_pager(Arc::clone(&pager))))
+                .collect()
+        };
+
+        let mut catalog = Catalog::new();
+        catalog.tables = with_pager(&self.committed_tables);
+        catalog.indexes = with_pager(&self.committed_indexes);
+        catalog.table_schemas = self.committed_table_schemas.clone();
+        catalog.table_names = self.committed_table_names.clone();
+        catalog.index_schemas = self.committed_index_schemas.clone();
+        catalog.next_table_id = self.committed_next_ids.0;
+        catalog.next_index_id = self.committed_next_ids.1;
+        catalog.schema_cookie = self.schema_cookie;
+        Ok(catalog)
+    }
+
+    /// Applies the columns of a table to a record about to be inserted.
+    /// Returns `None` if the table has no columns, and the record is stored as it is.
+    fn conform(&self, table_id: TableId, record: &Record) -> io::Result<Option<Record>> {
+        self.table_schemas
+            .get(&table_id)
+            .map(|table_schema| table_schema.apply(record))
+            .transpose()
+    }
+}
+
+/// Builds a row of the schema table.
+fn schema_row(kind: &str, first: u32, second: u32) -> Record {
+    Record::with_values(vec![
+        SqliteValue::String(kind.to_string()),
+        SqliteValue::Integer(first as i64),
+        SqliteValue::Integer(second as i64),
+    ])
+}
+
+/// Reads a number from a row of the schema table.
+fn schema_value(row: &Record, index: usize) -> io::Result<u32> {
+    match row.get_value(index) {
+        Some(SqliteValue::Integer(value)) => u32::try_from(*value)
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "Invalid schema table row")),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "Invalid schema table row",
+        )),
+    }
+}
+
+/// Gets the columns of a tree from one of the schema maps of the catalog. Indexes have none.
+fn table_schema_of(
+    table_schemas: &HashMap<TableId, TableSchema>,
+    tree_type: TreeType,
+    id: u32,
+) -> Option<&TableSchema> {
+    match tree_type {
+        TreeType::Table => table_schemas.get(&id),
+        TreeType::Index => None,
+    }
+}
+
+/// Finds a row of a table, filling the columns added to the table after the row was written.
+fn find_row(
+    btree: &BTree,
+    table_schema: Option<&TableSchema>,
+    rowid: i64,
+) -> io::Result<Option<Record>> {
+    let record = btree.find(rowid)?;
+    Ok(match table_schema {
+        Some(table_schema) => record.map(|record| table_schema.complete(rowid, record)),
+        None => record,
+    })
+}
+
+/// Finds some columns of a row of a table, the ones added after the row was written taking their default.
+fn find_row_columns(
+    btree: &BTree,
+    table_schema: Option<&TableSchema>,
+    rowid: i64,
+    columns: &[usize],
+) -> io::Result<Option<Record>> {
+    let defaults = table_schema.map(TableSchema::defaults).unwrap_or_default();
+    let mut record = btree.find_columns(rowid, columns, &defaults)?;
+    let primary_key = table_schema.and_then(TableSchema::primary_key);
+    if let (Some(record), Some(primary_key)) = (&mut record, primary_key) {
+        for (i, _) in columns
+            .iter()
+            .enumerate()
+            .filter(|(_, &column)| column == primary_key)
+        {
+            record.set_value(i, SqliteValue::Integer(rowid));
+        }
+    }
+    Ok(record)
 }
 
-impl RQLite {
-    /// Creates a new database file with the specified configuration.
+impl RQLite {
+    /// Creates a new database file with the specified configuration.
+    ///
+    /// # Parameters
+    /// * `path` - Path where the database file will be created.
+    /// * `config` - Optional configuration. If None, default values are used.
+    ///
+    /// # Errors
+    /// Returns an error if:
+    /// - The database file cannot be created
+    /// - The configuration values are invalid
+    /// - There are disk I/O issues
+    ///
+    /// # Returns
+    /// A new RQLite instance connected to the created database.
+    ///
+    /// # Example
+    /// ```rust
+    /// # fn main() -> std::io::Result<()> {
+    /// # let dir = tempfile::tempdir()?;
+    /// use rqlite_engine::{RQLite, RQLiteConfig};
+    ///
+    /// // Create with default configuration
+    /// let db = RQLite::create(dir.path().join("my_database.db"), None)?;
+    ///
+    /// // Create with custom configuration
+    /// let config = RQLiteConfig {
+    ///     page_size: 8192,
+    ///     buffer_pool_size: 2000,
+    ///     ..Default::default()
+    /// };
+    /// let db = RQLite::create(dir.path().join("my_tuned_database.db"), Some(config))?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn create<P: AsRef<Path>>(path: P, config: Option<RQLiteConfig>) -> io::Result<Self> {
+        Self::create_with_vfs(Arc::new(OsVfs), path, config)
+    }
+
+    /// Creates a new database that lives only in memory.
+    ///
+    /// Nothing ever touches the disk, and the database is gone when the last handle is dropped.
+    /// Useful for tests and for ephemeral data like caches.
+    ///
+    /// # Parameters
+    /// * `config` - Optional configuration. If None, default values are used.
+    ///
+    /// # Errors
+    /// Returns an error if the configuration values are invalid.
+    ///
+    /// # Returns
+    /// A new RQLite instance connected to an empty in-memory database.
+    ///
+    /// # Example
+    /// ```rust
+    /// # fn main() -> std::io::Result<()> {
+    /// use rqlite_engine::{RQLite, Record, SqliteValue};
+    ///
+    /// let db = RQLite::create_in_memory(None)?;
+    /// let table_id = db.create_table()?;
+    /// db.table_insert(table_id, 1, &Record::with_values(vec![SqliteValue::Integer(42)]))?;
+    /// assert!(db.table_find(table_id, 1)?.is_some());
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn create_in_memory(config: Option<RQLiteConfig>) -> io::Result<Self> {
+        Self::create_with_vfs(Arc::new(MemoryVfs::new()), ":memory:", config)
+    }
+
+    /// Creates a new database file in the given VFS (see `storage::vfs`).
+    ///
+    /// # Parameters
+    /// * `vfs` - The file system the database lives in.
+    /// * `path` - Path of the database file inside the VFS.
+    /// * `config` - Optional configuration. If None, default values are used.
+    ///
+    /// # Errors
+    /// Returns an error if the file cannot be created or the configuration values are invalid.
+    ///
+    /// # Returns
+    /// A new RQLite instance connected to the created database.
+    pub fn create_with_vfs<P: AsRef<Path>>(
+        vfs: Arc<dyn Vfs>,
+        path: P,
+        config: Option<RQLiteConfig>,
+    ) -> io::Result<Self> {
+        let config = config.unwrap_or_default();
+        
+        let pager = Pager::create_with_vfs(
+            vfs,
+            path,
+            config.page_size,
+            Some(config.buffer_pool_size),
+            config.reserved_space,
+        )?;
+        if config.checksums {
+            pager.enable_checksums()?;
+        }
+
+        Self::from_pager(pager, config)
+    }
+
+    /// Opens an existing database file.
+    ///
+    /// # Parameters
+    /// * `path` - Path to the existing database file.
+    /// * `config` - Optional configuration. If None, default values are used.
+    ///
+    /// # Errors
+    /// Returns an error if:
+    /// - The database file does not exist or cannot be opened
+    /// - The file is corrupted or has an invalid format
+    /// - There are disk I/O issues
+    ///
+    /// # Returns
+    /// An RQLite instance connected to the existing database.
+    ///
+    /// # Example
+    /// ```rust
+    /// # fn main() -> std::io::Result<()> {
+    /// # let dir = tempfile::tempdir()?;
+    /// # let path = dir.path().join("existing_database.db");
+    /// # rqlite_engine::RQLite::create(&path, None)?.close()?;
+    /// use rqlite_engine::RQLite;
+    ///
+    /// let db = RQLite::open(&path, None)?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn open<P: AsRef<Path>>(path: P, config: Option<RQLiteConfig>) -> io::Result<Self> {
+        Self::open_with_vfs(Arc::new(OsVfs), path, config)
+    }
+
+    /// Opens an existing database file of the given VFS (see `storage::vfs`).
+    ///
+    /// # Parameters
+    /// * `vfs` - The file system the database lives in.
+    /// * `path` - Path of the database file inside the VFS.
+    /// * `config` - Optional configuration. If None, default values are used.
+    ///
+    /// # Errors
+    /// Returns an error if the file does not exist in the VFS or has an invalid format.
+    ///
+    /// # Returns
+    /// An RQLite instance connected to the existing database.
+    ///
+    /// # Example
+    /// ```rust
+    /// # fn main() -> std::io::Result<()> {
+    /// use rqlite_engine::{MemoryVfs, RQLite};
+    /// use std::sync::Arc;
+    ///
+    /// let vfs = Arc::new(MemoryVfs::new());
+    /// RQLite::create_with_vfs(vfs.clone(), "cache.db", None)?.close()?;
+    ///
+    /// // The file is still there while the VFS is alive
+    /// let db = RQLite::open_with_vfs(vfs, "cache.db", None)?;
+    /// assert_eq!(db.page_count()?, 1);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn open_with_vfs<P: AsRef<Path>>(
+        vfs: Arc<dyn Vfs>,
+        path: P,
+        config: Option<RQLiteConfig>,
+    ) -> io::Result<Self> {
+        let config = config.unwrap_or_default();
+        
+        let pager = Pager::open_with_vfs(vfs, path, Some(config.buffer_pool_size))?;
+        Self::from_pager(pager, config)
+    }
+
+    /// Wraps a pager into a new handle, loading the catalog from its schema table.
+    fn from_pager(pager: Pager, config: RQLiteConfig) -> io::Result<Self> {
+        if !config.busy_timeout.is_zero() {
+            pager.set_busy_handler(Some(timeout_handler(config.busy_timeout)))?;
+        }
+        pager.set_synchronous(config.synchronous)?;
+        pager.set_payload_fractions(config.max_payload_fraction, config.min_payload_fraction)?;
+        pager.set_group_commit(config.group_commit)?;
+        pager.set_mmap_size(config.mmap_size)?;
+        pager.set_read_ahead(config.read_ahead)?;
+        if config.background_writer.is_some() {
+            pager.set_background_writer(config.background_writer)?;
+        }
+
+        let pager = Arc::new(pager);
+        let catalog = Catalog::load(&pager, &config)?;
+        Ok(RQLite {
+            shared: Arc::new(Shared {
+                pager,
+                catalog: RwLock::new(catalog),
+                config,
+            }),
+        })
+    }
+
+    /// Acquires the catalog for reading.
+    ///
+    /// A panic in another thread cannot leave the maps themselves half-updated,
+    /// so a poisoned lock is recovered instead of failing every later call.
+    fn catalog(&self) -> RwLockReadGuard<'_, Catalog> {
+        self.shared.catalog.read().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Acquires the catalog for writing. Writers are serialized by this lock.
+    fn catalog_mut(&self) -> RwLockWriteGuard<'_, Catalog> {
+        self.shared.catalog.write().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Runs a read operation on a table or an index. The operation also gets the columns of a table if it has them.
+    ///
+    /// The thread that began the active transaction reads the live tree, to see its own changes.
+    /// Everybody else reads a snapshot of the last commit.
+    fn read<R>(
+        &self,
+        tree_type: TreeType,
+        id: u32,
+        op: impl FnOnce(&BTree, Option<&TableSchema>) -> io::Result<R>,
+    ) -> io::Result<R> {
+        if self.shared.pager.owns_transaction()? {
+            self.read_live(tree_type, id, op)
+        } else {
+            self.read_snapshot(tree_type, id, op)
+        }
+    }
+
+    /// Runs a read operation on the live tree, uncommitted changes included, holding the SHARED lock of the file.
+    fn read_live<R>(
+        &self,
+        tree_type: TreeType,
+        id: u32,
+        op: impl FnOnce(&BTree, Option<&TableSchema>) -> io::Result<R>,
+    ) -> io::Result<R> {
+        let catalog = self.catalog();
+        let btree = catalog.tree(tree_type, id)?;
+        let table_schema = table_schema_of(&catalog.table_schemas, tree_type, id);
+        let pager = &self.shared.pager;
+
+        pager.begin_read()?;
+        let result = op(btree, table_schema);
+        let released = pager.end_read();
+
+        let value = result?;
+        released?;
+        Ok(value)
+    }
+
+    /// Runs a read operation on a snapshot of the last commit.
+    /// The catalog is released as soon as we have the root, so long reads do not hold back writers.
+    fn read_snapshot<R>(
+        &self,
+        tree_type: TreeType,
+        id: u32,
+        op: impl FnOnce(&BTree, Option<&TableSchema>) -> io::Result<R>,
+    ) -> io::Result<R> {
+        // Commits happen under the catalog lock, so the committed roots always match the snapshot
+        let (btree, table_schema) = {
+            let catalog = self.catalog();
+            let committed = catalog.committed_tree(tree_type, id)?;
+            let table_schema =
+                table_schema_of(&catalog.committed_table_schemas, tree_type, id).cloned();
+            let btree = committed.with_pager(Arc::new(self.shared.pager.snapshot()?));
+            (btree, table_schema)
+        };
+        op(&btree, table_schema.as_ref())
+    }
+
+    /// Loads the statistics of a table or an index from the stat table, the same way `read` reads a tree:
+    /// the thread of the active transaction sees the stat table of the transaction, everybody else the committed one.
+    ///
+    /// # Errors
+    /// Returns a `NotFound` error if the tree does not exist, or an error if the stat table cannot be read.
+    fn read_stats(&self, tree_type: TreeType, id: u32) -> io::Result<Option<TreeStats>> {
+        let pager = &self.shared.pager;
+        if pager.owns_transaction()? {
+            let catalog = self.catalog();
+            catalog.tree(tree_type, id)?;
+            let Some(stats) = &catalog.stats else {
+                return Ok(None);
+            };
+
+            pager.begin_read()?;
+            let result = load_stats(stats, tree_type, id);
+            let released = pager.end_read();
+
+            let value = result?;
+            released?;
+            Ok(value)
+        } else {
+            let stats = {
+                let catalog = self.catalog();
+                catalog.committed_tree(tree_type, id)?;
+                match &catalog.committed_stats {
+                    Some(stats) => stats.with_pager(Arc::new(pager.snapshot()?)),
+                    None => return Ok(None),
+                }
+            };
+            load_stats(&stats, tree_type, id)
+        }
+    }
+
+    /// Runs a write operation that must leave nothing behind when it fails, like a SQL statement.
+    /// `write` rolls back its own transaction on errors, but inside an explicit transaction the operation
+    /// only gets a savepoint (`STATEMENT_SAVEPOINT`), which is rolled back if it fails.
+    ///
+    /// # Errors
+    /// Returns the error of the operation, or an error if the savepoint cannot be rolled back or released.
+    /// The savepoint is released even when its rollback fails, and the first error is returned.
+    fn statement<R>(
+        &self,
+        catalog: &mut Catalog,
+        op: impl FnOnce(&mut Catalog) -> io::Result<R>,
+    ) -> io::Result<R> {
+        let pager = &self.shared.pager;
+        pager.savepoint(STATEMENT_SAVEPOINT)?;
+        catalog.savepoint(STATEMENT_SAVEPOINT);
+
+        let result = op(catalog);
+        let rolled_back = match result {
+            Ok(_) => Ok(()),
+            Err(_) => {
+                catalog.rollback_to_savepoint(STATEMENT_SAVEPOINT);
+                pager.rollback_to_savepoint(STATEMENT_SAVEPOINT)
+            }
+        };
+        // The savepoint goes on every path, or the next statements would nest under a stale one
+        let released = pager.release_savepoint(STATEMENT_SAVEPOINT);
+        catalog.release_savepoint(STATEMENT_SAVEPOINT);
+
+        let value = result?;
+        rolled_back?;
+        released?;
+        Ok(value)
+    }
+
+    /// Checks if the caller may work in the active transaction: it runs on the thread that began it,
+    /// or through its `Transaction` guard.
+    fn owns_transaction(&self) -> io::Result<bool> {
+        Ok(GUARDED.with(Cell::get) || self.shared.pager.owns_transaction()?)
+    }
+
+    /// Fails if the active transaction belongs to another thread. Only its owner may end it or
+    /// work with its savepoints: anybody else would throw away or commit changes it knows nothing about.
+    /// Call it holding the catalog lock, under which transactions begin and end.
+    ///
+    /// # Errors
+    /// Returns a `PermissionDenied` error if a transaction is active and the caller does not own it.
+    fn check_owner(&self) -> io::Result<()> {
+        if self.shared.pager.in_transaction()? && !self.owns_transaction()? {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "The active transaction belongs to another thread",
+            ));
+        }
+        Ok(())
+    }
+
+    /// Runs a write operation. Outside of an explicit transaction the operation runs in its own one,
+    /// which is committed when it succeeds and rolled back when it fails.
+    ///
+    /// Only the thread that began the active transaction, or its `Transaction` guard, writes in it. Anybody else
+    /// waits for it to end, calling the busy handler like for a lock held by another process: joining it would
+    /// throw the write away if the owner rolls back, without the caller ever knowing.
+    ///
+    /// # Errors
+    /// Returns a `Busy` error if another thread keeps its transaction open until the busy handler gives up,
+    /// or the error of the operation or of its commit.
+    fn write<R>(&self, op: impl FnOnce(&mut Catalog) -> io::Result<R>) -> io::Result<R> {
+        let pager = &self.shared.pager;
+        let mut retries = 0;
+        // Transactions begin and end under the catalog lock, so nobody takes or drops one while we hold it
+        let (mut catalog, autocommit) = loop {
+            let catalog = self.catalog_mut();
+            if !pager.in_transaction()? {
+                break (catalog, true);
+            }
+            if self.owns_transaction()? {
+                break (catalog, false);
+            }
+
+            // The owner needs the catalog to end its transaction
+            drop(catalog);
+            if !pager.busy_retry(retries)? {
+                return Err(busy_error(LockLevel::Reserved, LockLevel::Unlocked));
+            }
+            retries += 1;
+        };
+
+        if autocommit {
+            pager.begin_transaction()?;
+            if let Err(e) = catalog.refresh(pager, &self.shared.config) {
+                let _ = pager.rollback_transaction();
+                return Err(e);
+            }
+        }
+
+        let result = op(&mut catalog);
+        let mut commit = None;
+        if autocommit {
+            match &result {
+                Ok(_) => match catalog
+                    .persist(pager, &self.shared.config)
+                    .and_then(|_| pager.commit_transaction_deferred())
+                {
+                    Ok(number) => {
+                        catalog.commit();
+                        commit = Some(number);
+                    }
+                    Err(e) => {
+                        let _ = pager.rollback_transaction();
+                        catalog.rollback();
+                        return Err(e);
+                    }
+                },
+                Err(_) => {
+                    let _ = pager.rollback_transaction();
+                    catalog.rollback();
+                }
+            }
+        }
+
+        // Wait for the sync without the catalog, so the next writer can go on in the meantime
+        drop(catalog);
+        if let Some(commit) = commit {
+            pager.sync_commit(commit)?;
+        }
+        result
+    }
+
+    /// Creates a new table in the database.
+    ///
+    /// # Errors
+    /// Returns an error if:
+    /// - The table cannot be created due to disk space issues
+    /// - There are I/O problems
+    ///
+    /// # Returns
+    /// The unique identifier for the newly created table.
+    ///
+    /// # Example
+    /// ```rust
+    /// # fn main() -> std::io::Result<()> {
+    /// # let db = rqlite_engine::RQLite::create_in_memory(None)?;
+    /// let table_id = db.create_table()?;
+    /// println!("Created table with ID: {}", table_id);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn create_table(&self) -> io::Result<TableId> {
+        self.add_table(None)
+    }
+
+    /// Creates a new table with columns. Records inserted in the table go through its schema: missing columns
+    /// take their DEFAULT, values are converted to the affinity of their column, and NOT NULL is enforced
+    /// (see `TableSchema::apply`). The schema is saved in the catalog with the table.
+    ///
+    /// # Parameters
+    /// * `columns` - The columns of the table, in order.
+    ///
+    /// # Errors
+    /// Returns an error if:
+    /// - There are no columns, or two columns have the same name
+    /// - The table cannot be created due to disk space issues
+    /// - There are I/O problems
+    ///
+    /// # Returns
+    /// The unique identifier for the newly created table.
+    ///
+    /// # Example
+    /// ```rust
+    /// # fn main() -> std::io::Result<()> {
+    /// # let db = rqlite_engine::RQLite::create_in_memory(None)?;
+    /// use rqlite_engine::{Column, Record, SqliteValue};
+    ///
+    /// let table_id = db.create_table_with_columns(vec![
+    ///     Column::new("id", "INTEGER").not_null(),
+    ///     Column::new("name", "TEXT"),
+    /// ])?;
+    /// db.table_insert(table_id, 1, &Record::with_values(vec![SqliteValue::String("1".to_string())]))?;
+    /// let row = db.table_find(table_id, 1)?.unwrap();
+    /// assert_eq!(row.values, vec![SqliteValue::Integer(1), SqliteValue::Null]);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn create_table_with_columns(&self, columns: Vec<Column>) -> io::Result<TableId> {
+        self.add_table(Some(TableSchema::new(columns)?))
+    }
+
+    /// Creates the B-Tree of a new table and adds it to the catalog, with its columns if it has them.
+    fn add_table(&self, table_schema: Option<TableSchema>) -> io::Result<TableId> {
+        let config = &self.shared.config;
+
+        self.write(|catalog| {
+            let btree = BTree::create(
+                TreeType::Table,
+                Arc::clone(&self.shared.pager),
+                config.page_size,
+                config.reserved_space,
+                config.max_payload_fraction,
+                config.min_payload_fraction,
+            )?;
+
+            let table_id = catalog.next_table_id;
+            catalog.next_table_id += 1;
+            catalog.tables.insert(table_id, btree);
+            if let Some(table_schema) = table_schema {
+                catalog.table_schemas.insert(table_id, table_schema);
+            }
+            Ok(table_id)
+        })
+    }
+
+    /// Creates a new index on a table.
+    ///
+    /// # Parameters
+    /// * `table_id` - The table to create the index on.
+    ///
+    /// # Errors
+    /// Returns an error if:
+    /// - The specified table does not exist
+    /// - The index cannot be created due to disk space issues
+    /// - There are I/O problems
+    ///
+    /// # Returns
+    /// The unique identifier for the newly created index.
+    ///
+    /// # Example
+    /// ```rust
+    /// # fn main() -> std::io::Result<()> {
+    /// # let db = rqlite_engine::RQLite::create_in_memory(None)?;
+    /// let table_id = db.create_table()?;
+    /// let index_id = db.create_index(table_id)?;
+    /// println!("Created index with ID: {}", index_id);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn create_index(&self, _table_id: TableId) -> io::Result<IndexId> {
+        let config = &self.shared.config;
+
+        self.write(|catalog| {
+            let btree = BTree::create(
+                TreeType::Index,
+                Arc::clone(&self.shared.pager),
+                config.page_size,
+                config.reserved_space,
+                config.max_payload_fraction,
+                config.min_payload_fraction,
+            )?;
+
+            let index_id = catalog.next_index_id;
+            catalog.next_index_id += 1;
+            catalog.indexes.insert(index_id, btree);
+            Ok(index_id)
+        })
+    }
+
+    /// Inserts a record into the specified table.
+    /// If the table was created with columns, the record goes through its schema first (see `create_table_with_columns`),
+    /// and the indexes created on the table with SQL get its entries, like with an INSERT.
+    ///
+    /// # Parameters
+    /// * `table_id` - The table to insert into.
+    /// * `rowid` - The row identifier for the record.
+    /// * `record` - The record to insert.
+    ///
+    /// # Errors
+    /// Returns an error if:
+    /// - The specified table does not exist
+    /// - The rowid already exists in the table
+    /// - The record does not fit the columns of the table
+    /// - A UNIQUE index of the table already has its values (an `AlreadyExists` error)
+    /// - There are disk space or I/O issues
+    ///
+    /// # Returns
+    /// Success indication.
+    ///
+    /// # Example
+    /// ```rust
+    /// # fn main() -> std::io::Result<()> {
+    /// # let db = rqlite_engine::RQLite::create_in_memory(None)?;
+    /// use rqlite_engine::utils::serialization::SqliteValue;
+    /// use rqlite_engine::tree::record::Record;
+    ///
+    /// let table_id = db.create_table()?;
+    /// let record = Record::with_values(vec![
+    ///     SqliteValue::Integer(42),
+    ///     SqliteValue::String("Hello".to_string()),
+    /// ]);
+    /// db.table_insert(table_id, 1, &record)?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn table_insert(&self, table_id: TableId, rowid: i64, record: &Record) -> io::Result<()> {
+        self.write(|catalog| {
+            self.statement(catalog, |catalog| {
+                let conformed = catalog.conform(table_id, record)?;
+                sql::executor::insert_row(
+                    catalog,
+                    table_id,
+                    rowid,
+                    conformed.as_ref().unwrap_or(record),
+                )
+            })
+        })
+    }
+
+    /// Finds a record in the specified table by its rowid.
+    ///
+    /// # Parameters
+    /// * `table_id` - The table to search in.
+    /// * `rowid` - The row identifier to search for.
+    ///
+    /// # Errors
+    /// Returns an error if:
+    /// - The specified table does not exist
+    /// - There are I/O issues
+    ///
+    /// # Returns
+    /// The record if found, or None if no record exists with the given rowid.
+    ///
+    /// # Example
+    /// ```rust
+    /// # fn main() -> std::io::Result<()> {
+    /// # let db = rqlite_engine::RQLite::create_in_memory(None)?;
+    /// # let table_id = db.create_table()?;
+    /// match db.table_find(table_id, 1)? {
+    ///     Some(record) => println!("Found record with {} values", record.len()),
+    ///     None => println!("Record not found"),
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn table_find(&self, table_id: TableId, rowid: i64) -> io::Result<Option<Record>> {
+        self.read(TreeType::Table, table_id, |btree, table_schema| {
+            find_row(btree, table_schema, rowid)
+        })
+    }
+
+    /// Finds some columns of a record in the specified table by its rowid.
+    /// Only those values are decoded, and the overflow pages of a large record are only read
+    /// if one of them is there (see `BTree::find_columns`).
+    ///
+    /// # Parameters
+    /// * `table_id` - The table to search in.
+    /// * `rowid` - The row identifier to search for.
+    /// * `columns` - Indexes of the columns to get. Columns past the end of the record take their DEFAULT
+    ///   if the table has columns, and are NULL otherwise.
+    ///
+    /// # Errors
+    /// Returns an error if:
+    /// - The specified table does not exist
+    /// - There are I/O issues
+    ///
+    /// # Returns
+    /// A record with the requested columns in the requested order, or None if no record exists with the given rowid.
+    ///
+    /// # Example
+    /// ```rust
+    /// # fn main() -> std::io::Result<()> {
+    /// # let db = rqlite_engine::RQLite::create_in_memory(None)?;
+    /// use rqlite_engine::{Record, SqliteValue};
+    ///
+    /// let table_id = db.create_table()?;
+    /// let record = Record::with_values(vec![
+    ///     SqliteValue::Integer(1),
+    ///     SqliteValue::String("name".to_string()),
+    ///     SqliteValue::Blob(vec![0; 20_000]),
+    /// ]);
+    /// db.table_insert(table_id, 1, &record)?;
+    /// let name = db.table_find_columns(table_id, 1, &[1])?.unwrap();
+    /// assert_eq!(name.values, vec![SqliteValue::String("name".to_string())]);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn table_find_columns(
+        &self,
+        table_id: TableId,
+        rowid: i64,
+        columns: &[usize],
+    ) -> io::Result<Option<Record>> {
+        self.read(TreeType::Table, table_id, |btree, table_schema| {
+            find_row_columns(btree, table_schema, rowid, columns)
+        })
+    }
+
+    /// Deletes a record from the specified table, and its entries from the indexes created on the table with SQL.
+    ///
+    /// # Parameters
+    /// * `table_id` - The table to delete from.
+    /// * `rowid` - The row identifier of the record to delete.
+    ///
+    /// # Errors
+    /// Returns an error if:
+    /// - The specified table does not exist
+    /// - There are I/O issues
+    ///
+    /// # Returns
+    /// True if a record was deleted, false if no record existed with the given rowid.
+    ///
+    /// # Example
+    /// ```rust
+    /// # fn main() -> std::io::Result<()> {
+    /// # let db = rqlite_engine::RQLite::create_in_memory(None)?;
+    /// # let table_id = db.create_table()?;
+    /// if db.table_delete(table_id, 1)? {
+    ///     println!("Record deleted successfully");
+    /// } else {
+    ///     println!("Record not found");
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn table_delete(&self, table_id: TableId, rowid: i64) -> io::Result<bool> {
+        self.write(|catalog| {
+            self.statement(catalog, |catalog| {
+                sql::executor::delete_row(catalog, table_id, rowid)
+            })
+        })
+    }
+
+    /// Inserts an entry into the specified index.
+    ///
+    /// # Parameters
+    /// * `index_id` - The index to insert into.
+    /// * `key` - The key to index.
+    /// * `rowid` - The row identifier associated with this key.
+    ///
+    /// # Errors
+    /// Returns an error if:
+    /// - The specified index does not exist
+    /// - There are disk space or I/O issues
+    ///
+    /// # Returns
+    /// Success indication.
+    ///
+    /// # Example
+    /// ```rust
+    /// # fn main() -> std::io::Result<()> {
+    /// # let db = rqlite_engine::RQLite::create_in_memory(None)?;
+    /// # let table_id = db.create_table()?;
+    /// use rqlite_engine::utils::serialization::{SqliteValue, serialize_values};
+    ///
+    /// let index_id = db.create_index(table_id)?;
+    /// 
+    /// // Create a key payload (in this case, an integer key)
+    /// let mut key_payload = Vec::new();
+    /// serialize_values(&[SqliteValue::Integer(42)], &mut key_payload)?;
+    /// 
+    /// db.index_insert(index_id, &key_payload, 1)?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn index_insert(&self, index_id: IndexId, key: &[u8], rowid: i64) -> io::Result<()> {
+        self.write(|catalog| catalog.index_mut(index_id)?.insert_index(key, rowid))
+    }
+
+    /// Finds an entry in the specified index.
+    ///
+    /// # Parameters
+    /// * `index_id` - The index to search in.
+    /// * `key` - The key to search for.
+    ///
+    /// # Errors
+    /// Returns an error if:
+    /// - The specified index does not exist
+    /// - There are I/O issues
+    ///
+    /// # Returns
+    /// A tuple containing:
+    /// - True if the key was found, false otherwise
+    /// - The leaf page number where the key is located
+    /// - The index within that page
+    ///
+    /// # Example
+    /// ```rust
+    /// # fn main() -> std::io::Result<()> {
+    /// # let db = rqlite_engine::RQLite::create_in_memory(None)?;
+    /// # let table_id = db.create_table()?;
+    /// # let index_id = db.create_index(table_id)?;
+    /// use rqlite_engine::utils::cmp::KeyValue;
+    ///
+    /// let key = KeyValue::Integer(42);
+    /// let (found, page, index) = db.index_find(index_id, &key)?;
+    /// if found {
+    ///     println!("Key found at page {} index {}", page, index);
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn index_find(&self, index_id: IndexId, key: &KeyValue) -> io::Result<(bool, u32, u16)> {
+        self.read(TreeType::Index, index_id, |btree, _| {
+            btree.find_index_key(key)
+        })
+    }
+
+    /// Deletes an entry from the specified index.
+    ///
+    /// # Parameters
+    /// * `index_id` - The index to delete from.
+    /// * `key` - The key to delete.
+    ///
+    /// # Errors
+    /// Returns an error if:
+    /// - The specified index does not exist
+    /// - There are I/O issues
+    ///
+    /// # Returns
+    /// True if an entry was deleted, false if no entry existed with the given key.
+    ///
+    /// # Example
+    /// ```rust
+    /// # fn main() -> std::io::Result<()> {
+    /// # let db = rqlite_engine::RQLite::create_in_memory(None)?;
+    /// # let table_id = db.create_table()?;
+    /// # let index_id = db.create_index(table_id)?;
+    /// use rqlite_engine::utils::cmp::KeyValue;
+    ///
+    /// let key = KeyValue::Integer(42);
+    /// if db.index_delete(index_id, &key)? {
+    ///     println!("Index entry deleted successfully");
+    /// } else {
+    ///     println!("Index entry not found");
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn index_delete(&self, index_id: IndexId, key: &KeyValue) -> io::Result<bool> {
+        self.write(|catalog| catalog.index_mut(index_id)?.delete_index(key))
+    }
+
+    /// Loads rows into an empty table much faster than inserting them one by one.
+    ///
+    /// The leaves are filled from left to right up to `bulk_fill_factor` and the interior levels are built on top
+    /// of them, so the table ends up with fewer, fuller pages written in order (see tree/bulk.rs).
+    /// Rows sorted by rowid go straight into the tree. Unsorted rows are sorted first, spilling to temporary files
+    /// past `sort_memory`. The rows of a table with columns go through its schema, like in `table_insert`, and the
+    /// indexes created on the table with SQL are filled once the table is loaded.
+    ///
+    /// # Parameters
+    /// * `table_id` - The table to load. It must be empty.
+    /// * `rows` - The rowids and records of the rows.
+    ///
+    /// # Errors
+    /// Returns an error if:
+    /// - The specified table does not exist or is not empty
+    /// - Two rows have the same rowid, or the same values in a UNIQUE index of the table
+    /// - A row does not fit the columns of the table
+    /// - There are disk space or I/O issues
+    ///
+    /// # Example
+    /// ```rust
+    /// # fn main() -> std::io::Result<()> {
+    /// # let db = rqlite_engine::RQLite::create_in_memory(None)?;
+    /// use rqlite_engine::utils::serialization::SqliteValue;
+    /// use rqlite_engine::tree::record::Record;
+    ///
+    /// let table_id = db.create_table()?;
+    /// let rows = (1..=1000).map(|rowid| (rowid, Record::with_values(vec![SqliteValue::Integer(rowid * 2)])));
+    /// db.bulk_load(table_id, rows)?;
+    /// assert!(db.table_find(table_id, 500)?.is_some());
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn bulk_load<I>(&self, table_id: TableId, rows: I) -> io::Result<()>
+    where
+        I: IntoIterator<Item = (i64, Record)>,
+    {
+        let config = &self.shared.config;
+        self.write(|catalog| {
+            self.statement(catalog, |catalog| {
+                // The load stops at the first row that does not fit, and the statement savepoint rolls it all back
+                let table_schema = catalog.table_schemas.get(&table_id).cloned();
+                let mut failed = None;
+                let rows = rows
+                    .into_iter()
+                    .map_while(|(rowid, record)| match &table_schema {
+                        Some(table_schema) => match table_schema.apply(&record) {
+                            Ok(record) => Some((rowid, record)),
+                            Err(e) => {
+                                failed = Some(e);
+                                None
+                            }
+                        },
+                        None => Some((rowid, record)),
+                    });
+                BulkLoader::new(catalog.table_mut(table_id)?)
+                    .fill_factor(config.bulk_fill_factor)
+                    .sort_memory(config.sort_memory)
+                    .load_table(rows)?;
+                failed.map_or(Ok(()), Err)?;
+                sql::executor::fill_indexes(catalog, table_id)
+            })
+        })
+    }
+
+    /// Loads entries into an empty index, the same way `bulk_load` loads a table.
+    ///
+    /// # Parameters
+    /// * `index_id` - The index to load. It must be empty.
+    /// * `entries` - The serialized keys and the rowids they point to.
+    ///
+    /// # Errors
+    /// Returns an error if:
+    /// - The specified index does not exist or is not empty
+    /// - A key cannot be parsed, or an entry is there twice
+    /// - There are disk space or I/O issues
+    ///
+    /// # Example
+    /// ```rust
+    /// # fn main() -> std::io::Result<()> {
+    /// # let db = rqlite_engine::RQLite::create_in_memory(None)?;
+    /// # let table_id = db.create_table()?;
+    /// use rqlite_engine::utils::serialization::{SqliteValue, serialize_values};
+    /// use rqlite_engine::utils::cmp::KeyValue;
+    ///
+    /// let index_id = db.create_index(table_id)?;
+    /// let mut entries = Vec::new();
+    /// for rowid in 1..=100 {
+    ///     let mut key = Vec::new();
+    ///     serialize_values(&[SqliteValue::Integer(rowid * 10)], &mut key)?;
+    ///     entries.push((key, rowid));
+    /// }
+    /// db.bulk_load_index(index_id, entries)?;
+    /// assert!(db.index_find(index_id, &KeyValue::Integer(420))?.0);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn bulk_load_index<I>(&self, index_id: IndexId, entries: I) -> io::Result<()>
+    where
+        I: IntoIterator<Item = (Vec<u8>, i64)>,
+    {
+        let config = &self.shared.config;
+        self.write(|catalog| {
+            BulkLoader::new(catalog.index_mut(index_id)?)
+                .fill_factor(config.bulk_fill_factor)
+                .sort_memory(config.sort_memory)
+                .load_index(entries)
+        })
+    }
+
+    /// Creates an external sorter with the memory budget of the database (`sort_memory`),
+    /// for sorting more records than should be kept in memory, like the rows of an ORDER BY.
+    ///
+    /// # Example
+    /// ```rust
+    /// # fn main() -> std::io::Result<()> {
+    /// # let db = rqlite_engine::RQLite::create_in_memory(None)?;
+    /// use rqlite_engine::{Record, SortOrder, SqliteValue};
+    ///
+    /// let mut sorter = db.sorter().order(vec![SortOrder::Descending]);
+    /// for n in [1, 3, 2] {
+    ///     sorter.push(Record::with_values(vec![SqliteValue::Integer(n)]), Record::new())?;
+    /// }
+    /// let first = sorter.finish()?.next().unwrap()?;
+    /// assert!(matches!(first.0.values[0], SqliteValue::Integer(3)));
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn sorter(&self) -> ExternalSorter {
+        ExternalSorter::new(self.shared.config.sort_memory)
+    }
+
+    /// Gathers the statistics of every table and index and saves them in the stat table, like `ANALYZE` in SQLite.
     ///
-    /// # Parameters
-    /// * `path` - Path where the database file will be created.
-    /// * `config` - Optional configuration. If None, default values are used.
+    /// Every page of every tree is read (see tree/stats.rs), so this takes a while on a big database and blocks
+    /// the other writers meanwhile. The statistics are not kept up to date afterwards: run it again after
+    /// large changes. Inside a transaction the statistics are saved, or rolled back, with it.
     ///
     /// # Errors
-    /// Returns an error if:
-    /// - The database file cannot be created
-    /// - The configuration values are invalid
-    /// - There are disk I/O issues
-    ///
-    /// # Returns
-    /// A new RQLite instance connected to the created database.
+    /// Returns an error if a tree cannot be read or the stat table cannot be written.
     ///
     /// # Example
     /// ```rust
-    /// use rqlite_engine::{RQLite, RQLiteConfig};
-    ///
-    /// // Create with default configuration
-    /// let db = RQLite::create("my_database.db", None)?;
+    /// # fn main() -> std::io::Result<()> {
+    /// # let db = rqlite_engine::RQLite::create_in_memory(None)?;
+    /// use rqlite_engine::{Record, SqliteValue};
     ///
-    /// // Create with custom configuration
-    /// let config = RQLiteConfig {
-    ///     page_size: 8192,
-    ///     buffer_pool_size: 2000,
-    ///     ..Default::default()
-    /// };
-    /// let db = RQLite::create("my_database.db", Some(config))?;
+    /// let table_id = db.create_table()?;
+    /// for rowid in 1..=100 {
+    ///     db.table_insert(table_id, rowid, &Record::with_values(vec![SqliteValue::Integer(rowid)]))?;
+    /// }
+    /// db.analyze()?;
+    /// assert_eq!(db.table_stats(table_id)?.unwrap().row_count, 100);
+    /// # Ok(())
+    /// # }
     /// ```
-    pub fn create<P: AsRef<Path>>(path: P, config: Option<RQLiteConfig>) -> io::Result<Self> {
-        let config = config.unwrap_or_default();
-        
-        let pager = Pager::create(
-            path,
-            config.page_size,
-            Some(config.buffer_pool_size),
-            config.reserved_space,
-        )?;
+    pub fn analyze(&self) -> io::Result<()> {
+        let config = &self.shared.config;
+        self.write(|catalog| {
+            let mut table_ids: Vec<TableId> = catalog.tables.keys().copied().collect();
+            table_ids.sort_unstable();
+            let mut index_ids: Vec<IndexId> = catalog.indexes.keys().copied().collect();
+            index_ids.sort_unstable();
+
+            let mut gathered = Vec::new();
+            for table_id in table_ids {
+                let stats = catalog.table(table_id)?.analyze(config.stat_samples)?;
+                gathered.push((TreeType::Table, table_id, stats));
+            }
+            for index_id in index_ids {
+                let stats = catalog.index(index_id)?.analyze(config.stat_samples)?;
+                gathered.push((TreeType::Index, index_id, stats));
+            }
 
-        Ok(RQLite {
-            pager: Arc::new(pager),
-            tables: HashMap::new(),
-            indexes: HashMap::new(),
-            config,
-            next_table_id: 1,
-            next_index_id: 1,
+            let stat_table = match &mut catalog.stats {
+                Some(stat_table) => stat_table,
+                None => catalog.stats.insert(BTree::create(
+                    TreeType::Table,
+                    Arc::clone(&self.shared.pager),
+                    config.page_size,
+                    config.reserved_space,
+                    config.max_payload_fraction,
+                    config.min_payload_fraction,
+                )?),
+            };
+            for (tree_type, id, stats) in gathered {
+                save_stats(stat_table, tree_type, id, &stats)?;
+            }
+            Ok(())
         })
     }
 
-    /// Opens an existing database file.
+    /// Gets the statistics of a table saved by the last `analyze`.
     ///
     /// # Parameters
-    /// * `path` - Path to the existing database file.
-    /// * `config` - Optional configuration. If None, default values are used.
+    /// * `table_id` - The table to get the statistics of.
     ///
     /// # Errors
-    /// Returns an error if:
-    /// - The database file does not exist or cannot be opened
-    /// - The file is corrupted or has an invalid format
-    /// - There are disk I/O issues
+    /// Returns an error if the table does not exist or the stat table cannot be read.
     ///
     /// # Returns
-    /// An RQLite instance connected to the existing database.
+    /// The statistics, or `None` if the table was created after the last `analyze`.
+    pub fn table_stats(&self, table_id: TableId) -> io::Result<Option<TreeStats>> {
+        self.read_stats(TreeType::Table, table_id)
+    }
+
+    /// Gets the statistics of an index saved by the last `analyze`, histogram included.
+    ///
+    /// # Parameters
+    /// * `index_id` - The index to get the statistics of.
+    ///
+    /// # Errors
+    /// Returns an error if the index does not exist or the stat table cannot be read.
+    ///
+    /// # Returns
+    /// The statistics, or `None` if the index was created after the last `analyze`.
+    pub fn index_stats(&self, index_id: IndexId) -> io::Result<Option<TreeStats>> {
+        self.read_stats(TreeType::Index, index_id)
+    }
+
+    /// Creates a named sequence, a counter stored in the database that several tables can draw their ids from
+    /// (see tree/sequence.rs).
+    ///
+    /// # Parameters
+    /// * `name` - Name of the sequence, unique ignoring ASCII case.
+    /// * `start` - First value handed out.
+    /// * `increment` - Difference between two values, negative for a sequence that goes down.
+    /// * `cache` - How many values a connection reserves with each write. More means fewer writes,
+    ///   but the values reserved and not used are lost. 0 makes the sequence gap-free: every value is written
+    ///   in the transaction of the caller, and given again if it rolls back.
+    ///
+    /// # Errors
+    /// Returns an error if:
+    /// - The name is empty or the increment is 0
+    /// - There is already a sequence with that name (an `AlreadyExists` error)
+    /// - There are I/O issues
     ///
     /// # Example
     /// ```rust
-    /// use rqlite_engine::RQLite;
+    /// # fn main() -> std::io::Result<()> {
+    /// # let db = rqlite_engine::RQLite::create_in_memory(None)?;
+    /// use rqlite_engine::{Record, SqliteValue};
     ///
-    /// let db = RQLite::open("existing_database.db", None)?;
+    /// let orders = db.create_table()?;
+    /// let refunds = db.create_table()?;
+    /// db.create_sequence("documents", 1000, 1, 50)?;
+    ///
+    /// let record = Record::with_values(vec![SqliteValue::Integer(42)]);
+    /// db.table_insert(orders, db.next_value("documents")?, &record)?;
+    /// db.table_insert(refunds, db.next_value("documents")?, &record)?;
+    /// assert!(db.table_find(orders, 1000)?.is_some());
+    /// assert!(db.table_find(refunds, 1001)?.is_some());
+    /// # Ok(())
+    /// # }
     /// ```
-    pub fn open<P: AsRef<Path>>(path: P, config: Option<RQLiteConfig>) -> io::Result<Self> {
-        let config = config.unwrap_or_default();
-        
-        let pager = Pager::open(path, Some(config.buffer_pool_size))?;
-
-        // In a complete implementation, you would load table and index metadata
-        // from the database's system tables here. For now, we start with empty collections.
-        Ok(RQLite {
-            pager: Arc::new(pager),
-            tables: HashMap::new(),
-            indexes: HashMap::new(),
-            config,
-            next_table_id: 1,
-            next_index_id: 1,
+    pub fn create_sequence(
+        &self,
+        name: &str,
+        start: i64,
+        increment: i64,
+        cache: u32,
+    ) -> io::Result<()> {
+        let config = &self.shared.config;
+        self.write(|catalog| {
+            let sequences = match &mut catalog.sequences {
+                Some(sequences) => sequences,
+                None => catalog.sequences.insert(Catalog::new_tree(
+                    &self.shared.pager,
+                    config,
+                    TreeType::Table,
+                )?),
+            };
+            tree::sequence::create_sequence(sequences, name, start, increment, cache)
         })
     }
 
-    /// Creates a new table in the database.
+    /// Gets the next value of a sequence.
+    ///
+    /// The values come from the range this connection reserved, when there is one left, without writing anything.
+    /// Otherwise the next range is reserved, in the active transaction if there is one, or in a transaction of
+    /// its own. A gap-free sequence writes every value in the active transaction.
+    /// A range reserved in a transaction that is still open is only handed out in that transaction,
+    /// since it is forgotten if the transaction rolls back: other threads wait for it to end, as for any write.
+    ///
+    /// # Parameters
+    /// * `name` - Name of the sequence, ignoring ASCII case.
     ///
     /// # Errors
-    /// Returns an error if:
-    /// - The table cannot be created due to disk space issues
-    /// - There are I/O problems
+    /// Returns a `NotFound` error if there is no such sequence, an `InvalidInput` error if it went past
+    /// the end of the i64 range, a `Busy` error if another thread keeps its transaction open until the busy handler
+    /// gives up, or an error if there are I/O issues.
     ///
     /// # Returns
-    /// The unique identifier for the newly created table.
-    ///
-    /// # Example
-    /// ```rust
-    /// let table_id = db.create_table()?;
-    /// println!("Created table with ID: {}", table_id);
-    /// ```
-    pub fn create_table(&mut self) -> io::Result<TableId> {
-        let table_id = self.next_table_id;
-        self.next_table_id += 1;
-
-        let btree = BTree::create(
-            TreeType::Table,
-           Arc::clone(&self.pager),
-            self.config.page_size,
-            self.config.reserved_space,
-            self.config.max_payload_fraction,
-            self.config.min_payload_fraction,
-        )?;
+    /// The value, which nobody else was given.
+    pub fn next_value(&self, name: &str) -> io::Result<i64> {
+        let key = name.to_ascii_lowercase();
+        {
+            let mut catalog = self.catalog_mut();
+            if let Some(range) = catalog.sequence_ranges.get_mut(&key) {
+                // A range reserved in a transaction is forgotten if it rolls back, so only the owner hands it out
+                if !range.uncommitted || self.owns_transaction()? {
+                    if let Some(value) = range.take() {
+                        return Ok(value);
+                    }
+                }
+            }
+        }
 
-        self.tables.insert(table_id, btree);
-        Ok(table_id)
+        self.write(|catalog| {
+            // Another thread may have reserved a range while we waited for the catalog
+            if let Some(value) = catalog
+                .sequence_ranges
+                .get_mut(&key)
+                .and_then(SequenceRange::take)
+            {
+                return Ok(value);
+            }
+
+            let sequences = catalog
+                .sequences
+                .as_mut()
+                .ok_or_else(|| tree::sequence::no_such_sequence(name))?;
+            let (mut range, gap_free) = tree::sequence::reserve(sequences, name)?;
+            let value = range
+                .take()
+                .expect("A reserved range has at least one value");
+            if gap_free || range.remaining == 0 {
+                catalog.sequence_ranges.remove(&key);
+            } else {
+                catalog.sequence_ranges.insert(key, range);
+            }
+            Ok(value)
+        })
     }
 
-    /// Creates a new index on a table.
+    /// Sets the next value a sequence hands out, like `ALTER SEQUENCE RESTART WITH`.
+    /// The range this connection reserved is dropped, but other connections hand out the rest of theirs first.
     ///
     /// # Parameters
-    /// * `table_id` - The table to create the index on.
+    /// * `name` - Name of the sequence, ignoring ASCII case.
+    /// * `value` - The next value.
     ///
     /// # Errors
-    /// Returns an error if:
-    /// - The specified table does not exist
-    /// - The index cannot be created due to disk space issues
-    /// - There are I/O problems
+    /// Returns a `NotFound` error if there is no such sequence, or an error if there are I/O issues.
+    pub fn set_value(&self, name: &str, value: i64) -> io::Result<()> {
+        self.write(|catalog| {
+            let sequences = catalog
+                .sequences
+                .as_mut()
+                .ok_or_else(|| tree::sequence::no_such_sequence(name))?;
+            tree::sequence::set_value(sequences, name, value)?;
+            catalog.sequence_ranges.remove(&name.to_ascii_lowercase());
+            Ok(())
+        })
+    }
+
+    /// Drops a sequence.
+    ///
+    /// # Parameters
+    /// * `name` - Name of the sequence, ignoring ASCII case.
+    ///
+    /// # Errors
+    /// Returns a `NotFound` error if there is no such sequence, or an error if there are I/O issues.
+    pub fn drop_sequence(&self, name: &str) -> io::Result<()> {
+        self.write(|catalog| {
+            let sequences = catalog
+                .sequences
+                .as_mut()
+                .ok_or_else(|| tree::sequence::no_such_sequence(name))?;
+            tree::sequence::drop_sequence(sequences, name)?;
+            catalog.sequence_ranges.remove(&name.to_ascii_lowercase());
+            Ok(())
+        })
+    }
+
+    /// Begins a new transaction.
+    /// Operations performed outside of a transaction are committed one by one.
+    /// The transaction is shared by every handle of the database and holds the RESERVED lock of the file,
+    /// so writers of other processes must wait until it ends.
+    /// Prefer `transaction` or `with_transaction`, which cannot leave a transaction open by accident.
+    ///
+    /// # Errors
+    /// Returns an error if there are I/O issues or if a transaction is already active.
+    /// Returns a `Busy` error if another process is writing and the busy timeout expires.
     ///
     /// # Returns
-    /// The unique identifier for the newly created index.
+    /// Success indication.
     ///
     /// # Example
     /// ```rust
-    /// let table_id = db.create_table()?;
-    /// let index_id = db.create_index(table_id)?;
-    /// println!("Created index with ID: {}", index_id);
+    /// # fn main() -> std::io::Result<()> {
+    /// # let db = rqlite_engine::RQLite::create_in_memory(None)?;
+    /// db.begin_transaction()?;
+    /// // Perform multiple operations...
+    /// db.commit_transaction()?; // or db.rollback_transaction()?
+    /// # Ok(())
+    /// # }
     /// ```
-    pub fn create_index(&mut self, _table_id: TableId) -> io::Result<IndexId> {
-        let index_id = self.next_index_id;
-        self.next_index_id += 1;
-
-        let btree = BTree::create(
-            TreeType::Index,
-            self.pager.clone(), // Cloning the pager is not ideal, but necessary for now.
-            self.config.page_size,
-            self.config.reserved_space,
-            self.config.max_payload_fraction,
-            self.config.min_payload_fraction,
-        )?;
-
-        self.indexes.insert(index_id, btree);
-        Ok(index_id)
+    pub fn begin_transaction(&self) -> io::Result<()> {
+        let mut catalog = self.catalog_mut();
+        let pager = &self.shared.pager;
+        pager.begin_transaction()?;
+        // Another connection may have changed the schema since our last transaction
+        if let Err(e) = catalog.refresh(pager, &self.shared.config) {
+            let _ = pager.rollback_transaction();
+            return Err(e);
+        }
+        Ok(())
     }
 
-    /// Inserts a record into the specified table.
-    ///
-    /// # Parameters
-    /// * `table_id` - The table to insert into.
-    /// * `rowid` - The row identifier for the record.
-    /// * `record` - The record to insert.
+    /// Commits the current transaction, making all changes permanent.
+    /// Only the thread that began it can commit it.
     ///
     /// # Errors
     /// Returns an error if:
-    /// - The specified table does not exist
-    /// - The rowid already exists in the table
-    /// - There are disk space or I/O issues
+    /// - No transaction is active
+    /// - The transaction belongs to another thread (a `PermissionDenied` error)
+    /// - There are I/O issues during commit
     ///
     /// # Returns
     /// Success indication.
     ///
     /// # Example
     /// ```rust
-    /// use rqlite_engine::utils::serialization::SqliteValue;
-    /// use rqlite_engine::tree::record::Record;
-    ///
-    /// let table_id = db.create_table()?;
-    /// let record = Record::with_values(vec![
-    ///     SqliteValue::Integer(42),
-    ///     SqliteValue::String("Hello".to_string()),
-    /// ]);
+    /// # fn main() -> std::io::Result<()> {
+    /// # let db = rqlite_engine::RQLite::create_in_memory(None)?;
+    /// # let table_id = db.create_table()?;
+    /// # let record = rqlite_engine::Record::with_values(vec![rqlite_engine::SqliteValue::Integer(42)]);
+    /// db.begin_transaction()?;
     /// db.table_insert(table_id, 1, &record)?;
+    /// db.commit_transaction()?; // Changes are now permanent
+    /// # Ok(())
+    /// # }
     /// ```
-    pub fn table_insert(&mut self, table_id: TableId, rowid: i64, record: &Record) -> io::Result<()> {
-        let btree = self.tables.get_mut(&table_id).ok_or_else(|| {
-            io::Error::new(io::ErrorKind::NotFound, format!("Table {} not found", table_id))
-        })?;
-
-        btree.insert(rowid, record)
+    pub fn commit_transaction(&self) -> io::Result<()> {
+        let mut catalog = self.catalog_mut();
+        self.check_owner()?;
+        catalog.persist(&self.shared.pager, &self.shared.config)?;
+        let commit = self.shared.pager.commit_transaction_deferred()?;
+        catalog.commit();
+        drop(catalog);
+        self.shared.pager.sync_commit(commit)
     }
 
-    /// Finds a record in the specified table by its rowid.
-    ///
-    /// # Parameters
-    /// * `table_id` - The table to search in.
-    /// * `rowid` - The row identifier to search for.
+    /// Rolls back the current transaction, discarding all changes.
+    /// Only the thread that began it can roll it back.
     ///
     /// # Errors
     /// Returns an error if:
-    /// - The specified table does not exist
-    /// - There are I/O issues
+    /// - No transaction is active
+    /// - The transaction belongs to another thread (a `PermissionDenied` error)
+    /// - There are I/O issues during rollback
     ///
     /// # Returns
-    /// The record if found, or None if no record exists with the given rowid.
+    /// Success indication.
     ///
     /// # Example
     /// ```rust
-    /// match db.table_find(table_id, 1)? {
-    ///     Some(record) => println!("Found record with {} values", record.len()),
-    ///     None => println!("Record not found"),
-    /// }
+    /// # fn main() -> std::io::Result<()> {
+    /// # let db = rqlite_engine::RQLite::create_in_memory(None)?;
+    /// # let table_id = db.create_table()?;
+    /// # let record = rqlite_engine::Record::with_values(vec![rqlite_engine::SqliteValue::Integer(42)]);
+    /// db.begin_transaction()?;
+    /// db.table_insert(table_id, 1, &record)?;
+    /// db.rollback_transaction()?; // Changes are discarded
+    /// # Ok(())
+    /// # }
     /// ```
-    pub fn table_find(&self, table_id: TableId, rowid: i64) -> io::Result<Option<Record>> {
-        let btree = self.tables.get(&table_id).ok_or_else(|| {
-            io::Error::new(io::ErrorKind::NotFound, format!("Table {} not found", table_id))
-        })?;
-
-        btree.find(rowid)
+    pub fn rollback_transaction(&self) -> io::Result<()> {
+        let mut catalog = self.catalog_mut();
+        self.check_owner()?;
+        self.shared.pager.rollback_transaction()?;
+        catalog.rollback();
+        Ok(())
     }
 
-    /// Deletes a record from the specified table.
+    /// Opens a savepoint in the active transaction, like `SAVEPOINT name` in SQLite.
+    /// The changes made after it can be undone with `rollback_to_savepoint` without losing the rest of the transaction.
     ///
     /// # Parameters
-    /// * `table_id` - The table to delete from.
-    /// * `rowid` - The row identifier of the record to delete.
+    /// * `name` - Name of the savepoint. Names are case-insensitive and can be repeated, the innermost one wins.
     ///
     /// # Errors
-    /// Returns an error if:
-    /// - The specified table does not exist
-    /// - There are I/O issues
-    ///
-    /// # Returns
-    /// True if a record was deleted, false if no record existed with the given rowid.
+    /// Returns an `InvalidInput` error if no transaction is active,
+    /// or a `PermissionDenied` error if it belongs to another thread.
     ///
     /// # Example
     /// ```rust
-    /// if db.table_delete(table_id, 1)? {
-    ///     println!("Record deleted successfully");
-    /// } else {
-    ///     println!("Record not found");
-    /// }
+    /// # fn main() -> std::io::Result<()> {
+    /// # let db = rqlite_engine::RQLite::create_in_memory(None)?;
+    /// # let table_id = db.create_table()?;
+    /// # let record = rqlite_engine::Record::with_values(vec![rqlite_engine::SqliteValue::Integer(42)]);
+    /// db.begin_transaction()?;
+    /// db.table_insert(table_id, 1, &record)?;
+    ///
+    /// db.savepoint("second_batch")?;
+    /// db.table_insert(table_id, 2, &record)?;
+    /// db.rollback_to_savepoint("second_batch")?; // Only row 2 is undone
+    /// db.release_savepoint("second_batch")?;
+    ///
+    /// db.commit_transaction()?;
+    /// assert!(db.table_find(table_id, 1)?.is_some());
+    /// assert!(db.table_find(table_id, 2)?.is_none());
+    /// # Ok(())
+    /// # }
     /// ```
-    pub fn table_delete(&mut self, table_id: TableId, rowid: i64) -> io::Result<bool> {
-        let btree = self.tables.get_mut(&table_id).ok_or_else(|| {
-            io::Error::new(io::ErrorKind::NotFound, format!("Table {} not found", table_id))
-        })?;
+    pub fn savepoint(&self, name: &str) -> io::Result<()> {
+        let mut catalog = self.catalog_mut();
+        self.check_owner()?;
+        self.shared.pager.savepoint(name)?;
+        catalog.savepoint(name);
+        Ok(())
+    }
 
-        btree.delete(rowid)
+    /// Releases a savepoint and the ones opened after it, like `RELEASE name` in SQLite.
+    /// Their changes stay in the transaction.
+    ///
+    /// # Parameters
+    /// * `name` - Name of the savepoint.
+    ///
+    /// # Errors
+    /// Returns an `InvalidInput` error if no transaction is active or there is no savepoint with that name,
+    /// or a `PermissionDenied` error if the transaction belongs to another thread.
+    pub fn release_savepoint(&self, name: &str) -> io::Result<()> {
+        let mut catalog = self.catalog_mut();
+        self.check_owner()?;
+        self.shared.pager.release_savepoint(name)?;
+        catalog.release_savepoint(name);
+        Ok(())
     }
 
-    /// Inserts an entry into the specified index.
+    /// Undoes the changes made since a savepoint was opened, like `ROLLBACK TO name` in SQLite.
+    /// The savepoint stays open, and the ones opened after it are released.
     ///
     /// # Parameters
-    /// * `index_id` - The index to insert into.
-    /// * `key` - The key to index.
-    /// * `rowid` - The row identifier associated with this key.
+    /// * `name` - Name of the savepoint.
     ///
     /// # Errors
-    /// Returns an error if:
-    /// - The specified index does not exist
-    /// - There are disk space or I/O issues
+    /// Returns an `InvalidInput` error if no transaction is active or there is no savepoint with that name,
+    /// a `PermissionDenied` error if the transaction belongs to another thread,
+    /// or an I/O error if the original pages cannot be restored.
+    pub fn rollback_to_savepoint(&self, name: &str) -> io::Result<()> {
+        let mut catalog = self.catalog_mut();
+        self.check_owner()?;
+        self.shared.pager.rollback_to_savepoint(name)?;
+        catalog.rollback_to_savepoint(name);
+        Ok(())
+    }
+
+    /// Begins a transaction owned by the returned guard.
+    /// The transaction is rolled back when the guard is dropped without calling `Transaction::commit`,
+    /// so returning early with `?` cannot leave it open.
+    ///
+    /// # Errors
+    /// Returns an `InvalidInput` error if a transaction is already active,
+    /// or a `Busy` error if another process is writing and the busy timeout expires.
     ///
     /// # Returns
-    /// Success indication.
+    /// The transaction guard.
     ///
     /// # Example
     /// ```rust
-    /// use rqlite_engine::utils::serialization::{SqliteValue, serialize_values};
-    ///
-    /// let index_id = db.create_index(table_id)?;
-    /// 
-    /// // Create a key payload (in this case, an integer key)
-    /// let mut key_payload = Vec::new();
-    /// serialize_values(&[SqliteValue::Integer(42)], &mut key_payload)?;
-    /// 
-    /// db.index_insert(index_id, &key_payload, 1)?;
+    /// # fn main() -> std::io::Result<()> {
+    /// # let db = rqlite_engine::RQLite::create_in_memory(None)?;
+    /// # let table_id = db.create_table()?;
+    /// # let record = rqlite_engine::Record::with_values(vec![rqlite_engine::SqliteValue::Integer(42)]);
+    /// let tx = db.transaction()?;
+    /// tx.table_insert(table_id, 1, &record)?;
+    /// tx.commit()?;
+    /// # Ok(())
+    /// # }
     /// ```
-    pub fn index_insert(&mut self, index_id: IndexId, key: &[u8], rowid: i64) -> io::Result<()> {
-        let btree = self.indexes.get_mut(&index_id).ok_or_else(|| {
-            io::Error::new(io::ErrorKind::NotFound, format!("Index {} not found", index_id))
-        })?;
-
-        btree.insert_index(key, rowid)
+    pub fn transaction(&self) -> io::Result<Transaction<'_>> {
+        Transaction::begin(self)
     }
 
-    /// Finds an entry in the specified index.
+    /// Runs a closure inside a transaction, committing it when the closure returns `Ok`
+    /// and rolling it back when it returns `Err`.
     ///
     /// # Parameters
-    /// * `index_id` - The index to search in.
-    /// * `key` - The key to search for.
+    /// * `f` - The work to do. It receives the transaction to run its operations on.
     ///
     /// # Errors
-    /// Returns an error if:
-    /// - The specified index does not exist
-    /// - There are I/O issues
+    /// Returns the error of the closure, the error of the commit, or an `InvalidInput` error
+    /// if a transaction is already active.
     ///
     /// # Returns
-    /// A tuple containing:
-    /// - True if the key was found, false otherwise
-    /// - The leaf page number where the key is located
-    /// - The index within that page
+    /// The value returned by the closure.
     ///
     /// # Example
     /// ```rust
-    /// use rqlite_engine::utils::cmp::KeyValue;
-    ///
-    /// let key = KeyValue::Integer(42);
-    /// let (found, page, index) = db.index_find(index_id, &key)?;
-    /// if found {
-    ///     println!("Key found at page {} index {}", page, index);
-    /// }
+    /// # fn main() -> std::io::Result<()> {
+    /// # let db = rqlite_engine::RQLite::create_in_memory(None)?;
+    /// # let record = rqlite_engine::Record::with_values(vec![rqlite_engine::SqliteValue::Integer(42)]);
+    /// let table_id = db.with_transaction(|tx| {
+    ///     let table_id = tx.create_table()?;
+    ///     tx.table_insert(table_id, 1, &record)?;
+    ///     Ok(table_id)
+    /// })?;
+    /// # Ok(())
+    /// # }
     /// ```
-    pub fn index_find(&self, index_id: IndexId, key: &KeyValue) -> io::Result<(bool, u32, u16)> {
-        let btree = self.indexes.get(&index_id).ok_or_else(|| {
-            io::Error::new(io::ErrorKind::NotFound, format!("Index {} not found", index_id))
-        })?;
+    pub fn with_transaction<R, F>(&self, f: F) -> io::Result<R>
+    where
+        F: FnOnce(&Transaction<'_>) -> io::Result<R>,
+    {
+        let tx = self.transaction()?;
+        // On error the guard is dropped here, which rolls the transaction back
+        let value = f(&tx)?;
+        tx.commit()?;
+        Ok(value)
+    }
+
+    /// Runs the writes of a `Transaction` guard. The guard can be sent to or shared with other threads,
+    /// so inside this scope `write` lets the current thread into the active transaction whatever thread began it.
+    pub(crate) fn guarded<R>(&self, op: impl FnOnce() -> io::Result<R>) -> io::Result<R> {
+        /// Restores the flag even if the operation panics.
+        struct Reset(bool);
+        impl Drop for Reset {
+            fn drop(&mut self) {
+                GUARDED.with(|guarded| guarded.set(self.0));
+            }
+        }
 
-        btree.find_index_key(key)
+        let _reset = Reset(GUARDED.with(|guarded| guarded.replace(true)));
+        op()
     }
 
-    /// Deletes an entry from the specified index.
+    /// Runs SQL statements, separated by semicolons. Outside of an explicit transaction each statement is
+    /// a transaction of its own, and inside one a statement that fails undoes its own changes and only those.
+    /// `BEGIN`, `COMMIT` and `ROLLBACK` work like `begin_transaction` and friends.
     ///
     /// # Parameters
-    /// * `index_id` - The index to delete from.
-    /// * `key` - The key to delete.
+    /// * `sql` - The statements.
+    /// * `params` - Values for the `?` and `?N` parameters of the statements, numbered across all of them.
     ///
     /// # Errors
     /// Returns an error if:
-    /// - The specified index does not exist
+    /// - The SQL is not valid, or names a table, a column or a function that does not exist (see `sql_error`)
+    /// - The number of parameters does not match the statements
+    /// - A constraint fails: NOT NULL, UNIQUE or PRIMARY KEY (an `AlreadyExists` error)
     /// - There are I/O issues
     ///
+    /// The statements before the one that fails keep their changes.
+    ///
     /// # Returns
-    /// True if an entry was deleted, false if no entry existed with the given key.
+    /// The number of rows inserted, updated or deleted by all the statements.
     ///
     /// # Example
     /// ```rust
-    /// use rqlite_engine::utils::cmp::KeyValue;
+    /// # fn main() -> std::io::Result<()> {
+    /// # let db = rqlite_engine::RQLite::create_in_memory(None)?;
+    /// use rqlite_engine::SqliteValue;
     ///
-    /// let key = KeyValue::Integer(42);
-    /// if db.index_delete(index_id, &key)? {
-    ///     println!("Index entry deleted successfully");
-    /// } else {
-    ///     println!("Index entry not found");
-    /// }
+    /// db.execute("CREATE TABLE users (id INTEGER PRIMARY KEY, name TEXT NOT NULL)", &[])?;
+    /// let inserted = db.execute(
+    ///     "INSERT INTO users (name) VALUES (?), (?)",
+    ///     &[SqliteValue::String("ann".to_string()), SqliteValue::String("bob".to_string())],
+    /// )?;
+    /// assert_eq!(inserted, 2);
+    /// assert_eq!(db.execute("DELETE FROM users WHERE id = 1", &[])?, 1);
+    /// # Ok(())
+    /// # }
     /// ```
-    pub fn index_delete(&mut self, index_id: IndexId, key: &KeyValue) -> io::Result<bool> {
-        let btree = self.indexes.get_mut(&index_id).ok_or_else(|| {
-            io::Error::new(io::ErrorKind::NotFound, format!("Index {} not found", index_id))
-        })?;
-
-        btree.delete_index(key)
+    pub fn execute(&self, sql: &str, params: &[SqliteValue]) -> io::Result<usize> {
+        self.run_sql(sql, params, false, false)
+            .map(|(changes, _)| changes)
     }
 
-    /// Begins a new transaction.
+    /// Runs a single SQL statement like `execute`, and returns its rows.
+    ///
+    /// A query sees the changes of the active transaction on the thread that began it. On other threads it reads
+    /// a snapshot of the last commit, like `table_find`.
+    ///
+    /// # Parameters
+    /// * `sql` - The statement, usually a SELECT. A trailing semicolon is allowed.
+    /// * `params` - Values for the `?` and `?N` parameters of the statement.
     ///
     /// # Errors
-    /// Returns an error if there are I/O issues or if a transaction is already active.
+    /// Returns the same errors as `execute`, and an `InvalidInput` error if `sql` holds more than one
+    /// statement: use `execute` to run those.
     ///
     /// # Returns
-    /// Success indication.
+    /// The rows of the statement, which are none if it is not a SELECT.
     ///
     /// # Example
     /// ```rust
-    /// db.begin_transaction()?;
-    /// // Perform multiple operations...
-    /// db.commit_transaction()?; // or db.rollback_transaction()?
+    /// # fn main() -> std::io::Result<()> {
+    /// # let db = rqlite_engine::RQLite::create_in_memory(None)?;
+    /// use rqlite_engine::SqliteValue;
+    ///
+    /// db.execute("CREATE TABLE t (a INTEGER, b TEXT)", &[])?;
+    /// db.execute("INSERT INTO t VALUES (1, 'x'), (2, 'y'), (3, 'x')", &[])?;
+    ///
+    /// let rows = db.query("SELECT b, count(*) FROM t WHERE a > ? GROUP BY b ORDER BY b", &[SqliteValue::Integer(1)])?;
+    /// assert_eq!(rows.len(), 2);
+    /// assert_eq!(rows.get(0).unwrap().values, vec![SqliteValue::String("x".to_string()), SqliteValue::Integer(1)]);
+    /// # Ok(())
+    /// # }
     /// ```
-    pub fn begin_transaction(&self) -> io::Result<()> {
-        self.pager.begin_transaction()
+    pub fn query(&self, sql: &str, params: &[SqliteValue]) -> io::Result<Rows> {
+        self.run_sql(sql, params, false, true).map(|(_, rows)| rows)
     }
 
-    /// Commits the current transaction, making all changes permanent.
+    /// Runs SQL statements for `execute` and `query`, or for the same methods of a `Transaction` guard.
+    /// The guard reads the live trees from any thread, and ends its transaction itself,
+    /// so `BEGIN`, `COMMIT` and `ROLLBACK` are refused there. With `single`, as for `query`,
+    /// the SQL must hold at most one statement.
+    ///
+    /// # Returns
+    /// The number of rows changed by all the statements, and the rows of the last one.
+    pub(crate) fn run_sql(
+        &self,
+        sql: &str,
+        params: &[SqliteValue],
+        guarded: bool,
+        single: bool,
+    ) -> io::Result<(usize, Rows)> {
+        let (statements, parameters) = sql::parser::parse(sql)?;
+        if single && statements.len() > 1 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "Expected a single statement, got {}; use execute to run several",
+                    statements.len()
+                ),
+            ));
+        }
+        if params.len() != parameters {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Expected {} parameters, got {}", parameters, params.len()),
+            ));
+        }
+
+        let executor = sql::executor::Executor {
+            sql,
+            params,
+            pager: &self.shared.pager,
+            config: &self.shared.config,
+        };
+        let mut changes = 0;
+        let mut rows = Rows::default();
+        for statement in &statements {
+            rows = Rows::default();
+            match statement {
+                Statement::Begin | Statement::Commit | Statement::Rollback if guarded => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        "Use Transaction::commit or Transaction::rollback to end a transaction",
+                    ));
+                }
+                Statement::Begin => self.begin_transaction()?,
+                Statement::Commit => self.commit_transaction()?,
+                Statement::Rollback => self.rollback_transaction()?,
+                Statement::Select(select) => {
+                    let pager = &self.shared.pager;
+                    if guarded || pager.owns_transaction()? {
+                        let catalog = self.catalog();
+                        pager.begin_read()?;
+                        let result = executor.select(&catalog, select);
+                        let released = pager.end_read();
+                        rows = result?;
+                        released?;
+                    } else {
+                        // Commits happen under the catalog lock, so the committed roots always match the snapshot
+                        let snapshot = self.catalog().snapshot(pager)?;
+                        rows = executor.select(&snapshot, select)?;
+                    }
+                }
+                statement => {
+                    changes += self.write(|catalog| {
+                        self.statement(catalog, |catalog| executor.execute(catalog, statement))
+                    })?;
+                }
+            }
+        }
+        Ok((changes, rows))
+    }
+
+    /// Forces all pending changes to be written to disk.
     ///
     /// # Errors
-    /// Returns an error if:
-    /// - No transaction is active
-    /// - There are I/O issues during commit
+    /// Returns an error if there are I/O issues during the flush operation.
     ///
     /// # Returns
     /// Success indication.
     ///
     /// # Example
     /// ```rust
-    /// db.begin_transaction()?;
+    /// # fn main() -> std::io::Result<()> {
+    /// # let db = rqlite_engine::RQLite::create_in_memory(None)?;
+    /// # let table_id = db.create_table()?;
+    /// # let record = rqlite_engine::Record::with_values(vec![rqlite_engine::SqliteValue::Integer(42)]);
     /// db.table_insert(table_id, 1, &record)?;
-    /// db.commit_transaction()?; // Changes are now permanent
+    /// db.flush()?; // Ensure changes are written to disk
+    /// # Ok(())
+    /// # }
     /// ```
-    pub fn commit_transaction(&self) -> io::Result<()> {
-        self.pager.commit_transaction()
+    pub fn flush(&self) -> io::Result<()> {
+        self.shared.pager.flush()
     }
 
-    /// Rolls back the current transaction, discarding all changes.
+    /// Sets how long to keep retrying when another process holds a conflicting lock on the database file.
+    /// It replaces any busy handler set before.
     ///
-    /// # Errors
-    /// Returns an error if:
-    /// - No transaction is active
-    /// - There are I/O issues during rollback
+    /// # Parameters
+    /// * `timeout` - Total time to keep retrying. Zero fails right away with a `Busy` error.
     ///
-    /// # Returns
-    /// Success indication.
+    /// # Errors
+    /// Returns an error if the pager cannot be accessed.
     ///
     /// # Example
     /// ```rust
-    /// db.begin_transaction()?;
-    /// db.table_insert(table_id, 1, &record)?;
-    /// db.rollback_transaction()?; // Changes are discarded
+    /// # fn main() -> std::io::Result<()> {
+    /// # let db = rqlite_engine::RQLite::create_in_memory(None)?;
+    /// use std::time::Duration;
+    ///
+    /// db.set_busy_timeout(Duration::from_millis(500))?;
+    /// # Ok(())
+    /// # }
     /// ```
-    pub fn rollback_transaction(&self) -> io::Result<()> {
-        self.pager.rollback_transaction()
+    pub fn set_busy_timeout(&self, timeout: Duration) -> io::Result<()> {
+        let handler = if timeout.is_zero() {
+            None
+        } else {
+            Some(timeout_handler(timeout))
+        };
+        self.shared.pager.set_busy_handler(handler)
     }
 
-    /// Forces all pending changes to be written to disk.
+    /// Sets a custom busy handler, called when another process holds a conflicting lock on the database file.
+    /// It receives the number of times it was already called for the same lock, and returns `true`
+    /// to retry or `false` to give up with a `Busy` error. It replaces the busy timeout.
     ///
-    /// # Errors
-    /// Returns an error if there are I/O issues during the flush operation.
+    /// # Parameters
+    /// * `handler` - The busy handler.
     ///
-    /// # Returns
-    /// Success indication.
+    /// # Errors
+    /// Returns an error if the pager cannot be accessed.
     ///
     /// # Example
     /// ```rust
-    /// db.table_insert(table_id, 1, &record)?;
-    /// db.flush()?; // Ensure changes are written to disk
+    /// # fn main() -> std::io::Result<()> {
+    /// # let db = rqlite_engine::RQLite::create_in_memory(None)?;
+    /// // Retry up to ten times, yielding to other threads in between
+    /// db.set_busy_handler(|retries| {
+    ///     std::thread::yield_now();
+    ///     retries < 10
+    /// })?;
+    /// # Ok(())
+    /// # }
     /// ```
-    pub fn flush(&self) -> io::Result<()> {
-        self.pager.flush()
+    pub fn set_busy_handler<F>(&self, handler: F) -> io::Result<()>
+    where
+        F: Fn(u32) -> bool + Send + Sync + 'static,
+    {
+        self.shared.pager.set_busy_handler(Some(Arc::new(handler)))
     }
 
     /// Closes the database, flushing any pending changes.
+    /// If other handles to the same database are still alive, this only flushes
+    /// and the database stays open for them.
     ///
     /// # Errors
     /// Returns an error if there are I/O issues during the close operation.
@@ -548,21 +2488,26 @@ impl RQLite {
     ///
     /// # Example
     /// ```rust
+    /// # fn main() -> std::io::Result<()> {
+    /// # let db = rqlite_engine::RQLite::create_in_memory(None)?;
     /// // Perform database operations...
     /// db.close()?; // Properly close the database
+    /// # Ok(())
+    /// # }
     /// ```
     pub fn close(self) -> io::Result<()> {
-        // Since pager is in Arc, we need to extract it
-        match Arc::try_unwrap(self.pager) {
+        // Other handles may still be using the database, in that case we only flush
+        let shared = match Arc::try_unwrap(self.shared) {
+            Ok(shared) => shared,
+            Err(shared) => return shared.pager.flush(),
+        };
+
+        // The B-Trees hold their own references to the pager, release them first
+        drop(shared.catalog);
+        match Arc::try_unwrap(shared.pager) {
             Ok(pager) => pager.close(),
-            Err(_) => {
-                // If there are still references to the pager, just flush
-                // This shouldn't happen in normal usage, but provides a fallback
-                Ok(())
-            }
+            Err(pager) => pager.flush(),
         }
-    
-    
     }
 
     /// Gets the current database configuration.
@@ -572,12 +2517,16 @@ impl RQLite {
     ///
     /// # Example
     /// ```rust
+    /// # fn main() -> std::io::Result<()> {
+    /// # let db = rqlite_engine::RQLite::create_in_memory(None)?;
     /// let config = db.config();
     /// println!("Page size: {} bytes", config.page_size);
     /// println!("Buffer pool size: {} pages", config.buffer_pool_size);
+    /// # Ok(())
+    /// # }
     /// ```
     pub fn config(&self) -> &RQLiteConfig {
-        &self.config
+        &self.shared.config
     }
 
     /// Gets the total number of pages in the database.
@@ -590,11 +2539,15 @@ impl RQLite {
     ///
     /// # Example
     /// ```rust
+    /// # fn main() -> std::io::Result<()> {
+    /// # let db = rqlite_engine::RQLite::create_in_memory(None)?;
     /// let page_count = db.page_count()?;
     /// println!("Database has {} pages", page_count);
+    /// # Ok(())
+    /// # }
     /// ```
     pub fn page_count(&self) -> io::Result<u32> {
-        self.pager.page_count()
+        self.shared.pager.page_count()
     }
 
     /// Gets information about the tables in the database.
@@ -604,14 +2557,18 @@ impl RQLite {
     ///
     /// # Example
     /// ```rust
+    /// # fn main() -> std::io::Result<()> {
+    /// # let db = rqlite_engine::RQLite::create_in_memory(None)?;
     /// let tables = db.list_tables();
     /// println!("Database contains {} tables", tables.len());
     /// for table_id in tables {
     ///     println!("Table ID: {}", table_id);
     /// }
+    /// # Ok(())
+    /// # }
     /// ```
     pub fn list_tables(&self) -> Vec<TableId> {
-        self.tables.keys().cloned().collect()
+        self.catalog().tables.keys().cloned().collect()
     }
 
     /// Gets information about the indexes in the database.
@@ -621,14 +2578,19 @@ impl RQLite {
     ///
     /// # Example
     /// ```rust
+    /// # fn main() -> std::io::Result<()> {
+    /// # let db = rqlite_engine::RQLite::create_in_memory(None)?;
+    /// # let table_id = db.create_table()?;
     /// let indexes = db.list_indexes();
     /// println!("Database contains {} indexes", indexes.len());
     /// for index_id in indexes {
     ///     println!("Index ID: {}", index_id);
     /// }
+    /// # Ok(())
+    /// # }
     /// ```
     pub fn list_indexes(&self) -> Vec<IndexId> {
-        self.indexes.keys().cloned().collect()
+        self.catalog().indexes.keys().cloned().collect()
     }
 
     /// Checks if a table exists in the database.
@@ -641,14 +2603,19 @@ impl RQLite {
     ///
     /// # Example
     /// ```rust
+    /// # fn main() -> std::io::Result<()> {
+    /// # let db = rqlite_engine::RQLite::create_in_memory(None)?;
+    /// # let table_id = db.create_table()?;
     /// if db.table_exists(table_id) {
     ///     println!("Table {} exists", table_id);
     /// } else {
     ///     println!("Table {} does not exist", table_id);
     /// }
+    /// # Ok(())
+    /// # }
     /// ```
     pub fn table_exists(&self, table_id: TableId) -> bool {
-        self.tables.contains_key(&table_id)
+        self.catalog().tables.contains_key(&table_id)
     }
 
     /// Checks if an index exists in the database.
@@ -661,14 +2628,20 @@ impl RQLite {
     ///
     /// # Example
     /// ```rust
+    /// # fn main() -> std::io::Result<()> {
+    /// # let db = rqlite_engine::RQLite::create_in_memory(None)?;
+    /// # let table_id = db.create_table()?;
+    /// # let index_id = db.create_index(table_id)?;
     /// if db.index_exists(index_id) {
     ///     println!("Index {} exists", index_id);
     /// } else {
     ///     println!("Index {} does not exist", index_id);
     /// }
+    /// # Ok(())
+    /// # }
     /// ```
     pub fn index_exists(&self, index_id: IndexId) -> bool {
-        self.indexes.contains_key(&index_id)
+        self.catalog().indexes.contains_key(&index_id)
     }
 
     /// Gets the root page number for a specific table.
@@ -684,15 +2657,272 @@ impl RQLite {
     ///
     /// # Example
     /// ```rust
+    /// # fn main() -> std::io::Result<()> {
+    /// # let db = rqlite_engine::RQLite::create_in_memory(None)?;
+    /// # let table_id = db.create_table()?;
     /// let root_page = db.table_root_page(table_id)?;
     /// println!("Table {} root page: {}", table_id, root_page);
+    /// # Ok(())
+    /// # }
     /// ```
     pub fn table_root_page(&self, table_id: TableId) -> io::Result<u32> {
-        let btree = self.tables.get(&table_id).ok_or_else(|| {
-            io::Error::new(io::ErrorKind::NotFound, format!("Table {} not found", table_id))
-        })?;
+        Ok(self.catalog().table(table_id)?.root_page())
+    }
+
+    /// Gets the columns of a table.
+    ///
+    /// # Parameters
+    /// * `table_id` - The table ID to get the columns of.
+    ///
+    /// # Errors
+    /// Returns an error if the table does not exist.
+    ///
+    /// # Returns
+    /// The schema of the table, or `None` if it was created without columns.
+    ///
+    /// # Example
+    /// ```rust
+    /// # fn main() -> std::io::Result<()> {
+    /// # let db = rqlite_engine::RQLite::create_in_memory(None)?;
+    /// use rqlite_engine::{Affinity, Column};
+    ///
+    /// let table_id = db.create_table_with_columns(vec![Column::new("price", "DECIMAL(10,2)")])?;
+    /// let schema = db.table_schema(table_id)?.unwrap();
+    /// assert_eq!(schema.columns()[0].affinity(), Affinity::Numeric);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn table_schema(&self, table_id: TableId) -> io::Result<Option<TableSchema>> {
+        let catalog = self.catalog();
+        catalog.table(table_id)?;
+        Ok(catalog.table_schemas.get(&table_id).cloned())
+    }
+
+    /// Adds a column at the end of a table, like `ALTER TABLE ADD COLUMN`.
+    ///
+    /// Only the schema changes, the rows are not rewritten: the rows stored before read the DEFAULT
+    /// of the new column, as in SQLite. That is why a NOT NULL column needs a default value.
+    ///
+    /// # Parameters
+    /// * `table_id` - The table to add the column to.
+    /// * `column` - The new column.
+    ///
+    /// # Errors
+    /// Returns an error if:
+    /// - The table does not exist, or was created without columns
+    /// - A column with the same name exists, or the column is NOT NULL with a NULL default
+    /// - There are I/O issues
+    ///
+    /// # Example
+    /// ```rust
+    /// # fn main() -> std::io::Result<()> {
+    /// # let db = rqlite_engine::RQLite::create_in_memory(None)?;
+    /// use rqlite_engine::{Column, Record, SqliteValue};
+    ///
+    /// let table_id = db.create_table_with_columns(vec![Column::new("name", "TEXT")])?;
+    /// db.table_insert(table_id, 1, &Record::with_values(vec![SqliteValue::String("ann".to_string())]))?;
+    ///
+    /// db.add_column(table_id, Column::new("score", "INTEGER").default_value(SqliteValue::Integer(0)))?;
+    /// let row = db.table_find(table_id, 1)?.unwrap();
+    /// assert_eq!(row.values[1], SqliteValue::Integer(0));
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn add_column(&self, table_id: TableId, column: Column) -> io::Result<()> {
+        self.write(|catalog| catalog.table_schema_mut(table_id)?.add_column(column))
+    }
+
+    /// Renames a column of a table, like `ALTER TABLE RENAME COLUMN`. Only the schema changes.
+    ///
+    /// # Parameters
+    /// * `table_id` - The table of the column.
+    /// * `name` - Current name of the column, ignoring ASCII case.
+    /// * `new_name` - New name of the column.
+    ///
+    /// # Errors
+    /// Returns an error if:
+    /// - The table does not exist, or was created without columns
+    /// - There is no such column, or the new name is taken by another column
+    /// - There are I/O issues
+    ///
+    /// # Example
+    /// ```rust
+    /// # fn main() -> std::io::Result<()> {
+    /// # let db = rqlite_engine::RQLite::create_in_memory(None)?;
+    /// use rqlite_engine::Column;
+    ///
+    /// let table_id = db.create_table_with_columns(vec![Column::new("nmae", "TEXT")])?;
+    /// db.rename_column(table_id, "nmae", "name")?;
+    /// assert_eq!(db.table_schema(table_id)?.unwrap().column_index("name"), Some(0));
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn rename_column(&self, table_id: TableId, name: &str, new_name: &str) -> io::Result<()> {
+        self.write(|catalog| {
+            catalog
+                .table_schema_mut(table_id)?
+                .rename_column(name, new_name)?;
+            // Indexes created with SQL name their columns
+            for index_schema in catalog.index_schemas.values_mut() {
+                if index_schema.table_id != table_id {
+                    continue;
+                }
+                for column in &mut index_schema.columns {
+                    if column.eq_ignore_ascii_case(name) {
+                        *column = new_name.to_string();
+                    }
+                }
+            }
+            Ok(())
+        })
+    }
+
+    /// Drops a column of a table, like `ALTER TABLE DROP COLUMN`.
+    ///
+    /// Unlike adding or renaming a column, this rewrites the rows of the table that have a value for the column,
+    /// as the values after it move one place to the left. It takes as long as updating every row.
+    ///
+    /// # Parameters
+    /// * `table_id` - The table of the column.
+    /// * `name` - Name of the column, ignoring ASCII case.
+    ///
+    /// # Errors
+    /// Returns an error if:
+    /// - The table does not exist, or was created without columns
+    /// - There is no such column, it is the only column of the table, the INTEGER PRIMARY KEY,
+    ///   or an index created with SQL uses it
+    /// - There are I/O issues
+    ///
+    /// # Example
+    /// ```rust
+    /// # fn main() -> std::io::Result<()> {
+    /// # let db = rqlite_engine::RQLite::create_in_memory(None)?;
+    /// use rqlite_engine::{Column, Record, SqliteValue};
+    ///
+    /// let table_id = db.create_table_with_columns(vec![Column::new("a", ""), Column::new("b", "")])?;
+    /// db.table_insert(table_id, 1, &Record::with_values(vec![SqliteValue::Integer(1), SqliteValue::Integer(2)]))?;
+    ///
+    /// db.drop_column(table_id, "a")?;
+    /// assert_eq!(db.table_find(table_id, 1)?.unwrap().values, vec![SqliteValue::Integer(2)]);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn drop_column(&self, table_id: TableId, name: &str) -> io::Result<()> {
+        self.write(|catalog| {
+            let mut table_schema = catalog.table_schema_mut(table_id)?.clone();
+            let indexed = catalog.index_schemas.values().find(|index_schema| {
+                index_schema.table_id == table_id
+                    && index_schema
+                        .columns
+                        .iter()
+                        .any(|column| column.eq_ignore_ascii_case(name))
+            });
+            if let Some(index_schema) = indexed {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!(
+                        "Cannot drop column {}, index {} uses it",
+                        name, index_schema.name
+                    ),
+                ));
+            }
+            let position = table_schema.drop_column(name)?;
+
+            // Rows written before the column was added do not have it, and stay as they are
+            let btree = catalog.table_mut(table_id)?;
+            let mut rowids = Vec::new();
+            for row in btree.scan()? {
+                let (rowid, record) = row?;
+                if record.len() > position {
+                    rowids.push(rowid);
+                }
+            }
+            for rowid in rowids {
+                let Some(mut record) = btree.find(rowid)? else {
+                    continue;
+                };
+                record.values.remove(position);
+                btree.delete(rowid)?;
+                btree.insert(rowid, &record)?;
+            }
+
+            catalog.table_schemas.insert(table_id, table_schema);
+            Ok(())
+        })
+    }
+
+    /// Names a table, or renames it, like `ALTER TABLE RENAME TO`. Only the catalog changes.
+    /// Tables have no name until they are given one, and are still known by their ID.
+    ///
+    /// # Parameters
+    /// * `table_id` - The table to name.
+    /// * `name` - New name of the table.
+    ///
+    /// # Errors
+    /// Returns an error if:
+    /// - The table does not exist
+    /// - The name is empty, or another table has it (ignoring ASCII case)
+    /// - There are I/O issues
+    ///
+    /// # Example
+    /// ```rust
+    /// # fn main() -> std::io::Result<()> {
+    /// # let db = rqlite_engine::RQLite::create_in_memory(None)?;
+    /// let table_id = db.create_table()?;
+    /// db.rename_table(table_id, "users")?;
+    /// assert_eq!(db.find_table("USERS"), Some(table_id));
+    /// assert_eq!(db.table_name(table_id)?.as_deref(), Some("users"));
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn rename_table(&self, table_id: TableId, name: &str) -> io::Result<()> {
+        if name.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "A table name cannot be empty",
+            ));
+        }
+
+        self.write(|catalog| {
+            catalog.table(table_id)?;
+            match catalog.find_table(name) {
+                Some(other) if other != table_id => Err(io::Error::new(
+                    io::ErrorKind::AlreadyExists,
+                    format!("There is already another table named {}", name),
+                )),
+                _ => {
+                    catalog.table_names.insert(table_id, name.to_string());
+                    Ok(())
+                }
+            }
+        })
+    }
+
+    /// Gets the name of a table.
+    ///
+    /// # Parameters
+    /// * `table_id` - The table ID to get the name of.
+    ///
+    /// # Errors
+    /// Returns an error if the table does not exist.
+    ///
+    /// # Returns
+    /// The name of the table, or `None` if it was never given one (see `rename_table`).
+    pub fn table_name(&self, table_id: TableId) -> io::Result<Option<String>> {
+        let catalog = self.catalog();
+        catalog.table(table_id)?;
+        Ok(catalog.table_names.get(&table_id).cloned())
+    }
 
-        Ok(btree.root_page())
+    /// Finds a table by name, ignoring ASCII case.
+    ///
+    /// # Parameters
+    /// * `name` - Name of the table.
+    ///
+    /// # Returns
+    /// The ID of the table, or `None` if no table has that name.
+    pub fn find_table(&self, name: &str) -> Option<TableId> {
+        self.catalog().find_table(name)
     }
 
     /// Gets the root page number for a specific index.
@@ -708,20 +2938,22 @@ impl RQLite {
     ///
     /// # Example
     /// ```rust
+    /// # fn main() -> std::io::Result<()> {
+    /// # let db = rqlite_engine::RQLite::create_in_memory(None)?;
+    /// # let table_id = db.create_table()?;
+    /// # let index_id = db.create_index(table_id)?;
     /// let root_page = db.index_root_page(index_id)?;
     /// println!("Index {} root page: {}", index_id, root_page);
+    /// # Ok(())
+    /// # }
     /// ```
     pub fn index_root_page(&self, index_id: IndexId) -> io::Result<u32> {
-        let btree = self.indexes.get(&index_id).ok_or_else(|| {
-            io::Error::new(io::ErrorKind::NotFound, format!("Index {} not found", index_id))
-        })?;
-
-        Ok(btree.root_page())
+        Ok(self.catalog().index(index_id)?.root_page())
     }
 }
 
 // Re-export commonly used types for convenience
-pub use utils::serialization::{SqliteValue, serialize_values, deserialize_values};
+pub use utils::serialization::{Affinity, SqliteValue, serialize_values, deserialize_values};
 
 #[cfg(test)]
 mod tests {
@@ -750,9 +2982,7 @@ mod tests {
 
     #[test]
     fn test_table_operations() {
-        let dir = tempdir().unwrap();
-        let db_path = dir.path().join("test.db");
-        let mut db = RQLite::create(db_path, None).unwrap();
+        let db = RQLite::create_in_memory(None).unwrap();
 
         // Create table
         let table_id = db.create_table().unwrap();
@@ -790,9 +3020,7 @@ mod tests {
 
     #[test]
     fn test_index_operations() {
-        let dir = tempdir().unwrap();
-        let db_path = dir.path().join("test.db");
-        let mut db = RQLite::create(db_path, None).unwrap();
+        let db = RQLite::create_in_memory(None).unwrap();
 
         // Create table and index
         let table_id = db.create_table().unwrap();
@@ -821,9 +3049,7 @@ mod tests {
 
     #[test]
     fn test_transaction_operations() {
-        let dir = tempdir().unwrap();
-        let db_path = dir.path().join("test.db");
-        let mut db = RQLite::create(db_path, None).unwrap();
+        let db = RQLite::create_in_memory(None).unwrap();
 
         let table_id = db.create_table().unwrap();
         let record = Record::with_values(vec![SqliteValue::Integer(42)]);
@@ -847,9 +3073,7 @@ mod tests {
 
     #[test]
     fn test_multiple_tables_and_indexes() {
-        let dir = tempdir().unwrap();
-        let db_path = dir.path().join("test.db");
-        let mut db = RQLite::create(db_path, None).unwrap();
+        let db = RQLite::create_in_memory(None).unwrap();
 
         // Create multiple tables
         let table1 = db.create_table().unwrap();
@@ -904,32 +3128,255 @@ mod tests {
     }
 
     #[test]
-    fn test_configuration() {
+    fn test_in_memory_database() {
+        let vfs: Arc<dyn Vfs> = Arc::new(MemoryVfs::new());
+        let config = RQLiteConfig {
+            buffer_pool_size: 8,
+            ..Default::default()
+        };
+        assert!(RQLite::open_with_vfs(Arc::clone(&vfs), "missing.db", None).is_err());
+
+        let db =
+            RQLite::create_with_vfs(Arc::clone(&vfs), "memory.db", Some(config.clone())).unwrap();
+        let table_id = db.create_table().unwrap();
+        let record = Record::with_values(vec![SqliteValue::String("x".repeat(200))]);
+        // Enough rows for the tiny cache to write pages back to the in-memory file
+        for rowid in 1..=200 {
+            db.table_insert(table_id, rowid, &record).unwrap();
+        }
+
+        db.begin_transaction().unwrap();
+        for rowid in 201..=400 {
+            db.table_insert(table_id, rowid, &record).unwrap();
+        }
+        db.rollback_transaction().unwrap();
+        assert!(db.table_find(table_id, 200).unwrap().is_some());
+        assert!(db.table_find(table_id, 201).unwrap().is_none());
+
+        let page_count = db.page_count().unwrap();
+        let root_page = db.table_root_page(table_id).unwrap();
+        db.close().unwrap();
+
+        // The pages stay in the VFS and can be opened again, tables included
+        let db = RQLite::open_with_vfs(vfs, "memory.db", Some(config)).unwrap();
+        assert_eq!(db.page_count().unwrap(), page_count);
+        assert_eq!(db.table_root_page(table_id).unwrap(), root_page);
+        assert!(db.table_find(table_id, 200).unwrap().is_some());
+        assert!(db.table_find(table_id, 201).unwrap().is_none());
+        assert_eq!(db.create_table().unwrap(), table_id + 1);
+    }
+
+    #[test]
+    fn test_configuration() {
+        let dir = tempdir().unwrap();
+        let db_path = dir.path().join("test.db");
+
+        let config = RQLiteConfig {
+            page_size: 8192,
+            buffer_pool_size: 500,
+            reserved_space: 64,
+            checksums: true,
+            max_payload_fraction: 200,
+            min_payload_fraction: 50,
+            busy_timeout: Duration::from_millis(250),
+            synchronous: Synchronous::Normal,
+            group_commit: true,
+            mmap_size: 1 << 20,
+            read_ahead: 16,
+            background_writer: Some(BackgroundWriter::default()),
+            bulk_fill_factor: 75,
+            sort_memory: 1 << 20,
+            stat_samples: 8,
+        };
+
+        let db = RQLite::create(db_path, Some(config.clone())).unwrap();
+        
+        assert_eq!(db.config().page_size, config.page_size);
+        assert_eq!(db.config().buffer_pool_size, config.buffer_pool_size);
+        assert_eq!(db.config().reserved_space, config.reserved_space);
+        assert_eq!(db.config().max_payload_fraction, config.max_payload_fraction);
+        assert_eq!(db.config().min_payload_fraction, config.min_payload_fraction);
+        assert_eq!(db.config().busy_timeout, config.busy_timeout);
+        assert_eq!(db.config().synchronous, config.synchronous);
+        assert!(db.config().group_commit);
+        assert_eq!(db.config().mmap_size, config.mmap_size);
+        assert_eq!(db.config().read_ahead, config.read_ahead);
+        assert_eq!(db.config().background_writer, config.background_writer);
+        assert_eq!(db.config().bulk_fill_factor, config.bulk_fill_factor);
+        assert_eq!(db.config().sort_memory, config.sort_memory);
+        assert_eq!(db.config().stat_samples, config.stat_samples);
+        assert!(db.config().checksums);
+    }
+
+    #[test]
+    fn test_checksums_opt_in() {
+        use crate::utils::checksum::corrupt_page;
+
+        // No room for the checksum
+        let config = RQLiteConfig {
+            checksums: true,
+            ..Default::default()
+        };
+        let error = RQLite::create_in_memory(Some(config)).err().unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+
+        for checksums in [false, true] {
+            let vfs = Arc::new(MemoryVfs::new());
+            let config = RQLiteConfig {
+                page_size: 1024,
+                reserved_space: 8,
+                checksums,
+                ..Default::default()
+            };
+            let db = RQLite::create_with_vfs(vfs.clone(), "checksums.db", Some(config)).unwrap();
+            let table_id = db.create_table().unwrap();
+            let record = Record::with_values(vec![SqliteValue::Integer(1)]);
+            db.table_insert(table_id, 1, &record).unwrap();
+            db.close().unwrap();
+
+            // Scribble over the reserved space of the last page
+            let file = vfs.open(Path::new("checksums.db"), false).unwrap();
+            let page_count = ((file.size().unwrap() - 100) / 1024) as u32;
+            let offset = page_count as u64 * 1024 + 100 - 4;
+            file.write_at(&[0xAB; 4], offset).unwrap();
+
+            // Only the database that opted in notices, whatever the config of the handle opening it says
+            let found = RQLite::open_with_vfs(vfs, "checksums.db", None)
+                .and_then(|db| db.table_find(table_id, 1));
+            if checksums {
+                assert_eq!(corrupt_page(&found.err().unwrap()), Some(page_count));
+            } else {
+                assert!(found.unwrap().is_some());
+            }
+        }
+    }
+
+    #[test]
+    fn test_mmap_database() {
+        let dir = tempdir().unwrap();
+        let db_path = dir.path().join("mmap.db");
+        let config = RQLiteConfig {
+            buffer_pool_size: 8,
+            mmap_size: 1 << 24,
+            read_ahead: 4,
+            ..Default::default()
+        };
+
+        let db = RQLite::create(&db_path, Some(config.clone())).unwrap();
+        let table_id = db.create_table().unwrap();
+        let record = Record::with_values(vec![SqliteValue::String("x".repeat(200))]);
+        for rowid in 1..=300 {
+            db.table_insert(table_id, rowid, &record).unwrap();
+        }
+        // Rolling back shrinks the file again, and the map with it
+        db.begin_transaction().unwrap();
+        for rowid in 301..=600 {
+            db.table_insert(table_id, rowid, &record).unwrap();
+        }
+        db.rollback_transaction().unwrap();
+        db.close().unwrap();
+
+        // With a tiny cache, most of these reads are served by the map, and read ahead of time
+        let db = RQLite::open(&db_path, Some(config)).unwrap();
+        for rowid in 1..=300 {
+            assert!(db.table_find(table_id, rowid).unwrap().is_some());
+        }
+        assert!(db.table_find(table_id, 301).unwrap().is_none());
+        assert!(db.shared.pager.cache_stats().unwrap().prefetch_hits > 0);
+    }
+
+    #[test]
+    fn test_background_writer_database() {
+        let dir = tempdir().unwrap();
+        let db_path = dir.path().join("writer.db");
+        let config = RQLiteConfig {
+            buffer_pool_size: 32,
+            background_writer: Some(BackgroundWriter {
+                interval: Duration::from_millis(1),
+                dirty_ratio: 0.25,
+                max_age: Duration::from_millis(1),
+                max_pages: 16,
+            }),
+            ..Default::default()
+        };
+
+        let db = RQLite::create(&db_path, Some(config.clone())).unwrap();
+        let table_id = db.create_table().unwrap();
+        let record = Record::with_values(vec![SqliteValue::String("x".repeat(300))]);
+        db.begin_transaction().unwrap();
+        for rowid in 1..=1000 {
+            db.table_insert(table_id, rowid, &record).unwrap();
+            if rowid % 100 == 0 {
+                std::thread::sleep(Duration::from_millis(5));
+            }
+        }
+        db.commit_transaction().unwrap();
+        assert!(db.shared.pager.cache_stats().unwrap().pages_written_back > 0);
+
+        // Rolled back pages written by the writer come back from the journal
+        db.begin_transaction().unwrap();
+        for rowid in 1..=500 {
+            db.table_delete(table_id, rowid).unwrap();
+            if rowid % 100 == 0 {
+                std::thread::sleep(Duration::from_millis(5));
+            }
+        }
+        db.rollback_transaction().unwrap();
+        db.close().unwrap();
+
+        let db = RQLite::open(&db_path, Some(config)).unwrap();
+        for rowid in 1..=1000 {
+            assert!(db.table_find(table_id, rowid).unwrap().is_some());
+        }
+        assert!(db.table_find(table_id, 1001).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_bulk_load() {
         let dir = tempdir().unwrap();
-        let db_path = dir.path().join("test.db");
+        let db_path = dir.path().join("bulk.db");
+        let db = RQLite::create(&db_path, None).unwrap();
+        let table_id = db.create_table().unwrap();
+        let index_id = db.create_index(table_id).unwrap();
 
-        let config = RQLiteConfig {
-            page_size: 8192,
-            buffer_pool_size: 500,
-            reserved_space: 64,
-            max_payload_fraction: 200,
-            min_payload_fraction: 50,
-        };
+        let rows = (1..=2000).rev().map(|rowid| {
+            let record = Record::with_values(vec![SqliteValue::Integer(rowid * 10)]);
+            (rowid, record)
+        });
+        db.bulk_load(table_id, rows).unwrap();
+        let entries = (1..=2000).map(|rowid| {
+            let mut key = Vec::new();
+            serialize_values(&[SqliteValue::Integer(rowid * 10)], &mut key).unwrap();
+            (key, rowid)
+        });
+        db.bulk_load_index(index_id, entries).unwrap();
+
+        // Only empty trees can be loaded
+        let more = vec![(2001, Record::with_values(vec![SqliteValue::Null]))];
+        assert!(db.bulk_load(table_id, more).is_err());
+        let root_page = db.table_root_page(table_id).unwrap();
+        db.close().unwrap();
 
-        let db = RQLite::create(db_path, Some(config.clone())).unwrap();
-        
-        assert_eq!(db.config().page_size, config.page_size);
-        assert_eq!(db.config().buffer_pool_size, config.buffer_pool_size);
-        assert_eq!(db.config().reserved_space, config.reserved_space);
-        assert_eq!(db.config().max_payload_fraction, config.max_payload_fraction);
-        assert_eq!(db.config().min_payload_fraction, config.min_payload_fraction);
+        // The new roots are in the schema
+        let db = RQLite::open(&db_path, None).unwrap();
+        assert_eq!(db.table_root_page(table_id).unwrap(), root_page);
+        for rowid in 1..=2000 {
+            let record = db.table_find(table_id, rowid).unwrap().unwrap();
+            match record.get_value(0) {
+                Some(SqliteValue::Integer(value)) => assert_eq!(*value, rowid * 10),
+                other => panic!("Unexpected value {:?}", other),
+            }
+            let key = KeyValue::Integer(rowid * 10);
+            assert!(db.index_find(index_id, &key).unwrap().0);
+        }
+        assert!(!db.index_find(index_id, &KeyValue::Integer(5)).unwrap().0);
     }
 
     #[test]
     fn test_error_handling() {
         let dir = tempdir().unwrap();
         let db_path = dir.path().join("test.db");
-        let mut db = RQLite::create(db_path, None).unwrap();
+        let db = RQLite::create(db_path, None).unwrap();
 
         // Test operations on non-existent table
         let result = db.table_find(999, 1);
@@ -960,7 +3407,7 @@ mod tests {
     fn test_large_dataset() {
         let dir = tempdir().unwrap();
         let db_path = dir.path().join("test.db");
-        let mut db = RQLite::create(db_path, None).unwrap();
+        let db = RQLite::create(db_path, None).unwrap();
 
         let table_id = db.create_table().unwrap();
 
@@ -1027,7 +3474,7 @@ mod tests {
 
         // Create database and insert data
         {
-            let mut db = RQLite::create(&db_path, None).unwrap();
+            let db = RQLite::create(&db_path, None).unwrap();
             table_id = db.create_table().unwrap();
             db.table_insert(table_id, 1, &record).unwrap();
             db.flush().unwrap();
@@ -1046,7 +3493,7 @@ mod tests {
     fn test_flush_and_close() {
         let dir = tempdir().unwrap();
         let db_path = dir.path().join("test.db");
-        let mut db = RQLite::create(db_path, None).unwrap();
+        let db = RQLite::create(db_path, None).unwrap();
 
         let table_id = db.create_table().unwrap();
         let record = Record::with_values(vec![SqliteValue::Integer(42)]);
@@ -1064,7 +3511,7 @@ mod tests {
     fn test_page_count() {
         let dir = tempdir().unwrap();
         let db_path = dir.path().join("test.db");
-        let mut db = RQLite::create(db_path, None).unwrap();
+        let db = RQLite::create(db_path, None).unwrap();
 
         let initial_pages = db.page_count().unwrap();
         assert!(initial_pages > 0);
@@ -1096,7 +3543,7 @@ mod tests {
     fn test_pager_sharing_efficiency() {
         let dir = tempdir().unwrap();
         let db_path = dir.path().join("shared_test.db");
-        let mut db = RQLite::create(db_path, None).unwrap();
+        let db = RQLite::create(db_path, None).unwrap();
 
         // Crear múltiples tablas - todas deberían compartir el mismo pager
         let table1 = db.create_table().unwrap();
@@ -1140,7 +3587,7 @@ mod tests {
     fn test_transaction_consistency_across_tables() {
         let dir = tempdir().unwrap();
         let db_path = dir.path().join("transaction_test.db");
-        let mut db = RQLite::create(db_path, None).unwrap();
+        let db = RQLite::create(db_path, None).unwrap();
 
         let table1 = db.create_table().unwrap();
         let table2 = db.create_table().unwrap();
@@ -1176,7 +3623,7 @@ mod tests {
     fn test_memory_efficiency() {
         let dir = tempdir().unwrap();
         let db_path = dir.path().join("memory_test.db");
-        let mut db = RQLite::create(db_path, None).unwrap();
+        let db = RQLite::create(db_path, None).unwrap();
 
         // Crear muchas tablas para probar eficiencia
         let table_count = 50;
@@ -1242,12 +3689,864 @@ mod tests {
         drop(pager_clone3);
         assert_eq!(Arc::strong_count(&pager), 1); // solo original
     }
+    #[test]
+    fn test_handle_is_send_and_sync() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<RQLite>();
+    }
+
+    #[test]
+    fn test_concurrent_writers_and_readers() {
+        let dir = tempdir().unwrap();
+        let db_path = dir.path().join("threads_test.db");
+        let db = RQLite::create(db_path, None).unwrap();
+        let table_id = db.create_table().unwrap();
+
+        let threads = 4;
+        let rows_per_thread = 50;
+
+        // Each writer owns a disjoint range of rowids
+        let writers: Vec<_> = (0..threads)
+            .map(|t| {
+                let db = db.clone();
+                std::thread::spawn(move || {
+                    for i in 0..rows_per_thread {
+                        let rowid = t * rows_per_thread + i + 1;
+                        let record = Record::with_values(vec![
+                            SqliteValue::Integer(rowid),
+                            SqliteValue::String(format!("Thread {} row {}", t, i)),
+                        ]);
+                        db.table_insert(table_id, rowid, &record).unwrap();
+                    }
+                })
+            })
+            .collect();
+        for writer in writers {
+            writer.join().unwrap();
+        }
+
+        // Readers share the handle through an Arc and run at the same time
+        let shared = Arc::new(db);
+        let readers: Vec<_> = (0..threads)
+            .map(|_| {
+                let db = Arc::clone(&shared);
+                std::thread::spawn(move || {
+                    for rowid in 1..=threads * rows_per_thread {
+                        let record = db.table_find(table_id, rowid).unwrap().unwrap();
+                        match &record.values[0] {
+                            SqliteValue::Integer(val) => assert_eq!(*val, rowid),
+                            _ => panic!("Expected integer"),
+                        }
+                    }
+                })
+            })
+            .collect();
+        for reader in readers {
+            reader.join().unwrap();
+        }
+    }
+
+    #[test]
+    fn test_readers_see_committed_snapshot() {
+        let dir = tempdir().unwrap();
+        let db_path = dir.path().join("snapshot_test.db");
+        let db = RQLite::create(db_path, None).unwrap();
+        let table_id = db.create_table().unwrap();
+        let record = Record::with_values(vec![SqliteValue::Integer(1)]);
+        db.table_insert(table_id, 1, &record).unwrap();
+
+        let committed_root = db.table_root_page(table_id).unwrap();
+
+        // Enough rows to split the root, so the tree the writer sees has another root page
+        db.begin_transaction().unwrap();
+        for rowid in 2..=300 {
+            let record = Record::with_values(vec![
+                SqliteValue::Integer(rowid),
+                SqliteValue::String(format!("Uncommitted row {}", rowid)),
+            ]);
+            db.table_insert(table_id, rowid, &record).unwrap();
+        }
+        assert!(db.table_find(table_id, 300).unwrap().is_some());
+        assert_ne!(db.table_root_page(table_id).unwrap(), committed_root);
+
+        let reader = db.clone();
+        std::thread::spawn(move || {
+            assert!(reader.table_find(table_id, 1).unwrap().is_some());
+            assert!(reader.table_find(table_id, 300).unwrap().is_none());
+        })
+        .join()
+        .unwrap();
+
+        db.commit_transaction().unwrap();
+
+        let reader = db.clone();
+        std::thread::spawn(move || {
+            assert!(reader.table_find(table_id, 300).unwrap().is_some());
+        })
+        .join()
+        .unwrap();
+    }
+
+    #[test]
+    fn test_rollback_restores_catalog() {
+        let dir = tempdir().unwrap();
+        let db_path = dir.path().join("catalog_rollback_test.db");
+        let db = RQLite::create(db_path, None).unwrap();
+        let table_id = db.create_table().unwrap();
+
+        db.begin_transaction().unwrap();
+        let new_table = db.create_table().unwrap();
+        assert!(db.table_exists(new_table));
+
+        // Other threads do not see the table until it is committed
+        let reader = db.clone();
+        std::thread::spawn(move || {
+            let err = reader.table_find(new_table, 1).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::NotFound);
+        })
+        .join()
+        .unwrap();
+
+        db.rollback_transaction().unwrap();
+        assert!(db.table_exists(table_id));
+        assert!(!db.table_exists(new_table));
+    }
+
+    #[test]
+    fn test_only_the_owner_ends_a_transaction() {
+        let dir = tempdir().unwrap();
+        let db = RQLite::create(dir.path().join("owner_test.db"), None).unwrap();
+        let table_id = db.create_table().unwrap();
+        let record = Record::with_values(vec![SqliteValue::Integer(1)]);
+
+        db.begin_transaction().unwrap();
+        db.table_insert(table_id, 1, &record).unwrap();
+        db.savepoint("owner").unwrap();
+
+        let other = db.clone();
+        std::thread::spawn(move || {
+            let denied = [
+                other.rollback_transaction(),
+                other.commit_transaction(),
+                other.savepoint("other"),
+                other.rollback_to_savepoint("owner"),
+                other.release_savepoint("owner"),
+                other.execute("ROLLBACK", &[]).map(|_| ()),
+            ];
+            for result in denied {
+                assert_eq!(result.unwrap_err().kind(), io::ErrorKind::PermissionDenied);
+            }
+        })
+        .join()
+        .unwrap();
+
+        db.release_savepoint("owner").unwrap();
+        db.commit_transaction().unwrap();
+        assert!(db.table_find(table_id, 1).unwrap().is_some());
+
+        // A guard owns its transaction on whatever thread it goes to
+        let tx = db.transaction().unwrap();
+        tx.table_insert(table_id, 2, &record).unwrap();
+        std::thread::scope(|scope| {
+            scope.spawn(move || {
+                tx.savepoint("moved").unwrap();
+                tx.release("moved").unwrap();
+                tx.commit().unwrap();
+            });
+        });
+        assert!(db.table_find(table_id, 2).unwrap().is_some());
+    }
+
+    /// A `MemoryVfs` whose syncs take as long as those of a slow disk, and are written down.
+    struct SlowSyncVfs {
+        inner: MemoryVfs,
+        delay: Duration,
+        /// Synced file and `data_only` flag of every sync, in order.
+        syncs: Arc<std::sync::Mutex<Vec<(std::path::PathBuf, bool)>>>,
+    }
+
+    impl SlowSyncVfs {
+        fn new(delay: Duration) -> Self {
+            Self {
+                inner: MemoryVfs::new(),
+                delay,
+                syncs: Arc::default(),
+            }
+        }
+
+        /// Takes the syncs done so far.
+        fn take_syncs(&self) -> Vec<(std::path::PathBuf, bool)> {
+            std::mem::take(&mut *self.syncs.lock().unwrap())
+        }
+    }
+
+    impl Vfs for SlowSyncVfs {
+        fn open(&self, path: &Path, create: bool) -> io::Result<Arc<dyn storage::VfsFile>> {
+            Ok(Arc::new(SlowSyncFile {
+                inner: self.inner.open(path, create)?,
+                path: path.to_path_buf(),
+                delay: self.delay,
+                syncs: Arc::clone(&self.syncs),
+            }))
+        }
+
+        fn delete(&self, path: &Path) -> io::Result<()> {
+            self.inner.delete(path)
+        }
+
+        fn exists(&self, path: &Path) -> io::Result<bool> {
+            self.inner.exists(path)
+        }
+    }
+
+    /// A file of the `SlowSyncVfs`.
+    struct SlowSyncFile {
+        inner: Arc<dyn storage::VfsFile>,
+        path: std::path::PathBuf,
+        delay: Duration,
+        syncs: Arc<std::sync::Mutex<Vec<(std::path::PathBuf, bool)>>>,
+    }
+
+    impl storage::VfsFile for SlowSyncFile {
+        fn read_at(&self, buffer: &mut [u8], offset: u64) -> io::Result<()> {
+            self.inner.read_at(buffer, offset)
+        }
+
+        fn write_at(&self, buffer: &[u8], offset: u64) -> io::Result<()> {
+            self.inner.write_at(buffer, offset)
+        }
+
+        fn sync(&self, data_only: bool) -> io::Result<()> {
+            std::thread::sleep(self.delay);
+            self.syncs
+                .lock()
+                .unwrap()
+                .push((self.path.clone(), data_only));
+            self.inner.sync(data_only)
+        }
+
+        fn truncate(&self, size: u64) -> io::Result<()> {
+            self.inner.truncate(size)
+        }
+
+        fn size(&self) -> io::Result<u64> {
+            self.inner.size()
+        }
+
+        fn lock(&self, kind: storage::lock::LockKind, start: u64, len: u64) -> io::Result<()> {
+            self.inner.lock(kind, start, len)
+        }
+    }
+
+    #[test]
+    fn test_group_commit_writers() {
+        // With syncs this slow, the writers pile up behind each one
+        let vfs = Arc::new(SlowSyncVfs::new(Duration::from_millis(5)));
+        let config = RQLiteConfig {
+            group_commit: true,
+            ..Default::default()
+        };
+        let db =
+            RQLite::create_with_vfs(vfs.clone(), "group_commit_test.db", Some(config)).unwrap();
+        let table_id = db.create_table().unwrap();
+        vfs.take_syncs();
+        let syncs_before = db.shared.pager.sync_count().unwrap();
+
+        let threads = 8;
+        let rows_per_thread = 25;
+        let writers: Vec<_> = (0..threads)
+            .map(|t| {
+                let db = db.clone();
+                std::thread::spawn(move || {
+                    for i in 0..rows_per_thread {
+                        let rowid = t * rows_per_thread + i + 1;
+                        let record = Record::with_values(vec![SqliteValue::Integer(rowid)]);
+                        db.table_insert(table_id, rowid, &record).unwrap();
+                    }
+                })
+            })
+            .collect();
+        for writer in writers {
+            writer.join().unwrap();
+        }
+
+        // Every insert is a commit, and the commits share their syncs of the database file
+        let commits = (threads * rows_per_thread) as usize;
+        let syncs = vfs
+            .take_syncs()
+            .into_iter()
+            .filter(|(path, _)| path == Path::new("group_commit_test.db"))
+            .count();
+        assert!(syncs < commits, "{} syncs for {} commits", syncs, commits);
+        assert_eq!(
+            db.shared.pager.sync_count().unwrap() - syncs_before,
+            syncs as u64
+        );
+        for rowid in 1..=threads * rows_per_thread {
+            assert!(db.table_find(table_id, rowid).unwrap().is_some());
+        }
+    }
+
+    #[test]
+    fn test_synchronous_syncs_of_the_vfs() {
+        for synchronous in [Synchronous::Off, Synchronous::Normal, Synchronous::Full] {
+            let vfs = Arc::new(SlowSyncVfs::new(Duration::ZERO));
+            let config = RQLiteConfig {
+                synchronous,
+                ..Default::default()
+            };
+            let db = RQLite::create_with_vfs(vfs.clone(), "sync_test.db", Some(config)).unwrap();
+            let table_id = db.create_table().unwrap();
+            vfs.take_syncs();
+
+            // Half of the rows have an overflow chain of a few pages, which are synced with the commit
+            for rowid in 1..=10 {
+                let blob = vec![rowid as u8; if rowid % 2 == 0 { 10_000 } else { 10 }];
+                let record = Record::with_values(vec![SqliteValue::Blob(blob)]);
+                db.table_insert(table_id, rowid, &record).unwrap();
+            }
+
+            // Off never syncs, not even the journal. Normal only syncs the data (fdatasync), Full everything
+            let syncs = vfs.take_syncs();
+            let file_syncs = syncs
+                .iter()
+                .filter(|(path, _)| path.as_path() == Path::new("sync_test.db"))
+                .count();
+            assert!(file_syncs <= 10, "{} syncs of the file", file_syncs);
+            match synchronous {
+                Synchronous::Off => assert!(syncs.is_empty(), "{:?}", syncs),
+                Synchronous::Normal => {
+                    assert!(!syncs.is_empty());
+                    assert!(syncs.iter().all(|(_, data_only)| *data_only), "{:?}", syncs);
+                }
+                _ => {
+                    assert!(!syncs.is_empty());
+                    assert!(syncs.iter().all(|(_, data_only)| !data_only), "{:?}", syncs);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_close_with_other_handles_alive() {
+        let dir = tempdir().unwrap();
+        let db_path = dir.path().join("handles_test.db");
+        let db = RQLite::create(db_path, None).unwrap();
+        let other = db.clone();
+
+        let table_id = db.create_table().unwrap();
+        let record = Record::with_values(vec![SqliteValue::Integer(7)]);
+        db.table_insert(table_id, 1, &record).unwrap();
+
+        // Closing one handle must not affect the others
+        db.close().unwrap();
+        assert!(other.table_exists(table_id));
+        assert!(other.table_find(table_id, 1).unwrap().is_some());
+        other.close().unwrap();
+    }
+
+    #[test]
+    fn test_analyze_persists_statistics() {
+        let dir = tempdir().unwrap();
+        let db_path = dir.path().join("analyze_test.db");
+        let db = RQLite::create(&db_path, None).unwrap();
+        let table_id = db.create_table().unwrap();
+        let index_id = db.create_index(table_id).unwrap();
+        for rowid in 1..=500 {
+            let record = Record::with_values(vec![SqliteValue::Integer(rowid % 50)]);
+            db.table_insert(table_id, rowid, &record).unwrap();
+            let mut key = Vec::new();
+            serialize_values(&[SqliteValue::Integer(rowid % 50)], &mut key).unwrap();
+            db.index_insert(index_id, &key, rowid).unwrap();
+        }
+        assert_eq!(db.table_stats(table_id).unwrap(), None);
+
+        db.analyze().unwrap();
+        let table_stats = db.table_stats(table_id).unwrap().unwrap();
+        assert_eq!(table_stats.row_count, 500);
+        assert_eq!(table_stats.distinct_keys, None);
+        let index_stats = db.index_stats(index_id).unwrap().unwrap();
+        assert_eq!(index_stats.row_count, 500);
+        assert_eq!(index_stats.distinct_keys, Some(50));
+        assert_eq!(index_stats.samples.len(), db.config().stat_samples);
+
+        // Trees created later have no statistics until the next analyze
+        let new_table = db.create_table().unwrap();
+        assert_eq!(db.table_stats(new_table).unwrap(), None);
+        let err = db.table_stats(new_table + 1).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+        db.close().unwrap();
+
+        let db = RQLite::open(&db_path, None).unwrap();
+        assert_eq!(db.table_stats(table_id).unwrap(), Some(table_stats));
+        assert_eq!(db.index_stats(index_id).unwrap(), Some(index_stats));
+    }
+
+    #[test]
+    fn test_analyze_in_transaction() {
+        let dir = tempdir().unwrap();
+        let db_path = dir.path().join("analyze_transaction_test.db");
+        let db = RQLite::create(db_path, None).unwrap();
+        let table_id = db.create_table().unwrap();
+        let record = Record::with_values(vec![SqliteValue::Integer(1)]);
+        db.table_insert(table_id, 1, &record).unwrap();
+
+        db.begin_transaction().unwrap();
+        db.analyze().unwrap();
+        assert_eq!(db.table_stats(table_id).unwrap().unwrap().row_count, 1);
+
+        // Other threads do not see the statistics until they are committed
+        let reader = db.clone();
+        std::thread::spawn(move || {
+            assert_eq!(reader.table_stats(table_id).unwrap(), None);
+        })
+        .join()
+        .unwrap();
+
+        db.rollback_transaction().unwrap();
+        assert_eq!(db.table_stats(table_id).unwrap(), None);
+
+        db.analyze().unwrap();
+        db.table_insert(table_id, 2, &record).unwrap();
+        // The statistics stay as they were when analyzed
+        assert_eq!(db.table_stats(table_id).unwrap().unwrap().row_count, 1);
+        db.analyze().unwrap();
+        assert_eq!(db.table_stats(table_id).unwrap().unwrap().row_count, 2);
+    }
+
+    #[test]
+    fn test_sequences() {
+        let dir = tempdir().unwrap();
+        let db_path = dir.path().join("sequence_test.db");
+        let db = RQLite::create(&db_path, None).unwrap();
+        db.create_sequence("ids", 1, 1, 10).unwrap();
+        db.create_sequence("tickets", 100, -10, 0).unwrap();
+        assert!(db.create_sequence("IDS", 1, 1, 10).is_err());
+        assert_eq!(
+            db.next_value("missing").unwrap_err().kind(),
+            io::ErrorKind::NotFound
+        );
+
+        // The cached range is handed out without committing anything
+        assert_eq!(db.next_value("ids").unwrap(), 1);
+        let commits = db.shared.pager.get_header().unwrap().change_counter;
+        for expected in 2..=10 {
+            assert_eq!(db.next_value("Ids").unwrap(), expected);
+        }
+        assert_eq!(
+            db.shared.pager.get_header().unwrap().change_counter,
+            commits
+        );
+        assert_eq!(db.next_value("ids").unwrap(), 11);
+        assert_eq!(
+            db.shared.pager.get_header().unwrap().change_counter,
+            commits + 1
+        );
+
+        // A gap-free sequence rolls back with the transaction
+        db.begin_transaction().unwrap();
+        assert_eq!(db.next_value("tickets").unwrap(), 100);
+        assert_eq!(db.next_value("tickets").unwrap(), 90);
+        db.rollback_transaction().unwrap();
+        let tx = db.transaction().unwrap();
+        assert_eq!(tx.next_value("tickets").unwrap(), 100);
+        tx.commit().unwrap();
+
+        // A range reserved in a transaction that rolls back is forgotten, not handed out again
+        db.set_value("ids", 50).unwrap();
+        db.begin_transaction().unwrap();
+        assert_eq!(db.next_value("ids").unwrap(), 50);
+        db.rollback_transaction().unwrap();
+        assert_eq!(db.next_value("ids").unwrap(), 50);
+        assert_eq!(db.next_value("ids").unwrap(), 51);
+
+        // Other connections reserve after the ranges of this one
+        let other = RQLite::open(&db_path, None).unwrap();
+        assert_eq!(other.next_value("ids").unwrap(), 60);
+        assert_eq!(db.next_value("ids").unwrap(), 52);
+        other.close().unwrap();
+        db.close().unwrap();
+
+        let db = RQLite::open(&db_path, None).unwrap();
+        assert_eq!(db.next_value("ids").unwrap(), 70);
+        assert_eq!(db.next_value("tickets").unwrap(), 90);
+        db.drop_sequence("tickets").unwrap();
+        assert!(db.next_value("tickets").is_err());
+    }
+
+    #[test]
+    fn test_sequence_range_of_rolled_back_transaction() {
+        let dir = tempdir().unwrap();
+        let config = RQLiteConfig {
+            busy_timeout: Duration::from_secs(10),
+            ..Default::default()
+        };
+        let db = RQLite::create(dir.path().join("sequence_threads.db"), Some(config)).unwrap();
+        db.create_sequence("ids", 1, 1, 10).unwrap();
+
+        db.begin_transaction().unwrap();
+        assert_eq!(db.next_value("ids").unwrap(), 1);
+
+        // The range of the transaction is not handed out on another thread, which waits for the rollback
+        let other = db.clone();
+        let waiting = std::thread::spawn(move || other.next_value("ids").unwrap());
+        std::thread::sleep(Duration::from_millis(50));
+        db.rollback_transaction().unwrap();
+        let other_value = waiting.join().unwrap();
+
+        // The other thread reserved the values again, and this one goes on after them
+        assert_eq!(other_value, 1);
+        assert_eq!(db.next_value("ids").unwrap(), 2);
+        assert_eq!(db.next_value("ids").unwrap(), 3);
+    }
+
+    #[test]
+    fn test_table_with_columns() {
+        let dir = tempdir().unwrap();
+        let db_path = dir.path().join("columns_test.db");
+        let db = RQLite::create(&db_path, None).unwrap();
+        let table_id = db
+            .create_table_with_columns(vec![
+                Column::new("id", "INTEGER").not_null(),
+                Column::new("name", "VARCHAR(32)").not_null(),
+                Column::new("balance", "REAL").default_value(SqliteValue::Integer(0)),
+            ])
+            .unwrap();
+
+        let record = Record::with_values(vec![
+            SqliteValue::String("10".to_string()),
+            SqliteValue::Integer(2024),
+        ]);
+        db.table_insert(table_id, 1, &record).unwrap();
+        let stored = db.table_find(table_id, 1).unwrap().unwrap();
+        assert_eq!(
+            stored.values,
+            vec![
+                SqliteValue::Integer(10),
+                SqliteValue::String("2024".to_string()),
+                SqliteValue::Float(0.0),
+            ]
+        );
+
+        let missing_name = Record::with_values(vec![SqliteValue::Integer(2)]);
+        let err = db.table_insert(table_id, 2, &missing_name).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert!(db.table_find(table_id, 2).unwrap().is_none());
+
+        // A bad row fails the whole bulk load
+        let other = db
+            .create_table_with_columns(vec![Column::new("n", "INT").not_null()])
+            .unwrap();
+        let rows = (1..=100).map(|rowid| {
+            let value = if rowid == 50 {
+                SqliteValue::Null
+            } else {
+                SqliteValue::String(rowid.to_string())
+            };
+            (rowid, Record::with_values(vec![value]))
+        });
+        assert!(db.bulk_load(other, rows).is_err());
+        assert!(db.table_find(other, 1).unwrap().is_none());
+        let rows = (1..=100).map(|rowid| {
+            let value = SqliteValue::Float(rowid as f64);
+            (rowid, Record::with_values(vec![value]))
+        });
+        db.bulk_load(other, rows).unwrap();
+        let stored = db.table_find(other, 7).unwrap().unwrap();
+        assert_eq!(stored.values, vec![SqliteValue::Integer(7)]);
+
+        // Tables without columns store records as they come
+        let plain = db.create_table().unwrap();
+        assert_eq!(db.table_schema(plain).unwrap(), None);
+        db.table_insert(plain, 1, &missing_name).unwrap();
+
+        let schema = db.table_schema(table_id).unwrap().unwrap();
+        db.close().unwrap();
+        let db = RQLite::open(&db_path, None).unwrap();
+        assert_eq!(db.table_schema(table_id).unwrap(), Some(schema));
+        assert_eq!(db.table_schema(plain).unwrap(), None);
+        let err = db.table_insert(table_id, 3, &missing_name).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn test_bulk_load_in_transaction() {
+        let db = RQLite::create_in_memory(None).unwrap();
+        let table_id = db
+            .create_table_with_columns(vec![Column::new("n", "INT").not_null()])
+            .unwrap();
+        let other = db.create_table().unwrap();
+        let rows = |bad: i64| {
+            (1..=100).map(move |rowid| {
+                let value = if rowid == bad {
+                    SqliteValue::Null
+                } else {
+                    SqliteValue::Integer(rowid)
+                };
+                (rowid, Record::with_values(vec![value]))
+            })
+        };
+
+        // The rows before the bad one must not stay in the transaction and get committed with it
+        let tx = db.transaction().unwrap();
+        let record = Record::with_values(vec![SqliteValue::Integer(1)]);
+        tx.table_insert(other, 1, &record).unwrap();
+        let err = db.bulk_load(table_id, rows(50)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert!(tx.table_find(table_id, 1).unwrap().is_none());
+
+        // The table is still empty, so it can be loaded again, and the rest of the transaction is intact
+        db.bulk_load(table_id, rows(0)).unwrap();
+        tx.commit().unwrap();
+        assert!(db.table_find(other, 1).unwrap().is_some());
+        for rowid in 1..=100 {
+            assert!(db.table_find(table_id, rowid).unwrap().is_some());
+        }
+    }
+
+    /// A `MemoryVfs` whose files cannot shrink while it is armed.
+    struct FailingTruncateVfs {
+        inner: MemoryVfs,
+        armed: Arc<std::sync::atomic::AtomicBool>,
+    }
+
+    impl Vfs for FailingTruncateVfs {
+        fn open(&self, path: &Path, create: bool) -> io::Result<Arc<dyn storage::VfsFile>> {
+            Ok(Arc::new(FailingTruncateFile {
+                inner: self.inner.open(path, create)?,
+                armed: Arc::clone(&self.armed),
+            }))
+        }
+
+        fn delete(&self, path: &Path) -> io::Result<()> {
+            self.inner.delete(path)
+        }
+
+        fn exists(&self, path: &Path) -> io::Result<bool> {
+            self.inner.exists(path)
+        }
+    }
+
+    /// A file of the `FailingTruncateVfs`.
+    struct FailingTruncateFile {
+        inner: Arc<dyn storage::VfsFile>,
+        armed: Arc<std::sync::atomic::AtomicBool>,
+    }
+
+    impl storage::VfsFile for FailingTruncateFile {
+        fn read_at(&self, buffer: &mut [u8], offset: u64) -> io::Result<()> {
+            self.inner.read_at(buffer, offset)
+        }
+
+        fn write_at(&self, buffer: &[u8], offset: u64) -> io::Result<()> {
+            self.inner.write_at(buffer, offset)
+        }
+
+        fn sync(&self, data_only: bool) -> io::Result<()> {
+            self.inner.sync(data_only)
+        }
+
+        fn truncate(&self, size: u64) -> io::Result<()> {
+            // Growing the file is how pages get allocated, so only shrinking fails
+            if self.armed.load(std::sync::atomic::Ordering::SeqCst) && size < self.inner.size()? {
+                return Err(io::Error::other("Truncate failed"));
+            }
+            self.inner.truncate(size)
+        }
+
+        fn size(&self) -> io::Result<u64> {
+            self.inner.size()
+        }
+
+        fn lock(&self, kind: storage::lock::LockKind, start: u64, len: u64) -> io::Result<()> {
+            self.inner.lock(kind, start, len)
+        }
+    }
+
+    #[test]
+    fn test_failed_statement_rollback_releases_savepoint() {
+        let vfs = Arc::new(FailingTruncateVfs {
+            inner: MemoryVfs::new(),
+            armed: Arc::default(),
+        });
+        let db = RQLite::create_with_vfs(vfs.clone(), "truncate_test.db", None).unwrap();
+        let table_id = db
+            .create_table_with_columns(vec![Column::new("s", "TEXT").not_null()])
+            .unwrap();
+        // The rows take new pages, so rolling the load back shrinks the file
+        let rows = (1..=200).map(|rowid| {
+            let value = if rowid == 200 {
+                SqliteValue::Null
+            } else {
+                SqliteValue::String("x".repeat(500))
+            };
+            (rowid, Record::with_values(vec![value]))
+        });
+
+        db.begin_transaction().unwrap();
+        db.savepoint("outer").unwrap();
+        vfs.armed.store(true, std::sync::atomic::Ordering::SeqCst);
+        let err = db.bulk_load(table_id, rows).unwrap_err();
+        vfs.armed.store(false, std::sync::atomic::Ordering::SeqCst);
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+
+        // Neither the pager nor the catalog keeps the statement savepoint
+        let err = db
+            .shared
+            .pager
+            .release_savepoint(STATEMENT_SAVEPOINT)
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert!(db.catalog().find_savepoint(STATEMENT_SAVEPOINT).is_none());
+
+        db.rollback_to_savepoint("outer").unwrap();
+        db.release_savepoint("outer").unwrap();
+        let record = Record::with_values(vec![SqliteValue::String("y".to_string())]);
+        db.table_insert(table_id, 1, &record).unwrap();
+        db.commit_transaction().unwrap();
+        assert!(db.table_find(table_id, 1).unwrap().is_some());
+        assert!(db.table_find(table_id, 2).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_rollback_forgets_columns() {
+        let db = RQLite::create_in_memory(None).unwrap();
+        db.begin_transaction().unwrap();
+        db.savepoint("before").unwrap();
+        let table_id = db
+            .create_table_with_columns(vec![Column::new("a", "TEXT")])
+            .unwrap();
+        assert!(db.table_schema(table_id).unwrap().is_some());
+        db.rollback_to_savepoint("before").unwrap();
+        assert!(db.table_schema(table_id).is_err());
+        db.rollback_transaction().unwrap();
+    }
+
+    #[test]
+    fn test_alter_table_columns() {
+        let dir = tempdir().unwrap();
+        let db_path = dir.path().join("alter.db");
+        let text = |value: &str| SqliteValue::String(value.to_string());
+
+        let db = RQLite::create(&db_path, None).unwrap();
+        let table_id = db
+            .create_table_with_columns(vec![
+                Column::new("id", "INTEGER"),
+                Column::new("name", "TEXT"),
+            ])
+            .unwrap();
+        for rowid in 1..=200 {
+            let record = Record::with_values(vec![SqliteValue::Integer(rowid), text("user")]);
+            db.table_insert(table_id, rowid, &record).unwrap();
+        }
+        let cookie = db.shared.pager.get_header().unwrap().schema_cookie;
+        let root_page = db.table_root_page(table_id).unwrap();
+
+        // Old rows read the default of the new column, and are not rewritten
+        let score = Column::new("score", "REAL").default_value(SqliteValue::Integer(10));
+        db.add_column(table_id, score).unwrap();
+        assert_eq!(db.table_root_page(table_id).unwrap(), root_page);
+        assert!(db.shared.pager.get_header().unwrap().schema_cookie > cookie);
+        let row = db.table_find(table_id, 7).unwrap().unwrap();
+        assert_eq!(
+            row.values,
+            vec![
+                SqliteValue::Integer(7),
+                text("user"),
+                SqliteValue::Float(10.0)
+            ]
+        );
+        let projected = db
+            .table_find_columns(table_id, 7, &[2, 0])
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            projected.values,
+            vec![SqliteValue::Float(10.0), SqliteValue::Integer(7)]
+        );
+        let new_row = Record::with_values(vec![
+            SqliteValue::Integer(201),
+            text("new"),
+            SqliteValue::Integer(3),
+        ]);
+        db.table_insert(table_id, 201, &new_row).unwrap();
+
+        let err = db
+            .add_column(table_id, Column::new("flag", "INT").not_null())
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        let plain = db.create_table().unwrap();
+        assert!(db.add_column(plain, Column::new("a", "")).is_err());
+
+        // Renaming is metadata only, dropping rewrites the rows that have the column
+        db.rename_column(table_id, "name", "login").unwrap();
+        db.drop_column(table_id, "id").unwrap();
+        let schema = db.table_schema(table_id).unwrap().unwrap();
+        let names: Vec<&str> = schema
+            .columns()
+            .iter()
+            .map(|column| column.name.as_str())
+            .collect();
+        assert_eq!(names, vec!["login", "score"]);
+        assert_eq!(
+            db.table_find(table_id, 7).unwrap().unwrap().values,
+            vec![text("user"), SqliteValue::Float(10.0)]
+        );
+        assert_eq!(
+            db.table_find(table_id, 201).unwrap().unwrap().values,
+            vec![text("new"), SqliteValue::Float(3.0)]
+        );
+        db.close().unwrap();
+
+        let db = RQLite::open(&db_path, None).unwrap();
+        assert_eq!(db.table_schema(table_id).unwrap().unwrap(), schema);
+        assert_eq!(
+            db.table_find(table_id, 200).unwrap().unwrap().values,
+            vec![text("user"), SqliteValue::Float(10.0)]
+        );
+        let err = db.drop_column(table_id, "id").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn test_rename_table() {
+        let dir = tempdir().unwrap();
+        let db_path = dir.path().join("rename.db");
+
+        let db = RQLite::create(&db_path, None).unwrap();
+        let users = db.create_table().unwrap();
+        let orders = db
+            .create_table_with_columns(vec![Column::new("total", "REAL")])
+            .unwrap();
+        assert_eq!(db.table_name(users).unwrap(), None);
+        db.rename_table(users, "users").unwrap();
+        db.rename_table(orders, "orders").unwrap();
+        let err = db.rename_table(orders, "Users").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+        db.rename_table(users, "Users").unwrap();
+        assert!(db.rename_table(users, "").is_err());
+
+        db.begin_transaction().unwrap();
+        db.rename_table(orders, "purchases").unwrap();
+        assert_eq!(db.find_table("purchases"), Some(orders));
+        db.rollback_transaction().unwrap();
+        assert_eq!(db.find_table("purchases"), None);
+        db.close().unwrap();
+
+        let db = RQLite::open(&db_path, None).unwrap();
+        assert_eq!(db.find_table("users"), Some(users));
+        assert_eq!(db.table_name(users).unwrap().as_deref(), Some("Users"));
+        assert_eq!(db.find_table("ORDERS"), Some(orders));
+        assert!(db.table_schema(orders).unwrap().is_some());
+        assert!(db.table_schema(users).unwrap().is_none());
+    }
+
     /// ADDED THIS TESTS AFTER LAST REFACTORING TO INCLUDE THE SHARED PAGER ACCROSS ALL TABLES. 
     #[test]
     fn test_close_with_arc() {
         let dir = tempdir().unwrap();
         let db_path = dir.path().join("close_test.db");
-        let mut db = RQLite::create(db_path, None).unwrap();
+        let db = RQLite::create(db_path, None).unwrap();
 
         // Crear algunas tablas
         let _table1 = db.create_table().unwrap();
@@ -1262,7 +4561,7 @@ mod tests {
     fn test_large_database_simulation() {
         let dir = tempdir().unwrap();
         let db_path = dir.path().join("large_db_test.db");
-        let mut db = RQLite::create(db_path, None).unwrap();
+        let db = RQLite::create(db_path, None).unwrap();
 
         // Simular una base de datos grande
         let table_count = 20;
@@ -18,12 +18,32 @@
 //! |                        DATA                          |
 //! |                                                      |
 //! |------------------------------------------------------|
+//!
+//! FREE SPACE
+//! Removing a cell does not move the others. Its bytes become a freeblock, as in SQLite: the freeblocks of a page
+//! are chained by offset from the header (`first_free_block_offset`), and each one starts with the offset of the next
+//! one and its own size. Free runs next to each other are a single freeblock, and runs under 4 bytes cannot hold that
+//! header, so they are counted as fragments (`fragmented_free_bytes`) instead.
+//! New cells take the first freeblock big enough (first fit), and then the unallocated space under the content start.
+//! When neither is enough but the page has the bytes all together, it is defragmented: the cells are packed against
+//! the end of the page again.
+//! In memory the chain is not stored, it is worked out from the offsets of the cells, so it cannot get out of sync
+//! with them. It is written out with the page.
 use crate::header::HEADER_SIZE;
+use crate::tree::cell::{
+    PayloadLimits, DEFAULT_MAX_PAYLOAD_FRACTION, DEFAULT_MIN_PAYLOAD_FRACTION,
+};
 
 use std::fmt;
 use std::io::Cursor;
 use std::io::{self, Read, Write};
 
+/// Smallest run of free bytes that can be a freeblock: 2 bytes for the offset of the next one and 2 for the size.
+const MIN_FREE_BLOCK_SIZE: u16 = 4;
+
+/// Most fragmented bytes a page keeps before it is defragmented. Same limit as SQLite.
+const MAX_FRAGMENTED_BYTES: usize = 60;
+
 /// Page Types on SQLite. There are basically two types of pages:
 /// Table pages: A table page is a B-Tree page that stores data from a table.
 /// Index pages: An index page is a B-Tree page that stores data from an index.
@@ -437,9 +457,31 @@ impl BTreePage {
         Ok(page)
     }
 
-    /// Sets the content start offset based on the page size and reserved space.
+    /// Repacks the cell content against the end of the usable area and sets the
+    /// content start offset just below it. Same as `defragment`.
+    ///
+    /// Truncating cells or changing their size leaves holes between the remaining ones,
+    /// so the cell offsets are recomputed here instead of only moving the content start back
+    /// to the end of the page (which let new cells be placed on top of live ones).
     pub fn update_content_start_offset(&mut self) {
-        self.header.content_start_offset = self.page_size as u16 - self.reserved_space as u16;
+        self.defragment();
+    }
+
+    /// Moves all the cells to the end of the usable area, in slot order, so that all the free space
+    /// of the page is in one piece between the slot array and the content. The freeblocks and fragments are gone after this.
+    ///
+    /// `add_cell` and `insert_cell` do it on their own when a cell does not fit in any freeblock
+    /// nor in the unallocated space, but the page has enough free bytes all together.
+    pub fn defragment(&mut self) {
+        let mut offset = self.usable_end() as u16;
+        for (index, cell) in self.cells.iter().enumerate() {
+            offset -= cell.size() as u16;
+            if let Some(slot) = self.cell_indices.get_mut(index) {
+                *slot = offset;
+            }
+        }
+        self.header.content_start_offset = offset;
+        self.update_free_blocks();
     }
 
     /// Addse a cell to the B-Tree page.
@@ -447,13 +489,32 @@ impl BTreePage {
     /// * `cell` - The cell to add to the page.
     /// # Errors
     /// Returns an error if the cell cannot be added due to insufficient space or if the cell type is incompatible with the page type.
-    /// # Panics
-    /// Panics if the cell type is incompatible with the page type.
     /// # Returns
     /// Returns the index of the cell in the slot array.
     /// # Notes
-    /// The cell is added to the page and the content start offset is updated. T
+    /// The slot goes at the end of the slot array. The content goes wherever `insert_cell` finds room for it.
     pub fn add_cell(&mut self, cell: BTreeCell) -> io::Result<u16> {
+        let index = self.cells.len() as u16;
+        self.insert_cell(index, cell)?;
+        Ok(index)
+    }
+
+    /// Inserts a cell at a position of the slot array, shifting the slots after it.
+    ///
+    /// Space for the content is allocated the way SQLite does it:
+    /// 1. The first freeblock big enough, taking the bytes at its end. What is left of the block stays in the chain,
+    ///    or becomes a fragment if it is under 4 bytes.
+    /// 2. The unallocated space between the slot array and the content, moving the content start down.
+    /// 3. If neither fits but the page has enough free bytes counting freeblocks and fragments, the page is defragmented first.
+    ///
+    /// # Parameters
+    /// * `index` - Position of the new slot. It can be the number of cells, to append it.
+    /// * `cell` - The cell to insert.
+    ///
+    /// # Errors
+    /// Returns an error if the cell type is incompatible with the page type, the index is past the end of the slot array,
+    /// or the page does not have enough free space.
+    pub fn insert_cell(&mut self, index: u16, cell: BTreeCell) -> io::Result<()> {
         // Verify the type compatibility
         // Check if the cell type is compatible with the page type
         match (&self.header.page_type, &cell) {
@@ -472,26 +533,16 @@ impl BTreePage {
                 ));
             }
         }
+        if index as usize > self.cells.len() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Cell index {} out of bounds", index),
+            ));
+        }
 
-        // Compute the required space to store the cell
+        // Compute the required space to store the cell, plus 2 bytes for its offset in the slot array
         let cell_size = cell.size();
-        let cell_index_size = 2; // 2 additional bytes for the cell offset in the slot array
-
-        // Compute the available space in the page
-        let header_size = self.header.size();
-        let cell_indices_size = self.cell_indices.len() * cell_index_size; // Current space occupied by indices
-                                                                           // Total used space in the page. If we are at page 1 we need to add the header size, as the database header is stored on page 1.
-        let used_space = if self.page_number == 1 {
-            HEADER_SIZE + header_size + cell_indices_size
-        } else {
-            header_size + cell_indices_size
-        };
-
-        // Calculate the available space for the new cell
-        // The content start offset is the point where the content starts (after the header and the slot array).
-        let content_start = self.header.content_start_offset as usize;
-        let available_space = content_start - used_space - cell_index_size; // Restar el espacio para el nuevo índice
-
+        let available_space = self.free_space().saturating_sub(2);
         if cell_size > available_space {
             return Err(io::Error::new(
                 io::ErrorKind::InvalidInput,
@@ -502,41 +553,189 @@ impl BTreePage {
             ));
         }
 
-        // Update the content start offset
-        self.header.content_start_offset -= cell_size as u16;
+        let offset = self.allocate(cell_size);
+        self.cell_indices.insert(index as usize, offset);
+        self.cells.insert(index as usize, cell);
+        self.header.cell_count += 1;
+        self.update_free_blocks();
 
-        // Append the cell index to the slot array
-        self.cell_indices.push(self.header.content_start_offset);
+        Ok(())
+    }
 
-        // Append the cell to the cells vector
-        self.cells.push(cell);
+    /// Removes a cell from the page. Its bytes go to the freeblock chain, merged with the free bytes
+    /// next to them, or back to the unallocated space if the cell was the first one of the content area.
+    /// The other cells do not move.
+    ///
+    /// # Parameters
+    /// * `index` - Position of the cell in the slot array.
+    ///
+    /// # Errors
+    /// Returns an error if the index is out of bounds.
+    ///
+    /// # Returns
+    /// The removed cell.
+    pub fn remove_cell(&mut self, index: u16) -> io::Result<BTreeCell> {
+        if index as usize >= self.cells.len() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Cell index {} out of bounds", index),
+            ));
+        }
 
-        // Update the first free block offset
-        self.header.cell_count += 1;
+        let cell = self.cells.remove(index as usize);
+        self.cell_indices.remove(index as usize);
+        self.header.cell_count -= 1;
+
+        // The free bytes right above the content start are unallocated space, not a freeblock
+        self.header.content_start_offset = self
+            .cell_indices
+            .iter()
+            .min()
+            .copied()
+            .unwrap_or(self.usable_end() as u16);
+        self.update_free_blocks();
+
+        // Fragments cannot be reused, and the header only has one byte to count them
+        if self.header.fragmented_free_bytes as usize > MAX_FRAGMENTED_BYTES {
+            self.defragment();
+        }
+
+        Ok(cell)
+    }
 
-        Ok((self.cell_indices.len() - 1) as u16)
+    /// Gets the freeblock chain of the page: the runs of at least 4 free bytes between the cells,
+    /// as `(offset, size)` pairs sorted by offset.
+    /// Runs of less than 4 bytes cannot hold the header of a freeblock, so they are fragments instead.
+    pub fn free_blocks(&self) -> Vec<(u16, u16)> {
+        self.gaps()
+            .into_iter()
+            .filter(|(_, size)| *size >= MIN_FREE_BLOCK_SIZE)
+            .collect()
     }
 
-    /// Returns the free space on the page .
+    /// Returns the free space on the page: the unallocated space between the slot array and the content,
+    /// plus the freeblocks and the fragments.
     pub fn free_space(&self) -> usize {
-        let header_size = self.header.size();
-        let cell_indices_size = self.cell_indices.len() * 2; // 2 bytes per index
+        let unallocated =
+            (self.header.content_start_offset as usize).saturating_sub(self.used_space());
+        let freed: usize = self.gaps().iter().map(|(_, size)| *size as usize).sum();
+        unallocated + freed
+    }
 
-        let used_space = if self.page_number == 1 {
-            HEADER_SIZE + header_size + cell_indices_size
+    /// Bytes at the beginning of the page taken by the headers and the slot array.
+    fn used_space(&self) -> usize {
+        // If we are at page 1 we need to add the header size, as the database header is stored on page 1.
+        let database_header = if self.page_number == 1 {
+            HEADER_SIZE
         } else {
-            header_size + cell_indices_size
+            0
         };
+        database_header + self.header.size() + self.cell_indices.len() * 2
+    }
 
-        let content_size = self.page_size as usize - self.header.content_start_offset as usize;
+    /// Offset of the end of the usable area, where the reserved space starts.
+    fn usable_end(&self) -> usize {
+        self.page_size as usize - self.reserved_space as usize
+    }
+
+    /// Finds room for `size` bytes of content (see `insert_cell`), with 2 more bytes for the new slot.
+    /// The caller checked that the page has enough free space.
+    fn allocate(&mut self, size: usize) -> u16 {
+        let slots_end = self.used_space() + 2;
+        let content_start = self.header.content_start_offset as usize;
+
+        if slots_end <= content_start {
+            let fragmented = self.header.fragmented_free_bytes as usize;
+            for (offset, block_size) in self.free_blocks() {
+                let left = (block_size as usize).checked_sub(size);
+                match left {
+                    Some(left)
+                        if left >= MIN_FREE_BLOCK_SIZE as usize
+                            || fragmented + left <= MAX_FRAGMENTED_BYTES =>
+                    {
+                        return offset + left as u16;
+                    }
+                    _ => {}
+                }
+            }
+            if slots_end + size <= content_start {
+                self.header.content_start_offset -= size as u16;
+                return self.header.content_start_offset;
+            }
+        }
 
-        self.page_size as usize - used_space - content_size - self.reserved_space as usize
+        self.defragment();
+        self.header.content_start_offset -= size as u16;
+        self.header.content_start_offset
+    }
+
+    /// Gets the runs of free bytes between the content start and the end of the usable area,
+    /// as `(offset, size)` pairs sorted by offset.
+    fn gaps(&self) -> Vec<(u16, u16)> {
+        let mut cells = self
+            .cell_indices
+            .iter()
+            .zip(&self.cells)
+            .map(|(offset, cell)| (*offset as usize, cell.size()))
+            .collect::<Vec<_>>();
+        cells.sort_unstable();
+
+        // Only the header of a page built by hand can have the content start over the slot array
+        let mut gaps = Vec::new();
+        let mut position = (self.header.content_start_offset as usize).max(self.used_space());
+        for (offset, size) in cells {
+            if offset > position {
+                gaps.push((position as u16, (offset - position) as u16));
+            }
+            position = position.max(offset + size);
+        }
+        // Pages being read do not know their size yet
+        if self.page_size > 0 && position < self.usable_end() {
+            gaps.push((position as u16, (self.usable_end() - position) as u16));
+        }
+        gaps
+    }
+
+    /// Sets the first freeblock and the fragmented bytes of the header from the cells of the page.
+    fn update_free_blocks(&mut self) {
+        (
+            self.header.first_free_block_offset,
+            self.header.fragmented_free_bytes,
+        ) = self.free_space_header();
+    }
+
+    /// Works out the first freeblock and the fragmented bytes from the cells of the page.
+    fn free_space_header(&self) -> (u16, u8) {
+        let gaps = self.gaps();
+        let first_free_block = gaps
+            .iter()
+            .find(|(_, size)| *size >= MIN_FREE_BLOCK_SIZE)
+            .map_or(0, |(offset, _)| *offset);
+        let fragmented: usize = gaps
+            .iter()
+            .filter(|(_, size)| *size < MIN_FREE_BLOCK_SIZE)
+            .map(|(_, size)| *size as usize)
+            .sum();
+        (first_free_block, fragmented.min(u8::MAX as usize) as u8)
     }
 }
 
-// Updated implementation for BTreePage
-impl ByteSerializable for BTreePage {
-    fn read_from<R: Read>(reader: &mut R) -> io::Result<Self> {
+impl BTreePage {
+    /// Reads a page whose cells were built with the given payload limits, which say how much of each payload
+    /// is in the page and so where the pointer to its overflow chain is.
+    ///
+    /// # Parameters
+    /// * `reader` - The usable part of the page, from its header on.
+    /// * `limits` - The limits the tree of the page builds its cells with.
+    ///
+    /// # Errors
+    /// Returns an `InvalidData` error if a cell does not fit in the page, or an error if the page cannot be read.
+    pub fn read_with_limits<R: Read>(reader: &mut R, limits: PayloadLimits) -> io::Result<Self> {
+        Self::read_cells(reader, Some(limits))
+    }
+
+    /// Reads a page. Without `limits`, the page is taken as all usable, with the default payload fractions.
+    fn read_cells<R: Read>(reader: &mut R, limits: Option<PayloadLimits>) -> io::Result<Self> {
         // Read the header
         let header = BTreePageHeader::read_from(reader)?;
 
@@ -571,6 +770,13 @@ impl ByteSerializable for BTreePage {
         let header_size = page.header.size();
         let indices_size = page.cell_indices.len() * 2; // 2 bytes per index
         let content_start = header_size + indices_size;
+        let limits = limits.unwrap_or_else(|| {
+            PayloadLimits::new(
+                content_start + remaining_data.len(),
+                DEFAULT_MAX_PAYLOAD_FRACTION,
+                DEFAULT_MIN_PAYLOAD_FRACTION,
+            )
+        });
 
         for &cell_index in &page.cell_indices {
             // Calculate the actual offset in the remaining_data
@@ -601,8 +807,14 @@ impl ByteSerializable for BTreePage {
 
                     // Read the payload
                     let payload_size = payload_size as usize;
-                    let available_bytes = remaining_data.len() - cell_offset - header_bytes;
-                    let local_size = payload_size.min(available_bytes);
+                    let local_size = limits.local_size(payload_size);
+                    let cell_end = cell_offset
+                        + header_bytes
+                        + local_size
+                        + if local_size < payload_size { 4 } else { 0 };
+                    if cell_end > remaining_data.len() {
+                        return Err(cell_out_of_range(cell_offset));
+                    }
 
                     let mut payload = vec![0u8; local_size];
                     let payload_start = cell_cursor.position() as usize;
@@ -613,16 +825,12 @@ impl ByteSerializable for BTreePage {
                     // Check if there's an overflow page (if payload doesn't fit)
                     let overflow_page = if local_size < payload_size {
                         let overflow_offset = cell_offset + header_bytes + local_size;
-                        if overflow_offset + 4 <= remaining_data.len() {
-                            Some(u32::from_be_bytes([
-                                remaining_data[overflow_offset],
-                                remaining_data[overflow_offset + 1],
-                                remaining_data[overflow_offset + 2],
-                                remaining_data[overflow_offset + 3],
-                            ]))
-                        } else {
-                            None
-                        }
+                        Some(u32::from_be_bytes([
+                            remaining_data[overflow_offset],
+                            remaining_data[overflow_offset + 1],
+                            remaining_data[overflow_offset + 2],
+                            remaining_data[overflow_offset + 3],
+                        ]))
                     } else {
                         None
                     };
@@ -669,8 +877,14 @@ impl ByteSerializable for BTreePage {
 
                     // Read the payload
                     let payload_size = payload_size as usize;
-                    let available_bytes = remaining_data.len() - cell_offset - header_bytes;
-                    let local_size = payload_size.min(available_bytes);
+                    let local_size = limits.local_size(payload_size);
+                    let cell_end = cell_offset
+                        + header_bytes
+                        + local_size
+                        + if local_size < payload_size { 4 } else { 0 };
+                    if cell_end > remaining_data.len() {
+                        return Err(cell_out_of_range(cell_offset));
+                    }
 
                     let mut payload = vec![0u8; local_size];
                     let payload_start = cell_cursor.position() as usize;
@@ -681,16 +895,12 @@ impl ByteSerializable for BTreePage {
                     // Check if there's an overflow page
                     let overflow_page = if local_size < payload_size {
                         let overflow_offset = cell_offset + header_bytes + local_size;
-                        if overflow_offset + 4 <= remaining_data.len() {
-                            Some(u32::from_be_bytes([
-                                remaining_data[overflow_offset],
-                                remaining_data[overflow_offset + 1],
-                                remaining_data[overflow_offset + 2],
-                                remaining_data[overflow_offset + 3],
-                            ]))
-                        } else {
-                            None
-                        }
+                        Some(u32::from_be_bytes([
+                            remaining_data[overflow_offset],
+                            remaining_data[overflow_offset + 1],
+                            remaining_data[overflow_offset + 2],
+                            remaining_data[overflow_offset + 3],
+                        ]))
                     } else {
                         None
                     };
@@ -727,8 +937,14 @@ impl ByteSerializable for BTreePage {
 
                     // Read the payload
                     let payload_size = payload_size as usize;
-                    let available_bytes = remaining_data.len() - cell_offset - header_bytes;
-                    let local_size = payload_size.min(available_bytes);
+                    let local_size = limits.local_size(payload_size);
+                    let cell_end = cell_offset
+                        + header_bytes
+                        + local_size
+                        + if local_size < payload_size { 4 } else { 0 };
+                    if cell_end > remaining_data.len() {
+                        return Err(cell_out_of_range(cell_offset));
+                    }
 
                     let mut payload = vec![0u8; local_size];
                     payload.copy_from_slice(
@@ -738,16 +954,12 @@ impl ByteSerializable for BTreePage {
                     // Check if there's an overflow page
                     let overflow_page = if local_size < payload_size {
                         let overflow_offset = cell_offset + header_bytes + local_size;
-                        if overflow_offset + 4 <= remaining_data.len() {
-                            Some(u32::from_be_bytes([
-                                remaining_data[overflow_offset],
-                                remaining_data[overflow_offset + 1],
-                                remaining_data[overflow_offset + 2],
-                                remaining_data[overflow_offset + 3],
-                            ]))
-                        } else {
-                            None
-                        }
+                        Some(u32::from_be_bytes([
+                            remaining_data[overflow_offset],
+                            remaining_data[overflow_offset + 1],
+                            remaining_data[overflow_offset + 2],
+                            remaining_data[overflow_offset + 3],
+                        ]))
                     } else {
                         None
                     };
@@ -772,10 +984,28 @@ impl ByteSerializable for BTreePage {
 
         Ok(page)
     }
+}
+
+/// Error for a cell that runs past the end of its page.
+fn cell_out_of_range(cell_offset: usize) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("Cell at offset {} overflows its page", cell_offset),
+    )
+}
+
+impl ByteSerializable for BTreePage {
+    /// Reads a page taking all of it as usable, with the default payload fractions (see `read_with_limits`).
+    fn read_from<R: Read>(reader: &mut R) -> io::Result<Self> {
+        Self::read_cells(reader, None)
+    }
 
     fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
-        // Write the header
-        self.header.write_to(writer)?;
+        // Write the header, with the freeblocks and fragments of the cells as they are now
+        let free_blocks = self.free_blocks();
+        let mut header = self.header.clone();
+        (header.first_free_block_offset, header.fragmented_free_bytes) = self.free_space_header();
+        header.write_to(writer)?;
 
         // Write cell indices
         for &idx in &self.cell_indices {
@@ -890,6 +1120,14 @@ impl ByteSerializable for BTreePage {
             }
         }
 
+        // Chain the freeblocks: every one starts with the offset of the next one (0 for the last) and its size
+        for (index, (offset, size)) in free_blocks.iter().enumerate() {
+            let next = free_blocks.get(index + 1).map_or(0, |(next, _)| *next);
+            let start = *offset as usize - content_start;
+            content_buffer[start..start + 2].copy_from_slice(&next.to_be_bytes());
+            content_buffer[start + 2..start + 4].copy_from_slice(&size.to_be_bytes());
+        }
+
         // Write the content buffer
         writer.write_all(&content_buffer)?;
 
@@ -1988,7 +2226,7 @@ mod tests {
         let free = FreePage::new(1, 4096, 6);
 
         // Enums Page
-        let pages = vec![
+        let pages = [
             Page::BTree(table_leaf),
             Page::BTree(table_interior),
             Page::BTree(index_leaf),
@@ -2277,4 +2515,220 @@ mod tests {
         assert_eq!(page_overflow.page_size(), 4096);
         assert_eq!(page_free.page_size(), 4096);
     }
+
+    /// A table leaf cell of exactly `size` bytes (rowids under 128).
+    fn leaf_cell(row_id: i64, size: usize) -> BTreeCell {
+        // The payload size takes a second byte from 128 up
+        let length = if size - 2 < 128 { size - 2 } else { size - 3 };
+        let payload = vec![row_id as u8; length];
+        BTreeCell::TableLeaf(TableLeafCell {
+            payload_size: payload.len() as u64,
+            row_id,
+            payload,
+            overflow_page: None,
+        })
+    }
+
+    fn row_ids(page: &BTreePage) -> Vec<i64> {
+        page.cells
+            .iter()
+            .map(|cell| match cell {
+                BTreeCell::TableLeaf(cell) => cell.row_id,
+                _ => unreachable!(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_btree_page_remove_cell_free_blocks() {
+        let mut page = BTreePage::new(PageType::TableLeaf, 4096, 2, 0, None).unwrap();
+        for row_id in 1..=5 {
+            page.add_cell(leaf_cell(row_id, 100)).unwrap();
+        }
+        let offsets = page.cell_indices.clone();
+        let free_space = page.free_space();
+        assert!(page.free_blocks().is_empty());
+
+        // The cells around the removed one stay where they were
+        let removed = page.remove_cell(1).unwrap();
+        assert!(matches!(removed, BTreeCell::TableLeaf(cell) if cell.row_id == 2));
+        assert_eq!(
+            page.cell_indices,
+            vec![offsets[0], offsets[2], offsets[3], offsets[4]]
+        );
+        assert_eq!(page.free_blocks(), vec![(offsets[1], 100)]);
+        assert_eq!(page.header.first_free_block_offset, offsets[1]);
+        assert_eq!(page.free_space(), free_space + 102);
+
+        // Freeblocks next to each other merge
+        page.remove_cell(1).unwrap();
+        assert_eq!(page.free_blocks(), vec![(offsets[2], 200)]);
+
+        // The first cell of the content area goes back to the unallocated space
+        page.remove_cell(2).unwrap();
+        assert_eq!(page.header.content_start_offset, offsets[3]);
+        assert_eq!(page.free_blocks(), vec![(offsets[2], 200)]);
+        assert_eq!(row_ids(&page), vec![1, 4]);
+    }
+
+    #[test]
+    fn test_btree_page_first_fit() {
+        let mut page = BTreePage::new(PageType::TableLeaf, 4096, 2, 0, None).unwrap();
+        for row_id in 1..=5 {
+            page.add_cell(leaf_cell(row_id, 100)).unwrap();
+        }
+        let offsets = page.cell_indices.clone();
+        page.remove_cell(3).unwrap();
+        page.remove_cell(1).unwrap();
+        let content_start = page.header.content_start_offset;
+
+        // Too big for the freeblocks, so it goes to the unallocated space
+        page.insert_cell(1, leaf_cell(6, 150)).unwrap();
+        assert_eq!(page.header.content_start_offset, content_start - 150);
+
+        // The first freeblock of the chain (the lowest) that fits, taking its last bytes
+        page.insert_cell(1, leaf_cell(7, 60)).unwrap();
+        assert_eq!(page.cell_indices[1], offsets[3] + 40);
+        assert_eq!(
+            page.free_blocks(),
+            vec![(offsets[3], 40), (offsets[1], 100)]
+        );
+
+        // A remainder under 4 bytes is a fragment
+        page.add_cell(leaf_cell(8, 98)).unwrap();
+        assert_eq!(page.free_blocks(), vec![(offsets[3], 40)]);
+        assert_eq!(page.header.fragmented_free_bytes, 2);
+        assert_eq!(row_ids(&page), vec![1, 7, 6, 3, 5, 8]);
+    }
+
+    #[test]
+    fn test_btree_page_defragment_on_insert() {
+        let mut page = BTreePage::new(PageType::TableLeaf, 1024, 2, 0, None).unwrap();
+        let mut row_id = 0;
+        while page.free_space() >= 102 {
+            row_id += 1;
+            page.add_cell(leaf_cell(row_id, 100)).unwrap();
+        }
+
+        // Every other cell removed, but not the lowest one: lots of free bytes, but no run of 150
+        for index in (1..row_id as u16 - 1).step_by(2).rev() {
+            page.remove_cell(index).unwrap();
+        }
+        assert!(page.free_space() > 152);
+        assert!(page.free_blocks().iter().all(|(_, size)| *size == 100));
+
+        page.add_cell(leaf_cell(100, 150)).unwrap();
+        assert!(page.free_blocks().is_empty());
+        assert_eq!(page.header.fragmented_free_bytes, 0);
+
+        // Packed against the end of the page, in slot order
+        let mut offset = 1024;
+        for (index, cell) in page.cells.iter().enumerate() {
+            offset -= cell.size() as u16;
+            assert_eq!(page.cell_indices[index], offset);
+        }
+
+        // Too big for the page even defragmented
+        let free_space = page.free_space();
+        assert!(page.add_cell(leaf_cell(101, free_space - 1)).is_err());
+        assert_eq!(page.free_space(), free_space);
+    }
+
+    #[test]
+    fn test_btree_page_fragment_limit() {
+        let mut page = BTreePage::new(PageType::TableLeaf, 4096, 2, 0, None).unwrap();
+        for row_id in 1..=30 {
+            page.add_cell(leaf_cell(row_id, 3)).unwrap();
+            page.add_cell(leaf_cell(row_id + 100, 10)).unwrap();
+        }
+
+        // Each 3 byte cell between two others leaves a fragment
+        for index in 0..20 {
+            page.remove_cell(index).unwrap();
+            assert_eq!(
+                page.header.fragmented_free_bytes as usize,
+                3 * (index as usize + 1)
+            );
+        }
+        let free_space = page.free_space();
+
+        // Until there are too many of them
+        page.remove_cell(20).unwrap();
+        assert_eq!(page.header.fragmented_free_bytes, 0);
+        assert!(page.free_blocks().is_empty());
+        assert_eq!(page.free_space(), free_space + 5);
+    }
+
+    #[test]
+    fn test_btree_page_free_block_serialization() {
+        let mut page = BTreePage::new(PageType::TableLeaf, 4096, 2, 0, None).unwrap();
+        for row_id in 1..=6 {
+            page.add_cell(leaf_cell(row_id, 50)).unwrap();
+        }
+        page.remove_cell(4).unwrap();
+        page.remove_cell(1).unwrap();
+        page.add_cell(leaf_cell(7, 48)).unwrap();
+        let free_blocks = page.free_blocks();
+        assert_eq!(free_blocks.len(), 1);
+        assert_eq!(page.header.fragmented_free_bytes, 2);
+
+        let mut buffer = Vec::new();
+        page.write_to(&mut buffer).unwrap();
+
+        // The chain is in the page: offset of the next freeblock, then the size
+        let (offset, size) = free_blocks[0];
+        assert_eq!(&buffer[1..3], &offset.to_be_bytes());
+        assert_eq!(&buffer[offset as usize..offset as usize + 2], &[0, 0]);
+        assert_eq!(
+            &buffer[offset as usize + 2..offset as usize + 4],
+            &size.to_be_bytes()
+        );
+
+        let mut read_page = BTreePage::read_from(&mut Cursor::new(buffer)).unwrap();
+        read_page.page_size = 4096;
+        read_page.page_number = 2;
+        assert_eq!(read_page.header.first_free_block_offset, offset);
+        assert_eq!(read_page.header.fragmented_free_bytes, 2);
+        assert_eq!(read_page.free_blocks(), free_blocks);
+        assert_eq!(read_page.free_space(), page.free_space());
+        assert_eq!(row_ids(&read_page), vec![1, 3, 4, 6, 7]);
+    }
+
+    #[test]
+    fn test_btree_page_overflow_cells_roundtrip() {
+        use crate::tree::cell::BTreeCellFactory;
+
+        // The cell ends with the pointer to the chain, well before the end of the page
+        let limits = PayloadLimits::new(1016, 255, 32);
+        let mut page = BTreePage::new(PageType::TableLeaf, 1024, 2, 8, None).unwrap();
+        let payload = (0..1504).map(|i| i as u8).collect::<Vec<_>>();
+        let (cell, overflow) = BTreeCellFactory::create_table_leaf_cell(
+            7,
+            payload.clone(),
+            limits.max_local_payload,
+            limits.min_local_payload,
+            limits.usable_size,
+        )
+        .unwrap();
+        let BTreeCell::TableLeaf(mut leaf) = cell else {
+            panic!("Expected TableLeaf");
+        };
+        let local_size = limits.local_size(payload.len());
+        assert_eq!(overflow.unwrap().len(), payload.len() - local_size);
+        leaf.overflow_page = Some(0xCAFE);
+        page.add_cell(BTreeCell::TableLeaf(leaf)).unwrap();
+
+        let mut buffer = Vec::new();
+        page.write_to(&mut buffer).unwrap();
+        let read_page =
+            BTreePage::read_with_limits(&mut Cursor::new(&buffer[..1016]), limits).unwrap();
+        match &read_page.cells[0] {
+            BTreeCell::TableLeaf(leaf_cell) => {
+                assert_eq!(leaf_cell.payload_size, 1504);
+                assert_eq!(leaf_cell.payload, payload[..local_size]);
+                assert_eq!(leaf_cell.overflow_page, Some(0xCAFE));
+            }
+            _ => panic!("Expected TableLeaf"),
+        }
+    }
 }
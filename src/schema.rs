@@ -0,0 +1,606 @@
+//! # Schema Module
+//!
+//! A `Record` is just a list of values, and the storage engine is happy to keep whatever it is given.
+//! That is fine for the layers that know what they are doing, but most tables have columns with a name and
+//! a declared type, and SQLite does a bit of work with them on every insert:
+//!
+//! - Every value goes through the affinity of its column (see `Affinity` in utils/serialization.rs), so the
+//!   text `'42'` inserted in an INTEGER column is stored as the integer 42.
+//! - Columns missing at the end of the record take their DEFAULT value, or NULL if they have none.
+//! - A record with more values than columns is rejected, and so is a NULL in a NOT NULL column.
+//!
+//! A `TableSchema` is that list of columns. Tables created with `RQLite::create_table_with_columns` have one,
+//! saved in their row of the schema table, and `table_insert` and `bulk_load` apply it to every record.
+//! Tables created with `create_table` have none and keep storing records as they come.
+//!
+//! Schemas change over time, and the operations that change them copy what `ALTER TABLE` does in SQLite:
+//!
+//! - `add_column` only changes the schema. The records already stored are one value short, and reading them
+//!   fills the missing columns at the end with their DEFAULT (see `TableSchema::complete`), so an added
+//!   column costs nothing however big the table is. That is also why a NOT NULL column needs a default.
+//! - `rename_column` only changes the schema too, records do not know the names of their values.
+//! - `drop_column` is the expensive one: the values after the dropped column move one place to the left,
+//!   so `RQLite::drop_column` rewrites every row of the table.
+//!
+//! Every change to the schema table bumps `Header::schema_cookie`, like in SQLite, which is how other
+//! connections to the database know their copy of the catalog is stale.
+//!
+//! A column can also be the rowid of the table, what SQLite calls an INTEGER PRIMARY KEY. Its value is the
+//! rowid the record is stored under, so records keep a NULL in its place and reads put the rowid back.
+//!
+//! Indexes created with SQL (see the sql module) have an `IndexSchema`: their name, their table and the columns
+//! they are on, saved in their row of the schema table the same way.
+
+use std::io;
+
+use crate::tree::record::Record;
+use crate::utils::serialization::{Affinity, SqliteValue};
+
+/// A column of a table.
+///
+/// # Example
+/// ```rust
+/// use rqlite_engine::{Column, SqliteValue};
+///
+/// let column = Column::new("status", "VARCHAR(16)")
+///     .not_null()
+///     .default_value(SqliteValue::String("new".to_string()));
+/// assert!(column.not_null);
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct Column {
+    /// Name of the column.
+    pub name: String,
+    /// Type in the column definition, like `VARCHAR(255)`. It only matters for the affinity of the column.
+    /// Empty if the column has no type.
+    pub declared_type: String,
+    /// Whether NULL values are rejected.
+    pub not_null: bool,
+    /// Value of the column when a record does not have it. `None` is the same as DEFAULT NULL.
+    pub default: Option<SqliteValue>,
+    /// Whether the column is the rowid of the table, like an INTEGER PRIMARY KEY in SQLite.
+    pub primary_key: bool,
+}
+
+impl Column {
+    /// Creates a nullable column without a default value.
+    ///
+    /// # Parameters
+    /// * `name` - Name of the column.
+    /// * `declared_type` - Declared type of the column, empty for none.
+    pub fn new(name: impl Into<String>, declared_type: impl Into<String>) -> Self {
+        Column {
+            name: name.into(),
+            declared_type: declared_type.into(),
+            not_null: false,
+            default: None,
+            primary_key: false,
+        }
+    }
+
+    /// Makes the column NOT NULL.
+    pub fn not_null(mut self) -> Self {
+        self.not_null = true;
+        self
+    }
+
+    /// Sets the DEFAULT value of the column.
+    pub fn default_value(mut self, value: SqliteValue) -> Self {
+        self.default = Some(value);
+        self
+    }
+
+    /// Makes the column the rowid of the table, like an INTEGER PRIMARY KEY.
+    pub fn primary_key(mut self) -> Self {
+        self.primary_key = true;
+        self
+    }
+
+    /// Gets the affinity of the column, from its declared type.
+    pub fn affinity(&self) -> Affinity {
+        Affinity::from_declared_type(&self.declared_type)
+    }
+
+    /// Gets the value the column takes when a record does not have it, with the affinity of the column applied.
+    pub fn default_or_null(&self) -> SqliteValue {
+        let value = self.default.clone().unwrap_or(SqliteValue::Null);
+        self.affinity().apply(value)
+    }
+}
+
+/// The columns of a table.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TableSchema {
+    columns: Vec<Column>,
+}
+
+impl TableSchema {
+    /// Creates the schema of a table.
+    ///
+    /// # Parameters
+    /// * `columns` - The columns of the table, in order.
+    ///
+    /// # Errors
+    /// Returns an `InvalidInput` error if there are no columns, a column has no name,
+    /// two columns have the same name (names are compared ignoring ASCII case, as in SQLite)
+    /// or more than one column is the primary key.
+    pub fn new(columns: Vec<Column>) -> io::Result<Self> {
+        if columns.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "A table must have at least one column",
+            ));
+        }
+        for (i, column) in columns.iter().enumerate() {
+            if column.name.is_empty() {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("Column {} has no name", i),
+                ));
+            }
+            if columns[..i]
+                .iter()
+                .any(|other| other.name.eq_ignore_ascii_case(&column.name))
+            {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("Duplicate column name: {}", column.name),
+                ));
+            }
+        }
+        if columns.iter().filter(|column| column.primary_key).count() > 1 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "A table can only have one primary key",
+            ));
+        }
+        Ok(TableSchema { columns })
+    }
+
+    /// Gets the columns of the table, in order.
+    pub fn columns(&self) -> &[Column] {
+        &self.columns
+    }
+
+    /// Finds the position of a column by name, ignoring ASCII case.
+    pub fn column_index(&self, name: &str) -> Option<usize> {
+        self.columns
+            .iter()
+            .position(|column| column.name.eq_ignore_ascii_case(name))
+    }
+
+    /// Finds the position of the column that is the rowid of the table, if there is one.
+    pub fn primary_key(&self) -> Option<usize> {
+        self.columns.iter().position(|column| column.primary_key)
+    }
+
+    /// Turns a record into the one to store: missing columns take their default, every value goes through
+    /// the affinity of its column, and the NOT NULL constraints are checked.
+    /// The primary key column is stored as NULL, as its value is the rowid.
+    ///
+    /// # Parameters
+    /// * `record` - The record to insert.
+    ///
+    /// # Errors
+    /// Returns an `InvalidInput` error if the record has more values than the table has columns,
+    /// or a NOT NULL column ends up NULL.
+    ///
+    /// # Returns
+    /// The record with one value per column.
+    pub fn apply(&self, record: &Record) -> io::Result<Record> {
+        if record.len() > self.columns.len() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "Table has {} columns but {} values were supplied",
+                    self.columns.len(),
+                    record.len()
+                ),
+            ));
+        }
+
+        let mut values = Vec::with_capacity(self.columns.len());
+        for (i, column) in self.columns.iter().enumerate() {
+            if column.primary_key {
+                values.push(SqliteValue::Null);
+                continue;
+            }
+            let value = match record.values.get(i) {
+                Some(value) => column.affinity().apply(value.clone()),
+                None => column.default_or_null(),
+            };
+            if column.not_null && value == SqliteValue::Null {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("NOT NULL constraint failed: {}", column.name),
+                ));
+            }
+            values.push(value);
+        }
+        Ok(Record::with_values(values))
+    }
+
+    /// Fills the columns missing at the end of a stored record with their defaults,
+    /// which is how records written before an `add_column` are read, and puts the rowid in the primary key.
+    ///
+    /// # Parameters
+    /// * `rowid` - The rowid the record is stored under.
+    /// * `record` - A record read from the table.
+    ///
+    /// # Returns
+    /// The record with at least one value per column.
+    pub fn complete(&self, rowid: i64, mut record: Record) -> Record {
+        for column in self.columns.iter().skip(record.len()) {
+            record.add_value(column.default_or_null());
+        }
+        if let Some(primary_key) = self.primary_key() {
+            record.set_value(primary_key, SqliteValue::Integer(rowid));
+        }
+        record
+    }
+
+    /// Gets the value of every column for the records that do not have it, in column order.
+    /// This is what `BTree::find_columns` needs to read the columns added after a record was written.
+    pub fn defaults(&self) -> Vec<SqliteValue> {
+        self.columns.iter().map(Column::default_or_null).collect()
+    }
+
+    /// Adds a column at the end of the table, like `ALTER TABLE ADD COLUMN`.
+    /// The records already stored do not change, they read the default of the new column.
+    ///
+    /// # Parameters
+    /// * `column` - The new column.
+    ///
+    /// # Errors
+    /// Returns an `InvalidInput` error if the column has no name, a column with the same name exists,
+    /// the column is NOT NULL without a default value other than NULL, or it is a primary key.
+    pub fn add_column(&mut self, column: Column) -> io::Result<()> {
+        if column.primary_key {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Cannot add a PRIMARY KEY column: {}", column.name),
+            ));
+        }
+        if column.not_null && column.default_or_null() == SqliteValue::Null {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "Cannot add a NOT NULL column with default value NULL: {}",
+                    column.name
+                ),
+            ));
+        }
+
+        let mut columns = self.columns.clone();
+        columns.push(column);
+        *self = TableSchema::new(columns)?;
+        Ok(())
+    }
+
+    /// Renames a column, like `ALTER TABLE RENAME COLUMN`. Records do not change.
+    ///
+    /// # Parameters
+    /// * `name` - Current name of the column, ignoring ASCII case.
+    /// * `new_name` - New name of the column.
+    ///
+    /// # Errors
+    /// Returns a `NotFound` error if there is no such column, or an `InvalidInput` error if the new name
+    /// is empty or taken by another column.
+    pub fn rename_column(&mut self, name: &str, new_name: &str) -> io::Result<()> {
+        let position = self.position(name)?;
+        let mut columns = self.columns.clone();
+        columns[position].name = new_name.to_string();
+        *self = TableSchema::new(columns)?;
+        Ok(())
+    }
+
+    /// Removes a column, like `ALTER TABLE DROP COLUMN`. The records stored have to drop the value
+    /// of the column too (see `RQLite::drop_column`).
+    ///
+    /// # Parameters
+    /// * `name` - Name of the column, ignoring ASCII case.
+    ///
+    /// # Errors
+    /// Returns a `NotFound` error if there is no such column, or an `InvalidInput` error if it is the only one
+    /// or the primary key.
+    ///
+    /// # Returns
+    /// The position the column had, which is the value to remove from the records.
+    pub fn drop_column(&mut self, name: &str) -> io::Result<usize> {
+        let position = self.position(name)?;
+        if self.primary_key() == Some(position) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Cannot drop the PRIMARY KEY column: {}", name),
+            ));
+        }
+        if self.columns.len() == 1 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Cannot drop the only column of a table: {}", name),
+            ));
+        }
+        self.columns.remove(position);
+        Ok(position)
+    }
+
+    /// Finds the position of a column by name, or a `NotFound` error.
+    fn position(&self, name: &str) -> io::Result<usize> {
+        self.column_index(name).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::NotFound, format!("No such column: {}", name))
+        })
+    }
+
+    /// Serializes the schema for the schema table: four values per column,
+    /// the name, the declared type, the flags (1 if it is NOT NULL, plus 2 if it is the primary key)
+    /// and the default value.
+    ///
+    /// # Errors
+    /// Returns an error if the record cannot be serialized.
+    pub fn to_bytes(&self) -> io::Result<Vec<u8>> {
+        let mut record = Record::new();
+        for column in &self.columns {
+            record.add_value(SqliteValue::String(column.name.clone()));
+            record.add_value(SqliteValue::String(column.declared_type.clone()));
+            let flags = column.not_null as i64 | (column.primary_key as i64) << 1;
+            record.add_value(SqliteValue::Integer(flags));
+            record.add_value(column.default.clone().unwrap_or(SqliteValue::Null));
+        }
+        record.to_bytes()
+    }
+
+    /// Deserializes a schema written by `to_bytes`.
+    ///
+    /// # Errors
+    /// Returns an `InvalidData` error if the bytes are not a valid schema.
+    pub fn from_bytes(data: &[u8]) -> io::Result<Self> {
+        let invalid = || io::Error::new(io::ErrorKind::InvalidData, "Invalid table schema");
+        let (record, _) = Record::from_bytes(data)?;
+        if record.values.len() % 4 != 0 {
+            return Err(invalid());
+        }
+
+        let mut columns = Vec::with_capacity(record.values.len() / 4);
+        for chunk in record.values.chunks(4) {
+            let column = match chunk {
+                [SqliteValue::String(name), SqliteValue::String(declared_type), SqliteValue::Integer(flags), default] => {
+                    Column {
+                        name: name.clone(),
+                        declared_type: declared_type.clone(),
+                        not_null: flags & 1 != 0,
+                        default: match default {
+                            SqliteValue::Null => None,
+                            value => Some(value.clone()),
+                        },
+                        primary_key: flags & 2 != 0,
+                    }
+                }
+                _ => return Err(invalid()),
+            };
+            columns.push(column);
+        }
+        TableSchema::new(columns).map_err(|_| invalid())
+    }
+}
+
+/// What an index created with SQL is on.
+#[derive(Debug, Clone, PartialEq)]
+pub struct IndexSchema {
+    /// Name of the index.
+    pub name: String,
+    /// Table the index is on.
+    pub table_id: u32,
+    /// Names of the indexed columns, in order.
+    pub columns: Vec<String>,
+    /// Whether two rows cannot have the same values in the indexed columns. NULLs are all distinct, as in SQLite.
+    pub unique: bool,
+}
+
+impl IndexSchema {
+    /// Serializes the index for the schema table: the name, the table, 1 if it is unique and the column names.
+    ///
+    /// # Errors
+    /// Returns an error if the record cannot be serialized.
+    pub fn to_bytes(&self) -> io::Result<Vec<u8>> {
+        let mut record = Record::with_values(vec![
+            SqliteValue::String(self.name.clone()),
+            SqliteValue::Integer(self.table_id as i64),
+            SqliteValue::Integer(self.unique as i64),
+        ]);
+        for column in &self.columns {
+            record.add_value(SqliteValue::String(column.clone()));
+        }
+        record.to_bytes()
+    }
+
+    /// Deserializes an index written by `to_bytes`.
+    ///
+    /// # Errors
+    /// Returns an `InvalidData` error if the bytes are not a valid index.
+    pub fn from_bytes(data: &[u8]) -> io::Result<Self> {
+        let invalid = || io::Error::new(io::ErrorKind::InvalidData, "Invalid index schema");
+        let (record, _) = Record::from_bytes(data)?;
+        let [SqliteValue::String(name), SqliteValue::Integer(table_id), SqliteValue::Integer(unique), columns @ ..] =
+            &record.values[..]
+        else {
+            return Err(invalid());
+        };
+
+        let columns = columns
+            .iter()
+            .map(|column| match column {
+                SqliteValue::String(column) => Ok(column.clone()),
+                _ => Err(invalid()),
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+        if columns.is_empty() {
+            return Err(invalid());
+        }
+        Ok(IndexSchema {
+            name: name.clone(),
+            table_id: u32::try_from(*table_id).map_err(|_| invalid())?,
+            columns,
+            unique: *unique != 0,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn users() -> TableSchema {
+        TableSchema::new(vec![
+            Column::new("id", "INTEGER").not_null(),
+            Column::new("name", "TEXT").not_null(),
+            Column::new("score", "REAL").default_value(SqliteValue::Integer(0)),
+            Column::new("data", ""),
+        ])
+        .unwrap()
+    }
+
+    #[test]
+    fn test_invalid_schemas() {
+        let err = TableSchema::new(Vec::new()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        let err =
+            TableSchema::new(vec![Column::new("a", "INT"), Column::new("A", "TEXT")]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert!(TableSchema::new(vec![Column::new("", "INT")]).is_err());
+    }
+
+    #[test]
+    fn test_apply_coerces_and_fills_defaults() {
+        let schema = users();
+        let record = Record::with_values(vec![
+            SqliteValue::String("7".to_string()),
+            SqliteValue::Integer(42),
+        ]);
+        let stored = schema.apply(&record).unwrap();
+        assert_eq!(
+            stored.values,
+            vec![
+                SqliteValue::Integer(7),
+                SqliteValue::String("42".to_string()),
+                SqliteValue::Float(0.0),
+                SqliteValue::Null,
+            ]
+        );
+        assert_eq!(schema.column_index("SCORE"), Some(2));
+        assert_eq!(schema.column_index("missing"), None);
+    }
+
+    #[test]
+    fn test_apply_checks_constraints() {
+        let schema = users();
+        let err = schema
+            .apply(&Record::with_values(vec![SqliteValue::Integer(1)]))
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert!(err.to_string().contains("name"));
+
+        let too_many = Record::with_values(vec![SqliteValue::Integer(1); 5]);
+        let err = schema.apply(&too_many).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn test_add_column_fills_old_records() {
+        let mut schema = users();
+        let old = schema
+            .apply(&Record::with_values(vec![
+                SqliteValue::Integer(1),
+                SqliteValue::String("ann".to_string()),
+            ]))
+            .unwrap();
+
+        let column =
+            Column::new("active", "BOOLEAN").default_value(SqliteValue::String("1".to_string()));
+        schema.add_column(column).unwrap();
+        assert_eq!(schema.columns().len(), 5);
+        let read = schema.complete(1, old.clone());
+        assert_eq!(read.values[..4], old.values[..]);
+        // Affinity applies to the default as well
+        assert_eq!(read.values[4], SqliteValue::Integer(1));
+        assert_eq!(schema.defaults()[2], SqliteValue::Float(0.0));
+
+        let err = schema
+            .add_column(Column::new("flag", "INT").not_null())
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert!(schema.add_column(Column::new("NAME", "TEXT")).is_err());
+        assert_eq!(schema.columns().len(), 5);
+    }
+
+    #[test]
+    fn test_rename_and_drop_column() {
+        let mut schema = users();
+        schema.rename_column("SCORE", "points").unwrap();
+        assert_eq!(schema.column_index("points"), Some(2));
+        assert_eq!(schema.column_index("score"), None);
+        let err = schema.rename_column("missing", "other").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+        let err = schema.rename_column("points", "Name").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+
+        assert_eq!(schema.drop_column("name").unwrap(), 1);
+        assert_eq!(schema.column_index("data"), Some(2));
+        schema.drop_column("data").unwrap();
+        schema.drop_column("points").unwrap();
+        let err = schema.drop_column("id").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn test_schema_roundtrip() {
+        let schema = users();
+        let bytes = schema.to_bytes().unwrap();
+        assert_eq!(TableSchema::from_bytes(&bytes).unwrap(), schema);
+        assert!(TableSchema::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+
+        let index = IndexSchema {
+            name: "users_name".to_string(),
+            table_id: 3,
+            columns: vec!["name".to_string(), "score".to_string()],
+            unique: true,
+        };
+        assert_eq!(
+            IndexSchema::from_bytes(&index.to_bytes().unwrap()).unwrap(),
+            index
+        );
+        assert!(IndexSchema::from_bytes(&bytes).is_err());
+    }
+
+    #[test]
+    fn test_primary_key_is_the_rowid() {
+        let schema = TableSchema::new(vec![
+            Column::new("id", "INTEGER").primary_key(),
+            Column::new("name", "TEXT"),
+        ])
+        .unwrap();
+        assert_eq!(schema.primary_key(), Some(0));
+
+        let stored = schema
+            .apply(&Record::with_values(vec![
+                SqliteValue::Integer(7),
+                SqliteValue::String("ann".to_string()),
+            ]))
+            .unwrap();
+        assert_eq!(stored.values[0], SqliteValue::Null);
+        assert_eq!(
+            schema.complete(7, stored).values[0],
+            SqliteValue::Integer(7)
+        );
+
+        let bytes = schema.to_bytes().unwrap();
+        assert_eq!(TableSchema::from_bytes(&bytes).unwrap(), schema);
+        let two_keys = TableSchema::new(vec![
+            Column::new("a", "INTEGER").primary_key(),
+            Column::new("b", "INTEGER").primary_key(),
+        ]);
+        assert!(two_keys.is_err());
+
+        let mut schema = schema;
+        assert!(schema.drop_column("id").is_err());
+    }
+}
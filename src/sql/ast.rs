@@ -0,0 +1,316 @@
+//! # SQL Syntax Tree
+//!
+//! What the parser makes of a statement. Names keep the offset they were written at, so the executor can
+//! point at them when a table or a column does not exist, the same way the parser points at syntax errors.
+
+use crate::utils::serialization::{Affinity, SqliteValue};
+
+/// A name as written in the statement, quotes removed.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Ident {
+    pub name: String,
+    /// Byte offset of the name in the SQL string.
+    pub offset: usize,
+}
+
+/// A statement.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Statement {
+    CreateTable {
+        name: Ident,
+        if_not_exists: bool,
+        columns: Vec<ColumnDef>,
+        constraints: Vec<TableConstraint>,
+    },
+    CreateIndex {
+        name: Ident,
+        table: Ident,
+        columns: Vec<Ident>,
+        unique: bool,
+        if_not_exists: bool,
+    },
+    DropTable {
+        name: Ident,
+        if_exists: bool,
+    },
+    DropIndex {
+        name: Ident,
+        if_exists: bool,
+    },
+    Insert {
+        table: Ident,
+        /// The column list, if there is one.
+        columns: Option<Vec<Ident>>,
+        source: InsertSource,
+    },
+    Select(Box<Select>),
+    Update {
+        table: Ident,
+        assignments: Vec<(Ident, Expr)>,
+        filter: Option<Expr>,
+    },
+    Delete {
+        table: Ident,
+        filter: Option<Expr>,
+    },
+    Begin,
+    Commit,
+    Rollback,
+}
+
+/// A column of a CREATE TABLE.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct ColumnDef {
+    pub name: Ident,
+    /// The type as written, like `VARCHAR(255)`, or empty.
+    pub declared_type: String,
+    pub primary_key: bool,
+    pub not_null: bool,
+    pub unique: bool,
+    pub default: Option<Expr>,
+}
+
+/// A constraint on several columns of a CREATE TABLE.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum TableConstraint {
+    PrimaryKey(Vec<Ident>),
+    Unique(Vec<Ident>),
+}
+
+/// Where the rows of an INSERT come from.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum InsertSource {
+    Values(Vec<Vec<Expr>>),
+    Select(Box<Select>),
+    DefaultValues,
+}
+
+/// A SELECT.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Select {
+    pub distinct: bool,
+    pub columns: Vec<ResultColumn>,
+    /// The tables of the FROM clause, the first one with no join.
+    pub from: Vec<Join>,
+    pub filter: Option<Expr>,
+    pub group_by: Vec<Expr>,
+    pub having: Option<Expr>,
+    pub order_by: Vec<OrderTerm>,
+    pub limit: Option<Expr>,
+    pub offset: Option<Expr>,
+}
+
+/// A column of the result of a SELECT.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum ResultColumn {
+    /// `*`, all the columns of all the tables.
+    All,
+    /// `table.*`.
+    TableAll(Ident),
+    Expr {
+        expr: Expr,
+        alias: Option<String>,
+        /// The expression as written, the name of the column when there is no alias.
+        text: String,
+    },
+}
+
+/// How a table of the FROM clause is joined to the ones before it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum JoinKind {
+    /// `JOIN`, `INNER JOIN`, or the first table.
+    Inner,
+    /// `LEFT [OUTER] JOIN`.
+    Left,
+    /// `CROSS JOIN` or a comma.
+    Cross,
+}
+
+/// A table of the FROM clause.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Join {
+    pub kind: JoinKind,
+    pub table: Ident,
+    pub alias: Option<String>,
+    pub on: Option<Expr>,
+}
+
+/// A term of an ORDER BY.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct OrderTerm {
+    pub expr: Expr,
+    pub descending: bool,
+}
+
+/// Unary operators.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum UnaryOp {
+    Negate,
+    Plus,
+    Not,
+}
+
+/// Binary operators.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum BinaryOp {
+    Or,
+    And,
+    Eq,
+    NotEq,
+    Lt,
+    LtEq,
+    Gt,
+    GtEq,
+    Is,
+    IsNot,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+    Concat,
+}
+
+impl BinaryOp {
+    /// Checks if the operator compares its operands, which is when SQLite applies column affinities to them.
+    pub fn is_comparison(self) -> bool {
+        matches!(
+            self,
+            BinaryOp::Eq
+                | BinaryOp::NotEq
+                | BinaryOp::Lt
+                | BinaryOp::LtEq
+                | BinaryOp::Gt
+                | BinaryOp::GtEq
+                | BinaryOp::Is
+                | BinaryOp::IsNot
+        )
+    }
+}
+
+/// An expression.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Expr {
+    Literal(SqliteValue),
+    /// A parameter, by its position in the parameters of the call (from 0).
+    Parameter(usize),
+    /// A column, as written. The executor replaces it with a `Slot`.
+    Column {
+        table: Option<Ident>,
+        name: Ident,
+    },
+    /// A value of the row the executor evaluates the expression on, with the affinity of its column if it is one.
+    Slot {
+        index: usize,
+        affinity: Option<Affinity>,
+    },
+    Unary {
+        op: UnaryOp,
+        expr: Box<Expr>,
+    },
+    Binary {
+        op: BinaryOp,
+        left: Box<Expr>,
+        right: Box<Expr>,
+    },
+    IsNull {
+        expr: Box<Expr>,
+        negated: bool,
+    },
+    Between {
+        expr: Box<Expr>,
+        low: Box<Expr>,
+        high: Box<Expr>,
+        negated: bool,
+    },
+    InList {
+        expr: Box<Expr>,
+        list: Vec<Expr>,
+        negated: bool,
+    },
+    Like {
+        expr: Box<Expr>,
+        pattern: Box<Expr>,
+        negated: bool,
+    },
+    Cast {
+        expr: Box<Expr>,
+        declared_type: String,
+    },
+    /// A function call. `COUNT(*)` has no arguments and `star` set.
+    Function {
+        name: Ident,
+        args: Vec<Expr>,
+        distinct: bool,
+        star: bool,
+    },
+}
+
+impl Expr {
+    /// Calls `f` on the expression and every expression inside it, parents first.
+    pub fn visit(&self, f: &mut impl FnMut(&Expr)) {
+        f(self);
+        for child in self.children() {
+            child.visit(f);
+        }
+    }
+
+    /// Rebuilds the expression bottom-up, replacing every sub-expression with what `f` makes of it.
+    /// `f` can stop the rebuild with an error.
+    pub fn transform<E>(mut self, f: &mut impl FnMut(Expr) -> Result<Expr, E>) -> Result<Expr, E> {
+        for child in self.children_mut() {
+            let taken = std::mem::replace(child, Expr::Literal(SqliteValue::Null));
+            *child = taken.transform(f)?;
+        }
+        f(self)
+    }
+
+    /// The expressions right inside this one, in the order they are written.
+    ///
+    /// The walks go through this list, and not a match of their own, so each level of an expression takes
+    /// little of the stack: expressions can be as deep as the parser allows.
+    fn children(&self) -> Vec<&Expr> {
+        match self {
+            Expr::Literal(_) | Expr::Parameter(_) | Expr::Column { .. } | Expr::Slot { .. } => {
+                Vec::new()
+            }
+            Expr::Unary { expr, .. } | Expr::IsNull { expr, .. } | Expr::Cast { expr, .. } => {
+                vec![expr]
+            }
+            Expr::Binary { left, right, .. } => vec![left, right],
+            Expr::Between {
+                expr, low, high, ..
+            } => vec![expr, low, high],
+            Expr::InList { expr, list, .. } => {
+                let mut children = vec![&**expr];
+                children.extend(list);
+                children
+            }
+            Expr::Like { expr, pattern, .. } => vec![expr, pattern],
+            Expr::Function { args, .. } => args.iter().collect(),
+        }
+    }
+
+    /// Same as `children`, to change them.
+    fn children_mut(&mut self) -> Vec<&mut Expr> {
+        match self {
+            Expr::Literal(_) | Expr::Parameter(_) | Expr::Column { .. } | Expr::Slot { .. } => {
+                Vec::new()
+            }
+            Expr::Unary { expr, .. } | Expr::IsNull { expr, .. } | Expr::Cast { expr, .. } => {
+                vec![expr]
+            }
+            Expr::Binary { left, right, .. } => vec![left, right],
+            Expr::Between {
+                expr, low, high, ..
+            } => vec![expr, low, high],
+            Expr::InList { expr, list, .. } => {
+                let mut children = vec![&mut **expr];
+                children.extend(list);
+                children
+            }
+            Expr::Like { expr, pattern, .. } => vec![expr, pattern],
+            Expr::Function { args, .. } => args.iter_mut().collect(),
+        }
+    }
+}
@@ -0,0 +1,2796 @@
+//! # SQL Executor
+//!
+//! Runs the statements of the parser on the catalog. Every statement works on rows laid out the same way:
+//! for each table of the FROM clause, its rowid followed by its columns, one table after the other. Column names
+//! are resolved once, before any row is read, into `Expr::Slot`s with their position in that layout, so evaluating
+//! an expression on a row is just looking values up.
+//!
+//! Reading the tables of a SELECT is a nested loop, one level per table. The conditions of the WHERE clause are
+//! split at the ANDs, and each one is checked at the first level where all the tables it uses have a row,
+//! so rows that cannot match are dropped as early as possible. The equalities also choose how a level reads its
+//! table (see `Access`): `rowid = x` reads one row, equalities on the first columns of an index seek them,
+//! and the rest scans the table. Every row is checked against the conditions anyway, the access path only has to
+//! find all the rows that can match, which keeps it simple.
+//!
+//! Aggregates work on groups of rows. They are taken out of the expressions before the rows are read,
+//! each one replaced by a slot after the end of the row, and once the groups are complete the row of a group
+//! is one of its rows followed by the results of the aggregates. Columns outside of an aggregate take their value
+//! from that row, as in SQLite.
+
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::sync::Arc;
+
+use super::ast::{
+    BinaryOp, ColumnDef, Expr, Ident, InsertSource, JoinKind, ResultColumn, Select, Statement,
+    TableConstraint, UnaryOp,
+};
+use super::key::{encode_key, encode_values};
+use super::{error_at, Rows};
+use crate::schema::{Column, IndexSchema, TableSchema};
+use crate::storage::pager::Pager;
+use crate::tree::btree::{BTree, TreeType};
+use crate::tree::record::Record;
+use crate::utils::cmp::KeyValue;
+use crate::utils::serialization::{
+    parse_numeric, real_to_text, serialize_values, Affinity, SqliteValue,
+};
+use crate::{Catalog, IndexId, RQLiteConfig, TableId};
+
+/// Names that stand for the rowid of a table when it has no column with that name.
+const ROWID_NAMES: [&str; 3] = ["rowid", "oid", "_rowid_"];
+
+/// Runs statements, with the SQL they come from (to point at errors) and the parameters of the call.
+pub(crate) struct Executor<'a> {
+    pub sql: &'a str,
+    pub params: &'a [SqliteValue],
+    pub pager: &'a Arc<Pager>,
+    pub config: &'a RQLiteConfig,
+}
+
+/// A table of the scope of an expression.
+struct ScopeTable {
+    /// The alias of the table, or its name.
+    name: String,
+    /// Names and affinities of the columns.
+    columns: Vec<(String, Affinity)>,
+    /// Slot of the rowid, the columns come right after it.
+    base: usize,
+    /// Position of the INTEGER PRIMARY KEY column, if there is one.
+    primary_key: Option<usize>,
+}
+
+impl ScopeTable {
+    fn new(name: &str, table_schema: &TableSchema, base: usize) -> Self {
+        ScopeTable {
+            name: name.to_string(),
+            columns: table_schema
+                .columns()
+                .iter()
+                .map(|column| (column.name.clone(), column.affinity()))
+                .collect(),
+            base,
+            primary_key: table_schema.primary_key(),
+        }
+    }
+
+    /// Number of slots of the table: the rowid and the columns.
+    fn width(&self) -> usize {
+        1 + self.columns.len()
+    }
+
+    /// Finds the slot of a column, or of the rowid.
+    fn slot(&self, name: &str) -> Option<Expr> {
+        match self
+            .columns
+            .iter()
+            .position(|(column, _)| column.eq_ignore_ascii_case(name))
+        {
+            // The INTEGER PRIMARY KEY is the rowid, which seeks better
+            Some(i) if Some(i) == self.primary_key => Some(self.rowid()),
+            Some(i) => Some(Expr::Slot {
+                index: self.base + 1 + i,
+                affinity: Some(self.columns[i].1),
+            }),
+            None if is_rowid_name(name) => Some(self.rowid()),
+            None => None,
+        }
+    }
+
+    fn rowid(&self) -> Expr {
+        Expr::Slot {
+            index: self.base,
+            affinity: Some(Affinity::Integer),
+        }
+    }
+}
+
+/// How a level of a nested loop reads its table.
+enum Access {
+    /// Every row, in rowid order.
+    Scan,
+    /// The row whose rowid is the value of the expression.
+    Rowid(Expr),
+    /// The rows whose first columns in the index have the values of the expressions.
+    Index(IndexId, Vec<usize>, Vec<Expr>),
+}
+
+/// A level of a nested loop: a table of the FROM clause.
+struct Level<'c> {
+    btree: &'c BTree,
+    table_schema: &'c TableSchema,
+    access: Access,
+    /// For a LEFT JOIN, the conditions of the ON clause. A row of the tables before this one that matches no row
+    /// of this table goes on with NULLs in its place.
+    left_on: Option<Vec<Expr>>,
+    /// Conditions checked once this level has a row.
+    filters: Vec<Expr>,
+}
+
+/// An index of a table, as the executor maintains it.
+struct TableIndex {
+    id: IndexId,
+    /// Positions of the columns of the index in the table.
+    columns: Vec<usize>,
+    unique: bool,
+}
+
+/// Aggregate functions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum AggregateKind {
+    Count,
+    Sum,
+    Total,
+    Avg,
+    Min,
+    Max,
+}
+
+/// An aggregate taken out of an expression. Its argument is `None` for `COUNT(*)`.
+struct Aggregate {
+    kind: AggregateKind,
+    arg: Option<Expr>,
+    distinct: bool,
+}
+
+/// Running state of an aggregate over a group.
+#[derive(Default)]
+struct Accumulator {
+    count: i64,
+    int_sum: i64,
+    float_sum: f64,
+    /// Whether a float was added to the sum, which makes it a float.
+    float: bool,
+    best: Option<SqliteValue>,
+    /// Encoded values already seen, for DISTINCT.
+    seen: HashSet<Vec<u8>>,
+}
+
+/// What an INSERT or an UPDATE writes a value to.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Target {
+    Column(usize),
+    Rowid,
+}
+
+impl Executor<'_> {
+    /// Runs a statement that is not a query nor a transaction statement.
+    ///
+    /// # Returns
+    /// The number of rows inserted, updated or deleted.
+    pub fn execute(&self, catalog: &mut Catalog, statement: &Statement) -> io::Result<usize> {
+        match statement {
+            Statement::CreateTable {
+                name,
+                if_not_exists,
+                columns,
+                constraints,
+            } => self.create_table(catalog, name, *if_not_exists, columns, constraints),
+            Statement::CreateIndex {
+                name,
+                table,
+                columns,
+                unique,
+                if_not_exists,
+            } => self.create_index(catalog, name, table, columns, *unique, *if_not_exists),
+            Statement::DropTable { name, if_exists } => self.drop_table(catalog, name, *if_exists),
+            Statement::DropIndex { name, if_exists } => self.drop_index(catalog, name, *if_exists),
+            Statement::Insert {
+                table,
+                columns,
+                source,
+            } => self.insert(catalog, table, columns.as_deref(), source),
+            Statement::Update {
+                table,
+                assignments,
+                filter,
+            } => self.update(catalog, table, assignments, filter.as_ref()),
+            Statement::Delete { table, filter } => self.delete(catalog, table, filter.as_ref()),
+            Statement::Select(select) => self.select(catalog, select).map(|_| 0),
+            Statement::Begin | Statement::Commit | Statement::Rollback => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Transaction statements are not run by the executor",
+            )),
+        }
+    }
+
+    /// Runs a SELECT.
+    pub fn select(&self, catalog: &Catalog, select: &Select) -> io::Result<Rows> {
+        // The scope: every table of the FROM clause, one after the other
+        let mut scope = Vec::new();
+        let mut tables = Vec::new();
+        for join in &select.from {
+            let (table_id, table_schema) = self.table(catalog, &join.table)?;
+            let name = join.alias.as_deref().unwrap_or(&join.table.name);
+            let base = scope
+                .last()
+                .map_or(0, |table: &ScopeTable| table.base + table.width());
+            scope.push(ScopeTable::new(name, table_schema, base));
+            tables.push((catalog.table(table_id)?, table_schema));
+        }
+        let width = scope.last().map_or(0, |table| table.base + table.width());
+
+        // The result columns, with their names
+        let mut columns = Vec::new();
+        let mut names = Vec::new();
+        for column in &select.columns {
+            match column {
+                ResultColumn::All | ResultColumn::TableAll(_) => {
+                    let chosen: Vec<&ScopeTable> = match column {
+                        ResultColumn::TableAll(table) => vec![scope
+                            .iter()
+                            .find(|scope_table| scope_table.name.eq_ignore_ascii_case(&table.name))
+                            .ok_or_else(|| {
+                                self.error(table.offset, format!("no such table: {}", table.name))
+                            })?],
+                        _ => scope.iter().collect(),
+                    };
+                    if chosen.is_empty() {
+                        return Err(self.error(0, "no tables specified"));
+                    }
+                    for scope_table in chosen {
+                        for (i, (name, affinity)) in scope_table.columns.iter().enumerate() {
+                            columns.push(Expr::Slot {
+                                index: scope_table.base + 1 + i,
+                                affinity: Some(*affinity),
+                            });
+                            names.push(name.clone());
+                        }
+                    }
+                }
+                ResultColumn::Expr { expr, alias, text } => {
+                    names.push(match (alias, expr) {
+                        (Some(alias), _) => alias.clone(),
+                        (None, Expr::Column { name, .. }) => name.name.clone(),
+                        (None, _) => text.clone(),
+                    });
+                    columns.push(self.resolve(expr, &scope)?);
+                }
+            }
+        }
+
+        // ORDER BY terms are a position in the result, an alias of a result column, or an expression
+        let mut order_by = Vec::new();
+        for term in &select.order_by {
+            let position = match &term.expr {
+                Expr::Literal(SqliteValue::Integer(position)) => {
+                    if *position < 1 || *position as usize > columns.len() {
+                        return Err(self.error(
+                            0,
+                            format!(
+                                "ORDER BY term out of range - should be between 1 and {}",
+                                columns.len()
+                            ),
+                        ));
+                    }
+                    Some(*position as usize - 1)
+                }
+                Expr::Column { table: None, name } => select.columns.iter().position(|column| {
+                    matches!(column, ResultColumn::Expr { alias: Some(alias), .. }
+                        if alias.eq_ignore_ascii_case(&name.name))
+                }),
+                _ => None,
+            };
+            let key = match position {
+                Some(position) => Err(position),
+                None => Ok(self.resolve(&term.expr, &scope)?),
+            };
+            order_by.push((key, term.descending));
+        }
+
+        let filter = self.resolve_plain(select.filter.as_ref(), &scope)?;
+        let group_by = select
+            .group_by
+            .iter()
+            .map(|expr| {
+                let expr = substitute_aliases(expr, select, &scope);
+                self.resolve_plain(Some(&expr), &scope)
+            })
+            .collect::<io::Result<Vec<_>>>()?
+            .into_iter()
+            .flatten()
+            .collect::<Vec<_>>();
+        let having = select
+            .having
+            .as_ref()
+            .map(|having| self.resolve(&substitute_aliases(having, select, &scope), &scope))
+            .transpose()?;
+
+        // Aggregates become slots after the end of the row
+        let mut aggregates = Vec::new();
+        let columns = columns
+            .into_iter()
+            .map(|expr| self.extract_aggregates(expr, width, &mut aggregates))
+            .collect::<io::Result<Vec<_>>>()?;
+        let having = having
+            .map(|having| self.extract_aggregates(having, width, &mut aggregates))
+            .transpose()?;
+        let order_by = order_by
+            .into_iter()
+            .map(|(key, descending)| match key {
+                Ok(expr) => Ok((
+                    Ok(self.extract_aggregates(expr, width, &mut aggregates)?),
+                    descending,
+                )),
+                Err(position) => Ok((Err(position), descending)),
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+        let grouped = !group_by.is_empty() || !aggregates.is_empty();
+
+        let rows = self.read_rows(catalog, &scope, &tables, &select.from, filter)?;
+        let rows = if grouped {
+            self.group(rows, &group_by, &aggregates, having.as_ref(), width)?
+        } else {
+            rows
+        };
+
+        // The result, with the sort keys of each row
+        let mut results = Vec::with_capacity(rows.len());
+        let mut seen = HashSet::new();
+        for row in &rows {
+            let values = columns
+                .iter()
+                .map(|expr| self.eval(expr, row))
+                .collect::<io::Result<Vec<_>>>()?;
+            if select.distinct && !seen.insert(encode_values(&values)) {
+                continue;
+            }
+            let keys = order_by
+                .iter()
+                .map(|(key, _)| match key {
+                    Ok(expr) => self.eval(expr, row),
+                    Err(position) => Ok(values[*position].clone()),
+                })
+                .collect::<io::Result<Vec<_>>>()?;
+            results.push((values, keys));
+        }
+        if !order_by.is_empty() {
+            results.sort_by(|(_, a), (_, b)| {
+                a.iter()
+                    .zip(b)
+                    .zip(&order_by)
+                    .map(|((a, b), (_, descending))| match descending {
+                        true => compare_values(b, a),
+                        false => compare_values(a, b),
+                    })
+                    .find(|ordering| ordering.is_ne())
+                    .unwrap_or(Ordering::Equal)
+            });
+        }
+
+        let offset = match &select.offset {
+            Some(offset) => self.eval_count(offset, "OFFSET")?.unwrap_or(0),
+            None => 0,
+        };
+        let limit = match &select.limit {
+            Some(limit) => self.eval_count(limit, "LIMIT")?,
+            None => None,
+        };
+        let records = results
+            .into_iter()
+            .skip(offset)
+            .take(limit.unwrap_or(usize::MAX))
+            .map(|(values, _)| Record::with_values(values))
+            .collect();
+        Ok(Rows::new(names, records))
+    }
+
+    /// Evaluates a LIMIT or an OFFSET. Negative values are `None`, no limit.
+    fn eval_count(&self, expr: &Expr, clause: &str) -> io::Result<Option<usize>> {
+        let expr = self.resolve(expr, &[])?;
+        match Affinity::Integer.apply(self.eval(&expr, &[])?) {
+            SqliteValue::Integer(count) => Ok(usize::try_from(count).ok()),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("datatype mismatch: {} must be an integer", clause),
+            )),
+        }
+    }
+
+    /// Reads the rows of the FROM clause that satisfy the WHERE clause, or a single empty row if there is no FROM.
+    fn read_rows(
+        &self,
+        catalog: &Catalog,
+        scope: &[ScopeTable],
+        tables: &[(&BTree, &TableSchema)],
+        joins: &[super::ast::Join],
+        filter: Option<Expr>,
+    ) -> io::Result<Vec<Vec<SqliteValue>>> {
+        let mut conditions = Vec::new();
+        if let Some(filter) = filter {
+            split_and(filter, &mut conditions);
+        }
+
+        let mut levels = Vec::with_capacity(tables.len());
+        for (i, ((btree, table_schema), join)) in tables.iter().zip(joins).enumerate() {
+            let on = join
+                .on
+                .as_ref()
+                .map(|on| self.resolve_plain(Some(on), &scope[..=i]))
+                .transpose()?
+                .flatten();
+            let mut left_on = None;
+            if let Some(on) = on {
+                if join.kind == JoinKind::Left {
+                    let mut on_conditions = Vec::new();
+                    split_and(on, &mut on_conditions);
+                    left_on = Some(on_conditions);
+                } else {
+                    split_and(on, &mut conditions);
+                }
+            } else if join.kind == JoinKind::Left {
+                left_on = Some(Vec::new());
+            }
+            levels.push(Level {
+                btree,
+                table_schema,
+                access: Access::Scan,
+                left_on,
+                filters: Vec::new(),
+            });
+        }
+
+        // Each condition goes to the first level where all its tables have a row
+        let mut constant = Vec::new();
+        for condition in conditions {
+            let last_slot = max_slot(&condition);
+            match last_slot.and_then(|slot| {
+                scope
+                    .iter()
+                    .position(|table| slot < table.base + table.width())
+            }) {
+                Some(level) => levels[level].filters.push(condition),
+                None if levels.is_empty() => constant.push(condition),
+                None => levels[0].filters.push(condition),
+            }
+        }
+        for i in 0..levels.len() {
+            let table_id = catalog.find_table(&joins[i].table.name);
+            let candidates: Vec<&Expr> = levels[i]
+                .filters
+                .iter()
+                .chain(levels[i].left_on.iter().flatten())
+                .collect();
+            levels[i].access = self.plan(catalog, table_id, &scope[i], &candidates);
+        }
+
+        let mut rows = Vec::new();
+        if levels.is_empty() {
+            if self.check(&constant, &[])? {
+                rows.push(Vec::new());
+            }
+            return Ok(rows);
+        }
+        let mut row = Vec::new();
+        self.nested_loop(catalog, &levels, &mut row, &mut rows)?;
+        Ok(rows)
+    }
+
+    /// Chooses how to read a table from the equalities on its columns that can be evaluated before reading it.
+    fn plan(
+        &self,
+        catalog: &Catalog,
+        table_id: Option<TableId>,
+        table: &ScopeTable,
+        conditions: &[&Expr],
+    ) -> Access {
+        // Equalities between a column of the table and something known before reading it
+        let mut known: Vec<(usize, Affinity, Expr)> = Vec::new();
+        for condition in conditions {
+            let Expr::Binary {
+                op: BinaryOp::Eq,
+                left,
+                right,
+            } = condition
+            else {
+                continue;
+            };
+            for (column, value) in [(left, right), (right, left)] {
+                let Expr::Slot {
+                    index,
+                    affinity: Some(affinity),
+                } = **column
+                else {
+                    continue;
+                };
+                let in_table = (table.base..table.base + table.width()).contains(&index);
+                let before = max_slot(value).is_none_or(|slot| slot < table.base);
+                if in_table && before && seekable(affinity, expr_affinity(value)) {
+                    known.push((index - table.base, affinity, (**value).clone()));
+                }
+            }
+        }
+
+        if let Some((_, _, value)) = known.iter().find(|(slot, _, _)| *slot == 0) {
+            return Access::Rowid(value.clone());
+        }
+        let Some(table_id) = table_id else {
+            return Access::Scan;
+        };
+
+        // The index with the most columns known, from the first one
+        let mut best: Option<(IndexId, Vec<usize>, Vec<Expr>)> = None;
+        let mut index_ids: Vec<&IndexId> = catalog.index_schemas.keys().collect();
+        index_ids.sort();
+        for index_id in index_ids {
+            let index_schema = &catalog.index_schemas[index_id];
+            if index_schema.table_id != table_id {
+                continue;
+            }
+            let mut columns = Vec::new();
+            let mut values = Vec::new();
+            for name in &index_schema.columns {
+                let position = table
+                    .columns
+                    .iter()
+                    .position(|(column, _)| column.eq_ignore_ascii_case(name));
+                let Some(value) = position
+                    .and_then(|position| known.iter().find(|(slot, _, _)| *slot == position + 1))
+                else {
+                    break;
+                };
+                columns.push(value.0 - 1);
+                values.push(value.2.clone());
+            }
+            if !values.is_empty()
+                && best
+                    .as_ref()
+                    .is_none_or(|(_, _, best)| values.len() > best.len())
+            {
+                best = Some((*index_id, columns, values));
+            }
+        }
+        match best {
+            Some((index_id, columns, values)) => Access::Index(index_id, columns, values),
+            None => Access::Scan,
+        }
+    }
+
+    /// Runs a level of the nested loop for the row the levels before it built.
+    fn nested_loop(
+        &self,
+        catalog: &Catalog,
+        levels: &[Level],
+        row: &mut Vec<SqliteValue>,
+        rows: &mut Vec<Vec<SqliteValue>>,
+    ) -> io::Result<()> {
+        let Some((level, rest)) = levels.split_first() else {
+            rows.push(row.clone());
+            return Ok(());
+        };
+        let base = row.len();
+
+        let mut matched = false;
+        for values in self.table_rows(catalog, level, row)? {
+            row.truncate(base);
+            row.extend(values);
+            if let Some(on) = &level.left_on {
+                if !self.check(on, row)? {
+                    continue;
+                }
+                matched = true;
+            }
+            if self.check(&level.filters, row)? {
+                self.nested_loop(catalog, rest, row, rows)?;
+            }
+        }
+        if level.left_on.is_some() && !matched {
+            row.truncate(base);
+            row.resize(
+                base + 1 + level.table_schema.columns().len(),
+                SqliteValue::Null,
+            );
+            if self.check(&level.filters, row)? {
+                self.nested_loop(catalog, rest, row, rows)?;
+            }
+        }
+        row.truncate(base);
+        Ok(())
+    }
+
+    /// Reads the rows of the table of a level that its access path finds: the rowid, then the columns.
+    fn table_rows(
+        &self,
+        catalog: &Catalog,
+        level: &Level,
+        row: &[SqliteValue],
+    ) -> io::Result<Vec<Vec<SqliteValue>>> {
+        let table_schema = level.table_schema;
+        let with_rowid = |rowid: i64, record: Record| {
+            let mut values = vec![SqliteValue::Integer(rowid)];
+            values.extend(table_schema.complete(rowid, record).values);
+            values
+        };
+
+        let rowids = match &level.access {
+            Access::Scan => {
+                return level
+                    .btree
+                    .scan()?
+                    .map(|entry| entry.map(|(rowid, record)| with_rowid(rowid, record)))
+                    .collect();
+            }
+            Access::Rowid(expr) => match Affinity::Integer.apply(self.eval(expr, row)?) {
+                SqliteValue::Integer(rowid) => vec![rowid],
+                _ => Vec::new(),
+            },
+            Access::Index(index_id, columns, exprs) => {
+                let mut values = Vec::with_capacity(exprs.len());
+                for (column, expr) in columns.iter().zip(exprs) {
+                    let affinity = table_schema.columns()[*column].affinity();
+                    match affinity.apply(self.eval(expr, row)?) {
+                        // Nothing is equal to NULL
+                        SqliteValue::Null => return Ok(Vec::new()),
+                        value => values.push(value),
+                    }
+                }
+                seek(catalog.index(*index_id)?, &encode_values(&values), None)?
+            }
+        };
+
+        let mut rows = Vec::with_capacity(rowids.len());
+        for rowid in rowids {
+            if let Some(record) = level.btree.find(rowid)? {
+                rows.push(with_rowid(rowid, record));
+            }
+        }
+        Ok(rows)
+    }
+
+    /// Puts rows in groups and computes the aggregates of each group.
+    /// Every group becomes one of its rows followed by the results of the aggregates.
+    fn group(
+        &self,
+        rows: Vec<Vec<SqliteValue>>,
+        group_by: &[Expr],
+        aggregates: &[Aggregate],
+        having: Option<&Expr>,
+        width: usize,
+    ) -> io::Result<Vec<Vec<SqliteValue>>> {
+        let mut positions: HashMap<Vec<u8>, usize> = HashMap::new();
+        let mut groups: Vec<(Vec<SqliteValue>, Vec<Accumulator>)> = Vec::new();
+        for row in rows {
+            let key = group_by
+                .iter()
+                .map(|expr| self.eval(expr, &row))
+                .collect::<io::Result<Vec<_>>>()?;
+            let position = *positions.entry(encode_values(&key)).or_insert_with(|| {
+                groups.push((
+                    row.clone(),
+                    aggregates.iter().map(|_| Accumulator::default()).collect(),
+                ));
+                groups.len() - 1
+            });
+            for (aggregate, accumulator) in aggregates.iter().zip(&mut groups[position].1) {
+                let value = match &aggregate.arg {
+                    Some(arg) => Some(self.eval(arg, &row)?),
+                    None => None,
+                };
+                accumulator.add(aggregate, value)?;
+            }
+        }
+        // Aggregates without GROUP BY make one row, even out of no rows
+        if groups.is_empty() && group_by.is_empty() {
+            groups.push((
+                vec![SqliteValue::Null; width],
+                aggregates.iter().map(|_| Accumulator::default()).collect(),
+            ));
+        }
+
+        let mut result = Vec::with_capacity(groups.len());
+        for (mut row, accumulators) in groups {
+            row.extend(
+                aggregates
+                    .iter()
+                    .zip(accumulators)
+                    .map(|(aggregate, accumulator)| accumulator.finish(aggregate.kind)),
+            );
+            if let Some(having) = having {
+                if truth(&self.eval(having, &row)?) != Some(true) {
+                    continue;
+                }
+            }
+            result.push(row);
+        }
+        Ok(result)
+    }
+
+    /// Replaces the aggregates of a resolved expression with slots after the end of the row.
+    fn extract_aggregates(
+        &self,
+        expr: Expr,
+        width: usize,
+        aggregates: &mut Vec<Aggregate>,
+    ) -> io::Result<Expr> {
+        expr.transform(&mut |expr| {
+            let Expr::Function {
+                name,
+                args,
+                distinct,
+                star,
+            } = &expr
+            else {
+                return Ok(expr);
+            };
+            let Some(kind) = aggregate_kind(&name.name, args.len(), *star) else {
+                return Ok(expr);
+            };
+            if args
+                .iter()
+                .any(|arg| max_slot(arg).is_some_and(|slot| slot >= width))
+            {
+                return Err(self.error(
+                    name.offset,
+                    format!("misuse of aggregate function {}()", name.name),
+                ));
+            }
+            aggregates.push(Aggregate {
+                kind,
+                arg: args.first().cloned(),
+                distinct: *distinct,
+            });
+            Ok(Expr::Slot {
+                index: width + aggregates.len() - 1,
+                affinity: None,
+            })
+        })
+    }
+
+    /// Resolves an expression where aggregates are not allowed, like a WHERE clause.
+    fn resolve_plain(&self, expr: Option<&Expr>, scope: &[ScopeTable]) -> io::Result<Option<Expr>> {
+        let Some(expr) = expr else {
+            return Ok(None);
+        };
+        let mut aggregate = None;
+        expr.visit(&mut |expr| {
+            if let Expr::Function {
+                name, args, star, ..
+            } = expr
+            {
+                if aggregate.is_none() && aggregate_kind(&name.name, args.len(), *star).is_some() {
+                    aggregate = Some(name.clone());
+                }
+            }
+        });
+        if let Some(name) = aggregate {
+            return Err(self.error(
+                name.offset,
+                format!("misuse of aggregate function {}()", name.name),
+            ));
+        }
+        self.resolve(expr, scope).map(Some)
+    }
+
+    /// Replaces the columns of an expression with their slots, and checks the functions it calls.
+    fn resolve(&self, expr: &Expr, scope: &[ScopeTable]) -> io::Result<Expr> {
+        expr.clone().transform(&mut |expr| match expr {
+            Expr::Column { table, name } => self.resolve_column(table.as_ref(), &name, scope),
+            Expr::Function {
+                name,
+                args,
+                distinct,
+                star,
+            } => {
+                let known = match aggregate_kind(&name.name, args.len(), star) {
+                    Some(_) => true,
+                    None => !star && !distinct && scalar_arity(&name.name, args.len()),
+                };
+                if !known {
+                    let message = if is_function(&name.name) {
+                        format!("wrong number of arguments to function {}()", name.name)
+                    } else {
+                        format!("no such function: {}", name.name)
+                    };
+                    return Err(self.error(name.offset, message));
+                }
+                Ok(Expr::Function {
+                    name,
+                    args,
+                    distinct,
+                    star,
+                })
+            }
+            expr => Ok(expr),
+        })
+    }
+
+    /// Finds the slot of a column in the scope.
+    fn resolve_column(
+        &self,
+        table: Option<&Ident>,
+        name: &Ident,
+        scope: &[ScopeTable],
+    ) -> io::Result<Expr> {
+        let full_name = match table {
+            Some(table) => format!("{}.{}", table.name, name.name),
+            None => name.name.clone(),
+        };
+        let no_such_column = || self.error(name.offset, format!("no such column: {}", full_name));
+
+        if let Some(table) = table {
+            let scope_table = scope
+                .iter()
+                .find(|scope_table| scope_table.name.eq_ignore_ascii_case(&table.name))
+                .ok_or_else(|| {
+                    self.error(table.offset, format!("no such column: {}", full_name))
+                })?;
+            return scope_table.slot(&name.name).ok_or_else(no_such_column);
+        }
+
+        // A real column wins over a rowid name, and must be in only one table
+        let mut found = scope
+            .iter()
+            .filter(|scope_table| {
+                scope_table
+                    .columns
+                    .iter()
+                    .any(|(column, _)| column.eq_ignore_ascii_case(&name.name))
+            })
+            .collect::<Vec<_>>();
+        if found.is_empty() && is_rowid_name(&name.name) {
+            found = scope.iter().collect();
+        }
+        match found[..] {
+            [scope_table] => scope_table.slot(&name.name).ok_or_else(no_such_column),
+            [] => Err(no_such_column()),
+            _ => Err(self.error(name.offset, format!("ambiguous column name: {}", name.name))),
+        }
+    }
+
+    /// Checks conditions on a row: they must all be true, NULL is not.
+    fn check(&self, conditions: &[Expr], row: &[SqliteValue]) -> io::Result<bool> {
+        for condition in conditions {
+            if truth(&self.eval(condition, row)?) != Some(true) {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    /// Evaluates a resolved expression on a row.
+    ///
+    /// The nodes with operands are evaluated by methods of their own, called for each level of the tree, so the
+    /// frame of this one stays small: an expression can be `MAX_EXPR_DEPTH` deep.
+    fn eval(&self, expr: &Expr, row: &[SqliteValue]) -> io::Result<SqliteValue> {
+        match expr {
+            Expr::Literal(value) => Ok(value.clone()),
+            Expr::Parameter(index) => Ok(self
+                .params
+                .get(*index)
+                .cloned()
+                .unwrap_or(SqliteValue::Null)),
+            Expr::Slot { index, .. } => Ok(row.get(*index).cloned().unwrap_or(SqliteValue::Null)),
+            Expr::Column { name, .. } => {
+                Err(self.error(name.offset, format!("no such column: {}", name.name)))
+            }
+            Expr::Unary { op, expr } => self.eval_unary(*op, expr, row),
+            Expr::Binary { op, left, right } => match op {
+                BinaryOp::And | BinaryOp::Or => self.eval_logical(*op, left, right, row),
+                op if op.is_comparison() => self.eval_comparison(*op, left, right, row),
+                op => self.eval_arithmetic(*op, left, right, row),
+            },
+            Expr::IsNull { expr, negated } => self.eval_is_null(expr, *negated, row),
+            Expr::Between {
+                expr,
+                low,
+                high,
+                negated,
+            } => self.eval_between(expr, low, high, *negated, row),
+            Expr::InList {
+                expr,
+                list,
+                negated,
+            } => self.eval_in_list(expr, list, *negated, row),
+            Expr::Like {
+                expr,
+                pattern,
+                negated,
+            } => self.eval_like(expr, pattern, *negated, row),
+            Expr::Cast {
+                expr,
+                declared_type,
+            } => self.eval_cast(expr, declared_type, row),
+            Expr::Function { name, args, .. } => self.eval_function(name, args, row),
+        }
+    }
+
+    /// Evaluates a unary operator.
+    fn eval_unary(&self, op: UnaryOp, expr: &Expr, row: &[SqliteValue]) -> io::Result<SqliteValue> {
+        let value = self.eval(expr, row)?;
+        Ok(match op {
+            UnaryOp::Plus => value,
+            UnaryOp::Not => match truth(&value) {
+                Some(value) => SqliteValue::Integer(!value as i64),
+                None => SqliteValue::Null,
+            },
+            UnaryOp::Negate => match to_number(&value) {
+                Some(SqliteValue::Integer(i)) => match i.checked_neg() {
+                    Some(i) => SqliteValue::Integer(i),
+                    None => SqliteValue::Float(-(i as f64)),
+                },
+                Some(SqliteValue::Float(f)) => SqliteValue::Float(-f),
+                _ => SqliteValue::Null,
+            },
+        })
+    }
+
+    /// Evaluates AND and OR, which skip their right side when the left one decides.
+    fn eval_logical(
+        &self,
+        op: BinaryOp,
+        left: &Expr,
+        right: &Expr,
+        row: &[SqliteValue],
+    ) -> io::Result<SqliteValue> {
+        let left = truth(&self.eval(left, row)?);
+        Ok(match op {
+            BinaryOp::And => {
+                if left == Some(false) {
+                    return Ok(SqliteValue::Integer(0));
+                }
+                match (left, truth(&self.eval(right, row)?)) {
+                    (_, Some(false)) => SqliteValue::Integer(0),
+                    (Some(true), Some(true)) => SqliteValue::Integer(1),
+                    _ => SqliteValue::Null,
+                }
+            }
+            _ => {
+                if left == Some(true) {
+                    return Ok(SqliteValue::Integer(1));
+                }
+                match (left, truth(&self.eval(right, row)?)) {
+                    (_, Some(true)) => SqliteValue::Integer(1),
+                    (Some(false), Some(false)) => SqliteValue::Integer(0),
+                    _ => SqliteValue::Null,
+                }
+            }
+        })
+    }
+
+    /// Evaluates a comparison, with the affinities of its operands.
+    fn eval_comparison(
+        &self,
+        op: BinaryOp,
+        left: &Expr,
+        right: &Expr,
+        row: &[SqliteValue],
+    ) -> io::Result<SqliteValue> {
+        let (a, b) = self.comparison_operands(left, right, row)?;
+        Ok(compare(op, &a, &b))
+    }
+
+    /// Evaluates an arithmetic operator or a concatenation.
+    fn eval_arithmetic(
+        &self,
+        op: BinaryOp,
+        left: &Expr,
+        right: &Expr,
+        row: &[SqliteValue],
+    ) -> io::Result<SqliteValue> {
+        let left = self.eval(left, row)?;
+        let right = self.eval(right, row)?;
+        Ok(arithmetic(op, &left, &right))
+    }
+
+    /// Evaluates `IS NULL` and `IS NOT NULL`.
+    fn eval_is_null(
+        &self,
+        expr: &Expr,
+        negated: bool,
+        row: &[SqliteValue],
+    ) -> io::Result<SqliteValue> {
+        let is_null = self.eval(expr, row)? == SqliteValue::Null;
+        Ok(SqliteValue::Integer((is_null != negated) as i64))
+    }
+
+    /// Evaluates a BETWEEN: the value is compared with each bound like with `>=` and `<=`.
+    fn eval_between(
+        &self,
+        expr: &Expr,
+        low: &Expr,
+        high: &Expr,
+        negated: bool,
+        row: &[SqliteValue],
+    ) -> io::Result<SqliteValue> {
+        let (value, low) = self.comparison_operands(expr, low, row)?;
+        let (_, high) = self.comparison_operands(expr, high, row)?;
+        let above = compare(BinaryOp::GtEq, &value, &low);
+        let below = compare(BinaryOp::LtEq, &value, &high);
+        let between = match (truth(&above), truth(&below)) {
+            (Some(false), _) | (_, Some(false)) => Some(false),
+            (Some(true), Some(true)) => Some(true),
+            _ => None,
+        };
+        Ok(negate(between, negated))
+    }
+
+    /// Evaluates an IN list: true if an item is equal, NULL if none is but one is NULL.
+    fn eval_in_list(
+        &self,
+        expr: &Expr,
+        list: &[Expr],
+        negated: bool,
+        row: &[SqliteValue],
+    ) -> io::Result<SqliteValue> {
+        let mut found = Some(false);
+        for item in list {
+            let (value, item) = self.comparison_operands(expr, item, row)?;
+            match truth(&compare(BinaryOp::Eq, &value, &item)) {
+                Some(true) => {
+                    found = Some(true);
+                    break;
+                }
+                None => found = None,
+                Some(false) => {}
+            }
+        }
+        Ok(negate(found, negated))
+    }
+
+    /// Evaluates a LIKE.
+    fn eval_like(
+        &self,
+        expr: &Expr,
+        pattern: &Expr,
+        negated: bool,
+        row: &[SqliteValue],
+    ) -> io::Result<SqliteValue> {
+        let value = self.eval(expr, row)?;
+        let pattern = self.eval(pattern, row)?;
+        if value == SqliteValue::Null || pattern == SqliteValue::Null {
+            return Ok(SqliteValue::Null);
+        }
+        let matched = like(&to_text(&pattern), &to_text(&value));
+        Ok(SqliteValue::Integer((matched != negated) as i64))
+    }
+
+    /// Evaluates a CAST.
+    fn eval_cast(
+        &self,
+        expr: &Expr,
+        declared_type: &str,
+        row: &[SqliteValue],
+    ) -> io::Result<SqliteValue> {
+        Ok(cast(self.eval(expr, row)?, declared_type))
+    }
+
+    /// Calls a scalar function. Aggregates have been replaced with slots before, so any left is misused.
+    fn eval_function(
+        &self,
+        name: &Ident,
+        args: &[Expr],
+        row: &[SqliteValue],
+    ) -> io::Result<SqliteValue> {
+        let args = args
+            .iter()
+            .map(|arg| self.eval(arg, row))
+            .collect::<io::Result<Vec<_>>>()?;
+        call(&name.name, args).ok_or_else(|| {
+            self.error(
+                name.offset,
+                format!("misuse of aggregate function {}()", name.name),
+            )
+        })
+    }
+
+    /// Evaluates the operands of a comparison, applying the affinities of SQLite:
+    /// a column with a numeric affinity makes the other side numeric, a TEXT column makes it text
+    /// if it has no affinity of its own.
+    fn comparison_operands(
+        &self,
+        left: &Expr,
+        right: &Expr,
+        row: &[SqliteValue],
+    ) -> io::Result<(SqliteValue, SqliteValue)> {
+        let mut a = self.eval(left, row)?;
+        let mut b = self.eval(right, row)?;
+        let numeric = |affinity: Option<Affinity>| {
+            matches!(
+                affinity,
+                Some(Affinity::Integer | Affinity::Real | Affinity::Numeric)
+            )
+        };
+        let none = |affinity: Option<Affinity>| matches!(affinity, None | Some(Affinity::Blob));
+        let (left, right) = (expr_affinity(left), expr_affinity(right));
+        if numeric(left) && !numeric(right) {
+            b = Affinity::Numeric.apply(b);
+        } else if numeric(right) && !numeric(left) {
+            a = Affinity::Numeric.apply(a);
+        } else if left == Some(Affinity::Text) && none(right) {
+            b = Affinity::Text.apply(b);
+        } else if right == Some(Affinity::Text) && none(left) {
+            a = Affinity::Text.apply(a);
+        }
+        Ok((a, b))
+    }
+
+    /// Gets a table by name for SQL, which needs its columns.
+    fn table<'c>(
+        &self,
+        catalog: &'c Catalog,
+        name: &Ident,
+    ) -> io::Result<(TableId, &'c TableSchema)> {
+        let table_id = catalog
+            .find_table(&name.name)
+            .ok_or_else(|| self.error(name.offset, format!("no such table: {}", name.name)))?;
+        let table_schema = catalog.table_schemas.get(&table_id).ok_or_else(|| {
+            self.error(name.offset, format!("table {} has no columns", name.name))
+        })?;
+        Ok((table_id, table_schema))
+    }
+
+    /// Runs a CREATE TABLE.
+    fn create_table(
+        &self,
+        catalog: &mut Catalog,
+        name: &Ident,
+        if_not_exists: bool,
+        definitions: &[ColumnDef],
+        constraints: &[TableConstraint],
+    ) -> io::Result<usize> {
+        if catalog.find_table(&name.name).is_some() || catalog.find_index(&name.name).is_some() {
+            if if_not_exists {
+                return Ok(0);
+            }
+            return Err(self.error(name.offset, format!("table {} already exists", name.name)));
+        }
+
+        let mut columns: Vec<Column> = Vec::with_capacity(definitions.len());
+        let mut primary_key = Vec::new();
+        let mut unique = Vec::new();
+        for definition in definitions {
+            if columns
+                .iter()
+                .any(|column| column.name.eq_ignore_ascii_case(&definition.name.name))
+            {
+                return Err(self.error(
+                    definition.name.offset,
+                    format!("duplicate column name: {}", definition.name.name),
+                ));
+            }
+            let mut column = Column::new(&definition.name.name, &definition.declared_type);
+            column.not_null = definition.not_null;
+            if let Some(default) = &definition.default {
+                column.default = Some(self.eval(&self.resolve(default, &[])?, &[])?);
+            }
+            if definition.primary_key {
+                primary_key.push(vec![definition.name.clone()]);
+            }
+            if definition.unique {
+                unique.push(vec![definition.name.clone()]);
+            }
+            columns.push(column);
+        }
+        for constraint in constraints {
+            match constraint {
+                TableConstraint::PrimaryKey(names) => primary_key.push(names.clone()),
+                TableConstraint::Unique(names) => unique.push(names.clone()),
+            }
+        }
+        for names in primary_key.iter().chain(&unique) {
+            for column in names {
+                if !columns
+                    .iter()
+                    .any(|c| c.name.eq_ignore_ascii_case(&column.name))
+                {
+                    return Err(
+                        self.error(column.offset, format!("no such column: {}", column.name))
+                    );
+                }
+            }
+        }
+        if primary_key.len() > 1 {
+            return Err(self.error(
+                name.offset,
+                format!("table {} has more than one primary key", name.name),
+            ));
+        }
+
+        // A single INTEGER PRIMARY KEY is the rowid, any other primary key is a unique index
+        let mut autoindexes = unique;
+        if let Some(names) = primary_key.pop() {
+            let rowid_alias = match &names[..] {
+                [column] => columns
+                    .iter_mut()
+                    .find(|c| c.name.eq_ignore_ascii_case(&column.name))
+                    .filter(|c| c.declared_type.eq_ignore_ascii_case("INTEGER")),
+                _ => None,
+            };
+            match rowid_alias {
+                Some(column) => column.primary_key = true,
+                None => autoindexes.insert(0, names),
+            }
+        }
+        let table_schema = TableSchema::new(columns)?;
+
+        let btree = Catalog::new_tree(self.pager, self.config, TreeType::Table)?;
+        let table_id = catalog.next_table_id;
+        catalog.next_table_id += 1;
+        catalog.tables.insert(table_id, btree);
+        catalog.table_schemas.insert(table_id, table_schema);
+        catalog.table_names.insert(table_id, name.name.clone());
+
+        for (i, names) in autoindexes.into_iter().enumerate() {
+            let index_schema = IndexSchema {
+                name: format!("sqlite_autoindex_{}_{}", name.name, i + 1),
+                table_id,
+                columns: names.into_iter().map(|column| column.name).collect(),
+                unique: true,
+            };
+            self.add_index(catalog, index_schema)?;
+        }
+        Ok(0)
+    }
+
+    /// Runs a CREATE INDEX.
+    fn create_index(
+        &self,
+        catalog: &mut Catalog,
+        name: &Ident,
+        table: &Ident,
+        columns: &[Ident],
+        unique: bool,
+        if_not_exists: bool,
+    ) -> io::Result<usize> {
+        if catalog.find_index(&name.name).is_some() || catalog.find_table(&name.name).is_some() {
+            if if_not_exists {
+                return Ok(0);
+            }
+            return Err(self.error(name.offset, format!("index {} already exists", name.name)));
+        }
+        let (table_id, table_schema) = self.table(catalog, table)?;
+        for column in columns {
+            if table_schema.column_index(&column.name).is_none() {
+                return Err(self.error(column.offset, format!("no such column: {}", column.name)));
+            }
+        }
+
+        let index_schema = IndexSchema {
+            name: name.name.clone(),
+            table_id,
+            columns: columns.iter().map(|column| column.name.clone()).collect(),
+            unique,
+        };
+        self.add_index(catalog, index_schema)?;
+        Ok(0)
+    }
+
+    /// Creates an index and fills it with the rows of its table.
+    fn add_index(&self, catalog: &mut Catalog, index_schema: IndexSchema) -> io::Result<()> {
+        let table_id = index_schema.table_id;
+        let table_schema = &catalog.table_schemas[&table_id];
+        let index = TableIndex {
+            id: catalog.next_index_id,
+            columns: index_schema
+                .columns
+                .iter()
+                .filter_map(|column| table_schema.column_index(column))
+                .collect(),
+            unique: index_schema.unique,
+        };
+
+        let mut btree = Catalog::new_tree(self.pager, self.config, TreeType::Index)?;
+        fill_index(catalog, table_id, &index, &mut btree)?;
+
+        catalog.next_index_id += 1;
+        catalog.indexes.insert(index.id, btree);
+        catalog.index_schemas.insert(index.id, index_schema);
+        Ok(())
+    }
+
+    /// Runs a DROP TABLE, which drops the indexes of the table too.
+    fn drop_table(
+        &self,
+        catalog: &mut Catalog,
+        name: &Ident,
+        if_exists: bool,
+    ) -> io::Result<usize> {
+        let Some(table_id) = catalog.find_table(&name.name) else {
+            if if_exists {
+                return Ok(0);
+            }
+            return Err(self.error(name.offset, format!("no such table: {}", name.name)));
+        };
+
+        let index_ids: Vec<IndexId> = catalog
+            .index_schemas
+            .iter()
+            .filter(|(_, index_schema)| index_schema.table_id == table_id)
+            .map(|(index_id, _)| *index_id)
+            .collect();
+        for index_id in index_ids {
+            catalog.index(index_id)?.free_tree()?;
+            catalog.indexes.remove(&index_id);
+            catalog.index_schemas.remove(&index_id);
+        }
+        catalog.table(table_id)?.free_tree()?;
+        catalog.tables.remove(&table_id);
+        catalog.table_schemas.remove(&table_id);
+        catalog.table_names.remove(&table_id);
+        Ok(0)
+    }
+
+    /// Runs a DROP INDEX.
+    fn drop_index(
+        &self,
+        catalog: &mut Catalog,
+        name: &Ident,
+        if_exists: bool,
+    ) -> io::Result<usize> {
+        let Some(index_id) = catalog.find_index(&name.name) else {
+            if if_exists {
+                return Ok(0);
+            }
+            return Err(self.error(name.offset, format!("no such index: {}", name.name)));
+        };
+        if catalog.index_schemas[&index_id]
+            .name
+            .starts_with("sqlite_autoindex_")
+        {
+            return Err(self.error(
+                name.offset,
+                "index associated with UNIQUE or PRIMARY KEY constraint cannot be dropped",
+            ));
+        }
+        catalog.index(index_id)?.free_tree()?;
+        catalog.indexes.remove(&index_id);
+        catalog.index_schemas.remove(&index_id);
+        Ok(0)
+    }
+
+    /// Runs an INSERT.
+    fn insert(
+        &self,
+        catalog: &mut Catalog,
+        table: &Ident,
+        columns: Option<&[Ident]>,
+        source: &InsertSource,
+    ) -> io::Result<usize> {
+        let (table_id, table_schema) = self.table(catalog, table)?;
+        let table_schema = table_schema.clone();
+        let targets = match columns {
+            Some(columns) => columns
+                .iter()
+                .map(|column| self.target(&table_schema, table, column))
+                .collect::<io::Result<Vec<_>>>()?,
+            None => (0..table_schema.columns().len())
+                .map(Target::Column)
+                .collect(),
+        };
+
+        let rows = match source {
+            InsertSource::Values(rows) => {
+                let mut values = Vec::with_capacity(rows.len());
+                for row in rows {
+                    values.push(
+                        row.iter()
+                            .map(|expr| {
+                                self.eval(&self.resolve_plain(Some(expr), &[])?.unwrap(), &[])
+                            })
+                            .collect::<io::Result<Vec<_>>>()?,
+                    );
+                }
+                values
+            }
+            InsertSource::Select(select) => self
+                .select(catalog, select)?
+                .into_iter()
+                .map(|record| record.values)
+                .collect(),
+            InsertSource::DefaultValues => vec![Vec::new()],
+        };
+        if let Some(row) = rows.iter().find(|row| row.len() != targets.len()) {
+            return Err(self.error(
+                table.offset,
+                format!(
+                    "table {} has {} columns but {} values were supplied",
+                    table.name,
+                    targets.len(),
+                    row.len()
+                ),
+            ));
+        }
+
+        for row in &rows {
+            let mut values: Vec<SqliteValue> = table_schema
+                .columns()
+                .iter()
+                .map(|column| column.default.clone().unwrap_or(SqliteValue::Null))
+                .collect();
+            let mut rowid = None;
+            for (target, value) in targets.iter().zip(row) {
+                match target {
+                    Target::Column(i) if Some(*i) == table_schema.primary_key() => {
+                        rowid = Some(value.clone())
+                    }
+                    Target::Column(i) => values[*i] = value.clone(),
+                    Target::Rowid => rowid = Some(value.clone()),
+                }
+            }
+            let rowid = match rowid {
+                Some(SqliteValue::Null) | None => next_rowid(catalog.table(table_id)?)?,
+                Some(value) => rowid_value(value)?,
+            };
+
+            let record = table_schema.apply(&Record::with_values(values))?;
+            if catalog.table(table_id)?.find(rowid)?.is_some() {
+                return Err(self.rowid_failed(catalog, table_id, &table_schema));
+            }
+            insert_row(catalog, table_id, rowid, &record)?;
+        }
+        Ok(rows.len())
+    }
+
+    /// Runs an UPDATE.
+    fn update(
+        &self,
+        catalog: &mut Catalog,
+        table: &Ident,
+        assignments: &[(Ident, Expr)],
+        filter: Option<&Expr>,
+    ) -> io::Result<usize> {
+        let (table_id, table_schema) = self.table(catalog, table)?;
+        let table_schema = table_schema.clone();
+        let scope = [ScopeTable::new(&table.name, &table_schema, 0)];
+        let assignments = assignments
+            .iter()
+            .map(|(column, expr)| {
+                let target = self.target(&table_schema, table, column)?;
+                let expr = self.resolve_plain(Some(expr), &scope)?.unwrap();
+                Ok((target, expr))
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+        let rows = self.matching_rows(catalog, table, &scope, filter)?;
+
+        let indexes = table_indexes(catalog, table_id, &table_schema);
+        for row in &rows {
+            let SqliteValue::Integer(old_rowid) = row[0] else {
+                unreachable!("The first slot is the rowid");
+            };
+            let mut values = row[1..].to_vec();
+            let mut rowid = old_rowid;
+            for (target, expr) in &assignments {
+                let value = self.eval(expr, row)?;
+                match target {
+                    Target::Column(i) if Some(*i) == table_schema.primary_key() => {
+                        rowid = rowid_value(value)?
+                    }
+                    Target::Column(i) => values[*i] = value,
+                    Target::Rowid => rowid = rowid_value(value)?,
+                }
+            }
+
+            let record = table_schema.apply(&Record::with_values(values))?;
+            if rowid != old_rowid && catalog.table(table_id)?.find(rowid)?.is_some() {
+                return Err(self.rowid_failed(catalog, table_id, &table_schema));
+            }
+            let full = table_schema.complete(rowid, record.clone());
+            for index in &indexes {
+                let old_values = index_values(index, &row[1..]);
+                let new_values = index_values(index, &full.values);
+                if old_values == new_values && rowid == old_rowid {
+                    continue;
+                }
+                delete_entry(catalog.index_mut(index.id)?, &old_values, old_rowid)?;
+                if index.unique
+                    && !seek(
+                        catalog.index(index.id)?,
+                        &unique_prefix(&new_values),
+                        Some(1),
+                    )?
+                    .is_empty()
+                {
+                    return Err(unique_failed(catalog, table_id, &index.columns));
+                }
+                insert_entry(catalog.index_mut(index.id)?, &new_values, rowid)?;
+            }
+            let btree = catalog.table_mut(table_id)?;
+            btree.delete(old_rowid)?;
+            btree.insert(rowid, &record)?;
+        }
+        Ok(rows.len())
+    }
+
+    /// Runs a DELETE.
+    fn delete(
+        &self,
+        catalog: &mut Catalog,
+        table: &Ident,
+        filter: Option<&Expr>,
+    ) -> io::Result<usize> {
+        let (table_id, table_schema) = self.table(catalog, table)?;
+        let table_schema = table_schema.clone();
+        let scope = [ScopeTable::new(&table.name, &table_schema, 0)];
+        let rows = self.matching_rows(catalog, table, &scope, filter)?;
+
+        let indexes = table_indexes(catalog, table_id, &table_schema);
+        for row in &rows {
+            let SqliteValue::Integer(rowid) = row[0] else {
+                unreachable!("The first slot is the rowid");
+            };
+            for index in &indexes {
+                delete_entry(
+                    catalog.index_mut(index.id)?,
+                    &index_values(index, &row[1..]),
+                    rowid,
+                )?;
+            }
+            catalog.table_mut(table_id)?.delete(rowid)?;
+        }
+        Ok(rows.len())
+    }
+
+    /// Reads the rows of a table that an UPDATE or a DELETE changes, before changing any of them.
+    fn matching_rows(
+        &self,
+        catalog: &Catalog,
+        table: &Ident,
+        scope: &[ScopeTable],
+        filter: Option<&Expr>,
+    ) -> io::Result<Vec<Vec<SqliteValue>>> {
+        let (table_id, table_schema) = self.table(catalog, table)?;
+        let filter = self.resolve_plain(filter, scope)?;
+        let join = super::ast::Join {
+            kind: JoinKind::Inner,
+            table: table.clone(),
+            alias: None,
+            on: None,
+        };
+        let tables = [(catalog.table(table_id)?, table_schema)];
+        self.read_rows(catalog, scope, &tables, &[join], filter)
+    }
+
+    /// Finds what a column of an INSERT or an UPDATE writes to.
+    fn target(
+        &self,
+        table_schema: &TableSchema,
+        table: &Ident,
+        column: &Ident,
+    ) -> io::Result<Target> {
+        match table_schema.column_index(&column.name) {
+            Some(i) => Ok(Target::Column(i)),
+            None if is_rowid_name(&column.name) => Ok(Target::Rowid),
+            None => Err(self.error(
+                column.offset,
+                format!("table {} has no column named {}", table.name, column.name),
+            )),
+        }
+    }
+
+    /// The error for a duplicate rowid.
+    fn rowid_failed(
+        &self,
+        catalog: &Catalog,
+        table_id: TableId,
+        table_schema: &TableSchema,
+    ) -> io::Error {
+        let table_name = catalog
+            .table_names
+            .get(&table_id)
+            .cloned()
+            .unwrap_or_default();
+        let column = match table_schema.primary_key() {
+            Some(primary_key) => table_schema.columns()[primary_key].name.clone(),
+            None => "rowid".to_string(),
+        };
+        io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("UNIQUE constraint failed: {}.{}", table_name, column),
+        )
+    }
+
+    /// Builds a `SqlError` at an offset of the SQL.
+    fn error(&self, offset: usize, message: impl Into<String>) -> io::Error {
+        error_at(self.sql, offset, message)
+    }
+}
+
+impl Accumulator {
+    /// Adds the value of a row to the aggregate. `None` is a row of `COUNT(*)`.
+    fn add(&mut self, aggregate: &Aggregate, value: Option<SqliteValue>) -> io::Result<()> {
+        let Some(value) = value else {
+            self.count += 1;
+            return Ok(());
+        };
+        if value == SqliteValue::Null {
+            return Ok(());
+        }
+        if aggregate.distinct
+            && !self
+                .seen
+                .insert(encode_values(std::slice::from_ref(&value)))
+        {
+            return Ok(());
+        }
+
+        self.count += 1;
+        match aggregate.kind {
+            AggregateKind::Count => {}
+            AggregateKind::Sum | AggregateKind::Total | AggregateKind::Avg => {
+                match to_number(&value) {
+                    Some(SqliteValue::Integer(i)) if !self.float => {
+                        match self.int_sum.checked_add(i) {
+                            Some(sum) => self.int_sum = sum,
+                            None if aggregate.kind == AggregateKind::Sum => {
+                                return Err(io::Error::new(
+                                    io::ErrorKind::InvalidInput,
+                                    "integer overflow",
+                                ))
+                            }
+                            None => {
+                                self.float = true;
+                                self.float_sum = self.int_sum as f64 + i as f64;
+                            }
+                        }
+                    }
+                    Some(SqliteValue::Integer(i)) => self.float_sum += i as f64,
+                    Some(SqliteValue::Float(f)) => {
+                        if !self.float {
+                            self.float = true;
+                            self.float_sum = self.int_sum as f64;
+                        }
+                        self.float_sum += f;
+                    }
+                    _ => {}
+                }
+            }
+            AggregateKind::Min | AggregateKind::Max => {
+                let better = match &self.best {
+                    None => true,
+                    Some(best) => {
+                        let ordering = compare_values(&value, best);
+                        match aggregate.kind {
+                            AggregateKind::Min => ordering.is_lt(),
+                            _ => ordering.is_gt(),
+                        }
+                    }
+                };
+                if better {
+                    self.best = Some(value);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Gets the result of the aggregate.
+    fn finish(self, kind: AggregateKind) -> SqliteValue {
+        let sum = match self.float {
+            true => self.float_sum,
+            false => self.int_sum as f64,
+        };
+        match kind {
+            AggregateKind::Count => SqliteValue::Integer(self.count),
+            AggregateKind::Sum if self.count == 0 => SqliteValue::Null,
+            AggregateKind::Sum if self.float => SqliteValue::Float(self.float_sum),
+            AggregateKind::Sum => SqliteValue::Integer(self.int_sum),
+            AggregateKind::Total => SqliteValue::Float(sum),
+            AggregateKind::Avg if self.count == 0 => SqliteValue::Null,
+            AggregateKind::Avg => SqliteValue::Float(sum / self.count as f64),
+            AggregateKind::Min | AggregateKind::Max => self.best.unwrap_or(SqliteValue::Null),
+        }
+    }
+}
+
+/// Checks if a name is one of the names of the rowid.
+fn is_rowid_name(name: &str) -> bool {
+    ROWID_NAMES
+        .iter()
+        .any(|rowid| rowid.eq_ignore_ascii_case(name))
+}
+
+/// Replaces the names in a GROUP BY or a HAVING that are aliases of result columns, and not columns of the tables,
+/// with the expressions of the result columns.
+fn substitute_aliases(expr: &Expr, select: &Select, scope: &[ScopeTable]) -> Expr {
+    let substituted: Result<Expr, ()> = expr.clone().transform(&mut |expr| {
+        let Expr::Column { table: None, name } = &expr else {
+            return Ok(expr);
+        };
+        if scope.iter().any(|table| table.slot(&name.name).is_some()) {
+            return Ok(expr);
+        }
+        let aliased = select.columns.iter().find_map(|column| match column {
+            ResultColumn::Expr {
+                expr,
+                alias: Some(alias),
+                ..
+            } if alias.eq_ignore_ascii_case(&name.name) => Some(expr.clone()),
+            _ => None,
+        });
+        Ok(aliased.unwrap_or(expr))
+    });
+    substituted.unwrap_or_else(|()| expr.clone())
+}
+
+/// Splits a condition at its ANDs.
+fn split_and(expr: Expr, conditions: &mut Vec<Expr>) {
+    match expr {
+        Expr::Binary {
+            op: BinaryOp::And,
+            left,
+            right,
+        } => {
+            split_and(*left, conditions);
+            split_and(*right, conditions);
+        }
+        expr => conditions.push(expr),
+    }
+}
+
+/// Gets the last slot an expression uses, if it uses any.
+fn max_slot(expr: &Expr) -> Option<usize> {
+    let mut max = None;
+    expr.visit(&mut |expr| {
+        if let Expr::Slot { index, .. } = expr {
+            max = max.max(Some(*index));
+        }
+    });
+    max
+}
+
+/// Gets the affinity of an expression: the one of its column, or of the type of a CAST.
+fn expr_affinity(expr: &Expr) -> Option<Affinity> {
+    match expr {
+        Expr::Slot { affinity, .. } => *affinity,
+        Expr::Cast { declared_type, .. } => Some(Affinity::from_declared_type(declared_type)),
+        _ => None,
+    }
+}
+
+/// Checks if `column = value` can seek a column, that is, if converting the value to the affinity of the column
+/// finds every stored value the comparison is true for.
+fn seekable(column: Affinity, value: Option<Affinity>) -> bool {
+    match column {
+        Affinity::Integer | Affinity::Real | Affinity::Numeric => true,
+        Affinity::Text => matches!(value, None | Some(Affinity::Blob | Affinity::Text)),
+        Affinity::Blob => matches!(value, None | Some(Affinity::Blob)),
+    }
+}
+
+/// Finds the aggregate a call is, if it is one. `min` and `max` with several arguments are scalar functions.
+fn aggregate_kind(name: &str, args: usize, star: bool) -> Option<AggregateKind> {
+    let kind = match name.to_ascii_lowercase().as_str() {
+        "count" if star || args == 1 => AggregateKind::Count,
+        _ if star || args != 1 => return None,
+        "sum" => AggregateKind::Sum,
+        "total" => AggregateKind::Total,
+        "avg" => AggregateKind::Avg,
+        "min" => AggregateKind::Min,
+        "max" => AggregateKind::Max,
+        _ => return None,
+    };
+    Some(kind)
+}
+
+/// Checks if a scalar function exists and takes that many arguments.
+fn scalar_arity(name: &str, args: usize) -> bool {
+    match name.to_ascii_lowercase().as_str() {
+        "abs" | "length" | "lower" | "upper" | "typeof" => args == 1,
+        "ifnull" | "nullif" => args == 2,
+        "substr" => args == 2 || args == 3,
+        "coalesce" | "min" | "max" => args >= 2,
+        _ => false,
+    }
+}
+
+/// Checks if a function exists at all, to tell a wrong number of arguments from an unknown function.
+fn is_function(name: &str) -> bool {
+    (0..4).any(|args| scalar_arity(name, args) || aggregate_kind(name, args, false).is_some())
+}
+
+/// Calls a scalar function. Returns `None` for an aggregate, which cannot be called on a single row.
+fn call(name: &str, args: Vec<SqliteValue>) -> Option<SqliteValue> {
+    let null = SqliteValue::Null;
+    Some(match (name.to_ascii_lowercase().as_str(), &args[..]) {
+        ("abs", [value]) => match to_number(value) {
+            Some(SqliteValue::Integer(i)) => match i.checked_abs() {
+                Some(i) => SqliteValue::Integer(i),
+                None => SqliteValue::Float((i as f64).abs()),
+            },
+            Some(SqliteValue::Float(f)) => SqliteValue::Float(f.abs()),
+            _ => null,
+        },
+        ("length", [value]) => match value {
+            SqliteValue::Null => null,
+            SqliteValue::Blob(blob) => SqliteValue::Integer(blob.len() as i64),
+            value => SqliteValue::Integer(to_text(value).chars().count() as i64),
+        },
+        ("lower", [SqliteValue::Null]) | ("upper", [SqliteValue::Null]) => null,
+        ("lower", [value]) => SqliteValue::String(to_text(value).to_lowercase()),
+        ("upper", [value]) => SqliteValue::String(to_text(value).to_uppercase()),
+        ("typeof", [value]) => SqliteValue::String(
+            match value {
+                SqliteValue::Null => "null",
+                SqliteValue::Integer(_) => "integer",
+                SqliteValue::Float(_) => "real",
+                SqliteValue::String(_) => "text",
+                SqliteValue::Blob(_) => "blob",
+            }
+            .to_string(),
+        ),
+        ("ifnull" | "coalesce", args) => args
+            .iter()
+            .find(|value| **value != SqliteValue::Null)
+            .cloned()
+            .unwrap_or(null),
+        ("nullif", [a, b]) => match compare(BinaryOp::Eq, a, b) {
+            SqliteValue::Integer(1) => null,
+            _ => a.clone(),
+        },
+        ("substr", [value, start, rest @ ..]) => {
+            if *value == SqliteValue::Null || *start == SqliteValue::Null {
+                return Some(null);
+            }
+            let chars: Vec<char> = to_text(value).chars().collect();
+            let len = chars.len() as i64;
+            let start = match to_number(start) {
+                Some(SqliteValue::Integer(i)) => i,
+                Some(SqliteValue::Float(f)) => f as i64,
+                _ => 0,
+            };
+            let count = match rest.first().map(to_number) {
+                Some(Some(SqliteValue::Integer(i))) => i,
+                Some(Some(SqliteValue::Float(f))) => f as i64,
+                Some(_) => return Some(null),
+                None => len,
+            };
+            // Positions are from 1, negative ones from the end, and a negative count takes the characters before
+            let first = match start {
+                start if start > 0 => start - 1,
+                0 => -1,
+                start => len + start,
+            };
+            let (from, to) = match count {
+                count if count >= 0 => (first, first.saturating_add(count)),
+                count => (first.saturating_add(count), first),
+            };
+            let (from, to) = (from.clamp(0, len) as usize, to.clamp(0, len) as usize);
+            SqliteValue::String(chars[from..to].iter().collect())
+        }
+        ("min" | "max", args) => {
+            if args.contains(&null) {
+                return Some(null);
+            }
+            let max = name.eq_ignore_ascii_case("max");
+            args.iter()
+                .cloned()
+                .reduce(|best, value| {
+                    let ordering = compare_values(&value, &best);
+                    if (max && ordering.is_gt()) || (!max && ordering.is_lt()) {
+                        value
+                    } else {
+                        best
+                    }
+                })
+                .unwrap_or(null)
+        }
+        _ => return None,
+    })
+}
+
+/// Gets the truth of a value: NULL is unknown, numbers are true if they are not 0,
+/// and text and blobs are read as numbers.
+fn truth(value: &SqliteValue) -> Option<bool> {
+    match value {
+        SqliteValue::Null => None,
+        value => match to_number(value) {
+            Some(SqliteValue::Integer(i)) => Some(i != 0),
+            Some(SqliteValue::Float(f)) => Some(f != 0.0),
+            _ => Some(false),
+        },
+    }
+}
+
+/// Negates a truth value for NOT IN and NOT BETWEEN, leaving unknown as NULL.
+fn negate(value: Option<bool>, negated: bool) -> SqliteValue {
+    match value {
+        Some(value) => SqliteValue::Integer((value != negated) as i64),
+        None => SqliteValue::Null,
+    }
+}
+
+/// Reads a value as a number, for arithmetic and CAST. Text is read up to the first character that cannot be
+/// part of a number, so `'12abc'` is 12 and text that does not start with a number is 0, as in SQLite.
+fn to_number(value: &SqliteValue) -> Option<SqliteValue> {
+    match value {
+        SqliteValue::Null => None,
+        SqliteValue::Integer(_) | SqliteValue::Float(_) => Some(value.clone()),
+        SqliteValue::String(text) => Some(numeric_prefix(text)),
+        SqliteValue::Blob(blob) => Some(numeric_prefix(&String::from_utf8_lossy(blob))),
+    }
+}
+
+/// Reads the number at the start of some text: a sign, digits, a fraction and an exponent.
+fn numeric_prefix(text: &str) -> SqliteValue {
+    let text = text.trim_start();
+    let bytes = text.as_bytes();
+    let digits = |mut end: usize| {
+        while bytes.get(end).is_some_and(u8::is_ascii_digit) {
+            end += 1;
+        }
+        end
+    };
+
+    let mut end = digits(usize::from(matches!(bytes.first(), Some(b'+' | b'-'))));
+    if bytes.get(end) == Some(&b'.') {
+        end = digits(end + 1);
+    }
+    if matches!(bytes.get(end), Some(b'e' | b'E')) {
+        let sign = usize::from(matches!(bytes.get(end + 1), Some(b'+' | b'-')));
+        let exponent = digits(end + 1 + sign);
+        // An exponent without digits is not part of the number
+        if exponent > end + 1 + sign {
+            end = exponent;
+        }
+    }
+    parse_numeric(&text[..end]).unwrap_or(SqliteValue::Integer(0))
+}
+
+/// Reads a value as text.
+fn to_text(value: &SqliteValue) -> String {
+    match value {
+        SqliteValue::Null => String::new(),
+        SqliteValue::Integer(i) => i.to_string(),
+        SqliteValue::Float(f) => real_to_text(*f),
+        SqliteValue::String(text) => text.clone(),
+        SqliteValue::Blob(blob) => String::from_utf8_lossy(blob).into_owned(),
+    }
+}
+
+/// Runs an arithmetic operator, or `||`. Integer overflow gives a float, and dividing by zero gives NULL.
+fn arithmetic(op: BinaryOp, a: &SqliteValue, b: &SqliteValue) -> SqliteValue {
+    if op == BinaryOp::Concat {
+        if *a == SqliteValue::Null || *b == SqliteValue::Null {
+            return SqliteValue::Null;
+        }
+        return SqliteValue::String(to_text(a) + &to_text(b));
+    }
+    let (Some(a), Some(b)) = (to_number(a), to_number(b)) else {
+        return SqliteValue::Null;
+    };
+
+    if let (SqliteValue::Integer(a), SqliteValue::Integer(b)) = (&a, &b) {
+        let (a, b) = (*a, *b);
+        let result = match op {
+            BinaryOp::Add => a.checked_add(b),
+            BinaryOp::Sub => a.checked_sub(b),
+            BinaryOp::Mul => a.checked_mul(b),
+            BinaryOp::Div | BinaryOp::Rem if b == 0 => return SqliteValue::Null,
+            BinaryOp::Div => a.checked_div(b),
+            _ => Some(a.checked_rem(b).unwrap_or(0)),
+        };
+        if let Some(result) = result {
+            return SqliteValue::Integer(result);
+        }
+    }
+
+    let float = |value: &SqliteValue| match value {
+        SqliteValue::Integer(i) => *i as f64,
+        SqliteValue::Float(f) => *f,
+        _ => 0.0,
+    };
+    let (x, y) = (float(&a), float(&b));
+    let result = match op {
+        BinaryOp::Add => x + y,
+        BinaryOp::Sub => x - y,
+        BinaryOp::Mul => x * y,
+        BinaryOp::Div if y == 0.0 => return SqliteValue::Null,
+        BinaryOp::Div => x / y,
+        _ => {
+            // % works on the integer parts
+            let (x, y) = (x as i64, y as i64);
+            if y == 0 {
+                return SqliteValue::Null;
+            }
+            x.checked_rem(y).unwrap_or(0) as f64
+        }
+    };
+    SqliteValue::Float(result)
+}
+
+/// Runs a comparison operator on values that already went through the affinities.
+fn compare(op: BinaryOp, a: &SqliteValue, b: &SqliteValue) -> SqliteValue {
+    let null = *a == SqliteValue::Null || *b == SqliteValue::Null;
+    let result = match op {
+        BinaryOp::Is => compare_values(a, b).is_eq() && (null == (*a == *b)),
+        BinaryOp::IsNot => !(compare_values(a, b).is_eq() && (null == (*a == *b))),
+        _ if null => return SqliteValue::Null,
+        BinaryOp::Eq => compare_values(a, b).is_eq(),
+        BinaryOp::NotEq => compare_values(a, b).is_ne(),
+        BinaryOp::Lt => compare_values(a, b).is_lt(),
+        BinaryOp::LtEq => compare_values(a, b).is_le(),
+        BinaryOp::Gt => compare_values(a, b).is_gt(),
+        _ => compare_values(a, b).is_ge(),
+    };
+    SqliteValue::Integer(result as i64)
+}
+
+/// Orders two values the way SQLite sorts them: NULLs, then numbers by value, then text, then blobs.
+pub(crate) fn compare_values(a: &SqliteValue, b: &SqliteValue) -> Ordering {
+    let rank = |value: &SqliteValue| match value {
+        SqliteValue::Null => 0,
+        SqliteValue::Integer(_) | SqliteValue::Float(_) => 1,
+        SqliteValue::String(_) => 2,
+        SqliteValue::Blob(_) => 3,
+    };
+    match (a, b) {
+        (SqliteValue::Integer(a), SqliteValue::Integer(b)) => a.cmp(b),
+        (SqliteValue::Float(a), SqliteValue::Float(b)) => a.total_cmp(b),
+        (SqliteValue::Integer(i), SqliteValue::Float(f)) => compare_int_float(*i, *f),
+        (SqliteValue::Float(f), SqliteValue::Integer(i)) => compare_int_float(*i, *f).reverse(),
+        (SqliteValue::String(a), SqliteValue::String(b)) => a.cmp(b),
+        (SqliteValue::Blob(a), SqliteValue::Blob(b)) => a.cmp(b),
+        (a, b) => rank(a).cmp(&rank(b)),
+    }
+}
+
+/// Compares an integer with a float exactly, even when the integer does not fit in a float.
+fn compare_int_float(i: i64, f: f64) -> Ordering {
+    match (i as f64).partial_cmp(&f) {
+        Some(Ordering::Equal) => (i as i128).cmp(&(f as i128)),
+        Some(ordering) => ordering,
+        None => Ordering::Greater,
+    }
+}
+
+/// Matches a LIKE pattern: `%` is any run of characters, `_` any one character, and ASCII letters match
+/// regardless of case, as in SQLite.
+fn like(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+
+    // Backtracking on the last %, which is enough as a % matches anything the next ones would
+    let (mut p, mut t) = (0, 0);
+    let mut star: Option<(usize, usize)> = None;
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == '_' || pattern[p].eq_ignore_ascii_case(&text[t])) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == '%' {
+            star = Some((p, t));
+            p += 1;
+        } else if let Some((star_p, star_t)) = star {
+            p = star_p + 1;
+            t = star_t + 1;
+            star = Some((star_p, star_t + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '%')
+}
+
+/// Runs a CAST.
+fn cast(value: SqliteValue, declared_type: &str) -> SqliteValue {
+    if value == SqliteValue::Null {
+        return value;
+    }
+    match Affinity::from_declared_type(declared_type) {
+        Affinity::Integer => match to_number(&value) {
+            Some(SqliteValue::Float(f)) => SqliteValue::Integer(f as i64),
+            Some(number) => number,
+            None => SqliteValue::Integer(0),
+        },
+        Affinity::Real => match to_number(&value) {
+            Some(SqliteValue::Integer(i)) => SqliteValue::Float(i as f64),
+            Some(number) => number,
+            None => SqliteValue::Float(0.0),
+        },
+        Affinity::Numeric => to_number(&value).unwrap_or(SqliteValue::Integer(0)),
+        Affinity::Text => SqliteValue::String(to_text(&value)),
+        Affinity::Blob => match value {
+            SqliteValue::Blob(blob) => SqliteValue::Blob(blob),
+            value => SqliteValue::Blob(to_text(&value).into_bytes()),
+        },
+    }
+}
+
+/// Turns the value of a rowid, or of an INTEGER PRIMARY KEY, into the rowid.
+fn rowid_value(value: SqliteValue) -> io::Result<i64> {
+    match Affinity::Integer.apply(value) {
+        SqliteValue::Integer(rowid) => Ok(rowid),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "datatype mismatch",
+        )),
+    }
+}
+
+/// Picks the rowid of a row inserted without one: one more than the largest rowid of the table.
+fn next_rowid(btree: &BTree) -> io::Result<i64> {
+    match btree.max_rowid()? {
+        None => Ok(1),
+        Some(rowid) => rowid
+            .checked_add(1)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "database or disk is full")),
+    }
+}
+
+/// Inserts a row in a table, and its entries in the indexes created on the table with SQL.
+/// Every insert goes through here, from SQL or from `RQLite::table_insert`, so the indexes stay complete.
+///
+/// # Parameters
+/// * `record` - The row, already through the schema of the table.
+///
+/// # Errors
+/// Returns an `AlreadyExists` error if the rowid is taken or a UNIQUE index already has the values of the row.
+/// The table or some indexes may have changed then: the caller rolls its statement back.
+pub(crate) fn insert_row(
+    catalog: &mut Catalog,
+    table_id: TableId,
+    rowid: i64,
+    record: &Record,
+) -> io::Result<()> {
+    catalog.table_mut(table_id)?.insert(rowid, record)?;
+    let Some(table_schema) = catalog.table_schemas.get(&table_id) else {
+        return Ok(());
+    };
+    let indexes = table_indexes(catalog, table_id, table_schema);
+    if indexes.is_empty() {
+        return Ok(());
+    }
+    let full = table_schema.complete(rowid, record.clone());
+    for index in &indexes {
+        let values = index_values(index, &full.values);
+        if index.unique
+            && !seek(catalog.index(index.id)?, &unique_prefix(&values), Some(1))?.is_empty()
+        {
+            return Err(unique_failed(catalog, table_id, &index.columns));
+        }
+        insert_entry(catalog.index_mut(index.id)?, &values, rowid)?;
+    }
+    Ok(())
+}
+
+/// Deletes a row from a table, and its entries from the indexes created on the table with SQL.
+///
+/// # Returns
+/// True if the row was there.
+pub(crate) fn delete_row(catalog: &mut Catalog, table_id: TableId, rowid: i64) -> io::Result<bool> {
+    if let Some(table_schema) = catalog.table_schemas.get(&table_id) {
+        let indexes = table_indexes(catalog, table_id, table_schema);
+        if !indexes.is_empty() {
+            let Some(record) = catalog.table(table_id)?.find(rowid)? else {
+                return Ok(false);
+            };
+            let full = table_schema.complete(rowid, record);
+            for index in &indexes {
+                let values = index_values(index, &full.values);
+                delete_entry(catalog.index_mut(index.id)?, &values, rowid)?;
+            }
+        }
+    }
+    catalog.table_mut(table_id)?.delete(rowid)
+}
+
+/// Adds the rows of a table to the indexes created on it with SQL, after `RQLite::bulk_load` filled the table
+/// without them. The indexes were empty, as the table was.
+///
+/// # Errors
+/// Returns an `AlreadyExists` error if two rows have the same values in a UNIQUE index.
+pub(crate) fn fill_indexes(catalog: &mut Catalog, table_id: TableId) -> io::Result<()> {
+    let Some(table_schema) = catalog.table_schemas.get(&table_id) else {
+        return Ok(());
+    };
+    for index in table_indexes(catalog, table_id, table_schema) {
+        // The index leaves the catalog while it is filled, so the table can be read meanwhile
+        let mut btree = catalog.indexes.remove(&index.id).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("Index {} not found", index.id),
+            )
+        })?;
+        let filled = fill_index(catalog, table_id, &index, &mut btree);
+        catalog.indexes.insert(index.id, btree);
+        filled?;
+    }
+    Ok(())
+}
+
+/// Adds the entries of every row of a table to an index, checking its UNIQUE constraint.
+fn fill_index(
+    catalog: &Catalog,
+    table_id: TableId,
+    index: &TableIndex,
+    btree: &mut BTree,
+) -> io::Result<()> {
+    let table_schema = &catalog.table_schemas[&table_id];
+    for entry in catalog.table(table_id)?.scan()? {
+        let (rowid, record) = entry?;
+        let record = table_schema.complete(rowid, record);
+        let values = index_values(index, &record.values);
+        if index.unique && !seek(btree, &unique_prefix(&values), Some(1))?.is_empty() {
+            return Err(unique_failed(catalog, table_id, &index.columns));
+        }
+        insert_entry(btree, &values, rowid)?;
+    }
+    Ok(())
+}
+
+/// Gets the indexes created with SQL on a table.
+fn table_indexes(
+    catalog: &Catalog,
+    table_id: TableId,
+    table_schema: &TableSchema,
+) -> Vec<TableIndex> {
+    catalog
+        .index_schemas
+        .iter()
+        .filter(|(_, index_schema)| index_schema.table_id == table_id)
+        .map(|(index_id, index_schema)| TableIndex {
+            id: *index_id,
+            columns: index_schema
+                .columns
+                .iter()
+                .filter_map(|column| table_schema.column_index(column))
+                .collect(),
+            unique: index_schema.unique,
+        })
+        .collect()
+}
+
+/// Gets the values of the columns of an index out of the columns of a row.
+fn index_values(index: &TableIndex, columns: &[SqliteValue]) -> Vec<SqliteValue> {
+    index
+        .columns
+        .iter()
+        .map(|column| columns.get(*column).cloned().unwrap_or(SqliteValue::Null))
+        .collect()
+}
+
+/// Gets the prefix a duplicate of values in a UNIQUE index would have. Values with a NULL have no duplicates,
+/// as NULLs are all distinct, so they get a prefix no entry has.
+fn unique_prefix(values: &[SqliteValue]) -> Vec<u8> {
+    if values.contains(&SqliteValue::Null) {
+        return vec![0xFF];
+    }
+    encode_values(values)
+}
+
+/// Finds the rowids of the index entries whose key starts with a prefix.
+fn seek(index: &BTree, prefix: &[u8], limit: Option<usize>) -> io::Result<Vec<i64>> {
+    let mut rowids = Vec::new();
+    for entry in index.scan_index(Some(KeyValue::Blob(prefix.to_vec())))? {
+        let (key, rowid) = entry?;
+        match key {
+            KeyValue::Blob(key) if key.starts_with(prefix) => rowids.push(rowid),
+            _ => break,
+        }
+        if limit.is_some_and(|limit| rowids.len() >= limit) {
+            break;
+        }
+    }
+    Ok(rowids)
+}
+
+/// Inserts the entry of a row in an index.
+fn insert_entry(index: &mut BTree, values: &[SqliteValue], rowid: i64) -> io::Result<()> {
+    let mut payload = Vec::new();
+    serialize_values(
+        &[SqliteValue::Blob(encode_key(values, rowid))],
+        &mut payload,
+    )?;
+    index.insert_index(&payload, rowid)
+}
+
+/// Deletes the entry of a row from an index.
+fn delete_entry(index: &mut BTree, values: &[SqliteValue], rowid: i64) -> io::Result<()> {
+    index.delete_index(&KeyValue::Blob(encode_key(values, rowid)))?;
+    Ok(())
+}
+
+/// The error for a duplicate in a UNIQUE index.
+fn unique_failed(catalog: &Catalog, table_id: TableId, columns: &[usize]) -> io::Error {
+    let table_name = catalog
+        .table_names
+        .get(&table_id)
+        .cloned()
+        .unwrap_or_default();
+    let table_schema = &catalog.table_schemas[&table_id];
+    let columns: Vec<String> = columns
+        .iter()
+        .map(|column| format!("{}.{}", table_name, table_schema.columns()[*column].name))
+        .collect();
+    io::Error::new(
+        io::ErrorKind::AlreadyExists,
+        format!("UNIQUE constraint failed: {}", columns.join(", ")),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use crate::{sql_error, RQLite, Record, SqliteValue};
+
+    fn int(i: i64) -> SqliteValue {
+        SqliteValue::Integer(i)
+    }
+
+    fn text(s: &str) -> SqliteValue {
+        SqliteValue::String(s.to_string())
+    }
+
+    /// Runs a query and returns the values of its rows.
+    fn rows(db: &RQLite, sql: &str) -> Vec<Vec<SqliteValue>> {
+        db.query(sql, &[])
+            .unwrap()
+            .into_iter()
+            .map(|record| record.values)
+            .collect()
+    }
+
+    fn people() -> RQLite {
+        let db = RQLite::create_in_memory(None).unwrap();
+        db.execute(
+            "CREATE TABLE people (id INTEGER PRIMARY KEY, name TEXT NOT NULL, age INTEGER, city TEXT);
+             CREATE INDEX people_city ON people (city, age);
+             INSERT INTO people (name, age, city) VALUES
+                ('ann', 31, 'paris'), ('bob', 25, 'rome'), ('cid', 40, 'paris'), ('dan', NULL, 'oslo');",
+            &[],
+        )
+        .unwrap();
+        db
+    }
+
+    #[test]
+    fn test_select_filter_order_limit() {
+        let db = people();
+        assert_eq!(
+            rows(
+                &db,
+                "SELECT name FROM people WHERE city = 'paris' ORDER BY age DESC"
+            ),
+            vec![vec![text("cid")], vec![text("ann")]]
+        );
+        // The text '25' is compared as a number, as age has a numeric affinity
+        assert_eq!(
+            rows(&db, "SELECT id FROM people WHERE age = '25'"),
+            vec![vec![int(2)]]
+        );
+        assert_eq!(
+            rows(
+                &db,
+                "SELECT id FROM people WHERE age IS NULL OR name LIKE 'A%'"
+            ),
+            vec![vec![int(1)], vec![int(4)]]
+        );
+        assert_eq!(
+            rows(&db, "SELECT name FROM people ORDER BY 1 LIMIT 2 OFFSET 1"),
+            vec![vec![text("bob")], vec![text("cid")]]
+        );
+        assert_eq!(
+            rows(
+                &db,
+                "SELECT DISTINCT city FROM people WHERE age BETWEEN 20 AND 35 OR city IN ('oslo')"
+            ),
+            vec![vec![text("paris")], vec![text("rome")], vec![text("oslo")]]
+        );
+        assert_eq!(
+            rows(&db, "SELECT * FROM people WHERE rowid = 3")[0][1],
+            text("cid")
+        );
+
+        let result = db
+            .query(
+                "SELECT name AS who, age + 1 FROM people WHERE id = ?",
+                &[int(1)],
+            )
+            .unwrap();
+        assert_eq!(result.columns(), ["who", "age + 1"]);
+        assert_eq!(result.get(0).unwrap().values, vec![text("ann"), int(32)]);
+
+        // A query runs one statement, so none is dropped without being seen
+        assert_eq!(db.query("SELECT 1;", &[]).unwrap().len(), 1);
+        for sql in ["SELECT 1; SELECT 2", "DELETE FROM people; SELECT 1"] {
+            let error = db.query(sql, &[]).unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+            let error = db.transaction().unwrap().query(sql, &[]).unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+        }
+        assert_eq!(rows(&db, "SELECT count(*) FROM people"), vec![vec![int(4)]]);
+    }
+
+    #[test]
+    fn test_aggregates_and_groups() {
+        let db = people();
+        assert_eq!(
+            rows(
+                &db,
+                "SELECT count(*), count(age), sum(age), max(name), avg(age) FROM people"
+            ),
+            vec![vec![
+                int(4),
+                int(3),
+                int(96),
+                text("dan"),
+                SqliteValue::Float(32.0)
+            ]]
+        );
+        assert_eq!(
+            rows(
+                &db,
+                "SELECT city, count(*) AS n FROM people GROUP BY city HAVING n < 2 ORDER BY city"
+            ),
+            vec![vec![text("oslo"), int(1)], vec![text("rome"), int(1)]]
+        );
+        // Aggregates over no rows still make a row
+        assert_eq!(
+            rows(&db, "SELECT count(*), sum(age) FROM people WHERE age > 100"),
+            vec![vec![int(0), SqliteValue::Null]]
+        );
+
+        let error = db
+            .query("SELECT name FROM people WHERE count(*) > 1", &[])
+            .unwrap_err();
+        assert!(sql_error(&error)
+            .unwrap()
+            .message
+            .contains("misuse of aggregate"));
+    }
+
+    #[test]
+    fn test_joins() {
+        let db = people();
+        db.execute(
+            "CREATE TABLE pets (owner INTEGER, kind TEXT);
+             INSERT INTO pets VALUES (1, 'cat'), (1, 'dog'), (3, 'fish');",
+            &[],
+        )
+        .unwrap();
+
+        assert_eq!(
+            rows(
+                &db,
+                "SELECT p.name, pets.kind FROM people p JOIN pets ON pets.owner = p.id ORDER BY 2"
+            ),
+            vec![
+                vec![text("ann"), text("cat")],
+                vec![text("ann"), text("dog")],
+                vec![text("cid"), text("fish")]
+            ]
+        );
+        assert_eq!(
+            rows(
+                &db,
+                "SELECT name, count(kind) FROM people LEFT JOIN pets ON owner = id GROUP BY name ORDER BY name"
+            ),
+            vec![
+                vec![text("ann"), int(2)],
+                vec![text("bob"), int(0)],
+                vec![text("cid"), int(1)],
+                vec![text("dan"), int(0)]
+            ]
+        );
+        assert_eq!(
+            rows(&db, "SELECT count(*) FROM people, pets"),
+            vec![vec![int(12)]]
+        );
+
+        let error = db.query("SELECT rowid FROM people, pets", &[]).unwrap_err();
+        assert!(sql_error(&error).unwrap().message.contains("ambiguous"));
+    }
+
+    #[test]
+    fn test_update_delete_and_indexes() {
+        let db = people();
+        assert_eq!(
+            db.execute(
+                "UPDATE people SET city = 'rome', age = age + 1 WHERE city = 'paris'",
+                &[]
+            )
+            .unwrap(),
+            2
+        );
+        assert_eq!(
+            rows(
+                &db,
+                "SELECT name, age FROM people WHERE city = 'rome' AND age > 30 ORDER BY name"
+            ),
+            vec![vec![text("ann"), int(32)], vec![text("cid"), int(41)]]
+        );
+        assert!(rows(&db, "SELECT * FROM people WHERE city = 'paris'").is_empty());
+
+        // Changing the INTEGER PRIMARY KEY moves the row
+        db.execute("UPDATE people SET id = 10 WHERE name = 'bob'", &[])
+            .unwrap();
+        assert_eq!(
+            rows(&db, "SELECT name FROM people WHERE id = 10"),
+            vec![vec![text("bob")]]
+        );
+
+        assert_eq!(
+            db.execute("DELETE FROM people WHERE city = 'rome'", &[])
+                .unwrap(),
+            3
+        );
+        assert_eq!(
+            rows(&db, "SELECT name FROM people"),
+            vec![vec![text("dan")]]
+        );
+        assert!(rows(&db, "SELECT * FROM people WHERE city = 'rome' AND age = 32").is_empty());
+
+        db.execute("DROP INDEX people_city; DROP TABLE people", &[])
+            .unwrap();
+        assert!(db.query("SELECT * FROM people", &[]).is_err());
+        db.execute("DROP TABLE IF EXISTS people", &[]).unwrap();
+    }
+
+    #[test]
+    fn test_raw_writes_keep_indexes() {
+        let db = people();
+        db.execute("CREATE UNIQUE INDEX people_name ON people (name)", &[])
+            .unwrap();
+        let table_id = db.find_table("people").unwrap();
+        let person = |name: &str, age: i64, city: &str| {
+            Record::with_values(vec![SqliteValue::Null, text(name), int(age), text(city)])
+        };
+
+        // The indexes see the rows written without SQL, and their UNIQUE constraints hold
+        db.table_insert(table_id, 10, &person("eve", 28, "oslo"))
+            .unwrap();
+        assert_eq!(
+            rows(
+                &db,
+                "SELECT id FROM people WHERE city = 'oslo' AND age = 28"
+            ),
+            vec![vec![int(10)]]
+        );
+        let error = db
+            .table_insert(table_id, 11, &person("ann", 50, "lima"))
+            .unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::AlreadyExists);
+        assert!(db.table_find(table_id, 11).unwrap().is_none());
+        assert!(rows(&db, "SELECT * FROM people WHERE city = 'lima'").is_empty());
+
+        assert!(db.table_delete(table_id, 10).unwrap());
+        assert!(!db.table_delete(table_id, 10).unwrap());
+        assert!(rows(&db, "SELECT * FROM people WHERE city = 'oslo' AND age = 28").is_empty());
+        db.execute("INSERT INTO people (name) VALUES ('eve')", &[])
+            .unwrap();
+
+        // A bulk load fills the indexes of the table, or fails as a whole
+        db.execute("CREATE TABLE pets (name TEXT UNIQUE, owner TEXT)", &[])
+            .unwrap();
+        db.execute("CREATE INDEX pets_owner ON pets (owner)", &[])
+            .unwrap();
+        let pets = db.find_table("pets").unwrap();
+        let pet =
+            |rowid: i64, name: &str| (rowid, Record::with_values(vec![text(name), text("ann")]));
+        let error = db
+            .bulk_load(pets, vec![pet(1, "rex"), pet(2, "tom"), pet(3, "rex")])
+            .unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::AlreadyExists);
+        assert!(rows(&db, "SELECT * FROM pets").is_empty());
+        db.bulk_load(pets, vec![pet(1, "rex"), pet(2, "tom")])
+            .unwrap();
+        assert_eq!(
+            rows(
+                &db,
+                "SELECT name FROM pets WHERE owner = 'ann' ORDER BY name"
+            ),
+            vec![vec![text("rex")], vec![text("tom")]]
+        );
+        assert!(db
+            .execute("INSERT INTO pets VALUES ('tom', 'bob')", &[])
+            .is_err());
+    }
+
+    #[test]
+    fn test_constraints() {
+        let db = RQLite::create_in_memory(None).unwrap();
+        db.execute(
+            "CREATE TABLE t (id INTEGER PRIMARY KEY, email TEXT UNIQUE, score REAL DEFAULT 1.5)",
+            &[],
+        )
+        .unwrap();
+        db.execute("INSERT INTO t (email) VALUES ('a@x'), (NULL), (NULL)", &[])
+            .unwrap();
+        assert_eq!(
+            rows(&db, "SELECT score FROM t WHERE id = 1"),
+            vec![vec![SqliteValue::Float(1.5)]]
+        );
+
+        let error = db
+            .execute("INSERT INTO t (email) VALUES ('a@x')", &[])
+            .unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::AlreadyExists);
+        assert_eq!(error.to_string(), "UNIQUE constraint failed: t.email");
+        let error = db
+            .execute("INSERT INTO t (id) VALUES (2)", &[])
+            .unwrap_err();
+        assert_eq!(error.to_string(), "UNIQUE constraint failed: t.id");
+        assert!(db
+            .execute("UPDATE t SET email = 'a@x' WHERE id = 2", &[])
+            .is_err());
+        assert!(db.execute("DROP INDEX sqlite_autoindex_t_1", &[]).is_err());
+        assert_eq!(rows(&db, "SELECT count(*) FROM t"), vec![vec![int(3)]]);
+
+        // A failing statement of a multi-row INSERT undoes all its rows
+        assert!(db
+            .execute("INSERT INTO t (email) VALUES ('b@x'), ('a@x')", &[])
+            .is_err());
+        assert!(rows(&db, "SELECT * FROM t WHERE email = 'b@x'").is_empty());
+
+        db.execute("CREATE TABLE u (a TEXT NOT NULL)", &[]).unwrap();
+        assert!(db.execute("INSERT INTO u VALUES (NULL)", &[]).is_err());
+        assert!(db
+            .execute("CREATE UNIQUE INDEX bad ON t (score)", &[])
+            .is_err());
+    }
+
+    #[test]
+    fn test_error_positions() {
+        let db = people();
+        let error = db
+            .query("SELECT name,\n  nmae FROM people", &[])
+            .unwrap_err();
+        let position = sql_error(&error).unwrap();
+        assert_eq!(position.message, "no such column: nmae");
+        assert_eq!((position.line, position.column), (2, 3));
+
+        let error = db.query("SELECT * FROM peple", &[]).unwrap_err();
+        assert_eq!(sql_error(&error).unwrap().offset, 14);
+        let error = db
+            .query("SELECT lenght(name) FROM people", &[])
+            .unwrap_err();
+        assert_eq!(
+            sql_error(&error).unwrap().message,
+            "no such function: lenght"
+        );
+        let error = db
+            .execute("INSERT INTO people (nope) VALUES (1)", &[])
+            .unwrap_err();
+        assert_eq!(sql_error(&error).unwrap().offset, 20);
+        assert!(db.execute("SELECT ?", &[]).is_err());
+    }
+
+    #[test]
+    fn test_deep_expressions() {
+        // The deepest expressions the parser takes are evaluated on the stack of a test thread
+        let db = people();
+        let sum = vec!["age"; 1000].join(" + ");
+        let query = format!("SELECT {} FROM people WHERE name = 'bob'", sum);
+        assert_eq!(rows(&db, &query), vec![vec![int(25_000)]]);
+        let filter = vec!["age > 30"; 500].join(" AND ");
+        assert_eq!(
+            rows(&db, &format!("SELECT name FROM people WHERE {}", filter)).len(),
+            2
+        );
+        let nested = format!("{}age{}", "abs(".repeat(100), ")".repeat(100));
+        assert_eq!(
+            rows(&db, &format!("SELECT {} FROM people WHERE id = 1", nested)),
+            vec![vec![int(31)]]
+        );
+
+        // Deeper ones are refused, and are not a stack overflow
+        let sum = vec!["age"; 5000].join(" + ");
+        let query = format!("SELECT {} FROM people", sum);
+        let error = db.query(&query, &[]).unwrap_err();
+        assert_eq!(
+            sql_error(&error).unwrap().message,
+            "Expression tree is too large (maximum depth 1000)"
+        );
+        let nested = format!("{}1{}", "(".repeat(500), ")".repeat(500));
+        let error = db.query(&format!("SELECT {}", nested), &[]).unwrap_err();
+        assert_eq!(sql_error(&error).unwrap().message, "parser stack overflow");
+    }
+
+    #[test]
+    fn test_expressions() {
+        let db = RQLite::create_in_memory(None).unwrap();
+        assert_eq!(
+            rows(
+                &db,
+                "SELECT 7 / 2, 7 % 3, 1 / 0, 'a' || 1, -(2 + 3) * 2, NULL = NULL, NULL IS NULL, \
+                 typeof(1.0), upper('x'), substr('hello', 2, 3), coalesce(NULL, 4), CAST('12x' AS INTEGER)"
+            ),
+            vec![vec![
+                int(3),
+                int(1),
+                SqliteValue::Null,
+                text("a1"),
+                int(-10),
+                SqliteValue::Null,
+                int(1),
+                text("real"),
+                text("X"),
+                text("ell"),
+                int(4),
+                int(12)
+            ]]
+        );
+        assert_eq!(
+            rows(
+                &db,
+                "SELECT 1 < 'a', x'01' > 'z', NOT 0, 1 IN (NULL, 2), 9223372036854775807 + 1"
+            ),
+            vec![vec![
+                int(1),
+                int(1),
+                int(1),
+                SqliteValue::Null,
+                SqliteValue::Float(9223372036854775808.0)
+            ]]
+        );
+        assert_eq!(
+            rows(
+                &db,
+                "SELECT -9223372036854775808, -9223372036854775808 % -1, \
+                 - -9223372036854775808, -9223372036854775808.0"
+            ),
+            vec![vec![
+                int(i64::MIN),
+                int(0),
+                SqliteValue::Float(9223372036854775808.0),
+                SqliteValue::Float(-9223372036854775808.0)
+            ]]
+        );
+    }
+
+    #[test]
+    fn test_transactions_and_persistence() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("sql.db");
+        {
+            let db = RQLite::create(&path, None).unwrap();
+            db.execute(
+                "CREATE TABLE t (a INTEGER, b TEXT); CREATE INDEX t_b ON t (b)",
+                &[],
+            )
+            .unwrap();
+            db.execute("BEGIN; INSERT INTO t VALUES (1, 'x'); ROLLBACK", &[])
+                .unwrap();
+            db.execute("BEGIN; INSERT INTO t VALUES (2, 'y');", &[])
+                .unwrap();
+            // A failing statement in a transaction does not undo the ones before it
+            assert!(db.execute("INSERT INTO t VALUES (3, 'z', 0)", &[]).is_err());
+            db.execute("COMMIT", &[]).unwrap();
+
+            let tx = db.transaction().unwrap();
+            tx.execute("INSERT INTO t VALUES (4, 'w')", &[]).unwrap();
+            assert_eq!(
+                tx.query("SELECT count(*) FROM t", &[])
+                    .unwrap()
+                    .get(0)
+                    .unwrap()
+                    .values,
+                vec![int(2)]
+            );
+            assert!(tx.execute("COMMIT", &[]).is_err());
+            tx.commit().unwrap();
+            db.close().unwrap();
+        }
+
+        let db = RQLite::open(&path, None).unwrap();
+        assert_eq!(
+            rows(&db, "SELECT a FROM t WHERE b = 'y' OR b = 'w' ORDER BY a"),
+            vec![vec![int(2)], vec![int(4)]]
+        );
+        assert_eq!(
+            rows(&db, "SELECT a FROM t WHERE b = 'w'"),
+            vec![vec![int(4)]]
+        );
+    }
+}
@@ -0,0 +1,132 @@
+//! # Index Keys
+//!
+//! Index B-Trees order their entries by the first value of the key record only (see `extract_key_from_payload`),
+//! which is not enough for an index on several columns, or for two rows with the same value.
+//! So the key of an index created with SQL is a single blob, built so that comparing the blobs byte by byte
+//! gives the same order as comparing the values one by one:
+//!
+//! - NULL is `0x00`, and sorts first.
+//! - A number is `0x01`, then the float value with the sign bit flipped (and every other bit too if it was
+//!   negative), so bigger numbers have bigger bytes, then 8 more bytes with how far an integer is from that float,
+//!   which keeps integers above 2^53 apart. Integers and floats with the same value have the same bytes.
+//! - Text is `0x02` and blobs are `0x03`, then their bytes with every `0x00` written as `0x00 0xFF`,
+//!   and `0x00 0x00` at the end, so a value never looks like the start of a longer one.
+//!
+//! The key ends with the rowid, which makes every key unique: two rows with the same values are two entries,
+//! and deleting the entry of a row never deletes the one of another. The entries of some values are the keys
+//! that start with the encoding of those values, which is what an equality lookup seeks.
+
+use crate::utils::serialization::SqliteValue;
+
+/// Appends the order-preserving encoding of a value.
+pub(crate) fn encode_value(value: &SqliteValue, key: &mut Vec<u8>) {
+    match value {
+        SqliteValue::Null => key.push(0x00),
+        SqliteValue::Integer(i) => {
+            encode_number(*i as f64, (*i as i128 - *i as f64 as i128) as i64, key)
+        }
+        SqliteValue::Float(f) if f.is_nan() => key.push(0x00),
+        SqliteValue::Float(f) => encode_number(*f, 0, key),
+        SqliteValue::String(text) => encode_bytes(0x02, text.as_bytes(), key),
+        SqliteValue::Blob(blob) => encode_bytes(0x03, blob, key),
+    }
+}
+
+/// Builds the key of an index entry: the values, then the rowid.
+pub(crate) fn encode_key(values: &[SqliteValue], rowid: i64) -> Vec<u8> {
+    let mut key = encode_values(values);
+    key.extend_from_slice(&flip_sign(rowid).to_be_bytes());
+    key
+}
+
+/// Encodes values, which gives the prefix shared by the keys of the entries with those values.
+pub(crate) fn encode_values(values: &[SqliteValue]) -> Vec<u8> {
+    let mut key = Vec::new();
+    for value in values {
+        encode_value(value, &mut key);
+    }
+    key
+}
+
+fn encode_number(f: f64, delta: i64, key: &mut Vec<u8>) {
+    // Adding 0.0 turns -0.0 into 0.0, which must be the same key
+    let bits = (f + 0.0).to_bits();
+    let ordered = if bits >> 63 == 1 {
+        !bits
+    } else {
+        bits | 1 << 63
+    };
+    key.push(0x01);
+    key.extend_from_slice(&ordered.to_be_bytes());
+    key.extend_from_slice(&flip_sign(delta).to_be_bytes());
+}
+
+fn encode_bytes(tag: u8, bytes: &[u8], key: &mut Vec<u8>) {
+    key.push(tag);
+    for &byte in bytes {
+        key.push(byte);
+        if byte == 0x00 {
+            key.push(0xFF);
+        }
+    }
+    key.extend_from_slice(&[0x00, 0x00]);
+}
+
+/// Flips the sign bit, so the big-endian bytes of signed integers sort like the integers.
+fn flip_sign(i: i64) -> i64 {
+    i ^ i64::MIN
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_keys_sort_like_values() {
+        let values = [
+            SqliteValue::Null,
+            SqliteValue::Float(-1e300),
+            SqliteValue::Integer(i64::MIN),
+            SqliteValue::Integer(-1),
+            SqliteValue::Float(-0.5),
+            SqliteValue::Integer(0),
+            SqliteValue::Float(0.5),
+            SqliteValue::Integer(1 << 53),
+            SqliteValue::Integer((1 << 53) + 1),
+            SqliteValue::Integer(i64::MAX),
+            SqliteValue::String(String::new()),
+            SqliteValue::String("a".to_string()),
+            SqliteValue::String("a\0".to_string()),
+            SqliteValue::String("b".to_string()),
+            SqliteValue::Blob(vec![0]),
+        ];
+        for pair in values.windows(2) {
+            assert!(
+                encode_values(&pair[..1]) < encode_values(&pair[1..]),
+                "{:?}",
+                pair
+            );
+        }
+
+        // Multi-column keys compare column by column
+        let a = encode_values(&[
+            SqliteValue::String("a".to_string()),
+            SqliteValue::Integer(9),
+        ]);
+        let ab = encode_values(&[
+            SqliteValue::String("ab".to_string()),
+            SqliteValue::Integer(1),
+        ]);
+        assert!(a < ab);
+
+        assert_eq!(
+            encode_values(&[SqliteValue::Integer(3)]),
+            encode_values(&[SqliteValue::Float(3.0)])
+        );
+        let key = encode_key(&[SqliteValue::Integer(3)], -7);
+        assert!(key.starts_with(&encode_values(&[SqliteValue::Integer(3)])));
+        assert!(
+            encode_key(&[SqliteValue::Integer(3)], -7) < encode_key(&[SqliteValue::Integer(3)], 2)
+        );
+    }
+}
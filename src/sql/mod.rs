@@ -0,0 +1,176 @@
+//! # SQL Module
+//!
+//! Everything so far speaks in table IDs, rowids and records, which is fine for a storage engine but not what
+//! most people want to write. This module puts a small SQL front end on top: `RQLite::execute` and
+//! `RQLite::query` take a SQL string and parameters, and run it over the same B-Trees and records.
+//!
+//! It comes in the usual pieces:
+//!
+//! - The tokenizer (tokenizer.rs) splits the string into tokens, remembering their offsets.
+//! - The parser (parser.rs) is a recursive-descent parser that turns the tokens into the statements of ast.rs.
+//!   It knows CREATE TABLE, CREATE INDEX, DROP TABLE, DROP INDEX, INSERT, SELECT (with WHERE, GROUP BY, HAVING,
+//!   ORDER BY, LIMIT, DISTINCT and inner, left and cross joins), UPDATE, DELETE, BEGIN, COMMIT and ROLLBACK.
+//! - The executor (executor.rs) runs a statement on the catalog. There is no query planner to speak of:
+//!   a table is read by rowid when the WHERE clause gives it (`rowid = ?`, or the INTEGER PRIMARY KEY), through an
+//!   index when it gives all the columns at the start of one, and scanned otherwise. Joins are nested loops.
+//!
+//! Tables created with SQL are named tables with a `TableSchema`, so they are also there for `table_find` and
+//! the rest, and the other way around, tables created with `create_table_with_columns` are there for SQL once they
+//! have a name (see `rename_table`). Tables without columns cannot be used from SQL.
+//!
+//! Indexes created with SQL keep their entries in the key format of key.rs, and have an `IndexSchema` with their
+//! name and columns. INSERT, UPDATE and DELETE keep them up to date, and UNIQUE indexes (including the ones made for
+//! the UNIQUE and PRIMARY KEY constraints of a CREATE TABLE) reject duplicates. `table_insert` and `table_delete`
+//! know nothing about them, so a table with SQL indexes should only be changed with SQL.
+//!
+//! Values follow the SQLite rules: comparisons apply the affinity of the columns they involve, NULL makes most
+//! things NULL, and a WHERE only keeps the rows where it is true. Errors in the SQL itself, like a syntax error or
+//! an unknown column, are `SqlError`s with the line and column of the offending token.
+
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+pub(crate) mod ast;
+pub(crate) mod executor;
+pub(crate) mod key;
+pub(crate) mod parser;
+pub(crate) mod tokenizer;
+
+use crate::tree::record::Record;
+
+/// Error payload used when a SQL statement cannot be parsed, or names a table or a column that does not exist.
+/// It is carried inside an `io::Error` of kind `InvalidInput`, use `sql_error` to detect it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SqlError {
+    /// What went wrong.
+    pub message: String,
+    /// Byte offset of the offending token in the SQL string.
+    pub offset: usize,
+    /// Line of the offending token, from 1.
+    pub line: usize,
+    /// Column of the offending token in its line, in characters, from 1.
+    pub column: usize,
+}
+
+impl fmt::Display for SqlError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} at line {}, column {}",
+            self.message, self.line, self.column
+        )
+    }
+}
+
+impl Error for SqlError {}
+
+/// Builds the `io::Error` for a problem at an offset of a SQL string.
+pub(crate) fn error_at(sql: &str, offset: usize, message: impl Into<String>) -> io::Error {
+    let before = &sql[..offset.min(sql.len())];
+    let line_start = before.rfind('\n').map_or(0, |newline| newline + 1);
+    let error = SqlError {
+        message: message.into(),
+        offset,
+        line: before.matches('\n').count() + 1,
+        column: before[line_start..].chars().count() + 1,
+    };
+    io::Error::new(io::ErrorKind::InvalidInput, error)
+}
+
+/// Checks if an error was caused by the SQL of a statement.
+///
+/// # Parameters
+/// * `error` - The error to check.
+///
+/// # Returns
+/// The `SqlError` with the position of the problem, or `None` if the error is something else.
+pub fn sql_error(error: &io::Error) -> Option<&SqlError> {
+    error
+        .get_ref()
+        .and_then(|inner| inner.downcast_ref::<SqlError>())
+}
+
+/// The result of a query: the names of its columns and its rows, one `Record` per row.
+///
+/// # Example
+/// ```rust
+/// # fn main() -> std::io::Result<()> {
+/// # let db = rqlite_engine::RQLite::create_in_memory(None)?;
+/// use rqlite_engine::SqliteValue;
+///
+/// let rows = db.query("SELECT 1 + 1 AS two, 'x'", &[])?;
+/// assert_eq!(rows.columns(), ["two", "'x'"]);
+/// for row in &rows {
+///     assert_eq!(row.values[0], SqliteValue::Integer(2));
+/// }
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Rows {
+    columns: Vec<String>,
+    rows: Vec<Record>,
+}
+
+impl Rows {
+    /// Creates a result from its column names and rows.
+    pub(crate) fn new(columns: Vec<String>, rows: Vec<Record>) -> Self {
+        Rows { columns, rows }
+    }
+
+    /// Names of the columns: their alias, or the expression as written.
+    pub fn columns(&self) -> &[String] {
+        &self.columns
+    }
+
+    /// Finds the position of a column by name, ignoring ASCII case.
+    pub fn column_index(&self, name: &str) -> Option<usize> {
+        self.columns
+            .iter()
+            .position(|column| column.eq_ignore_ascii_case(name))
+    }
+
+    /// Number of rows.
+    pub fn len(&self) -> usize {
+        self.rows.len()
+    }
+
+    /// Checks if there are no rows.
+    pub fn is_empty(&self) -> bool {
+        self.rows.is_empty()
+    }
+
+    /// Gets a row by position.
+    pub fn get(&self, index: usize) -> Option<&Record> {
+        self.rows.get(index)
+    }
+
+    /// Iterates over the rows.
+    pub fn iter(&self) -> std::slice::Iter<'_, Record> {
+        self.rows.iter()
+    }
+
+    /// Takes the rows.
+    pub fn into_records(self) -> Vec<Record> {
+        self.rows
+    }
+}
+
+impl IntoIterator for Rows {
+    type Item = Record;
+    type IntoIter = std::vec::IntoIter<Record>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.rows.into_iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a Rows {
+    type Item = &'a Record;
+    type IntoIter = std::slice::Iter<'a, Record>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.rows.iter()
+    }
+}
@@ -0,0 +1,1291 @@
+//! # SQL Parser
+//!
+//! A recursive-descent parser: one method per rule of the grammar, each one consuming the tokens of its rule
+//! and calling the methods of the rules inside it. Expressions go from the operators that bind the least
+//! (`OR`) to the ones that bind the most (unary minus), with the precedence SQLite gives them:
+//!
+//! ```text
+//! OR
+//! AND
+//! NOT
+//! =  ==  !=  <>  IS  IS NOT  IN  LIKE  BETWEEN  ISNULL  NOTNULL
+//! <  <=  >  >=
+//! +  -
+//! *  /  %
+//! ||
+//! unary -  +
+//! ```
+//!
+//! Errors point at the token where the statement stopped making sense, with what was expected there.
+//!
+//! Expressions are bounded, like in SQLite: a tree can be 1000 levels high, and nesting (parentheses, function
+//! arguments, NOT and signs, the only places where the rules above call themselves) can go 100 levels deep.
+//! Everything that walks an expression after this recurses, so these bounds are what keeps a long
+//! `a + a + ...` or a pile of parentheses from overflowing the stack.
+
+use std::io;
+use std::ops::ControlFlow;
+
+use super::ast::{
+    BinaryOp, ColumnDef, Expr, Ident, InsertSource, Join, JoinKind, OrderTerm, ResultColumn,
+    Select, Statement, TableConstraint, UnaryOp,
+};
+use super::error_at;
+use super::tokenizer::{tokenize, Token, TokenKind};
+use crate::utils::serialization::SqliteValue;
+
+/// Keywords that cannot be used as unquoted names, because they would make the grammar ambiguous.
+const RESERVED: [&str; 63] = [
+    "ADD",
+    "ALL",
+    "ALTER",
+    "AND",
+    "AS",
+    "ASC",
+    "BEGIN",
+    "BETWEEN",
+    "BY",
+    "CASE",
+    "CAST",
+    "CHECK",
+    "COLLATE",
+    "COMMIT",
+    "CONSTRAINT",
+    "CREATE",
+    "CROSS",
+    "DEFAULT",
+    "DELETE",
+    "DESC",
+    "DISTINCT",
+    "DROP",
+    "ELSE",
+    "END",
+    "EXISTS",
+    "FROM",
+    "GROUP",
+    "HAVING",
+    "IN",
+    "INDEX",
+    "INNER",
+    "INSERT",
+    "INTO",
+    "IS",
+    "ISNULL",
+    "JOIN",
+    "LEFT",
+    "LIKE",
+    "LIMIT",
+    "NOT",
+    "NOTNULL",
+    "NULL",
+    "OFFSET",
+    "ON",
+    "OR",
+    "ORDER",
+    "OUTER",
+    "PRIMARY",
+    "REFERENCES",
+    "ROLLBACK",
+    "SELECT",
+    "SET",
+    "TABLE",
+    "THEN",
+    "TRANSACTION",
+    "UNION",
+    "UNIQUE",
+    "UPDATE",
+    "USING",
+    "VALUES",
+    "WHEN",
+    "WHERE",
+    "WITH",
+];
+
+/// The highest expression tree a statement can have: the `SQLITE_MAX_EXPR_DEPTH` of SQLite. The executor walks
+/// expressions recursively, so this bounds the stack it uses.
+const MAX_EXPR_DEPTH: usize = 1000;
+
+/// How deep expressions can be nested in each other. Each level of nesting goes through a dozen rules of the
+/// parser, so it gets a much lower bound than the height of the tree. The error is the one of SQLite when its
+/// parser runs out of stack.
+const MAX_NESTING: usize = 100;
+
+/// Parses a string with one or more statements separated by semicolons.
+///
+/// Parameters are numbered across the whole string: a `?` takes the number after the biggest one used so far,
+/// and `?NNN` takes `NNN`, as in SQLite. So `INSERT INTO t VALUES (?); INSERT INTO t VALUES (?)` has two.
+///
+/// # Errors
+/// Returns a `SqlError` with the position of the first token that does not fit the grammar.
+///
+/// # Returns
+/// The statements, and how many parameters they take.
+pub(crate) fn parse(sql: &str) -> io::Result<(Vec<Statement>, usize)> {
+    let mut parser = Parser {
+        sql,
+        tokens: tokenize(sql)?,
+        pos: 0,
+        parameters: 0,
+        depth: 0,
+        height: 0,
+    };
+
+    let mut statements = Vec::new();
+    loop {
+        while parser.eat_symbol(";") {}
+        if parser.peek().kind == TokenKind::Eof {
+            break;
+        }
+        statements.push(parser.statement()?);
+        if parser.peek().kind != TokenKind::Eof && !parser.peek().is_symbol(";") {
+            return Err(parser.unexpected("\";\" or the end of the statement"));
+        }
+    }
+    Ok((statements, parser.parameters))
+}
+
+/// State of the parser: the tokens and the one we are at.
+struct Parser<'a> {
+    sql: &'a str,
+    tokens: Vec<Token>,
+    pos: usize,
+    /// Number of parameters seen so far.
+    parameters: usize,
+    /// How many expressions the one being parsed is nested in, through parentheses, arguments, NOT and signs.
+    depth: usize,
+    /// Height of the tree of the expression the last expression rule returned.
+    height: usize,
+}
+
+impl Parser<'_> {
+    /// The current token. The `Eof` at the end is never consumed, so there is always one.
+    fn peek(&self) -> &Token {
+        &self.tokens[self.pos]
+    }
+
+    /// The token after the current one.
+    fn peek_next(&self) -> &Token {
+        &self.tokens[(self.pos + 1).min(self.tokens.len() - 1)]
+    }
+
+    /// Consumes the current token.
+    fn advance(&mut self) -> Token {
+        let token = self.tokens[self.pos].clone();
+        if token.kind != TokenKind::Eof {
+            self.pos += 1;
+        }
+        token
+    }
+
+    /// Offset right after the last consumed token.
+    fn last_end(&self) -> usize {
+        self.pos
+            .checked_sub(1)
+            .map_or(0, |last| self.tokens[last].end)
+    }
+
+    /// The error for an unexpected current token.
+    fn unexpected(&self, expected: &str) -> io::Error {
+        let token = self.peek();
+        error_at(
+            self.sql,
+            token.offset,
+            format!(
+                "near {}: syntax error, expected {}",
+                token.describe(self.sql),
+                expected
+            ),
+        )
+    }
+
+    /// Consumes the current token if it is the keyword.
+    fn eat_keyword(&mut self, keyword: &str) -> bool {
+        let found = self.peek().is_keyword(keyword);
+        if found {
+            self.advance();
+        }
+        found
+    }
+
+    /// Consumes the keyword, or fails.
+    fn expect_keyword(&mut self, keyword: &str) -> io::Result<()> {
+        if self.eat_keyword(keyword) {
+            Ok(())
+        } else {
+            Err(self.unexpected(keyword))
+        }
+    }
+
+    /// Consumes the current token if it is the symbol.
+    fn eat_symbol(&mut self, symbol: &str) -> bool {
+        let found = self.peek().is_symbol(symbol);
+        if found {
+            self.advance();
+        }
+        found
+    }
+
+    /// Consumes the symbol, or fails.
+    fn expect_symbol(&mut self, symbol: &str) -> io::Result<()> {
+        if self.eat_symbol(symbol) {
+            Ok(())
+        } else {
+            Err(self.unexpected(&format!("\"{}\"", symbol)))
+        }
+    }
+
+    /// Checks if the current token can be a name.
+    fn at_ident(&self) -> bool {
+        match &self.peek().kind {
+            TokenKind::Word(word) => !is_reserved(word),
+            TokenKind::Quoted(_) => true,
+            _ => false,
+        }
+    }
+
+    /// Consumes a name, or fails.
+    fn ident(&mut self, what: &str) -> io::Result<Ident> {
+        if !self.at_ident() {
+            return Err(self.unexpected(what));
+        }
+        let token = self.advance();
+        let (TokenKind::Word(name) | TokenKind::Quoted(name)) = token.kind else {
+            unreachable!("at_ident only accepts words and quoted names");
+        };
+        Ok(Ident {
+            name,
+            offset: token.offset,
+        })
+    }
+
+    /// Consumes `IF NOT EXISTS` if it is there.
+    fn if_not_exists(&mut self) -> io::Result<bool> {
+        if !self.eat_keyword("IF") {
+            return Ok(false);
+        }
+        self.expect_keyword("NOT")?;
+        self.expect_keyword("EXISTS")?;
+        Ok(true)
+    }
+
+    /// Consumes `IF EXISTS` if it is there.
+    fn if_exists(&mut self) -> io::Result<bool> {
+        if !self.eat_keyword("IF") {
+            return Ok(false);
+        }
+        self.expect_keyword("EXISTS")?;
+        Ok(true)
+    }
+
+    /// Parses a comma-separated list of at least one item.
+    fn list<T>(&mut self, mut item: impl FnMut(&mut Self) -> io::Result<T>) -> io::Result<Vec<T>> {
+        let mut items = vec![item(self)?];
+        while self.eat_symbol(",") {
+            items.push(item(self)?);
+        }
+        Ok(items)
+    }
+
+    /// Parses an expression nested in the one being parsed.
+    ///
+    /// # Errors
+    /// Returns a `SqlError` if it would be nested deeper than `MAX_NESTING`.
+    fn nested(&mut self, rule: fn(&mut Self) -> io::Result<Expr>) -> io::Result<Expr> {
+        if self.depth >= MAX_NESTING {
+            return Err(error_at(
+                self.sql,
+                self.peek().offset,
+                "parser stack overflow",
+            ));
+        }
+        self.depth += 1;
+        let expr = rule(self);
+        self.depth -= 1;
+        expr
+    }
+
+    /// Parses a comma-separated list of nested expressions.
+    ///
+    /// # Returns
+    /// The expressions, and the height of the highest one.
+    fn expr_list(&mut self) -> io::Result<(Vec<Expr>, usize)> {
+        let mut height = 0;
+        let list = self.list(|parser| {
+            let expr = parser.nested(Self::expr)?;
+            height = height.max(parser.height);
+            Ok(expr)
+        })?;
+        Ok((list, height))
+    }
+
+    /// Height of a new node over children at most `height` high.
+    ///
+    /// # Errors
+    /// Returns a `SqlError` if the node would make the tree higher than `MAX_EXPR_DEPTH`.
+    fn grow(&self, height: usize) -> io::Result<usize> {
+        if height >= MAX_EXPR_DEPTH {
+            return Err(error_at(
+                self.sql,
+                self.peek().offset,
+                format!(
+                    "Expression tree is too large (maximum depth {})",
+                    MAX_EXPR_DEPTH
+                ),
+            ));
+        }
+        Ok(height + 1)
+    }
+
+    /// Parses a statement.
+    fn statement(&mut self) -> io::Result<Statement> {
+        let token = self.peek().clone();
+        if token.is_keyword("CREATE") {
+            self.create()
+        } else if token.is_keyword("DROP") {
+            self.drop()
+        } else if token.is_keyword("INSERT") {
+            self.insert()
+        } else if token.is_keyword("SELECT") {
+            Ok(Statement::Select(Box::new(self.select()?)))
+        } else if token.is_keyword("UPDATE") {
+            self.update()
+        } else if token.is_keyword("DELETE") {
+            self.delete()
+        } else if token.is_keyword("BEGIN") {
+            self.advance();
+            for mode in ["DEFERRED", "IMMEDIATE", "EXCLUSIVE"] {
+                if self.eat_keyword(mode) {
+                    break;
+                }
+            }
+            self.eat_keyword("TRANSACTION");
+            Ok(Statement::Begin)
+        } else if token.is_keyword("COMMIT") || token.is_keyword("END") {
+            self.advance();
+            self.eat_keyword("TRANSACTION");
+            Ok(Statement::Commit)
+        } else if token.is_keyword("ROLLBACK") {
+            self.advance();
+            self.eat_keyword("TRANSACTION");
+            Ok(Statement::Rollback)
+        } else {
+            Err(self.unexpected("a statement"))
+        }
+    }
+
+    /// Parses a CREATE TABLE or a CREATE INDEX.
+    fn create(&mut self) -> io::Result<Statement> {
+        self.expect_keyword("CREATE")?;
+        let unique = self.eat_keyword("UNIQUE");
+        if unique || self.peek().is_keyword("INDEX") {
+            self.expect_keyword("INDEX")?;
+            let if_not_exists = self.if_not_exists()?;
+            let name = self.ident("an index name")?;
+            self.expect_keyword("ON")?;
+            let table = self.ident("a table name")?;
+            self.expect_symbol("(")?;
+            let columns = self.list(|parser| {
+                let column = parser.ident("a column name")?;
+                if !parser.eat_keyword("ASC") {
+                    parser.eat_keyword("DESC");
+                }
+                Ok(column)
+            })?;
+            self.expect_symbol(")")?;
+            return Ok(Statement::CreateIndex {
+                name,
+                table,
+                columns,
+                unique,
+                if_not_exists,
+            });
+        }
+
+        self.expect_keyword("TABLE")?;
+        let if_not_exists = self.if_not_exists()?;
+        let name = self.ident("a table name")?;
+        self.expect_symbol("(")?;
+        let mut columns = Vec::new();
+        let mut constraints = Vec::new();
+        loop {
+            if self.eat_keyword("CONSTRAINT") {
+                self.ident("a constraint name")?;
+            }
+            if self.eat_keyword("PRIMARY") {
+                self.expect_keyword("KEY")?;
+                constraints.push(TableConstraint::PrimaryKey(self.column_list()?));
+            } else if self.eat_keyword("UNIQUE") {
+                constraints.push(TableConstraint::Unique(self.column_list()?));
+            } else if constraints.is_empty() {
+                columns.push(self.column_def()?);
+            } else {
+                return Err(self.unexpected("a table constraint"));
+            }
+            if !self.eat_symbol(",") {
+                break;
+            }
+        }
+        self.expect_symbol(")")?;
+        Ok(Statement::CreateTable {
+            name,
+            if_not_exists,
+            columns,
+            constraints,
+        })
+    }
+
+    /// Parses a list of column names between parentheses.
+    fn column_list(&mut self) -> io::Result<Vec<Ident>> {
+        self.expect_symbol("(")?;
+        let columns = self.list(|parser| parser.ident("a column name"))?;
+        self.expect_symbol(")")?;
+        Ok(columns)
+    }
+
+    /// Parses a column of a CREATE TABLE: its name, its type and its constraints.
+    fn column_def(&mut self) -> io::Result<ColumnDef> {
+        let name = self.ident("a column name")?;
+
+        // The type is any run of names, with up to two numbers between parentheses: DOUBLE PRECISION, VARCHAR(10)
+        let start = self.peek().offset;
+        let mut declared_type = String::new();
+        if self.at_ident() {
+            while self.at_ident() {
+                self.advance();
+            }
+            if self.eat_symbol("(") {
+                self.list(|parser| {
+                    if !parser.eat_symbol("-") {
+                        parser.eat_symbol("+");
+                    }
+                    match parser.peek().kind {
+                        TokenKind::Integer(_) | TokenKind::Float(_) => {
+                            parser.advance();
+                            Ok(())
+                        }
+                        _ => Err(parser.unexpected("a number")),
+                    }
+                })?;
+                self.expect_symbol(")")?;
+            }
+            declared_type = self.sql[start..self.last_end()].to_string();
+        }
+
+        let mut column = ColumnDef {
+            name,
+            declared_type,
+            primary_key: false,
+            not_null: false,
+            unique: false,
+            default: None,
+        };
+        loop {
+            if self.eat_keyword("CONSTRAINT") {
+                self.ident("a constraint name")?;
+            }
+            if self.eat_keyword("PRIMARY") {
+                self.expect_keyword("KEY")?;
+                if !self.eat_keyword("ASC") {
+                    self.eat_keyword("DESC");
+                }
+                self.eat_keyword("AUTOINCREMENT");
+                column.primary_key = true;
+            } else if self.eat_keyword("NOT") {
+                self.expect_keyword("NULL")?;
+                column.not_null = true;
+            } else if self.eat_keyword("NULL") {
+            } else if self.eat_keyword("UNIQUE") {
+                column.unique = true;
+            } else if self.eat_keyword("DEFAULT") {
+                column.default = Some(if self.eat_symbol("(") {
+                    let expr = self.expr()?;
+                    self.expect_symbol(")")?;
+                    expr
+                } else {
+                    self.unary()?
+                });
+            } else if self.peek().kind == TokenKind::Eof
+                || self.peek().is_symbol(",")
+                || self.peek().is_symbol(")")
+            {
+                return Ok(column);
+            } else {
+                return Err(self.unexpected("a column constraint"));
+            }
+        }
+    }
+
+    /// Parses a DROP TABLE or a DROP INDEX.
+    fn drop(&mut self) -> io::Result<Statement> {
+        self.expect_keyword("DROP")?;
+        if self.eat_keyword("TABLE") {
+            let if_exists = self.if_exists()?;
+            let name = self.ident("a table name")?;
+            Ok(Statement::DropTable { name, if_exists })
+        } else if self.eat_keyword("INDEX") {
+            let if_exists = self.if_exists()?;
+            let name = self.ident("an index name")?;
+            Ok(Statement::DropIndex { name, if_exists })
+        } else {
+            Err(self.unexpected("TABLE or INDEX"))
+        }
+    }
+
+    /// Parses an INSERT.
+    fn insert(&mut self) -> io::Result<Statement> {
+        self.expect_keyword("INSERT")?;
+        self.expect_keyword("INTO")?;
+        let table = self.ident("a table name")?;
+        let columns = if self.peek().is_symbol("(") {
+            Some(self.column_list()?)
+        } else {
+            None
+        };
+
+        let source = if self.eat_keyword("VALUES") {
+            InsertSource::Values(self.list(|parser| {
+                parser.expect_symbol("(")?;
+                let values = parser.list(Self::expr)?;
+                parser.expect_symbol(")")?;
+                Ok(values)
+            })?)
+        } else if self.peek().is_keyword("SELECT") {
+            InsertSource::Select(Box::new(self.select()?))
+        } else if columns.is_none() && self.eat_keyword("DEFAULT") {
+            self.expect_keyword("VALUES")?;
+            InsertSource::DefaultValues
+        } else {
+            return Err(self.unexpected("VALUES or SELECT"));
+        };
+        Ok(Statement::Insert {
+            table,
+            columns,
+            source,
+        })
+    }
+
+    /// Parses a SELECT.
+    fn select(&mut self) -> io::Result<Select> {
+        self.expect_keyword("SELECT")?;
+        let distinct = self.eat_keyword("DISTINCT");
+        if !distinct {
+            self.eat_keyword("ALL");
+        }
+        let columns = self.list(Self::result_column)?;
+
+        let mut from = Vec::new();
+        if self.eat_keyword("FROM") {
+            from.push(self.join(JoinKind::Inner)?);
+            loop {
+                let kind = if self.eat_symbol(",") {
+                    JoinKind::Cross
+                } else if self.eat_keyword("CROSS") {
+                    self.expect_keyword("JOIN")?;
+                    JoinKind::Cross
+                } else if self.eat_keyword("LEFT") {
+                    self.eat_keyword("OUTER");
+                    self.expect_keyword("JOIN")?;
+                    JoinKind::Left
+                } else if self.eat_keyword("INNER") || self.peek().is_keyword("JOIN") {
+                    self.expect_keyword("JOIN")?;
+                    JoinKind::Inner
+                } else {
+                    break;
+                };
+                let mut join = self.join(kind)?;
+                if self.eat_keyword("ON") {
+                    join.on = Some(self.expr()?);
+                }
+                from.push(join);
+            }
+        }
+
+        let filter = if self.eat_keyword("WHERE") {
+            Some(self.expr()?)
+        } else {
+            None
+        };
+        let mut group_by = Vec::new();
+        let mut having = None;
+        if self.eat_keyword("GROUP") {
+            self.expect_keyword("BY")?;
+            group_by = self.list(Self::expr)?;
+            if self.eat_keyword("HAVING") {
+                having = Some(self.expr()?);
+            }
+        }
+        let mut order_by = Vec::new();
+        if self.eat_keyword("ORDER") {
+            self.expect_keyword("BY")?;
+            order_by = self.list(|parser| {
+                let expr = parser.expr()?;
+                let descending = if parser.eat_keyword("DESC") {
+                    true
+                } else {
+                    parser.eat_keyword("ASC");
+                    false
+                };
+                Ok(OrderTerm { expr, descending })
+            })?;
+        }
+        let (mut limit, mut offset) = (None, None);
+        if self.eat_keyword("LIMIT") {
+            limit = Some(self.expr()?);
+            if self.eat_keyword("OFFSET") {
+                offset = Some(self.expr()?);
+            } else if self.eat_symbol(",") {
+                // LIMIT offset, count
+                offset = limit.replace(self.expr()?);
+            }
+        }
+
+        Ok(Select {
+            distinct,
+            columns,
+            from,
+            filter,
+            group_by,
+            having,
+            order_by,
+            limit,
+            offset,
+        })
+    }
+
+    /// Parses a table of the FROM clause, without its ON.
+    fn join(&mut self, kind: JoinKind) -> io::Result<Join> {
+        let table = self.ident("a table name")?;
+        let alias = if self.eat_keyword("AS") || self.at_ident() {
+            Some(self.ident("an alias")?.name)
+        } else {
+            None
+        };
+        Ok(Join {
+            kind,
+            table,
+            alias,
+            on: None,
+        })
+    }
+
+    /// Parses a column of the result of a SELECT.
+    fn result_column(&mut self) -> io::Result<ResultColumn> {
+        if self.eat_symbol("*") {
+            return Ok(ResultColumn::All);
+        }
+        if self.at_ident() && self.peek_next().is_symbol(".") {
+            if let Some(star) = self.tokens.get(self.pos + 2) {
+                if star.is_symbol("*") {
+                    let table = self.ident("a table name")?;
+                    self.advance();
+                    self.advance();
+                    return Ok(ResultColumn::TableAll(table));
+                }
+            }
+        }
+
+        let start = self.peek().offset;
+        let expr = self.expr()?;
+        let text = self.sql[start..self.last_end()].to_string();
+        let alias = if self.eat_keyword("AS") {
+            match self.peek().kind.clone() {
+                TokenKind::String(alias) => {
+                    self.advance();
+                    Some(alias)
+                }
+                _ => Some(self.ident("an alias")?.name),
+            }
+        } else if self.at_ident() {
+            Some(self.ident("an alias")?.name)
+        } else {
+            None
+        };
+        Ok(ResultColumn::Expr { expr, alias, text })
+    }
+
+    /// Parses an UPDATE.
+    fn update(&mut self) -> io::Result<Statement> {
+        self.expect_keyword("UPDATE")?;
+        let table = self.ident("a table name")?;
+        self.expect_keyword("SET")?;
+        let assignments = self.list(|parser| {
+            let column = parser.ident("a column name")?;
+            parser.expect_symbol("=")?;
+            Ok((column, parser.expr()?))
+        })?;
+        let filter = if self.eat_keyword("WHERE") {
+            Some(self.expr()?)
+        } else {
+            None
+        };
+        Ok(Statement::Update {
+            table,
+            assignments,
+            filter,
+        })
+    }
+
+    /// Parses a DELETE.
+    fn delete(&mut self) -> io::Result<Statement> {
+        self.expect_keyword("DELETE")?;
+        self.expect_keyword("FROM")?;
+        let table = self.ident("a table name")?;
+        let filter = if self.eat_keyword("WHERE") {
+            Some(self.expr()?)
+        } else {
+            None
+        };
+        Ok(Statement::Delete { table, filter })
+    }
+
+    /// Parses an expression.
+    fn expr(&mut self) -> io::Result<Expr> {
+        let mut left = self.and()?;
+        let mut height = self.height;
+        while self.eat_keyword("OR") {
+            let right = self.and()?;
+            height = self.grow(height.max(self.height))?;
+            left = binary(BinaryOp::Or, left, right);
+        }
+        self.height = height;
+        Ok(left)
+    }
+
+    fn and(&mut self) -> io::Result<Expr> {
+        let mut left = self.not()?;
+        let mut height = self.height;
+        while self.eat_keyword("AND") {
+            let right = self.not()?;
+            height = self.grow(height.max(self.height))?;
+            left = binary(BinaryOp::And, left, right);
+        }
+        self.height = height;
+        Ok(left)
+    }
+
+    fn not(&mut self) -> io::Result<Expr> {
+        if self.peek().is_keyword("NOT") {
+            return self.prefix(UnaryOp::Not, Self::not);
+        }
+        self.equality()
+    }
+
+    fn equality(&mut self) -> io::Result<Expr> {
+        let mut left = self.comparison()?;
+        let mut height = self.height;
+        loop {
+            let op = match &self.peek().kind {
+                TokenKind::Symbol("=" | "==") => BinaryOp::Eq,
+                TokenKind::Symbol("!=" | "<>") => BinaryOp::NotEq,
+                _ => {
+                    self.height = height;
+                    match self.equality_keyword(left)? {
+                        ControlFlow::Continue(expr) => {
+                            left = expr;
+                            height = self.height;
+                            continue;
+                        }
+                        ControlFlow::Break(expr) => return Ok(expr),
+                    }
+                }
+            };
+            self.advance();
+            let right = self.comparison()?;
+            height = self.grow(height.max(self.height))?;
+            left = binary(op, left, right);
+        }
+    }
+
+    /// Parses an operator of the equality level that is made of keywords, like IS or NOT IN, and its right side.
+    /// It is apart from `equality` to keep the stack frame of that one, which nested expressions go through, small.
+    ///
+    /// `self.height` is the height of `left` when called, and the one of the result when it returns.
+    ///
+    /// # Returns
+    /// The expression with the operator, or `Break` with `left` if there is no such operator at the current token.
+    fn equality_keyword(&mut self, left: Expr) -> io::Result<ControlFlow<Expr, Expr>> {
+        let height = self.height;
+        if self.eat_keyword("IS") {
+            let op = if self.eat_keyword("NOT") {
+                BinaryOp::IsNot
+            } else {
+                BinaryOp::Is
+            };
+            let right = self.comparison()?;
+            self.height = self.grow(height.max(self.height))?;
+            return Ok(ControlFlow::Continue(binary(op, left, right)));
+        }
+        if self.peek().is_keyword("ISNULL") || self.peek().is_keyword("NOTNULL") {
+            let negated = self.advance().is_keyword("NOTNULL");
+            self.height = self.grow(height)?;
+            return Ok(ControlFlow::Continue(Expr::IsNull {
+                expr: Box::new(left),
+                negated,
+            }));
+        }
+
+        // The rest can all be negated: NOT IN, NOT LIKE, NOT BETWEEN, NOT NULL
+        let negated = self.peek().is_keyword("NOT")
+            && ["IN", "LIKE", "BETWEEN", "NULL"]
+                .iter()
+                .any(|keyword| self.peek_next().is_keyword(keyword));
+        if negated {
+            self.advance();
+        }
+
+        let expr = if self.eat_keyword("IN") {
+            self.expect_symbol("(")?;
+            let (list, list_height) = if self.peek().is_symbol(")") {
+                (Vec::new(), 0)
+            } else {
+                self.expr_list()?
+            };
+            self.expect_symbol(")")?;
+            self.height = self.grow(height.max(list_height))?;
+            Expr::InList {
+                expr: Box::new(left),
+                list,
+                negated,
+            }
+        } else if self.eat_keyword("LIKE") {
+            let pattern = self.comparison()?;
+            self.height = self.grow(height.max(self.height))?;
+            Expr::Like {
+                expr: Box::new(left),
+                pattern: Box::new(pattern),
+                negated,
+            }
+        } else if self.eat_keyword("BETWEEN") {
+            let low = self.comparison()?;
+            let low_height = self.height;
+            self.expect_keyword("AND")?;
+            let high = self.comparison()?;
+            self.height = self.grow(height.max(low_height).max(self.height))?;
+            Expr::Between {
+                expr: Box::new(left),
+                low: Box::new(low),
+                high: Box::new(high),
+                negated,
+            }
+        } else if negated && self.eat_keyword("NULL") {
+            self.height = self.grow(height)?;
+            Expr::IsNull {
+                expr: Box::new(left),
+                negated: true,
+            }
+        } else {
+            return Ok(ControlFlow::Break(left));
+        };
+        Ok(ControlFlow::Continue(expr))
+    }
+
+    fn comparison(&mut self) -> io::Result<Expr> {
+        let mut left = self.additive()?;
+        let mut height = self.height;
+        loop {
+            let op = match &self.peek().kind {
+                TokenKind::Symbol("<") => BinaryOp::Lt,
+                TokenKind::Symbol("<=") => BinaryOp::LtEq,
+                TokenKind::Symbol(">") => BinaryOp::Gt,
+                TokenKind::Symbol(">=") => BinaryOp::GtEq,
+                _ => break,
+            };
+            self.advance();
+            let right = self.additive()?;
+            height = self.grow(height.max(self.height))?;
+            left = binary(op, left, right);
+        }
+        self.height = height;
+        Ok(left)
+    }
+
+    fn additive(&mut self) -> io::Result<Expr> {
+        let mut left = self.multiplicative()?;
+        let mut height = self.height;
+        loop {
+            let op = match &self.peek().kind {
+                TokenKind::Symbol("+") => BinaryOp::Add,
+                TokenKind::Symbol("-") => BinaryOp::Sub,
+                _ => break,
+            };
+            self.advance();
+            let right = self.multiplicative()?;
+            height = self.grow(height.max(self.height))?;
+            left = binary(op, left, right);
+        }
+        self.height = height;
+        Ok(left)
+    }
+
+    fn multiplicative(&mut self) -> io::Result<Expr> {
+        let mut left = self.concat()?;
+        let mut height = self.height;
+        loop {
+            let op = match &self.peek().kind {
+                TokenKind::Symbol("*") => BinaryOp::Mul,
+                TokenKind::Symbol("/") => BinaryOp::Div,
+                TokenKind::Symbol("%") => BinaryOp::Rem,
+                _ => break,
+            };
+            self.advance();
+            let right = self.concat()?;
+            height = self.grow(height.max(self.height))?;
+            left = binary(op, left, right);
+        }
+        self.height = height;
+        Ok(left)
+    }
+
+    fn concat(&mut self) -> io::Result<Expr> {
+        let mut left = self.unary()?;
+        let mut height = self.height;
+        while self.eat_symbol("||") {
+            let right = self.unary()?;
+            height = self.grow(height.max(self.height))?;
+            left = binary(BinaryOp::Concat, left, right);
+        }
+        self.height = height;
+        Ok(left)
+    }
+
+    fn unary(&mut self) -> io::Result<Expr> {
+        let op = if self.peek().is_symbol("-") {
+            UnaryOp::Negate
+        } else if self.peek().is_symbol("+") {
+            UnaryOp::Plus
+        } else {
+            return self.primary();
+        };
+        // 9223372036854775808 is too big for an i64, but its negation is i64::MIN
+        let next = self.peek_next();
+        if op == UnaryOp::Negate
+            && matches!(next.kind, TokenKind::Float(_))
+            && &self.sql[next.offset..next.end] == "9223372036854775808"
+        {
+            self.advance();
+            self.advance();
+            self.height = 1;
+            return Ok(Expr::Literal(SqliteValue::Integer(i64::MIN)));
+        }
+        self.prefix(op, Self::unary)
+    }
+
+    /// Parses a prefix operator at the current token and its operand.
+    ///
+    /// This is apart from the rules that call it so the stack frame they keep while recursing stays small.
+    fn prefix(
+        &mut self,
+        op: UnaryOp,
+        operand: fn(&mut Self) -> io::Result<Expr>,
+    ) -> io::Result<Expr> {
+        self.advance();
+        let expr = self.nested(operand)?;
+        self.height = self.grow(self.height)?;
+        Ok(Expr::Unary {
+            op,
+            expr: Box::new(expr),
+        })
+    }
+
+    /// Parses a literal, a parameter, a column, a function call, a CAST or an expression between parentheses.
+    fn primary(&mut self) -> io::Result<Expr> {
+        // Everything but the parentheses, which only group, is a node of its own
+        self.height = 1;
+        let token = self.peek().clone();
+        let literal = match &token.kind {
+            TokenKind::Integer(value) => Some(SqliteValue::Integer(*value)),
+            TokenKind::Float(value) => Some(SqliteValue::Float(*value)),
+            TokenKind::String(value) => Some(SqliteValue::String(value.clone())),
+            TokenKind::Blob(value) => Some(SqliteValue::Blob(value.clone())),
+            _ if token.is_keyword("NULL") => Some(SqliteValue::Null),
+            _ => None,
+        };
+        if let Some(literal) = literal {
+            self.advance();
+            return Ok(Expr::Literal(literal));
+        }
+
+        if let TokenKind::Parameter(number) = token.kind {
+            self.advance();
+            let number = number.unwrap_or(self.parameters + 1);
+            self.parameters = self.parameters.max(number);
+            return Ok(Expr::Parameter(number - 1));
+        }
+
+        if self.eat_symbol("(") {
+            let expr = self.nested(Self::expr)?;
+            self.expect_symbol(")")?;
+            return Ok(expr);
+        }
+
+        if self.eat_keyword("CAST") {
+            return self.cast();
+        }
+
+        if !self.at_ident() {
+            return Err(self.unexpected("an expression"));
+        }
+        let name = self.ident("an expression")?;
+
+        if self.eat_symbol("(") {
+            return self.function(name);
+        }
+
+        if self.eat_symbol(".") {
+            let column = self.ident("a column name")?;
+            return Ok(Expr::Column {
+                table: Some(name),
+                name: column,
+            });
+        }
+        if matches!(token.kind, TokenKind::Word(_)) {
+            if name.name.eq_ignore_ascii_case("TRUE") {
+                return Ok(Expr::Literal(SqliteValue::Integer(1)));
+            }
+            if name.name.eq_ignore_ascii_case("FALSE") {
+                return Ok(Expr::Literal(SqliteValue::Integer(0)));
+            }
+        }
+        Ok(Expr::Column { table: None, name })
+    }
+
+    /// Parses a CAST, after its keyword.
+    fn cast(&mut self) -> io::Result<Expr> {
+        self.expect_symbol("(")?;
+        let expr = self.nested(Self::expr)?;
+        let height = self.grow(self.height)?;
+        self.expect_keyword("AS")?;
+        let start = self.peek().offset;
+        if !self.at_ident() {
+            return Err(self.unexpected("a type name"));
+        }
+        while self.at_ident() {
+            self.advance();
+        }
+        let declared_type = self.sql[start..self.last_end()].to_string();
+        self.expect_symbol(")")?;
+        self.height = height;
+        Ok(Expr::Cast {
+            expr: Box::new(expr),
+            declared_type,
+        })
+    }
+
+    /// Parses the arguments of a call to a function, after its opening parenthesis.
+    fn function(&mut self, name: Ident) -> io::Result<Expr> {
+        let mut args = Vec::new();
+        let mut args_height = 0;
+        let mut star = false;
+        let distinct = self.eat_keyword("DISTINCT");
+        if !distinct && self.eat_symbol("*") {
+            star = true;
+        } else if distinct || !self.peek().is_symbol(")") {
+            (args, args_height) = self.expr_list()?;
+        }
+        let height = self.grow(args_height)?;
+        self.expect_symbol(")")?;
+        self.height = height;
+        Ok(Expr::Function {
+            name,
+            args,
+            distinct,
+            star,
+        })
+    }
+}
+
+/// Checks if a word is a reserved keyword.
+fn is_reserved(word: &str) -> bool {
+    RESERVED
+        .iter()
+        .any(|keyword| keyword.eq_ignore_ascii_case(word))
+}
+
+/// Builds a binary expression.
+fn binary(op: BinaryOp, left: Expr, right: Expr) -> Expr {
+    Expr::Binary {
+        op,
+        left: Box::new(left),
+        right: Box::new(right),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sql::sql_error;
+
+    fn parse_one(sql: &str) -> Statement {
+        let (mut statements, _) = parse(sql).unwrap();
+        assert_eq!(statements.len(), 1);
+        statements.remove(0)
+    }
+
+    fn column(name: &str) -> Expr {
+        Expr::Column {
+            table: None,
+            name: Ident {
+                name: name.to_string(),
+                offset: 0,
+            },
+        }
+    }
+
+    /// Sets the offsets of the columns to 0, to compare expressions without caring where they were written.
+    fn strip(expr: Expr) -> Expr {
+        expr.transform(&mut |expr| {
+            Ok::<_, ()>(match expr {
+                Expr::Column { name, .. } => column(&name.name),
+                expr => expr,
+            })
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn test_parse_precedence() {
+        let Statement::Select(select) = parse_one("SELECT a + b * 2 = c OR NOT d AND e") else {
+            panic!("Expected a SELECT");
+        };
+        let ResultColumn::Expr { expr, text, .. } = &select.columns[0] else {
+            panic!("Expected an expression");
+        };
+        assert_eq!(text, "a + b * 2 = c OR NOT d AND e");
+
+        let product = binary(
+            BinaryOp::Mul,
+            column("b"),
+            Expr::Literal(SqliteValue::Integer(2)),
+        );
+        let sum = binary(BinaryOp::Add, column("a"), product);
+        let not_d = Expr::Unary {
+            op: UnaryOp::Not,
+            expr: Box::new(column("d")),
+        };
+        let expected = binary(
+            BinaryOp::Or,
+            binary(BinaryOp::Eq, sum, column("c")),
+            binary(BinaryOp::And, not_d, column("e")),
+        );
+        assert_eq!(strip(expr.clone()), expected);
+    }
+
+    #[test]
+    fn test_parse_statements() {
+        let (statements, parameters) = parse(
+            "CREATE TABLE IF NOT EXISTS users (id INTEGER PRIMARY KEY, name VARCHAR(20) NOT NULL DEFAULT 'x', UNIQUE (name));
+             CREATE UNIQUE INDEX users_name ON users (name DESC);
+             INSERT INTO users (name) VALUES (?), (?3);
+             SELECT u.name, count(*) AS n FROM users u LEFT JOIN posts p ON p.user = u.id, tags
+                WHERE u.id BETWEEN 1 AND 10 AND name NOT LIKE 'a%' GROUP BY u.name HAVING n > 1
+                ORDER BY 2 DESC LIMIT 5, 10;
+             UPDATE users SET name = upper(name) WHERE id IN (1, 2);
+             DELETE FROM users WHERE name IS NOT NULL;
+             BEGIN IMMEDIATE; COMMIT; END TRANSACTION; ROLLBACK;
+             DROP INDEX IF EXISTS users_name; DROP TABLE users",
+        )
+        .unwrap();
+        assert_eq!(statements.len(), 12);
+        assert_eq!(parameters, 3);
+
+        let Statement::CreateTable {
+            columns,
+            constraints,
+            if_not_exists,
+            ..
+        } = &statements[0]
+        else {
+            panic!("Expected a CREATE TABLE");
+        };
+        assert!(if_not_exists);
+        assert_eq!(columns[1].declared_type, "VARCHAR(20)");
+        assert!(columns[0].primary_key && columns[1].not_null);
+        assert_eq!(
+            columns[1].default,
+            Some(Expr::Literal(SqliteValue::String("x".to_string())))
+        );
+        assert_eq!(constraints.len(), 1);
+
+        let Statement::Select(select) = &statements[3] else {
+            panic!("Expected a SELECT");
+        };
+        let kinds: Vec<JoinKind> = select.from.iter().map(|join| join.kind).collect();
+        assert_eq!(
+            kinds,
+            vec![JoinKind::Inner, JoinKind::Left, JoinKind::Cross]
+        );
+        assert_eq!(select.from[0].alias.as_deref(), Some("u"));
+        assert_eq!(select.group_by.len(), 1);
+        assert!(select.having.is_some() && select.order_by[0].descending);
+        assert_eq!(select.offset, Some(Expr::Literal(SqliteValue::Integer(5))));
+        assert_eq!(select.limit, Some(Expr::Literal(SqliteValue::Integer(10))));
+        assert!(matches!(statements[8], Statement::Commit));
+    }
+
+    #[test]
+    fn test_parse_error_positions() {
+        let error = parse("SELECT a,\nFROM t").unwrap_err();
+        let position = sql_error(&error).unwrap();
+        assert_eq!((position.line, position.column), (2, 1));
+        assert!(
+            position.message.contains("near \"FROM\""),
+            "{}",
+            position.message
+        );
+
+        let error = parse("CREATE TABLE t (a INTEGER").unwrap_err();
+        let position = sql_error(&error).unwrap();
+        assert_eq!(position.offset, 25);
+        assert!(
+            position.message.contains("end of input"),
+            "{}",
+            position.message
+        );
+
+        let error = parse("SELECT 1 SELECT 2").unwrap_err();
+        assert_eq!(sql_error(&error).unwrap().column, 10);
+        let error = parse("INSERT INTO t VALUES (1, )").unwrap_err();
+        assert_eq!(sql_error(&error).unwrap().offset, 25);
+        assert!(parse("DROP VIEW v").is_err());
+    }
+
+    #[test]
+    fn test_parse_depth_limits() {
+        let message = |sql: &str| sql_error(&parse(sql).unwrap_err()).unwrap().message.clone();
+        let too_large = "Expression tree is too large (maximum depth 1000)";
+
+        // A chain of operators is as high as it has operators, plus one for the operand at the bottom
+        let chain = |terms: usize| vec!["1"; terms].join(" + ");
+        assert!(parse(&format!("SELECT {}", chain(1000))).is_ok());
+        assert_eq!(message(&format!("SELECT {}", chain(1001))), too_large);
+        assert_eq!(message(&format!("SELECT {}", chain(5000))), too_large);
+        assert_eq!(
+            message(&format!("SELECT a FROM t WHERE {} = 1", chain(1000))),
+            too_large
+        );
+        // Nesting adds the height of what is inside, but parentheses alone do not
+        assert!(parse(&format!("SELECT ({}) * 2", chain(999))).is_ok());
+        assert_eq!(
+            message(&format!("SELECT abs({}) * 2", chain(999))),
+            too_large
+        );
+
+        let nested = |open: &str, levels: usize| {
+            format!("SELECT {}1{}", open.repeat(levels), ")".repeat(levels))
+        };
+        assert!(parse(&nested("(", 100)).is_ok());
+        assert!(parse(&nested("abs(", 100)).is_ok());
+        for levels in [101, 500, 5000] {
+            assert_eq!(message(&nested("(", levels)), "parser stack overflow");
+        }
+        assert_eq!(message(&nested("abs(", 5000)), "parser stack overflow");
+        assert_eq!(
+            message(&format!("SELECT {}1", "NOT ".repeat(5000))),
+            "parser stack overflow"
+        );
+        assert_eq!(
+            message(&format!("SELECT {}1", "- ".repeat(5000))),
+            "parser stack overflow"
+        );
+    }
+}
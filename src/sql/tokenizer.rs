@@ -0,0 +1,346 @@
+//! # SQL Tokenizer
+//!
+//! Splits a SQL string into tokens, remembering where each one starts and ends so the parser can point
+//! at the exact place of a syntax error, and take the text of an expression as the name of its result column.
+//!
+//! Keywords are not told apart from identifiers here: both are `Word`s, and the parser decides what they are
+//! from where they appear, which is also how SQLite lets most keywords be used as names.
+//! Comments (`-- to the end of the line` and `/* ... */`) and whitespace are skipped.
+
+use std::io;
+
+use super::error_at;
+
+/// What a token is.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum TokenKind {
+    /// A keyword or an unquoted identifier, as written.
+    Word(String),
+    /// An identifier between double quotes, brackets or backticks.
+    Quoted(String),
+    /// An integer literal. Integers too big for an i64 are `Float`s, as in SQLite.
+    Integer(i64),
+    /// A real literal.
+    Float(f64),
+    /// A string literal between single quotes.
+    String(String),
+    /// A blob literal, `X'CAFE'`.
+    Blob(Vec<u8>),
+    /// A parameter: `?` is `None`, `?NNN` has its number.
+    Parameter(Option<usize>),
+    /// An operator or a punctuation sign.
+    Symbol(&'static str),
+    /// End of the input.
+    Eof,
+}
+
+/// A token and its place in the SQL string.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Token {
+    pub kind: TokenKind,
+    /// Byte offset of the first character of the token.
+    pub offset: usize,
+    /// Byte offset right after the last character of the token.
+    pub end: usize,
+}
+
+impl Token {
+    /// Checks if the token is the given keyword, ignoring ASCII case.
+    pub fn is_keyword(&self, keyword: &str) -> bool {
+        matches!(&self.kind, TokenKind::Word(word) if word.eq_ignore_ascii_case(keyword))
+    }
+
+    /// Checks if the token is the given symbol.
+    pub fn is_symbol(&self, symbol: &str) -> bool {
+        matches!(self.kind, TokenKind::Symbol(s) if s == symbol)
+    }
+
+    /// Describes the token for error messages, like SQLite does: `near "FROM"`.
+    pub fn describe(&self, sql: &str) -> String {
+        match self.kind {
+            TokenKind::Eof => "end of input".to_string(),
+            _ => format!("\"{}\"", &sql[self.offset..self.end]),
+        }
+    }
+}
+
+/// Symbols, the longest ones first so `<=` is not read as `<` followed by `=`.
+const SYMBOLS: [&str; 19] = [
+    "<>", "<=", ">=", "==", "!=", "||", "(", ")", ",", ";", ".", "*", "+", "-", "/", "%", "=", "<",
+    ">",
+];
+
+/// Splits a SQL string into tokens. The last token is always `Eof`.
+///
+/// # Errors
+/// Returns a `SqlError` pointing at the problem if a string, a quoted identifier or a comment is not closed,
+/// a literal is malformed or a character is not valid SQL.
+pub(crate) fn tokenize(sql: &str) -> io::Result<Vec<Token>> {
+    let bytes = sql.as_bytes();
+    let mut tokens = Vec::new();
+    let mut pos = 0;
+
+    while pos < bytes.len() {
+        let start = pos;
+        let c = bytes[pos];
+
+        if c.is_ascii_whitespace() {
+            pos += 1;
+            continue;
+        }
+        if sql[pos..].starts_with("--") {
+            pos = sql[pos..]
+                .find('\n')
+                .map_or(bytes.len(), |end| pos + end + 1);
+            continue;
+        }
+        if sql[pos..].starts_with("/*") {
+            pos = match sql[pos + 2..].find("*/") {
+                Some(end) => pos + 2 + end + 2,
+                None => return Err(error_at(sql, start, "unterminated comment")),
+            };
+            continue;
+        }
+
+        let kind = if (c == b'x' || c == b'X') && bytes.get(pos + 1) == Some(&b'\'') {
+            let (text, end) = quoted(sql, pos + 1, '\'')
+                .ok_or_else(|| error_at(sql, start, "unterminated blob literal"))?;
+            pos = end;
+            TokenKind::Blob(
+                parse_hex(&text).ok_or_else(|| error_at(sql, start, "malformed blob literal"))?,
+            )
+        } else if c.is_ascii_alphabetic() || c == b'_' || c >= 0x80 {
+            while pos < bytes.len()
+                && (bytes[pos].is_ascii_alphanumeric()
+                    || bytes[pos] == b'_'
+                    || bytes[pos] == b'$'
+                    || bytes[pos] >= 0x80)
+            {
+                pos += 1;
+            }
+            TokenKind::Word(sql[start..pos].to_string())
+        } else if c.is_ascii_digit()
+            || (c == b'.' && bytes.get(pos + 1).is_some_and(u8::is_ascii_digit))
+        {
+            let (kind, end) = number(sql, pos)?;
+            pos = end;
+            kind
+        } else if c == b'\'' {
+            let (text, end) = quoted(sql, pos, '\'')
+                .ok_or_else(|| error_at(sql, start, "unterminated string"))?;
+            pos = end;
+            TokenKind::String(text)
+        } else if c == b'"' || c == b'`' || c == b'[' {
+            let close = match c {
+                b'[' => ']',
+                other => other as char,
+            };
+            let (text, end) = quoted(sql, pos, close)
+                .ok_or_else(|| error_at(sql, start, "unterminated identifier"))?;
+            pos = end;
+            TokenKind::Quoted(text)
+        } else if c == b'?' {
+            pos += 1;
+            while pos < bytes.len() && bytes[pos].is_ascii_digit() {
+                pos += 1;
+            }
+            if pos == start + 1 {
+                TokenKind::Parameter(None)
+            } else {
+                match sql[start + 1..pos].parse::<usize>() {
+                    Ok(number) if number > 0 => TokenKind::Parameter(Some(number)),
+                    _ => return Err(error_at(sql, start, "invalid parameter number")),
+                }
+            }
+        } else if let Some(symbol) = SYMBOLS
+            .iter()
+            .find(|symbol| sql[pos..].starts_with(**symbol))
+        {
+            pos += symbol.len();
+            TokenKind::Symbol(symbol)
+        } else {
+            let c = sql[pos..].chars().next().unwrap_or_default();
+            return Err(error_at(
+                sql,
+                start,
+                format!("unrecognized token: \"{}\"", c),
+            ));
+        };
+
+        tokens.push(Token {
+            kind,
+            offset: start,
+            end: pos,
+        });
+    }
+
+    tokens.push(Token {
+        kind: TokenKind::Eof,
+        offset: bytes.len(),
+        end: bytes.len(),
+    });
+    Ok(tokens)
+}
+
+/// Reads the text between a quote at `start` and its closing quote, where two closing quotes in a row
+/// stand for one (except for brackets, which cannot be escaped).
+///
+/// # Returns
+/// The text and the offset right after the closing quote, or `None` if the quote is not closed.
+fn quoted(sql: &str, start: usize, close: char) -> Option<(String, usize)> {
+    let mut text = String::new();
+    let mut chars = sql[start + 1..].char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        if c == close {
+            if close != ']' && chars.peek().is_some_and(|(_, next)| *next == close) {
+                chars.next();
+                text.push(c);
+                continue;
+            }
+            return Some((text, start + 1 + i + 1));
+        }
+        text.push(c);
+    }
+    None
+}
+
+/// Reads a numeric literal: a decimal integer, a hexadecimal integer (`0x1F`) or a real (`1.5`, `.5`, `1e10`).
+fn number(sql: &str, start: usize) -> io::Result<(TokenKind, usize)> {
+    let bytes = sql.as_bytes();
+    let mut pos = start;
+
+    if sql[start..].starts_with("0x") || sql[start..].starts_with("0X") {
+        pos += 2;
+        while pos < bytes.len() && bytes[pos].is_ascii_hexdigit() {
+            pos += 1;
+        }
+        // Hexadecimal literals are 64-bit two's complement numbers, 0xFFFFFFFFFFFFFFFF is -1
+        return match u64::from_str_radix(&sql[start + 2..pos], 16) {
+            Ok(value) if !is_word_byte(bytes.get(pos)) => {
+                Ok((TokenKind::Integer(value as i64), pos))
+            }
+            _ => Err(error_at(sql, start, "malformed hexadecimal literal")),
+        };
+    }
+
+    let mut real = false;
+    while pos < bytes.len() && bytes[pos].is_ascii_digit() {
+        pos += 1;
+    }
+    if bytes.get(pos) == Some(&b'.') {
+        real = true;
+        pos += 1;
+        while pos < bytes.len() && bytes[pos].is_ascii_digit() {
+            pos += 1;
+        }
+    }
+    if matches!(bytes.get(pos), Some(b'e' | b'E')) {
+        real = true;
+        pos += 1;
+        if matches!(bytes.get(pos), Some(b'+' | b'-')) {
+            pos += 1;
+        }
+        let digits = pos;
+        while pos < bytes.len() && bytes[pos].is_ascii_digit() {
+            pos += 1;
+        }
+        if pos == digits {
+            return Err(error_at(sql, start, "malformed real literal"));
+        }
+    }
+    if is_word_byte(bytes.get(pos)) {
+        return Err(error_at(sql, start, "malformed number"));
+    }
+
+    let text = &sql[start..pos];
+    let kind = match text.parse::<i64>() {
+        Ok(value) if !real => TokenKind::Integer(value),
+        _ => TokenKind::Float(
+            text.parse::<f64>()
+                .map_err(|_| error_at(sql, start, "malformed number"))?,
+        ),
+    };
+    Ok((kind, pos))
+}
+
+/// Checks if a byte can be part of a word, which a number cannot be followed by.
+fn is_word_byte(byte: Option<&u8>) -> bool {
+    byte.is_some_and(|b| b.is_ascii_alphanumeric() || *b == b'_')
+}
+
+/// Decodes the hexadecimal digits of a blob literal, which must come in pairs.
+fn parse_hex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(text.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sql::sql_error;
+
+    fn kinds(sql: &str) -> Vec<TokenKind> {
+        tokenize(sql)
+            .unwrap()
+            .into_iter()
+            .map(|token| token.kind)
+            .collect()
+    }
+
+    #[test]
+    fn test_tokenize_literals_and_symbols() {
+        assert_eq!(
+            kinds("SELECT a<=1.5, 'it''s', x'CAFE', ?, ?3 -- comment\n FROM \"my table\" /* c */;"),
+            vec![
+                TokenKind::Word("SELECT".to_string()),
+                TokenKind::Word("a".to_string()),
+                TokenKind::Symbol("<="),
+                TokenKind::Float(1.5),
+                TokenKind::Symbol(","),
+                TokenKind::String("it's".to_string()),
+                TokenKind::Symbol(","),
+                TokenKind::Blob(vec![0xCA, 0xFE]),
+                TokenKind::Symbol(","),
+                TokenKind::Parameter(None),
+                TokenKind::Symbol(","),
+                TokenKind::Parameter(Some(3)),
+                TokenKind::Word("FROM".to_string()),
+                TokenKind::Quoted("my table".to_string()),
+                TokenKind::Symbol(";"),
+                TokenKind::Eof,
+            ]
+        );
+        assert_eq!(
+            kinds("0x10 9223372036854775808 1e3 .5"),
+            vec![
+                TokenKind::Integer(16),
+                TokenKind::Float(9223372036854775808.0),
+                TokenKind::Float(1000.0),
+                TokenKind::Float(0.5),
+                TokenKind::Eof,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_tokenize_error_positions() {
+        let error = tokenize("SELECT 1,\n  'open").unwrap_err();
+        let position = sql_error(&error).unwrap();
+        assert_eq!(
+            (position.offset, position.line, position.column),
+            (12, 2, 3)
+        );
+        assert!(position.message.contains("unterminated string"));
+
+        let error = tokenize("SELECT 12abc").unwrap_err();
+        assert_eq!(sql_error(&error).unwrap().column, 8);
+        let error = tokenize("SELECT # FROM t").unwrap_err();
+        assert_eq!(sql_error(&error).unwrap().offset, 7);
+        assert!(tokenize("SELECT x'ABC'").is_err());
+    }
+}
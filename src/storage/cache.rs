@@ -53,6 +53,10 @@ pub struct BufferFrame {
     last_accessed: std::time::Instant,
     /// Track if the page is being written to prevent concurrent access issues
     is_being_written: bool,
+    /// The page was read ahead of time (see `Pager::read_ahead`), and nobody asked for it yet
+    prefetched: bool,
+    /// When the page went from clean to dirty, to know how long it has been waiting to be written
+    dirtied_at: Option<std::time::Instant>,
 }
 
 impl BufferFrame {
@@ -63,6 +67,8 @@ impl BufferFrame {
             is_dirty: false,
             last_accessed: std::time::Instant::now(),
             is_being_written: false,
+            prefetched: false,
+            dirtied_at: None,
         }
     }
 
@@ -82,10 +88,12 @@ impl BufferFrame {
     pub fn mark_dirty(&mut self) {
         self.is_dirty = true;
         self.last_accessed = std::time::Instant::now();
+        self.dirtied_at.get_or_insert(self.last_accessed);
     }
 
     pub fn reset_dirty(&mut self) {
         self.is_dirty = false;
+        self.dirtied_at = None;
     }
 
     pub fn is_pinned(&self) -> bool {
@@ -113,6 +121,10 @@ impl BufferFrame {
         self.last_accessed
     }
 
+    pub fn dirtied_at(&self) -> Option<std::time::Instant> {
+        self.dirtied_at
+    }
+
     pub fn set_being_written(&mut self, writing: bool) {
         self.is_being_written = writing;
     }
@@ -132,7 +144,7 @@ pub struct BufferPool {
     stats: BufferPoolStats, // Basic statistics just for tracking
 }
 
-#[derive(Debug, Default)]
+#[derive(Debug, Default, Clone)]
 pub struct BufferPoolStats {
     pub cache_hits: u64,
     pub cache_misses: u64,
@@ -140,6 +152,14 @@ pub struct BufferPoolStats {
     pub pages_written: u64,
     pub pin_operations: u64,
     pub unpin_operations: u64,
+    /// Pages read ahead of a sequential scan
+    pub pages_prefetched: u64,
+    /// Prefetched pages that were asked for before being evicted
+    pub prefetch_hits: u64,
+    /// Dirty pages that had to be written back to make room for another page
+    pub dirty_evictions: u64,
+    /// Dirty pages written back by the background writer (see `Pager::set_background_writer`)
+    pub pages_written_back: u64,
 }
 
 impl BufferPoolStats {
@@ -192,12 +212,10 @@ impl BufferPool {
             let evicted = self.evict_page();
             
             match evicted {
-                Some((evicted_num, evicted_page)) => {
-                    // Get dirty status before the page is gone
-                    let was_dirty = self.frames.get(&evicted_num)
-                        .map(|f| f.is_dirty())
-                        .unwrap_or(false);
-                    
+                Some((evicted_num, evicted_page, was_dirty)) => {
+                    if was_dirty {
+                        self.stats.dirty_evictions += 1;
+                    }
                     // Now add the new page
                     let mut frame = BufferFrame::new(page);
                     if pin {
@@ -213,9 +231,12 @@ impl BufferPool {
                     // I am not sure if this could be handled by the Pager to avoid logic duplication and keep this more SOLID.
                     // However I have been running into issues when releasing the evicted page to the Pager previousle, although this should be already fixed with the new implementation with guards and callbacks.
                     // Anyway, I do not think this can cause any problem.
+                    // Write through a slice cursor: writing to the `Vec` directly would append after the zeroed bytes.
                     let size = evicted_page.page_size();
                     let mut buffer = vec![0u8; size as usize];
-                    evicted_page.write_to(&mut buffer).expect("Failed to serialize page to buffer");
+                    evicted_page
+                        .write_to(&mut std::io::Cursor::new(&mut buffer[..]))
+                        .expect("Failed to serialize page to buffer");
                     
                     
                     return AddPageResult::Evicted(evicted_num, buffer, was_dirty);
@@ -249,13 +270,35 @@ impl BufferPool {
     /// # Returns
     /// A boolean indicating whether the page is in the buffer pool.
     pub fn contains_page(&mut self, page_number: u32) -> bool {
-        let contains = self.frames.contains_key(&page_number);
-        if contains {
-            self.stats.cache_hits += 1;
+        match self.frames.get_mut(&page_number) {
+            Some(frame) => {
+                self.stats.cache_hits += 1;
+                if frame.prefetched {
+                    frame.prefetched = false;
+                    self.stats.prefetch_hits += 1;
+                }
+                true
+            }
+            None => {
+                self.stats.cache_misses += 1;
+                false
+            }
+        }
+    }
+
+    /// Marks a page that was just added as read ahead of time, so we can tell if the read-ahead paid off.
+    /// # Parameters
+    /// * `page_number`: The unique identifier for the page.
+    /// # Returns
+    /// A boolean indicating whether the page is in the buffer pool.
+    pub fn mark_prefetched(&mut self, page_number: u32) -> bool {
+        if let Some(frame) = self.frames.get_mut(&page_number) {
+            frame.prefetched = true;
+            self.stats.pages_prefetched += 1;
+            true
         } else {
-            self.stats.cache_misses += 1;
+            false
         }
-        contains
     }
 
     /// I recently moved this logic from th Pager to here to keep the validation in memory.
@@ -393,7 +436,8 @@ impl BufferPool {
     /// This replaces the original eviction logic with a more sophisticated approach based on LRU-K policy.
     /// Finds the candidates for eviction based on their last accessed time and pin status.
     /// If no candidates are found, it means the buffer is full and therefore returns `None`.
-    fn evict_page(&mut self) -> Option<(u32, Page)> {
+    /// The returned flag tells whether the evicted frame was dirty, as the frame itself is gone by then.
+    fn evict_page(&mut self) -> Option<(u32, Page, bool)> {
     
         // First, try to find the least recently used unpinned page
         let mut candidates: Vec<_> = self.lru_list
@@ -432,7 +476,7 @@ impl BufferPool {
             if let Some(frame) = self.frames.remove(&page_number) {
                 self.stats.pages_evicted += 1;
                 
-                return Some((page_number, frame.page.clone()));
+                return Some((page_number, frame.page.clone(), frame.is_dirty()));
             }
         }
         None
@@ -482,6 +526,34 @@ impl BufferPool {
             .collect()
     }
 
+    /// Get the dirty pages nobody is using or writing, least recently used first,
+    /// with the time each of them became dirty. These can be written back without getting in anybody's way.
+    /// # Returns
+    /// A vector of page numbers and the time they became dirty.
+    pub fn write_back_candidates(&self) -> Vec<(u32, std::time::Instant)> {
+        let mut candidates = self
+            .frames
+            .iter()
+            .filter(|(_, frame)| {
+                frame.is_dirty() && !frame.is_pinned() && !frame.is_being_written()
+            })
+            .map(|(page_number, frame)| {
+                let dirtied_at = frame.dirtied_at().unwrap_or(frame.last_accessed());
+                (*page_number, frame.last_accessed(), dirtied_at)
+            })
+            .collect::<Vec<_>>();
+        candidates.sort_by_key(|(_, last_accessed, _)| *last_accessed);
+        candidates
+            .into_iter()
+            .map(|(page_number, _, dirtied_at)| (page_number, dirtied_at))
+            .collect()
+    }
+
+    /// Count pages the background writer wrote back, for the statistics.
+    pub fn record_write_back(&mut self, pages: usize) {
+        self.stats.pages_written_back += pages as u64;
+    }
+
     /// Method used to remove a page from the buffer pool.
     /// This method removes a page by its number, ensuring that it is not pinned or being written to.
     /// # Parameters
@@ -533,14 +605,14 @@ impl BufferPool {
     pub fn is_dirty(&self, page_number: u32) -> bool {
         self.frames
             .get(&page_number)
-            .map_or(false, |frame| frame.is_dirty())
+            .is_some_and(|frame| frame.is_dirty())
     }
 
     /// Utility method to check if a page is pinned
     pub fn is_pinned(&self, page_number: u32) -> bool {
         self.frames
             .get(&page_number)
-            .map_or(false, |frame| frame.is_pinned())
+            .is_some_and(|frame| frame.is_pinned())
     }
 
     /// Utility method to get the pin count of a page
@@ -672,7 +744,26 @@ impl BufferPool {
         count
     }
 
-    /// Additional methods for RAII guard support
+    /// Drops every unpinned page that is not dirty.
+    /// Used when the database file was changed by another process and the cached copies cannot be trusted anymore.
+    /// # Returns
+    /// The number of pages dropped.
+    pub fn discard_clean_pages(&mut self) -> usize {
+        let clean_pages: Vec<u32> = self.frames
+            .iter()
+            .filter(|(_, frame)| !frame.is_pinned() && !frame.is_dirty())
+            .map(|(page_number, _)| *page_number)
+            .collect();
+
+        for page_number in &clean_pages {
+            self.frames.remove(page_number);
+        }
+        self.lru_list.retain(|p| self.frames.contains_key(p));
+
+        clean_pages.len()
+    }
+
+    // Additional methods for RAII guard support
 
     /// Simpler contains check without statistics. More efficient for quick checks. To be used in actual Pager code.
     pub fn contains_page_simple(&self, page_number: u32) -> bool {
@@ -774,6 +865,55 @@ mod buffer_pool_tests {
         assert_eq!(pool.get_stats().unpin_operations, 1);
     }
 
+    #[test]
+    fn test_prefetch_stats() {
+        let mut pool = BufferPool::new(3);
+        pool.add_page(1, create_test_page(1), false);
+        pool.add_page(2, create_test_page(2), false);
+        assert!(pool.mark_prefetched(2));
+        assert!(!pool.mark_prefetched(3));
+        assert_eq!(pool.get_stats().pages_prefetched, 1);
+
+        // Only the first hit on a prefetched page counts as a prefetch hit
+        assert!(pool.contains_page(1));
+        assert!(pool.contains_page(2));
+        assert!(pool.contains_page(2));
+        assert_eq!(pool.get_stats().cache_hits, 3);
+        assert_eq!(pool.get_stats().prefetch_hits, 1);
+    }
+
+    #[test]
+    fn test_write_back_candidates() {
+        let mut pool = BufferPool::new(3);
+        pool.add_page(1, create_test_page(1), false);
+        pool.add_page(2, create_test_page(2), false);
+        pool.add_page(3, create_test_page(3), true);
+        pool.mark_dirty(2);
+        std::thread::sleep(std::time::Duration::from_millis(5));
+        pool.mark_dirty(1);
+        pool.mark_dirty(3);
+
+        // Pinned pages are still in use, and the least recently used page comes first
+        let candidates = pool.write_back_candidates();
+        let page_numbers = candidates.iter().map(|(n, _)| *n).collect::<Vec<_>>();
+        assert_eq!(page_numbers, vec![2, 1]);
+        assert!(candidates[0].1 < candidates[1].1);
+
+        // Touching a dirty page again does not make it younger
+        let dirtied_at = candidates[0].1;
+        pool.mark_dirty(2);
+        let candidates = pool.write_back_candidates();
+        assert_eq!(candidates[1], (2, dirtied_at));
+
+        pool.mark_clean(2);
+        assert_eq!(pool.write_back_candidates().len(), 1);
+
+        // Evicting a dirty page counts as a dirty eviction
+        pool.unpin_page(3);
+        pool.add_page(4, create_test_page(4), false);
+        assert_eq!(pool.get_stats().dirty_evictions, 1);
+    }
+
     #[test]
     fn test_smart_eviction() {
         let mut pool = BufferPool::new(2);
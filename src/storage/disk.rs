@@ -3,11 +3,23 @@
 //! This module implements the required functionality to manage the low-level operations
 //! of a database file. It provides the necessary methods to read and write pages,
 //! manage the database header, and allocate new pages as needed.
-use std::fs::{File, OpenOptions};
-use std::io::{self, Read, Seek, SeekFrom, Write};
+//! The file itself is reached through a `VfsFile` (see vfs.rs), by default a regular file of the OS.
+//!
+//! Optionally, the first `mmap_size` bytes of the file are memory mapped (see mmap.rs) and pages are read from the map
+//! instead of with a syscall each. The map only serves reads while we hold a lock on the file: without one, another process
+//! could truncate the file under us, and touching a mapped page past the end of the file is a SIGBUS, not an error.
+//!
+//! A `PageReader` reads pages without borrowing the `DiskManager`, so the pager can let other threads use the cache
+//! while it waits for the disk. Those reads always go through the file, and the `generation` tells the pager whether
+//! something (a write, a truncation, a lock change) happened in the meantime that could have made them stale.
+use std::io;
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
-use crate::header::{Header, SQLITE_HEADER_STRING};
+use super::lock::{FileLock, LockLevel};
+use super::mmap::MemoryMap;
+use super::vfs::{OsVfs, Vfs, VfsFile};
+use crate::header::{Header, HEADER_SIZE, SQLITE_HEADER_STRING};
 
 /// The `DiskManager` struct is the main component for managing the database file on disk.
 /// It provides methods to read and write pages, manage the database header, and allocate new pages as needed.
@@ -16,10 +28,40 @@ pub struct DiskManager {
     /// Path to the database file.
     pub path: PathBuf,
     /// Handler for the database file.
-    file: File,
+    file: Arc<dyn VfsFile>,
     /// Page size in bytes. Page size is fixed for the entire database.
     /// It is set when the database is created and cannot be changed later.
     page_size: u32,
+    /// Multi-process lock held on the file (see the lock.rs module).
+    lock: FileLock,
+    /// Most bytes of the file to memory map for reading. Zero disables the map.
+    mmap_size: u64,
+    /// Read-only map of the first `min(mmap_size, file size)` bytes of the file, if any.
+    map: Option<MemoryMap>,
+    /// Bumped by everything that can make a page read earlier out of date (see `generation`).
+    generation: u64,
+}
+
+/// Reads pages of the database file on its own, without holding the `DiskManager` (see `DiskManager::page_reader`).
+pub struct PageReader {
+    file: Arc<dyn VfsFile>,
+    page_size: u32,
+}
+
+impl PageReader {
+    /// Reads a page from the file, never from the map.
+    ///
+    /// # Parameters
+    /// * `page_number` - The page number to read (starting from 1).
+    /// * `buffer` - Where to store the page. Its size must be the page size.
+    ///
+    /// # Errors
+    /// Returns an error if the page number or the buffer size are invalid, or if the page cannot be read.
+    pub fn read_page(&self, page_number: u32, buffer: &mut [u8]) -> io::Result<()> {
+        check_page_buffer(self.page_size, page_number, buffer)?;
+        self.file
+            .read_at(buffer, page_offset(self.page_size, page_number))
+    }
 }
 
 impl DiskManager {
@@ -38,12 +80,28 @@ impl DiskManager {
     /// A new instance of DiskManager connected to the specified database file.
     ///
     pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
-        let file = OpenOptions::new().read(true).write(true).open(&path)?;
+        Self::open_with_vfs(&OsVfs, path)
+    }
+
+    /// Opens an existing database file of the given VFS. Same as `open`, which uses the file system of the OS.
+    ///
+    /// # Parameters
+    /// * `vfs` - The file system the database lives in.
+    /// * `path` - Path to the database file.
+    ///
+    /// # Errors
+    /// Returns an error if the file cannot be opened or if the header is invalid.
+    pub fn open_with_vfs<P: AsRef<Path>>(vfs: &dyn Vfs, path: P) -> io::Result<Self> {
+        let file = vfs.open(path.as_ref(), false)?;
 
         let mut disk_manager = DiskManager {
             path: path.as_ref().to_path_buf(),
             file,
             page_size: 0, // Initialized as 0, will be set when reading the header
+            lock: FileLock::new(),
+            mmap_size: 0,
+            map: None,
+            generation: 0,
         };
 
         // Read the header to get the page size
@@ -69,18 +127,34 @@ impl DiskManager {
     /// A new instance of DiskManager connected to the newly created database file.
     ///
     pub fn create<P: AsRef<Path>>(path: P, page_size: u32) -> io::Result<Self> {
-        // Create the file with write and read permissions
-        let file = OpenOptions::new()
-            .read(true)
-            .write(true)
-            .create(true)
-            .truncate(true)
-            .open(&path)?;
+        Self::create_with_vfs(&OsVfs, path, page_size)
+    }
+
+    /// Creates a new database file in the given VFS. Same as `create`, which uses the file system of the OS.
+    ///
+    /// # Parameters
+    /// * `vfs` - The file system the database lives in.
+    /// * `path` - Path to the database file.
+    /// * `page_size` - Size of each page in bytes.
+    ///
+    /// # Errors
+    /// Returns an error if the file cannot be created or if the page size is invalid.
+    pub fn create_with_vfs<P: AsRef<Path>>(
+        vfs: &dyn Vfs,
+        path: P,
+        page_size: u32,
+    ) -> io::Result<Self> {
+        // Create the file, or empty it if it already exists
+        let file = vfs.open(path.as_ref(), true)?;
 
         let mut disk_manager = DiskManager {
             path: path.as_ref().to_path_buf(),
             file,
             page_size,
+            lock: FileLock::new(),
+            mmap_size: 0,
+            map: None,
+            generation: 0,
         };
 
         // Create the header with the specified page size
@@ -98,33 +172,26 @@ impl DiskManager {
 
     /// Reads the header of the database file. Will fail if the database file is corrupted or the header is invalid (aka we do not have the magic string).
     ///
-    /// Parameters
-    /// * `&mut self` - A mutable reference to the DiskManager instance.
-    ///     
     /// # Errors
     /// Returns an error if the file cannot be read or if the header is invalid.
     ///
     /// # Returns
     /// A Header instance containing the database header information.
     ///
-    pub fn read_header(&mut self) -> io::Result<Header> {
-        self.file.seek(SeekFrom::Start(0))?;
+    pub fn read_header(&self) -> io::Result<Header> {
+        let mut buffer = [0u8; HEADER_SIZE];
+        self.file.read_at(&mut buffer, 0)?;
 
         // Verify the signature
-        // Read the first 16 bytes to check the signature
-        let mut signature = [0u8; 16];
-        self.file.read_exact(&mut signature)?;
-
-        if &signature != SQLITE_HEADER_STRING {
+        // The first 16 bytes must be the magic string
+        if &buffer[..16] != SQLITE_HEADER_STRING {
             return Err(io::Error::new(
                 io::ErrorKind::InvalidData,
                 "Invalid signature: expected SQLITE_HEADER_STRING",
             ));
         }
 
-        // Back to the beginning of the file to read the header
-        self.file.seek(SeekFrom::Start(0))?;
-        Header::read_from(&mut self.file)
+        Header::read_from(&mut &buffer[..])
     }
 
     /// Writes the header to the database file.
@@ -137,8 +204,9 @@ impl DiskManager {
     /// Returns an error if the file cannot be written to or if the header is invalid.
     ///
     pub fn write_header(&mut self, header: &Header) -> io::Result<()> {
-        self.file.seek(SeekFrom::Start(0))?;
-        header.write_to(&mut self.file)
+        let mut buffer = Vec::with_capacity(HEADER_SIZE);
+        header.write_to(&mut buffer)?;
+        self.file.write_at(&buffer, 0)
     }
 
     ///  Reads and entire page from the database file.
@@ -158,34 +226,58 @@ impl DiskManager {
     /// The page number is 1-based, meaning the first page is page 1.
     /// The buffer size must match the page size of the database.
     /// We cannot read blocks from the file that are not aligned with the page size. This is why this should be the only accessor to the database file.
-    pub fn read_page(&mut self, page_number: u32, buffer: &mut [u8]) -> io::Result<()> {
-        if page_number == 0 {
+    /// Reads are positional (`pread`) or come from the map, there is no cursor to move, so any number of them can run at the same time.
+    pub fn read_page(&self, page_number: u32, buffer: &mut [u8]) -> io::Result<()> {
+        check_page_buffer(self.page_size, page_number, buffer)?;
+        let offset = self.page_offset(page_number);
+
+        if self.lock.level() != LockLevel::Unlocked {
+            if let Some(map) = &self.map {
+                if map.read_at(buffer, offset) {
+                    return Ok(());
+                }
+            }
+        }
+
+        self.file.read_at(buffer, offset)
+    }
+
+    /// Reads a run of contiguous pages with a single read, for read-ahead.
+    ///
+    /// # Parameters
+    /// * `first_page` - The first page to read (starting from 1).
+    /// * `buffer` - Where to store the pages. Its size must be a (non-zero) multiple of the page size.
+    ///
+    /// # Errors
+    /// Returns an error if the buffer size or the page number are invalid, or if some of the pages do not exist.
+    pub fn read_pages(&self, first_page: u32, buffer: &mut [u8]) -> io::Result<()> {
+        if first_page == 0 {
             return Err(io::Error::new(
                 io::ErrorKind::InvalidInput,
                 "Invalid page number: 0 (pages start from 1)",
             ));
         }
-
-        // Compute the page offset
-        // The offset is calculated as (page_number - 1) * page_size
-        // This is a common way to calculate the offset for fixed-size pages in a file. As all pages are the same size, we can use this formula.
-        let offset = self.page_offset(page_number);
-        // println!("Reading page {} at offset {}", page_number, offset);
-
-        self.file.seek(SeekFrom::Start(offset))?;
-
-        if buffer.len() != self.page_size as usize {
+        if buffer.is_empty() || !buffer.len().is_multiple_of(self.page_size as usize) {
             return Err(io::Error::new(
                 io::ErrorKind::InvalidInput,
                 format!(
-                    "Buffer size is incorrect: expected {}, obtained {}",
+                    "Buffer size is not a multiple of the page size ({}): {}",
                     self.page_size,
                     buffer.len()
                 ),
             ));
         }
 
-        self.file.read_exact(buffer)
+        let offset = self.page_offset(first_page);
+        if self.lock.level() != LockLevel::Unlocked {
+            if let Some(map) = &self.map {
+                if map.read_at(buffer, offset) {
+                    return Ok(());
+                }
+            }
+        }
+
+        self.file.read_at(buffer, offset)
     }
 
     /// Writes an entire page to the database file.
@@ -210,7 +302,6 @@ impl DiskManager {
         }
 
         let offset = self.page_offset(page_number);
-        self.file.seek(SeekFrom::Start(offset))?;
 
         if buffer.len() != self.page_size as usize {
             return Err(io::Error::new(
@@ -223,7 +314,60 @@ impl DiskManager {
             ));
         }
 
-        self.file.write_all(buffer)
+        self.generation += 1;
+        self.file.write_at(buffer, offset)
+    }
+
+    /// Writes a batch of pages to the database file.
+    /// The pages are sorted by page number, and each run of contiguous pages goes to the file in a single
+    /// vectored write (`pwritev`), instead of one syscall per page.
+    ///
+    /// # Parameters
+    /// * `pages` - The page numbers and the contents to write, in any order.
+    ///
+    /// # Errors
+    /// Returns an error if a page number or buffer is invalid, in which case nothing is written,
+    /// or if the file cannot be written to, in which case some of the pages may have been written.
+    pub fn write_pages(&mut self, pages: &[(u32, &[u8])]) -> io::Result<()> {
+        for (page_number, buffer) in pages {
+            if *page_number == 0 {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "Invalid page number: 0 (pages start from 1)",
+                ));
+            }
+            if buffer.len() != self.page_size as usize {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!(
+                        "Buffer size is incorrect: expected {}, obtained {}",
+                        self.page_size,
+                        buffer.len()
+                    ),
+                ));
+            }
+        }
+
+        self.generation += 1;
+        let mut sorted = pages.iter().collect::<Vec<_>>();
+        sorted.sort_by_key(|(page_number, _)| *page_number);
+
+        let mut run: Vec<&[u8]> = Vec::new();
+        let mut first = 0;
+        for (page_number, buffer) in sorted {
+            if !run.is_empty() && first + run.len() as u32 != *page_number {
+                self.file.write_vectored_at(&run, self.page_offset(first))?;
+                run.clear();
+            }
+            if run.is_empty() {
+                first = *page_number;
+            }
+            run.push(buffer);
+        }
+        if !run.is_empty() {
+            self.file.write_vectored_at(&run, self.page_offset(first))?;
+        }
+        Ok(())
     }
 
     /// Allocates new pages in the database file.
@@ -241,7 +385,7 @@ impl DiskManager {
         // Get the current file size
         // This is important because we need to know how many pages we have already allocated.
         // We will use this to calculate the new size of the file.
-        let file_size = self.file.metadata()?.len();
+        let file_size = self.file.size()?;
 
         // Calculate the current number of pages. We cannot use page_count() here, because it would cause an error at diskmanager creation.
         // We will use the file size to calculate the number of pages.
@@ -254,7 +398,7 @@ impl DiskManager {
 
         // Update the file size
         // This is important because we need to ensure that the file is large enough to accommodate the new pages.
-        self.file.set_len(new_size)?;
+        self.file.truncate(new_size)?;
 
         // Initialize the new pages with zeros
         let zeros = vec![0u8; self.page_size as usize];
@@ -269,9 +413,47 @@ impl DiskManager {
 
         self.write_header(&header)?;
 
+        // Map the new pages too
+        self.remap()?;
+
         Ok(first_new_page)
     }
 
+    /// Raises the multi-process lock on the database file. Never blocks.
+    ///
+    /// # Parameters
+    /// * `level` - The lock level to acquire.
+    ///
+    /// # Errors
+    /// Returns a `Busy` error (see `lock::is_busy`) if another process holds a conflicting lock.
+    pub fn lock(&mut self, level: LockLevel) -> io::Result<()> {
+        let was_unlocked = self.lock.level() == LockLevel::Unlocked;
+        self.lock.lock(self.file.as_ref(), level)?;
+        self.generation += 1;
+        if was_unlocked {
+            // Other processes may have grown or shrunk the file while we were not looking
+            self.remap()?;
+        }
+        Ok(())
+    }
+
+    /// Lowers the multi-process lock on the database file.
+    ///
+    /// # Parameters
+    /// * `level` - Either `LockLevel::Shared` or `LockLevel::Unlocked`.
+    ///
+    /// # Errors
+    /// Returns an error if the level is not valid or the lock cannot be released.
+    pub fn unlock(&mut self, level: LockLevel) -> io::Result<()> {
+        self.generation += 1;
+        self.lock.unlock(self.file.as_ref(), level)
+    }
+
+    /// Gets the level of the lock currently held on the database file.
+    pub fn lock_level(&self) -> LockLevel {
+        self.lock.level()
+    }
+
     /// Computes the offset of a page in the database file.
     /// This method calculates the offset based on the page number and the page size.
     /// The offset is calculated as (page_number - 1) * page_size.
@@ -287,13 +469,34 @@ impl DiskManager {
     /// The offset is calculated as (page_number - 1) * page_size. Therefore, page 1 starts at offset 0, page 2 starts at offset page_size, and so on.
     /// This is a common way to calculate the offset for fixed-size pages in a file.
     fn page_offset(&self, page_number: u32) -> u64 {
-        (page_number as u64 - 1) * self.page_size as u64 + 100 // We add 100 bytes to account for the header and other metadata.
+        page_offset(self.page_size, page_number)
+    }
+
+    /// Shrinks the database file to the given number of pages, dropping the pages after them.
+    /// This is the opposite of `allocate_pages`, used to get rid of the pages of a transaction that rolls back.
+    ///
+    /// # Parameters
+    /// * `page_count` - The number of pages to keep.
+    ///
+    /// # Errors
+    /// Returns an error if the file cannot be resized or the header cannot be updated.
+    pub fn truncate_pages(&mut self, page_count: u32) -> io::Result<()> {
+        self.generation += 1;
+        self.file
+            .truncate(page_count as u64 * self.page_size as u64 + 100)?;
+
+        let mut header = self.read_header()?;
+        header.database_size = page_count;
+        self.write_header(&header)?;
+
+        // Nothing of the map may point past the end of the file
+        self.remap()
     }
 
     /// Obtains the number of pages in the database file.
     /// This method calculates the number of pages by dividing the file size by the page size.
     pub fn page_count(&self) -> io::Result<u32> {
-        let file_size = self.file.metadata()?.len();
+        let file_size = self.file.size()?;
         // We subtract 100 bytes to account for the header and other metadata.
         Ok(((file_size - 100) / self.page_size as u64) as u32)
     }
@@ -310,8 +513,116 @@ impl DiskManager {
     /// # Errors
     /// Returns an error if the file cannot be synced.
     pub fn sync(&mut self) -> io::Result<()> {
-        self.file.sync_all()
+        self.file.sync(false)
+    }
+
+    /// Syncs the contents of the file, but not its metadata (fdatasync).
+    /// Cheaper than `sync`, and still enough to find the pages after a power loss, as the file size counts as data.
+    ///
+    /// # Errors
+    /// Returns an error if the file cannot be synced.
+    pub fn sync_data(&mut self) -> io::Result<()> {
+        self.file.sync(true)
+    }
+
+    /// Sets how many bytes of the file are memory mapped for reading, like `PRAGMA mmap_size` in SQLite.
+    /// The map covers the file up to this size, and is remapped as the file grows or shrinks.
+    ///
+    /// # Parameters
+    /// * `mmap_size` - Most bytes to map. Zero turns the map off, which is the default.
+    ///
+    /// # Errors
+    /// Returns an error if the size of the file cannot be read.
+    pub fn set_mmap_size(&mut self, mmap_size: u64) -> io::Result<()> {
+        self.mmap_size = mmap_size;
+        self.remap()
+    }
+
+    /// Gets the number of bytes of the file that are currently memory mapped.
+    pub fn mapped_size(&self) -> u64 {
+        self.map.as_ref().map_or(0, |map| map.len() as u64)
     }
+
+    /// Maps the file again if its size changed, up to `mmap_size` bytes.
+    ///
+    /// A VFS that cannot map its files, or a map that fails (say, out of address space), is not an error:
+    /// the pages are read through the file as usual, like SQLite does.
+    ///
+    /// # Errors
+    /// Returns an error if the size of the file cannot be read.
+    fn remap(&mut self) -> io::Result<()> {
+        let len = if self.mmap_size == 0 {
+            0
+        } else {
+            self.file.size()?.min(self.mmap_size).min(usize::MAX as u64)
+        };
+        if len == self.mapped_size() {
+            return Ok(());
+        }
+
+        // Unmap first, so the old and the new map never take address space at the same time
+        self.map = None;
+        if len > 0 {
+            self.map = self.file.map(len as usize).unwrap_or(None);
+        }
+        Ok(())
+    }
+
+    /// Gets another handle to the database file, that can be synced without going through the DiskManager.
+    pub fn file_handle(&self) -> Arc<dyn VfsFile> {
+        Arc::clone(&self.file)
+    }
+
+    /// Gets a reader of the pages of the file that does not borrow the DiskManager.
+    /// It always reads through the file: the map can go away, or the file shrink under it, once we let go of it.
+    pub fn page_reader(&self) -> PageReader {
+        PageReader {
+            file: self.file_handle(),
+            page_size: self.page_size,
+        }
+    }
+
+    /// Checks if `read_page` would copy the page out of the map rather than read it from the file.
+    ///
+    /// # Parameters
+    /// * `page_number` - The page number (starting from 1).
+    pub fn is_mapped(&self, page_number: u32) -> bool {
+        page_number > 0
+            && self.lock.level() != LockLevel::Unlocked
+            && self.page_offset(page_number) + self.page_size as u64 <= self.mapped_size()
+    }
+
+    /// Gets the generation of the file. It changes with every write, truncation and lock change,
+    /// so a page read by a `PageReader` is still good if the generation is the same after the read as before it.
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+}
+
+/// Computes the offset of a page in the database file (see `DiskManager::page_offset`).
+fn page_offset(page_size: u32, page_number: u32) -> u64 {
+    (page_number as u64 - 1) * page_size as u64 + 100 // We add 100 bytes to account for the header and other metadata.
+}
+
+/// Checks the page number and the buffer of a page read.
+fn check_page_buffer(page_size: u32, page_number: u32, buffer: &[u8]) -> io::Result<()> {
+    if page_number == 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Invalid page number: 0 (pages start from 1)",
+        ));
+    }
+    if buffer.len() != page_size as usize {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "Buffer size is incorrect: expected {}, obtained {}",
+                page_size,
+                buffer.len()
+            ),
+        ));
+    }
+    Ok(())
 }
 
 #[cfg(test)]
@@ -388,8 +699,8 @@ mod tests {
 
         // Prepare some data to write
         let mut data = vec![0u8; 4096];
-        for i in 0..100 {
-            data[i] = i as u8;
+        for (i, byte) in data.iter_mut().take(100).enumerate() {
+            *byte = i as u8;
         }
 
         // Write the data to page 1
@@ -403,6 +714,32 @@ mod tests {
         assert_eq!(&buffer[0..100], &data[0..100]);
     }
 
+    #[test]
+    fn test_page_reader() {
+        let dir = tempdir().unwrap();
+        let db_path = dir.path().join("test.db");
+        let mut disk_manager = DiskManager::create(&db_path, 4096).unwrap();
+        disk_manager.allocate_pages(1).unwrap();
+
+        // The reader outlives the borrow, and sees what the DiskManager writes
+        let reader = disk_manager.page_reader();
+        let generation = disk_manager.generation();
+        disk_manager.write_page(2, &[7u8; 4096]).unwrap();
+        assert_ne!(disk_manager.generation(), generation);
+
+        let mut buffer = vec![0u8; 4096];
+        reader.read_page(2, &mut buffer).unwrap();
+        assert_eq!(buffer, vec![7u8; 4096]);
+        assert!(reader.read_page(0, &mut buffer).is_err());
+        assert!(reader.read_page(3, &mut buffer).is_err());
+
+        // So do lock changes, other processes can write once we let go
+        let generation = disk_manager.generation();
+        disk_manager.lock(LockLevel::Shared).unwrap();
+        disk_manager.unlock(LockLevel::Unlocked).unwrap();
+        assert_ne!(disk_manager.generation(), generation);
+    }
+
     #[test]
     fn test_allocate_pages() {
         let dir = tempdir().unwrap();
@@ -426,6 +763,128 @@ mod tests {
         assert_eq!(header.database_size, 3);
     }
 
+    #[test]
+    fn test_truncate_pages() {
+        let dir = tempdir().unwrap();
+        let db_path = dir.path().join("test.db");
+        let mut disk_manager = DiskManager::create(&db_path, 4096).unwrap();
+
+        disk_manager.allocate_pages(4).unwrap();
+        assert_eq!(disk_manager.page_count().unwrap(), 5);
+
+        // Back to the first two pages
+        disk_manager.truncate_pages(2).unwrap();
+        assert_eq!(disk_manager.page_count().unwrap(), 2);
+        assert_eq!(disk_manager.read_header().unwrap().database_size, 2);
+
+        // The file can grow again from there
+        assert_eq!(disk_manager.allocate_pages(1).unwrap(), 3);
+    }
+
+    #[test]
+    fn test_read_pages() {
+        let dir = tempdir().unwrap();
+        let db_path = dir.path().join("test.db");
+        let mut disk_manager = DiskManager::create(&db_path, 4096).unwrap();
+        disk_manager.allocate_pages(3).unwrap();
+        for page_number in 2..=4 {
+            disk_manager
+                .write_page(page_number, &vec![page_number as u8; 4096])
+                .unwrap();
+        }
+
+        let mut buffer = vec![0u8; 3 * 4096];
+        disk_manager.read_pages(2, &mut buffer).unwrap();
+        for (i, page) in buffer.chunks(4096).enumerate() {
+            assert!(page.iter().all(|&byte| byte == i as u8 + 2));
+        }
+
+        // Past the end of the file, or with a bad buffer
+        assert!(disk_manager.read_pages(3, &mut buffer).is_err());
+        assert!(disk_manager.read_pages(0, &mut buffer[..4096]).is_err());
+        assert!(disk_manager.read_pages(2, &mut buffer[..100]).is_err());
+        assert!(disk_manager.read_pages(2, &mut []).is_err());
+    }
+
+    #[test]
+    fn test_write_pages() {
+        let dir = tempdir().unwrap();
+        let db_path = dir.path().join("test.db");
+        let mut disk_manager = DiskManager::create(&db_path, 4096).unwrap();
+        disk_manager.allocate_pages(5).unwrap();
+
+        // Two runs, 2..=4 and 6, given out of order
+        let pages = [2u32, 6, 4, 3].map(|page_number| (page_number, vec![page_number as u8; 4096]));
+        let batch = pages
+            .iter()
+            .map(|(page_number, buffer)| (*page_number, buffer.as_slice()))
+            .collect::<Vec<_>>();
+        disk_manager.write_pages(&batch).unwrap();
+
+        let mut buffer = vec![0u8; 4096];
+        for page_number in 1..=6 {
+            disk_manager.read_page(page_number, &mut buffer).unwrap();
+            let expected = if page_number == 1 || page_number == 5 {
+                0
+            } else {
+                page_number as u8
+            };
+            assert!(
+                buffer.iter().all(|&byte| byte == expected),
+                "page {}",
+                page_number
+            );
+        }
+
+        // A bad page in the batch writes nothing
+        let bad = [(5u32, &buffer[..]), (0, &buffer[..])];
+        assert!(disk_manager.write_pages(&bad).is_err());
+        let small = [(5u32, &buffer[..100])];
+        assert!(disk_manager.write_pages(&small).is_err());
+        disk_manager.read_page(5, &mut buffer).unwrap();
+        assert!(buffer.iter().all(|&byte| byte == 0));
+        assert!(disk_manager.write_pages(&[]).is_ok());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_mmap_reads() {
+        let dir = tempdir().unwrap();
+        let db_path = dir.path().join("test.db");
+        let mut disk_manager = DiskManager::create(&db_path, 4096).unwrap();
+        assert_eq!(disk_manager.mapped_size(), 0);
+
+        disk_manager.set_mmap_size(1 << 20).unwrap();
+        assert_eq!(disk_manager.mapped_size(), 100 + 4096);
+
+        // The map grows with the file, and sees the writes made through the file handle
+        disk_manager.allocate_pages(2).unwrap();
+        assert_eq!(disk_manager.mapped_size(), 100 + 3 * 4096);
+        let data = vec![7u8; 4096];
+        disk_manager.write_page(3, &data).unwrap();
+
+        disk_manager.lock(LockLevel::Shared).unwrap();
+        let mut buffer = vec![0u8; 4096];
+        disk_manager.read_page(3, &mut buffer).unwrap();
+        assert_eq!(buffer, data);
+
+        // And shrinks with it
+        disk_manager.truncate_pages(1).unwrap();
+        assert_eq!(disk_manager.mapped_size(), 100 + 4096);
+        assert!(disk_manager.read_page(3, &mut buffer).is_err());
+
+        // A page that is only partly mapped is read through the file
+        disk_manager.set_mmap_size(4096).unwrap();
+        assert_eq!(disk_manager.mapped_size(), 4096);
+        disk_manager.write_page(1, &data).unwrap();
+        disk_manager.read_page(1, &mut buffer).unwrap();
+        assert_eq!(buffer, data);
+
+        disk_manager.set_mmap_size(0).unwrap();
+        assert_eq!(disk_manager.mapped_size(), 0);
+        disk_manager.unlock(LockLevel::Unlocked).unwrap();
+    }
+
     #[test]
     fn test_invalid_operations() {
         let dir = tempdir().unwrap();
@@ -0,0 +1,354 @@
+//! # Journal Module
+//!
+//! The rollback journal on disk. The pager keeps the original image of every page a transaction changes in memory
+//! (see pager.rs), which is enough to roll back while the process is alive. But a transaction can write pages to the
+//! database file before it commits (when the cache spills), and a commit writes many pages that must land together.
+//! If the process dies or the machine loses power in the middle, the file is left with half a transaction in it.
+//!
+//! So before the first page of a transaction reaches the database file, the original images are appended to a journal
+//! file next to it (`<database>-journal`, like the rollback journal of SQLite, https://www.sqlite.org/atomiccommit.html)
+//! and the journal is synced. The commit is done the moment the journal is deleted, after the database file is synced.
+//! A journal found when opening the database means that a transaction never finished: it is "hot", and playing it back
+//! puts the database as it was before that transaction began.
+//!
+//! The layout of the file is:
+//!
+//! - Header: magic string (8 bytes), page size (4 bytes), number of pages of the database before the transaction (4 bytes),
+//!   the database header before the transaction (100 bytes) and a CRC32C of all that (4 bytes).
+//! - One record per page: page number (4 bytes), the page as it is stored in the database file,
+//!   and a CRC32C of the page number and the page (4 bytes).
+//!
+//! Records are only trusted up to the first one that does not match its checksum: anything after that was being written
+//! when the crash happened, and pages are never written to the database before their record is synced.
+//!
+//! With group commit, a commit does not wait for its sync, so its journal cannot be deleted right away. The next commits
+//! join that journal (appending only the pages it does not have yet) and the shared sync deletes it for all of them.
+//! Playing it back undoes the whole group, which is fine, as none of those commits has returned yet.
+use std::collections::HashSet;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use super::vfs::{Vfs, VfsFile};
+use crate::header::{Header, HEADER_SIZE};
+use crate::utils::checksum::crc32c;
+
+/// Magic string at the beginning of every journal.
+pub const JOURNAL_MAGIC: &[u8; 8] = b"rqljrnl\x01";
+
+/// Size of the journal header in bytes.
+const JOURNAL_HEADER_SIZE: usize = 8 + 4 + 4 + HEADER_SIZE + 4;
+
+/// Contents of a hot journal, ready to be played back.
+pub struct HotJournal {
+    /// Database header before the transaction.
+    pub header: Header,
+    /// Number of pages of the database before the transaction.
+    pub page_count: u32,
+    /// Original image of the pages the transaction changed, as stored in the database file.
+    pub pages: Vec<(u32, Vec<u8>)>,
+}
+
+/// The journal file of a database.
+pub struct Journal {
+    /// File system the database lives in.
+    vfs: Arc<dyn Vfs>,
+    /// Path of the journal file.
+    path: PathBuf,
+    /// The journal file, while a transaction has one.
+    file: Option<Arc<dyn VfsFile>>,
+    /// Pages already in the file.
+    pages: HashSet<u32>,
+    /// Offset where the next record goes.
+    end: u64,
+    /// Size of the pages of the database.
+    page_size: u32,
+    /// Whether everything written to the file has been synced.
+    synced: bool,
+    /// Last commit of the group waiting for a sync to delete the journal, if any.
+    pending: Option<u64>,
+}
+
+impl Journal {
+    /// Creates the journal of a database. Nothing is written until `begin` is called.
+    ///
+    /// # Parameters
+    /// * `vfs` - File system the database lives in.
+    /// * `database` - Path of the database file.
+    /// * `page_size` - Size of the pages of the database.
+    pub fn new(vfs: Arc<dyn Vfs>, database: &Path, page_size: u32) -> Self {
+        let mut path = database.as_os_str().to_owned();
+        path.push("-journal");
+        Journal {
+            vfs,
+            path: PathBuf::from(path),
+            file: None,
+            pages: HashSet::new(),
+            end: 0,
+            page_size,
+            synced: true,
+            pending: None,
+        }
+    }
+
+    /// Gets the path of the journal file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Checks if the active transaction has a journal file.
+    pub fn is_open(&self) -> bool {
+        self.file.is_some()
+    }
+
+    /// Gets the last commit of the group whose journal is waiting for a sync.
+    pub fn pending(&self) -> Option<u64> {
+        self.pending
+    }
+
+    /// Leaves the journal to the group sync, after a commit that did not wait for it (see `finish_group`).
+    ///
+    /// # Parameters
+    /// * `commit` - Number of the commit.
+    pub fn set_pending(&mut self, commit: u64) {
+        if self.file.is_some() {
+            self.pending = Some(commit);
+        }
+    }
+
+    /// Checks if the journal file already has the original image of a page.
+    pub fn contains(&self, page_number: u32) -> bool {
+        self.pages.contains(&page_number)
+    }
+
+    /// Creates the journal file for a transaction, with the state of the database before it.
+    ///
+    /// # Parameters
+    /// * `header` - Database header before the transaction.
+    /// * `page_count` - Number of pages of the database before the transaction.
+    ///
+    /// # Errors
+    /// Returns an error if the file cannot be created or written.
+    pub fn begin(&mut self, header: &Header, page_count: u32) -> io::Result<()> {
+        let mut buffer = Vec::with_capacity(JOURNAL_HEADER_SIZE);
+        buffer.extend_from_slice(JOURNAL_MAGIC);
+        buffer.extend_from_slice(&self.page_size.to_be_bytes());
+        buffer.extend_from_slice(&page_count.to_be_bytes());
+        header.write_to(&mut buffer)?;
+        let checksum = crc32c(&buffer);
+        buffer.extend_from_slice(&checksum.to_be_bytes());
+
+        let file = self.vfs.open(&self.path, true)?;
+        file.write_at(&buffer, 0)?;
+        self.file = Some(file);
+        self.pages.clear();
+        self.end = buffer.len() as u64;
+        self.synced = false;
+        Ok(())
+    }
+
+    /// Appends the original image of a page. Pages already in the journal are skipped,
+    /// the first image is the one that matters.
+    ///
+    /// # Parameters
+    /// * `page_number` - Number of the page.
+    /// * `page` - The page, serialized as it is stored in the database file.
+    ///
+    /// # Errors
+    /// Returns an error if there is no journal file or the record cannot be written.
+    pub fn append(&mut self, page_number: u32, page: &[u8]) -> io::Result<()> {
+        if self.pages.contains(&page_number) {
+            return Ok(());
+        }
+        let file = self
+            .file
+            .as_ref()
+            .ok_or_else(|| io::Error::other("The journal is not open"))?;
+
+        let mut record = Vec::with_capacity(page.len() + 8);
+        record.extend_from_slice(&page_number.to_be_bytes());
+        record.extend_from_slice(page);
+        let checksum = crc32c(&record);
+        record.extend_from_slice(&checksum.to_be_bytes());
+
+        file.write_at(&record, self.end)?;
+        self.end += record.len() as u64;
+        self.pages.insert(page_number);
+        self.synced = false;
+        Ok(())
+    }
+
+    /// Syncs the journal file, so that its records survive a power loss.
+    ///
+    /// # Parameters
+    /// * `data_only` - Sync only the contents (fdatasync).
+    ///
+    /// # Errors
+    /// Returns an error if the file cannot be synced.
+    pub fn sync(&mut self, data_only: bool) -> io::Result<()> {
+        match &self.file {
+            Some(file) if !self.synced => {
+                file.sync(data_only)?;
+                self.synced = true;
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Deletes the journal file of the transaction. This is the moment a commit (or a rollback) becomes final.
+    ///
+    /// # Errors
+    /// Returns an error if the file cannot be deleted.
+    pub fn finish(&mut self) -> io::Result<()> {
+        if self.file.take().is_some() {
+            self.pages.clear();
+            self.end = 0;
+            self.synced = true;
+            self.pending = None;
+            self.vfs.delete(&self.path)?;
+        }
+        Ok(())
+    }
+
+    /// Deletes the journal of a group of commits once a sync covers all of them.
+    ///
+    /// # Parameters
+    /// * `synced` - Last commit known to be on stable storage.
+    ///
+    /// # Errors
+    /// Returns an error if the file cannot be deleted.
+    pub fn finish_group(&mut self, synced: u64) -> io::Result<()> {
+        match self.pending {
+            Some(commit) if commit <= synced => self.finish(),
+            _ => Ok(()),
+        }
+    }
+
+    /// Checks if there is a journal file left by a transaction that did not finish.
+    /// The caller must make sure no other connection is in the middle of a transaction (see lock.rs).
+    ///
+    /// # Errors
+    /// Returns an error if the VFS cannot tell.
+    pub fn is_hot(&self) -> io::Result<bool> {
+        Ok(self.file.is_none() && self.vfs.exists(&self.path)?)
+    }
+
+    /// Reads a hot journal.
+    ///
+    /// # Errors
+    /// Returns an error if the file cannot be read.
+    ///
+    /// # Returns
+    /// The journal, or `None` if it does not even have a complete header.
+    /// In that case the crash happened before any page of the database was touched.
+    pub fn read_hot(&self) -> io::Result<Option<HotJournal>> {
+        let file = self.vfs.open(&self.path, false)?;
+        let size = file.size()?;
+        if size < JOURNAL_HEADER_SIZE as u64 {
+            return Ok(None);
+        }
+
+        let mut buffer = vec![0u8; JOURNAL_HEADER_SIZE];
+        file.read_at(&mut buffer, 0)?;
+        let (contents, checksum) = buffer.split_at(JOURNAL_HEADER_SIZE - 4);
+        if &contents[..8] != JOURNAL_MAGIC || read_u32(checksum) != crc32c(contents) {
+            return Ok(None);
+        }
+        let page_size = read_u32(&contents[8..12]);
+        let page_count = read_u32(&contents[12..16]);
+        let header = Header::read_from(&mut &contents[16..])?;
+
+        let record_size = page_size as u64 + 8;
+        let mut pages = Vec::new();
+        let mut offset = JOURNAL_HEADER_SIZE as u64;
+        while offset + record_size <= size {
+            let mut record = vec![0u8; record_size as usize];
+            file.read_at(&mut record, offset)?;
+            let (contents, checksum) = record.split_at(record_size as usize - 4);
+            if read_u32(checksum) != crc32c(contents) {
+                break;
+            }
+            pages.push((read_u32(&contents[..4]), contents[4..].to_vec()));
+            offset += record_size;
+        }
+
+        Ok(Some(HotJournal {
+            header,
+            page_count,
+            pages,
+        }))
+    }
+
+    /// Deletes a hot journal once it has been played back.
+    ///
+    /// # Errors
+    /// Returns an error if the file cannot be deleted.
+    pub fn delete_hot(&self) -> io::Result<()> {
+        self.vfs.delete(&self.path)
+    }
+}
+
+/// Reads a big-endian u32 from the first four bytes of a slice.
+fn read_u32(bytes: &[u8]) -> u32 {
+    u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::vfs::MemoryVfs;
+
+    #[test]
+    fn test_journal_round_trip() {
+        let vfs: Arc<dyn Vfs> = Arc::new(MemoryVfs::new());
+        let mut journal = Journal::new(Arc::clone(&vfs), Path::new("test.db"), 512);
+        assert_eq!(journal.path(), Path::new("test.db-journal"));
+        assert!(!journal.is_hot().unwrap());
+
+        let mut header = Header::with_page_size(512).unwrap();
+        header.user_version = 3;
+        journal.begin(&header, 7).unwrap();
+        journal.append(2, &[2u8; 512]).unwrap();
+        journal.append(5, &[5u8; 512]).unwrap();
+        // The first image of a page wins
+        journal.append(2, &[9u8; 512]).unwrap();
+        assert!(journal.contains(5));
+        journal.sync(false).unwrap();
+
+        // Another connection sees the journal of a transaction that never finished
+        let other = Journal::new(Arc::clone(&vfs), Path::new("test.db"), 512);
+        assert!(other.is_hot().unwrap());
+        let hot = other.read_hot().unwrap().unwrap();
+        assert_eq!(hot.page_count, 7);
+        assert_eq!(hot.header.user_version, 3);
+        assert_eq!(hot.pages.len(), 2);
+        assert_eq!(hot.pages[0], (2, vec![2u8; 512]));
+        assert_eq!(hot.pages[1], (5, vec![5u8; 512]));
+
+        journal.finish().unwrap();
+        assert!(!other.is_hot().unwrap());
+    }
+
+    #[test]
+    fn test_torn_journal() {
+        let vfs: Arc<dyn Vfs> = Arc::new(MemoryVfs::new());
+        let mut journal = Journal::new(Arc::clone(&vfs), Path::new("test.db"), 512);
+        journal
+            .begin(&Header::with_page_size(512).unwrap(), 3)
+            .unwrap();
+        journal.append(1, &[1u8; 512]).unwrap();
+        journal.append(2, &[2u8; 512]).unwrap();
+
+        // Tear the second record: only the first one can be trusted
+        let file = vfs.open(journal.path(), false).unwrap();
+        let torn = JOURNAL_HEADER_SIZE as u64 + 520 + 100;
+        file.write_at(&[0xAA; 8], torn).unwrap();
+        let hot = journal.read_hot().unwrap().unwrap();
+        assert_eq!(hot.pages.len(), 1);
+
+        // A journal without a complete header never had pages written to the database after it
+        file.truncate(JOURNAL_HEADER_SIZE as u64 - 1).unwrap();
+        assert!(journal.read_hot().unwrap().is_none());
+    }
+}
@@ -0,0 +1,398 @@
+//! # Lock Module
+//!
+//! This module implements multi-process locking of the database file, following the same protocol SQLite uses on unix.
+//! Locks are POSIX advisory `fcntl` byte-range locks placed on bytes far beyond the data we actually use,
+//! so they never interfere with real reads and writes:
+//!
+//! - `PENDING_BYTE`: a single byte at 1GB. A write lock on it announces that a writer wants the exclusive lock.
+//! - `RESERVED_BYTE`: the byte right after. Whoever holds a write lock on it is the only allowed writer.
+//! - The shared range: 510 bytes after those. Readers hold read locks on it, the exclusive writer a write lock.
+//!
+//! A connection climbs through the following states: UNLOCKED -> SHARED -> RESERVED -> PENDING -> EXCLUSIVE.
+//! Many processes can be SHARED at the same time, only one can be RESERVED (it can keep reading while the others read too),
+//! PENDING prevents new readers from coming in and EXCLUSIVE is only granted when all the readers are gone.
+//! See https://www.sqlite.org/lockingv3.html for the details.
+//!
+//! A lock that cannot be granted never blocks: it fails with a `Busy` error, and it is up to the caller
+//! (in our case, the Pager) to retry it by means of a busy handler.
+//!
+//! NOTE: `fcntl` locks belong to processes, not to file handles. Two handles of the same process never conflict,
+//! and closing any handle of a file releases all the locks the process holds on it.
+//! This is why a process should share a single Pager (which `RQLite` handles already do) instead of opening the file twice.
+//!
+//! The byte ranges are locked through the `VfsFile` of the database (see vfs.rs), the `fcntl` calls live in `set_lock`,
+//! which is what the files of `OsVfs` use.
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::sync::Arc;
+use std::time::Duration;
+
+use super::vfs::VfsFile;
+
+/// Offset of the byte used for the PENDING lock.
+pub const PENDING_BYTE: u64 = 0x4000_0000;
+/// Offset of the byte used for the RESERVED lock.
+pub const RESERVED_BYTE: u64 = PENDING_BYTE + 1;
+/// Offset of the first byte of the range used for SHARED and EXCLUSIVE locks.
+pub const SHARED_FIRST: u64 = PENDING_BYTE + 2;
+/// Number of bytes of the shared range.
+pub const SHARED_SIZE: u64 = 510;
+
+/// Lock states of a database file, ordered from weakest to strongest.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LockLevel {
+    /// No lock is held.
+    Unlocked,
+    /// The file can be read, but not written.
+    Shared,
+    /// The holder plans to write. Other processes can still acquire new SHARED locks.
+    Reserved,
+    /// The holder wants to write as soon as possible and waits for the current readers to finish.
+    /// No new SHARED locks are granted.
+    Pending,
+    /// The holder can write to the file. No other lock can be held.
+    Exclusive,
+}
+
+/// Error payload used when a lock cannot be acquired because another process holds a conflicting one.
+/// It is carried inside an `io::Error` of kind `WouldBlock`, use `is_busy` to detect it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BusyError {
+    /// Lock level we tried to acquire.
+    pub requested: LockLevel,
+    /// Lock level we were holding when the attempt failed.
+    pub held: LockLevel,
+}
+
+impl fmt::Display for BusyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Database is busy: cannot acquire {:?} lock while holding {:?}",
+            self.requested, self.held
+        )
+    }
+}
+
+impl Error for BusyError {}
+
+/// Builds the `io::Error` returned when a lock is busy.
+pub fn busy_error(requested: LockLevel, held: LockLevel) -> io::Error {
+    io::Error::new(io::ErrorKind::WouldBlock, BusyError { requested, held })
+}
+
+/// Checks if an error was caused by a busy lock.
+///
+/// # Parameters
+/// * `error` - The error to check.
+///
+/// # Returns
+/// `true` if the error carries a `BusyError`.
+pub fn is_busy(error: &io::Error) -> bool {
+    error
+        .get_ref()
+        .is_some_and(|inner| inner.downcast_ref::<BusyError>().is_some())
+}
+
+/// Callback invoked when a lock is busy. It receives the number of times it has already been called
+/// for the same lock and returns `true` to retry or `false` to give up with a `Busy` error.
+pub type BusyHandler = Arc<dyn Fn(u32) -> bool + Send + Sync>;
+
+/// Creates a busy handler that sleeps and retries until the given amount of time has passed.
+/// Delays grow like the ones of the SQLite default busy handler, starting at 1ms and capped at 100ms.
+///
+/// # Parameters
+/// * `timeout` - Total time to keep retrying. A zero timeout gives up immediately.
+///
+/// # Returns
+/// The busy handler.
+pub fn timeout_handler(timeout: Duration) -> BusyHandler {
+    const DELAYS_MS: [u64; 12] = [1, 2, 5, 10, 15, 20, 25, 25, 25, 50, 50, 100];
+
+    let timeout_ms = timeout.as_millis() as u64;
+    Arc::new(move |count| {
+        let count = count as usize;
+        let (delay, waited) = if count < DELAYS_MS.len() {
+            (DELAYS_MS[count], DELAYS_MS[..count].iter().sum::<u64>())
+        } else {
+            let total: u64 = DELAYS_MS.iter().sum();
+            (100, total + 100 * (count - DELAYS_MS.len()) as u64)
+        };
+
+        if waited >= timeout_ms {
+            return false;
+        }
+        std::thread::sleep(Duration::from_millis(delay.min(timeout_ms - waited)));
+        true
+    })
+}
+
+/// Tracks the lock a process holds on a database file and moves it between states.
+#[derive(Debug)]
+pub struct FileLock {
+    level: LockLevel,
+}
+
+impl Default for FileLock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FileLock {
+    /// Creates a new lock tracker in the UNLOCKED state.
+    pub fn new() -> Self {
+        FileLock {
+            level: LockLevel::Unlocked,
+        }
+    }
+
+    /// Gets the level of the lock currently held.
+    pub fn level(&self) -> LockLevel {
+        self.level
+    }
+
+    /// Raises the lock to the requested level. Does nothing if the lock is already at that level or above.
+    ///
+    /// # Parameters
+    /// * `file` - The database file, which places the byte-range locks.
+    /// * `level` - The level to acquire. SHARED can only be acquired from UNLOCKED,
+    ///   and every other level requires SHARED to be held first.
+    ///
+    /// # Errors
+    /// Returns a `Busy` error if another process holds a conflicting lock.
+    /// If acquiring EXCLUSIVE fails after PENDING was obtained, the lock stays at PENDING,
+    /// so that no new readers get in while we wait for the current ones.
+    pub fn lock(&mut self, file: &dyn VfsFile, level: LockLevel) -> io::Result<()> {
+        if self.level >= level {
+            return Ok(());
+        }
+
+        match level {
+            LockLevel::Unlocked => Ok(()),
+            LockLevel::Shared => {
+                // A read lock on the pending byte fails if a writer is waiting for EXCLUSIVE
+                self.try_range(file, LockKind::Read, PENDING_BYTE, 1, level)?;
+                let result = self.try_range(file, LockKind::Read, SHARED_FIRST, SHARED_SIZE, level);
+                file.lock(LockKind::Unlock, PENDING_BYTE, 1)?;
+                result?;
+                self.level = LockLevel::Shared;
+                Ok(())
+            }
+            LockLevel::Reserved => {
+                self.require_shared(level)?;
+                self.try_range(file, LockKind::Write, RESERVED_BYTE, 1, level)?;
+                self.level = LockLevel::Reserved;
+                Ok(())
+            }
+            LockLevel::Pending | LockLevel::Exclusive => {
+                self.require_shared(level)?;
+                if self.level < LockLevel::Pending {
+                    self.try_range(file, LockKind::Write, PENDING_BYTE, 1, level)?;
+                    self.level = LockLevel::Pending;
+                }
+                if level == LockLevel::Exclusive {
+                    self.try_range(file, LockKind::Write, SHARED_FIRST, SHARED_SIZE, level)?;
+                    self.level = LockLevel::Exclusive;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Lowers the lock to the requested level. Does nothing if the lock is already at that level or below.
+    ///
+    /// # Parameters
+    /// * `file` - The database file, which places the byte-range locks.
+    /// * `level` - Either SHARED or UNLOCKED.
+    ///
+    /// # Errors
+    /// Returns an error if the level is not SHARED or UNLOCKED, or if the locks cannot be released.
+    pub fn unlock(&mut self, file: &dyn VfsFile, level: LockLevel) -> io::Result<()> {
+        if level > LockLevel::Shared {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Cannot unlock to {:?}, only Shared or Unlocked are allowed", level),
+            ));
+        }
+        if self.level <= level {
+            return Ok(());
+        }
+
+        if level == LockLevel::Shared {
+            if self.level == LockLevel::Exclusive {
+                // Downgrade our write lock on the shared range to a read lock
+                file.lock(LockKind::Read, SHARED_FIRST, SHARED_SIZE)?;
+            }
+            // Release both the pending and the reserved bytes
+            file.lock(LockKind::Unlock, PENDING_BYTE, 2)?;
+        } else {
+            file.lock(LockKind::Unlock, PENDING_BYTE, 2 + SHARED_SIZE)?;
+        }
+
+        self.level = level;
+        Ok(())
+    }
+
+    /// Fails if the SHARED lock required by the next transition is not held.
+    fn require_shared(&self, requested: LockLevel) -> io::Result<()> {
+        if self.level < LockLevel::Shared {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Cannot acquire {:?} lock without holding a Shared lock", requested),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Tries to lock a byte range, turning conflicts into `Busy` errors.
+    fn try_range(
+        &self,
+        file: &dyn VfsFile,
+        kind: LockKind,
+        start: u64,
+        len: u64,
+        requested: LockLevel,
+    ) -> io::Result<()> {
+        file.lock(kind, start, len).map_err(|e| {
+            if is_conflict(&e) {
+                busy_error(requested, self.level)
+            } else {
+                e
+            }
+        })
+    }
+}
+
+/// Kind of byte-range lock.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LockKind {
+    /// Shared lock, many holders can read the range.
+    Read,
+    /// Exclusive lock, a single holder.
+    Write,
+    /// Releases the locks held on the range.
+    Unlock,
+}
+
+/// Places (or removes) a non-blocking `fcntl` lock on a byte range of the file.
+#[cfg(unix)]
+pub(crate) fn set_lock(file: &File, kind: LockKind, start: u64, len: u64) -> io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+
+    // SAFETY: `flock` is a plain C struct, all zeroes is a valid value for it.
+    let mut flock: libc::flock = unsafe { std::mem::zeroed() };
+    flock.l_type = match kind {
+        LockKind::Read => libc::F_RDLCK,
+        LockKind::Write => libc::F_WRLCK,
+        LockKind::Unlock => libc::F_UNLCK,
+    } as _;
+    flock.l_whence = libc::SEEK_SET as _;
+    flock.l_start = start as libc::off_t;
+    flock.l_len = len as libc::off_t;
+
+    // SAFETY: the descriptor is valid for as long as `file` is alive and `flock` outlives the call.
+    let result = unsafe { libc::fcntl(file.as_raw_fd(), libc::F_SETLK, &flock) };
+    if result == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Advisory locks are only implemented for unix. On other platforms locking always succeeds.
+#[cfg(not(unix))]
+pub(crate) fn set_lock(_file: &File, _kind: LockKind, _start: u64, _len: u64) -> io::Result<()> {
+    Ok(())
+}
+
+/// Checks if an `fcntl` error means that another process holds a conflicting lock.
+fn is_conflict(error: &io::Error) -> bool {
+    #[cfg(unix)]
+    {
+        matches!(error.raw_os_error(), Some(libc::EAGAIN) | Some(libc::EACCES))
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = error;
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::vfs::OsFile;
+    use std::fs::OpenOptions;
+    use tempfile::tempdir;
+
+    /// Lock files must be opened for reading and writing, as read locks need read access and write locks write access.
+    fn open_lock_file(dir: &tempfile::TempDir) -> OsFile {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(dir.path().join("lock.db"))
+            .unwrap();
+        OsFile::new(file)
+    }
+
+    #[test]
+    fn test_lock_transitions() {
+        let dir = tempdir().unwrap();
+        let file = open_lock_file(&dir);
+        let mut lock = FileLock::new();
+
+        assert_eq!(lock.level(), LockLevel::Unlocked);
+        lock.lock(&file, LockLevel::Shared).unwrap();
+        lock.lock(&file, LockLevel::Reserved).unwrap();
+        lock.lock(&file, LockLevel::Exclusive).unwrap();
+        assert_eq!(lock.level(), LockLevel::Exclusive);
+
+        // Asking for a weaker lock is a no-op
+        lock.lock(&file, LockLevel::Shared).unwrap();
+        assert_eq!(lock.level(), LockLevel::Exclusive);
+
+        lock.unlock(&file, LockLevel::Shared).unwrap();
+        assert_eq!(lock.level(), LockLevel::Shared);
+        lock.unlock(&file, LockLevel::Unlocked).unwrap();
+        assert_eq!(lock.level(), LockLevel::Unlocked);
+    }
+
+    #[test]
+    fn test_invalid_transitions() {
+        let dir = tempdir().unwrap();
+        let file = open_lock_file(&dir);
+        let mut lock = FileLock::new();
+
+        // Reserved requires a shared lock first
+        assert!(lock.lock(&file, LockLevel::Reserved).is_err());
+        // Only Shared and Unlocked are valid unlock targets
+        assert!(lock.unlock(&file, LockLevel::Reserved).is_err());
+    }
+
+    #[test]
+    fn test_busy_error_detection() {
+        let busy = busy_error(LockLevel::Exclusive, LockLevel::Reserved);
+        assert!(is_busy(&busy));
+        assert_eq!(busy.kind(), io::ErrorKind::WouldBlock);
+        assert!(!is_busy(&io::Error::other("something else")));
+    }
+
+    #[test]
+    fn test_timeout_handler() {
+        let handler = timeout_handler(Duration::ZERO);
+        assert!(!handler(0));
+
+        let handler = timeout_handler(Duration::from_millis(10));
+        let start = std::time::Instant::now();
+        let mut count = 0;
+        while handler(count) {
+            count += 1;
+        }
+        assert!(count > 0);
+        assert!(start.elapsed() >= Duration::from_millis(10));
+    }
+}
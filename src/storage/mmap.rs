@@ -0,0 +1,157 @@
+//! # Mmap Module
+//!
+//! A read-only memory map of the first bytes of a file, for the mmap read path of the DiskManager.
+//! With the file mapped, reading a page that misses the cache is a `memcpy` out of the page cache of the OS
+//! instead of a `pread` syscall, which is most of the cost of a read-mostly workload over a big file.
+//! It is the same idea as `PRAGMA mmap_size` in SQLite (https://www.sqlite.org/mmap.html).
+//!
+//! Only the reads go through the map. Writes keep going through the file handle, and as the map is shared
+//! with the page cache of the OS, it sees them right away.
+//!
+//! I went for a few lines of `libc` instead of pulling a crate for this. Only unix has it, on other platforms
+//! `MemoryMap::new` says it is not supported and the DiskManager keeps reading through the file.
+use std::fs::File;
+use std::io;
+
+/// A read-only, shared memory map of the beginning of a file. Unmapped when dropped.
+#[derive(Debug)]
+pub struct MemoryMap {
+    ptr: *mut u8,
+    len: usize,
+}
+
+// SAFETY: the map is read-only and owned by this struct, any thread can read from it or unmap it.
+unsafe impl Send for MemoryMap {}
+// SAFETY: see above, `&MemoryMap` only allows reading.
+unsafe impl Sync for MemoryMap {}
+
+impl MemoryMap {
+    /// Maps the first `len` bytes of a file.
+    ///
+    /// # Parameters
+    /// * `file` - The file to map. It can be closed afterwards, the map keeps its own reference.
+    /// * `len` - Number of bytes to map. Must not be zero, and should not go past the end of the file:
+    ///   touching the part of a map after the end of its file kills the process with a SIGBUS.
+    ///
+    /// # Errors
+    /// Returns an error if the length is zero or the file cannot be mapped.
+    #[cfg(unix)]
+    pub fn new(file: &File, len: usize) -> io::Result<Self> {
+        use std::os::unix::io::AsRawFd;
+
+        if len == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Cannot map zero bytes",
+            ));
+        }
+
+        // SAFETY: we ask for a brand new mapping (null hint), so no existing memory is affected,
+        // and the descriptor is valid for the duration of the call.
+        let ptr = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                len,
+                libc::PROT_READ,
+                libc::MAP_SHARED,
+                file.as_raw_fd(),
+                0,
+            )
+        };
+        if ptr == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(MemoryMap {
+            ptr: ptr as *mut u8,
+            len,
+        })
+    }
+
+    /// Memory maps are only implemented for unix.
+    ///
+    /// # Errors
+    /// Always returns an `Unsupported` error.
+    #[cfg(not(unix))]
+    pub fn new(_file: &File, _len: usize) -> io::Result<Self> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "Memory maps are not supported on this platform",
+        ))
+    }
+
+    /// Gets the number of bytes mapped.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Checks if the map is empty. It never is, as zero bytes cannot be mapped.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Copies the bytes at the given offset into the buffer.
+    ///
+    /// # Parameters
+    /// * `buffer` - Where to copy the bytes to. It is filled completely.
+    /// * `offset` - Offset of the first byte in the file.
+    ///
+    /// # Returns
+    /// `false`, without copying anything, if the range is not (entirely) mapped.
+    pub fn read_at(&self, buffer: &mut [u8], offset: u64) -> bool {
+        match offset.checked_add(buffer.len() as u64) {
+            Some(end) if end <= self.len as u64 => {}
+            _ => return false,
+        }
+
+        // SAFETY: the range is inside the map, which lives as long as `self`, and cannot overlap
+        // the buffer, that is a Rust allocation. The bytes may be changed by a write of this same
+        // file while we copy, but the callers read and write the file under the same lock.
+        unsafe {
+            std::ptr::copy_nonoverlapping(
+                self.ptr.add(offset as usize),
+                buffer.as_mut_ptr(),
+                buffer.len(),
+            );
+        }
+        true
+    }
+}
+
+#[cfg(unix)]
+impl Drop for MemoryMap {
+    fn drop(&mut self) {
+        // SAFETY: the pointer and length are those of a mapping we created and nobody else unmaps.
+        unsafe {
+            libc::munmap(self.ptr as *mut libc::c_void, self.len);
+        }
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_memory_map() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("mapped");
+        let mut file = File::create(&path).unwrap();
+        file.write_all(&[1, 2, 3, 4, 5, 6, 7, 8]).unwrap();
+        let file = File::open(&path).unwrap();
+
+        assert!(MemoryMap::new(&file, 0).is_err());
+        let map = MemoryMap::new(&file, 6).unwrap();
+        assert_eq!(map.len(), 6);
+
+        let mut buffer = [0u8; 4];
+        assert!(map.read_at(&mut buffer, 2));
+        assert_eq!(buffer, [3, 4, 5, 6]);
+
+        // Past the mapped bytes, even if the file has them
+        assert!(!map.read_at(&mut buffer, 4));
+        assert!(!map.read_at(&mut buffer, u64::MAX));
+    }
+}
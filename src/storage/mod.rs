@@ -4,9 +4,15 @@
 //! from disk, as well as caching frequently accessed data in memory to improve performance.
 pub mod cache;
 pub mod disk;
+pub mod journal;
+pub mod lock;
+pub mod mmap;
 pub mod pager;
+pub mod vfs;
 
 // Re-exporting the necessary components for external use
 pub use cache::BufferPool;
 pub use disk::DiskManager;
-pub use pager::Pager;
+pub use lock::LockLevel;
+pub use pager::{Pager, Synchronous};
+pub use vfs::{MemoryVfs, OsVfs, Vfs, VfsFile};
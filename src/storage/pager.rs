@@ -10,24 +10,212 @@
 //! that is used by the BTree modules to manage pages.
 //! Sqlite uses the pager to manage transactions too. This is done by writing the original page contents to a journal file.
 //! I have implemented a simple journal mechanism that stores the original pages in memory. Initially i was not going to add the journal thing to my storage engine implementation,
-//! because you know, they do not actually pay me for this, but creating a simpler journal in memory can be enough in most cases. Probably in the future will create a more robust journal with disk support.
+//! because you know, they do not actually pay me for this, but creating a simpler journal in memory can be enough in most cases.
+//! The in-memory journal is enough to roll back, but not to survive a crash, so now the original images also go to a journal
+//! file before the transaction writes anything to the database file (see journal.rs). A journal left behind by a crash is
+//! played back the next time somebody takes a lock on the database.
 //! Sqlite pager: https://www.sqlite.org/src/tree/pager.c
+//! A page is copied into the journal the first time a transaction changes it, so the journal always has the original image.
+//! The pages a transaction allocates are not journaled: the file is just truncated back to its original size on rollback.
+//! If the cache is full of dirty pages in the middle of a transaction, some of them are written to the file before the commit
+//! (spilled). That is fine, because their original images are still in the journal and rollback writes them back.
 //!
 //! To ensure page pinning and unpinning is safe, we use RAII guards that automatically pins a page when created
 //! and unpins it when dropped. This prevents deadlocks and ensures that pages are not evicted while in use.
 //!
 //! I have been about two months to implement this guard thing, and i am probably not the most experienced Rust developer,
 //! so if you have any suggestions on how to improve this code, please let me know.
+//!
+//! The pager is also in charge of the multi-process locks (see the lock.rs module). Readers hold a SHARED lock while they read,
+//! transactions take the RESERVED lock when they begin, and anything that writes to the file escalates to EXCLUSIVE,
+//! which is kept until the transaction commits or rolls back. When a lock is busy, the busy handler decides whether to retry.
+//! Every commit bumps the change counter of the header, so when we get a lock back we know if another process
+//! changed the file in the meantime and the cached pages must be thrown away.
+//!
+//! Readers of other threads must not see the pages of a transaction that is still running, nor a mix of
+//! two commits if a commit lands in the middle of a long read. For that, `snapshot` gives a read-only view of the
+//! database as of the last commit. The view answers with the journal copy of the pages the active transaction changed,
+//! and every commit that happens while snapshots are open keeps the images it replaced around (the `versions`),
+//! until the last snapshot that could need them is gone. It is a very small MVCC, but it is enough for the B-Trees.
+//! Snapshot pages are always handed out as copies, so a reader never aliases a page the writer is modifying.
+//!
+//! How often the file is synced is configurable with `Synchronous`, like `PRAGMA synchronous` in SQLite.
+//! With group commit, a commit writes its pages and releases the locks, and only then waits for the sync, outside the pager lock.
+//! The commits that land while a sync is running all share the next one, so a busy database does one fsync for a bunch of commits.
+//! Every commit still returns after its pages are on the disk, so this trades some latency for a lot of throughput, not durability.
+//!
+//! Transactions can also have savepoints (SAVEPOINT, RELEASE and ROLLBACK TO in SQLite). Each savepoint is a level of
+//! the journal with the images of the pages as they were when the savepoint was opened: a page is copied into every
+//! open level the first time it changes after it. Rolling back to a savepoint restores only the pages of its level,
+//! and releasing it just forgets the levels, as the outer ones already have the images they need.
+//!
+//! Dirty pages normally reach the file when the transaction commits, or when the cache is full and a reader needs the frame,
+//! which makes the reader pay for the write. The optional background writer (`set_background_writer`) is a thread that
+//! writes back the dirty pages nobody is using ahead of time, when too much of the cache is dirty or a page has been dirty
+//! for too long, so that the readers find clean pages to evict.
+//!
+//! Misses on consecutive pages usually mean somebody is walking the leaves of a B-Tree in order, so the pager can read
+//! the next few pages ahead of time in a single read (`set_read_ahead`). `cache_stats` tells whether that paid off.
+//!
+//! When the database opted in when it was created (`enable_checksums`, a flag in the header), the last four bytes
+//! of the reserved space of each page hold a CRC32C of the page. It is written every time a page goes to the file and checked every time one is read back, so a torn write or
+//! a flipped bit comes back as a `CorruptError` naming the page (see utils/checksum.rs) instead of as garbage rows.
+use std::collections::{BTreeMap, HashMap};
 use std::io;
 use std::ops::{Deref, DerefMut};
 use std::path::Path;
 
-use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, Weak};
+use std::thread::{self, JoinHandle, ThreadId};
+use std::time::{Duration, Instant};
 
-use super::cache::{AddPageResult, BufferPool};
+use super::cache::{AddPageResult, BufferPool, BufferPoolStats};
 use super::disk::DiskManager;
+use super::journal::Journal;
+use super::lock::{is_busy, BusyHandler, LockLevel};
+use super::vfs::{OsVfs, Vfs, VfsFile};
 use crate::header::Header;
 use crate::page::{BTreePage, ByteSerializable, FreePage, OverflowPage, Page, PageType};
+use crate::tree::cell::{
+    PayloadLimits, DEFAULT_MAX_PAYLOAD_FRACTION, DEFAULT_MIN_PAYLOAD_FRACTION,
+};
+use crate::utils::checksum::{corrupt_error, verify_checksum, write_checksum, CHECKSUM_SIZE};
+
+/// How hard the pager tries to get the commits to stable storage, like `PRAGMA synchronous` in SQLite.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Synchronous {
+    /// Never sync, the OS decides when the pages reach the disk.
+    /// A crash of the process is fine, but a power loss can lose the last commits or leave the file corrupted.
+    Off,
+    /// Sync the data of the file at every commit (fdatasync), without its metadata.
+    Normal,
+    /// Sync the whole file at every commit (fsync).
+    #[default]
+    Full,
+    /// Like `Full`, with one more sync between the pages and the header,
+    /// so the new change counter never reaches the disk before the pages it announces.
+    Extra,
+}
+
+/// When the background writer (see `Pager::set_background_writer`) writes dirty pages back to the file.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BackgroundWriter {
+    /// Time between two rounds of the writer.
+    pub interval: Duration,
+    /// Fraction of the cache that can be dirty before the writer starts writing back the least recently used pages.
+    pub dirty_ratio: f64,
+    /// Pages that have been dirty for longer than this are written back even below the ratio.
+    pub max_age: Duration,
+    /// Most pages written back in a round, so a round never keeps the pager locked for long.
+    pub max_pages: usize,
+}
+
+impl Default for BackgroundWriter {
+    fn default() -> Self {
+        BackgroundWriter {
+            interval: Duration::from_millis(50),
+            dirty_ratio: 0.5,
+            max_age: Duration::from_secs(1),
+            max_pages: 64,
+        }
+    }
+}
+
+/// The background writer thread of a pager.
+struct WriterThread {
+    /// Set to `true` to stop the thread. The thread holds the lock for the whole of each round,
+    /// so whoever holds it knows the thread is not using the pager.
+    stop: Arc<(Mutex<bool>, Condvar)>,
+    handle: JoinHandle<()>,
+}
+
+impl WriterThread {
+    /// Tells the thread to stop and waits until it does.
+    fn stop(self) -> io::Result<()> {
+        let (stopped, wake) = &*self.stop;
+        *stopped
+            .lock()
+            .map_err(|e| io::Error::other(format!("Lock poisoned: {}", e)))? = true;
+        wake.notify_all();
+        self.handle
+            .join()
+            .map_err(|_| io::Error::other("The background writer panicked"))
+    }
+}
+
+/// Sync bookkeeping shared by the commits of a pager, for group commit.
+struct SyncState {
+    /// Another handle to the database file, to sync it without holding the pager lock.
+    file: Arc<dyn VfsFile>,
+    /// Last commit whose pages were written to the file.
+    written: AtomicU64,
+    /// Last commit known to be on stable storage. Committers wait on this lock while a sync runs.
+    synced: Mutex<u64>,
+    /// Number of syncs done so far.
+    count: AtomicU64,
+    /// The journal file. Commits that do not wait for their sync leave it to the sync that covers them.
+    /// Lock order: pager, then `synced`, then this one.
+    journal: Mutex<Journal>,
+}
+
+impl SyncState {
+    fn new(file: Arc<dyn VfsFile>, journal: Journal) -> Self {
+        SyncState {
+            file,
+            written: AtomicU64::new(0),
+            synced: Mutex::new(0),
+            count: AtomicU64::new(0),
+            journal: Mutex::new(journal),
+        }
+    }
+
+    /// Locks the journal.
+    fn journal(&self) -> io::Result<std::sync::MutexGuard<'_, Journal>> {
+        self.journal
+            .lock()
+            .map_err(|e| io::Error::other(format!("Lock poisoned: {}", e)))
+    }
+
+    /// Waits until a commit is on stable storage, syncing the file if no running sync covers it.
+    fn sync_commit(&self, commit: u64, synchronous: Synchronous) -> io::Result<()> {
+        let mut synced = self
+            .synced
+            .lock()
+            .map_err(|e| io::Error::other(format!("Lock poisoned: {}", e)))?;
+        if *synced >= commit {
+            return Ok(());
+        }
+
+        // Every commit written by now is covered by this sync, not only ours.
+        // Commits write under this lock, so none of them can join the journal while the sync runs.
+        let target = self.written.load(Ordering::SeqCst);
+        match synchronous {
+            Synchronous::Off => {}
+            Synchronous::Normal => self.sync_file(true)?,
+            Synchronous::Full | Synchronous::Extra => self.sync_file(false)?,
+        }
+        self.journal()?.finish_group(target)?;
+        *synced = target;
+        Ok(())
+    }
+
+    /// Syncs the database file and counts it.
+    fn sync_file(&self, data_only: bool) -> io::Result<()> {
+        self.file.sync(data_only)?;
+        self.count.fetch_add(1, Ordering::SeqCst);
+        Ok(())
+    }
+}
+
+/// A savepoint of the active transaction, with the pages changed since it was opened.
+struct Savepoint {
+    name: String,
+    /// Original image of every page changed after the savepoint.
+    pages: HashMap<u32, Page>,
+    /// Number of pages of the database when the savepoint was opened.
+    page_count: u32,
+}
 
 /// Internal structure that manages the actual pager state
 /// This is wrapped in Arc<Mutex<>> to allow safe sharing between guards
@@ -36,16 +224,62 @@ struct PagerInner {
     disk_manager: DiskManager,
     page_cache: BufferPool,
     page_size: u32,
-    journal_pages: Vec<(u32, Page)>,
+    /// Original image of every page changed by the active transaction.
+    journal_pages: HashMap<u32, Page>,
+    /// Number of pages of the database when the active transaction began.
+    journal_page_count: u32,
+    /// Original header, if the active transaction changed it.
+    journal_header: Option<Header>,
     reserved_space: u8,
+    /// Whether the pages carry a checksum in their reserved space, as the header says.
+    checksums: bool,
+    /// Payload fractions the trees build their cells with, to split the payloads the same way when reading a page.
+    payload_fractions: (u8, u8),
     dirty: bool,
+    /// Callback deciding whether to retry a lock held by another process.
+    busy_handler: Option<BusyHandler>,
+    /// Whether an explicit transaction is active.
+    in_transaction: bool,
+    /// Number of read scopes currently open (see `begin_read`).
+    readers: usize,
+    /// Change counter of the header the last time we held a lock, to detect changes made by other processes.
+    change_counter: u32,
+    /// Thread that began the active transaction.
+    writer: Option<ThreadId>,
+    /// Number of transactions committed since the pager was opened. Snapshots are taken at one of these.
+    commit_seq: u64,
+    /// Open snapshots, counted by the commit they were taken at.
+    snapshots: BTreeMap<u64, usize>,
+    /// Committed images replaced while snapshots were open, with the commit that replaced each of them.
+    versions: HashMap<u32, Vec<(u64, Page)>>,
+    /// Savepoints of the active transaction, the innermost one last.
+    savepoints: Vec<Savepoint>,
+    /// When to sync the file.
+    synchronous: Synchronous,
+    /// Whether commits wait for a shared sync instead of syncing on their own.
+    group_commit: bool,
+    /// Syncs shared between commits.
+    sync_state: Arc<SyncState>,
+    /// Most pages to read ahead when the misses look like a sequential scan. Zero disables read-ahead.
+    read_ahead: u32,
+    /// Last page that missed the cache, or that read-ahead brought in.
+    last_miss: u32,
+    /// How many misses in a row were on the page right after the previous one.
+    sequential_misses: u32,
+    /// The thread writing dirty pages back in the background, if it is running.
+    background_writer: Option<WriterThread>,
 }
 
+/// Misses on consecutive pages it takes before we call it a sequential scan and start reading ahead.
+const SEQUENTIAL_MISSES: u32 = 2;
+
 /// RAII guard for immutable page access
 /// Automatically unpins the page when dropped
 pub struct PageGuard {
     page_number: u32,
     pager: Arc<Mutex<PagerInner>>,
+    /// Private copy of the page, for guards handed out by snapshots. Nothing is pinned in that case.
+    copy: Option<Page>,
     /// We used to store a raw pointer to avoid lifetime issues, but ensure safety through pinning
     /// I found this PhantomData thing is more memory safe and idiomatic
     _phantom: std::marker::PhantomData<Page>, // Aparently this is needed to avoid lifetime issues
@@ -55,6 +289,9 @@ pub struct PageGuard {
 impl PageGuard {
     /// Gets a reference to the page
     pub fn page(&self) -> &Page {
+        if let Some(page) = &self.copy {
+            return page;
+        }
         // This is a bit tricky - we need to ensure the page stays valid
         // We'll use a callback-based approach to ensure safety.
         // See the page callback methods in the pager for details.
@@ -73,6 +310,9 @@ impl PageGuard {
 
 impl Drop for PageGuard {
     fn drop(&mut self) {
+        if self.copy.is_some() {
+            return;
+        }
         // This ensures the page is unpinned when the guard goes out of scope
         if let Ok(mut inner) = self.pager.lock() {
             let _ = inner.page_cache.unpin_page(self.page_number);
@@ -130,9 +370,10 @@ impl Drop for PageGuardMut {
 }
 
 /// The Pager manages page-level operations on the database
-#[derive(Clone)]
 pub struct Pager {
     inner: Arc<Mutex<PagerInner>>,
+    /// Commit this pager is pinned to, if it is a read-only snapshot.
+    snapshot: Option<u64>,
 }
 
 impl Pager {
@@ -148,20 +389,61 @@ impl Pager {
     /// # Returns
     /// A new Pager instance
     pub fn open<P: AsRef<Path>>(path: P, buffer_pool_size: Option<usize>) -> io::Result<Self> {
-        let mut disk_manager = DiskManager::open(path)?;
+        Self::open_with_vfs(Arc::new(OsVfs), path, buffer_pool_size)
+    }
+
+    /// Opens an existing database file of the given VFS
+    ///
+    /// # Parameters
+    /// * `vfs` - The file system the database lives in (see vfs.rs)
+    /// * `path` - Path to the database file
+    /// * `buffer_pool_size` - Optional size of the buffer pool (default: 1000 pages)
+    ///
+    /// # Errors
+    /// Returns an error if the file cannot be opened or if the header is invalid
+    pub fn open_with_vfs<P: AsRef<Path>>(
+        vfs: Arc<dyn Vfs>,
+        path: P,
+        buffer_pool_size: Option<usize>,
+    ) -> io::Result<Self> {
+        let disk_manager = DiskManager::open_with_vfs(vfs.as_ref(), &path)?;
         let header = disk_manager.read_header()?;
+        // A hot journal is played back when the first lock is taken, not here (see `recover`)
+        let journal = Journal::new(vfs, path.as_ref(), header.page_size);
+        let sync_state = Arc::new(SyncState::new(disk_manager.file_handle(), journal));
 
         let inner = PagerInner {
             disk_manager,
             page_cache: BufferPool::new(buffer_pool_size.unwrap_or(1000)),
-            journal_pages: Vec::new(),
+            journal_pages: HashMap::new(),
+            journal_page_count: 0,
+            journal_header: None,
             page_size: header.page_size,
             reserved_space: header.reserved_space,
+            checksums: header.has_checksums(),
+            payload_fractions: (DEFAULT_MAX_PAYLOAD_FRACTION, DEFAULT_MIN_PAYLOAD_FRACTION),
             dirty: false,
+            busy_handler: None,
+            in_transaction: false,
+            readers: 0,
+            change_counter: header.change_counter,
+            writer: None,
+            commit_seq: 0,
+            snapshots: BTreeMap::new(),
+            versions: HashMap::new(),
+            savepoints: Vec::new(),
+            synchronous: Synchronous::default(),
+            group_commit: false,
+            sync_state,
+            read_ahead: 0,
+            last_miss: 0,
+            sequential_misses: 0,
+            background_writer: None,
         };
 
         Ok(Pager {
             inner: Arc::new(Mutex::new(inner)),
+            snapshot: None,
         })
     }
 
@@ -184,23 +466,78 @@ impl Pager {
         buffer_pool_size: Option<usize>,
         reserved_space: u8,
     ) -> io::Result<Self> {
-        let mut disk_manager = DiskManager::create(path, page_size)?;
+        Self::create_with_vfs(
+            Arc::new(OsVfs),
+            path,
+            page_size,
+            buffer_pool_size,
+            reserved_space,
+        )
+    }
+
+    /// Creates a new database file in the given VFS
+    ///
+    /// # Parameters
+    /// * `vfs` - The file system the database lives in (see vfs.rs)
+    /// * `path` - Path where to create the database file
+    /// * `page_size` - Size of each page in bytes (must be a power of 2 between 512 and 65536)
+    /// * `buffer_pool_size` - Optional size of the buffer pool (default: 1000 pages)
+    /// * `reserved_space` - Reserved space at the end of each page
+    ///
+    /// # Errors
+    /// Returns an error if the file cannot be created or if the page size is invalid
+    pub fn create_with_vfs<P: AsRef<Path>>(
+        vfs: Arc<dyn Vfs>,
+        path: P,
+        page_size: u32,
+        buffer_pool_size: Option<usize>,
+        reserved_space: u8,
+    ) -> io::Result<Self> {
+        let mut disk_manager = DiskManager::create_with_vfs(vfs.as_ref(), &path, page_size)?;
 
         let mut header = disk_manager.read_header()?;
         header.reserved_space = reserved_space;
         disk_manager.write_header(&header)?;
+        // The new file must not lose its header to a power loss, and a journal of an older file with this name means nothing now
+        disk_manager.sync()?;
+        let journal = Journal::new(vfs, path.as_ref(), page_size);
+        if journal.is_hot()? {
+            journal.delete_hot()?;
+        }
+        let sync_state = Arc::new(SyncState::new(disk_manager.file_handle(), journal));
 
         let inner = PagerInner {
             disk_manager,
             page_cache: BufferPool::new(buffer_pool_size.unwrap_or(1000)),
             page_size,
-            journal_pages: Vec::new(),
+            journal_pages: HashMap::new(),
+            journal_page_count: 0,
+            journal_header: None,
             reserved_space,
+            checksums: false,
+            payload_fractions: (DEFAULT_MAX_PAYLOAD_FRACTION, DEFAULT_MIN_PAYLOAD_FRACTION),
             dirty: false,
+            busy_handler: None,
+            in_transaction: false,
+            readers: 0,
+            change_counter: header.change_counter,
+            writer: None,
+            commit_seq: 0,
+            snapshots: BTreeMap::new(),
+            versions: HashMap::new(),
+            savepoints: Vec::new(),
+            synchronous: Synchronous::default(),
+            group_commit: false,
+            sync_state,
+            read_ahead: 0,
+            last_miss: 0,
+            sequential_misses: 0,
+            background_writer: None,
         };
 
         Ok(Pager {
             inner: Arc::new(Mutex::new(inner)),
+            snapshot: None,
         })
     }
 
@@ -230,13 +567,45 @@ impl Pager {
         let mut inner = self
             .inner
             .lock()
-            .map_err(|e| io::Error::new(io::ErrorKind::Other, format!("Lock poisoned: {}", e)))?;
+            .map_err(|e| io::Error::other(format!("Lock poisoned: {}", e)))?;
+
+        if let Some(seq) = self.snapshot {
+            let page = match Self::snapshot_copy(&inner, page_number, seq) {
+                Some(page) => page,
+                None => {
+                    // Misses read the file without the lock here too. A commit may land meanwhile,
+                    // so `snapshot_page` looks for a copy again once we have the lock back
+                    if !inner.page_cache.contains_page(page_number) {
+                        inner = Self::load_page(&self.inner, inner, page_number)?;
+                    }
+                    Self::snapshot_page(&inner, page_number, seq)?
+                }
+            };
+            if let Some(expected) = expected_type {
+                if page.page_type() != expected {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!(
+                            "Page type mismatch for page {}: expected {:?}, found {:?}",
+                            page_number,
+                            expected,
+                            page.page_type()
+                        ),
+                    ));
+                }
+            }
+            return Ok(PageGuard {
+                page_number,
+                pager: Arc::clone(&self.inner),
+                copy: Some(page),
+                _phantom: std::marker::PhantomData,
+            });
+        }
 
         // Load page if not in cache
-        if !inner.page_cache.contains_page_simple(page_number) {
+        if !inner.page_cache.contains_page(page_number) {
             // Load the page from disk
-            Self::load_page(&mut inner, page_number)?;
-            // Create and return the guard
+            inner = Self::load_page(&self.inner, inner, page_number)?;
         }
 
         // Validate page type if specified
@@ -252,6 +621,7 @@ impl Pager {
         Ok(PageGuard {
             page_number,
             pager: Arc::clone(&self.inner),
+            copy: None,
             _phantom: std::marker::PhantomData,
         })
     }
@@ -277,44 +647,28 @@ impl Pager {
         page_number: u32,
         expected_type: Option<PageType>,
     ) -> io::Result<PageGuardMut> {
-        let page_for_journal = {
-            let mut inner = self.inner.lock().map_err(|e| {
-                io::Error::new(io::ErrorKind::Other, format!("Lock poisoned: {}", e))
-            })?;
-
-            // Load page if not in cache
-            if !inner.page_cache.contains_page_simple(page_number) {
-                Self::load_page(&mut inner, page_number)?;
-            }
-
-            // Validate page type if specified
-            if let Some(expected) = expected_type {
-                inner
-                    .page_cache
-                    .validate_page_type(page_number, expected)
-                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
-            }
-
-            // Add to journal for transaction support
-            let result = inner.page_cache.get_page_for_journal(page_number);
-
-            if let Some(page) = result {
-                Some(page.clone())
-            } else {
-                None
-            }
-        };
-
+        self.require_writable()?;
         let mut inner = self
             .inner
             .lock()
-            .map_err(|e| io::Error::new(io::ErrorKind::Other, format!("Lock poisoned: {}", e)))?;
+            .map_err(|e| io::Error::other(format!("Lock poisoned: {}", e)))?;
+
+        // Load page if not in cache
+        if !inner.page_cache.contains_page(page_number) {
+            inner = Self::load_page(&self.inner, inner, page_number)?;
+        }
 
-        // Add the page to the journal if it was not already there
-        if let Some(page) = page_for_journal {
-            inner.journal_pages.push((page_number, page.clone()));
+        // Validate page type if specified
+        if let Some(expected) = expected_type {
+            inner
+                .page_cache
+                .validate_page_type(page_number, expected)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
         }
 
+        // Copy the page into the journal the first time the transaction changes it
+        Self::journal_page(&mut inner, page_number);
+
         // Pin the page for mutable access
         inner.page_cache.pin_page_for_guard_mut(page_number)?;
         // We are dirty now
@@ -375,7 +729,7 @@ impl Pager {
         F: FnOnce(&mut Page) -> io::Result<R>,
     {
         let mut guard = self.get_page_mut(page_number, expected_type)?;
-        f(&mut guard.page_mut())
+        f(guard.page_mut())
     }
 
     /// Gets the header of the database
@@ -386,10 +740,10 @@ impl Pager {
     /// # Returns
     /// The database header
     pub fn get_header(&self) -> io::Result<Header> {
-        let mut inner = self
+        let inner = self
             .inner
             .lock()
-            .map_err(|e| io::Error::new(io::ErrorKind::Other, format!("Lock poisoned: {}", e)))?;
+            .map_err(|e| io::Error::other(format!("Lock poisoned: {}", e)))?;
         inner.disk_manager.read_header()
     }
 
@@ -401,10 +755,16 @@ impl Pager {
     /// # Errors
     /// Returns an error if the header cannot be written
     pub fn update_header(&self, header: &Header) -> io::Result<()> {
+        self.require_writable()?;
         let mut inner = self
             .inner
             .lock()
-            .map_err(|e| io::Error::new(io::ErrorKind::Other, format!("Lock poisoned: {}", e)))?;
+            .map_err(|e| io::Error::other(format!("Lock poisoned: {}", e)))?;
+        Self::acquire(&mut inner, LockLevel::Exclusive)?;
+        if inner.in_transaction && inner.journal_header.is_none() {
+            inner.journal_header = Some(inner.disk_manager.read_header()?);
+        }
+        Self::write_journal(&mut inner)?;
         inner.disk_manager.write_header(header)?;
         inner.dirty = true;
         Ok(())
@@ -426,11 +786,14 @@ impl Pager {
         page_type: PageType,
         right_most_page: Option<u32>,
     ) -> io::Result<u32> {
+        self.require_writable()?;
         let mut inner = self
             .inner
             .lock()
-            .map_err(|e| io::Error::new(io::ErrorKind::Other, format!("Lock poisoned: {}", e)))?;
+            .map_err(|e| io::Error::other(format!("Lock poisoned: {}", e)))?;
 
+        Self::acquire(&mut inner, LockLevel::Exclusive)?;
+        Self::write_journal(&mut inner)?;
         let page_number = inner.disk_manager.allocate_pages(1)?;
         let btree_page = BTreePage::new(
             page_type,
@@ -444,8 +807,8 @@ impl Pager {
         let buffer = Self::serialize_page(&inner, &page)?;
         inner.disk_manager.write_page(page_number, &buffer)?;
 
-        // Add to cache
-        inner.page_cache.add_page(page_number, page, false);
+        // Add to cache, writing back whatever dirty page gets evicted to make room
+        Self::cache_page(&mut inner, page_number, page)?;
 
         Ok(page_number)
     }
@@ -464,10 +827,11 @@ impl Pager {
     /// # Returns
     /// The page number of the newly created page
     pub fn create_overflow_page(&self, next_page: u32, data: Vec<u8>) -> io::Result<u32> {
+        self.require_writable()?;
         let mut inner = self
             .inner
             .lock()
-            .map_err(|e| io::Error::new(io::ErrorKind::Other, format!("Lock poisoned: {}", e)))?;
+            .map_err(|e| io::Error::other(format!("Lock poisoned: {}", e)))?;
 
         let max_data_size = inner.page_size as usize - 13; // Reserve 4 bytes for the next page pointer
         if data.len() > max_data_size {
@@ -481,13 +845,16 @@ impl Pager {
             ));
         }
 
+        Self::acquire(&mut inner, LockLevel::Exclusive)?;
+        Self::write_journal(&mut inner)?;
         let page_number = inner.disk_manager.allocate_pages(1)?;
         let overflow_page = OverflowPage::new(next_page, data, inner.page_size, page_number)?;
 
         let page = Page::Overflow(overflow_page);
         let buffer = Self::serialize_page(&inner, &page)?;
         inner.disk_manager.write_page(page_number, &buffer)?;
-        inner.disk_manager.sync()?;
+        // The commit syncs the page with the rest, as the synchronous level says
+        inner.dirty = true;
         Ok(page_number)
     }
 
@@ -502,11 +869,14 @@ impl Pager {
     /// # Returns
     /// The page number of the newly created page
     pub fn create_free_page(&self, next_page: u32) -> io::Result<u32> {
+        self.require_writable()?;
         let mut inner = self
             .inner
             .lock()
-            .map_err(|e| io::Error::new(io::ErrorKind::Other, format!("Lock poisoned: {}", e)))?;
+            .map_err(|e| io::Error::other(format!("Lock poisoned: {}", e)))?;
 
+        Self::acquire(&mut inner, LockLevel::Exclusive)?;
+        Self::write_journal(&mut inner)?;
         let page_number = inner.disk_manager.allocate_pages(1)?;
         let free_page = FreePage::new(next_page, inner.page_size, page_number);
 
@@ -517,165 +887,1297 @@ impl Pager {
         Ok(page_number)
     }
 
-    /// Begins a new transaction
+    /// Begins a new transaction.
+    /// The RESERVED lock is taken right away, so that two transactions of different processes cannot deadlock
+    /// waiting for each other to release their SHARED locks.
     ///
     /// # Errors
-    /// Currently always succeeds
+    /// Returns an error if a transaction is already active, or a `Busy` error if another process
+    /// holds the RESERVED lock and the busy handler gives up.
     pub fn begin_transaction(&self) -> io::Result<()> {
+        self.require_writable()?;
         let mut inner = self
             .inner
             .lock()
-            .map_err(|e| io::Error::new(io::ErrorKind::Other, format!("Lock poisoned: {}", e)))?;
+            .map_err(|e| io::Error::other(format!("Lock poisoned: {}", e)))?;
+
+        if inner.in_transaction {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Cannot start a transaction within a transaction",
+            ));
+        }
+
+        Self::acquire(&mut inner, LockLevel::Reserved)?;
+        inner.in_transaction = true;
+        inner.writer = Some(thread::current().id());
         inner.journal_pages.clear();
+        inner.journal_page_count = inner.disk_manager.page_count()?;
+        inner.journal_header = None;
         inner.dirty = true;
         Ok(())
     }
 
-    /// Commits the current transaction
+    /// Commits the current transaction, writing its pages under the EXCLUSIVE lock and releasing it afterwards.
+    /// Returns once the pages are synced as `Synchronous` requires.
     ///
     /// # Errors
-    /// Returns an error if the flush operation fails
+    /// Returns an error if no transaction is active or if the flush operation fails.
+    /// On a `Busy` error the transaction stays active, so the commit can be retried.
     pub fn commit_transaction(&self) -> io::Result<()> {
-        self.flush()?;
+        let commit = self.commit_transaction_deferred()?;
+        self.sync_commit(commit)
+    }
+
+    /// Commits the current transaction like `commit_transaction`, but with group commit enabled
+    /// it does not wait for the sync: call `sync_commit` for that.
+    /// This lets the caller release its own locks before waiting, so the next transaction can start in the meantime.
+    ///
+    /// # Errors
+    /// Returns an error if no transaction is active or if the flush operation fails.
+    ///
+    /// # Returns
+    /// The number of the commit, to pass to `sync_commit`.
+    pub fn commit_transaction_deferred(&self) -> io::Result<u64> {
+        self.require_writable()?;
         let mut inner = self
             .inner
             .lock()
-            .map_err(|e| io::Error::new(io::ErrorKind::Other, format!("Lock poisoned: {}", e)))?;
-        inner.journal_pages.clear();
-        Ok(())
+            .map_err(|e| io::Error::other(format!("Lock poisoned: {}", e)))?;
+
+        Self::require_transaction(&inner)?;
+        let deferred = inner.group_commit;
+        // A group sync cannot run while we write, or it could delete the journal our pages depend on
+        let sync_state = Arc::clone(&inner.sync_state);
+        let mut synced = sync_state
+            .synced
+            .lock()
+            .map_err(|e| io::Error::other(format!("Lock poisoned: {}", e)))?;
+        if inner.page_cache.dirty_page_count() > 0
+            || inner.disk_manager.lock_level() == LockLevel::Exclusive
+        {
+            Self::append_journal(&mut inner)?;
+        }
+        Self::flush_inner(&mut inner, deferred)?;
+        inner.commit_seq += 1;
+        let commit = inner.commit_seq;
+        inner.sync_state.written.store(commit, Ordering::SeqCst);
+        if deferred {
+            sync_state.journal()?.set_pending(commit);
+        } else {
+            // Synced already, nobody has to wait for this one, nor for the commits of the group it joined
+            sync_state.journal()?.finish()?;
+            *synced = (*synced).max(commit);
+        }
+        drop(synced);
+
+        // Open snapshots still need the images this commit replaced
+        let journal_pages = std::mem::take(&mut inner.journal_pages);
+        if !inner.snapshots.is_empty() {
+            for (page_number, page) in journal_pages {
+                inner.versions.entry(page_number).or_default().push((commit, page));
+            }
+        }
+        inner.journal_header = None;
+
+        inner.savepoints.clear();
+        inner.in_transaction = false;
+        inner.writer = None;
+        Self::release(&mut inner)?;
+        Ok(commit)
     }
 
-    /// Rolls back the current transaction
+    /// Waits until a commit is on stable storage. With group commit, the first committer to get here syncs the file
+    /// for every commit written so far, and the others just wait for it.
+    ///
+    /// # Parameters
+    /// * `commit` - Number of the commit, as returned by `commit_transaction_deferred`.
     ///
     /// # Errors
-    /// Returns an error if pages cannot be restored from the journal
-    pub fn rollback_transaction(&self) -> io::Result<()> {
+    /// Returns an error if the file cannot be synced. The commit is already visible in that case, but it may not survive a power loss.
+    pub fn sync_commit(&self, commit: u64) -> io::Result<()> {
+        let (sync_state, synchronous) = {
+            let inner = self
+                .inner
+                .lock()
+                .map_err(|e| io::Error::other(format!("Lock poisoned: {}", e)))?;
+            (Arc::clone(&inner.sync_state), inner.synchronous)
+        };
+        sync_state.sync_commit(commit, synchronous)?;
+
+        // The EXCLUSIVE lock was kept while the journal of the group was around
         let mut inner = self
             .inner
             .lock()
-            .map_err(|e| io::Error::new(io::ErrorKind::Other, format!("Lock poisoned: {}", e)))?;
-
-        // Restore pages from journal
-        // Cloning the pages may not be the most efficient way, but it's safe
-        let journal_pages = inner.journal_pages.clone();
-
-        for (page_number, page) in journal_pages.iter() {
-            let buffer = Self::serialize_page(&inner, page)?;
-            inner.disk_manager.write_page(*page_number, &buffer)?;
+            .map_err(|e| io::Error::other(format!("Lock poisoned: {}", e)))?;
+        Self::release(&mut inner)
+    }
 
-            // Update cache with restored page
-            inner
-                .page_cache
-                .update_page(*page_number, page.clone())
-                .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
-        }
+    /// Sets how often the file is synced.
+    ///
+    /// # Parameters
+    /// * `synchronous` - The synchronous level.
+    ///
+    /// # Errors
+    /// Returns an error if the pager lock is poisoned.
+    pub fn set_synchronous(&self, synchronous: Synchronous) -> io::Result<()> {
+        let mut inner = self
+            .inner
+            .lock()
+            .map_err(|e| io::Error::other(format!("Lock poisoned: {}", e)))?;
+        inner.synchronous = synchronous;
+        Ok(())
+    }
 
-        inner.page_cache.mark_clean_all();
-        inner.dirty = false;
-        inner.journal_pages.clear();
+    /// Sets the payload fractions the trees of this database build their cells with (see `PayloadLimits`).
+    /// Pages are read back with them, so they must be set before any page with an overflow cell is read.
+    /// The default ones are those of `RQLiteConfig`.
+    ///
+    /// # Parameters
+    /// * `max_payload_fraction` - Maximum fraction of a page that can be occupied by a payload.
+    /// * `min_payload_fraction` - Minimum fraction of a page that must be occupied by a payload.
+    pub fn set_payload_fractions(
+        &self,
+        max_payload_fraction: u8,
+        min_payload_fraction: u8,
+    ) -> io::Result<()> {
+        let mut inner = self
+            .inner
+            .lock()
+            .map_err(|e| io::Error::other(format!("Lock poisoned: {}", e)))?;
+        inner.payload_fractions = (max_payload_fraction, min_payload_fraction);
         Ok(())
     }
 
-    /// Flushes all dirty pages to disk
+    /// Turns on page checksums (see `utils::checksum`) for a database that was just created,
+    /// and records it in the header so every later open checks them too.
+    /// Databases that did not opt in when they were created never use them, whatever their reserved space:
+    /// older files may keep anything in there.
     ///
     /// # Errors
-    /// Returns an error if pages cannot be written to disk
-    pub fn flush(&self) -> io::Result<()> {
+    /// Returns an `InvalidInput` error if the reserved space has no room for a checksum,
+    /// or if the database already has pages, which were written without one.
+    pub fn enable_checksums(&self) -> io::Result<()> {
         let mut inner = self
             .inner
             .lock()
-            .map_err(|e| io::Error::new(io::ErrorKind::Other, format!("Lock poisoned: {}", e)))?;
-
-        if !inner.dirty {
-            return Ok(());
+            .map_err(|e| io::Error::other(format!("Lock poisoned: {}", e)))?;
+        if (inner.reserved_space as usize) < CHECKSUM_SIZE {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "Page checksums need at least {} bytes of reserved space, the database has {}",
+                    CHECKSUM_SIZE, inner.reserved_space
+                ),
+            ));
         }
-
-        // Get all dirty pages
-        let dirty_pages = inner
-            .page_cache
-            .get_dirty_pages()
-            .into_iter()
-            .map(|(n, p)| (n, p.clone()))
-            .collect::<Vec<_>>();
-
-        // Write each dirty page to disk
-        for (page_number, page) in dirty_pages {
-            inner.page_cache.prepare_page_for_write(page_number);
-
-            let buffer = Self::serialize_page(&inner, &page)?;
-            inner.disk_manager.write_page(page_number, &buffer)?;
-
-            inner.page_cache.finish_page_write(page_number);
-            inner.page_cache.mark_clean(page_number);
+        if inner.in_transaction || inner.disk_manager.page_count()? > 1 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Page checksums can only be enabled on a new database",
+            ));
         }
 
+        let mut header = inner.disk_manager.read_header()?;
+        header.set_checksums(true);
+        inner.disk_manager.write_header(&header)?;
         inner.disk_manager.sync()?;
-        inner.dirty = false;
+        inner.checksums = true;
         Ok(())
     }
 
-    /// Closes the pager, flushing any pending changes
+    /// Enables or disables group commit (see the module docs).
+    ///
+    /// # Parameters
+    /// * `enabled` - Whether concurrent commits share their syncs.
     ///
     /// # Errors
-    /// Returns an error if the flush operation fails
-    pub fn close(self) -> io::Result<()> {
-        self.flush() // ?? Maybe we should not flush here?
+    /// Returns an error if the pager lock is poisoned.
+    pub fn set_group_commit(&self, enabled: bool) -> io::Result<()> {
+        let mut inner = self
+            .inner
+            .lock()
+            .map_err(|e| io::Error::other(format!("Lock poisoned: {}", e)))?;
+        inner.group_commit = enabled;
+        Ok(())
     }
 
-    /// Gets the total number of pages in the database
+    /// Sets how many bytes of the database file are memory mapped for reading (see `DiskManager::set_mmap_size`).
+    ///
+    /// # Parameters
+    /// * `mmap_size` - Most bytes to map. Zero turns the map off.
     ///
     /// # Errors
-    /// Returns an error if the page count cannot be determined
+    /// Returns an error if the pager lock is poisoned or the size of the file cannot be read.
+    pub fn set_mmap_size(&self, mmap_size: u64) -> io::Result<()> {
+        let mut inner = self
+            .inner
+            .lock()
+            .map_err(|e| io::Error::other(format!("Lock poisoned: {}", e)))?;
+        inner.disk_manager.set_mmap_size(mmap_size)
+    }
+
+    /// Sets how many pages to read ahead when the pager detects a sequential scan (see `read_ahead`).
     ///
-    /// # Returns
-    /// The total number of pages
-    pub fn page_count(&self) -> io::Result<u32> {
+    /// # Parameters
+    /// * `pages` - Most pages to read ahead at a time. Zero disables read-ahead.
+    ///
+    /// # Errors
+    /// Returns an error if the pager lock is poisoned.
+    pub fn set_read_ahead(&self, pages: u32) -> io::Result<()> {
+        let mut inner = self
+            .inner
+            .lock()
+            .map_err(|e| io::Error::other(format!("Lock poisoned: {}", e)))?;
+        inner.read_ahead = pages;
+        Ok(())
+    }
+
+    /// Gets the statistics of the buffer pool: hits, misses, evictions, read-ahead, and so on.
+    ///
+    /// # Errors
+    /// Returns an error if the pager lock is poisoned.
+    pub fn cache_stats(&self) -> io::Result<BufferPoolStats> {
         let inner = self
             .inner
             .lock()
-            .map_err(|e| io::Error::new(io::ErrorKind::Other, format!("Lock poisoned: {}", e)))?;
-        inner.disk_manager.page_count()
+            .map_err(|e| io::Error::other(format!("Lock poisoned: {}", e)))?;
+        Ok(inner.page_cache.get_stats().clone())
     }
 
-    // Private helper methods
+    /// Starts, reconfigures or stops the background writer.
+    ///
+    /// Without it, the dirty pages of a transaction are only written by the commit, or by whoever needs
+    /// their frame when the cache is full, which is usually a reader missing the cache in `load_page`.
+    /// The writer is a thread that wakes up every `interval` and writes back the dirty pages nobody is using
+    /// when too much of the cache is dirty or when they have been dirty for too long, so those readers
+    /// find clean frames to evict. It spills pages the same way the eviction does: the journal goes first,
+    /// under the EXCLUSIVE lock. It never waits for the lock, if other processes are reading it tries again later.
+    /// It stops with the last handle of the pager (`close` or `drop`).
+    ///
+    /// # Parameters
+    /// * `config` - When to write back the pages, or `None` to stop the writer.
+    ///
+    /// # Errors
+    /// Returns an error if this is a snapshot, the thread cannot be started, or the previous one panicked.
+    pub fn set_background_writer(&self, config: Option<BackgroundWriter>) -> io::Result<()> {
+        self.require_writable()?;
+        self.stop_background_writer()?;
+        let Some(config) = config else {
+            return Ok(());
+        };
 
-    /// Loads a page from disk into the cache
-    fn load_page(inner: &mut PagerInner, page_number: u32) -> io::Result<()> {
-        let page_count = inner.disk_manager.page_count()?;
-        if page_number == 0 || page_number > page_count {
-            return Err(io::Error::new(
-                io::ErrorKind::NotFound,
-                format!("Page number out of range: {}", page_number),
-            ));
+        let stop = Arc::new((Mutex::new(false), Condvar::new()));
+        let pager = Arc::downgrade(&self.inner);
+        let thread_stop = Arc::clone(&stop);
+        let handle = thread::Builder::new()
+            .name("rqlite-writer".to_string())
+            .spawn(move || Self::run_background_writer(pager, thread_stop, config))?;
+
+        let replaced = {
+            let mut inner = self
+                .inner
+                .lock()
+                .map_err(|e| io::Error::other(format!("Lock poisoned: {}", e)))?;
+            inner
+                .background_writer
+                .replace(WriterThread { stop, handle })
+        };
+        // Somebody else started one in the meantime
+        match replaced {
+            Some(writer) => writer.stop(),
+            None => Ok(()),
         }
+    }
 
-        // Read page from disk
-        let mut buffer = vec![0u8; inner.page_size as usize];
-        inner.disk_manager.read_page(page_number, &mut buffer)?;
+    /// Gets the number of times the file was synced since the pager was opened.
+    /// Handy to see what the synchronous level and group commit are doing.
+    ///
+    /// # Errors
+    /// Returns an error if the pager lock is poisoned.
+    pub fn sync_count(&self) -> io::Result<u64> {
+        let inner = self
+            .inner
+            .lock()
+            .map_err(|e| io::Error::other(format!("Lock poisoned: {}", e)))?;
+        Ok(inner.sync_state.count.load(Ordering::SeqCst))
+    }
 
-        // Parse the page
-        let page = Self::parse_page(inner, page_number, &buffer)?;
-        // Create and return the guard
+    /// Rolls back the current transaction
+    ///
+    /// # Errors
+    /// Returns an error if no transaction is active or if pages cannot be restored from the journal
+    pub fn rollback_transaction(&self) -> io::Result<()> {
+        self.require_writable()?;
+        let mut inner = self
+            .inner
+            .lock()
+            .map_err(|e| io::Error::other(format!("Lock poisoned: {}", e)))?;
 
-        // Add to cache, handling eviction if necessary.
-        // There is a risk of deadlock here if the cache is full and we try to evict a page
-        // that is currently being accessed. This should be handled by the cache itself.
+        Self::require_transaction(&inner)?;
+
+        // Pages only reach the disk during a transaction under the EXCLUSIVE lock.
+        // Without it the file still has the original pages, and only the cache must be restored.
+        let spilled = inner.disk_manager.lock_level() == LockLevel::Exclusive;
+
+        // Restore pages from journal
+        let journal_pages = std::mem::take(&mut inner.journal_pages);
+
+        for (page_number, page) in journal_pages {
+            if spilled {
+                let buffer = Self::serialize_page(&inner, &page)?;
+                inner.disk_manager.write_page(page_number, &buffer)?;
+            }
+
+            // Update cache with restored page
+            if inner.page_cache.contains_page_simple(page_number) {
+                inner
+                    .page_cache
+                    .update_page(page_number, page)
+                    .map_err(io::Error::other)?;
+            }
+        }
+
+        if let Some(header) = inner.journal_header.take() {
+            inner.disk_manager.write_header(&header)?;
+        }
+        let page_count = inner.journal_page_count;
+        Self::truncate(&mut inner, page_count)?;
+
+        // The file is back as it was, so the journal of the transaction is not needed anymore.
+        // A journal of commits waiting for their group sync is not ours, it stays.
+        let sync_state = Arc::clone(&inner.sync_state);
+        let mut journal = sync_state.journal()?;
+        if journal.is_open() && journal.pending().is_none() {
+            Self::sync_file(&mut inner)?;
+            journal.finish()?;
+        }
+        drop(journal);
+
+        inner.page_cache.mark_clean_all();
+        inner.dirty = false;
+        inner.savepoints.clear();
+        inner.in_transaction = false;
+        inner.writer = None;
+        Self::release(&mut inner)
+    }
+
+    /// Opens a savepoint in the active transaction.
+    /// Savepoints can be nested, and several of them can have the same name: the innermost one is used.
+    ///
+    /// # Parameters
+    /// * `name` - Name of the savepoint. Names are case-insensitive, as in SQLite.
+    ///
+    /// # Errors
+    /// Returns an error if no transaction is active.
+    pub fn savepoint(&self, name: &str) -> io::Result<()> {
+        self.require_writable()?;
+        let mut inner = self
+            .inner
+            .lock()
+            .map_err(|e| io::Error::other(format!("Lock poisoned: {}", e)))?;
+
+        Self::require_transaction(&inner)?;
+        let page_count = inner.disk_manager.page_count()?;
+        inner.savepoints.push(Savepoint {
+            name: name.to_string(),
+            pages: HashMap::new(),
+            page_count,
+        });
+        Ok(())
+    }
+
+    /// Releases a savepoint and every savepoint opened after it, keeping their changes in the transaction.
+    ///
+    /// # Parameters
+    /// * `name` - Name of the savepoint.
+    ///
+    /// # Errors
+    /// Returns an error if no transaction is active or there is no savepoint with that name.
+    pub fn release_savepoint(&self, name: &str) -> io::Result<()> {
+        self.require_writable()?;
+        let mut inner = self
+            .inner
+            .lock()
+            .map_err(|e| io::Error::other(format!("Lock poisoned: {}", e)))?;
+
+        let position = Self::find_savepoint(&inner, name)?;
+        inner.savepoints.truncate(position);
+        Ok(())
+    }
+
+    /// Undoes the changes made since a savepoint was opened.
+    /// The savepoints opened after it are released, and the savepoint itself stays open, so it can be rolled back to again.
+    ///
+    /// # Parameters
+    /// * `name` - Name of the savepoint.
+    ///
+    /// # Errors
+    /// Returns an error if no transaction is active, there is no savepoint with that name,
+    /// or the original pages cannot be put back in the cache.
+    pub fn rollback_to_savepoint(&self, name: &str) -> io::Result<()> {
+        self.require_writable()?;
+        let mut inner = self
+            .inner
+            .lock()
+            .map_err(|e| io::Error::other(format!("Lock poisoned: {}", e)))?;
+
+        let position = Self::find_savepoint(&inner, name)?;
+        inner.savepoints.truncate(position + 1);
+        let pages = std::mem::take(&mut inner.savepoints[position].pages);
+        let page_count = inner.savepoints[position].page_count;
+
+        // The restored pages still differ from the file (or may), so they go back to the cache as dirty pages
+        for (page_number, page) in pages {
+            if inner.page_cache.contains_page_simple(page_number) {
+                inner
+                    .page_cache
+                    .update_page(page_number, page)
+                    .map_err(io::Error::other)?;
+            } else {
+                Self::cache_page(&mut inner, page_number, page)?;
+                inner.page_cache.mark_dirty(page_number);
+            }
+        }
+        Self::truncate(&mut inner, page_count)?;
+        inner.dirty = true;
+        Ok(())
+    }
+
+    /// Checks if an explicit transaction is active.
+    ///
+    /// # Errors
+    /// Returns an error if the pager lock is poisoned.
+    pub fn in_transaction(&self) -> io::Result<bool> {
+        let inner = self
+            .inner
+            .lock()
+            .map_err(|e| io::Error::other(format!("Lock poisoned: {}", e)))?;
+        Ok(inner.in_transaction)
+    }
+
+    /// Checks if the active transaction was begun by the calling thread.
+    /// That thread must read the live pages to see its own changes, every other one should read a snapshot.
+    ///
+    /// # Errors
+    /// Returns an error if the pager lock is poisoned.
+    pub fn owns_transaction(&self) -> io::Result<bool> {
+        let inner = self
+            .inner
+            .lock()
+            .map_err(|e| io::Error::other(format!("Lock poisoned: {}", e)))?;
+        Ok(inner.in_transaction && inner.writer == Some(thread::current().id()))
+    }
+
+    /// Opens a read-only view of the database as of the last commit.
+    /// The view does not see the changes of the active transaction nor the ones committed after it was taken,
+    /// and it holds a read scope (see `begin_read`) until it is dropped.
+    /// Taking a snapshot of a snapshot gives another view of the same commit.
+    ///
+    /// # Errors
+    /// Returns a `Busy` error if a writer of another process is about to commit and the busy handler gives up.
+    ///
+    /// # Returns
+    /// A pager that only allows reads. Its pages are private copies, so they can be held as long as needed.
+    pub fn snapshot(&self) -> io::Result<Pager> {
+        let mut inner = self
+            .inner
+            .lock()
+            .map_err(|e| io::Error::other(format!("Lock poisoned: {}", e)))?;
+        Self::acquire(&mut inner, LockLevel::Shared)?;
+        inner.readers += 1;
+
+        let seq = self.snapshot.unwrap_or(inner.commit_seq);
+        *inner.snapshots.entry(seq).or_insert(0) += 1;
+
+        Ok(Pager {
+            inner: Arc::clone(&self.inner),
+            snapshot: Some(seq),
+        })
+    }
+
+    /// Checks if this pager is a read-only snapshot.
+    pub fn is_snapshot(&self) -> bool {
+        self.snapshot.is_some()
+    }
+
+    /// Opens a read scope, taking the SHARED lock if no lock is held yet.
+    /// Read scopes can be nested and shared between threads, the lock is released when the last one ends.
+    /// Every call must be paired with a call to `end_read`.
+    ///
+    /// # Errors
+    /// Returns a `Busy` error if a writer is about to commit and the busy handler gives up.
+    pub fn begin_read(&self) -> io::Result<()> {
+        let mut inner = self
+            .inner
+            .lock()
+            .map_err(|e| io::Error::other(format!("Lock poisoned: {}", e)))?;
+        Self::acquire(&mut inner, LockLevel::Shared)?;
+        inner.readers += 1;
+        Ok(())
+    }
+
+    /// Closes a read scope opened with `begin_read`.
+    ///
+    /// # Errors
+    /// Returns an error if the lock cannot be released.
+    pub fn end_read(&self) -> io::Result<()> {
+        let mut inner = self
+            .inner
+            .lock()
+            .map_err(|e| io::Error::other(format!("Lock poisoned: {}", e)))?;
+        inner.readers = inner.readers.saturating_sub(1);
+        Self::release(&mut inner)
+    }
+
+    /// Sets the callback used to retry locks held by other processes.
+    /// With no handler, a busy lock fails right away.
+    ///
+    /// # Parameters
+    /// * `handler` - The busy handler (see `lock::timeout_handler`), or `None` to remove it.
+    ///
+    /// # Errors
+    /// Returns an error if the pager lock is poisoned.
+    pub fn set_busy_handler(&self, handler: Option<BusyHandler>) -> io::Result<()> {
+        let mut inner = self
+            .inner
+            .lock()
+            .map_err(|e| io::Error::other(format!("Lock poisoned: {}", e)))?;
+        inner.busy_handler = handler;
+        Ok(())
+    }
+
+    /// Asks the busy handler whether to keep waiting for something that is busy, like `acquire` does for the
+    /// file locks. Used by the writers of other threads while a transaction is active.
+    /// The pager lock is not held while the handler sleeps.
+    ///
+    /// # Parameters
+    /// * `retries` - Number of times the handler was already called for the same wait.
+    ///
+    /// # Errors
+    /// Returns an error if the pager lock is poisoned.
+    ///
+    /// # Returns
+    /// `true` to try again, `false` to give up. Without a handler, it gives up right away.
+    pub fn busy_retry(&self, retries: u32) -> io::Result<bool> {
+        let handler = self
+            .inner
+            .lock()
+            .map_err(|e| io::Error::other(format!("Lock poisoned: {}", e)))?
+            .busy_handler
+            .clone();
+        Ok(handler.is_some_and(|handler| handler(retries)))
+    }
+
+    /// Gets the level of the lock this pager holds on the database file.
+    ///
+    /// # Errors
+    /// Returns an error if the pager lock is poisoned.
+    pub fn lock_level(&self) -> io::Result<LockLevel> {
+        let inner = self
+            .inner
+            .lock()
+            .map_err(|e| io::Error::other(format!("Lock poisoned: {}", e)))?;
+        Ok(inner.disk_manager.lock_level())
+    }
+
+    /// Flushes all dirty pages to disk.
+    /// Outside of a transaction the file lock is released afterwards.
+    ///
+    /// # Errors
+    /// Returns an error if pages cannot be written to disk, or a `Busy` error if other processes are still reading.
+    pub fn flush(&self) -> io::Result<()> {
+        let mut inner = self
+            .inner
+            .lock()
+            .map_err(|e| io::Error::other(format!("Lock poisoned: {}", e)))?;
+
+        if inner.in_transaction {
+            Self::write_journal(&mut inner)?;
+        } else {
+            Self::finish_pending(&mut inner)?;
+        }
+        Self::flush_inner(&mut inner, false)?;
+        Self::release(&mut inner)
+    }
+
+    /// Closes the pager, flushing any pending changes.
+    /// A transaction that is still active is rolled back, as nobody committed it.
+    /// The background writer is stopped if this is the last handle.
+    ///
+    /// # Errors
+    /// Returns an error if the rollback or the flush operation fails
+    pub fn close(self) -> io::Result<()> {
+        if self.snapshot.is_none() && self.is_last_handle() {
+            self.stop_background_writer()?;
+        }
+        if self.snapshot.is_none() && self.in_transaction()? {
+            self.rollback_transaction()?;
+        }
+        self.flush()
+    }
+
+    /// Gets the total number of pages in the database
+    ///
+    /// # Errors
+    /// Returns an error if the page count cannot be determined
+    ///
+    /// # Returns
+    /// The total number of pages
+    pub fn page_count(&self) -> io::Result<u32> {
+        let inner = self
+            .inner
+            .lock()
+            .map_err(|e| io::Error::other(format!("Lock poisoned: {}", e)))?;
+        inner.disk_manager.page_count()
+    }
+
+    // Private helper methods
+
+    /// Writes all dirty pages to disk under the EXCLUSIVE lock and bumps the change counter.
+    /// The final sync is left to the caller when `defer_sync` is set.
+    fn flush_inner(inner: &mut PagerInner, defer_sync: bool) -> io::Result<()> {
+        if !inner.dirty {
+            return Ok(());
+        }
+
+        // Get all dirty pages
+        let dirty_pages = inner
+            .page_cache
+            .get_dirty_pages()
+            .into_iter()
+            .map(|(n, p)| (n, p.clone()))
+            .collect::<Vec<_>>();
+
+        if !dirty_pages.is_empty() {
+            Self::acquire(inner, LockLevel::Exclusive)?;
+        }
+
+        let wrote_pages = !dirty_pages.is_empty();
+        Self::write_dirty_pages(inner, &dirty_pages)?;
+
+        // Let other processes know that their cached pages are stale
+        if inner.disk_manager.lock_level() == LockLevel::Exclusive {
+            if wrote_pages && inner.synchronous == Synchronous::Extra {
+                Self::sync_file(inner)?;
+            }
+            let mut header = inner.disk_manager.read_header()?;
+            header.increment_change_counter();
+            inner.disk_manager.write_header(&header)?;
+            inner.change_counter = header.change_counter;
+        }
+
+        if !defer_sync {
+            Self::sync_file(inner)?;
+        }
+        inner.dirty = false;
+        Ok(())
+    }
+
+    /// Writes pages of the cache to the file in one batch, so that runs of contiguous pages share a syscall.
+    /// The pages are marked clean only if the whole batch was written.
+    fn write_dirty_pages(inner: &mut PagerInner, pages: &[(u32, Page)]) -> io::Result<()> {
+        let mut buffers = Vec::with_capacity(pages.len());
+        for (page_number, page) in pages {
+            inner.page_cache.prepare_page_for_write(*page_number);
+            buffers.push((*page_number, Self::serialize_page(inner, page)?));
+        }
+        let batch = buffers
+            .iter()
+            .map(|(page_number, buffer)| (*page_number, buffer.as_slice()))
+            .collect::<Vec<_>>();
+        let written = inner.disk_manager.write_pages(&batch);
+        for (page_number, _) in pages {
+            inner.page_cache.finish_page_write(*page_number);
+            if written.is_ok() {
+                inner.page_cache.mark_clean(*page_number);
+            }
+        }
+        written
+    }
+
+    /// Body of the background writer thread: a round every `interval` until it is stopped or the pager is gone.
+    fn run_background_writer(
+        pager: Weak<Mutex<PagerInner>>,
+        stop: Arc<(Mutex<bool>, Condvar)>,
+        config: BackgroundWriter,
+    ) {
+        let (stopped, wake) = &*stop;
+        let Ok(mut stopped_guard) = stopped.lock() else {
+            return;
+        };
+        loop {
+            stopped_guard = match wake.wait_timeout(stopped_guard, config.interval) {
+                Ok((guard, _)) => guard,
+                Err(_) => return,
+            };
+            if *stopped_guard {
+                return;
+            }
+            let Some(pager) = pager.upgrade() else {
+                return;
+            };
+            if let Ok(mut inner) = pager.lock() {
+                // A failed round is not a big deal: the pages stay dirty and the commit writes them
+                let _ = Self::write_back(&mut inner, &config);
+            };
+        }
+    }
+
+    /// A round of the background writer: writes back the least recently used dirty pages while more of the cache
+    /// than `dirty_ratio` is dirty, and the pages that have been dirty for longer than `max_age`.
+    ///
+    /// Only the pages of an active transaction are written. Outside of one, a spilled page would keep the EXCLUSIVE
+    /// lock until somebody flushes, so those are left to `flush`.
+    ///
+    /// # Returns
+    /// The number of pages written back.
+    fn write_back(inner: &mut PagerInner, config: &BackgroundWriter) -> io::Result<usize> {
+        if !inner.in_transaction {
+            return Ok(0);
+        }
+
+        let now = Instant::now();
+        let allowed = (inner.page_cache.max_pages() as f64 * config.dirty_ratio) as usize;
+        let mut excess = inner.page_cache.dirty_page_count().saturating_sub(allowed);
+        let mut page_numbers = Vec::new();
+        for (page_number, dirtied_at) in inner.page_cache.write_back_candidates() {
+            if page_numbers.len() >= config.max_pages {
+                break;
+            }
+            if excess > 0 || now.duration_since(dirtied_at) >= config.max_age {
+                excess = excess.saturating_sub(1);
+                page_numbers.push(page_number);
+            }
+        }
+        if page_numbers.is_empty() {
+            return Ok(0);
+        }
+
+        // Never wait for the lock here, the next round can try again
+        let busy_handler = inner.busy_handler.take();
+        let acquired = Self::acquire(inner, LockLevel::Exclusive);
+        inner.busy_handler = busy_handler;
+        match acquired {
+            Err(e) if is_busy(&e) => return Ok(0),
+            result => result?,
+        }
+        Self::write_journal(inner)?;
+
+        let pages = page_numbers
+            .into_iter()
+            .filter_map(|page_number| {
+                let page = inner.page_cache.get_page_ref(page_number)?;
+                Some((page_number, page.clone()))
+            })
+            .collect::<Vec<_>>();
+        Self::write_dirty_pages(inner, &pages)?;
+        inner.page_cache.record_write_back(pages.len());
+        Ok(pages.len())
+    }
+
+    /// Stops the background writer, if it is running.
+    fn stop_background_writer(&self) -> io::Result<()> {
+        let writer = self
+            .inner
+            .lock()
+            .map_err(|e| io::Error::other(format!("Lock poisoned: {}", e)))?
+            .background_writer
+            .take();
+        match writer {
+            Some(writer) => writer.stop(),
+            None => Ok(()),
+        }
+    }
+
+    /// Checks if this is the last handle of the pager. The background writer holds one while it runs a round,
+    /// so we ask while holding its stop lock, when it cannot be in one.
+    fn is_last_handle(&self) -> bool {
+        let stop = match self.inner.lock() {
+            Ok(inner) => inner
+                .background_writer
+                .as_ref()
+                .map(|writer| Arc::clone(&writer.stop)),
+            Err(_) => None,
+        };
+        let _round = stop.as_ref().map(|stop| stop.0.lock());
+        Arc::strong_count(&self.inner) == 1
+    }
+
+    /// Syncs the database file as the synchronous level says.
+    fn sync_file(inner: &mut PagerInner) -> io::Result<()> {
+        match inner.synchronous {
+            Synchronous::Off => return Ok(()),
+            Synchronous::Normal => inner.disk_manager.sync_data()?,
+            Synchronous::Full | Synchronous::Extra => inner.disk_manager.sync()?,
+        }
+        inner.sync_state.count.fetch_add(1, Ordering::SeqCst);
+        Ok(())
+    }
+
+    /// Gets the journal file ready before the active transaction writes to the database file.
+    /// Does nothing outside of transactions.
+    fn write_journal(inner: &mut PagerInner) -> io::Result<()> {
+        if !inner.in_transaction {
+            return Ok(());
+        }
+        // The journal of a group waiting for its sync cannot take pages that are not committed yet
+        Self::finish_pending(inner)?;
+        Self::append_journal(inner)
+    }
+
+    /// Syncs the commits that are still waiting for their group sync, which deletes their journal.
+    fn finish_pending(inner: &mut PagerInner) -> io::Result<()> {
+        let pending = inner.sync_state.journal()?.pending();
+        if let Some(commit) = pending {
+            inner.sync_state.sync_commit(commit, inner.synchronous)?;
+        }
+        Ok(())
+    }
+
+    /// Appends to the journal file the original images the transaction has in memory, and syncs it.
+    /// The journal is created if the transaction does not have one yet. A commit can also join the journal of
+    /// the commits waiting for their group sync: the images those already have are older, so they are kept.
+    fn append_journal(inner: &mut PagerInner) -> io::Result<()> {
+        let sync_state = Arc::clone(&inner.sync_state);
+        let mut journal = sync_state.journal()?;
+        if !journal.is_open() {
+            let header = match &inner.journal_header {
+                Some(header) => header.clone(),
+                None => inner.disk_manager.read_header()?,
+            };
+            journal.begin(&header, inner.journal_page_count)?;
+        }
+
+        let mut page_numbers = inner
+            .journal_pages
+            .keys()
+            .filter(|page_number| !journal.contains(**page_number))
+            .copied()
+            .collect::<Vec<_>>();
+        page_numbers.sort_unstable();
+        for page_number in page_numbers {
+            let buffer = Self::serialize_page(inner, &inner.journal_pages[&page_number])?;
+            journal.append(page_number, &buffer)?;
+        }
+
+        match inner.synchronous {
+            Synchronous::Off => Ok(()),
+            Synchronous::Normal => journal.sync(true),
+            Synchronous::Full | Synchronous::Extra => journal.sync(false),
+        }
+    }
+
+    /// Plays back the journal left behind by a transaction that never finished, if there is one.
+    /// Called when we take the SHARED lock, before trusting anything in the file.
+    fn recover(inner: &mut PagerInner) -> io::Result<()> {
+        let sync_state = Arc::clone(&inner.sync_state);
+        let journal = sync_state.journal()?;
+        if !journal.is_hot()? {
+            return Ok(());
+        }
+
+        // Somebody holding RESERVED is alive and in the middle of that transaction, so the journal is theirs
+        match inner.disk_manager.lock(LockLevel::Reserved) {
+            Err(e) if is_busy(&e) => return Ok(()),
+            result => result?,
+        }
+        inner.disk_manager.lock(LockLevel::Exclusive)?;
+
+        if let Some(hot) = journal.read_hot()? {
+            for (page_number, buffer) in &hot.pages {
+                if *page_number <= hot.page_count {
+                    inner.disk_manager.write_page(*page_number, buffer)?;
+                }
+            }
+            inner.disk_manager.write_header(&hot.header)?;
+            inner.disk_manager.truncate_pages(hot.page_count)?;
+            Self::sync_file(inner)?;
+        }
+        journal.delete_hot()?;
+        drop(journal);
+
+        // Whatever we had cached may come from the transaction we just undid
+        inner.page_cache.discard_clean_pages();
+        inner.disk_manager.unlock(LockLevel::Shared)
+    }
+
+    /// Fails if this pager is a read-only snapshot.
+    fn require_writable(&self) -> io::Result<()> {
+        if self.snapshot.is_some() {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "Snapshots are read-only",
+            ));
+        }
+        Ok(())
+    }
+
+    /// Copies a page of the cache into the journal and the open savepoints that do not have it yet.
+    fn journal_page(inner: &mut PagerInner, page_number: u32) {
+        if !inner.in_transaction {
+            return;
+        }
+
+        // Pages allocated after a level was opened are not journaled in it, truncating the file is enough to get rid of them
+        let journaled = page_number > inner.journal_page_count
+            || inner.journal_pages.contains_key(&page_number);
+        let journaled = journaled
+            && inner.savepoints.iter().all(|savepoint| {
+                page_number > savepoint.page_count || savepoint.pages.contains_key(&page_number)
+            });
+        if journaled {
+            return;
+        }
+
+        if let Some(page) = inner.page_cache.get_page_for_journal(page_number) {
+            for savepoint in inner.savepoints.iter_mut() {
+                if page_number <= savepoint.page_count {
+                    savepoint
+                        .pages
+                        .entry(page_number)
+                        .or_insert_with(|| page.clone());
+                }
+            }
+            if page_number <= inner.journal_page_count {
+                inner.journal_pages.entry(page_number).or_insert(page);
+            }
+        }
+    }
+
+    /// Drops the pages allocated after the database had `page_count` pages, from the cache and from the file.
+    fn truncate(inner: &mut PagerInner, page_count: u32) -> io::Result<()> {
+        let current = inner.disk_manager.page_count()?;
+        if current <= page_count {
+            return Ok(());
+        }
+
+        for page_number in (page_count + 1)..=current {
+            inner.page_cache.force_unpin_page(page_number);
+            inner.page_cache.remove_page(page_number);
+        }
+        // Allocating the pages took the EXCLUSIVE lock, so we still have it
+        inner.disk_manager.truncate_pages(page_count)
+    }
+
+    /// Finds the innermost savepoint with the given name.
+    fn find_savepoint(inner: &PagerInner, name: &str) -> io::Result<usize> {
+        Self::require_transaction(inner)?;
+        inner
+            .savepoints
+            .iter()
+            .rposition(|savepoint| savepoint.name.eq_ignore_ascii_case(name))
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("No such savepoint: {}", name),
+                )
+            })
+    }
+
+    /// Fails if there is no active transaction.
+    fn require_transaction(inner: &PagerInner) -> io::Result<()> {
+        if !inner.in_transaction {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "No transaction is active",
+            ));
+        }
+        Ok(())
+    }
+
+    /// Acquires a file lock, calling the busy handler for as long as another process holds a conflicting one.
+    fn acquire(inner: &mut PagerInner, level: LockLevel) -> io::Result<()> {
+        let mut retries = 0;
+        loop {
+            let was_unlocked = inner.disk_manager.lock_level() == LockLevel::Unlocked;
+            let mut result = inner.disk_manager.lock(LockLevel::Shared);
+            // A hot journal is played back before anything else, which can find the lock busy too
+            if result.is_ok() && was_unlocked {
+                result = Self::recover(inner).and_then(|_| Self::validate_cache(inner));
+            }
+            let result = result.and_then(|_| inner.disk_manager.lock(level));
+
+            match result {
+                Ok(()) => return Ok(()),
+                Err(e) if is_busy(&e) => {
+                    // Never wait holding a SHARED lock we just took, or two writers could wait on each other forever
+                    if was_unlocked {
+                        inner.disk_manager.unlock(LockLevel::Unlocked)?;
+                    }
+                    let retry = inner
+                        .busy_handler
+                        .as_ref()
+                        .is_some_and(|handler| handler(retries));
+                    if !retry {
+                        return Err(e);
+                    }
+                    retries += 1;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Releases the file lock when it is no longer needed: keeps it during transactions and while commits
+    /// wait for their group sync, and downgrades it to SHARED while there are open read scopes.
+    fn release(inner: &mut PagerInner) -> io::Result<()> {
+        // The journal of a group of commits is only deleted by its sync, and the lock protects it until then
+        if inner.in_transaction || inner.sync_state.journal()?.pending().is_some() {
+            return Ok(());
+        }
+        let level = if inner.readers > 0 {
+            LockLevel::Shared
+        } else {
+            LockLevel::Unlocked
+        };
+        inner.disk_manager.unlock(level)
+    }
+
+    /// Drops the cached pages if another process committed since the last time we held a lock.
+    fn validate_cache(inner: &mut PagerInner) -> io::Result<()> {
+        let header = inner.disk_manager.read_header()?;
+        if header.change_counter != inner.change_counter {
+            inner.page_cache.discard_clean_pages();
+            inner.change_counter = header.change_counter;
+        }
+        Ok(())
+    }
+
+    /// Gets a copy of a page as it was at commit `seq`. Unless a copy was kept for the snapshots,
+    /// the page must be in the cache.
+    fn snapshot_page(inner: &PagerInner, page_number: u32, seq: u64) -> io::Result<Page> {
+        if let Some(page) = Self::snapshot_copy(inner, page_number, seq) {
+            return Ok(page);
+        }
+
+        inner
+            .page_cache
+            .get_page_ref(page_number)
+            .cloned()
+            .ok_or_else(|| io::Error::other(format!("Page {} not found in buffer pool", page_number)))
+    }
+
+    /// Gets the copy of a page kept for the snapshots, if the page changed since commit `seq`.
+    /// `None` means the snapshot sees the page as it is in the cache or the file.
+    fn snapshot_copy(inner: &PagerInner, page_number: u32, seq: u64) -> Option<Page> {
+        // If later commits replaced the page, the image replaced first is the one of the snapshot
+        let version = inner.versions.get(&page_number).and_then(|versions| {
+            versions
+                .iter()
+                .filter(|(replaced_at, _)| *replaced_at > seq)
+                .min_by_key(|(replaced_at, _)| *replaced_at)
+                .map(|(_, page)| page.clone())
+        });
+        if version.is_some() {
+            return version;
+        }
+
+        // The active transaction journals a page before changing it, so the journal has the committed copy
+        if inner.in_transaction {
+            return inner.journal_pages.get(&page_number).cloned();
+        }
+        None
+    }
+
+    /// Closes a snapshot taken at commit `seq`, dropping the versions no open snapshot needs anymore.
+    fn end_snapshot(inner: &mut PagerInner, seq: u64) -> io::Result<()> {
+        if let Some(count) = inner.snapshots.get_mut(&seq) {
+            *count -= 1;
+            if *count == 0 {
+                inner.snapshots.remove(&seq);
+            }
+        }
+
+        // A version is only seen by snapshots older than the commit that replaced it
+        match inner.snapshots.keys().next().copied() {
+            Some(oldest) => inner.versions.retain(|_, versions| {
+                versions.retain(|(replaced_at, _)| *replaced_at > oldest);
+                !versions.is_empty()
+            }),
+            None => inner.versions.clear(),
+        }
+
+        inner.readers = inner.readers.saturating_sub(1);
+        Self::release(inner)
+    }
+
+    /// Loads a page from disk into the cache, letting go of the pager lock while the page is read from the file,
+    /// so a slow disk does not keep the other threads away from the pages they already have in the cache.
+    ///
+    /// Several threads can miss the same page at the same time: the first one to put it in the cache wins,
+    /// and the others take that copy. If the file changed while we were not holding the lock (a page written back,
+    /// a rollback, another process), what we read may be stale, so we read the page again with the lock held.
+    ///
+    /// # Returns
+    /// The pager lock, held again.
+    fn load_page<'a>(
+        mutex: &'a Mutex<PagerInner>,
+        inner: MutexGuard<'a, PagerInner>,
+        page_number: u32,
+    ) -> io::Result<MutexGuard<'a, PagerInner>> {
+        let mut inner = inner;
+        let page_count = Self::check_page_number(&inner, page_number)?;
+        // Copying out of the map takes no time, and the map is only safe to touch with the lock held
+        if inner.disk_manager.is_mapped(page_number) {
+            Self::load_page_locked(&mut inner, page_number)?;
+            return Ok(inner);
+        }
+
+        let reader = inner.disk_manager.page_reader();
+        let generation = inner.disk_manager.generation();
+        let mut buffer = vec![0u8; inner.page_size as usize];
+        drop(inner);
+        let read = reader.read_page(page_number, &mut buffer);
+        let mut inner = mutex
+            .lock()
+            .map_err(|e| io::Error::other(format!("Lock poisoned: {}", e)))?;
+
+        if inner.page_cache.contains_page_simple(page_number) {
+            return Ok(inner);
+        }
+        if inner.disk_manager.generation() != generation {
+            Self::load_page_locked(&mut inner, page_number)?;
+            return Ok(inner);
+        }
+        read?;
+        Self::cache_loaded_page(&mut inner, page_number, page_count, &buffer)?;
+        Ok(inner)
+    }
+
+    /// Loads a page from disk into the cache, holding the pager lock all along.
+    fn load_page_locked(inner: &mut PagerInner, page_number: u32) -> io::Result<()> {
+        let page_count = Self::check_page_number(inner, page_number)?;
+
+        // Read page from disk
+        let mut buffer = vec![0u8; inner.page_size as usize];
+        inner.disk_manager.read_page(page_number, &mut buffer)?;
+        Self::cache_loaded_page(inner, page_number, page_count, &buffer)
+    }
+
+    /// Checks that a page is in the file before loading it.
+    ///
+    /// # Returns
+    /// The number of pages of the file.
+    fn check_page_number(inner: &PagerInner, page_number: u32) -> io::Result<u32> {
+        let page_count = inner.disk_manager.page_count()?;
+        if page_number == 0 || page_number > page_count {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("Page number out of range: {}", page_number),
+            ));
+        }
+        Ok(page_count)
+    }
+
+    /// Parses a page just read from the file and puts it in the cache, then reads ahead if it looks like a scan.
+    fn cache_loaded_page(
+        inner: &mut PagerInner,
+        page_number: u32,
+        page_count: u32,
+        buffer: &[u8],
+    ) -> io::Result<()> {
+        // Parse the page
+        let page = Self::parse_page(inner, page_number, buffer)?;
+
+        // Add to cache, handling eviction if necessary.
+        // There is a risk of deadlock here if the cache is full and we try to evict a page
+        // that is currently being accessed. This should be handled by the cache itself.
 
         // Deadlock
         // We cannot know if an evicted page is dirty or not, so we need to release it
         // Solved at the cache level, by returning early when there are no candidates for eviction
+        Self::cache_page(inner, page_number, page)?;
+
+        // Read-ahead is only a hint: if it fails, the pages are read one by one when they are asked for
+        let _ = Self::read_ahead(inner, page_number, page_count);
+        Ok(())
+    }
+
+    /// Reads the pages after a miss ahead of time, if the misses look like a sequential scan.
+    ///
+    /// Neither the B-Trees nor the callers tell us they are scanning, so we guess it from the misses:
+    /// when a few of them in a row land on consecutive pages (sibling leaves written in order, the pages of an
+    /// overflow chain), the next pages are read with a single larger read and put in the cache as prefetched.
+    /// The following miss is then on the page after them, so a long scan keeps prefetching.
+    ///
+    /// The read-ahead never takes more than half of the cache, so it cannot push out the page we just loaded,
+    /// and it stops instead of evicting a dirty page, as that would mean writing to the file.
+    ///
+    /// # Parameters
+    /// * `page_number` - The page that just missed the cache.
+    /// * `page_count` - Number of pages of the file.
+    ///
+    /// # Errors
+    /// Returns an error if the pages cannot be read or parsed. Pages read before the error stay in the cache.
+    fn read_ahead(inner: &mut PagerInner, page_number: u32, page_count: u32) -> io::Result<()> {
+        if page_number == inner.last_miss + 1 {
+            inner.sequential_misses += 1;
+        } else {
+            inner.sequential_misses = 0;
+        }
+        inner.last_miss = page_number;
+        if inner.read_ahead == 0 || inner.sequential_misses + 1 < SEQUENTIAL_MISSES {
+            return Ok(());
+        }
+
+        // The run stops at the end of the file or at the first page we already have
+        let limit = inner
+            .read_ahead
+            .min(inner.page_cache.max_pages() as u32 / 2);
+        let first = page_number + 1;
+        let mut count = 0;
+        while count < limit
+            && first + count <= page_count
+            && !inner.page_cache.contains_page_simple(first + count)
+        {
+            count += 1;
+        }
+        if count == 0 {
+            return Ok(());
+        }
+
+        let page_size = inner.page_size as usize;
+        let mut buffer = vec![0u8; count as usize * page_size];
+        inner.disk_manager.read_pages(first, &mut buffer)?;
+
+        for (i, data) in buffer.chunks(page_size).enumerate() {
+            if inner.page_cache.page_count() >= inner.page_cache.max_pages()
+                && inner.page_cache.dirty_page_count() > 0
+            {
+                break;
+            }
+            let prefetched = first + i as u32;
+            let page = Self::parse_page(inner, prefetched, data)?;
+            Self::cache_page(inner, prefetched, page)?;
+            inner.page_cache.mark_prefetched(prefetched);
+            inner.last_miss = prefetched;
+        }
+        Ok(())
+    }
+
+    /// Adds a page to the cache, writing back the evicted page if it was dirty.
+    fn cache_page(inner: &mut PagerInner, page_number: u32, page: Page) -> io::Result<()> {
+        // Writing back a dirty page requires the EXCLUSIVE lock, and it must be taken before the page leaves the cache
+        if inner.page_cache.page_count() >= inner.page_cache.max_pages()
+            && inner.page_cache.dirty_page_count() > 0
+        {
+            Self::acquire(inner, LockLevel::Exclusive)?;
+        }
+
         match inner.page_cache.add_page(page_number, page, false) {
             AddPageResult::Added | AddPageResult::Evicted(_, _, false) => {
                 return Ok(());
             }
-            AddPageResult::Evicted(evicted_page_number, buffer, true) => {
+            AddPageResult::Evicted(evicted_page_number, mut buffer, true) => {
+                Self::write_journal(inner)?;
+                Self::stamp_checksum(inner, &mut buffer);
                 inner
                     .disk_manager
                     .write_page(evicted_page_number, &buffer)?;
             }
             AddPageResult::Rejected => {
-                return Err(io::Error::new(
-                    io::ErrorKind::Other,
+                return Err(io::Error::other(
                     "Buffer pool is full and cannot evict a page",
                 ));
             }
@@ -683,18 +2185,49 @@ impl Pager {
         Ok(())
     }
 
+    /// Checks if the pages of this database carry a checksum in their reserved space.
+    fn has_checksums(inner: &PagerInner) -> bool {
+        inner.checksums
+    }
+
+    /// Writes the checksum at the end of a serialized page, if the database uses them.
+    fn stamp_checksum(inner: &PagerInner, buffer: &mut [u8]) {
+        if Self::has_checksums(inner) {
+            write_checksum(buffer);
+        }
+    }
+
     /// Parses a page from a buffer
+    ///
+    /// # Errors
+    /// Returns a `CorruptError` (see `corrupt_page`) if the page does not match its checksum.
     fn parse_page(inner: &PagerInner, page_number: u32, buffer: &[u8]) -> io::Result<Page> {
         if buffer.is_empty() {
             return Err(io::Error::new(io::ErrorKind::InvalidData, "Empty buffer"));
         }
 
+        if Self::has_checksums(inner) {
+            if !verify_checksum(buffer) {
+                return Err(corrupt_error(page_number));
+            }
+            // The reserved space is not part of the page content, so overflow data does not pick up the checksum
+            let usable = buffer.len() - inner.reserved_space as usize;
+            return Self::parse_usable_page(inner, page_number, &buffer[..usable]);
+        }
+        Self::parse_usable_page(inner, page_number, buffer)
+    }
+
+    /// Parses the usable part of a page, already without the reserved space when there is a checksum in it.
+    fn parse_usable_page(inner: &PagerInner, page_number: u32, buffer: &[u8]) -> io::Result<Page> {
         // Determine page type from first byte
         match buffer[0] {
             0x02 | 0x05 | 0x0A | 0x0D => {
                 // B-Tree page
                 let mut cursor = std::io::Cursor::new(buffer);
-                let mut btree_page = BTreePage::read_from(&mut cursor)?;
+                let (max_fraction, min_fraction) = inner.payload_fractions;
+                let usable_size = (inner.page_size - inner.reserved_space as u32) as usize;
+                let limits = PayloadLimits::new(usable_size, max_fraction, min_fraction);
+                let mut btree_page = BTreePage::read_with_limits(&mut cursor, limits)?;
                 btree_page.page_number = page_number;
                 btree_page.page_size = inner.page_size;
                 btree_page.reserved_space = inner.reserved_space;
@@ -729,15 +2262,44 @@ impl Pager {
         let mut buffer = vec![0u8; inner.page_size as usize];
         let mut cursor = std::io::Cursor::new(&mut buffer[..]);
         page.write_to(&mut cursor)?;
+        Self::stamp_checksum(inner, &mut buffer);
         Ok(buffer)
     }
 }
 
+impl Clone for Pager {
+    /// Clones the handle. A clone of a snapshot is registered as one more reader of the same commit.
+    fn clone(&self) -> Self {
+        if let Some(seq) = self.snapshot {
+            if let Ok(mut inner) = self.inner.lock() {
+                *inner.snapshots.entry(seq).or_insert(0) += 1;
+                inner.readers += 1;
+            }
+        }
+        Pager {
+            inner: Arc::clone(&self.inner),
+            snapshot: self.snapshot,
+        }
+    }
+}
+
 /// Ensure the pager properly cleans up on drop
 impl Drop for Pager {
     fn drop(&mut self) {
+        if let Some(seq) = self.snapshot {
+            if let Ok(mut inner) = self.inner.lock() {
+                let _ = Self::end_snapshot(&mut inner, seq);
+            }
+            return;
+        }
+        // The last handle going away stops the writer, and rolls back the transaction if one is open, the same as close does
+        if self.is_last_handle() {
+            let _ = self.stop_background_writer();
+            if self.in_transaction().unwrap_or(false) {
+                let _ = self.rollback_transaction();
+            }
+        }
         // Try to flush any pending changes
-        // Probably more ACID to use self.rollback_transaction() here, but as I am not implementing transactions still let's keep it like this.
         let _ = self.flush();
     }
 }
@@ -746,6 +2308,7 @@ impl Drop for Pager {
 mod tests {
     use super::*;
     use crate::page::{BTreeCell, TableLeafCell};
+    use crate::storage::vfs::MemoryVfs;
     use tempfile::tempdir;
 
     #[test]
@@ -893,6 +2456,554 @@ mod tests {
         }
     }
 
+    /// Adds a leaf cell with the given rowid to a B-Tree page.
+    fn add_row(pager: &Pager, page_number: u32, row_id: i64) {
+        pager
+            .get_page_mut_callback(page_number, None, |page| match page {
+                Page::BTree(btree_page) => btree_page
+                    .add_cell(BTreeCell::TableLeaf(TableLeafCell {
+                        payload_size: 1,
+                        row_id,
+                        payload: vec![1],
+                        overflow_page: None,
+                    }))
+                    .map(|_| ()),
+                _ => panic!("Expected BTree page"),
+            })
+            .unwrap();
+    }
+
+    /// Counts the cells of a B-Tree page.
+    fn cell_count(pager: &Pager, page_number: u32) -> u16 {
+        pager
+            .get_page_callback(page_number, None, |page| match page {
+                Page::BTree(btree_page) => btree_page.header.cell_count,
+                _ => panic!("Expected BTree page"),
+            })
+            .unwrap()
+    }
+
+    #[test]
+    fn test_snapshot_isolation() {
+        let dir = tempdir().unwrap();
+        let db_path = dir.path().join("test.db");
+        let pager = Pager::create(&db_path, 4096, Some(10), 0).unwrap();
+        let page_number = pager.create_btree_page(PageType::TableLeaf, None).unwrap();
+        pager.flush().unwrap();
+
+        let before = pager.snapshot().unwrap();
+        assert!(before.is_snapshot());
+
+        // Uncommitted changes are only visible to the live pager
+        pager.begin_transaction().unwrap();
+        add_row(&pager, page_number, 1);
+        assert_eq!(cell_count(&pager, page_number), 1);
+        assert_eq!(cell_count(&before, page_number), 0);
+
+        // A commit does not change what an open snapshot sees
+        pager.commit_transaction().unwrap();
+        assert_eq!(cell_count(&before, page_number), 0);
+
+        let after = pager.snapshot().unwrap();
+        assert_eq!(cell_count(&after, page_number), 1);
+
+        pager.begin_transaction().unwrap();
+        add_row(&pager, page_number, 2);
+        pager.commit_transaction().unwrap();
+        assert_eq!(cell_count(&before, page_number), 0);
+        assert_eq!(cell_count(&after, page_number), 1);
+        assert_eq!(cell_count(&pager, page_number), 2);
+
+        // The versions are dropped with the last snapshot that needs them
+        drop(before);
+        assert_eq!(pager.inner.lock().unwrap().versions.len(), 1);
+        drop(after);
+        assert!(pager.inner.lock().unwrap().versions.is_empty());
+        assert_eq!(pager.lock_level().unwrap(), LockLevel::Unlocked);
+    }
+
+    #[test]
+    fn test_snapshot_is_read_only() {
+        let dir = tempdir().unwrap();
+        let db_path = dir.path().join("test.db");
+        let pager = Pager::create(&db_path, 4096, Some(10), 0).unwrap();
+        let page_number = pager.create_btree_page(PageType::TableLeaf, None).unwrap();
+
+        let snapshot = pager.snapshot().unwrap();
+        let err = snapshot.get_page_mut(page_number, None).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::PermissionDenied);
+        assert!(snapshot.begin_transaction().is_err());
+        assert!(snapshot.create_btree_page(PageType::TableLeaf, None).is_err());
+
+        // Snapshot pages are copies, nothing stays pinned in the cache
+        let guard = snapshot.get_page(page_number, Some(PageType::TableLeaf)).unwrap();
+        assert_eq!(guard.page().page_type(), PageType::TableLeaf);
+        assert!(snapshot.get_page(page_number, Some(PageType::IndexLeaf)).is_err());
+        assert_eq!(pager.inner.lock().unwrap().page_cache.pinned_page_count(), 0);
+    }
+
+    #[test]
+    fn test_savepoints() {
+        let dir = tempdir().unwrap();
+        let db_path = dir.path().join("test.db");
+        let pager = Pager::create(&db_path, 4096, Some(10), 0).unwrap();
+        let first = pager.create_btree_page(PageType::TableLeaf, None).unwrap();
+        let second = pager.create_btree_page(PageType::TableLeaf, None).unwrap();
+
+        // Savepoints only exist inside transactions
+        assert!(pager.savepoint("outer").is_err());
+
+        pager.begin_transaction().unwrap();
+        add_row(&pager, first, 1);
+
+        pager.savepoint("outer").unwrap();
+        add_row(&pager, first, 2);
+        pager.savepoint("inner").unwrap();
+        add_row(&pager, first, 3);
+        add_row(&pager, second, 1);
+
+        // Only the changes made after the savepoint are undone, and the savepoint stays open
+        pager.rollback_to_savepoint("INNER").unwrap();
+        assert_eq!(cell_count(&pager, first), 2);
+        assert_eq!(cell_count(&pager, second), 0);
+
+        add_row(&pager, second, 2);
+        pager.rollback_to_savepoint("inner").unwrap();
+        assert_eq!(cell_count(&pager, second), 0);
+
+        // Rolling back to the outer savepoint releases the inner one
+        pager.rollback_to_savepoint("outer").unwrap();
+        assert_eq!(cell_count(&pager, first), 1);
+        assert!(pager.rollback_to_savepoint("inner").is_err());
+
+        // Released savepoints keep their changes
+        add_row(&pager, second, 3);
+        pager.release_savepoint("outer").unwrap();
+        assert!(pager.release_savepoint("outer").is_err());
+        pager.commit_transaction().unwrap();
+
+        assert_eq!(cell_count(&pager, first), 1);
+        assert_eq!(cell_count(&pager, second), 1);
+        assert!(pager.savepoint("outer").is_err());
+    }
+
+    #[test]
+    fn test_rollback_truncates_new_pages() {
+        let dir = tempdir().unwrap();
+        let db_path = dir.path().join("test.db");
+        // A tiny cache, so the transaction has to spill dirty pages to the file
+        let pager = Pager::create(&db_path, 4096, Some(3), 0).unwrap();
+        let pages: Vec<u32> = (0..4)
+            .map(|_| pager.create_btree_page(PageType::TableLeaf, None).unwrap())
+            .collect();
+        pager.flush().unwrap();
+        let page_count = pager.page_count().unwrap();
+
+        pager.begin_transaction().unwrap();
+        for (i, &page_number) in pages.iter().enumerate() {
+            // Changing a page twice must not journal the intermediate image
+            add_row(&pager, page_number, i as i64);
+            add_row(&pager, page_number, i as i64 + 100);
+        }
+        let new_page = pager.create_btree_page(PageType::TableLeaf, None).unwrap();
+        add_row(&pager, new_page, 1);
+
+        let mut header = pager.get_header().unwrap();
+        header.user_version = 7;
+        pager.update_header(&header).unwrap();
+        assert_eq!(pager.page_count().unwrap(), page_count + 1);
+
+        pager.rollback_transaction().unwrap();
+
+        assert_eq!(pager.page_count().unwrap(), page_count);
+        assert_eq!(pager.get_header().unwrap().user_version, 0);
+        assert!(pager.get_page(new_page, None).is_err());
+        for &page_number in &pages {
+            assert_eq!(cell_count(&pager, page_number), 0);
+        }
+        drop(pager);
+
+        // The file itself has the original pages back
+        let pager = Pager::open(&db_path, Some(3)).unwrap();
+        assert_eq!(pager.page_count().unwrap(), page_count);
+        for &page_number in &pages {
+            assert_eq!(cell_count(&pager, page_number), 0);
+        }
+    }
+
+    #[test]
+    fn test_page_checksums() {
+        use crate::utils::checksum::corrupt_page;
+        use std::io::{Seek, SeekFrom, Write};
+
+        let dir = tempdir().unwrap();
+        let db_path = dir.path().join("test.db");
+        // A cache of two pages, so some pages reach the file through eviction instead of flush
+        let pager = Pager::create(&db_path, 4096, Some(2), 8).unwrap();
+        pager.enable_checksums().unwrap();
+        let pages: Vec<u32> = (0..4)
+            .map(|i| {
+                let page_number = pager.create_btree_page(PageType::TableLeaf, None).unwrap();
+                add_row(&pager, page_number, i);
+                page_number
+            })
+            .collect();
+        let data = vec![7u8; 4096 - 13 - 8];
+        let overflow = pager.create_overflow_page(0, data.clone()).unwrap();
+        pager.flush().unwrap();
+        drop(pager);
+
+        let pager = Pager::open(&db_path, Some(2)).unwrap();
+        for &page_number in &pages {
+            assert_eq!(cell_count(&pager, page_number), 1);
+        }
+        // The checksum is not read back as part of the overflow data
+        pager
+            .get_page_callback(overflow, None, |page| match page {
+                Page::Overflow(overflow_page) => assert_eq!(overflow_page.data, data),
+                _ => panic!("Expected overflow page"),
+            })
+            .unwrap();
+        drop(pager);
+
+        // Flip one bit in the middle of the second page
+        let target = pages[1];
+        let mut file = std::fs::OpenOptions::new()
+            .write(true)
+            .read(true)
+            .open(&db_path)
+            .unwrap();
+        let offset = (target as u64 - 1) * 4096 + 100 + 2000;
+        let mut byte = [0u8; 1];
+        file.seek(SeekFrom::Start(offset)).unwrap();
+        std::io::Read::read_exact(&mut file, &mut byte).unwrap();
+        file.seek(SeekFrom::Start(offset)).unwrap();
+        file.write_all(&[byte[0] ^ 0x10]).unwrap();
+        drop(file);
+
+        let pager = Pager::open(&db_path, Some(2)).unwrap();
+        let error = pager
+            .get_page(target, None)
+            .err()
+            .expect("Corrupt page was read");
+        assert_eq!(corrupt_page(&error), Some(target));
+        assert_eq!(cell_count(&pager, pages[0]), 1);
+    }
+
+    #[test]
+    fn test_no_checksums_without_opt_in() {
+        let dir = tempdir().unwrap();
+        let db_path = dir.path().join("test.db");
+        // Reserved space alone does not mean checksums, older databases may keep anything in there
+        let pager = Pager::create(&db_path, 4096, None, 8).unwrap();
+        let page_number = pager.create_btree_page(PageType::TableLeaf, None).unwrap();
+        pager.flush().unwrap();
+        // Too late, the page is already written without one
+        let error = pager.enable_checksums().unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+        drop(pager);
+
+        // The last bytes of the page belong to nobody, nobody notices if they change
+        let mut file = std::fs::OpenOptions::new()
+            .write(true)
+            .open(&db_path)
+            .unwrap();
+        let offset = page_number as u64 * 4096 + 100 - 4;
+        std::io::Seek::seek(&mut file, std::io::SeekFrom::Start(offset)).unwrap();
+        std::io::Write::write_all(&mut file, &[0xAB; 4]).unwrap();
+        drop(file);
+
+        let pager = Pager::open(&db_path, None).unwrap();
+        assert_eq!(cell_count(&pager, page_number), 0);
+
+        // And there is no room for a checksum without reserved space
+        let pager = Pager::create(dir.path().join("small.db"), 4096, None, 0).unwrap();
+        let error = pager.enable_checksums().unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    /// Page reads of a `GatedVfs` wait for each other: each one waits until `expected` of them were in flight
+    /// at the same time, or gives up after a few seconds. So they only all get through quickly if nobody
+    /// keeps the others from reading while it waits.
+    struct Gate {
+        expected: usize,
+        /// Reads in flight, and most reads that were ever in flight at once.
+        state: Mutex<(usize, usize)>,
+        changed: Condvar,
+    }
+
+    impl Gate {
+        fn new(expected: usize) -> Self {
+            Self {
+                expected,
+                state: Mutex::new((0, 0)),
+                changed: Condvar::new(),
+            }
+        }
+
+        fn pass(&self) {
+            let mut state = self.state.lock().unwrap();
+            state.0 += 1;
+            state.1 = state.1.max(state.0);
+            self.changed.notify_all();
+            let deadline = Instant::now() + Duration::from_secs(5);
+            while state.1 < self.expected && Instant::now() < deadline {
+                state = self
+                    .changed
+                    .wait_timeout(state, deadline - Instant::now())
+                    .unwrap()
+                    .0;
+            }
+            state.0 -= 1;
+        }
+
+        /// Takes the most reads that were in flight at once, and starts counting again.
+        fn take_max(&self) -> usize {
+            std::mem::take(&mut self.state.lock().unwrap().1)
+        }
+    }
+
+    /// A `MemoryVfs` whose page reads go through a `Gate` while it is armed.
+    struct GatedVfs {
+        inner: MemoryVfs,
+        gate: Arc<Gate>,
+        armed: Arc<std::sync::atomic::AtomicBool>,
+    }
+
+    impl Vfs for GatedVfs {
+        fn open(&self, path: &Path, create: bool) -> io::Result<Arc<dyn VfsFile>> {
+            Ok(Arc::new(GatedFile {
+                inner: self.inner.open(path, create)?,
+                gate: Arc::clone(&self.gate),
+                armed: Arc::clone(&self.armed),
+            }))
+        }
+
+        fn delete(&self, path: &Path) -> io::Result<()> {
+            self.inner.delete(path)
+        }
+
+        fn exists(&self, path: &Path) -> io::Result<bool> {
+            self.inner.exists(path)
+        }
+    }
+
+    /// A file of the `GatedVfs`.
+    struct GatedFile {
+        inner: Arc<dyn VfsFile>,
+        gate: Arc<Gate>,
+        armed: Arc<std::sync::atomic::AtomicBool>,
+    }
+
+    impl VfsFile for GatedFile {
+        fn read_at(&self, buffer: &mut [u8], offset: u64) -> io::Result<()> {
+            // Only the pages, not the header
+            if buffer.len() == 4096 && self.armed.load(Ordering::SeqCst) {
+                self.gate.pass();
+            }
+            self.inner.read_at(buffer, offset)
+        }
+
+        fn write_at(&self, buffer: &[u8], offset: u64) -> io::Result<()> {
+            self.inner.write_at(buffer, offset)
+        }
+
+        fn sync(&self, data_only: bool) -> io::Result<()> {
+            self.inner.sync(data_only)
+        }
+
+        fn truncate(&self, size: u64) -> io::Result<()> {
+            self.inner.truncate(size)
+        }
+
+        fn size(&self) -> io::Result<u64> {
+            self.inner.size()
+        }
+
+        fn lock(&self, kind: super::super::lock::LockKind, start: u64, len: u64) -> io::Result<()> {
+            self.inner.lock(kind, start, len)
+        }
+    }
+
+    #[test]
+    fn test_parallel_misses() {
+        let threads = 4;
+        let vfs = Arc::new(GatedVfs {
+            inner: MemoryVfs::new(),
+            gate: Arc::new(Gate::new(threads)),
+            armed: Arc::default(),
+        });
+        let pager = Pager::create_with_vfs(vfs.clone(), "test.db", 4096, Some(16), 0).unwrap();
+        let pages: Vec<u32> = (0..threads as i64)
+            .map(|i| {
+                let page_number = pager.create_btree_page(PageType::TableLeaf, None).unwrap();
+                add_row(&pager, page_number, i);
+                page_number
+            })
+            .collect();
+        pager.flush().unwrap();
+        drop(pager);
+        vfs.armed.store(true, Ordering::SeqCst);
+
+        // Misses on different pages read the file at the same time
+        let pager = Pager::open_with_vfs(vfs.clone(), "test.db", Some(16)).unwrap();
+        let start = Instant::now();
+        thread::scope(|scope| {
+            for &page_number in &pages {
+                let pager = &pager;
+                scope.spawn(move || assert_eq!(cell_count(pager, page_number), 1));
+            }
+        });
+        assert_eq!(vfs.gate.take_max(), threads);
+        assert!(start.elapsed() < Duration::from_secs(4));
+
+        // So do misses on the same page, and the cache ends up with one copy of it that everybody sees
+        let pager = Pager::open_with_vfs(vfs.clone(), "test.db", Some(16)).unwrap();
+        thread::scope(|scope| {
+            for _ in 0..threads {
+                scope.spawn(|| assert_eq!(cell_count(&pager, pages[0]), 1));
+            }
+        });
+        assert_eq!(vfs.gate.take_max(), threads);
+        vfs.armed.store(false, Ordering::SeqCst);
+        add_row(&pager, pages[0], 10);
+        assert_eq!(cell_count(&pager, pages[0]), 2);
+    }
+
+    #[test]
+    fn test_hot_journal_recovery() {
+        let vfs: Arc<dyn Vfs> = Arc::new(MemoryVfs::new());
+        let pager = Pager::create_with_vfs(Arc::clone(&vfs), "test.db", 4096, Some(10), 0).unwrap();
+        let page_number = pager.create_btree_page(PageType::TableLeaf, None).unwrap();
+        pager.begin_transaction().unwrap();
+        add_row(&pager, page_number, 1);
+        pager.commit_transaction().unwrap();
+        let journal_path = Path::new("test.db-journal");
+        assert!(!vfs.exists(journal_path).unwrap());
+
+        // The transaction writes its pages to the file and dies before committing
+        pager.begin_transaction().unwrap();
+        add_row(&pager, page_number, 2);
+        pager.create_btree_page(PageType::TableLeaf, None).unwrap();
+        pager.flush().unwrap();
+        assert!(vfs.exists(journal_path).unwrap());
+        std::mem::forget(pager);
+
+        // The next one to take a lock on the database plays the journal back
+        let pager = Pager::open_with_vfs(Arc::clone(&vfs), "test.db", Some(10)).unwrap();
+        pager.begin_read().unwrap();
+        assert_eq!(cell_count(&pager, page_number), 1);
+        assert_eq!(pager.page_count().unwrap(), page_number);
+        assert!(!vfs.exists(journal_path).unwrap());
+        pager.end_read().unwrap();
+    }
+
+    #[test]
+    fn test_group_commit_keeps_journal_until_sync() {
+        let vfs: Arc<dyn Vfs> = Arc::new(MemoryVfs::new());
+        let pager = Pager::create_with_vfs(Arc::clone(&vfs), "test.db", 4096, Some(10), 0).unwrap();
+        let page_number = pager.create_btree_page(PageType::TableLeaf, None).unwrap();
+        pager.set_group_commit(true).unwrap();
+        let journal_path = Path::new("test.db-journal");
+
+        // Both commits share the journal, and the lock, until their sync
+        pager.begin_transaction().unwrap();
+        add_row(&pager, page_number, 1);
+        let first = pager.commit_transaction_deferred().unwrap();
+        pager.begin_transaction().unwrap();
+        add_row(&pager, page_number, 2);
+        let second = pager.commit_transaction_deferred().unwrap();
+        assert!(vfs.exists(journal_path).unwrap());
+        assert_eq!(pager.lock_level().unwrap(), LockLevel::Exclusive);
+
+        pager.sync_commit(first).unwrap();
+        assert!(!vfs.exists(journal_path).unwrap());
+        assert_eq!(pager.lock_level().unwrap(), LockLevel::Unlocked);
+        pager.sync_commit(second).unwrap();
+        assert_eq!(cell_count(&pager, page_number), 2);
+    }
+
+    #[test]
+    fn test_drop_rolls_back_open_transaction() {
+        let dir = tempdir().unwrap();
+        let db_path = dir.path().join("test.db");
+        let page_number = {
+            let pager = Pager::create(&db_path, 4096, None, 0).unwrap();
+            let page_number = pager.create_btree_page(PageType::TableLeaf, None).unwrap();
+            pager.flush().unwrap();
+
+            pager.begin_transaction().unwrap();
+            add_row(&pager, page_number, 1);
+            pager.create_btree_page(PageType::TableLeaf, None).unwrap();
+            page_number
+        };
+
+        let pager = Pager::open(&db_path, None).unwrap();
+        assert_eq!(pager.page_count().unwrap(), 2);
+        assert_eq!(cell_count(&pager, page_number), 0);
+    }
+
+    #[test]
+    fn test_synchronous_levels() {
+        let dir = tempdir().unwrap();
+        let expected = [
+            (Synchronous::Off, 0),
+            (Synchronous::Normal, 1),
+            (Synchronous::Full, 1),
+            (Synchronous::Extra, 2),
+        ];
+
+        for (i, (synchronous, syncs)) in expected.into_iter().enumerate() {
+            let db_path = dir.path().join(format!("sync_{}.db", i));
+            let pager = Pager::create(&db_path, 4096, None, 0).unwrap();
+            let page_number = pager.create_btree_page(PageType::TableLeaf, None).unwrap();
+            pager.flush().unwrap();
+            pager.set_synchronous(synchronous).unwrap();
+
+            let before = pager.sync_count().unwrap();
+            pager.begin_transaction().unwrap();
+            add_row(&pager, page_number, 1);
+            pager.commit_transaction().unwrap();
+            assert_eq!(pager.sync_count().unwrap() - before, syncs, "{:?}", synchronous);
+        }
+    }
+
+    #[test]
+    fn test_group_commit_shares_syncs() {
+        let dir = tempdir().unwrap();
+        let db_path = dir.path().join("test.db");
+        let pager = Pager::create(&db_path, 4096, None, 0).unwrap();
+        let page_number = pager.create_btree_page(PageType::TableLeaf, None).unwrap();
+        pager.flush().unwrap();
+        pager.set_group_commit(true).unwrap();
+
+        // Three commits land before anybody waits for the sync
+        let before = pager.sync_count().unwrap();
+        let commits: Vec<u64> = (0..3)
+            .map(|i| {
+                pager.begin_transaction().unwrap();
+                add_row(&pager, page_number, i);
+                pager.commit_transaction_deferred().unwrap()
+            })
+            .collect();
+        assert_eq!(pager.sync_count().unwrap(), before);
+
+        // The first one to wait syncs for everybody
+        for commit in commits {
+            pager.sync_commit(commit).unwrap();
+        }
+        assert_eq!(pager.sync_count().unwrap() - before, 1);
+
+        // Without group commit the commit syncs on its own, and waiting does nothing
+        pager.set_group_commit(false).unwrap();
+        pager.begin_transaction().unwrap();
+        add_row(&pager, page_number, 3);
+        let commit = pager.commit_transaction_deferred().unwrap();
+        pager.sync_commit(commit).unwrap();
+        assert_eq!(pager.sync_count().unwrap() - before, 2);
+        assert_eq!(cell_count(&pager, page_number), 4);
+    }
+
     #[test] // Apparently, if you forget to drop the guard the buffer pool will also return it to you, causing a potential deadlock. Must fix this bug,
     fn test_page_eviction_with_guards() {
         let dir = tempdir().unwrap();
@@ -1022,6 +3133,122 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_read_ahead() {
+        let dir = tempdir().unwrap();
+        let db_path = dir.path().join("test.db");
+        let pager = Pager::create(&db_path, 4096, None, 0).unwrap();
+        let pages = (0..40)
+            .map(|_| pager.create_btree_page(PageType::TableLeaf, None).unwrap())
+            .collect::<Vec<_>>();
+        pager.flush().unwrap();
+        drop(pager);
+
+        let scan = |read_ahead: u32, order: &[u32]| {
+            let pager = Pager::open(&db_path, Some(16)).unwrap();
+            pager.set_read_ahead(read_ahead).unwrap();
+            pager.begin_read().unwrap();
+            for page_number in order {
+                let guard = pager.get_page(*page_number, None).unwrap();
+                assert_eq!(guard.page().page_number(), *page_number);
+            }
+            pager.end_read().unwrap();
+            pager.cache_stats().unwrap()
+        };
+
+        // Without read-ahead every page of the scan is a miss
+        let stats = scan(0, &pages);
+        assert_eq!(stats.cache_misses, 40);
+        assert_eq!(stats.pages_prefetched, 0);
+
+        // With it, only the first pages and one page per batch are
+        let stats = scan(4, &pages);
+        assert!(stats.cache_misses < 15, "{:?}", stats);
+        assert!(stats.pages_prefetched >= 30, "{:?}", stats);
+        assert_eq!(stats.prefetch_hits, 40 - stats.cache_misses);
+
+        // Random access does not trigger it
+        let mut shuffled = pages.clone();
+        shuffled.reverse();
+        let stats = scan(4, &shuffled);
+        assert_eq!(stats.pages_prefetched, 0);
+    }
+
+    #[test]
+    fn test_background_writer() {
+        let dir = tempdir().unwrap();
+        let db_path = dir.path().join("test.db");
+        let pager = Pager::create(&db_path, 4096, Some(16), 0).unwrap();
+        let pages = (0..12)
+            .map(|_| pager.create_btree_page(PageType::TableLeaf, None).unwrap())
+            .collect::<Vec<_>>();
+        pager.flush().unwrap();
+        assert!(pager
+            .snapshot()
+            .unwrap()
+            .set_background_writer(Some(BackgroundWriter::default()))
+            .is_err());
+
+        let wait_for_write_back = |pages: u64| {
+            let deadline = Instant::now() + Duration::from_secs(10);
+            while pager.cache_stats().unwrap().pages_written_back < pages {
+                assert!(
+                    Instant::now() < deadline,
+                    "the writer did not write the pages back"
+                );
+                thread::sleep(Duration::from_millis(5));
+            }
+        };
+
+        // Above the dirty ratio, the least recently used pages are written back until it is met
+        pager.begin_transaction().unwrap();
+        for page_number in &pages {
+            add_row(&pager, *page_number, 1);
+        }
+        pager
+            .set_background_writer(Some(BackgroundWriter {
+                interval: Duration::from_millis(5),
+                dirty_ratio: 0.25,
+                max_age: Duration::from_secs(3600),
+                max_pages: 3,
+            }))
+            .unwrap();
+        wait_for_write_back(8);
+        thread::sleep(Duration::from_millis(50));
+        assert_eq!(pager.cache_stats().unwrap().pages_written_back, 8);
+        assert_eq!(pager.lock_level().unwrap(), LockLevel::Exclusive);
+
+        // The journal has the original images of the pages written back
+        pager.rollback_transaction().unwrap();
+        for page_number in &pages {
+            assert_eq!(cell_count(&pager, *page_number), 0);
+        }
+
+        // Old pages are written back even below the ratio, and the commit still writes the rest
+        pager
+            .set_background_writer(Some(BackgroundWriter {
+                interval: Duration::from_millis(5),
+                dirty_ratio: 1.0,
+                max_age: Duration::ZERO,
+                max_pages: 64,
+            }))
+            .unwrap();
+        pager.begin_transaction().unwrap();
+        for page_number in &pages {
+            add_row(&pager, *page_number, 2);
+        }
+        wait_for_write_back(8 + 12);
+        add_row(&pager, pages[0], 3);
+        pager.commit_transaction().unwrap();
+        pager.close().unwrap();
+
+        let pager = Pager::open(&db_path, None).unwrap();
+        assert_eq!(cell_count(&pager, pages[0]), 2);
+        for page_number in &pages[1..] {
+            assert_eq!(cell_count(&pager, *page_number), 1);
+        }
+    }
+
     #[test]
     fn test_flush_dirty_pages() {
         let dir = tempdir().unwrap();
@@ -0,0 +1,509 @@
+//! # VFS Module
+//!
+//! The VFS (virtual file system) is the layer between the storage engine and the operating system, like the one of SQLite
+//! (https://www.sqlite.org/vfs.html). The DiskManager never touches a `std::fs::File` directly, it asks a `Vfs` to open the
+//! database file and then talks to it through the `VfsFile` trait, so the same engine can run on top of anything that
+//! can store bytes at an offset.
+//!
+//! Two implementations ship with the crate:
+//!
+//! - `OsVfs`: regular files of the operating system, with positional reads and writes and `fcntl` locks (see lock.rs).
+//! - `MemoryVfs`: files that live in memory and vanish with the last handle. Good for tests and for ephemeral databases,
+//!   such as caches, that do not need to survive the process. They never hit the disk, so syncing them is a no-op.
+//!
+//! Files of the OS can also be memory mapped for reading (see mmap.rs). The other VFSs simply say they cannot.
+//!
+//! Besides the database file, a VFS holds the journal next to it (see journal.rs), which is why it can delete files too.
+//! Every method takes `&self`, as a file is shared between the DiskManager and the commits that sync it outside the pager lock.
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+
+use super::lock::{set_lock, LockKind};
+use super::mmap::MemoryMap;
+
+/// A file system the storage engine can open its files on.
+pub trait Vfs: Send + Sync {
+    /// Opens a file.
+    ///
+    /// # Parameters
+    /// * `path` - Path of the file. Its meaning depends on the VFS.
+    /// * `create` - If `true`, the file is created, or truncated to zero bytes if it already exists.
+    ///   Otherwise the file must exist.
+    ///
+    /// # Errors
+    /// Returns an error if the file cannot be opened or created.
+    ///
+    /// # Returns
+    /// A shared handle to the file.
+    fn open(&self, path: &Path, create: bool) -> io::Result<Arc<dyn VfsFile>>;
+
+    /// Deletes a file. Handles that are already open keep working on the old contents.
+    /// When this returns, the file must be gone for good, even across a power loss.
+    ///
+    /// # Errors
+    /// Returns an error if the file does not exist or cannot be deleted.
+    fn delete(&self, path: &Path) -> io::Result<()>;
+
+    /// Checks if a file exists.
+    ///
+    /// # Errors
+    /// Returns an error if the VFS cannot tell.
+    fn exists(&self, path: &Path) -> io::Result<bool>;
+}
+
+/// An open file of a VFS. All the I/O is positional, there is no cursor to share between threads.
+pub trait VfsFile: Send + Sync {
+    /// Fills the buffer with the bytes at the given offset.
+    ///
+    /// # Errors
+    /// Returns an `UnexpectedEof` error if the file ends before the buffer is full.
+    fn read_at(&self, buffer: &mut [u8], offset: u64) -> io::Result<()>;
+
+    /// Writes the whole buffer at the given offset, growing the file if needed.
+    ///
+    /// # Errors
+    /// Returns an error if the bytes cannot be written.
+    fn write_at(&self, buffer: &[u8], offset: u64) -> io::Result<()>;
+
+    /// Writes the buffers one after the other, starting at the given offset, growing the file if needed.
+    /// The default writes them one by one, a VFS that can do better (`pwritev`) overrides it.
+    ///
+    /// # Errors
+    /// Returns an error if the bytes cannot be written. Some of the buffers may have been written then.
+    fn write_vectored_at(&self, buffers: &[&[u8]], offset: u64) -> io::Result<()> {
+        let mut offset = offset;
+        for buffer in buffers {
+            self.write_at(buffer, offset)?;
+            offset += buffer.len() as u64;
+        }
+        Ok(())
+    }
+
+    /// Makes sure everything written so far survives a power loss.
+    ///
+    /// # Parameters
+    /// * `data_only` - Only the contents must be synced, not the metadata of the file (fdatasync instead of fsync).
+    ///
+    /// # Errors
+    /// Returns an error if the file cannot be synced.
+    fn sync(&self, data_only: bool) -> io::Result<()>;
+
+    /// Sets the size of the file, dropping the bytes after it or filling the new ones with zeros.
+    ///
+    /// # Errors
+    /// Returns an error if the file cannot be resized.
+    fn truncate(&self, size: u64) -> io::Result<()>;
+
+    /// Gets the size of the file in bytes.
+    ///
+    /// # Errors
+    /// Returns an error if the size cannot be read.
+    fn size(&self) -> io::Result<u64>;
+
+    /// Places or removes a byte-range lock, without blocking. The protocol built on top is in lock.rs.
+    ///
+    /// # Parameters
+    /// * `kind` - Read lock, write lock or unlock.
+    /// * `start` - First byte of the range.
+    /// * `len` - Number of bytes of the range.
+    ///
+    /// # Errors
+    /// Returns the raw error of the platform if the range is locked by someone else,
+    /// which `FileLock` turns into a `Busy` error.
+    fn lock(&self, kind: LockKind, start: u64, len: u64) -> io::Result<()>;
+
+    /// Maps the first bytes of the file into memory, read-only (see mmap.rs).
+    ///
+    /// # Parameters
+    /// * `len` - Number of bytes to map. Not zero, and not past the end of the file.
+    ///
+    /// # Errors
+    /// Returns an error if the file cannot be mapped.
+    ///
+    /// # Returns
+    /// `None` if the VFS cannot map its files, which is the default. The DiskManager reads with `read_at` then.
+    fn map(&self, len: usize) -> io::Result<Option<MemoryMap>> {
+        let _ = len;
+        Ok(None)
+    }
+}
+
+/// The VFS of the operating system.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct OsVfs;
+
+impl Vfs for OsVfs {
+    fn open(&self, path: &Path, create: bool) -> io::Result<Arc<dyn VfsFile>> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(create)
+            .truncate(create)
+            .open(path)?;
+        if create {
+            // The new directory entry must survive a power loss too, or a synced journal could vanish with it
+            sync_parent(path)?;
+        }
+        Ok(Arc::new(OsFile::new(file)))
+    }
+
+    fn delete(&self, path: &Path) -> io::Result<()> {
+        std::fs::remove_file(path)?;
+        sync_parent(path)
+    }
+
+    fn exists(&self, path: &Path) -> io::Result<bool> {
+        path.try_exists()
+    }
+}
+
+/// Syncs the directory of a file, so that creating or deleting the file is durable.
+/// Directories cannot be opened as files everywhere, in that case there is nothing we can do.
+fn sync_parent(path: &Path) -> io::Result<()> {
+    #[cfg(unix)]
+    {
+        let parent = match path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+        File::open(parent)?.sync_all()
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = path;
+        Ok(())
+    }
+}
+
+/// Most buffers a single `pwritev` takes on Linux.
+#[cfg(target_os = "linux")]
+const IOV_MAX: usize = 1024;
+
+/// A file of the operating system.
+#[derive(Debug)]
+pub struct OsFile {
+    file: File,
+    /// Without positional I/O we have to seek first, and the seek and the read must not be interleaved.
+    #[cfg(not(unix))]
+    cursor: Mutex<()>,
+}
+
+impl OsFile {
+    /// Wraps an open file, which must be readable and writable.
+    pub fn new(file: File) -> Self {
+        OsFile {
+            file,
+            #[cfg(not(unix))]
+            cursor: Mutex::new(()),
+        }
+    }
+}
+
+impl VfsFile for OsFile {
+    #[cfg(unix)]
+    fn read_at(&self, buffer: &mut [u8], offset: u64) -> io::Result<()> {
+        use std::os::unix::fs::FileExt;
+        self.file.read_exact_at(buffer, offset)
+    }
+
+    #[cfg(not(unix))]
+    fn read_at(&self, buffer: &mut [u8], offset: u64) -> io::Result<()> {
+        use std::io::{Read, Seek, SeekFrom};
+        let _cursor = self.cursor.lock().unwrap_or_else(|e| e.into_inner());
+        let mut file = &self.file;
+        file.seek(SeekFrom::Start(offset))?;
+        file.read_exact(buffer)
+    }
+
+    #[cfg(unix)]
+    fn write_at(&self, buffer: &[u8], offset: u64) -> io::Result<()> {
+        use std::os::unix::fs::FileExt;
+        self.file.write_all_at(buffer, offset)
+    }
+
+    #[cfg(not(unix))]
+    fn write_at(&self, buffer: &[u8], offset: u64) -> io::Result<()> {
+        use std::io::{Seek, SeekFrom, Write};
+        let _cursor = self.cursor.lock().unwrap_or_else(|e| e.into_inner());
+        let mut file = &self.file;
+        file.seek(SeekFrom::Start(offset))?;
+        file.write_all(buffer)
+    }
+
+    #[cfg(target_os = "linux")]
+    fn write_vectored_at(&self, buffers: &[&[u8]], offset: u64) -> io::Result<()> {
+        use std::os::unix::io::AsRawFd;
+
+        let mut offset = offset;
+        for chunk in buffers.chunks(IOV_MAX) {
+            let iovecs = chunk
+                .iter()
+                .map(|buffer| libc::iovec {
+                    iov_base: buffer.as_ptr() as *mut libc::c_void,
+                    iov_len: buffer.len(),
+                })
+                .collect::<Vec<_>>();
+            let total = chunk.iter().map(|buffer| buffer.len()).sum::<usize>();
+
+            let written = loop {
+                // SAFETY: the iovecs point to buffers that outlive the call, and pwritev only reads them.
+                let result = unsafe {
+                    libc::pwritev(
+                        self.file.as_raw_fd(),
+                        iovecs.as_ptr(),
+                        iovecs.len() as libc::c_int,
+                        offset as libc::off_t,
+                    )
+                };
+                if result >= 0 {
+                    break result as usize;
+                }
+                let error = io::Error::last_os_error();
+                if error.kind() != io::ErrorKind::Interrupted {
+                    return Err(error);
+                }
+            };
+
+            // A short write is rare enough to finish it buffer by buffer
+            let mut skip = written;
+            let mut position = offset + written as u64;
+            for buffer in chunk {
+                if skip >= buffer.len() {
+                    skip -= buffer.len();
+                    continue;
+                }
+                self.write_at(&buffer[skip..], position)?;
+                position += (buffer.len() - skip) as u64;
+                skip = 0;
+            }
+            offset += total as u64;
+        }
+        Ok(())
+    }
+
+    fn sync(&self, data_only: bool) -> io::Result<()> {
+        if data_only {
+            self.file.sync_data()
+        } else {
+            self.file.sync_all()
+        }
+    }
+
+    fn truncate(&self, size: u64) -> io::Result<()> {
+        self.file.set_len(size)
+    }
+
+    fn size(&self) -> io::Result<u64> {
+        Ok(self.file.metadata()?.len())
+    }
+
+    fn lock(&self, kind: LockKind, start: u64, len: u64) -> io::Result<()> {
+        set_lock(&self.file, kind, start, len)
+    }
+
+    fn map(&self, len: usize) -> io::Result<Option<MemoryMap>> {
+        if cfg!(unix) {
+            MemoryMap::new(&self.file, len).map(Some)
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+/// A VFS whose files live in memory.
+///
+/// Files are kept by path while the VFS is alive, so opening the same path again (without `create`)
+/// gives back the same bytes. A file handed out by `open` stays alive as long as someone holds it,
+/// even after the VFS is dropped.
+#[derive(Default)]
+pub struct MemoryVfs {
+    files: Mutex<HashMap<PathBuf, Arc<MemoryFile>>>,
+}
+
+impl MemoryVfs {
+    /// Creates an empty in-memory file system.
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Vfs for MemoryVfs {
+    fn open(&self, path: &Path, create: bool) -> io::Result<Arc<dyn VfsFile>> {
+        let mut files = self
+            .files
+            .lock()
+            .map_err(|e| io::Error::other(format!("Lock poisoned: {}", e)))?;
+
+        if create {
+            let file = files.entry(path.to_path_buf()).or_default();
+            file.truncate(0)?;
+            return Ok(Arc::clone(file) as Arc<dyn VfsFile>);
+        }
+
+        match files.get(path) {
+            Some(file) => Ok(Arc::clone(file) as Arc<dyn VfsFile>),
+            None => Err(not_found(path)),
+        }
+    }
+
+    fn delete(&self, path: &Path) -> io::Result<()> {
+        let mut files = self
+            .files
+            .lock()
+            .map_err(|e| io::Error::other(format!("Lock poisoned: {}", e)))?;
+        files
+            .remove(path)
+            .map(|_| ())
+            .ok_or_else(|| not_found(path))
+    }
+
+    fn exists(&self, path: &Path) -> io::Result<bool> {
+        let files = self
+            .files
+            .lock()
+            .map_err(|e| io::Error::other(format!("Lock poisoned: {}", e)))?;
+        Ok(files.contains_key(path))
+    }
+}
+
+/// Error for a path that is not in a `MemoryVfs`.
+fn not_found(path: &Path) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::NotFound,
+        format!("No such in-memory file: {}", path.display()),
+    )
+}
+
+/// A file of a `MemoryVfs`.
+#[derive(Debug, Default)]
+pub struct MemoryFile {
+    data: RwLock<Vec<u8>>,
+}
+
+impl VfsFile for MemoryFile {
+    fn read_at(&self, buffer: &mut [u8], offset: u64) -> io::Result<()> {
+        let data = self.data.read().unwrap_or_else(|e| e.into_inner());
+        let start = offset as usize;
+        let end = start + buffer.len();
+        if end > data.len() {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "failed to fill whole buffer",
+            ));
+        }
+        buffer.copy_from_slice(&data[start..end]);
+        Ok(())
+    }
+
+    fn write_at(&self, buffer: &[u8], offset: u64) -> io::Result<()> {
+        let mut data = self.data.write().unwrap_or_else(|e| e.into_inner());
+        let start = offset as usize;
+        let end = start + buffer.len();
+        if end > data.len() {
+            data.resize(end, 0);
+        }
+        data[start..end].copy_from_slice(buffer);
+        Ok(())
+    }
+
+    fn sync(&self, _data_only: bool) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn truncate(&self, size: u64) -> io::Result<()> {
+        let mut data = self.data.write().unwrap_or_else(|e| e.into_inner());
+        data.resize(size as usize, 0);
+        Ok(())
+    }
+
+    fn size(&self) -> io::Result<u64> {
+        Ok(self.data.read().unwrap_or_else(|e| e.into_inner()).len() as u64)
+    }
+
+    /// In-memory files can only be shared inside the process, and (just like `fcntl` locks) the locks
+    /// of a process never conflict with each other, so there is nothing to lock.
+    fn lock(&self, _kind: LockKind, _start: u64, _len: u64) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    /// Runs the same checks against any VFS.
+    fn check_file_operations(vfs: &dyn Vfs, path: &Path) {
+        let file = vfs.open(path, true).unwrap();
+        assert_eq!(file.size().unwrap(), 0);
+
+        file.write_at(b"hello", 10).unwrap();
+        assert_eq!(file.size().unwrap(), 15);
+
+        let mut buffer = [0xFFu8; 15];
+        file.read_at(&mut buffer, 0).unwrap();
+        assert_eq!(&buffer[..10], &[0u8; 10]);
+        assert_eq!(&buffer[10..], b"hello");
+
+        // Reading past the end fails instead of returning a short buffer
+        let error = file.read_at(&mut buffer, 5).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+
+        file.truncate(12).unwrap();
+        assert_eq!(file.size().unwrap(), 12);
+        file.truncate(20).unwrap();
+        let mut buffer = [0xFFu8; 10];
+        file.read_at(&mut buffer, 10).unwrap();
+        assert_eq!(&buffer, b"he\0\0\0\0\0\0\0\0");
+
+        file.sync(false).unwrap();
+        file.sync(true).unwrap();
+        file.write_vectored_at(&[b"ab", b"", b"cde"], 18).unwrap();
+        assert_eq!(file.size().unwrap(), 23);
+        let mut buffer = [0u8; 7];
+        file.read_at(&mut buffer, 16).unwrap();
+        assert_eq!(&buffer, b"\0\0abcde");
+        file.truncate(20).unwrap();
+
+        file.lock(LockKind::Read, 1000, 10).unwrap();
+        file.lock(LockKind::Unlock, 1000, 10).unwrap();
+
+        // Opening the file again sees the same contents, and creating it again empties it
+        assert_eq!(vfs.open(path, false).unwrap().size().unwrap(), 20);
+        assert_eq!(vfs.open(path, true).unwrap().size().unwrap(), 0);
+
+        assert!(vfs.exists(path).unwrap());
+        vfs.delete(path).unwrap();
+        assert!(!vfs.exists(path).unwrap());
+        assert!(vfs.open(path, false).is_err());
+        assert!(vfs.delete(path).is_err());
+    }
+
+    #[test]
+    fn test_os_vfs() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("vfs.db");
+        assert!(OsVfs.open(&path, false).is_err());
+        check_file_operations(&OsVfs, &path);
+    }
+
+    #[test]
+    fn test_memory_vfs() {
+        let vfs = MemoryVfs::new();
+        let path = Path::new("vfs.db");
+        assert_eq!(
+            vfs.open(path, false).err().map(|e| e.kind()),
+            Some(io::ErrorKind::NotFound)
+        );
+        check_file_operations(&vfs, path);
+
+        // Open handles outlive the VFS
+        let file = vfs.open(path, true).unwrap();
+        drop(vfs);
+        file.write_at(b"still here", 0).unwrap();
+        assert_eq!(file.size().unwrap(), 10);
+    }
+}
@@ -0,0 +1,640 @@
+//! # Transaction Module
+//!
+//! `RQLite::begin_transaction` and friends work, but they are free-floating calls: if anything in between
+//! returns early with `?`, the transaction stays open and the next caller finds it there.
+//! The `Transaction` type here is a guard that owns the transaction instead. Its operations run inside it,
+//! `commit` makes them permanent, and if the guard is dropped without committing (an early return, a panic...)
+//! everything is rolled back. This is the same RAII trick the pager uses with the page guards.
+//!
+//! For the common case there is also `RQLite::with_transaction`, which takes a closure, commits when it
+//! returns `Ok` and rolls back when it returns `Err`.
+//!
+//! There is a single transaction per database, so opening a transaction while another one is active fails.
+//! To undo only part of a transaction, open a savepoint in it (`Transaction::savepoint`) and roll back to it.
+use std::io;
+
+use crate::tree::btree::TreeType;
+use crate::{
+    find_row, find_row_columns, Column, IndexId, KeyValue, RQLite, Record, Rows, SqliteValue,
+    TableId,
+};
+
+/// An active transaction on a database.
+///
+/// The transaction is rolled back when the guard is dropped, unless `commit` was called.
+/// Reads through the guard see the changes made by the transaction, whatever the thread they run on.
+///
+/// # Example
+/// ```rust
+/// # fn main() -> std::io::Result<()> {
+/// # let dir = tempfile::tempdir()?;
+/// # let db = rqlite_engine::RQLite::create(dir.path().join("doc.db"), None)?;
+/// use rqlite_engine::{Record, SqliteValue};
+///
+/// let table_id = db.create_table()?;
+/// let record = Record::with_values(vec![SqliteValue::Integer(42)]);
+///
+/// let tx = db.transaction()?;
+/// tx.table_insert(table_id, 1, &record)?;
+/// assert!(tx.table_find(table_id, 1)?.is_some());
+/// tx.commit()?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct Transaction<'a> {
+    /// Database the transaction runs on.
+    db: &'a RQLite,
+    /// Whether the transaction was already committed or rolled back.
+    finished: bool,
+}
+
+impl<'a> Transaction<'a> {
+    /// Begins a transaction on a database.
+    pub(crate) fn begin(db: &'a RQLite) -> io::Result<Self> {
+        db.begin_transaction()?;
+        Ok(Transaction {
+            db,
+            finished: false,
+        })
+    }
+
+    /// Runs an operation of the guard. It joins the transaction from any thread: it is the guard that owns it,
+    /// not the thread that began it (see `RQLite::guarded`).
+    fn run<R>(&self, op: impl FnOnce(&RQLite) -> io::Result<R>) -> io::Result<R> {
+        self.db.guarded(|| op(self.db))
+    }
+
+    /// Creates a new table inside the transaction.
+    ///
+    /// # Errors
+    /// Returns an error if the table cannot be created.
+    ///
+    /// # Returns
+    /// The unique identifier for the newly created table.
+    pub fn create_table(&self) -> io::Result<TableId> {
+        self.run(|db| db.create_table())
+    }
+
+    /// Creates a new table with columns inside the transaction (see `RQLite::create_table_with_columns`).
+    ///
+    /// # Parameters
+    /// * `columns` - The columns of the table, in order.
+    ///
+    /// # Errors
+    /// Returns an error if the columns are not valid or the table cannot be created.
+    ///
+    /// # Returns
+    /// The unique identifier for the newly created table.
+    pub fn create_table_with_columns(&self, columns: Vec<Column>) -> io::Result<TableId> {
+        self.run(|db| db.create_table_with_columns(columns))
+    }
+
+    /// Adds a column at the end of a table inside the transaction (see `RQLite::add_column`).
+    ///
+    /// # Parameters
+    /// * `table_id` - The table to add the column to.
+    /// * `column` - The new column.
+    ///
+    /// # Errors
+    /// Returns an error if the table has no columns, the column is not valid or there are I/O issues.
+    pub fn add_column(&self, table_id: TableId, column: Column) -> io::Result<()> {
+        self.run(|db| db.add_column(table_id, column))
+    }
+
+    /// Renames a column of a table inside the transaction (see `RQLite::rename_column`).
+    ///
+    /// # Parameters
+    /// * `table_id` - The table of the column.
+    /// * `name` - Current name of the column.
+    /// * `new_name` - New name of the column.
+    ///
+    /// # Errors
+    /// Returns an error if there is no such column, the new name is taken or there are I/O issues.
+    pub fn rename_column(&self, table_id: TableId, name: &str, new_name: &str) -> io::Result<()> {
+        self.run(|db| db.rename_column(table_id, name, new_name))
+    }
+
+    /// Drops a column of a table inside the transaction, rewriting its rows (see `RQLite::drop_column`).
+    ///
+    /// # Parameters
+    /// * `table_id` - The table of the column.
+    /// * `name` - Name of the column.
+    ///
+    /// # Errors
+    /// Returns an error if there is no such column, it is the only one or there are I/O issues.
+    pub fn drop_column(&self, table_id: TableId, name: &str) -> io::Result<()> {
+        self.run(|db| db.drop_column(table_id, name))
+    }
+
+    /// Names or renames a table inside the transaction (see `RQLite::rename_table`).
+    ///
+    /// # Parameters
+    /// * `table_id` - The table to name.
+    /// * `name` - New name of the table.
+    ///
+    /// # Errors
+    /// Returns an error if the name is empty or taken, or there are I/O issues.
+    pub fn rename_table(&self, table_id: TableId, name: &str) -> io::Result<()> {
+        self.run(|db| db.rename_table(table_id, name))
+    }
+
+    /// Creates a new index on a table inside the transaction.
+    ///
+    /// # Parameters
+    /// * `table_id` - The table to create the index on.
+    ///
+    /// # Errors
+    /// Returns an error if the index cannot be created.
+    ///
+    /// # Returns
+    /// The unique identifier for the newly created index.
+    pub fn create_index(&self, table_id: TableId) -> io::Result<IndexId> {
+        self.run(|db| db.create_index(table_id))
+    }
+
+    /// Inserts a record into a table inside the transaction.
+    ///
+    /// # Parameters
+    /// * `table_id` - The table to insert into.
+    /// * `rowid` - The row identifier for the record.
+    /// * `record` - The record to insert.
+    ///
+    /// # Errors
+    /// Returns an error if the table does not exist, the rowid is taken, or there are I/O issues.
+    pub fn table_insert(&self, table_id: TableId, rowid: i64, record: &Record) -> io::Result<()> {
+        self.run(|db| db.table_insert(table_id, rowid, record))
+    }
+
+    /// Finds a record in a table, seeing the changes made by the transaction.
+    ///
+    /// # Parameters
+    /// * `table_id` - The table to search in.
+    /// * `rowid` - The row identifier to search for.
+    ///
+    /// # Errors
+    /// Returns an error if the table does not exist or there are I/O issues.
+    ///
+    /// # Returns
+    /// The record if found, or None if no record exists with the given rowid.
+    pub fn table_find(&self, table_id: TableId, rowid: i64) -> io::Result<Option<Record>> {
+        self.db
+            .read_live(TreeType::Table, table_id, |btree, table_schema| {
+                find_row(btree, table_schema, rowid)
+            })
+    }
+
+    /// Finds some columns of a record in a table, seeing the changes made by the transaction
+    /// (see `RQLite::table_find_columns`).
+    ///
+    /// # Parameters
+    /// * `table_id` - The table to search in.
+    /// * `rowid` - The row identifier to search for.
+    /// * `columns` - Indexes of the columns to get.
+    ///
+    /// # Errors
+    /// Returns an error if the table does not exist or there are I/O issues.
+    ///
+    /// # Returns
+    /// The requested columns if found, or None if no record exists with the given rowid.
+    pub fn table_find_columns(
+        &self,
+        table_id: TableId,
+        rowid: i64,
+        columns: &[usize],
+    ) -> io::Result<Option<Record>> {
+        self.db
+            .read_live(TreeType::Table, table_id, |btree, table_schema| {
+                find_row_columns(btree, table_schema, rowid, columns)
+            })
+    }
+
+    /// Deletes a record from a table inside the transaction.
+    ///
+    /// # Parameters
+    /// * `table_id` - The table to delete from.
+    /// * `rowid` - The row identifier of the record to delete.
+    ///
+    /// # Errors
+    /// Returns an error if the table does not exist or there are I/O issues.
+    ///
+    /// # Returns
+    /// True if a record was deleted, false if no record existed with the given rowid.
+    pub fn table_delete(&self, table_id: TableId, rowid: i64) -> io::Result<bool> {
+        self.run(|db| db.table_delete(table_id, rowid))
+    }
+
+    /// Inserts an entry into an index inside the transaction.
+    ///
+    /// # Parameters
+    /// * `index_id` - The index to insert into.
+    /// * `key` - The key to index.
+    /// * `rowid` - The row identifier the key points to.
+    ///
+    /// # Errors
+    /// Returns an error if the index does not exist or there are I/O issues.
+    pub fn index_insert(&self, index_id: IndexId, key: &[u8], rowid: i64) -> io::Result<()> {
+        self.run(|db| db.index_insert(index_id, key, rowid))
+    }
+
+    /// Finds a key in an index, seeing the changes made by the transaction.
+    ///
+    /// # Parameters
+    /// * `index_id` - The index to search in.
+    /// * `key` - The key to search for.
+    ///
+    /// # Errors
+    /// Returns an error if the index does not exist or there are I/O issues.
+    ///
+    /// # Returns
+    /// Whether the key was found, and the leaf page and position where it is (or would be).
+    pub fn index_find(&self, index_id: IndexId, key: &KeyValue) -> io::Result<(bool, u32, u16)> {
+        self.db.read_live(TreeType::Index, index_id, |btree, _| {
+            btree.find_index_key(key)
+        })
+    }
+
+    /// Deletes an entry from an index inside the transaction.
+    ///
+    /// # Parameters
+    /// * `index_id` - The index to delete from.
+    /// * `key` - The key to delete.
+    ///
+    /// # Errors
+    /// Returns an error if the index does not exist or there are I/O issues.
+    ///
+    /// # Returns
+    /// True if an entry was deleted, false if the key was not in the index.
+    pub fn index_delete(&self, index_id: IndexId, key: &KeyValue) -> io::Result<bool> {
+        self.run(|db| db.index_delete(index_id, key))
+    }
+
+    /// Creates a named sequence inside the transaction (see `RQLite::create_sequence`).
+    ///
+    /// # Parameters
+    /// * `name` - Name of the sequence.
+    /// * `start` - First value handed out.
+    /// * `increment` - Difference between two values.
+    /// * `cache` - How many values a connection reserves at once, 0 for a gap-free sequence.
+    ///
+    /// # Errors
+    /// Returns an error if the name is empty or taken, the increment is 0 or there are I/O issues.
+    pub fn create_sequence(
+        &self,
+        name: &str,
+        start: i64,
+        increment: i64,
+        cache: u32,
+    ) -> io::Result<()> {
+        self.run(|db| db.create_sequence(name, start, increment, cache))
+    }
+
+    /// Gets the next value of a sequence (see `RQLite::next_value`). The value of a gap-free sequence
+    /// is given again if the transaction rolls back.
+    ///
+    /// # Parameters
+    /// * `name` - Name of the sequence.
+    ///
+    /// # Errors
+    /// Returns an error if there is no such sequence, it has no values left or there are I/O issues.
+    ///
+    /// # Returns
+    /// The value.
+    pub fn next_value(&self, name: &str) -> io::Result<i64> {
+        self.run(|db| db.next_value(name))
+    }
+
+    /// Sets the next value a sequence hands out inside the transaction (see `RQLite::set_value`).
+    ///
+    /// # Parameters
+    /// * `name` - Name of the sequence.
+    /// * `value` - The next value.
+    ///
+    /// # Errors
+    /// Returns an error if there is no such sequence or there are I/O issues.
+    pub fn set_value(&self, name: &str, value: i64) -> io::Result<()> {
+        self.run(|db| db.set_value(name, value))
+    }
+
+    /// Drops a sequence inside the transaction.
+    ///
+    /// # Parameters
+    /// * `name` - Name of the sequence.
+    ///
+    /// # Errors
+    /// Returns an error if there is no such sequence or there are I/O issues.
+    pub fn drop_sequence(&self, name: &str) -> io::Result<()> {
+        self.run(|db| db.drop_sequence(name))
+    }
+
+    /// Runs SQL statements inside the transaction (see `RQLite::execute`).
+    /// `BEGIN`, `COMMIT` and `ROLLBACK` are refused, use `commit` and `rollback` instead.
+    ///
+    /// # Parameters
+    /// * `sql` - The statements.
+    /// * `params` - Values for the parameters of the statements.
+    ///
+    /// # Errors
+    /// Returns an error if a statement is not valid, fails a constraint, or there are I/O issues.
+    ///
+    /// # Returns
+    /// The number of rows inserted, updated or deleted.
+    pub fn execute(&self, sql: &str, params: &[SqliteValue]) -> io::Result<usize> {
+        self.run(|db| db.run_sql(sql, params, true, false))
+            .map(|(changes, _)| changes)
+    }
+
+    /// Runs a single SQL statement inside the transaction and returns its rows,
+    /// seeing the changes made by the transaction (see `RQLite::query`).
+    ///
+    /// # Parameters
+    /// * `sql` - The statement, usually a SELECT.
+    /// * `params` - Values for the parameters of the statement.
+    ///
+    /// # Errors
+    /// Returns the same errors as `execute`, and an error if `sql` holds more than one statement.
+    ///
+    /// # Returns
+    /// The rows of the statement.
+    pub fn query(&self, sql: &str, params: &[SqliteValue]) -> io::Result<Rows> {
+        self.run(|db| db.run_sql(sql, params, true, true))
+            .map(|(_, rows)| rows)
+    }
+
+    /// Opens a savepoint in the transaction (see `RQLite::savepoint`).
+    ///
+    /// # Parameters
+    /// * `name` - Name of the savepoint.
+    ///
+    /// # Errors
+    /// Returns an error if the transaction already ended.
+    pub fn savepoint(&self, name: &str) -> io::Result<()> {
+        self.run(|db| db.savepoint(name))
+    }
+
+    /// Releases a savepoint, keeping its changes in the transaction (see `RQLite::release_savepoint`).
+    ///
+    /// # Parameters
+    /// * `name` - Name of the savepoint.
+    ///
+    /// # Errors
+    /// Returns an error if there is no savepoint with that name.
+    pub fn release(&self, name: &str) -> io::Result<()> {
+        self.run(|db| db.release_savepoint(name))
+    }
+
+    /// Undoes the changes made since a savepoint was opened (see `RQLite::rollback_to_savepoint`).
+    ///
+    /// # Parameters
+    /// * `name` - Name of the savepoint.
+    ///
+    /// # Errors
+    /// Returns an error if there is no savepoint with that name or the original pages cannot be restored.
+    pub fn rollback_to(&self, name: &str) -> io::Result<()> {
+        self.run(|db| db.rollback_to_savepoint(name))
+    }
+
+    /// Commits the transaction, making all its changes permanent.
+    ///
+    /// # Errors
+    /// Returns an error if the changes cannot be written. The transaction is rolled back in that case.
+    pub fn commit(mut self) -> io::Result<()> {
+        self.run(|db| db.commit_transaction())?;
+        self.finished = true;
+        Ok(())
+    }
+
+    /// Rolls back the transaction, discarding all its changes.
+    ///
+    /// # Errors
+    /// Returns an error if the original pages cannot be restored.
+    pub fn rollback(mut self) -> io::Result<()> {
+        self.finished = true;
+        self.run(|db| db.rollback_transaction())
+    }
+}
+
+impl Drop for Transaction<'_> {
+    fn drop(&mut self) {
+        // Nobody committed, so whatever happened in between must be undone
+        if !self.finished {
+            let _ = self.run(|db| db.rollback_transaction());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::serialize_values;
+    use crate::storage::lock::is_busy;
+    use std::time::Duration;
+    use tempfile::tempdir;
+
+    fn record(value: i64) -> Record {
+        Record::with_values(vec![SqliteValue::Integer(value)])
+    }
+
+    #[test]
+    fn test_commit_and_drop() {
+        let dir = tempdir().unwrap();
+        let db = RQLite::create(dir.path().join("tx.db"), None).unwrap();
+        let table_id = db.create_table().unwrap();
+
+        let tx = db.transaction().unwrap();
+        tx.table_insert(table_id, 1, &record(1)).unwrap();
+        tx.commit().unwrap();
+        assert!(db.table_find(table_id, 1).unwrap().is_some());
+
+        {
+            let tx = db.transaction().unwrap();
+            tx.table_insert(table_id, 2, &record(2)).unwrap();
+            assert!(tx.table_find(table_id, 2).unwrap().is_some());
+            // Dropped without committing
+        }
+        assert!(db.table_find(table_id, 2).unwrap().is_none());
+        assert!(!db.shared.pager.in_transaction().unwrap());
+
+        let tx = db.transaction().unwrap();
+        tx.table_delete(table_id, 1).unwrap();
+        tx.rollback().unwrap();
+        assert!(db.table_find(table_id, 1).unwrap().is_some());
+    }
+
+    #[test]
+    fn test_early_return_rolls_back() {
+        let dir = tempdir().unwrap();
+        let db = RQLite::create(dir.path().join("tx.db"), None).unwrap();
+        let table_id = db.create_table().unwrap();
+
+        let import = |tables: &[TableId]| -> io::Result<()> {
+            let tx = db.transaction()?;
+            for (rowid, &table) in tables.iter().enumerate() {
+                tx.table_insert(table, rowid as i64 + 1, &record(1))?;
+            }
+            tx.commit()
+        };
+
+        // The missing table makes the second insert fail halfway
+        assert!(import(&[table_id, 999]).is_err());
+        assert!(db.table_find(table_id, 1).unwrap().is_none());
+
+        import(&[table_id, table_id]).unwrap();
+        assert!(db.table_find(table_id, 2).unwrap().is_some());
+    }
+
+    #[test]
+    fn test_with_transaction() {
+        let dir = tempdir().unwrap();
+        let db = RQLite::create(dir.path().join("tx.db"), None).unwrap();
+        let table_id = db.create_table().unwrap();
+
+        let index_id = db
+            .with_transaction(|tx| {
+                let index_id = tx.create_index(table_id)?;
+                tx.table_insert(table_id, 1, &record(1))?;
+                let mut key = Vec::new();
+                serialize_values(&[SqliteValue::Integer(1)], &mut key)?;
+                tx.index_insert(index_id, &key, 1)?;
+                Ok(index_id)
+            })
+            .unwrap();
+        assert!(db.table_find(table_id, 1).unwrap().is_some());
+        assert!(db.index_exists(index_id));
+        let (found, _, _) = db.index_find(index_id, &KeyValue::Integer(1)).unwrap();
+        assert!(found);
+
+        let result: io::Result<()> = db.with_transaction(|tx| {
+            tx.table_insert(table_id, 2, &record(2))?;
+            Err(io::Error::other("changed my mind"))
+        });
+        assert!(result.is_err());
+        assert!(db.table_find(table_id, 2).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_retry_batch_with_savepoint() {
+        let dir = tempdir().unwrap();
+        let db = RQLite::create(dir.path().join("tx.db"), None).unwrap();
+        let table_id = db.create_table().unwrap();
+
+        let tx = db.transaction().unwrap();
+        let mut attempts = 0;
+        let mut failed = false;
+        for batch in 0..3i64 {
+            loop {
+                tx.savepoint("batch").unwrap();
+                let result = (1..=200).try_for_each(|i| {
+                    let rowid = batch * 200 + i;
+                    // The second batch fails halfway the first time, after splitting a few pages
+                    if batch == 1 && !failed && i == 150 {
+                        failed = true;
+                        return Err(io::Error::other("batch failed"));
+                    }
+                    let record = Record::with_values(vec![
+                        SqliteValue::Integer(rowid),
+                        SqliteValue::String(format!("Imported row number {}", rowid)),
+                    ]);
+                    tx.table_insert(table_id, rowid, &record)
+                });
+                attempts += 1;
+
+                match result {
+                    Ok(()) => {
+                        tx.release("batch").unwrap();
+                        break;
+                    }
+                    Err(_) => tx.rollback_to("batch").unwrap(),
+                }
+            }
+        }
+        tx.commit().unwrap();
+
+        assert_eq!(attempts, 4);
+        for rowid in 1..=600 {
+            let record = db.table_find(table_id, rowid).unwrap().unwrap();
+            match &record.values[0] {
+                SqliteValue::Integer(value) => assert_eq!(*value, rowid),
+                _ => panic!("Expected integer"),
+            }
+        }
+        assert!(db.table_find(table_id, 601).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_rollback_to_forgets_new_tables() {
+        let dir = tempdir().unwrap();
+        let db = RQLite::create(dir.path().join("tx.db"), None).unwrap();
+
+        db.with_transaction(|tx| {
+            let kept = tx.create_table()?;
+            tx.savepoint("schema")?;
+            let dropped = tx.create_table()?;
+            tx.rollback_to("schema")?;
+
+            assert!(db.table_exists(kept));
+            assert!(!db.table_exists(dropped));
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(db.list_tables().len(), 1);
+    }
+
+    #[test]
+    fn test_nested_transaction_rejected() {
+        let dir = tempdir().unwrap();
+        let db = RQLite::create(dir.path().join("tx.db"), None).unwrap();
+        let table_id = db.create_table().unwrap();
+
+        let tx = db.transaction().unwrap();
+        tx.table_insert(table_id, 1, &record(1)).unwrap();
+
+        let err = db.transaction().err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        let err = db.with_transaction(|_| Ok(())).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+
+        // The outer transaction is still there
+        tx.commit().unwrap();
+        assert!(db.table_find(table_id, 1).unwrap().is_some());
+    }
+
+    #[test]
+    fn test_other_threads_do_not_join_the_guard() {
+        let dir = tempdir().unwrap();
+        let db = RQLite::create(dir.path().join("tx_threads.db"), None).unwrap();
+        let table_id = db.create_table().unwrap();
+
+        // Without a busy timeout the other thread gives up right away
+        let tx = db.transaction().unwrap();
+        tx.table_insert(table_id, 1, &record(1)).unwrap();
+        let writer = db.clone();
+        let err = std::thread::spawn(move || writer.table_insert(table_id, 2, &record(2)))
+            .join()
+            .unwrap()
+            .unwrap_err();
+        assert!(is_busy(&err));
+        drop(tx);
+        assert!(db.table_find(table_id, 1).unwrap().is_none());
+        assert!(db.table_find(table_id, 2).unwrap().is_none());
+
+        // With one it waits for the guard to roll back, then commits on its own
+        db.set_busy_timeout(Duration::from_secs(10)).unwrap();
+        let tx = db.transaction().unwrap();
+        tx.table_insert(table_id, 1, &record(1)).unwrap();
+        let writer = db.clone();
+        let handle = std::thread::spawn(move || writer.table_insert(table_id, 2, &record(2)));
+        std::thread::sleep(Duration::from_millis(50));
+        drop(tx);
+        handle.join().unwrap().unwrap();
+        assert!(db.table_find(table_id, 1).unwrap().is_none());
+        assert!(db.table_find(table_id, 2).unwrap().is_some());
+
+        // The guard itself still writes from any thread
+        let tx = db.transaction().unwrap();
+        std::thread::scope(|scope| {
+            scope.spawn(|| tx.table_insert(table_id, 3, &record(3)).unwrap());
+        });
+        tx.commit().unwrap();
+        assert!(db.table_find(table_id, 3).unwrap().is_some());
+    }
+}
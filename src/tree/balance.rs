@@ -0,0 +1,883 @@
+//! # Balance Module
+//!
+//! This is where the B-Tree keeps its shape after an insert or a delete, following what `balance()` does in
+//! SQLite's btree.c (https://www.sqlite.org/btreeinfo.html has a short description of it).
+//!
+//! The first version split a full page in two halves, and when a page got empty it borrowed one cell from a sibling
+//! or merged with it. That works, but every split leaves two half empty pages behind, and a page that lost
+//! all but one of its rows keeps its own page forever. So now, when a page overflows or drops under a third of its
+//! capacity, the cells of the page and of up to two of its neighbours (three siblings in total, like SQLite)
+//! are gathered and spread again over as few pages as they need, as evenly as possible:
+//! - First the pages are filled from the left, as full as they get. That gives the number of pages.
+//! - Then cells move from each page to its right neighbour, while the right one stays smaller than the left one.
+//!   SQLite makes a single pass from right to left, we go on until nothing moves, so that the last page is not left half empty.
+//! - The old page numbers are reused. If one more page is needed it is allocated, and the pages left over are freed.
+//! - The dividers in the parent are rebuilt. A leaf divider is the largest key of the page to its left,
+//!   as an interior key routes the keys equal to it to its left child. On interior levels the old dividers come down as cells
+//!   (pointing to the rightmost child of the page to their left), and the cells at the new boundaries go up instead.
+//!
+//! The parent can overflow or get underfull in turn, so the same goes on one level up until a page is fine.
+//!
+//! Tables are mostly written in rowid order though, and then the page that overflows is always the last leaf,
+//! and it will never get another row in the middle. Spreading it with its siblings would leave them all
+//! a third empty for good. So in that case the row starts a new last leaf on its own, and the full one stays as it is
+//! (`balance_quick` in SQLite), which leaves the leaves of a table written in order about 100% full.
+//!
+//! The root is where the tree grows and shrinks, as in the first version:
+//! - When the root overflows, a new root goes on top of it, with the old root as its only child.
+//!   The old root is then balanced like any other page, which gives the new root its first divider (`balance_deeper` in SQLite).
+//! - When the root ends up as an interior page without cells, its only child becomes the root (`balance_shallower`).
+//!   SQLite copies the child into the root page instead, to keep the root page number, but the catalog saves
+//!   the root page of every tree at commit anyway.
+//!
+//! There is no freelist yet, so freed pages are only marked as free pages. They stay in the file until it gets one.
+use std::io;
+
+use crate::page::{BTreeCell, BTreePage, FreePage, Page, PageType, TableInteriorCell};
+use crate::tree::btree::{BTree, TreeType};
+use crate::tree::node::extract_key_from_payload;
+
+/// Number of siblings balanced together, counting the page that needs it.
+const BALANCE_SIBLINGS: usize = 3;
+
+/// The content of a page while it is balanced. It does not have to fit in the page.
+struct Node {
+    page_type: PageType,
+    cells: Vec<BTreeCell>,
+    right_most: Option<u32>,
+}
+
+impl BTree {
+    /// Inserts a cell into a leaf, balancing the tree if the leaf overflows.
+    ///
+    /// # Parameters
+    /// * `leaf_page` - Page number of the leaf.
+    /// * `path` - The interior pages from the root down to the leaf, each with the index of the child taken.
+    /// * `index` - Position of the new cell in the leaf.
+    /// * `cell` - The cell to insert.
+    ///
+    /// # Errors
+    /// Returns an error if there are I/O issues.
+    pub(crate) fn insert_into_leaf(
+        &mut self,
+        leaf_page: u32,
+        path: Vec<(u32, u16)>,
+        index: u16,
+        cell: BTreeCell,
+    ) -> io::Result<()> {
+        // Most of the time the cell fits and nothing else changes
+        let cell = self
+            .pager()
+            .get_page_mut_callback(leaf_page, None, |page| match page {
+                Page::BTree(btree_page) if cell.size() + 2 <= btree_page.free_space() => {
+                    btree_page.insert_cell(index, cell)?;
+                    Ok(None)
+                }
+                Page::BTree(_) => Ok(Some(cell)),
+                _ => Err(not_a_btree_page(leaf_page)),
+            })?;
+
+        if let Some(cell) = cell {
+            let mut node = self.read_node(leaf_page)?;
+            if self.is_append(&node, &path, index)? {
+                return self.balance_quick(leaf_page, path, node, cell);
+            }
+            node.cells.insert(index as usize, cell);
+            self.balance(leaf_page, path, node)?;
+        }
+        Ok(())
+    }
+
+    /// Checks if an insert that overflows a leaf goes past the end of the tree, for `balance_quick`:
+    /// the tree is a table, the cell goes after the last one of the leaf, and the leaf is the rightmost child of its parent.
+    /// SQLite checks the same (and that the parent is not page 1, which is smaller than the others).
+    ///
+    /// # Errors
+    /// Returns an error if the parent cannot be read.
+    fn is_append(&self, leaf: &Node, path: &[(u32, u16)], index: u16) -> io::Result<bool> {
+        let Some(&(parent_page, child_index)) = path.last() else {
+            return Ok(false);
+        };
+        if self.tree_type() != TreeType::Table
+            || index as usize != leaf.cells.len()
+            || parent_page == 1
+        {
+            return Ok(false);
+        }
+
+        let parent_cells =
+            self.pager()
+                .get_page_callback(parent_page, None, |page| match page {
+                    Page::BTree(btree_page) => Ok(btree_page.cells.len()),
+                    _ => Err(not_a_btree_page(parent_page)),
+                })??;
+        Ok(child_index as usize == parent_cells)
+    }
+
+    /// Starts a new rightmost leaf with the cell, instead of spreading the full one with its siblings
+    /// (`balance_quick` in SQLite). The full leaf stays full, which is what rows inserted in rowid order want:
+    /// balancing would leave every leaf a third empty, and the rows never come back to fill them.
+    ///
+    /// # Parameters
+    /// * `leaf_page` - The rightmost leaf, which the cell does not fit in.
+    /// * `path` - The interior pages from the root down to the leaf, each with the index of the child taken.
+    /// * `leaf` - Content of the leaf, without the new cell.
+    /// * `cell` - The cell to insert.
+    ///
+    /// # Errors
+    /// Returns an error if there are I/O issues.
+    fn balance_quick(
+        &mut self,
+        leaf_page: u32,
+        mut path: Vec<(u32, u16)>,
+        leaf: Node,
+        cell: BTreeCell,
+    ) -> io::Result<()> {
+        let (parent_page, _) = path.pop().expect("The leaf has a parent");
+
+        let new_leaf = self.pager().create_btree_page(leaf.page_type, None)?;
+        let node = Node {
+            page_type: leaf.page_type,
+            cells: vec![cell],
+            right_most: None,
+        };
+        self.write_node(new_leaf, node)?;
+
+        // The full leaf gets a divider, and the new one is the rightmost child now
+        let last_cell = leaf.cells.last().expect("A full leaf has cells");
+        let divider = with_left_child(self.leaf_divider(last_cell)?, leaf_page);
+        let mut parent = self.read_node(parent_page)?;
+        parent.cells.push(divider);
+        parent.right_most = Some(new_leaf);
+        self.balance(parent_page, path, parent)
+    }
+
+    /// Removes a cell from a leaf, balancing the tree if the leaf gets underfull.
+    ///
+    /// # Parameters
+    /// * `leaf_page` - Page number of the leaf.
+    /// * `path` - The interior pages from the root down to the leaf, each with the index of the child taken.
+    /// * `index` - Position of the cell in the leaf.
+    ///
+    /// # Errors
+    /// Returns an error if there are I/O issues.
+    pub(crate) fn remove_from_leaf(
+        &mut self,
+        leaf_page: u32,
+        path: Vec<(u32, u16)>,
+        index: u16,
+    ) -> io::Result<()> {
+        let underfull = self
+            .pager()
+            .get_page_mut_callback(leaf_page, None, |page| match page {
+                Page::BTree(btree_page) => {
+                    btree_page.remove_cell(index)?;
+                    let capacity = btree_page.free_space() + content_size(&btree_page.cells);
+                    Ok(is_underfull(&btree_page.cells, capacity))
+                }
+                _ => Err(not_a_btree_page(leaf_page)),
+            })?;
+
+        // The root can be as empty as it likes
+        if underfull && !path.is_empty() {
+            let node = self.read_node(leaf_page)?;
+            self.balance(leaf_page, path, node)?;
+        }
+        Ok(())
+    }
+
+    /// Marks a page as free. The page is not added to any list (see the module docs).
+    ///
+    /// # Errors
+    /// Returns an error if the page cannot be read.
+    pub(crate) fn free_page(&self, page_number: u32) -> io::Result<()> {
+        let page_size = self.page_size();
+        self.pager()
+            .get_page_mut_callback(page_number, None, |page| {
+                *page = Page::Free(FreePage::new(0, page_size, page_number));
+                Ok(())
+            })
+    }
+
+    /// Frees every page of the tree, overflow pages included, when the tree is dropped.
+    /// The tree must not be used after that.
+    ///
+    /// # Errors
+    /// Returns an error if a page cannot be read or written.
+    pub(crate) fn free_tree(&self) -> io::Result<()> {
+        let mut pages = vec![self.root_page()];
+        while let Some(page_number) = pages.pop() {
+            let (cells, right_most) =
+                self.pager()
+                    .get_page_callback(page_number, None, |page| match page {
+                        Page::BTree(btree_page) => {
+                            Ok((btree_page.cells.clone(), btree_page.header.right_most_page))
+                        }
+                        _ => Err(io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            format!("Page {} is not a B-Tree page", page_number),
+                        )),
+                    })??;
+
+            for cell in cells {
+                let overflow_page = match cell {
+                    BTreeCell::TableLeaf(cell) => cell.overflow_page,
+                    BTreeCell::IndexLeaf(cell) => cell.overflow_page,
+                    BTreeCell::IndexInterior(cell) => {
+                        pages.push(cell.left_child_page);
+                        cell.overflow_page
+                    }
+                    BTreeCell::TableInterior(cell) => {
+                        pages.push(cell.left_child_page);
+                        None
+                    }
+                };
+                if let Some(overflow_page) = overflow_page {
+                    self.free_overflow_chain(overflow_page)?;
+                }
+            }
+            pages.extend(right_most);
+            self.free_page(page_number)?;
+        }
+        Ok(())
+    }
+
+    /// Writes the new content of a page, balancing it with its siblings, and then the parents, as long as needed.
+    ///
+    /// # Parameters
+    /// * `page_number` - The page that may need balancing.
+    /// * `path` - The interior pages from the root down to it, each with the index of the child taken.
+    /// * `node` - Content of the page, which may not fit in it.
+    ///
+    /// # Errors
+    /// Returns an error if there are I/O issues.
+    fn balance(
+        &mut self,
+        mut page_number: u32,
+        mut path: Vec<(u32, u16)>,
+        mut node: Node,
+    ) -> io::Result<()> {
+        while let Some((parent_page, child_index)) = path.pop() {
+            let capacity = self.capacity(page_number, node.page_type)?;
+            if content_size(&node.cells) <= capacity && !is_underfull(&node.cells, capacity) {
+                return self.write_node(page_number, node);
+            }
+
+            node = self.balance_nonroot(parent_page, child_index, page_number, node)?;
+            page_number = parent_page;
+        }
+
+        self.balance_root(page_number, node)
+    }
+
+    /// Writes the new content of the root, adding a level on top of it if it does not fit,
+    /// or removing it if it is left with a single child.
+    ///
+    /// # Errors
+    /// Returns an error if there are I/O issues.
+    fn balance_root(&mut self, root_page: u32, node: Node) -> io::Result<()> {
+        if content_size(&node.cells) > self.capacity(root_page, node.page_type)? {
+            // Deeper: a new root on top, with the old one as its only child, which gets balanced as usual
+            let new_root = self
+                .pager()
+                .create_btree_page(self.interior_type(), Some(root_page))?;
+            self.set_root_page(new_root);
+            return self.balance(root_page, vec![(new_root, 0)], node);
+        }
+
+        if let (true, Some(child_page)) = (node.cells.is_empty(), node.right_most) {
+            // Shallower: the only child becomes the root
+            self.set_root_page(child_page);
+            return self.free_page(root_page);
+        }
+
+        self.write_node(root_page, node)
+    }
+
+    /// Spreads the cells of a page and of up to two of its siblings over as few pages as they fit in (see the module docs).
+    ///
+    /// # Parameters
+    /// * `parent_page` - Page number of the parent.
+    /// * `child_index` - Index of the page among the children of the parent.
+    /// * `page_number` - The page that needs balancing.
+    /// * `node` - Content of the page, which may not fit in it.
+    ///
+    /// # Errors
+    /// Returns an error if there are I/O issues, or if the page is not where the parent says.
+    ///
+    /// # Returns
+    /// The new content of the parent, which is not written yet: it may need balancing too.
+    fn balance_nonroot(
+        &mut self,
+        parent_page: u32,
+        child_index: u16,
+        page_number: u32,
+        node: Node,
+    ) -> io::Result<Node> {
+        let mut parent = self.read_node(parent_page)?;
+        let child_count = parent.cells.len() + 1;
+        let child_index = child_index as usize;
+        if child_index >= child_count || child_at(&parent, child_index) != page_number {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "Page {} not found in its parent {}",
+                    page_number, parent_page
+                ),
+            ));
+        }
+
+        // The page with a neighbour at each side, or the first or last three children
+        let first = child_index
+            .saturating_sub(1)
+            .min(child_count.saturating_sub(BALANCE_SIBLINGS));
+        let last = (first + BALANCE_SIBLINGS).min(child_count);
+        let old_pages: Vec<u32> = (first..last).map(|i| child_at(&parent, i)).collect();
+
+        // Gather all the cells, in order. The dividers between interior pages come down with them.
+        let page_type = node.page_type;
+        let leaf = page_type.is_leaf();
+        let mut node = Some(node);
+        let mut cells = Vec::new();
+        let mut right_most = None;
+        for (i, &sibling_page) in old_pages.iter().enumerate() {
+            let sibling = match node.take() {
+                Some(node) if sibling_page == page_number => node,
+                other => {
+                    node = other;
+                    self.read_node(sibling_page)?
+                }
+            };
+            cells.extend(sibling.cells);
+            right_most = sibling.right_most;
+
+            if !leaf && i + 1 < old_pages.len() {
+                let divider = parent.cells[first + i].clone();
+                cells.push(with_left_child(divider, right_most.unwrap_or(0)));
+            }
+        }
+
+        // Spread them and write the pages
+        let capacity = self.capacity(page_number, page_type)?;
+        let ends = distribute(&cells, capacity, leaf);
+
+        let mut new_pages = Vec::with_capacity(ends.len());
+        for p in 0..ends.len() {
+            match old_pages.get(p) {
+                Some(&old_page) => new_pages.push(old_page),
+                None => new_pages.push(self.pager().create_btree_page(page_type, right_most)?),
+            }
+        }
+
+        let mut dividers = Vec::with_capacity(ends.len() - 1);
+        let mut cells = cells.into_iter();
+        let mut start = 0;
+        for (p, &end) in ends.iter().enumerate() {
+            let page_cells: Vec<BTreeCell> = cells.by_ref().take(end - start).collect();
+            start = end;
+
+            let mut page_right_most = right_most;
+            if p + 1 < ends.len() {
+                let divider = if leaf {
+                    self.leaf_divider(page_cells.last().expect("Pages are never empty"))?
+                } else {
+                    start += 1;
+                    let divider = cells.next().expect("Interior pages end with a divider");
+                    page_right_most = Some(left_child(&divider));
+                    divider
+                };
+                dividers.push(with_left_child(divider, new_pages[p]));
+            }
+
+            let page = Node {
+                page_type,
+                cells: page_cells,
+                right_most: page_right_most,
+            };
+            self.write_node(new_pages[p], page)?;
+        }
+
+        for &old_page in old_pages.iter().skip(new_pages.len()) {
+            self.free_page(old_page)?;
+        }
+
+        // Swap the old dividers for the new ones, and point the parent to the last page
+        let divider_count = dividers.len();
+        parent.cells.splice(first..last - 1, dividers);
+        let last_page = *new_pages.last().expect("There is always a page");
+        if last == child_count {
+            parent.right_most = Some(last_page);
+        } else {
+            let cell = parent.cells.remove(first + divider_count);
+            parent
+                .cells
+                .insert(first + divider_count, with_left_child(cell, last_page));
+        }
+
+        Ok(parent)
+    }
+
+    /// Builds the divider that goes to the parent for a leaf, from the last cell of the leaf.
+    ///
+    /// # Errors
+    /// Returns an error if the key of an index cell cannot be read, or its overflow pages created.
+    fn leaf_divider(&self, last_cell: &BTreeCell) -> io::Result<BTreeCell> {
+        match last_cell {
+            BTreeCell::TableLeaf(cell) => Ok(BTreeCell::TableInterior(TableInteriorCell {
+                left_child_page: 0,
+                key: cell.row_id,
+            })),
+            BTreeCell::IndexLeaf(cell) => {
+                let key = extract_key_from_payload(&cell.payload)?;
+                let payload = self.create_payload_from_key_value(&key)?;
+                self.index_interior_cell(0, payload)
+            }
+            _ => unreachable!("Leaves only have leaf cells"),
+        }
+    }
+
+    /// Reads the content of a page.
+    ///
+    /// # Errors
+    /// Returns an error if the page cannot be read or is not a B-Tree page.
+    fn read_node(&self, page_number: u32) -> io::Result<Node> {
+        self.pager()
+            .get_page_callback(page_number, None, |page| match page {
+                Page::BTree(btree_page) => Ok(Node {
+                    page_type: btree_page.header.page_type,
+                    cells: btree_page.cells.clone(),
+                    right_most: btree_page.header.right_most_page,
+                }),
+                _ => Err(not_a_btree_page(page_number)),
+            })?
+    }
+
+    /// Replaces the content of a page.
+    ///
+    /// # Errors
+    /// Returns an error if the content does not fit in the page or the page cannot be read.
+    fn write_node(&self, page_number: u32, node: Node) -> io::Result<()> {
+        let mut btree_page = BTreePage::new(
+            node.page_type,
+            self.page_size(),
+            page_number,
+            self.reserved_space(),
+            node.right_most,
+        )?;
+        for cell in node.cells {
+            btree_page.add_cell(cell)?;
+        }
+
+        self.pager()
+            .get_page_mut_callback(page_number, None, |page| {
+                *page = Page::BTree(btree_page);
+                Ok(())
+            })
+    }
+
+    /// Gets how many bytes of cells and slots fit in an empty page.
+    ///
+    /// # Errors
+    /// Returns an error if the page type is not a B-Tree page type.
+    fn capacity(&self, page_number: u32, page_type: PageType) -> io::Result<usize> {
+        let right_most = page_type.is_interior().then_some(0);
+        let empty = BTreePage::new(
+            page_type,
+            self.page_size(),
+            page_number,
+            self.reserved_space(),
+            right_most,
+        )?;
+        Ok(empty.free_space())
+    }
+
+    /// Gets the type of the interior pages of the tree.
+    fn interior_type(&self) -> PageType {
+        match self.tree_type() {
+            TreeType::Table => PageType::TableInterior,
+            TreeType::Index => PageType::IndexInterior,
+        }
+    }
+}
+
+/// Splits the cells into pages, returning where each page ends (exclusive).
+/// On interior levels the cell right after a page is the divider, so the next page starts one cell later.
+fn distribute(cells: &[BTreeCell], capacity: usize, leaf: bool) -> Vec<usize> {
+    let sizes: Vec<usize> = cells.iter().map(|cell| cell.size() + 2).collect();
+    let skip = if leaf { 0 } else { 1 };
+
+    // Fill from the left. A page takes at least one cell, whatever its size.
+    let mut ends = Vec::new();
+    let mut i = 0;
+    loop {
+        let mut size = 0;
+        let start = i;
+        while i < sizes.len() && (i == start || size + sizes[i] <= capacity) {
+            size += sizes[i];
+            i += 1;
+        }
+        ends.push(i);
+        if i + skip >= sizes.len() {
+            break;
+        }
+        i += skip;
+    }
+
+    // An interior level cannot end with a divider: the last page takes cells from the one before
+    if *ends.last().unwrap() < sizes.len() {
+        ends.push(sizes.len());
+    }
+
+    // Even out from the right: move cells to the right page while it stays smaller than the left one.
+    // A page that takes cells can let its right neighbour take some more, so it goes on until nothing moves.
+    let mut moved = true;
+    while moved {
+        moved = false;
+        for p in (1..ends.len()).rev() {
+            loop {
+                let left_start = if p == 1 { 0 } else { ends[p - 2] + skip };
+                let left_end = ends[p - 1];
+                let right_start = left_end + skip;
+                if left_end - left_start <= 1 {
+                    break;
+                }
+
+                let left_size: usize = sizes[left_start..left_end].iter().sum();
+                let right_size: usize = sizes[right_start..ends[p]].iter().sum();
+                let new_left = left_size - sizes[left_end - 1];
+                let new_right = right_size + sizes[left_end - 1 + skip];
+                let right_empty = right_start == ends[p];
+                if new_right > capacity || (!right_empty && new_right > new_left) {
+                    break;
+                }
+                ends[p - 1] -= 1;
+                moved = true;
+            }
+        }
+    }
+
+    ends
+}
+
+/// Gets the bytes that a list of cells takes in a page, counting their slots.
+fn content_size(cells: &[BTreeCell]) -> usize {
+    cells.iter().map(|cell| cell.size() + 2).sum()
+}
+
+/// Checks if a page is under a third full, which is when SQLite balances it too.
+fn is_underfull(cells: &[BTreeCell], capacity: usize) -> bool {
+    cells.is_empty() || content_size(cells) * 3 < capacity
+}
+
+/// Gets a child of an interior page, by index. The child after the last cell is the rightmost one.
+fn child_at(node: &Node, index: usize) -> u32 {
+    match node.cells.get(index) {
+        Some(cell) => left_child(cell),
+        None => node.right_most.unwrap_or(0),
+    }
+}
+
+/// Gets the left child of an interior cell.
+pub(crate) fn left_child(cell: &BTreeCell) -> u32 {
+    match cell {
+        BTreeCell::TableInterior(cell) => cell.left_child_page,
+        BTreeCell::IndexInterior(cell) => cell.left_child_page,
+        _ => unreachable!("Interior pages only have interior cells"),
+    }
+}
+
+/// Points an interior cell to another left child.
+pub(crate) fn with_left_child(cell: BTreeCell, page_number: u32) -> BTreeCell {
+    match cell {
+        BTreeCell::TableInterior(mut cell) => {
+            cell.left_child_page = page_number;
+            BTreeCell::TableInterior(cell)
+        }
+        BTreeCell::IndexInterior(mut cell) => {
+            cell.left_child_page = page_number;
+            BTreeCell::IndexInterior(cell)
+        }
+        _ => unreachable!("Dividers are interior cells"),
+    }
+}
+
+fn not_a_btree_page(page_number: u32) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("Expected a B-Tree page: {}", page_number),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::pager::Pager;
+    use crate::tree::record::Record;
+    use crate::utils::cmp::KeyValue;
+    use crate::utils::serialization::{serialize_values, SqliteValue};
+    use std::sync::Arc;
+    use tempfile::{tempdir, TempDir};
+
+    fn create_tree(tree_type: TreeType) -> (TempDir, BTree) {
+        let dir = tempdir().unwrap();
+        let pager = Pager::create(dir.path().join("test.db"), 4096, None, 0).unwrap();
+        let btree = BTree::create(tree_type, Arc::new(pager), 4096, 0, 255, 32).unwrap();
+        (dir, btree)
+    }
+
+    fn record(rowid: i64) -> Record {
+        let text = format!("row {}", rowid).repeat(1 + (rowid % 7) as usize);
+        Record::with_values(vec![SqliteValue::Integer(rowid), SqliteValue::String(text)])
+    }
+
+    fn index_key(value: i64) -> Vec<u8> {
+        let mut key = Vec::new();
+        serialize_values(
+            &[SqliteValue::String(format!("key {:08}", value))],
+            &mut key,
+        )
+        .unwrap();
+        key
+    }
+
+    /// The numbers 1 to n in a fixed, scrambled order.
+    fn shuffled(n: i64) -> Vec<i64> {
+        let mut values = (1..=n).collect::<Vec<_>>();
+        let mut seed = 7u64;
+        for i in (1..values.len()).rev() {
+            seed = seed
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            values.swap(i, (seed >> 33) as usize % (i + 1));
+        }
+        values
+    }
+
+    /// What `check_tree` found in the leaves.
+    #[derive(Default)]
+    struct Leaves {
+        count: usize,
+        content: usize,
+        capacity: usize,
+        depth: Option<usize>,
+    }
+
+    fn cell_key(cell: &BTreeCell) -> KeyValue {
+        match cell {
+            BTreeCell::TableLeaf(cell) => KeyValue::Integer(cell.row_id),
+            BTreeCell::TableInterior(cell) => KeyValue::Integer(cell.key),
+            BTreeCell::IndexLeaf(cell) => extract_key_from_payload(&cell.payload).unwrap(),
+            BTreeCell::IndexInterior(cell) => extract_key_from_payload(&cell.payload).unwrap(),
+        }
+    }
+
+    /// Walks the whole tree checking that the keys are sorted, inside the range given by the dividers
+    /// (larger than the one on the left, up to the one on the right), and that all the leaves are at the same depth.
+    fn check_tree(btree: &BTree) -> Leaves {
+        let mut leaves = Leaves::default();
+        check_page(btree, btree.root_page(), None, None, 0, &mut leaves);
+        leaves
+    }
+
+    fn check_page(
+        btree: &BTree,
+        page_number: u32,
+        lower: Option<&KeyValue>,
+        upper: Option<&KeyValue>,
+        depth: usize,
+        leaves: &mut Leaves,
+    ) {
+        let node = btree.read_node(page_number).unwrap();
+        let keys: Vec<KeyValue> = node.cells.iter().map(cell_key).collect();
+        for pair in keys.windows(2) {
+            assert!(
+                pair[0] < pair[1],
+                "Keys out of order in page {}",
+                page_number
+            );
+        }
+        for key in &keys {
+            assert!(lower.is_none_or(|lower| key > lower));
+            assert!(upper.is_none_or(|upper| key <= upper));
+        }
+
+        if node.page_type.is_leaf() {
+            assert_eq!(*leaves.depth.get_or_insert(depth), depth, "Unbalanced tree");
+            leaves.count += 1;
+            leaves.content += content_size(&node.cells);
+            leaves.capacity += btree.capacity(page_number, node.page_type).unwrap();
+            return;
+        }
+
+        let mut child_lower = lower;
+        for (i, key) in keys.iter().enumerate() {
+            let child = child_at(&node, i);
+            check_page(btree, child, child_lower, Some(key), depth + 1, leaves);
+            child_lower = Some(key);
+        }
+        let child = child_at(&node, keys.len());
+        check_page(btree, child, child_lower, upper, depth + 1, leaves);
+    }
+
+    #[test]
+    fn test_distribute() {
+        let (_dir, btree) = create_tree(TreeType::Table);
+        let cells: Vec<BTreeCell> = (1..=10)
+            .map(|rowid| btree.table_leaf_cell(rowid, vec![0; 98]).unwrap())
+            .collect();
+        let size = cells[0].size() + 2;
+
+        // Ten cells for pages of four: the last page gets as much as the one before
+        assert_eq!(distribute(&cells, 4 * size, true), vec![4, 7, 10]);
+        // Everything fits in one page
+        assert_eq!(distribute(&cells, 10 * size, true), vec![10]);
+        // A cell that does not fit anywhere still gets a page
+        assert_eq!(distribute(&cells[..2], size / 2, true), vec![1, 2]);
+        // Nothing to spread
+        assert_eq!(distribute(&[], size, true), vec![0]);
+
+        // On interior levels the cell between two pages goes up as the divider
+        let cells: Vec<BTreeCell> = (1..=10)
+            .map(|key| {
+                BTreeCell::TableInterior(TableInteriorCell {
+                    left_child_page: 2,
+                    key,
+                })
+            })
+            .collect();
+        let size = cells[0].size() + 2;
+        assert_eq!(distribute(&cells, 4 * size, false), vec![3, 7, 10]);
+        // A divider cannot be the last cell
+        assert_eq!(distribute(&cells[..5], 4 * size, false), vec![2, 5]);
+    }
+
+    #[test]
+    fn test_descending_inserts_fill_pages() {
+        // Backwards, every insert goes to the first leaf, which gets balanced with its siblings.
+        // Splitting in halves leaves the pages half full, three siblings together get them over two thirds full.
+        let (_dir, mut btree) = create_tree(TreeType::Table);
+        for rowid in (1..=3000).rev() {
+            btree.insert(rowid, &record(rowid)).unwrap();
+        }
+        for rowid in 1..=3000 {
+            assert!(btree.find(rowid).unwrap().is_some());
+        }
+        let leaves = check_tree(&btree);
+        assert!(leaves.count > 10);
+        assert!(leaves.content * 3 > leaves.capacity * 2);
+    }
+
+    #[test]
+    fn test_append_fills_pages() {
+        let (_dir, mut btree) = create_tree(TreeType::Table);
+        for rowid in 1..=3000 {
+            btree.insert(rowid, &record(rowid)).unwrap();
+        }
+        for rowid in 1..=3000 {
+            assert!(btree.find(rowid).unwrap().is_some());
+        }
+
+        // Every leaf but the last one is as full as it got
+        let leaves = check_tree(&btree);
+        assert!(leaves.count > 10);
+        assert!(leaves.content * 10 > leaves.capacity * 9);
+
+        // Rows in the middle still balance as usual
+        for rowid in (1..=3000).step_by(2) {
+            assert!(btree.delete(rowid).unwrap());
+        }
+        for rowid in (1..=3000).step_by(2) {
+            btree.insert(rowid, &record(rowid)).unwrap();
+        }
+        check_tree(&btree);
+        for rowid in 1..=3000 {
+            assert!(btree.find(rowid).unwrap().is_some());
+        }
+    }
+
+    #[test]
+    fn test_random_deletes_shrink_tree() {
+        let (_dir, mut btree) = create_tree(TreeType::Table);
+        for rowid in shuffled(3000) {
+            btree.insert(rowid, &record(rowid)).unwrap();
+        }
+        let before = check_tree(&btree);
+
+        // Four out of five rows go away
+        let deleted = shuffled(3000);
+        for &rowid in &deleted[..2400] {
+            assert!(btree.delete(rowid).unwrap());
+        }
+        for &rowid in &deleted[..2400] {
+            assert!(btree.find(rowid).unwrap().is_none());
+        }
+        for &rowid in &deleted[2400..] {
+            assert!(btree.find(rowid).unwrap().is_some());
+        }
+
+        // Only merging empty leaves would keep almost all of them
+        let after = check_tree(&btree);
+        assert!(after.count * 2 < before.count);
+
+        // And everything at all
+        for &rowid in &deleted[2400..] {
+            assert!(btree.delete(rowid).unwrap());
+        }
+        let leaves = check_tree(&btree);
+        assert_eq!((leaves.count, leaves.content), (1, 0));
+        assert_eq!(
+            btree.read_node(btree.root_page()).unwrap().page_type,
+            PageType::TableLeaf
+        );
+    }
+
+    #[test]
+    fn test_index_inserts_and_deletes() {
+        let (_dir, mut btree) = create_tree(TreeType::Index);
+        for value in shuffled(2000) {
+            btree.insert_index(&index_key(value), value).unwrap();
+        }
+        let before = check_tree(&btree);
+        assert!(before.depth.unwrap() > 0);
+
+        for value in (1..=2000).filter(|value| value % 4 != 0) {
+            let key = KeyValue::String(format!("key {:08}", value));
+            assert!(btree.delete_index(&key).unwrap());
+        }
+        for value in 1..=2000 {
+            let key = KeyValue::String(format!("key {:08}", value));
+            let (found, _, _) = btree.find_index_key(&key).unwrap();
+            assert_eq!(found, value % 4 == 0, "Key {}", value);
+        }
+
+        let after = check_tree(&btree);
+        assert!(after.count * 2 < before.count);
+    }
+
+    #[test]
+    fn test_large_cells() {
+        // Rows of about a third of a page, with some that spill to overflow pages
+        let (_dir, mut btree) = create_tree(TreeType::Table);
+        let large = |rowid: i64| {
+            let size = if rowid % 10 == 0 { 6000 } else { 1200 };
+            Record::with_values(vec![SqliteValue::Blob(vec![rowid as u8; size])])
+        };
+        for rowid in shuffled(300) {
+            btree.insert(rowid, &large(rowid)).unwrap();
+        }
+        for rowid in (1..=300).filter(|rowid| rowid % 3 != 0) {
+            assert!(btree.delete(rowid).unwrap());
+        }
+
+        check_tree(&btree);
+        for rowid in 1..=300 {
+            let found = btree.find(rowid).unwrap();
+            if rowid % 3 == 0 {
+                let found = found.unwrap();
+                assert_eq!(found.to_bytes().unwrap(), large(rowid).to_bytes().unwrap());
+            } else {
+                assert!(found.is_none());
+            }
+        }
+    }
+}
@@ -9,17 +9,20 @@
 //! This is quite inefficient, but for now I do not want to refactor the whole thing.
 //!
 //! Apart from that I am quite happy with the current solution.
+//!
+//! Keeping the pages full enough after inserts and deletes is the job of the balance module, which adds
+//! its own `impl BTree` block (`insert_into_leaf` and `remove_from_leaf` are the entry points).
 
 use std::io;
 use std::sync::Arc;
 
-use crate::page::{BTreeCell, PageType};
+use crate::page::{BTreeCell, Page, PageType, TableLeafCell};
 use crate::storage::pager::Pager;
 use crate::tree::cell::BTreeCellFactory;
 use crate::tree::node::{extract_key_from_payload, BTreeNode};
-use crate::tree::record::Record;
+use crate::tree::record::{Record, RecordRef};
 use crate::utils::cmp::KeyValue;
-use std::hash::Hasher;
+use crate::utils::serialization::SqliteValue;
 
 /// Represents a B-Tree in SQLite.
 ///
@@ -29,6 +32,7 @@ use std::hash::Hasher;
 ///
 /// This implementation uses a callback-based approach for node access
 /// to ensure safe memory management and proper cleanup.
+#[derive(Clone)]
 pub struct BTree {
     /// Number of the root page of the tree
     root_page: u32,
@@ -89,6 +93,20 @@ impl BTree {
         }
     }
 
+    /// Gets a handle to the same tree that goes through another pager, typically a snapshot.
+    ///
+    /// # Parameters
+    /// * `pager` - Pager to read and write the pages of the tree with.
+    ///
+    /// # Returns
+    /// A new B-Tree instance with the same root and settings.
+    pub fn with_pager(&self, pager: Arc<Pager>) -> Self {
+        BTree {
+            pager,
+            ..self.clone()
+        }
+    }
+
     /// Creates a new empty B-Tree.
     ///
     /// # Parameters
@@ -158,24 +176,19 @@ impl BTree {
         max_payload_fraction: u8,
         min_payload_fraction: u8,
     ) -> io::Result<Self> {
-        // Verify that the root page exists and is valid
-        let expected_page_type = match tree_type {
-            TreeType::Table => PageType::TableLeaf, // Could be TableInterior if tree has grown
-            TreeType::Index => PageType::IndexLeaf, // Could be IndexInterior if tree has grown
-        };
-
-        // Try to open the root page - this will validate that it exists and is the right type
-        let _root_node = pager.get_page_callback(root_page, Some(expected_page_type), |page| {
+        // Try to open the root page - this will validate that it exists and is the right type.
+        // The root is an interior page once the tree has grown, so the pager cannot check the type for us.
+        pager.get_page_callback(root_page, None, |page| {
             let actual_type = page.page_type();
 
             // For table trees, accept both leaf and interior as valid root types
-            let is_valid = match (tree_type, actual_type) {
+            let is_valid = matches!(
+                (tree_type, actual_type),
                 (TreeType::Table, PageType::TableLeaf)
-                | (TreeType::Table, PageType::TableInterior)
-                | (TreeType::Index, PageType::IndexLeaf)
-                | (TreeType::Index, PageType::IndexInterior) => true,
-                _ => false,
-            };
+                    | (TreeType::Table, PageType::TableInterior)
+                    | (TreeType::Index, PageType::IndexLeaf)
+                    | (TreeType::Index, PageType::IndexInterior)
+            );
 
             if !is_valid {
                 return Err(io::Error::new(
@@ -188,7 +201,7 @@ impl BTree {
             }
 
             Ok(())
-        })?;
+        })??;
 
         Ok(BTree {
             root_page,
@@ -223,10 +236,132 @@ impl BTree {
     ///
     /// # Returns
     /// Usable size in bytes.
-    fn usable_page_size(&self) -> usize {
+    pub(crate) fn usable_page_size(&self) -> usize {
         self.page_size as usize - self.reserved_space as usize
     }
 
+    /// Gets the size of the pages of the tree.
+    pub(crate) fn page_size(&self) -> u32 {
+        self.page_size
+    }
+
+    /// Gets the reserved space at the end of the pages of the tree.
+    pub(crate) fn reserved_space(&self) -> u8 {
+        self.reserved_space
+    }
+
+    /// Gets the pager of the tree.
+    pub(crate) fn pager(&self) -> &Arc<Pager> {
+        &self.pager
+    }
+
+    /// Moves the root of the tree to another page, for code that builds the tree without going through `insert`.
+    pub(crate) fn set_root_page(&mut self, root_page: u32) {
+        self.root_page = root_page;
+    }
+
+    /// Checks if the tree has no rows: its root is a leaf without cells.
+    ///
+    /// # Errors
+    /// Returns an error if the root page cannot be read.
+    pub(crate) fn is_empty(&self) -> io::Result<bool> {
+        let root_type = self.get_page_type(self.root_page)?;
+        if !root_type.is_leaf() {
+            return Ok(false);
+        }
+        Ok(BTreeNode::new(self.root_page, root_type).cell_count(&self.pager)? == 0)
+    }
+
+    /// Builds the leaf cell of a table row, moving the end of a large payload to an overflow chain.
+    ///
+    /// # Parameters
+    /// * `rowid` - Row ID of the row.
+    /// * `payload` - The serialized record.
+    ///
+    /// # Errors
+    /// Returns an error if the overflow pages cannot be created.
+    pub(crate) fn table_leaf_cell(&self, rowid: i64, payload: Vec<u8>) -> io::Result<BTreeCell> {
+        let (cell, overflow_data) = BTreeCellFactory::create_table_leaf_cell(
+            rowid,
+            payload,
+            self.max_local_payload(),
+            self.min_local_payload(),
+            self.usable_page_size(),
+        )?;
+
+        // Handle overflow if needed
+        match (cell, overflow_data) {
+            (BTreeCell::TableLeaf(mut leaf_cell), Some(overflow_data)) => {
+                leaf_cell.overflow_page = Some(self.create_overflow_chain(overflow_data)?);
+                Ok(BTreeCell::TableLeaf(leaf_cell))
+            }
+            (cell, _) => Ok(cell),
+        }
+    }
+
+    /// Builds the leaf cell of an index entry: the key followed by the rowid, with an overflow chain if it is too large.
+    ///
+    /// # Parameters
+    /// * `key` - The serialized key.
+    /// * `rowid` - Row ID the entry points to.
+    ///
+    /// # Errors
+    /// Returns an error if the rowid cannot be serialized or the overflow pages cannot be created.
+    pub(crate) fn index_leaf_cell(&self, key: &[u8], rowid: i64) -> io::Result<BTreeCell> {
+        // Add the rowid at the end of the payload
+        let mut payload = key.to_vec();
+        let rowid_record =
+            Record::with_values(vec![crate::utils::serialization::SqliteValue::Integer(
+                rowid,
+            )]);
+        payload.extend_from_slice(&rowid_record.to_bytes()?);
+
+        let (cell, overflow_data) = BTreeCellFactory::create_index_leaf_cell(
+            payload,
+            self.max_local_payload(),
+            self.min_local_payload(),
+            self.usable_page_size(),
+        )?;
+
+        match (cell, overflow_data) {
+            (BTreeCell::IndexLeaf(mut leaf_cell), Some(overflow_data)) => {
+                leaf_cell.overflow_page = Some(self.create_overflow_chain(overflow_data)?);
+                Ok(BTreeCell::IndexLeaf(leaf_cell))
+            }
+            (cell, _) => Ok(cell),
+        }
+    }
+
+    /// Builds an interior cell of an index tree, with an overflow chain if the key is too large.
+    ///
+    /// # Parameters
+    /// * `left_child` - Page of the subtree with the keys up to this one.
+    /// * `payload` - The serialized key.
+    ///
+    /// # Errors
+    /// Returns an error if the overflow pages cannot be created.
+    pub(crate) fn index_interior_cell(
+        &self,
+        left_child: u32,
+        payload: Vec<u8>,
+    ) -> io::Result<BTreeCell> {
+        let (cell, overflow_data) = BTreeCellFactory::create_index_interior_cell(
+            left_child,
+            payload,
+            self.max_local_payload(),
+            self.min_local_payload(),
+            self.usable_page_size(),
+        )?;
+
+        match (cell, overflow_data) {
+            (BTreeCell::IndexInterior(mut interior_cell), Some(overflow_data)) => {
+                interior_cell.overflow_page = Some(self.create_overflow_chain(overflow_data)?);
+                Ok(BTreeCell::IndexInterior(interior_cell))
+            }
+            (cell, _) => Ok(cell),
+        }
+    }
+
     /// Finds a record in a table B-Tree by its rowid.
     ///
     /// # Parameters
@@ -238,6 +373,121 @@ impl BTree {
     /// # Returns
     /// The record found, or `None` if no record exists with the given rowid.
     pub fn find(&self, rowid: i64) -> io::Result<Option<Record>> {
+        let Some(leaf_cell) = self.find_leaf_cell(rowid)? else {
+            return Ok(None);
+        };
+
+        // Get payload from the cell
+        let mut payload = leaf_cell.payload;
+
+        // Handle overflow chain if present
+        if let Some(overflow_page) = leaf_cell.overflow_page {
+            payload.extend_from_slice(&self.read_overflow_chain(overflow_page)?);
+        }
+
+        // Deserialize the record
+        let (record, _) = Record::from_bytes(&payload)?;
+        Ok(Some(record))
+    }
+
+    /// Finds some of the columns of a record in a table B-Tree.
+    ///
+    /// Only the requested values are decoded (see `RecordRef`), and when they are all in the part of the payload
+    /// stored in the leaf, the overflow pages of the record are not read at all.
+    ///
+    /// # Parameters
+    /// * `rowid` - Row ID to search for.
+    /// * `columns` - Indexes of the columns to get.
+    /// * `defaults` - Values of the columns missing from short records, by index (see `RecordRef::project`).
+    ///
+    /// # Errors
+    /// Returns an error if the tree is not a table tree or if there are I/O issues.
+    ///
+    /// # Returns
+    /// A record with the requested columns in the requested order, or `None` if no record exists with the given rowid.
+    pub fn find_columns(
+        &self,
+        rowid: i64,
+        columns: &[usize],
+        defaults: &[SqliteValue],
+    ) -> io::Result<Option<Record>> {
+        let Some(leaf_cell) = self.find_leaf_cell(rowid)? else {
+            return Ok(None);
+        };
+
+        let local = RecordRef::new(&leaf_cell.payload)?;
+        let Some(overflow_page) = leaf_cell.overflow_page else {
+            return local.project(columns, defaults).map(Some);
+        };
+        if columns
+            .iter()
+            .all(|&column| column >= local.len() || local.is_local(column))
+        {
+            return local.project(columns, defaults).map(Some);
+        }
+
+        let mut payload = leaf_cell.payload;
+        payload.extend_from_slice(&self.read_overflow_chain(overflow_page)?);
+        RecordRef::new(&payload)?
+            .project(columns, defaults)
+            .map(Some)
+    }
+
+    /// Gets the largest rowid of a table B-Tree, following the right-most pointers down to the last leaf.
+    /// This is where SQL inserts without a rowid put the new row, like SQLite does.
+    ///
+    /// # Errors
+    /// Returns an error if the tree is not a table tree or if there are I/O issues.
+    ///
+    /// # Returns
+    /// The largest rowid, or `None` if the table is empty.
+    pub fn max_rowid(&self) -> io::Result<Option<i64>> {
+        if self.tree_type != TreeType::Table {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Cannot get the rowids of an index tree",
+            ));
+        }
+
+        let mut current_page = self.root_page;
+        loop {
+            let (last_cell, right_most) =
+                self.pager
+                    .get_page_callback(current_page, None, |page| match page {
+                        Page::BTree(btree_page) => Ok((
+                            btree_page.cells.last().cloned(),
+                            btree_page.header.right_most_page,
+                        )),
+                        _ => Err(io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            format!("Page {} is not a B-Tree page", current_page),
+                        )),
+                    })??;
+            match (right_most, last_cell) {
+                (Some(right_most), _) => current_page = right_most,
+                (None, Some(BTreeCell::TableLeaf(cell))) => return Ok(Some(cell.row_id)),
+                (None, None) => return Ok(None),
+                (None, Some(_)) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "Expected a table leaf cell",
+                    ))
+                }
+            }
+        }
+    }
+
+    /// Finds the leaf cell of a row in a table B-Tree.
+    ///
+    /// # Parameters
+    /// * `rowid` - Row ID to search for.
+    ///
+    /// # Errors
+    /// Returns an error if the tree is not a table tree or if there are I/O issues.
+    ///
+    /// # Returns
+    /// The cell, with only the local part of the payload, or `None` if no record exists with the given rowid.
+    fn find_leaf_cell(&self, rowid: i64) -> io::Result<Option<TableLeafCell>> {
         if self.tree_type != TreeType::Table {
             return Err(io::Error::new(
                 io::ErrorKind::InvalidInput,
@@ -267,19 +517,7 @@ impl BTree {
                 let cell = leaf_node.get_cell_owned(idx, &self.pager)?;
 
                 match cell {
-                    BTreeCell::TableLeaf(leaf_cell) => {
-                        // Get payload from the cell
-                        let mut payload = leaf_cell.payload.clone();
-
-                        // Handle overflow chain if present
-                        if let Some(overflow_page) = leaf_cell.overflow_page {
-                            payload.extend_from_slice(&self.read_overflow_chain(overflow_page)?);
-                        }
-
-                        // Deserialize the record
-                        let (record, _) = Record::from_bytes(&payload)?;
-                        return Ok(Some(record));
-                    }
+                    BTreeCell::TableLeaf(leaf_cell) => return Ok(Some(leaf_cell)),
                     _ => {
                         return Err(io::Error::new(
                             io::ErrorKind::InvalidData,
@@ -356,7 +594,10 @@ impl BTree {
                 } else {
                     let cell_count = interior_node.cell_count(&self.pager)?;
 
-                    if idx == 0 {
+                    if idx >= cell_count {
+                        // The key is larger than all keys in this node (or the node only has its rightmost child)
+                        interior_node.get_right_most_child(&self.pager)?
+                    } else if idx == 0 {
                         // The key is smaller than all keys in this node
                         let cell = interior_node.get_cell_owned(0, &self.pager)?;
                         match cell {
@@ -365,9 +606,6 @@ impl BTree {
                             }
                             _ => unreachable!("Expected an index interior cell"),
                         }
-                    } else if idx >= cell_count {
-                        // The key is larger than all keys in this node
-                        interior_node.get_right_most_child(&self.pager)?
                     } else {
                         // The key falls between two keys
                         let cell = interior_node.get_cell_owned(idx, &self.pager)?;
@@ -402,46 +640,15 @@ impl BTree {
         }
 
         // Serialize the record
-        let payload = record.to_bytes()?;
-
-        // Create a table leaf cell
-        let (cell, overflow_data) = BTreeCellFactory::create_table_leaf_cell(
-            rowid,
-            payload,
-            self.max_local_payload(),
-            self.min_local_payload(),
-            self.usable_page_size(),
-        )?;
-
-        // Handle overflow if needed
-        let cell = if let Some(overflow_data) = overflow_data {
-            match cell {
-                BTreeCell::TableLeaf(mut leaf_cell) => {
-                    // Create overflow pages for the overflow data
-                    let overflow_page = self.create_overflow_chain(overflow_data)?;
-                    leaf_cell.overflow_page = Some(overflow_page);
-                    BTreeCell::TableLeaf(leaf_cell)
-                }
-                _ => unreachable!("Expected a table leaf cell"),
-            }
-        } else {
-            cell
-        };
+        let cell = self.table_leaf_cell(rowid, record.to_bytes()?)?;
 
         // Find the leaf node where the record should be inserted
         let (leaf_page, path) = self.find_leaf_for_insert_table(rowid)?;
         let leaf_node = BTreeNode::new(leaf_page, PageType::TableLeaf);
+        let (_, idx) = leaf_node.find_table_rowid(rowid, &self.pager)?;
 
-        // Try to insert the cell
-        let (split, median_key, new_node) = leaf_node.insert_cell_ordered(cell, &self.pager)?;
-        // println!("Insert cell: split={}, median_key={:?}", split, median_key);
-        
-        if split {
-            // Propagate the split up the tree
-            self.propagate_split_table(leaf_node, new_node.unwrap(), median_key.unwrap(), path)?;
-        }
-
-        Ok(())
+        // Insert the cell, balancing the tree if the leaf overflows
+        self.insert_into_leaf(leaf_page, path, idx, cell)
     }
 
     /// Inserts a key into an index B-Tree.
@@ -462,72 +669,15 @@ impl BTree {
 
         // Extract key value for comparison
         let key_value = extract_key_from_payload(key)?;
+        let cell = self.index_leaf_cell(key, rowid)?;
 
-        // Create a payload containing the key and rowid
-        let mut payload = key.to_vec();
-
-        // Add the rowid at the end of the payload
-        let rowid_record =
-            Record::with_values(vec![crate::utils::serialization::SqliteValue::Integer(
-                rowid,
-            )]);
-        let rowid_bytes = rowid_record.to_bytes()?;
-        payload.extend_from_slice(&rowid_bytes);
-
-        // Create an index leaf cell
-        let (cell, overflow_data) = BTreeCellFactory::create_index_leaf_cell(
-            payload,
-            self.max_local_payload(),
-            self.min_local_payload(),
-            self.usable_page_size(),
-        )?;
-
-        // Handle overflow if needed
-        let cell = if let Some(overflow_data) = overflow_data {
-            match cell {
-                BTreeCell::IndexLeaf(mut leaf_cell) => {
-                    // Create overflow pages for the overflow data
-                    let overflow_page = self.create_overflow_chain(overflow_data)?;
-                    leaf_cell.overflow_page = Some(overflow_page);
-                    BTreeCell::IndexLeaf(leaf_cell)
-                }
-                _ => unreachable!("Expected an index leaf cell"),
-            }
-        } else {
-            cell
-        };
-
-        // Check if the root page is empty
-        let root_type = self.get_page_type(self.root_page)?;
-        match root_type {
-            PageType::TableLeaf | PageType::IndexLeaf => {
-                // If the root is a leaf, we can insert directly
-                let root_node = BTreeNode::new(self.root_page, root_type);
-                let root_cell_count = root_node.cell_count(&self.pager)?;
-                if root_cell_count == 0 {
-                    // If the root is empty, we can directly insert the cell
-                    let _ = root_node.insert_cell(cell, &self.pager)?;
-                    return Ok(());
-                }
-            }
-            _ => {
-                // If the root is not a leaf, we need to handle it differently
-            }
-        }
         // Find the leaf node where the key should be inserted
-        let (_found, leaf_page, _index) = self.find_index_key(&key_value)?;
+        let (leaf_page, path) = self.find_leaf_for_insert_index(&key_value)?;
         let leaf_node = BTreeNode::new(leaf_page, PageType::IndexLeaf);
+        let (_, idx) = leaf_node.find_index_key(&key_value, &self.pager)?;
 
-        // Try to insert the cell
-        let (split, median_key, new_node) = leaf_node.insert_cell_ordered(cell, &self.pager)?;
-
-        if split {
-            // Propagate the split up the tree
-            let path = self.get_path_to_leaf_index(leaf_page, &key_value)?;
-            self.propagate_split_index(leaf_node, new_node.unwrap(), median_key.unwrap(), path)?;
-        }
-
-        Ok(())
+        // Insert the cell, balancing the tree if the leaf overflows
+        self.insert_into_leaf(leaf_page, path, idx, cell)
     }
 
     /// Deletes a record from a table B-Tree.
@@ -569,11 +719,8 @@ impl BTree {
             }
         }
 
-        // Delete the cell from the page
-        leaf_node.remove_cell(idx, &self.pager)?;
-
-        // Check if the node is underfilled and needs rebalancing
-        self.rebalance_after_delete(leaf_page, path)?;
+        // Delete the cell from the page, balancing the tree if the leaf gets underfull
+        self.remove_from_leaf(leaf_page, path, idx)?;
 
         Ok(true)
     }
@@ -597,7 +744,9 @@ impl BTree {
         }
 
         // Find the leaf node containing the key
-        let (found, leaf_page, idx) = self.find_index_key(key)?;
+        let (leaf_page, path) = self.find_leaf_for_insert_index(key)?;
+        let leaf_node = BTreeNode::new(leaf_page, PageType::IndexLeaf);
+        let (found, idx) = leaf_node.find_index_key(key, &self.pager)?;
 
         if !found {
             // Key not found
@@ -605,7 +754,6 @@ impl BTree {
         }
 
         // Get the cell to handle overflow pages
-        let leaf_node = BTreeNode::new(leaf_page, PageType::IndexLeaf);
         let cell = leaf_node.get_cell_owned(idx, &self.pager)?;
 
         if let BTreeCell::IndexLeaf(leaf_cell) = &cell {
@@ -615,14 +763,8 @@ impl BTree {
             }
         }
 
-        // Delete the cell from the page
-        leaf_node.remove_cell(idx, &self.pager)?;
-
-        // Get the path to the leaf for rebalancing
-        let path = self.get_path_to_leaf_index(leaf_page, key)?;
-
-        // Check if the node is underfilled and needs rebalancing
-        self.rebalance_after_delete(leaf_page, path)?;
+        // Delete the cell from the page, balancing the tree if the leaf gets underfull
+        self.remove_from_leaf(leaf_page, path, idx)?;
 
         Ok(true)
     }
@@ -639,7 +781,9 @@ impl BTree {
     /// Page number of the first overflow page.
     fn create_overflow_chain(&self, data: Vec<u8>) -> io::Result<u32> {
         // Calculate how much data can fit in each overflow page
-        let data_per_page = self.page_size as usize - 13; // 4 bytes for next_page pointer, 4 bytes for page number, 4 bytes for reserved space
+        // 1 byte for the page type, 4 bytes for next_page pointer, 4 bytes for page size, 4 bytes for page number.
+        // The reserved space at the end of the page is not ours (it can hold the page checksum).
+        let data_per_page = self.page_size as usize - 13 - self.reserved_space as usize;
 
         // Split the data into chunks
         let chunks: Vec<_> = data.chunks(data_per_page).collect();
@@ -683,7 +827,7 @@ impl BTree {
     ///
     /// # Returns
     /// Data stored in the overflow chain.
-    fn read_overflow_chain(&self, first_page: u32) -> io::Result<Vec<u8>> {
+    pub(crate) fn read_overflow_chain(&self, first_page: u32) -> io::Result<Vec<u8>> {
         let mut result = Vec::new();
         let mut current_page = first_page;
 
@@ -719,7 +863,7 @@ impl BTree {
     ///
     /// # Errors
     /// Returns an error if there are I/O issues.
-    fn free_overflow_chain(&self, first_page: u32) -> io::Result<()> {
+    pub(crate) fn free_overflow_chain(&self, first_page: u32) -> io::Result<()> {
         // Currently, we just mark the pages as free
         // In a complete implementation, you would add them to the freelist
         let mut current_page = first_page;
@@ -744,8 +888,9 @@ impl BTree {
 
             // Mark the page as free (add to freelist)
             // This would be implemented differently in a full SQLite implementation
-            // For now, we just create a free page
-            self.pager.create_free_page(0)?;
+            // For now, the page is only overwritten with a free page
+            drop(guard);
+            self.free_page(current_page)?;
 
             current_page = next_page;
         }
@@ -764,8 +909,8 @@ impl BTree {
     /// # Returns
     /// Tuple with:
     /// - Page number of the leaf node
-    /// - Path from root to the leaf (excluding the leaf itself)
-    fn find_leaf_for_insert_table(&self, key: i64) -> io::Result<(u32, Vec<u32>)> {
+    /// - Path from root to the leaf (excluding the leaf itself), with the index of the child taken at every page
+    fn find_leaf_for_insert_table(&self, key: i64) -> io::Result<(u32, Vec<(u32, u16)>)> {
         let mut current_page = self.root_page;
         let mut path = Vec::new();
 
@@ -777,344 +922,102 @@ impl BTree {
                 return Ok((current_page, path));
             }
 
-            path.push(current_page);
             let node = BTreeNode::new(current_page, current_type);
-            let (_, child_page, _) = node.find_table_key(key, &self.pager)?;
+            let (_, child_page, idx) = node.find_table_key(key, &self.pager)?;
+            path.push((current_page, idx));
             current_page = child_page;
         }
     }
 
-    /// Gets the path from the root to a leaf node for a specific key in an index tree.
+    /// Finds the leaf node where a key should be inserted for index trees.
     ///
     /// # Parameters
-    /// * `leaf_page` - Page number of the leaf node.
-    /// * `key` - Key to find the path for.
+    /// * `key` - Key to insert.
     ///
     /// # Errors
     /// Returns an error if there are I/O issues.
     ///
     /// # Returns
-    /// Path from root to the leaf (excluding the leaf itself).
-    fn get_path_to_leaf_index(&self, leaf_page: u32, key: &KeyValue) -> io::Result<Vec<u32>> {
-        if leaf_page == self.root_page {
-            return Ok(Vec::new());
-        }
-
+    /// Tuple with:
+    /// - Page number of the leaf node
+    /// - Path from root to the leaf (excluding the leaf itself), with the index of the child taken at every page
+    fn find_leaf_for_insert_index(&self, key: &KeyValue) -> io::Result<(u32, Vec<(u32, u16)>)> {
         let mut current_page = self.root_page;
         let mut path = Vec::new();
 
-        // Traverse the tree until we reach the target leaf
+        // Traverse the tree until we reach a leaf
         loop {
             let current_type = self.get_page_type(current_page)?;
 
             if current_type.is_leaf() {
-                if current_page == leaf_page {
-                    return Ok(path);
-                } else {
-                    return Err(io::Error::new(
-                        io::ErrorKind::InvalidData,
-                        "Could not find path to leaf",
-                    ));
-                }
+                return Ok((current_page, path));
             }
 
-            path.push(current_page);
+            // Keys equal to a cell go to its left child, larger ones to the next child
             let node = BTreeNode::new(current_page, current_type);
-            let (found, idx) = node.find_index_key(key, &self.pager)?;
-
-            let child_page = if found {
-                let cell = node.get_cell_owned(idx, &self.pager)?;
-                match cell {
+            let (_, idx) = node.find_index_key(key, &self.pager)?;
+            let child_page = if idx >= node.cell_count(&self.pager)? {
+                node.get_right_most_child(&self.pager)?
+            } else {
+                match node.get_cell_owned(idx, &self.pager)? {
                     BTreeCell::IndexInterior(cell) => cell.left_child_page,
                     _ => unreachable!("Expected an index interior cell"),
                 }
-            } else {
-                let cell_count = node.cell_count(&self.pager)?;
-
-                if idx == 0 {
-                    let cell = node.get_cell_owned(0, &self.pager)?;
-                    match cell {
-                        BTreeCell::IndexInterior(cell) => cell.left_child_page,
-                        _ => unreachable!("Expected an index interior cell"),
-                    }
-                } else if idx >= cell_count {
-                    node.get_right_most_child(&self.pager)?
-                } else {
-                    let cell = node.get_cell_owned(idx, &self.pager)?;
-                    match cell {
-                        BTreeCell::IndexInterior(cell) => cell.left_child_page,
-                        _ => unreachable!("Expected an index interior cell"),
-                    }
-                }
             };
 
-            if child_page == leaf_page {
-                return Ok(path);
-            }
-
+            path.push((current_page, idx));
             current_page = child_page;
         }
     }
 
-    /// Propagates a node split up the tree for table trees.
+    /// Creates an index payload from a median key.
+    ///
+    /// In SQLite index trees, interior nodes store the key that separates
+    /// the child subtrees. This key is extracted from the original payload
+    /// and serialized appropriately.
     ///
     /// # Parameters
-    /// * `left_node` - Left node after the split (original node).
-    /// * `right_node` - Right node after the split (new node).
-    /// * `median_key` - Key that separates the two nodes.
-    /// * `path` - Path from root to the split node.
+    /// * `median_key` - The median key value (typically an i64 hash or extracted key).
     ///
     /// # Errors
-    /// Returns an error if there are I/O issues.
-    fn propagate_split_table(
-        &mut self,
-        left_node: BTreeNode,
-        right_node: BTreeNode,
-        median_key: i64,
-        mut path: Vec<u32>,
-    ) -> io::Result<()> {
-        // If path is empty, we're splitting the root
-        if path.is_empty() {
-            // println!("Creating new root for table tree");
-            self.create_new_root_table(left_node, right_node, median_key)?;
-            return Ok(());
-        }
-
-        // Get the parent node
-        let parent_page = path.pop().unwrap();
-        let parent_node = BTreeNode::new(parent_page, PageType::TableInterior);
-
-        // Create a new cell for the parent
-        let cell = BTreeCellFactory::create_table_interior_cell(left_node.page_number, median_key);
-
-        // Update the parent's right-most child to point to the right node
-        parent_node.set_right_most_child(right_node.page_number, &self.pager)?;
+    /// Returns an error if serialization fails.
+    ///
+    /// # Returns
+    /// Serialized payload containing the key.
+    #[allow(dead_code)]
+    fn create_index_payload_from_median_key(&self, median_key: i64) -> io::Result<Vec<u8>> {
+        // For simplicity, we'll treat the median_key as an integer key
+        // In a real implementation, you might need to handle different data types
+        // based on the index definition
 
-        // Insert the cell into the parent
-        let (split, new_median, new_parent) = parent_node.insert_cell_ordered(cell, &self.pager)?;
+        let key_value = crate::utils::serialization::SqliteValue::Integer(median_key);
+        let mut payload = Vec::new();
 
-        if split {
-            // Recursively propagate the split up the tree
-            self.propagate_split_table(
-                parent_node,
-                new_parent.unwrap(),
-                new_median.unwrap(),
-                path,
-            )?;
-        }
+        // Serialize just the key (no rowid in interior nodes)
+        crate::utils::serialization::serialize_values(&[key_value], &mut payload)?;
 
-        Ok(())
+        Ok(payload)
     }
 
-    /// Propagates a node split up the tree for index trees.
+    /// Creates a payload from a KeyValue.
+    ///
+    /// This function does exactly the opposite of `extract_key_from_payload`.
+    /// It converts a KeyValue back into a serialized payload that can be stored
+    /// in index nodes.
     ///
     /// # Parameters
-    /// * `left_node` - Left node after the split (original node).
-    /// * `right_node` - Right node after the split (new node).
-    /// * `median_key` - Key that separates the two nodes.
-    /// * `path` - Path from root to the split node.
-    ///
-    /// # Errors
-    /// Returns an error if there are I/O issues.
-    fn propagate_split_index(
-        &mut self,
-        left_node: BTreeNode,
-        right_node: BTreeNode,
-        median_key: i64,
-        mut path: Vec<u32>,
-    ) -> io::Result<()> {
-        // If path is empty, we're splitting the root
-        if path.is_empty() {
-            self.create_new_root_index(left_node, right_node, median_key)?;
-            return Ok(());
-        }
-
-        // Get the parent node
-        let parent_page = path.pop().unwrap();
-        let parent_node = BTreeNode::new(parent_page, PageType::IndexInterior);
-
-        // For index trees, we need to create an interior cell with payload
-        // Extract the key from the median and create proper payload
-        let payload = self.create_index_payload_from_median_key(median_key)?;
-
-        let (cell, overflow) = BTreeCellFactory::create_index_interior_cell(
-            left_node.page_number,
-            payload,
-            self.max_local_payload(),
-            self.min_local_payload(),
-            self.usable_page_size(),
-        )?;
-
-        // Handle overflow if needed
-        let cell = if let Some(overflow_data) = overflow {
-            match cell {
-                BTreeCell::IndexInterior(mut interior_cell) => {
-                    let overflow_page = self.create_overflow_chain(overflow_data)?;
-                    interior_cell.overflow_page = Some(overflow_page);
-                    BTreeCell::IndexInterior(interior_cell)
-                }
-                _ => unreachable!("Expected an index interior cell"),
-            }
-        } else {
-            cell
-        };
-
-        // Update the parent's right-most child to point to the right node
-        parent_node.set_right_most_child(right_node.page_number, &self.pager)?;
-
-        // Insert the cell into the parent
-        let (split, new_median, new_parent) = parent_node.insert_cell_ordered(cell, &self.pager)?;
-
-        if split {
-            // Recursively propagate the split up the tree
-            self.propagate_split_index(
-                parent_node,
-                new_parent.unwrap(),
-                new_median.unwrap(),
-                path,
-            )?;
-        }
-
-        Ok(())
-    }
-
-    /// Creates a new root node after the root splits for table trees.
-    ///
-    /// # Parameters
-    /// * `left_node` - Left node after the split (original root).
-    /// * `right_node` - Right node after the split (new node).
-    /// * `median_key` - Key that separates the two nodes.
-    ///
-    /// # Errors
-    /// Returns an error if there are I/O issues.
-    fn create_new_root_table(
-        &mut self,
-        left_node: BTreeNode,
-        right_node: BTreeNode,
-        median_key: i64,
-    ) -> io::Result<()> {
-        // Create a new interior node to be the new root
-        let new_root = BTreeNode::create_interior(
-            PageType::TableInterior,
-            Some(right_node.page_number),
-            &self.pager,
-        )?;
-        // println!("Creating new root for table tree: {:?}", new_root);
-
-        // Create a cell pointing to the left node
-        let cell = BTreeCellFactory::create_table_interior_cell(left_node.page_number, median_key);
-        // println!("Creating interior cell for new root: {:?}", cell);
-        // Insert the cell into the new root
-        new_root.insert_cell(cell, &self.pager)?;
-
-        // Update the tree's root page
-        self.root_page = new_root.page_number;
-
-        Ok(())
-    }
-
-    /// Creates a new root node after the root splits for index trees.
-    ///
-    /// # Parameters
-    /// * `left_node` - Left node after the split (original root).
-    /// * `right_node` - Right node after the split (new node).
-    /// * `median_key` - Key that separates the two nodes.
-    ///
-    /// # Errors
-    /// Returns an error if there are I/O issues.
-    fn create_new_root_index(
-        &mut self,
-        left_node: BTreeNode,
-        right_node: BTreeNode,
-        median_key: i64,
-    ) -> io::Result<()> {
-        // Create a new interior node to be the new root
-        let new_root = BTreeNode::create_interior(
-            PageType::IndexInterior,
-            Some(right_node.page_number),
-            &self.pager,
-        )?;
-
-        // For index trees, we need to create an interior cell with payload
-        // Extract the key from the median and create proper payload
-        let payload = self.create_index_payload_from_median_key(median_key)?;
-
-        let (cell, overflow) = BTreeCellFactory::create_index_interior_cell(
-            left_node.page_number,
-            payload,
-            self.max_local_payload(),
-            self.min_local_payload(),
-            self.usable_page_size(),
-        )?;
-
-        // Handle overflow if needed
-        let cell = if let Some(overflow_data) = overflow {
-            match cell {
-                BTreeCell::IndexInterior(mut interior_cell) => {
-                    let overflow_page = self.create_overflow_chain(overflow_data)?;
-                    interior_cell.overflow_page = Some(overflow_page);
-                    BTreeCell::IndexInterior(interior_cell)
-                }
-                _ => unreachable!("Expected an index interior cell"),
-            }
-        } else {
-            cell
-        };
-
-        // Insert the cell into the new root
-        new_root.insert_cell(cell, &self.pager)?;
-
-        // Update the tree's root page
-        self.root_page = new_root.page_number;
-
-        Ok(())
-    }
-
-    /// Creates an index payload from a median key.
-    ///
-    /// In SQLite index trees, interior nodes store the key that separates
-    /// the child subtrees. This key is extracted from the original payload
-    /// and serialized appropriately.
-    ///
-    /// # Parameters
-    /// * `median_key` - The median key value (typically an i64 hash or extracted key).
-    ///
-    /// # Errors
-    /// Returns an error if serialization fails.
-    ///
-    /// # Returns
-    /// Serialized payload containing the key.
-    fn create_index_payload_from_median_key(&self, median_key: i64) -> io::Result<Vec<u8>> {
-        // For simplicity, we'll treat the median_key as an integer key
-        // In a real implementation, you might need to handle different data types
-        // based on the index definition
-
-        let key_value = crate::utils::serialization::SqliteValue::Integer(median_key);
-        let mut payload = Vec::new();
-
-        // Serialize just the key (no rowid in interior nodes)
-        crate::utils::serialization::serialize_values(&[key_value], &mut payload)?;
-
-        Ok(payload)
-    }
-
-    /// Creates a payload from a KeyValue.
-    ///
-    /// This function does exactly the opposite of `extract_key_from_payload`.
-    /// It converts a KeyValue back into a serialized payload that can be stored
-    /// in index nodes.
-    ///
-    /// # Parameters
-    /// * `key_value` - The key value to convert to payload
-    ///
-    /// # Returns
-    /// A serialized payload containing the key
+    /// * `key_value` - The key value to convert to payload
+    ///
+    /// # Returns
+    /// A serialized payload containing the key
     ///
     /// # Errors
     /// Returns an error if the payload cannot be serialized
-    #[allow(dead_code)]
-    fn create_payload_from_key_value(&self, key_value: &KeyValue) -> io::Result<Vec<u8>> {
-        use crate::utils::serialization::{serialize_values, SqliteValue};
+    pub(crate) fn create_payload_from_key_value(
+        &self,
+        key_value: &KeyValue,
+    ) -> io::Result<Vec<u8>> {
+        use crate::utils::serialization::serialize_values;
 
         // Convert the KeyValue to a SqliteValue
         let sqlite_value = match key_value {
@@ -1150,513 +1053,6 @@ impl BTree {
         KeyValue::Integer(median_key)
     }
 
-    /// Rebalances the tree after a deletion.
-    ///
-    /// # Parameters
-    /// * `leaf_page` - Page number of the leaf node where deletion occurred.
-    /// * `path` - Path from root to the leaf node.
-    ///
-    /// # Errors
-    /// Returns an error if there are I/O issues.
-    fn rebalance_after_delete(&mut self, leaf_page: u32, path: Vec<u32>) -> io::Result<()> {
-        // If this is the root or if the path is empty, no rebalancing needed
-        if path.is_empty() || leaf_page == self.root_page {
-            return Ok(());
-        }
-
-        // Define minimum cell count for a node (50% full)
-        // In SQLite, this is typically fill_factor / 2
-        let min_cell_count = 1; // Simplified - should be based on page size
-
-        // Check if the node needs rebalancing
-        let leaf_type = match self.tree_type {
-            TreeType::Table => PageType::TableLeaf,
-            TreeType::Index => PageType::IndexLeaf,
-        };
-
-        let leaf_node = BTreeNode::new(leaf_page, leaf_type);
-        let cell_count = leaf_node.cell_count(&self.pager)?;
-
-        if cell_count >= min_cell_count {
-            // Node has enough cells, no rebalancing needed
-            return Ok(());
-        }
-
-        // Node is underfilled, try to borrow or merge
-        let parent_page = *path.last().unwrap();
-        let parent_type = match self.tree_type {
-            TreeType::Table => PageType::TableInterior,
-            TreeType::Index => PageType::IndexInterior,
-        };
-
-        let parent_node = BTreeNode::new(parent_page, parent_type);
-
-        // Find the index of the current node in the parent
-        let (left_sibling, right_sibling) = self.find_siblings(&parent_node, leaf_page)?;
-
-        // Try to borrow from the right sibling first
-        if let Some(right_page) = right_sibling {
-            if self.borrow_from_sibling(&leaf_node, right_page, parent_page, false)? {
-                return Ok(());
-            }
-        }
-
-        // Try to borrow from the left sibling
-        if let Some(left_page) = left_sibling {
-            if self.borrow_from_sibling(&leaf_node, left_page, parent_page, true)? {
-                return Ok(());
-            }
-        }
-
-        // Borrowing failed, need to merge
-        // Prefer merging with left sibling if available
-        if let Some(left_page) = left_sibling {
-            self.merge_nodes(left_page, leaf_page, parent_page)?;
-        } else if let Some(right_page) = right_sibling {
-            self.merge_nodes(leaf_page, right_page, parent_page)?;
-        } else {
-            // No siblings available, this should never happen in a valid B-tree
-            return Err(io::Error::new(
-                io::ErrorKind::InvalidData,
-                "Node has no siblings for merging",
-            ));
-        }
-
-        // Check if parent needs rebalancing (recursive)
-        let new_path = if path.len() > 1 {
-            path[0..path.len() - 1].to_vec()
-        } else {
-            Vec::new()
-        };
-
-        self.rebalance_after_delete(parent_page, new_path)?;
-
-        Ok(())
-    }
-
-    /// Finds the sibling nodes of a given node.
-    ///
-    /// # Parameters
-    /// * `parent_node` - Parent node.
-    /// * `node_page` - Page number of the node to find siblings for.
-    ///
-    /// # Errors
-    /// Returns an error if there are I/O issues.
-    ///
-    /// # Returns
-    /// Tuple with:
-    /// - Option with page number of left sibling (None if no left sibling)
-    /// - Option with page number of right sibling (None if no right sibling)
-    fn find_siblings(
-        &self,
-        parent_node: &BTreeNode,
-        node_page: u32,
-    ) -> io::Result<(Option<u32>, Option<u32>)> {
-        let cell_count = parent_node.cell_count(&self.pager)?;
-
-        if cell_count == 0 {
-            // Parent has no cells, node must be the only child
-            return Ok((None, None));
-        }
-
-        // Find the position of node_page in the parent
-        let mut pos = None;
-
-        // Check if node is the rightmost child
-        let rightmost = parent_node.get_right_most_child(&self.pager)?;
-        if rightmost == node_page {
-            pos = Some(cell_count);
-        }
-
-        // If not rightmost, find the position by scanning cells
-        if pos.is_none() {
-            for i in 0..cell_count {
-                let cell = parent_node.get_cell_owned(i, &self.pager)?;
-
-                let child_page = match cell {
-                    BTreeCell::TableInterior(ref interior) => interior.left_child_page,
-                    BTreeCell::IndexInterior(ref interior) => interior.left_child_page,
-                    _ => unreachable!("Expected an interior cell"),
-                };
-
-                if child_page == node_page {
-                    pos = Some(i);
-                    break;
-                }
-            }
-        }
-
-        let pos = match pos {
-            Some(p) => p,
-            None => {
-                return Err(io::Error::new(
-                    io::ErrorKind::InvalidData,
-                    "Node not found in parent",
-                ));
-            }
-        };
-
-        // Determine siblings
-        let left_sibling = if pos > 0 {
-            // Get the left sibling
-            if pos == cell_count {
-                // Node is rightmost child, its left sibling is the left child of the last cell
-                let last_cell = parent_node.get_cell_owned(cell_count - 1, &self.pager)?;
-
-                match self.tree_type {
-                    TreeType::Table => match last_cell {
-                        BTreeCell::TableInterior(interior) => Some(interior.left_child_page),
-                        _ => unreachable!("Expected a table interior cell"),
-                    },
-                    TreeType::Index => match last_cell {
-                        BTreeCell::IndexInterior(interior) => Some(interior.left_child_page),
-                        _ => unreachable!("Expected an index interior cell"),
-                    },
-                }
-            } else {
-                // Get the left child of the previous cell
-                let prev_cell = parent_node.get_cell_owned(pos - 1, &self.pager)?;
-
-                match self.tree_type {
-                    TreeType::Table => match prev_cell {
-                        BTreeCell::TableInterior(interior) => Some(interior.left_child_page),
-                        _ => unreachable!("Expected a table interior cell"),
-                    },
-                    TreeType::Index => match prev_cell {
-                        BTreeCell::IndexInterior(interior) => Some(interior.left_child_page),
-                        _ => unreachable!("Expected an index interior cell"),
-                    },
-                }
-            }
-        } else {
-            None
-        };
-
-        let right_sibling = if pos < cell_count {
-            // Get the right sibling
-            if pos == 0 {
-                // Node is leftmost child, its right sibling is the left child of the first cell
-                let first_cell = parent_node.get_cell_owned(0, &self.pager)?;
-
-                match self.tree_type {
-                    TreeType::Table => match first_cell {
-                        BTreeCell::TableInterior(interior) => Some(interior.left_child_page),
-                        _ => unreachable!("Expected a table interior cell"),
-                    },
-                    TreeType::Index => match first_cell {
-                        BTreeCell::IndexInterior(interior) => Some(interior.left_child_page),
-                        _ => unreachable!("Expected an index interior cell"),
-                    },
-                }
-            } else if pos == cell_count {
-                // Node is rightmost child, it has no right sibling
-                None
-            } else {
-                // Get the left child of the next cell
-                let next_cell = parent_node.get_cell_owned(pos, &self.pager)?;
-
-                match self.tree_type {
-                    TreeType::Table => match next_cell {
-                        BTreeCell::TableInterior(interior) => Some(interior.left_child_page),
-                        _ => unreachable!("Expected a table interior cell"),
-                    },
-                    TreeType::Index => match next_cell {
-                        BTreeCell::IndexInterior(interior) => Some(interior.left_child_page),
-                        _ => unreachable!("Expected an index interior cell"),
-                    },
-                }
-            }
-        } else {
-            None
-        };
-
-        Ok((left_sibling, right_sibling))
-    }
-
-    /// Tries to borrow cells from a sibling node.
-    ///
-    /// # Parameters
-    /// * `target_node` - Node that needs more cells.
-    /// * `sibling_page` - Page number of the sibling node.
-    /// * `parent_page` - Page number of the parent node.
-    /// * `from_left` - `true` if borrowing from left sibling, `false` if from right.
-    ///
-    /// # Errors
-    /// Returns an error if there are I/O issues.
-    ///
-    /// # Returns
-    /// `true` if borrowing succeeded, `false` otherwise.
-    fn borrow_from_sibling(
-        &self,
-        target_node: &BTreeNode,
-        sibling_page: u32,
-        parent_page: u32,
-        from_left: bool,
-    ) -> io::Result<bool> {
-        // Define minimum cell count for a node after borrowing
-        let min_cell_count = 1; // Simplified - should be based on page size
-
-        // Open sibling node
-        let sibling_type = target_node.node_type;
-        let sibling_node = BTreeNode::new(sibling_page, sibling_type);
-
-        // Check if sibling has enough cells to spare
-        let sibling_cell_count = sibling_node.cell_count(&self.pager)?;
-
-        if sibling_cell_count <= min_cell_count {
-            // Sibling doesn't have enough cells to spare
-            return Ok(false);
-        }
-
-        // Get the separator key from the parent
-        let parent_type = match self.tree_type {
-            TreeType::Table => PageType::TableInterior,
-            TreeType::Index => PageType::IndexInterior,
-        };
-
-        let parent_node = BTreeNode::new(parent_page, parent_type);
-
-        if from_left {
-            // Borrow from the left sibling
-            // Get the rightmost cell from the left sibling
-            let sibling_cell = sibling_node.get_cell_owned(sibling_cell_count - 1, &self.pager)?;
-
-            // Remove the cell from the sibling
-            sibling_node.remove_cell(sibling_cell_count - 1, &self.pager)?;
-
-            // Update the separator key in the parent
-            let separator_idx =
-                self.find_separator_index(&parent_node, target_node.page_number, true)?;
-
-            // Update the separator key
-            let new_separator = match (self.tree_type, &sibling_cell) {
-                (TreeType::Table, BTreeCell::TableLeaf(leaf)) => leaf.row_id,
-                (TreeType::Index, BTreeCell::IndexLeaf(leaf)) => {
-                    let key_value = extract_key_from_payload(&leaf.payload)?;
-                    match key_value {
-                        KeyValue::Integer(i) => i,
-                        KeyValue::Float(f) => f as i64,
-                        _ => {
-                            // For string and blob, hash the key
-                            let mut hasher = std::collections::hash_map::DefaultHasher::new();
-                            std::hash::Hash::hash(&key_value, &mut hasher);
-                            hasher.finish() as i64
-                        }
-                    }
-                }
-                _ => unreachable!("Unexpected cell type"),
-            };
-
-            // Update the separator key in the parent using callback
-            parent_node.with_cell_mut(separator_idx, &self.pager, |cell| {
-                match self.tree_type {
-                    TreeType::Table => match cell {
-                        BTreeCell::TableInterior(ref mut interior) => {
-                            interior.key = new_separator;
-                        }
-                        _ => unreachable!("Expected a table interior cell"),
-                    },
-                    TreeType::Index => {
-                        // For index trees, we need to update the payload
-                        // This is simplified - in a real implementation, you'd handle this properly
-                    }
-                }
-                Ok(())
-            })?;
-
-            // Insert the cell into the target node
-            target_node.insert_cell_ordered(sibling_cell, &self.pager)?;
-
-            Ok(true)
-        } else {
-            // Borrow from the right sibling
-            // Get the leftmost cell from the right sibling
-            let sibling_cell = sibling_node.get_cell_owned(0, &self.pager)?;
-
-            // Remove the cell from the sibling
-            sibling_node.remove_cell(0, &self.pager)?;
-
-            // Update the separator key in the parent
-            let separator_idx =
-                self.find_separator_index(&parent_node, target_node.page_number, false)?;
-
-            // Update the separator key
-            let new_separator = match (self.tree_type, &sibling_cell) {
-                (TreeType::Table, BTreeCell::TableLeaf(leaf)) => leaf.row_id,
-                (TreeType::Index, BTreeCell::IndexLeaf(leaf)) => {
-                    let key_value = extract_key_from_payload(&leaf.payload)?;
-                    match key_value {
-                        KeyValue::Integer(i) => i,
-                        KeyValue::Float(f) => f as i64,
-                        _ => {
-                            // For string and blob, hash the key
-                            let mut hasher = std::collections::hash_map::DefaultHasher::new();
-                            std::hash::Hash::hash(&key_value, &mut hasher);
-                            hasher.finish() as i64
-                        }
-                    }
-                }
-                _ => unreachable!("Unexpected cell type"),
-            };
-
-            // Update the separator key in the parent using callback
-            parent_node.with_cell_mut(separator_idx, &self.pager, |cell| {
-                match self.tree_type {
-                    TreeType::Table => match cell {
-                        BTreeCell::TableInterior(ref mut interior) => {
-                            interior.key = new_separator;
-                        }
-                        _ => unreachable!("Expected a table interior cell"),
-                    },
-                    TreeType::Index => {
-                        // For index trees, we need to update the payload
-                        // This is simplified - in a real implementation, you'd handle this properly
-                    }
-                }
-                Ok(())
-            })?;
-
-            // Insert the cell into the target node
-            target_node.insert_cell_ordered(sibling_cell, &self.pager)?;
-
-            Ok(true)
-        }
-    }
-
-    /// Finds the index of the separator between two nodes in the parent node.
-    ///
-    /// # Parameters
-    /// * `parent_node` - Parent node.
-    /// * `child_page` - Page number of one of the child nodes.
-    /// * `is_left` - `true` if child_page is the left node, `false` if it's the right node.
-    ///
-    /// # Errors
-    /// Returns an error if there are I/O issues.
-    ///
-    /// # Returns
-    /// Index of the separator cell in the parent.
-    fn find_separator_index(
-        &self,
-        parent_node: &BTreeNode,
-        child_page: u32,
-        is_left: bool,
-    ) -> io::Result<u16> {
-        let cell_count = parent_node.cell_count(&self.pager)?;
-
-        // If child_page is the rightmost child, there's no separator (it's after the last key)
-        let rightmost = parent_node.get_right_most_child(&self.pager)?;
-        if child_page == rightmost {
-            if is_left {
-                // The separator is the last key in the parent
-                return Ok(cell_count - 1);
-            } else {
-                // There's no separator if child_page is rightmost and is_left is false
-                return Err(io::Error::new(
-                    io::ErrorKind::InvalidInput,
-                    "No separator for rightmost child",
-                ));
-            }
-        }
-
-        // Find the cell that has child_page as its left child
-        for i in 0..cell_count {
-            let cell = parent_node.get_cell_owned(i, &self.pager)?;
-
-            let cell_child = match cell {
-                BTreeCell::TableInterior(ref interior) => interior.left_child_page,
-                BTreeCell::IndexInterior(ref interior) => interior.left_child_page,
-                _ => unreachable!("Expected an interior cell"),
-            };
-
-            if cell_child == child_page {
-                if is_left {
-                    // The separator is this cell (since child_page is the left child)
-                    return Ok(i);
-                } else {
-                    // The separator is the previous cell (since child_page is the right child)
-                    if i == 0 {
-                        return Err(io::Error::new(
-                            io::ErrorKind::InvalidInput,
-                            "No separator for leftmost child",
-                        ));
-                    }
-                    return Ok(i - 1);
-                }
-            }
-        }
-
-        // Child page not found in parent
-        Err(io::Error::new(
-            io::ErrorKind::InvalidData,
-            "Child page not found in parent",
-        ))
-    }
-
-    /// Merges two adjacent nodes.
-    ///
-    /// # Parameters
-    /// * `left_page` - Page number of the left node.
-    /// * `right_page` - Page number of the right node.
-    /// * `parent_page` - Page number of the parent node.
-    ///
-    /// # Errors
-    /// Returns an error if there are I/O issues.
-    fn merge_nodes(&self, left_page: u32, right_page: u32, parent_page: u32) -> io::Result<()> {
-        // Open the nodes
-        let leaf_type = match self.tree_type {
-            TreeType::Table => PageType::TableLeaf,
-            TreeType::Index => PageType::IndexLeaf,
-        };
-
-        let left_node = BTreeNode::new(left_page, leaf_type);
-        let right_node = BTreeNode::new(right_page, leaf_type);
-
-        // Get the separator key from the parent
-        let parent_type = match self.tree_type {
-            TreeType::Table => PageType::TableInterior,
-            TreeType::Index => PageType::IndexInterior,
-        };
-
-        let parent_node = BTreeNode::new(parent_page, parent_type);
-
-        // Find the separator key in the parent
-        let separator_idx = self.find_separator_index(&parent_node, left_page, true)?;
-
-        // Move all cells from right to left
-        let right_cell_count = right_node.cell_count(&self.pager)?;
-
-        for i in 0..right_cell_count {
-            let cell = right_node.get_cell_owned(i, &self.pager)?;
-            left_node.insert_cell_ordered(cell, &self.pager)?;
-        }
-
-        // Remove the separator from the parent
-        parent_node.remove_cell(separator_idx, &self.pager)?;
-
-        // Update the rightmost child if necessary
-        parent_node.with_page_mut(&self.pager, |page| {
-            match page {
-                crate::page::Page::BTree(btree_page) => {
-                    // If the right node was the right-most child, update to point to the left node
-                    if let Some(right_most) = btree_page.header.right_most_page {
-                        if right_most == right_page {
-                            btree_page.header.right_most_page = Some(left_page);
-                        }
-                    }
-                    Ok(())
-                }
-                _ => unreachable!("Expected a B-Tree page"),
-            }
-        })?;
-
-        // Mark the right node as free
-        // This would normally add it to the free list
-        // For simplicity, we're just creating a free page (the page itself isn't actually freed)
-        self.pager.create_free_page(0)?;
-
-        Ok(())
-    }
-
     /// Gets the page type of a specific page.
     ///
     /// # Parameters
@@ -1694,7 +1090,6 @@ mod tests {
     use super::*;
     use crate::storage::pager::Pager;
     use crate::tree::record::Record;
-    use crate::utils::serialization::SqliteValue;
     use tempfile::tempdir;
 
     // Helper function to create a test pager
@@ -1922,6 +1317,48 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_reopen_grown_tree() {
+        let pager = create_test_pager();
+        let mut btree = BTree::create(TreeType::Table, Arc::new(pager), 4096, 0, 255, 32).unwrap();
+
+        // Descending rowids, so every cell goes in front of the ones already in the page
+        for rowid in (1..=40).rev() {
+            let record = create_test_record(vec![
+                SqliteValue::Integer(rowid),
+                SqliteValue::Blob(vec![rowid as u8; 100]),
+            ]);
+            btree.insert(rowid, &record).unwrap();
+        }
+
+        // The root is an interior page by now, and the tree can still be opened from it
+        assert_eq!(
+            btree.get_page_type(btree.root_page).unwrap(),
+            PageType::TableInterior
+        );
+        let btree = BTree::open(
+            btree.root_page,
+            TreeType::Table,
+            Arc::clone(&btree.pager),
+            4096,
+            0,
+            255,
+            32,
+        )
+        .unwrap();
+
+        for rowid in 1..=40 {
+            let record = btree
+                .find(rowid)
+                .unwrap()
+                .unwrap_or_else(|| panic!("rowid {} lost", rowid));
+            match &record.values[1] {
+                SqliteValue::Blob(value) => assert_eq!(value, &vec![rowid as u8; 100]),
+                _ => panic!("Expected Blob"),
+            }
+        }
+    }
+
     #[test]
     fn test_delete_record() {
         let pager = create_test_pager();
@@ -1962,6 +1399,47 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_delete_and_reinsert_reuses_space() {
+        let dir = tempdir().unwrap();
+        let db_path = dir.path().join("test.db");
+        let pager = Arc::new(Pager::create(&db_path, 4096, Some(8), 0).unwrap());
+        let mut btree = BTree::create(TreeType::Table, pager, 4096, 0, 255, 32).unwrap();
+
+        let record = |i: i64| {
+            create_test_record(vec![
+                SqliteValue::Integer(i),
+                SqliteValue::String(format!("Record {}", i).repeat(1 + (i % 5) as usize)),
+            ])
+        };
+        for i in 1..=400 {
+            btree.insert(i, &record(i)).unwrap();
+        }
+        let page_count = btree.pager.page_count().unwrap();
+
+        // The deleted cells leave freeblocks behind, and the new rows fit in them without splitting.
+        // The pages keep more than a third of their rows, so they are not balanced with their siblings,
+        // and the last leaf, which is the emptiest one, is left alone.
+        for i in (3..=300).step_by(3) {
+            assert!(btree.delete(i).unwrap());
+        }
+        for i in (3..=300).step_by(3) {
+            btree.insert(i, &record(i)).unwrap();
+        }
+        assert_eq!(btree.pager.page_count().unwrap(), page_count);
+
+        // And the pages with freeblocks read back fine
+        let root_page = btree.root_page;
+        btree.pager.flush().unwrap();
+        drop(btree);
+        let pager = Arc::new(Pager::open(&db_path, Some(8)).unwrap());
+        let btree = BTree::open(root_page, TreeType::Table, pager, 4096, 0, 255, 32).unwrap();
+        for i in 1..=400 {
+            let found = btree.find(i).unwrap().unwrap();
+            assert_eq!(found.to_bytes().unwrap(), record(i).to_bytes().unwrap());
+        }
+    }
+
     #[test]
     fn test_insert_and_find_index() {
         let pager = create_test_pager();
@@ -2097,6 +1575,48 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_find_columns_skips_overflow() {
+        let pager = create_test_pager();
+        let mut btree = BTree::create(TreeType::Table, Arc::new(pager), 4096, 0, 255, 32).unwrap();
+
+        let record = create_test_record(vec![
+            SqliteValue::Integer(7),
+            SqliteValue::String("name".to_string()),
+            SqliteValue::Blob(vec![0xAA; 10000]),
+        ]);
+        btree.insert(1, &record).unwrap();
+        for rowid in 2..=20 {
+            let small = create_test_record(vec![SqliteValue::Integer(rowid)]);
+            btree.insert(rowid, &small).unwrap();
+        }
+
+        let projected = btree.find_columns(1, &[1, 0, 5], &[]).unwrap().unwrap();
+        assert_eq!(
+            projected.values,
+            vec![
+                SqliteValue::String("name".to_string()),
+                SqliteValue::Integer(7),
+                SqliteValue::Null,
+            ]
+        );
+        let blob = btree.find_columns(1, &[2], &[]).unwrap().unwrap();
+        assert_eq!(blob.values, vec![SqliteValue::Blob(vec![0xAA; 10000])]);
+        assert!(btree.find_columns(21, &[0], &[]).unwrap().is_none());
+
+        // Without the overflow pages, only the columns in the leaf can still be read
+        let leaf_cell = btree.find_leaf_cell(1).unwrap().unwrap();
+        btree.free_page(leaf_cell.overflow_page.unwrap()).unwrap();
+        let projected = btree.find_columns(1, &[0, 1], &[]).unwrap().unwrap();
+        assert_eq!(projected.values[0], SqliteValue::Integer(7));
+        assert!(btree.find_columns(1, &[2], &[]).is_err());
+        assert!(btree.find(1).is_err());
+        assert_eq!(
+            btree.find_columns(5, &[0], &[]).unwrap().unwrap().values,
+            vec![SqliteValue::Integer(5)]
+        );
+    }
+
     #[test]
     fn test_btree_getters() {
         let pager = create_test_pager();
@@ -2268,7 +1788,7 @@ mod tests {
         // Test different types of KeyValues
         let test_cases = vec![
             KeyValue::Integer(42),
-            KeyValue::Float(3.14159),
+            KeyValue::Float(std::f64::consts::PI),
             KeyValue::String("Hello, SQLite!".to_string()),
             KeyValue::Blob(vec![0xDE, 0xAD, 0xBE, 0xEF]),
             KeyValue::Null,
@@ -0,0 +1,608 @@
+//! # Bulk Load Module
+//!
+//! Loading a big table one `insert` at a time is slow for two reasons: every row descends the tree from the root,
+//! and every page that fills up is split in two halves, so the tree ends up with its pages half empty and
+//! twice as many of them as it needs. When the rows come sorted by key we can do much better: fill the leaves
+//! from left to right up to the fill factor, and build the interior levels on top of them as we go.
+//! This is what SQLite does for CREATE INDEX (through its sorter) and PostgreSQL calls it a sorted build (nbtsort.c).
+//!
+//! The `TreeBuilder` keeps one open page per level of the tree, the rightmost one. When a page cannot take the next cell,
+//! it is written and a separator for it goes up to the open page of the level above, which can fill up in turn.
+//! The separator of a page is its largest key, as an interior key routes the keys equal to it to its left child.
+//! At the end, the open pages are closed from the bottom up, and the last one is the new root.
+//!
+//! The rows do not have to come sorted, though. The loader keeps them in memory up to a budget, checking the order:
+//! - If they were not sorted, everything goes through an `ExternalSorter` first (see the sorter module).
+//! - If they were, they go straight to the builder. Rows that show up out of order after that are set aside (sorted too)
+//!   and inserted the usual way once the tree is built, which is cheap as long as there are few of them.
+use std::cmp::Ordering;
+use std::io;
+
+use crate::page::{BTreeCell, BTreePage, Page, PageType, TableInteriorCell};
+use crate::tree::balance::{left_child, with_left_child};
+use crate::tree::btree::{BTree, TreeType};
+use crate::tree::node::extract_key_from_payload;
+use crate::tree::record::Record;
+use crate::tree::sorter::{compare_keys, ExternalSorter, DEFAULT_SORT_MEMORY};
+use crate::utils::serialization::{serialize_values, SqliteValue};
+
+/// Default fill factor of the pages built by the loader, in percent.
+/// A bit of room is left so that the next inserts and updates do not split every page right away.
+pub const DEFAULT_FILL_FACTOR: u8 = 90;
+
+/// Loads rows into an empty B-Tree, building it from the bottom up (see the module docs).
+pub struct BulkLoader<'a> {
+    btree: &'a mut BTree,
+    fill_factor: u8,
+    sort_memory: usize,
+}
+
+impl<'a> BulkLoader<'a> {
+    /// Creates a loader for a tree, with the default fill factor and memory budget.
+    ///
+    /// # Parameters
+    /// * `btree` - The tree to load. It must be empty when the load starts.
+    pub fn new(btree: &'a mut BTree) -> Self {
+        BulkLoader {
+            btree,
+            fill_factor: DEFAULT_FILL_FACTOR,
+            sort_memory: DEFAULT_SORT_MEMORY,
+        }
+    }
+
+    /// Sets how full the loader makes every page, in percent. Values outside 10 to 100 are clamped.
+    pub fn fill_factor(mut self, percent: u8) -> Self {
+        self.fill_factor = percent.clamp(10, 100);
+        self
+    }
+
+    /// Sets how many bytes of rows the loader keeps in memory before spilling sorted runs to temporary files.
+    pub fn sort_memory(mut self, bytes: usize) -> Self {
+        self.sort_memory = bytes;
+        self
+    }
+
+    /// Loads rows into a table tree.
+    ///
+    /// # Parameters
+    /// * `rows` - The rows and their rowids, preferably sorted by rowid.
+    ///
+    /// # Errors
+    /// Returns an error if the tree is not an empty table tree, two rows have the same rowid
+    /// (or a rowid the table already had), or there are I/O issues.
+    pub fn load_table<I>(self, rows: I) -> io::Result<()>
+    where
+        I: IntoIterator<Item = (i64, Record)>,
+    {
+        self.require(TreeType::Table)?;
+        let entries = rows.into_iter().map(|(rowid, record)| {
+            Ok((
+                Record::with_values(vec![SqliteValue::Integer(rowid)]),
+                record,
+            ))
+        });
+        self.load(entries)
+    }
+
+    /// Loads entries into an index tree.
+    ///
+    /// # Parameters
+    /// * `entries` - The serialized keys and the rowids they point to, preferably sorted by key and rowid.
+    ///
+    /// # Errors
+    /// Returns an error if the tree is not an empty index tree, a key cannot be parsed,
+    /// the same entry is there twice, or there are I/O issues.
+    pub fn load_index<I>(self, entries: I) -> io::Result<()>
+    where
+        I: IntoIterator<Item = (Vec<u8>, i64)>,
+    {
+        self.require(TreeType::Index)?;
+        let entries = entries.into_iter().map(|(key, rowid)| {
+            let (mut key, _) = Record::from_bytes(&key)?;
+            if key.is_empty() {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "Empty key in index entry",
+                ));
+            }
+            key.add_value(SqliteValue::Integer(rowid));
+            Ok((key, Record::new()))
+        });
+        self.load(entries)
+    }
+
+    /// Fails unless the tree has the given type and is empty.
+    fn require(&self, tree_type: TreeType) -> io::Result<()> {
+        if self.btree.tree_type() != tree_type {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "Cannot bulk load a {:?} tree as a {:?} tree",
+                    self.btree.tree_type(),
+                    tree_type
+                ),
+            ));
+        }
+        if !self.btree.is_empty()? {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Bulk loading needs an empty tree",
+            ));
+        }
+        Ok(())
+    }
+
+    /// Loads the entries, sorting them first if they do not come sorted (see the module docs).
+    ///
+    /// Every entry is a sort key and a record. The key of a row is its rowid, and the record the row itself.
+    /// The key of an index entry is the key of the index followed by the rowid, and the record is empty.
+    fn load(
+        self,
+        mut entries: impl Iterator<Item = io::Result<(Record, Record)>>,
+    ) -> io::Result<()> {
+        let tree_type = self.btree.tree_type();
+
+        // Find out if the input is sorted while it fits in memory
+        let mut buffer: Vec<(Record, Record)> = Vec::new();
+        let mut buffered = 0;
+        let mut sorted = true;
+        while buffered < self.sort_memory {
+            let Some(entry) = entries.next() else {
+                break;
+            };
+            let (key, record) = entry?;
+            if buffer
+                .last()
+                .is_some_and(|(last, _)| compare_keys(last, &key, &[]) != Ordering::Less)
+            {
+                sorted = false;
+            }
+            buffered += key.serialized_size() + record.serialized_size();
+            buffer.push((key, record));
+        }
+
+        let mut builder = TreeBuilder::new(self.btree, self.fill_factor);
+        let mut stragglers = None;
+        if sorted {
+            for (key, record) in buffer {
+                builder.push(key, record)?;
+            }
+            for entry in entries {
+                let (key, record) = entry?;
+                if builder.accepts(&key) {
+                    builder.push(key, record)?;
+                } else {
+                    stragglers
+                        .get_or_insert_with(|| ExternalSorter::new(self.sort_memory))
+                        .push(key, record)?;
+                }
+            }
+        } else {
+            let mut sorter = ExternalSorter::new(self.sort_memory);
+            for entry in buffer.into_iter().map(Ok).chain(entries) {
+                let (key, record) = entry?;
+                sorter.push(key, record)?;
+            }
+            for entry in sorter.finish()? {
+                let (key, record) = entry?;
+                builder.push(key, record)?;
+            }
+        }
+
+        let root_page = builder.finish()?;
+        self.btree.set_root_page(root_page);
+
+        // The rows that came out of order go in the usual way, already sorted so they hit the same leaves in a row
+        if let Some(stragglers) = stragglers {
+            for entry in stragglers.finish()? {
+                let (key, record) = entry?;
+                let rowid = rowid_of(&key);
+                match tree_type {
+                    TreeType::Table => {
+                        if self.btree.find(rowid)?.is_some() {
+                            return Err(duplicate_error(tree_type, &key));
+                        }
+                        self.btree.insert(rowid, &record)?;
+                    }
+                    TreeType::Index => self.btree.insert_index(&index_key(&key)?, rowid)?,
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Gets the rowid of a sort key, its last value.
+fn rowid_of(key: &Record) -> i64 {
+    match key.values.last() {
+        Some(SqliteValue::Integer(rowid)) => *rowid,
+        _ => unreachable!("Sort keys end with the rowid"),
+    }
+}
+
+/// Serializes the key of an index entry, that is, its sort key without the rowid.
+fn index_key(key: &Record) -> io::Result<Vec<u8>> {
+    let mut payload = Vec::new();
+    serialize_values(&key.values[..key.values.len() - 1], &mut payload)?;
+    Ok(payload)
+}
+
+/// The error for an entry that is already in the tree.
+fn duplicate_error(tree_type: TreeType, key: &Record) -> io::Error {
+    let message = match tree_type {
+        TreeType::Table => format!("Duplicate rowid {}", rowid_of(key)),
+        TreeType::Index => format!(
+            "Duplicate index entry {:?} for rowid {}",
+            &key.values[..key.values.len() - 1],
+            rowid_of(key)
+        ),
+    };
+    io::Error::new(io::ErrorKind::InvalidInput, message)
+}
+
+/// Builds a B-Tree from the bottom up out of sorted entries.
+struct TreeBuilder<'a> {
+    btree: &'a BTree,
+    /// Bytes of a page the builder fills, the fill factor applied to the usable size.
+    target: usize,
+    /// The open (rightmost) page of every level, the leaves first.
+    levels: Vec<BTreePage>,
+    /// The old, empty root of the tree, reused for the first page written.
+    spare: Option<u32>,
+    /// The sort key of the last entry pushed, to check the order.
+    last: Option<Record>,
+}
+
+impl<'a> TreeBuilder<'a> {
+    fn new(btree: &'a BTree, fill_factor: u8) -> Self {
+        // Page 1 also holds the database header, so it cannot take a full page of cells
+        let spare = Some(btree.root_page()).filter(|page_number| *page_number != 1);
+        let mut builder = TreeBuilder {
+            btree,
+            target: btree.usable_page_size() * fill_factor as usize / 100,
+            levels: Vec::new(),
+            spare,
+            last: None,
+        };
+        let leaf = builder.new_page(0);
+        builder.levels.push(leaf);
+        builder
+    }
+
+    /// Checks if an entry can go next, that is, its sort key comes strictly after the last one.
+    fn accepts(&self, key: &Record) -> bool {
+        self.last
+            .as_ref()
+            .is_none_or(|last| compare_keys(last, key, &[]) == Ordering::Less)
+    }
+
+    /// Appends an entry to the rightmost leaf.
+    ///
+    /// # Errors
+    /// Returns an error if the entry does not come after the previous one, or the pages cannot be written.
+    fn push(&mut self, key: Record, record: Record) -> io::Result<()> {
+        let tree_type = self.btree.tree_type();
+        if !self.accepts(&key) {
+            return Err(duplicate_error(tree_type, &key));
+        }
+        let cell = match tree_type {
+            TreeType::Table => self
+                .btree
+                .table_leaf_cell(rowid_of(&key), record.to_bytes()?)?,
+            TreeType::Index => self
+                .btree
+                .index_leaf_cell(&index_key(&key)?, rowid_of(&key))?,
+        };
+        self.add(0, cell)?;
+        self.last = Some(key);
+        Ok(())
+    }
+
+    /// Writes the open pages from the bottom up, each one the rightmost child of the one above.
+    ///
+    /// # Returns
+    /// The page number of the root.
+    fn finish(mut self) -> io::Result<u32> {
+        let mut child = None;
+        for mut page in std::mem::take(&mut self.levels) {
+            if let Some(child) = child {
+                page.header.right_most_page = Some(child);
+            }
+            child = Some(self.write(page)?);
+        }
+        Ok(child.expect("The builder always has a leaf level"))
+    }
+
+    /// Adds a cell to the open page of a level, writing the page first if it is full.
+    fn add(&mut self, level: usize, cell: BTreeCell) -> io::Result<()> {
+        if level == self.levels.len() {
+            let page = self.new_page(level);
+            self.levels.push(page);
+        }
+        if !self.fits(&self.levels[level], &cell) {
+            let page = self.new_page(level);
+            let full = std::mem::replace(&mut self.levels[level], page);
+            let separator = self.close(full)?;
+            self.add(level + 1, separator)?;
+        }
+        self.levels[level].add_cell(cell).map(|_| ())
+    }
+
+    /// Checks if a cell fits in a page without going over the fill factor.
+    /// Pages always get two cells, whatever the fill factor, so that the tree keeps branching.
+    fn fits(&self, page: &BTreePage, cell: &BTreeCell) -> bool {
+        let usable = self.btree.usable_page_size();
+        let content = usable - page.header.content_start_offset as usize;
+        let used = page.header.size() + page.cell_indices.len() * 2 + content;
+        let needed = cell.size() + 2;
+        used + needed <= usable && (page.cells.len() < 2 || used + needed <= self.target)
+    }
+
+    /// Writes a full page and gets the cell that points to it from the level above.
+    ///
+    /// The separator of a leaf is built from its last key. An interior page hands its last cell up instead:
+    /// the child of that cell becomes its rightmost child, and the key, the largest of the whole subtree, the separator.
+    fn close(&mut self, mut page: BTreePage) -> io::Result<BTreeCell> {
+        let separator = if page.header.page_type.is_leaf() {
+            match page.cells.last() {
+                Some(BTreeCell::TableLeaf(cell)) => BTreeCell::TableInterior(TableInteriorCell {
+                    left_child_page: 0,
+                    key: cell.row_id,
+                }),
+                Some(BTreeCell::IndexLeaf(cell)) => {
+                    let key = extract_key_from_payload(&cell.payload)?;
+                    let payload = self.btree.create_payload_from_key_value(&key)?;
+                    self.btree.index_interior_cell(0, payload)?
+                }
+                _ => unreachable!("Closed pages are never empty"),
+            }
+        } else {
+            let cell = page.cells.pop().expect("Closed pages are never empty");
+            page.cell_indices.pop();
+            page.header.cell_count -= 1;
+            page.update_content_start_offset();
+            page.header.right_most_page = Some(left_child(&cell));
+            cell
+        };
+
+        let page_number = self.write(page)?;
+        Ok(with_left_child(separator, page_number))
+    }
+
+    /// Writes a page to the file, in the spare page or in a new one.
+    fn write(&mut self, mut page: BTreePage) -> io::Result<u32> {
+        let pager = self.btree.pager();
+        let page_number = match self.spare.take() {
+            Some(page_number) => page_number,
+            None => pager.create_btree_page(page.header.page_type, page.header.right_most_page)?,
+        };
+        page.page_number = page_number;
+        pager.get_page_mut_callback(page_number, None, |cached| {
+            *cached = Page::BTree(page);
+            Ok(())
+        })?;
+        Ok(page_number)
+    }
+
+    /// Creates an empty page in memory for a level. The page number is set when it is written.
+    fn new_page(&self, level: usize) -> BTreePage {
+        let (leaf_type, interior_type) = match self.btree.tree_type() {
+            TreeType::Table => (PageType::TableLeaf, PageType::TableInterior),
+            TreeType::Index => (PageType::IndexLeaf, PageType::IndexInterior),
+        };
+        let (page_type, right_most_page) = if level == 0 {
+            (leaf_type, None)
+        } else {
+            (interior_type, Some(0))
+        };
+        BTreePage::new(
+            page_type,
+            self.btree.page_size(),
+            0,
+            self.btree.reserved_space(),
+            right_most_page,
+        )
+        .expect("The page type matches the right most page")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::pager::Pager;
+    use crate::utils::cmp::KeyValue;
+    use std::sync::Arc;
+    use tempfile::{tempdir, TempDir};
+
+    fn create_tree(tree_type: TreeType) -> (TempDir, BTree) {
+        let dir = tempdir().unwrap();
+        let pager = Pager::create(dir.path().join("test.db"), 4096, None, 0).unwrap();
+        let btree = BTree::create(tree_type, Arc::new(pager), 4096, 0, 255, 32).unwrap();
+        (dir, btree)
+    }
+
+    fn row(rowid: i64) -> (i64, Record) {
+        let text = format!("row {}", rowid).repeat(1 + (rowid % 7) as usize);
+        let record =
+            Record::with_values(vec![SqliteValue::Integer(rowid), SqliteValue::String(text)]);
+        (rowid, record)
+    }
+
+    fn index_key(value: i64) -> Vec<u8> {
+        let mut key = Vec::new();
+        serialize_values(&[SqliteValue::Integer(value)], &mut key).unwrap();
+        key
+    }
+
+    /// The rowids 1 to n in a fixed, scrambled order.
+    fn shuffled(n: i64) -> Vec<i64> {
+        let mut rowids = (1..=n).collect::<Vec<_>>();
+        let mut seed = 42u64;
+        for i in (1..rowids.len()).rev() {
+            seed = seed
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            rowids.swap(i, (seed >> 33) as usize % (i + 1));
+        }
+        rowids
+    }
+
+    fn root_type(btree: &BTree) -> PageType {
+        btree
+            .pager()
+            .get_page_callback(btree.root_page(), None, |page| page.page_type())
+            .unwrap()
+    }
+
+    fn check_rows(btree: &BTree, n: i64) {
+        for rowid in 1..=n {
+            let record = btree.find(rowid).unwrap().expect("row not found");
+            assert_eq!(record.to_bytes().unwrap(), row(rowid).1.to_bytes().unwrap());
+        }
+        assert!(btree.find(0).unwrap().is_none());
+        assert!(btree.find(n + 1).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_load_sorted_table() {
+        let (_dir, mut btree) = create_tree(TreeType::Table);
+        let root_page = btree.root_page();
+        BulkLoader::new(&mut btree)
+            .fill_factor(100)
+            .load_table((1..=3000).map(row))
+            .unwrap();
+        assert_ne!(btree.root_page(), root_page);
+        assert_eq!(root_type(&btree), PageType::TableInterior);
+        check_rows(&btree, 3000);
+        let packed = btree.pager().page_count().unwrap();
+
+        // The same rows inserted one by one, in any order, take more pages
+        let (_dir, mut inserted) = create_tree(TreeType::Table);
+        for (rowid, record) in shuffled(3000).into_iter().map(row) {
+            inserted.insert(rowid, &record).unwrap();
+        }
+        assert!(packed < inserted.pager().page_count().unwrap());
+
+        // The tree keeps working as usual afterwards
+        btree.insert(3001, &row(3001).1).unwrap();
+        assert!(btree.delete(1500).unwrap());
+        assert!(btree.find(1500).unwrap().is_none());
+        assert!(btree.find(3001).unwrap().is_some());
+    }
+
+    #[test]
+    fn test_load_unsorted_table() {
+        let (_dir, mut btree) = create_tree(TreeType::Table);
+
+        // A small budget so the sort spills a few runs
+        BulkLoader::new(&mut btree)
+            .sort_memory(16 << 10)
+            .load_table(shuffled(2000).into_iter().map(row))
+            .unwrap();
+        check_rows(&btree, 2000);
+    }
+
+    #[test]
+    fn test_load_with_stragglers() {
+        let (_dir, mut btree) = create_tree(TreeType::Table);
+
+        // Sorted for longer than the budget, then a few rows that belong earlier in the tree
+        let rowids = (1..=1500)
+            .filter(|rowid| rowid % 100 != 0)
+            .chain((1..=15).map(|i| i * 100).rev());
+        BulkLoader::new(&mut btree)
+            .sort_memory(4 << 10)
+            .load_table(rowids.map(row))
+            .unwrap();
+        check_rows(&btree, 1500);
+    }
+
+    #[test]
+    fn test_load_errors() {
+        // Duplicate rowids, sorted or not
+        let (_dir, mut btree) = create_tree(TreeType::Table);
+        let rows = vec![row(1), row(2), row(2)];
+        assert!(BulkLoader::new(&mut btree).load_table(rows).is_err());
+        let (_dir, mut btree) = create_tree(TreeType::Table);
+        let rows = vec![row(3), row(1), row(3)];
+        assert!(BulkLoader::new(&mut btree).load_table(rows).is_err());
+
+        // A tree that already has rows, or the wrong kind of tree
+        let (_dir, mut btree) = create_tree(TreeType::Table);
+        btree.insert(1, &row(1).1).unwrap();
+        assert!(BulkLoader::new(&mut btree)
+            .load_table(vec![row(2)])
+            .is_err());
+        assert!(BulkLoader::new(&mut btree)
+            .load_index(vec![(index_key(1), 1)])
+            .is_err());
+
+        // Nothing to load leaves the tree empty
+        let (_dir, mut btree) = create_tree(TreeType::Table);
+        BulkLoader::new(&mut btree).load_table(Vec::new()).unwrap();
+        assert!(btree.is_empty().unwrap());
+    }
+
+    #[test]
+    fn test_load_large_rows() {
+        let (_dir, mut btree) = create_tree(TreeType::Table);
+        let rows = (1..=50).map(|rowid| {
+            let record = Record::with_values(vec![SqliteValue::Blob(vec![rowid as u8; 10_000])]);
+            (rowid, record)
+        });
+        BulkLoader::new(&mut btree).load_table(rows).unwrap();
+        for rowid in 1..=50 {
+            let record = btree.find(rowid).unwrap().unwrap();
+            match record.get_value(0) {
+                Some(SqliteValue::Blob(blob)) => assert_eq!(blob, &vec![rowid as u8; 10_000]),
+                other => panic!("Unexpected value {:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn test_load_index() {
+        for sorted in [true, false] {
+            let (_dir, mut btree) = create_tree(TreeType::Index);
+            let values = if sorted {
+                (1..=3000).collect()
+            } else {
+                shuffled(3000)
+            };
+            let entries = values
+                .into_iter()
+                .map(|value| (index_key(value * 3), value));
+            BulkLoader::new(&mut btree)
+                .sort_memory(32 << 10)
+                .load_index(entries)
+                .unwrap();
+            assert_eq!(root_type(&btree), PageType::IndexInterior);
+
+            for value in 1..=3000 {
+                let (found, _, _) = btree.find_index_key(&KeyValue::Integer(value * 3)).unwrap();
+                assert!(found, "key {} not found", value * 3);
+            }
+            let (found, _, _) = btree.find_index_key(&KeyValue::Integer(4)).unwrap();
+            assert!(!found);
+        }
+    }
+
+    #[test]
+    fn test_load_index_text_keys() {
+        let (_dir, mut btree) = create_tree(TreeType::Index);
+        let entries = shuffled(1000).into_iter().map(|value| {
+            let mut key = Vec::new();
+            serialize_values(
+                &[SqliteValue::String(format!("key-{:05}", value))],
+                &mut key,
+            )
+            .unwrap();
+            (key, value)
+        });
+        BulkLoader::new(&mut btree).load_index(entries).unwrap();
+        for value in 1..=1000 {
+            let key = KeyValue::String(format!("key-{:05}", value));
+            assert!(btree.find_index_key(&key).unwrap().0);
+        }
+    }
+}
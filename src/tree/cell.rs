@@ -76,33 +76,12 @@ impl BTreeCellFactory {
         let payload_size = payload.len();
 
         // Determine how much of the payload is stored locally
-        // (similar to index leaf cell)
-        // If the payload is small enough, store it all locally.
-        // Otherwise, calculate the minimum local payload size (M) and store part of it locally and part in overflow.
-        let local_payload_size = if payload_size <= max_local_payload {
-            // Store all locally
-            payload_size
-        } else {
-            // Calculate M (minimum local payload size)
-            // Formula for M: (usable_size - 12) * X / 255
-            // where X is the minimum payload fraction.
-            // In sqlite, X is 32 (12.5%).
-            // This means that the minimum local payload size is 12.5% of the usable size.
-            // This way, if the data is too large, just keep the minimum local payload size and store the rest in overflow.
-            let m = min_local_payload.min((usable_size - 35) / 4);
-
-            if payload_size <= m {
-                // This is redundant as we have already checked but I am starting to become a bit paranoic
-                // with all this database programming stuff
-                // Store all locally
-                payload_size
-            } else {
-                // Store part locally and part in overflow
-                // Formula: M + ((payload_size - M) % (usable_size - 4))
-
-                m + ((payload_size - m) % (usable_size - 4))
-            }
-        };
+        let local_payload_size = Self::local_payload_size(
+            payload_size,
+            max_local_payload,
+            min_local_payload,
+            usable_size,
+        );
 
         // Part of the payload that is stored locally
         let local_payload = payload[0..local_payload_size].to_vec();
@@ -163,18 +142,12 @@ impl BTreeCellFactory {
         let payload_size = payload.len();
 
         // Determine how much of the payload is stored locally
-        let local_payload_size = if payload_size <= max_local_payload {
-            payload_size
-        } else {
-            // Calculate M again
-            let m = min_local_payload.min((usable_size - 35) / 4);
-
-            if payload_size <= m {
-                payload_size
-            } else {
-                m + ((payload_size - m) % (usable_size - 4))
-            }
-        };
+        let local_payload_size = Self::local_payload_size(
+            payload_size,
+            max_local_payload,
+            min_local_payload,
+            usable_size,
+        );
 
         // Part of the payload that is stored locally
         let local_payload = payload[0..local_payload_size].to_vec();
@@ -222,19 +195,12 @@ impl BTreeCellFactory {
         let payload_size = payload.len();
 
         // Determine how much of the payload is stored locally
-        // If the payload is small enough, store it all locally.
-        let local_payload_size = if payload_size <= max_local_payload {
-            payload_size
-        } else {
-            // Calculate M (minimum local payload size)
-            let m = min_local_payload.min((usable_size - 35) / 4);
-
-            if payload_size <= m {
-                payload_size
-            } else {
-                m + ((payload_size - m) % (usable_size - 4))
-            }
-        };
+        let local_payload_size = Self::local_payload_size(
+            payload_size,
+            max_local_payload,
+            min_local_payload,
+            usable_size,
+        );
 
         // Part of the payload that is stored locally
         // (similar to index leaf cell)
@@ -258,6 +224,52 @@ impl BTreeCellFactory {
         Ok((cell, overflow_payload))
     }
 
+    /// Calculates how many bytes of a payload its cell keeps in the page. The rest goes to an overflow chain.
+    ///
+    /// Reading a page must split the payloads the same way (see `PayloadLimits`), since the pointer to the chain
+    /// comes right after the local bytes.
+    ///
+    /// # Parameters
+    /// * `payload_size` - Size of the whole payload.
+    /// * `max_local_payload` - Maximum size of payload that can be stored locally.
+    /// * `min_local_payload` - Minimum size of payload that must be stored locally.
+    /// * `usable_size` - Usable size of the page (excluding reserved space).
+    ///
+    /// # Returns
+    /// Number of bytes of the payload stored in the cell.
+    pub fn local_payload_size(
+        payload_size: usize,
+        max_local_payload: usize,
+        min_local_payload: usize,
+        usable_size: usize,
+    ) -> usize {
+        // If the payload is small enough, store it all locally
+        if payload_size <= max_local_payload {
+            return payload_size;
+        }
+
+        // Calculate M (minimum local payload size)
+        // Formula for M: (usable_size - 12) * X / 255
+        // where X is the minimum payload fraction.
+        // In sqlite, X is 32 (12.5%).
+        // This means that the minimum local payload size is 12.5% of the usable size.
+        // This way, if the data is too large, just keep the minimum local payload size and store the rest in overflow.
+        let m = min_local_payload.min((usable_size - 35) / 4);
+        if payload_size <= m {
+            return payload_size;
+        }
+
+        // Store part locally and part in overflow, so the overflow pages are full
+        // Formula: M + ((payload_size - M) % (usable_size - 4))
+        // As in SQLite, that is only kept when it is not over the maximum, or the cell would not fit in a page
+        let surplus = m + ((payload_size - m) % (usable_size - 4));
+        if surplus <= max_local_payload {
+            surplus
+        } else {
+            m
+        }
+    }
+
     /// Calculates the maximum size of payload that can be stored locally.
     ///
     /// # Parameters
@@ -290,6 +302,59 @@ impl BTreeCellFactory {
     }
 }
 
+/// Default maximum payload fraction: a payload can fill a whole page, less the cell overhead (see `RQLiteConfig`).
+pub const DEFAULT_MAX_PAYLOAD_FRACTION: u8 = 255;
+
+/// Default minimum payload fraction, about 12.5% of the page (see `RQLiteConfig`).
+pub const DEFAULT_MIN_PAYLOAD_FRACTION: u8 = 32;
+
+/// Limits that decide how the payload of a cell is split between its page and an overflow chain.
+///
+/// A page must be read with the limits it was written with: the cells only record the size of the whole payload,
+/// so these are what say where the local bytes end and the pointer to the chain starts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PayloadLimits {
+    /// Usable size of the page (excluding reserved space).
+    pub usable_size: usize,
+    /// Maximum size of payload that can be stored locally.
+    pub max_local_payload: usize,
+    /// Minimum size of payload that must be stored locally.
+    pub min_local_payload: usize,
+}
+
+impl PayloadLimits {
+    /// Works out the limits of the pages of a tree.
+    ///
+    /// # Parameters
+    /// * `usable_size` - Usable size of the page (excluding reserved space).
+    /// * `max_payload_fraction` - Maximum fraction of a page that can be occupied by a payload.
+    /// * `min_payload_fraction` - Minimum fraction of a page that must be occupied by a payload.
+    pub fn new(usable_size: usize, max_payload_fraction: u8, min_payload_fraction: u8) -> Self {
+        PayloadLimits {
+            usable_size,
+            max_local_payload: BTreeCellFactory::max_local_payload(
+                usable_size,
+                max_payload_fraction,
+            ),
+            min_local_payload: BTreeCellFactory::min_local_payload(
+                usable_size,
+                min_payload_fraction,
+            ),
+        }
+    }
+
+    /// Calculates how many bytes of a payload of `payload_size` bytes are stored in its cell
+    /// (see `BTreeCellFactory::local_payload_size`).
+    pub fn local_size(&self, payload_size: usize) -> usize {
+        BTreeCellFactory::local_payload_size(
+            payload_size,
+            self.max_local_payload,
+            self.min_local_payload,
+            self.usable_size,
+        )
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -559,4 +624,18 @@ mod tests {
         let expected = (usable_size - 12) * 255 / 255;
         assert_eq!(min_local_100_percent, expected);
     }
+
+    #[test]
+    fn test_local_payload_size() {
+        // 512-byte pages: at most 477 bytes in the page, and M is 62
+        let limits = PayloadLimits::new(512, 255, 32);
+        assert_eq!(limits.local_size(477), 477);
+
+        // The overflow pages are full when the rest fits in the page...
+        assert_eq!(limits.local_size(670), 62 + (670 - 62) % 508);
+
+        // ...but M alone stays in the page when it would not
+        assert_eq!(62 + (1000 - 62) % 508, 492);
+        assert_eq!(limits.local_size(1000), 62);
+    }
 }
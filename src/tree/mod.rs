@@ -3,13 +3,23 @@
 //! This module implements a B-Tree data structure, which is used for indexing and storing
 //! data in a way that allows for efficient searching, insertion, and deletion operations.
 
+mod balance;
 pub mod btree;
+pub mod bulk;
 pub mod cell;
 pub mod node;
 pub mod record;
+pub mod scan;
+pub(crate) mod sequence;
+pub mod sorter;
+pub mod stats;
 
 // Re-export the necessary components for external use
 pub use btree::{BTree, TreeType};
+pub use bulk::BulkLoader;
 pub use cell::BTreeCellFactory;
 pub use node::BTreeNode;
 pub use record::Record;
+pub use scan::{IndexScan, TableScan};
+pub use sorter::{ExternalSorter, SortOrder};
+pub use stats::{KeySample, TreeStats};
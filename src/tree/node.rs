@@ -9,6 +9,7 @@
 //! The BtreeNode API provides also some callback functions to access pages and cells in a safe way, ensuring that the page type is correct before performing operations.
 //!
 //! The most important methods (those that modify the B-Tree structure) are `insert_cell_ordered` and `split`.
+//! The BTree itself does not split nodes one at a time anymore though, it balances them with their siblings (see the balance module).
 //!
 //! The search methods which implement a basic intra-node binary search are `find_index_key`, `find_table_rowid`, and `find_table_key`.
 //!
@@ -315,7 +316,7 @@ impl BTreeNode {
 
                     // Binary search
                     let mut left = 0;
-                    let mut right = cell_count.saturating_sub(1) as i32;
+                    let mut right = cell_count as i32 - 1;
 
                     while left <= right {
                         let mid = left + (right - left) / 2;
@@ -399,7 +400,7 @@ impl BTreeNode {
 
                     // Binary search
                     let mut left = 0;
-                    let mut right = cell_count.saturating_sub(1) as i32;
+                    let mut right = cell_count as i32 - 1;
 
                     while left <= right {
                         let mid = left + (right - left) / 2;
@@ -464,7 +465,7 @@ impl BTreeNode {
 
                     // Binary search
                     let mut left = 0;
-                    let mut right = cell_count.saturating_sub(1) as i32;
+                    let mut right = cell_count as i32 - 1;
 
                     while left <= right {
                         let mid = left + (right - left) / 2;
@@ -704,41 +705,9 @@ impl BTreeNode {
         let position = self.find_position_for_cell(&cell, pager)?;
 
         // Insert the cell at the calculated position
-        pager.get_page_mut_callback(self.page_number, Some(self.node_type), |page| {
-            match page {
-                Page::BTree(btree_page) => {
-                    // Calculate cell offset
-                    let offset = if btree_page.header.cell_count > 0
-                        && position < btree_page.header.cell_count
-                    {
-                        let existing_offset = btree_page.cell_indices[position as usize];
-                        existing_offset - cell_size as u16
-                    } else {
-                        btree_page.header.content_start_offset - cell_size as u16
-                    };
-
-                    // Insert the cell and its index
-                    if position < btree_page.header.cell_count {
-                        btree_page.cells.insert(position as usize, cell);
-                        btree_page.cell_indices.insert(position as usize, offset);
-                    } else {
-                        btree_page.cells.push(cell);
-                        btree_page.cell_indices.push(offset);
-                    }
-
-                    // Update page metadata
-                    btree_page.header.cell_count += 1;
-                    btree_page.header.content_start_offset = btree_page
-                        .cell_indices
-                        .iter()
-                        .min()
-                        .copied()
-                        .unwrap_or(btree_page.header.content_start_offset);
-
-                    Ok(())
-                }
-                _ => unreachable!("Page type already validated"),
-            }
+        pager.get_page_mut_callback(self.page_number, Some(self.node_type), |page| match page {
+            Page::BTree(btree_page) => btree_page.insert_cell(position, cell),
+            _ => unreachable!("Page type already validated"),
         })?;
 
         Ok((false, None, None))
@@ -826,7 +795,7 @@ impl BTreeNode {
                 // println!("Checking if table cell with row_id {} should go in new node with median key {}", table_cell.row_id, median_key);
                 // println!("Comparing {} >= {}", table_cell.row_id, median_key);
 
-                Ok(table_cell.row_id >= median_key)
+                Ok(table_cell.row_id > median_key)
             }
             (PageType::TableInterior, BTreeCell::TableInterior(table_cell)) => {
                 Ok(table_cell.key >= median_key)
@@ -1093,8 +1062,9 @@ impl BTreeNode {
                                 }
                             };
 
-                            // Collect cells to move
-                            for i in (split_point as usize)..btree_page.cells.len() {
+                            // Collect cells to move. The median stays on the left, as the
+                            // separator routes equal keys to the left child.
+                            for i in (split_point as usize + 1)..btree_page.cells.len() {
                                 cells_to_move.push(btree_page.cells[i].clone());
                                 // println!("Moving cell from index {}: {:?}", i, btree_page.cells[i]);
                             }
@@ -1143,7 +1113,14 @@ impl BTreeNode {
                         format!("Cell index {} out of bounds", index),
                     ));
                 }
-                Ok(f(&mut btree_page.cells[index as usize]))
+                let old_size = btree_page.cells[index as usize].size();
+                let result = f(&mut btree_page.cells[index as usize]);
+                // A varint key can change length, so repack rather than let the
+                // cell spill over its neighbour.
+                if btree_page.cells[index as usize].size() != old_size {
+                    btree_page.update_content_start_offset();
+                }
+                Ok(result)
             }
             _ => unreachable!("Page type already validated"),
         })?
@@ -1162,21 +1139,7 @@ impl BTreeNode {
     /// The removed cell
     pub fn remove_cell(&self, index: u16, pager: &Pager) -> io::Result<BTreeCell> {
         pager.get_page_mut_callback(self.page_number, Some(self.node_type), |page| match page {
-            Page::BTree(btree_page) => {
-                if index >= btree_page.header.cell_count {
-                    return Err(io::Error::new(
-                        io::ErrorKind::InvalidInput,
-                        format!("Cell index {} out of bounds", index),
-                    ));
-                }
-
-                let removed_cell = btree_page.cells.remove(index as usize);
-                btree_page.cell_indices.remove(index as usize);
-                btree_page.header.cell_count -= 1;
-                btree_page.update_content_start_offset();
-
-                Ok(removed_cell)
-            }
+            Page::BTree(btree_page) => btree_page.remove_cell(index),
             _ => unreachable!("Page type already validated"),
         })
     }
@@ -1803,13 +1766,13 @@ mod tests {
             (SqliteValue::Integer(20), "twenty"),
             (SqliteValue::String("apple".to_string()), "fruit1"),
             (SqliteValue::String("banana".to_string()), "fruit2"),
-            (SqliteValue::Float(3.14), "pi"),
+            (SqliteValue::Float(std::f64::consts::PI), "pi"),
         ];
 
         // Insert all test data
         for (key_value, _description) in &test_data {
             let mut payload = Vec::new();
-            crate::utils::serialization::serialize_values(&[key_value.clone()], &mut payload)
+            crate::utils::serialization::serialize_values(std::slice::from_ref(key_value), &mut payload)
                 .unwrap();
 
             let cell = BTreeCell::IndexLeaf(IndexLeafCell {
@@ -1929,7 +1892,7 @@ mod tests {
                 // Total cells should be preserved
                 let total_cells =
                     node.cell_count(&pager).unwrap() + new_node.cell_count(&pager).unwrap();
-                assert_eq!(total_cells, (i + 1) as u16);
+                assert_eq!(total_cells, i as u16);
 
                 // println!("Split occurred with {} cells, median: {}", (i+1), median);
                 break;
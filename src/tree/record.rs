@@ -5,16 +5,24 @@
 //! You can call records "rows" in SQLite terminology.
 //! Other database systems may call them "tuples", comming from the mathematical concept of Relational Algebra.
 //! I just call them "records" to avoid confusion with the Rust `tuple` type.
+//!
+//! Decoding a `Record` decodes all of its values, and copies every text and blob out of the payload.
+//! When only a couple of columns are needed, `RecordRef` is the cheaper way: it is a view over the payload
+//! that finds where each value starts in a single pass over the type tags, and decodes a value only
+//! when it is asked for, borrowing texts and blobs from the payload instead of copying them.
+//! It also works on a payload cut short, like the local part of a cell whose end is in overflow pages:
+//! the values that are entirely there can be read, which is what `BTree::find_columns` relies on.
 //! 
 use std::io::{self, Cursor, Read, Write};
 
-use crate::utils::serialization::{deserialize_values, serialize_values, SqliteValue};
+use crate::utils::serialization::{deserialize_values, serialize_values, SqliteType, SqliteValue};
+use crate::utils::varint::decode_varint;
 
 /// Represents a record in a SQLite database table.
 ///
 /// A record is a collection of values, each corresponding to a column in the table.
 // The `Record` struct is used internally by the SQLite engine to manage data storage and retrieval.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct Record {
     /// Values of the columns in the record.
     /// Each value corresponds to a column in the table.
@@ -189,6 +197,248 @@ impl Default for Record {
     }
 }
 
+/// A value of a `RecordRef`. Texts and blobs borrow from the payload.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ValueRef<'a> {
+    /// NULL
+    Null,
+    /// Signed integer
+    Integer(i64),
+    /// Floating point number
+    Float(f64),
+    /// UTF-8 encoded string
+    Text(&'a str),
+    /// Binary data
+    Blob(&'a [u8]),
+}
+
+impl ValueRef<'_> {
+    /// Copies the value into an owned `SqliteValue`.
+    pub fn to_value(&self) -> SqliteValue {
+        match *self {
+            ValueRef::Null => SqliteValue::Null,
+            ValueRef::Integer(i) => SqliteValue::Integer(i),
+            ValueRef::Float(f) => SqliteValue::Float(f),
+            ValueRef::Text(text) => SqliteValue::String(text.to_string()),
+            ValueRef::Blob(data) => SqliteValue::Blob(data.to_vec()),
+        }
+    }
+}
+
+/// A read-only view over a serialized record that decodes its values on demand.
+///
+/// # Example
+/// ```rust
+/// # fn main() -> std::io::Result<()> {
+/// use rqlite_engine::tree::record::{Record, RecordRef, ValueRef};
+/// use rqlite_engine::SqliteValue;
+///
+/// let record = Record::with_values(vec![
+///     SqliteValue::Integer(7),
+///     SqliteValue::String("seven".to_string()),
+/// ]);
+/// let payload = record.to_bytes()?;
+/// let view = RecordRef::new(&payload)?;
+/// assert_eq!(view.get(1)?, Some(ValueRef::Text("seven")));
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct RecordRef<'a> {
+    /// The payload, or the start of it.
+    data: &'a [u8],
+    /// Number of values of the record.
+    count: usize,
+    /// Where each value starts and ends in `data`, for the values that are entirely in it.
+    spans: Vec<(usize, usize)>,
+}
+
+impl<'a> RecordRef<'a> {
+    /// Creates a view over a serialized record, finding where its values are.
+    ///
+    /// # Parameters
+    /// * `data` - The serialized record. It can be cut short: only the values entirely in it can be read.
+    ///
+    /// # Errors
+    /// Returns an error if the number of values cannot be read or a type tag is not valid.
+    pub fn new(data: &'a [u8]) -> io::Result<Self> {
+        let mut reader = data;
+        let (count, mut position) = decode_varint(&mut reader)?;
+        if count < 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Negative count for values",
+            ));
+        }
+        let count = count as usize;
+
+        let mut spans = Vec::with_capacity(count.min(data.len()));
+        while spans.len() < count && position < data.len() {
+            let Some(size) = value_size(&data[position..])? else {
+                break;
+            };
+            if position + size > data.len() {
+                break;
+            }
+            spans.push((position, position + size));
+            position += size;
+        }
+
+        Ok(RecordRef { data, count, spans })
+    }
+
+    /// Gets the number of values of the record, including the ones not in the view.
+    pub fn len(&self) -> usize {
+        self.count
+    }
+
+    /// Checks if the record has no values.
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    /// Checks if a value is entirely in the view, so `get` can decode it.
+    pub fn is_local(&self, index: usize) -> bool {
+        index < self.spans.len()
+    }
+
+    /// Decodes a value.
+    ///
+    /// # Parameters
+    /// * `index` - Index of the value (starting from 0).
+    ///
+    /// # Errors
+    /// Returns an `UnexpectedEof` error if the value is past the end of the view,
+    /// or an `InvalidData` error if it is not valid.
+    ///
+    /// # Returns
+    /// The value, or `None` if the record has fewer values.
+    pub fn get(&self, index: usize) -> io::Result<Option<ValueRef<'a>>> {
+        if index >= self.count {
+            return Ok(None);
+        }
+        let Some(&(start, end)) = self.spans.get(index) else {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!("Value {} is past the end of the payload", index),
+            ));
+        };
+
+        let body = &self.data[start + 1..end];
+        let value = match SqliteType::from(self.data[start]) {
+            SqliteType::Null => ValueRef::Null,
+            SqliteType::Integer0 => ValueRef::Integer(0),
+            SqliteType::Integer1 => ValueRef::Integer(1),
+            SqliteType::Integer8
+            | SqliteType::Integer16
+            | SqliteType::Integer24
+            | SqliteType::Integer32
+            | SqliteType::Integer48
+            | SqliteType::Integer64 => {
+                // Big-endian two's complement, sign extended from the first byte
+                let sign = if body[0] & 0x80 != 0 { -1 } else { 0 };
+                ValueRef::Integer(
+                    body.iter()
+                        .fold(sign, |value: i64, byte| (value << 8) | *byte as i64),
+                )
+            }
+            SqliteType::Float64 => {
+                let mut bytes = [0u8; 8];
+                bytes.copy_from_slice(body);
+                ValueRef::Float(f64::from_be_bytes(bytes))
+            }
+            SqliteType::Blob => ValueRef::Blob(skip_length(body)?),
+            SqliteType::String => {
+                let text = std::str::from_utf8(skip_length(body)?).map_err(|_| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "Invalid UTF-8 sequence in STRING",
+                    )
+                })?;
+                ValueRef::Text(text)
+            }
+            SqliteType::Reserved10 | SqliteType::Reserved11 => {
+                unreachable!("Rejected by value_size")
+            }
+        };
+        Ok(Some(value))
+    }
+
+    /// Decodes some of the values into a record, in the order they are asked for.
+    /// Values past the end of the record take their default, the way SQLite reads columns missing from
+    /// records written before an `ALTER TABLE ADD COLUMN`.
+    ///
+    /// # Parameters
+    /// * `columns` - Indexes of the values to decode.
+    /// * `defaults` - Values of the columns missing from the record, by index. Columns past its end are NULL.
+    ///
+    /// # Errors
+    /// Returns an error if one of the values is not in the view or is not valid.
+    pub fn project(&self, columns: &[usize], defaults: &[SqliteValue]) -> io::Result<Record> {
+        let mut values = Vec::with_capacity(columns.len());
+        for &column in columns {
+            let value = match self.get(column)? {
+                Some(value) => value.to_value(),
+                None => defaults.get(column).cloned().unwrap_or(SqliteValue::Null),
+            };
+            values.push(value);
+        }
+        Ok(Record::with_values(values))
+    }
+
+    /// Decodes all the values into a record.
+    ///
+    /// # Errors
+    /// Returns an error if a value is not in the view or is not valid.
+    pub fn to_record(&self) -> io::Result<Record> {
+        let columns: Vec<usize> = (0..self.count).collect();
+        self.project(&columns, &[])
+    }
+}
+
+/// Gets the size of the value at the start of `data`, type tag included (see `SqliteValue::serialize`).
+/// Returns `None` if `data` ends before the length of a text or a blob.
+fn value_size(data: &[u8]) -> io::Result<Option<usize>> {
+    let size = match SqliteType::from(data[0]) {
+        SqliteType::Null | SqliteType::Integer0 | SqliteType::Integer1 => 1,
+        SqliteType::Integer8 => 2,
+        SqliteType::Integer16 => 3,
+        SqliteType::Integer24 => 4,
+        SqliteType::Integer32 => 5,
+        SqliteType::Integer48 => 7,
+        SqliteType::Integer64 | SqliteType::Float64 => 9,
+        SqliteType::Blob | SqliteType::String => {
+            let mut reader = &data[1..];
+            let (length, length_size) = match decode_varint(&mut reader) {
+                Ok(length) => length,
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+                Err(e) => return Err(e),
+            };
+            if length < 0 {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "Value length cannot be negative",
+                ));
+            }
+            1 + length_size + length as usize
+        }
+        SqliteType::Reserved10 | SqliteType::Reserved11 => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Reserved type tag {}", data[0]),
+            ))
+        }
+    };
+    Ok(Some(size))
+}
+
+/// Skips the length in front of a text or a blob.
+fn skip_length(body: &[u8]) -> io::Result<&[u8]> {
+    let mut reader = body;
+    let (_, length_size) = decode_varint(&mut reader)?;
+    Ok(&body[length_size..])
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -381,4 +631,92 @@ mod tests {
         let record = Record::default();
         assert!(record.is_empty());
     }
+
+    fn sample_record() -> Record {
+        Record::with_values(vec![
+            SqliteValue::Integer(-300),
+            SqliteValue::Null,
+            SqliteValue::String("hello".to_string()),
+            SqliteValue::Float(2.5),
+            SqliteValue::Blob(vec![9; 100]),
+            SqliteValue::Integer(i64::MIN),
+            SqliteValue::Integer(1),
+        ])
+    }
+
+    #[test]
+    fn test_record_ref_get() {
+        let record = sample_record();
+        let payload = record.to_bytes().unwrap();
+        let view = RecordRef::new(&payload).unwrap();
+
+        assert_eq!(view.len(), 7);
+        assert_eq!(view.get(0).unwrap(), Some(ValueRef::Integer(-300)));
+        assert_eq!(view.get(1).unwrap(), Some(ValueRef::Null));
+        assert_eq!(view.get(2).unwrap(), Some(ValueRef::Text("hello")));
+        assert_eq!(view.get(3).unwrap(), Some(ValueRef::Float(2.5)));
+        assert_eq!(view.get(4).unwrap(), Some(ValueRef::Blob(&[9; 100])));
+        assert_eq!(view.get(5).unwrap(), Some(ValueRef::Integer(i64::MIN)));
+        assert_eq!(view.get(6).unwrap(), Some(ValueRef::Integer(1)));
+        assert_eq!(view.get(7).unwrap(), None);
+        assert_eq!(view.to_record().unwrap(), record);
+
+        // The texts borrow from the payload
+        let Some(ValueRef::Text(text)) = view.get(2).unwrap() else {
+            panic!("Expected Text");
+        };
+        assert!(payload.as_ptr_range().contains(&text.as_ptr()));
+    }
+
+    #[test]
+    fn test_record_ref_project() {
+        let payload = sample_record().to_bytes().unwrap();
+        let view = RecordRef::new(&payload).unwrap();
+        let projected = view.project(&[3, 0, 9], &[]).unwrap();
+        assert_eq!(
+            projected.values,
+            vec![
+                SqliteValue::Float(2.5),
+                SqliteValue::Integer(-300),
+                SqliteValue::Null
+            ]
+        );
+
+        // Defaults only fill the columns past the end
+        let mut defaults = vec![SqliteValue::Integer(0); 9];
+        defaults.push(SqliteValue::String("added".to_string()));
+        let projected = view.project(&[9, 0], &defaults).unwrap();
+        assert_eq!(
+            projected.values,
+            vec![
+                SqliteValue::String("added".to_string()),
+                SqliteValue::Integer(-300)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_record_ref_cut_short() {
+        let payload = sample_record().to_bytes().unwrap();
+        // Cut in the middle of the blob
+        let view = RecordRef::new(&payload[..40]).unwrap();
+        assert_eq!(view.len(), 7);
+        assert!(view.is_local(3));
+        assert!(!view.is_local(4));
+        assert_eq!(view.get(2).unwrap(), Some(ValueRef::Text("hello")));
+        let err = view.get(5).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+        assert!(view.project(&[0, 4], &[]).is_err());
+    }
+
+    #[test]
+    fn test_record_ref_invalid_utf8() {
+        let mut payload = Record::with_values(vec![SqliteValue::String("ab".to_string())])
+            .to_bytes()
+            .unwrap();
+        let last = payload.len() - 1;
+        payload[last] = 0xFF;
+        let view = RecordRef::new(&payload).unwrap();
+        assert_eq!(view.get(0).unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
 }
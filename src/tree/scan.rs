@@ -0,0 +1,348 @@
+//! # Scan Module
+//!
+//! `BTree::find` gets one row by its rowid, but plenty of things need all of them: rewriting the rows of a
+//! table when one of its columns is dropped, or a query without a usable index. A `TableScan` goes through
+//! the rows of a table B-Tree in rowid order, one at a time.
+//!
+//! It keeps the path from the root to the current leaf, with the cells of every page on it, so moving to the
+//! next row is just taking the next cell, and going up and down the tree when a page runs out.
+//! The cells are copied out of the pages as they are visited, so only one page per level is in memory,
+//! and the tree is borrowed for the whole scan, so nobody can change it under our feet.
+//!
+//! An `IndexScan` does the same over the entries of an index B-Tree, in key order, and it can start
+//! at a key instead of the first entry: on the way down, the cells whose key is smaller are skipped.
+//! An interior key routes the keys equal to it to its left child (see the balance module), so the first
+//! child whose divider is not smaller than the key is the one to go down, and the same check on the leaf
+//! drops the entries before the key. After that every entry is at least the key, so the scan just goes on.
+
+use std::io;
+use std::vec;
+
+use crate::page::{BTreeCell, Page};
+use crate::tree::btree::{BTree, TreeType};
+use crate::tree::node::extract_key_from_payload;
+use crate::tree::record::Record;
+use crate::utils::cmp::KeyValue;
+use crate::utils::serialization::SqliteValue;
+
+/// Iterator over the rows of a table B-Tree, in rowid order. Created by `BTree::scan`.
+///
+/// Every item is the rowid and the record of a row, or the error that stopped the scan.
+pub struct TableScan<'a> {
+    btree: &'a BTree,
+    /// Pages from the root to the current leaf, with the cells left to visit and their right-most child.
+    path: Vec<ScanLevel>,
+    /// Whether the root page is still to be read.
+    started: bool,
+}
+
+/// Iterator over the entries of an index B-Tree, in key order. Created by `BTree::scan_index`.
+///
+/// Every item is the key of an entry (its first value, the one the tree is ordered by) and its rowid,
+/// or the error that stopped the scan.
+pub struct IndexScan<'a> {
+    btree: &'a BTree,
+    path: Vec<ScanLevel>,
+    started: bool,
+    /// Entries before this key are skipped.
+    start: Option<KeyValue>,
+}
+
+/// A page on the path of a scan.
+struct ScanLevel {
+    cells: std::iter::Peekable<vec::IntoIter<BTreeCell>>,
+    right_most: Option<u32>,
+}
+
+/// Reads the cells of a page of a scan.
+fn read_level(btree: &BTree, page_number: u32) -> io::Result<ScanLevel> {
+    btree
+        .pager()
+        .get_page_callback(page_number, None, |page| match page {
+            Page::BTree(btree_page) => Ok(ScanLevel {
+                cells: btree_page.cells.clone().into_iter().peekable(),
+                right_most: btree_page.header.right_most_page,
+            }),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Page {} is not a B-Tree page", page_number),
+            )),
+        })?
+}
+
+impl BTree {
+    /// Scans the rows of a table B-Tree in rowid order.
+    ///
+    /// # Errors
+    /// Returns an `InvalidInput` error if the tree is not a table tree.
+    ///
+    /// # Returns
+    /// An iterator over the rowids and records of the table.
+    pub fn scan(&self) -> io::Result<TableScan<'_>> {
+        if self.tree_type() != TreeType::Table {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Cannot scan the rows of an index tree",
+            ));
+        }
+        Ok(TableScan {
+            btree: self,
+            path: Vec::new(),
+            started: false,
+        })
+    }
+
+    /// Scans the entries of an index B-Tree in key order.
+    ///
+    /// # Parameters
+    /// * `start` - Key to start at: the entries with a smaller key are skipped. `None` starts at the first entry.
+    ///
+    /// # Errors
+    /// Returns an `InvalidInput` error if the tree is not an index tree.
+    ///
+    /// # Returns
+    /// An iterator over the keys and rowids of the index.
+    pub fn scan_index(&self, start: Option<KeyValue>) -> io::Result<IndexScan<'_>> {
+        if self.tree_type() != TreeType::Index {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Cannot scan the entries of a table tree",
+            ));
+        }
+        Ok(IndexScan {
+            btree: self,
+            path: Vec::new(),
+            started: false,
+            start,
+        })
+    }
+}
+
+impl TableScan<'_> {
+    /// Reads a page and puts it at the end of the path.
+    fn descend(&mut self, page_number: u32) -> io::Result<()> {
+        let level = read_level(self.btree, page_number)?;
+        self.path.push(level);
+        Ok(())
+    }
+
+    /// Moves to the next row.
+    fn advance(&mut self) -> io::Result<Option<(i64, Record)>> {
+        if !self.started {
+            self.started = true;
+            self.descend(self.btree.root_page())?;
+        }
+
+        while let Some(level) = self.path.last_mut() {
+            match level.cells.next() {
+                Some(BTreeCell::TableLeaf(cell)) => {
+                    let mut payload = cell.payload;
+                    if let Some(overflow_page) = cell.overflow_page {
+                        payload.extend_from_slice(&self.btree.read_overflow_chain(overflow_page)?);
+                    }
+                    let (record, _) = Record::from_bytes(&payload)?;
+                    return Ok(Some((cell.row_id, record)));
+                }
+                Some(BTreeCell::TableInterior(cell)) => self.descend(cell.left_child_page)?,
+                Some(_) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "Expected a table cell",
+                    ))
+                }
+                // The right-most child comes after the last cell, then the page is done
+                None => match level.right_most.take() {
+                    Some(page_number) => self.descend(page_number)?,
+                    None => {
+                        self.path.pop();
+                    }
+                },
+            }
+        }
+        Ok(None)
+    }
+}
+
+impl IndexScan<'_> {
+    /// Reads a page and puts it at the end of the path, skipping the cells before the start key.
+    fn descend(&mut self, page_number: u32) -> io::Result<()> {
+        let mut level = read_level(self.btree, page_number)?;
+        if let Some(start) = &self.start {
+            while let Some(cell) = level.cells.peek() {
+                let payload = match cell {
+                    BTreeCell::IndexLeaf(cell) => &cell.payload,
+                    BTreeCell::IndexInterior(cell) => &cell.payload,
+                    _ => break,
+                };
+                if extract_key_from_payload(payload)? >= *start {
+                    break;
+                }
+                level.cells.next();
+            }
+        }
+        self.path.push(level);
+        Ok(())
+    }
+
+    /// Moves to the next entry.
+    fn advance(&mut self) -> io::Result<Option<(KeyValue, i64)>> {
+        if !self.started {
+            self.started = true;
+            self.descend(self.btree.root_page())?;
+        }
+
+        while let Some(level) = self.path.last_mut() {
+            match level.cells.next() {
+                Some(BTreeCell::IndexLeaf(cell)) => {
+                    let mut payload = cell.payload;
+                    if let Some(overflow_page) = cell.overflow_page {
+                        payload.extend_from_slice(&self.btree.read_overflow_chain(overflow_page)?);
+                    }
+                    // The key record is followed by a record with the rowid (see `BTree::insert_index`)
+                    let key = extract_key_from_payload(&payload)?;
+                    let (_, key_size) = Record::from_bytes(&payload)?;
+                    let (rowid, _) = Record::from_bytes(&payload[key_size..])?;
+                    let Some(SqliteValue::Integer(rowid)) = rowid.get_value(0) else {
+                        return Err(io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            "Index entry without a rowid",
+                        ));
+                    };
+                    return Ok(Some((key, *rowid)));
+                }
+                Some(BTreeCell::IndexInterior(cell)) => self.descend(cell.left_child_page)?,
+                Some(_) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "Expected an index cell",
+                    ))
+                }
+                None => match level.right_most.take() {
+                    Some(page_number) => self.descend(page_number)?,
+                    None => {
+                        self.path.pop();
+                    }
+                },
+            }
+        }
+        Ok(None)
+    }
+}
+
+impl Iterator for IndexScan<'_> {
+    type Item = io::Result<(KeyValue, i64)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.advance() {
+            Ok(entry) => entry.map(Ok),
+            Err(e) => {
+                self.path.clear();
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+impl Iterator for TableScan<'_> {
+    type Item = io::Result<(i64, Record)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.advance() {
+            Ok(row) => row.map(Ok),
+            Err(e) => {
+                // A broken tree cannot be scanned any further
+                self.path.clear();
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::pager::Pager;
+    use crate::utils::serialization::serialize_values;
+    use std::sync::Arc;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_scan_in_rowid_order() {
+        let dir = tempdir().unwrap();
+        let pager = Pager::create(dir.path().join("scan_test.db"), 4096, None, 0).unwrap();
+        let mut btree = BTree::create(TreeType::Table, Arc::new(pager), 4096, 0, 255, 32).unwrap();
+        assert_eq!(btree.scan().unwrap().count(), 0);
+
+        // Enough rows for a few levels, inserted out of order, and one with overflow pages
+        for i in 0..2000i64 {
+            let rowid = (i * 7919) % 2000 + 1;
+            let record = Record::with_values(vec![SqliteValue::Integer(rowid)]);
+            btree.insert(rowid, &record).unwrap();
+        }
+        let large = Record::with_values(vec![SqliteValue::Blob(vec![7; 10_000])]);
+        btree.delete(1000).unwrap();
+        btree.insert(1000, &large).unwrap();
+
+        let rows: Vec<(i64, Record)> = btree.scan().unwrap().collect::<io::Result<_>>().unwrap();
+        assert_eq!(rows.len(), 2000);
+        for (i, (rowid, record)) in rows.iter().enumerate() {
+            assert_eq!(*rowid, i as i64 + 1);
+            if *rowid != 1000 {
+                assert_eq!(record.values, vec![SqliteValue::Integer(*rowid)]);
+            }
+        }
+        assert_eq!(rows[999].1, large);
+
+        let index =
+            BTree::create(TreeType::Index, Arc::clone(btree.pager()), 4096, 0, 255, 32).unwrap();
+        assert!(index.scan().is_err());
+        assert!(btree.scan_index(None).is_err());
+    }
+
+    #[test]
+    fn test_scan_index_from_key() {
+        let dir = tempdir().unwrap();
+        let pager = Pager::create(dir.path().join("scan_index_test.db"), 4096, None, 0).unwrap();
+        let mut index = BTree::create(TreeType::Index, Arc::new(pager), 4096, 0, 255, 32).unwrap();
+
+        // Even keys only, enough of them for interior pages
+        for i in 0..3000i64 {
+            let key = (i * 7919) % 3000 * 2;
+            let mut payload = Vec::new();
+            serialize_values(&[SqliteValue::Integer(key)], &mut payload).unwrap();
+            index.insert_index(&payload, key + 1).unwrap();
+        }
+
+        let all: Vec<(KeyValue, i64)> = index
+            .scan_index(None)
+            .unwrap()
+            .collect::<io::Result<_>>()
+            .unwrap();
+        assert_eq!(all.len(), 3000);
+        assert!(all.iter().enumerate().all(|(i, (key, rowid))| {
+            *key == KeyValue::Integer(i as i64 * 2) && *rowid == i as i64 * 2 + 1
+        }));
+
+        // Starting at a key, or between two keys
+        for start in [0, 1, 1234, 1235, 5998] {
+            let first = index
+                .scan_index(Some(KeyValue::Integer(start)))
+                .unwrap()
+                .next()
+                .unwrap()
+                .unwrap();
+            let expected = (start + 1) / 2 * 2;
+            assert_eq!(first, (KeyValue::Integer(expected), expected + 1));
+            let count = index
+                .scan_index(Some(KeyValue::Integer(start)))
+                .unwrap()
+                .count();
+            assert_eq!(count as i64, 3000 - expected / 2);
+        }
+        assert!(index
+            .scan_index(Some(KeyValue::Integer(6000)))
+            .unwrap()
+            .next()
+            .is_none());
+    }
+}
@@ -0,0 +1,271 @@
+//! # Sequence Module
+//!
+//! A rowid belongs to one table, so `BTree::insert` has no way to say that the orders, the invoices and the
+//! refunds all take their numbers from the same counter. Sequences are that counter: named, stored once for the
+//! whole database, and handing out `start`, `start + increment`, `start + 2 * increment`... to whoever asks,
+//! whatever table the value ends up in.
+//!
+//! They live in the sequence table, a table B-Tree of the catalog like the stat table, with one row per sequence:
+//! `[name, next value, increment, cache, start]`. The next value is the first one nobody has been given yet,
+//! or NULL once the sequence went past the largest (or smallest) i64.
+//!
+//! Writing that row for every value would make a sequence as slow as a commit, so a connection reserves `cache`
+//! values at once: the row moves past all of them in one write, and the connection hands them out from memory
+//! (see `SequenceRange`). The price is gaps: the values a connection reserved and did not use before closing are
+//! lost, and two connections hand out interleaved values. If the reservation was made in a transaction that
+//! rolls back, the connection forgets the range too, as another connection may reserve the same values again.
+//!
+//! With a cache of 0 there is no range, every value is a write of the row in the transaction of the caller.
+//! If that transaction rolls back, so does the sequence, and the next caller gets the same value:
+//! the values that end up committed have no gaps. It also means a connection waits for the transaction of another
+//! one to draw a value, the same as for any other write.
+
+use std::io;
+
+use crate::tree::btree::BTree;
+use crate::tree::record::Record;
+use crate::utils::serialization::SqliteValue;
+
+/// Values reserved by a connection, handed out from memory until they run out.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct SequenceRange {
+    /// The next value to hand out.
+    pub next: i64,
+    /// How many values are left, `next` included.
+    pub remaining: u64,
+    pub increment: i64,
+    /// Whether the range was reserved in the current transaction, and must be forgotten if it rolls back.
+    pub uncommitted: bool,
+}
+
+impl SequenceRange {
+    /// Takes the next value of the range, or `None` if it is used up.
+    pub fn take(&mut self) -> Option<i64> {
+        if self.remaining == 0 {
+            return None;
+        }
+        let value = self.next;
+        self.remaining -= 1;
+        if self.remaining > 0 {
+            // The reservation checked that the whole range fits in an i64
+            self.next += self.increment;
+        }
+        Some(value)
+    }
+}
+
+/// Adds a sequence to the sequence table.
+///
+/// # Parameters
+/// * `sequences` - The sequence table.
+/// * `name` - Name of the sequence, unique ignoring ASCII case.
+/// * `start` - First value of the sequence.
+/// * `increment` - Difference between two values, negative for a sequence that goes down.
+/// * `cache` - How many values a connection reserves at once, 0 for a gap-free sequence.
+///
+/// # Errors
+/// Returns an `InvalidInput` error if the name is empty or the increment is 0,
+/// an `AlreadyExists` error if there is already a sequence with that name, or an error if the table cannot be written.
+pub(crate) fn create_sequence(
+    sequences: &mut BTree,
+    name: &str,
+    start: i64,
+    increment: i64,
+    cache: u32,
+) -> io::Result<()> {
+    if name.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "A sequence name cannot be empty",
+        ));
+    }
+    if increment == 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("The increment of sequence {} cannot be 0", name),
+        ));
+    }
+    if find_sequence(sequences, name)?.is_some() {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("There is already a sequence named {}", name),
+        ));
+    }
+
+    let rowid = sequences.max_rowid()?.map_or(1, |rowid| rowid + 1);
+    let row = Record::with_values(vec![
+        SqliteValue::String(name.to_string()),
+        SqliteValue::Integer(start),
+        SqliteValue::Integer(increment),
+        SqliteValue::Integer(cache as i64),
+        SqliteValue::Integer(start),
+    ]);
+    sequences.insert(rowid, &row)
+}
+
+/// Removes a sequence from the sequence table.
+///
+/// # Errors
+/// Returns a `NotFound` error if there is no such sequence, or an error if the table cannot be written.
+pub(crate) fn drop_sequence(sequences: &mut BTree, name: &str) -> io::Result<()> {
+    let (rowid, _) = find_sequence(sequences, name)?.ok_or_else(|| no_such_sequence(name))?;
+    sequences.delete(rowid)?;
+    Ok(())
+}
+
+/// Reserves the next values of a sequence: as many as its cache, or a single one if it has none.
+///
+/// # Errors
+/// Returns a `NotFound` error if there is no such sequence, an `InvalidInput` error if it has no values left,
+/// or an error if the table cannot be read or written.
+///
+/// # Returns
+/// The reserved values, and whether the sequence is gap-free, in which case they must not be cached.
+pub(crate) fn reserve(sequences: &mut BTree, name: &str) -> io::Result<(SequenceRange, bool)> {
+    let (rowid, mut row) = find_sequence(sequences, name)?.ok_or_else(|| no_such_sequence(name))?;
+    let next = match row.get_value(1) {
+        Some(SqliteValue::Integer(next)) => *next,
+        Some(SqliteValue::Null) => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Sequence {} has no values left", name),
+            ))
+        }
+        _ => return Err(invalid_sequence_row()),
+    };
+    let increment = sequence_integer(&row, 2)?;
+    let cache = sequence_integer(&row, 3)?;
+
+    // How many values there are from `next` to the end of the i64 range, `next` included
+    let limit = if increment > 0 { i64::MAX } else { i64::MIN };
+    let available = (limit as i128 - next as i128) / increment as i128 + 1;
+    let count = (cache.max(1) as i128).min(available);
+    row.values[1] = match count < available {
+        true => SqliteValue::Integer((next as i128 + count * increment as i128) as i64),
+        false => SqliteValue::Null,
+    };
+    sequences.delete(rowid)?;
+    sequences.insert(rowid, &row)?;
+
+    let range = SequenceRange {
+        next,
+        remaining: count as u64,
+        increment,
+        uncommitted: true,
+    };
+    Ok((range, cache == 0))
+}
+
+/// Sets the next value a sequence hands out.
+///
+/// # Errors
+/// Returns a `NotFound` error if there is no such sequence, or an error if the table cannot be written.
+pub(crate) fn set_value(sequences: &mut BTree, name: &str, value: i64) -> io::Result<()> {
+    let (rowid, mut row) = find_sequence(sequences, name)?.ok_or_else(|| no_such_sequence(name))?;
+    row.values[1] = SqliteValue::Integer(value);
+    sequences.delete(rowid)?;
+    sequences.insert(rowid, &row)
+}
+
+/// Finds the row of a sequence by name, ignoring ASCII case. There are rarely more than a handful of sequences,
+/// so the table is just scanned.
+fn find_sequence(sequences: &BTree, name: &str) -> io::Result<Option<(i64, Record)>> {
+    for row in sequences.scan()? {
+        let (rowid, row) = row?;
+        match row.get_value(0) {
+            Some(SqliteValue::String(row_name)) if row_name.eq_ignore_ascii_case(name) => {
+                if row.len() < 5 {
+                    return Err(invalid_sequence_row());
+                }
+                return Ok(Some((rowid, row)));
+            }
+            Some(SqliteValue::String(_)) => {}
+            _ => return Err(invalid_sequence_row()),
+        }
+    }
+    Ok(None)
+}
+
+/// Reads an integer of a row of the sequence table.
+fn sequence_integer(row: &Record, index: usize) -> io::Result<i64> {
+    match row.get_value(index) {
+        Some(SqliteValue::Integer(value)) => Ok(*value),
+        _ => Err(invalid_sequence_row()),
+    }
+}
+
+/// The error for a sequence that does not exist.
+pub(crate) fn no_such_sequence(name: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::NotFound,
+        format!("No such sequence: {}", name),
+    )
+}
+
+fn invalid_sequence_row() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "Invalid sequence table row")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::pager::Pager;
+    use crate::tree::btree::TreeType;
+    use std::sync::Arc;
+    use tempfile::tempdir;
+
+    fn sequence_table() -> (tempfile::TempDir, BTree) {
+        let dir = tempdir().unwrap();
+        let pager = Pager::create(dir.path().join("sequence_test.db"), 4096, None, 0).unwrap();
+        let btree = BTree::create(TreeType::Table, Arc::new(pager), 4096, 0, 255, 32).unwrap();
+        (dir, btree)
+    }
+
+    #[test]
+    fn test_reserve_ranges() {
+        let (_dir, mut sequences) = sequence_table();
+        create_sequence(&mut sequences, "ids", 10, 5, 3).unwrap();
+        assert_eq!(
+            create_sequence(&mut sequences, "IDS", 1, 1, 0)
+                .unwrap_err()
+                .kind(),
+            io::ErrorKind::AlreadyExists
+        );
+        assert!(create_sequence(&mut sequences, "zero", 1, 0, 0).is_err());
+
+        let (mut range, gap_free) = reserve(&mut sequences, "Ids").unwrap();
+        assert!(!gap_free);
+        assert_eq!(
+            std::iter::from_fn(|| range.take()).collect::<Vec<_>>(),
+            vec![10, 15, 20]
+        );
+        let (mut range, _) = reserve(&mut sequences, "ids").unwrap();
+        assert_eq!(range.take(), Some(25));
+
+        set_value(&mut sequences, "ids", 100).unwrap();
+        assert_eq!(reserve(&mut sequences, "ids").unwrap().0.next, 100);
+        drop_sequence(&mut sequences, "ids").unwrap();
+        assert_eq!(
+            reserve(&mut sequences, "ids").unwrap_err().kind(),
+            io::ErrorKind::NotFound
+        );
+    }
+
+    #[test]
+    fn test_reserve_at_the_end_of_the_range() {
+        let (_dir, mut sequences) = sequence_table();
+        create_sequence(&mut sequences, "down", i64::MIN + 3, -2, 10).unwrap();
+        let (mut range, _) = reserve(&mut sequences, "down").unwrap();
+        assert_eq!(
+            std::iter::from_fn(|| range.take()).collect::<Vec<_>>(),
+            vec![i64::MIN + 3, i64::MIN + 1]
+        );
+        assert!(reserve(&mut sequences, "down").is_err());
+
+        create_sequence(&mut sequences, "gap_free", i64::MAX, 1, 0).unwrap();
+        let (range, gap_free) = reserve(&mut sequences, "gap_free").unwrap();
+        assert!(gap_free);
+        assert_eq!((range.next, range.remaining), (i64::MAX, 1));
+        assert!(reserve(&mut sequences, "gap_free").is_err());
+    }
+}
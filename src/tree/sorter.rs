@@ -0,0 +1,502 @@
+//! # Sorter Module
+//!
+//! An external merge sort for records, for whatever needs more rows sorted than it wants to keep in memory:
+//! building indexes over big tables (see the bulk loader) or an ORDER BY in the query layer.
+//! SQLite has the same thing in vdbesort.c.
+//!
+//! Every record comes with a sort key, which is a record too: the values of the columns to sort by.
+//! The sorter keeps them in memory up to a budget. When the budget fills up, the buffer is sorted and spilled
+//! to a temporary file as a sorted run. At the end, the runs are merged k-way with a heap, so the whole sort
+//! reads and writes every record once more than an in-memory sort, whatever the number of runs.
+//! If nothing was spilled, it is just an in-memory sort.
+//!
+//! The runs are plain files in the temporary directory (not pages of the database), written with the usual
+//! record serialization (`utils::serialization`): the key, then the record. They are deleted as soon as the
+//! sorted records are dropped, or the sorter if it never gets to finish.
+//!
+//! Keys are compared column by column, in the same order the B-Tree uses for `KeyValue`s:
+//! NULL < INTEGER < FLOAT < STRING < BLOB. The sort is stable, records with equal keys come out in the order
+//! they went in.
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering as AtomicOrdering};
+use std::sync::Arc;
+
+use crate::tree::record::Record;
+use crate::utils::serialization::SqliteValue;
+
+/// Default number of bytes of records a sorter keeps in memory before spilling them to temporary files.
+pub const DEFAULT_SORT_MEMORY: usize = 16 << 20;
+
+/// Direction of a column of the sort key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SortOrder {
+    /// Smallest values first.
+    #[default]
+    Ascending,
+    /// Largest values first.
+    Descending,
+}
+
+/// Compares two values with the ordering of `KeyValue`: NULL < INTEGER < FLOAT < STRING < BLOB,
+/// and each type with its natural ordering. NaN floats are equal to any other float.
+pub fn compare_values(a: &SqliteValue, b: &SqliteValue) -> Ordering {
+    fn rank(value: &SqliteValue) -> u8 {
+        match value {
+            SqliteValue::Null => 0,
+            SqliteValue::Integer(_) => 1,
+            SqliteValue::Float(_) => 2,
+            SqliteValue::String(_) => 3,
+            SqliteValue::Blob(_) => 4,
+        }
+    }
+
+    match (a, b) {
+        (SqliteValue::Integer(a), SqliteValue::Integer(b)) => a.cmp(b),
+        (SqliteValue::Float(a), SqliteValue::Float(b)) => {
+            a.partial_cmp(b).unwrap_or(Ordering::Equal)
+        }
+        (SqliteValue::String(a), SqliteValue::String(b)) => a.cmp(b),
+        (SqliteValue::Blob(a), SqliteValue::Blob(b)) => a.cmp(b),
+        _ => rank(a).cmp(&rank(b)),
+    }
+}
+
+/// Compares two sort keys column by column.
+///
+/// # Parameters
+/// * `a`, `b` - The keys to compare. If one is a prefix of the other, the shorter one comes first.
+/// * `order` - Direction of every column. Columns past the end of it are ascending.
+pub fn compare_keys(a: &Record, b: &Record, order: &[SortOrder]) -> Ordering {
+    for (column, (a, b)) in a.values.iter().zip(&b.values).enumerate() {
+        let ordering = compare_values(a, b);
+        if ordering != Ordering::Equal {
+            return match order.get(column) {
+                Some(SortOrder::Descending) => ordering.reverse(),
+                _ => ordering,
+            };
+        }
+    }
+    a.values.len().cmp(&b.values.len())
+}
+
+/// Sorts records by a key, spilling sorted runs to temporary files when they do not fit in memory
+/// (see the module docs).
+///
+/// # Example
+/// ```rust
+/// # fn main() -> std::io::Result<()> {
+/// use rqlite_engine::tree::record::Record;
+/// use rqlite_engine::tree::sorter::{ExternalSorter, SortOrder};
+/// use rqlite_engine::utils::serialization::SqliteValue;
+///
+/// let mut sorter = ExternalSorter::new(1 << 20).order(vec![SortOrder::Descending]);
+/// for n in [3, 1, 2] {
+///     let key = Record::with_values(vec![SqliteValue::Integer(n)]);
+///     let record = Record::with_values(vec![SqliteValue::String(format!("row {}", n))]);
+///     sorter.push(key, record)?;
+/// }
+///
+/// let keys = sorter
+///     .finish()?
+///     .map(|entry| entry.map(|(key, _)| key.values[0].clone()))
+///     .collect::<std::io::Result<Vec<_>>>()?;
+/// assert!(matches!(keys[..], [SqliteValue::Integer(3), SqliteValue::Integer(2), SqliteValue::Integer(1)]));
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct ExternalSorter {
+    memory: usize,
+    order: Vec<SortOrder>,
+    temp_dir: PathBuf,
+    buffer: Vec<(Record, Record)>,
+    buffered: usize,
+    runs: Vec<RunFile>,
+    len: usize,
+}
+
+impl ExternalSorter {
+    /// Creates an empty sorter, with ascending keys and the temporary directory of the system.
+    ///
+    /// # Parameters
+    /// * `memory` - Bytes of records to keep in memory before spilling a run.
+    pub fn new(memory: usize) -> Self {
+        ExternalSorter {
+            memory,
+            order: Vec::new(),
+            temp_dir: std::env::temp_dir(),
+            buffer: Vec::new(),
+            buffered: 0,
+            runs: Vec::new(),
+            len: 0,
+        }
+    }
+
+    /// Sets the direction of every column of the key. Columns past the end of it are ascending.
+    pub fn order(mut self, order: Vec<SortOrder>) -> Self {
+        self.order = order;
+        self
+    }
+
+    /// Sets the directory where the runs are written.
+    pub fn temp_dir<P: Into<PathBuf>>(mut self, dir: P) -> Self {
+        self.temp_dir = dir.into();
+        self
+    }
+
+    /// Gets the number of records pushed so far.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Checks if no record was pushed.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Gets the number of runs spilled to temporary files so far.
+    pub fn run_count(&self) -> usize {
+        self.runs.len()
+    }
+
+    /// Compares two keys with the order of this sorter.
+    pub fn compare(&self, a: &Record, b: &Record) -> Ordering {
+        compare_keys(a, b, &self.order)
+    }
+
+    /// Adds a record, spilling the buffer to a new run if it goes over the budget.
+    ///
+    /// # Parameters
+    /// * `key` - The values to sort by.
+    /// * `record` - The record that goes with them. It can be empty if the key is all there is.
+    ///
+    /// # Errors
+    /// Returns an error if the run cannot be written.
+    pub fn push(&mut self, key: Record, record: Record) -> io::Result<()> {
+        self.buffered += entry_size(&key, &record);
+        self.buffer.push((key, record));
+        self.len += 1;
+        if self.buffered >= self.memory {
+            self.spill()?;
+        }
+        Ok(())
+    }
+
+    /// Gets all the records pushed, sorted by key.
+    ///
+    /// # Errors
+    /// Returns an error if the last run cannot be written or the runs cannot be opened.
+    pub fn finish(mut self) -> io::Result<SortedRecords> {
+        if self.runs.is_empty() {
+            self.sort_buffer();
+            let buffer = std::mem::take(&mut self.buffer);
+            return Ok(SortedRecords(Source::Memory(buffer.into_iter())));
+        }
+        if !self.buffer.is_empty() {
+            self.spill()?;
+        }
+
+        let runs = std::mem::take(&mut self.runs);
+        let readers = runs
+            .iter()
+            .map(|run| File::open(&run.path).map(BufReader::new))
+            .collect::<io::Result<Vec<_>>>()?;
+        let mut merge = Merge {
+            order: Arc::from(std::mem::take(&mut self.order)),
+            heap: BinaryHeap::with_capacity(readers.len()),
+            readers,
+            _runs: runs,
+        };
+        for run in 0..merge.readers.len() {
+            merge.refill(run)?;
+        }
+        Ok(SortedRecords(Source::Merge(merge)))
+    }
+
+    /// Sorts the buffer and writes it to a new run.
+    fn spill(&mut self) -> io::Result<()> {
+        self.sort_buffer();
+        let path = self.temp_dir.join(format!(
+            "rqlite-sort-{}-{}",
+            std::process::id(),
+            RUN_COUNTER.fetch_add(1, AtomicOrdering::Relaxed)
+        ));
+        let run = RunFile { path };
+        let mut writer = BufWriter::new(File::create(&run.path)?);
+        for (key, record) in &self.buffer {
+            key.serialize(&mut writer)?;
+            record.serialize(&mut writer)?;
+        }
+        writer.flush()?;
+
+        self.runs.push(run);
+        self.buffer.clear();
+        self.buffered = 0;
+        Ok(())
+    }
+
+    /// Sorts the buffer by key, keeping the order of equal keys.
+    fn sort_buffer(&mut self) {
+        let order = &self.order;
+        self.buffer
+            .sort_by(|(a, _), (b, _)| compare_keys(a, b, order));
+    }
+}
+
+/// Rough number of bytes a record and its key take in memory, for the budget.
+fn entry_size(key: &Record, record: &Record) -> usize {
+    std::mem::size_of::<(Record, Record)>() + key.serialized_size() + record.serialized_size()
+}
+
+/// Number of run files created by this process, to give each one its own name.
+static RUN_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// A sorted run spilled to a temporary file. The file is deleted when the run is dropped.
+#[derive(Debug)]
+struct RunFile {
+    path: PathBuf,
+}
+
+impl Drop for RunFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// The records of an `ExternalSorter`, sorted by key, as `(key, record)` pairs.
+/// The runs are deleted when it is dropped.
+#[derive(Debug)]
+pub struct SortedRecords(Source);
+
+/// Where the sorted records come from.
+#[derive(Debug)]
+enum Source {
+    /// Everything fit in memory.
+    Memory(std::vec::IntoIter<(Record, Record)>),
+    /// K-way merge of the runs.
+    Merge(Merge),
+}
+
+impl Iterator for SortedRecords {
+    type Item = io::Result<(Record, Record)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match &mut self.0 {
+            Source::Memory(records) => records.next().map(Ok),
+            Source::Merge(merge) => merge.next(),
+        }
+    }
+}
+
+/// K-way merge of the runs of an `ExternalSorter`.
+#[derive(Debug)]
+struct Merge {
+    /// Shared with every head in the heap, which need it to compare themselves.
+    order: Arc<[SortOrder]>,
+    readers: Vec<BufReader<File>>,
+    heap: BinaryHeap<Head>,
+    /// Keeps the files around until the merge is done.
+    _runs: Vec<RunFile>,
+}
+
+impl Merge {
+    /// Puts the next record of a run in the heap, if the run has more.
+    fn refill(&mut self, run: usize) -> io::Result<()> {
+        let reader = &mut self.readers[run];
+        if reader.fill_buf()?.is_empty() {
+            return Ok(());
+        }
+        let (key, _) = Record::deserialize(reader)?;
+        let (record, _) = Record::deserialize(reader)?;
+        self.heap.push(Head {
+            key,
+            record,
+            run,
+            order: Arc::clone(&self.order),
+        });
+        Ok(())
+    }
+}
+
+impl Iterator for Merge {
+    type Item = io::Result<(Record, Record)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let Head {
+            key, record, run, ..
+        } = self.heap.pop()?;
+        Some(self.refill(run).map(|_| (key, record)))
+    }
+}
+
+/// The next record of a run, ordered so that the `BinaryHeap` (a max-heap) pops the smallest one first.
+/// Equal keys pop in the order of their runs, which keeps the sort stable.
+#[derive(Debug)]
+struct Head {
+    key: Record,
+    record: Record,
+    run: usize,
+    order: Arc<[SortOrder]>,
+}
+
+impl PartialEq for Head {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Head {}
+
+impl PartialOrd for Head {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Head {
+    fn cmp(&self, other: &Self) -> Ordering {
+        compare_keys(&other.key, &self.key, &self.order).then(other.run.cmp(&self.run))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    fn key(values: Vec<SqliteValue>) -> Record {
+        Record::with_values(values)
+    }
+
+    /// The numbers 0 to n - 1 in a fixed, scrambled order.
+    fn shuffled(n: i64) -> Vec<i64> {
+        (0..n).map(|i| (i * 7919) % n).collect()
+    }
+
+    #[test]
+    fn test_compare_keys() {
+        let values = [
+            SqliteValue::Null,
+            SqliteValue::Integer(-5),
+            SqliteValue::Integer(3),
+            SqliteValue::Float(-10.0),
+            SqliteValue::String("a".to_string()),
+            SqliteValue::String("b".to_string()),
+            SqliteValue::Blob(vec![0]),
+        ];
+        for (i, a) in values.iter().enumerate() {
+            for (j, b) in values.iter().enumerate() {
+                assert_eq!(compare_values(a, b), i.cmp(&j), "{:?} vs {:?}", a, b);
+            }
+        }
+
+        let short = key(vec![SqliteValue::Integer(1)]);
+        let long = key(vec![SqliteValue::Integer(1), SqliteValue::Integer(0)]);
+        assert_eq!(compare_keys(&short, &long, &[]), Ordering::Less);
+
+        let a = key(vec![SqliteValue::Integer(1), SqliteValue::Integer(5)]);
+        let b = key(vec![SqliteValue::Integer(1), SqliteValue::Integer(9)]);
+        let c = key(vec![SqliteValue::Integer(2), SqliteValue::Integer(0)]);
+        let order = [SortOrder::Ascending, SortOrder::Descending];
+        assert_eq!(compare_keys(&a, &b, &order), Ordering::Greater);
+        assert_eq!(compare_keys(&a, &c, &order), Ordering::Less);
+        assert_eq!(
+            compare_keys(&c, &a, &[SortOrder::Descending]),
+            Ordering::Less
+        );
+    }
+
+    #[test]
+    fn test_sort_in_memory() {
+        let mut sorter = ExternalSorter::new(1 << 20);
+        assert!(sorter.is_empty());
+        for (i, n) in [2, 1, 2, 0, 1].into_iter().enumerate() {
+            let record = key(vec![SqliteValue::Integer(i as i64)]);
+            sorter
+                .push(key(vec![SqliteValue::Integer(n)]), record)
+                .unwrap();
+        }
+        assert_eq!(sorter.len(), 5);
+        assert_eq!(sorter.run_count(), 0);
+
+        // Equal keys keep the order they were pushed in
+        let sorted = sorter
+            .finish()
+            .unwrap()
+            .map(|entry| {
+                let (key, record) = entry.unwrap();
+                match (&key.values[0], &record.values[0]) {
+                    (SqliteValue::Integer(n), SqliteValue::Integer(i)) => (*n, *i),
+                    _ => unreachable!(),
+                }
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(sorted, vec![(0, 3), (1, 1), (1, 4), (2, 0), (2, 2)]);
+    }
+
+    #[test]
+    fn test_sort_with_runs() {
+        let dir = tempdir().unwrap();
+        let mut sorter = ExternalSorter::new(4 << 10)
+            .order(vec![SortOrder::Descending])
+            .temp_dir(dir.path());
+        for n in shuffled(2000) {
+            let record = key(vec![SqliteValue::String(format!("row {}", n))]);
+            sorter
+                .push(
+                    key(vec![SqliteValue::Integer(n % 500), SqliteValue::Integer(n)]),
+                    record,
+                )
+                .unwrap();
+        }
+        assert!(sorter.run_count() > 2);
+        assert_eq!(
+            fs::read_dir(dir.path()).unwrap().count(),
+            sorter.run_count()
+        );
+
+        // Descending on the first column, ascending on the second, and the records come along
+        let mut sorted = sorter.finish().unwrap();
+        let mut previous: Option<Record> = None;
+        let mut count = 0;
+        for entry in sorted.by_ref() {
+            let (key, record) = entry.unwrap();
+            let n = match key.values[1] {
+                SqliteValue::Integer(n) => n,
+                _ => unreachable!(),
+            };
+            assert!(
+                matches!(&record.values[0], SqliteValue::String(s) if *s == format!("row {}", n))
+            );
+            if let Some(previous) = previous {
+                let order = [SortOrder::Descending];
+                assert_eq!(compare_keys(&previous, &key, &order), Ordering::Less);
+            }
+            previous = Some(key);
+            count += 1;
+        }
+        assert_eq!(count, 2000);
+
+        // The runs are deleted with the sorted records
+        drop(sorted);
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn test_drop_without_finish() {
+        let dir = tempdir().unwrap();
+        let mut sorter = ExternalSorter::new(1 << 10).temp_dir(dir.path());
+        for n in 0..500 {
+            sorter
+                .push(key(vec![SqliteValue::Integer(n)]), Record::new())
+                .unwrap();
+        }
+        assert!(sorter.run_count() > 0);
+        drop(sorter);
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 0);
+    }
+}
@@ -0,0 +1,612 @@
+//! # Statistics Module
+//!
+//! `BTree::analyze` reads a whole table or index B-Tree and works out what the query planner and the
+//! capacity planning want to know about it: how many rows it has, how deep it is, how many pages it takes
+//! and how full they are, and for indexes how many distinct keys there are and how they are spread.
+//!
+//! Like SQLite's ANALYZE, I read every page of the tree. Counting the rows any other way would only be an
+//! estimate, and a tree that is worth analyzing is not read that often. The pages are walked depth first,
+//! left to right, so the entries of an index come in key order and counting the distinct keys is just
+//! counting when the key changes. Only the first value of an index key counts, as it is the only one
+//! the tree is ordered by (see `extract_key_from_payload`).
+//!
+//! The histogram is what `sqlite_stat4` keeps: a few sample keys spread evenly over the index, each with
+//! how many entries have that key, how many come before it and how many distinct keys come before it.
+//! We only know where the samples go once we know how many entries there are, so indexes are walked twice.
+//!
+//! The statistics are saved in the stat table, a table B-Tree of the catalog that works like
+//! `sqlite_stat1` and `sqlite_stat4` put together (see `save_stats` for its rows). They are a picture of
+//! the tree when it was analyzed, nothing updates them after that.
+
+use std::io;
+
+use crate::page::{BTreeCell, Page};
+use crate::tree::btree::{BTree, TreeType};
+use crate::tree::node::extract_key_from_payload;
+use crate::tree::record::Record;
+use crate::utils::cmp::KeyValue;
+use crate::utils::serialization::SqliteValue;
+
+/// Default number of histogram samples per index, the same as `SQLITE_STAT4_SAMPLES`.
+pub const DEFAULT_SAMPLES: usize = 24;
+
+/// Most samples a stat table row can have, as the sample number takes the low byte of the rowid.
+pub const MAX_SAMPLES: usize = 255;
+
+/// Statistics of a table or index B-Tree, as gathered by `BTree::analyze`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TreeStats {
+    /// Number of rows of a table, or entries of an index.
+    pub row_count: u64,
+    /// Number of levels of the tree. A tree whose root is a leaf has depth 1.
+    pub depth: u32,
+    /// Number of leaf pages.
+    pub leaf_pages: u32,
+    /// Number of interior pages.
+    pub interior_pages: u32,
+    /// Number of overflow pages taken by large payloads.
+    pub overflow_pages: u32,
+    /// Average size of the payload of a row or an entry in bytes, overflow included.
+    pub avg_payload: f64,
+    /// How much of the leaf and interior pages the cells take, from 0 to 1.
+    pub fill: f64,
+    /// Number of distinct keys of an index. `None` for tables, whose rowids are all distinct.
+    pub distinct_keys: Option<u64>,
+    /// Histogram of the keys of an index, in key order. Empty for tables.
+    pub samples: Vec<KeySample>,
+}
+
+/// A sample key of an index histogram, like a row of `sqlite_stat4`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct KeySample {
+    /// The sampled key.
+    pub key: KeyValue,
+    /// Number of entries with this key.
+    pub equal: u64,
+    /// Number of entries with a smaller key.
+    pub less: u64,
+    /// Number of distinct keys smaller than this one.
+    pub distinct_less: u64,
+}
+
+impl TreeStats {
+    /// Gets the average number of entries per key of an index, what SQLite's planner calls the selectivity
+    /// of an equality lookup. `None` for tables.
+    pub fn rows_per_key(&self) -> Option<f64> {
+        let distinct = self.distinct_keys?;
+        if distinct == 0 {
+            return Some(0.0);
+        }
+        Some(self.row_count as f64 / distinct as f64)
+    }
+}
+
+/// What the walk needs from a page.
+struct PageInfo {
+    cells: Vec<BTreeCell>,
+    right_most: Option<u32>,
+    free_space: usize,
+    leaf: bool,
+}
+
+/// Totals gathered while walking the pages.
+#[derive(Default)]
+struct Totals {
+    depth: u32,
+    leaf_pages: u32,
+    interior_pages: u32,
+    overflow_pages: u32,
+    rows: u64,
+    payload: u64,
+    used: u64,
+    capacity: u64,
+    distinct: u64,
+    last_key: Option<KeyValue>,
+}
+
+impl BTree {
+    /// Reads the whole tree and gathers its statistics.
+    ///
+    /// # Parameters
+    /// * `samples` - Most sample keys in the histogram of an index, up to `MAX_SAMPLES`.
+    ///
+    /// # Errors
+    /// Returns an error if a page cannot be read or an index key cannot be parsed.
+    ///
+    /// # Returns
+    /// The statistics of the tree.
+    pub fn analyze(&self, samples: usize) -> io::Result<TreeStats> {
+        let index = self.tree_type() == TreeType::Index;
+        let mut totals = Totals::default();
+        self.walk(self.root_page(), 1, &mut |depth, page| {
+            totals.depth = totals.depth.max(depth);
+            let used: usize = page.cells.iter().map(|cell| cell.size() + 2).sum();
+            totals.used += used as u64;
+            totals.capacity += (used + page.free_space) as u64;
+            if !page.leaf {
+                totals.interior_pages += 1;
+                return Ok(());
+            }
+
+            totals.leaf_pages += 1;
+            for cell in &page.cells {
+                let (payload_size, local, overflow) = match cell {
+                    BTreeCell::TableLeaf(cell) => {
+                        (cell.payload_size, &cell.payload, cell.overflow_page)
+                    }
+                    BTreeCell::IndexLeaf(cell) => {
+                        (cell.payload_size, &cell.payload, cell.overflow_page)
+                    }
+                    _ => continue,
+                };
+                totals.rows += 1;
+                totals.payload += payload_size;
+                if overflow.is_some() {
+                    totals.overflow_pages += self.overflow_page_count(payload_size, local.len());
+                }
+                if index {
+                    let key = extract_key_from_payload(local)?;
+                    if totals.last_key.as_ref() != Some(&key) {
+                        totals.distinct += 1;
+                        totals.last_key = Some(key);
+                    }
+                }
+            }
+            Ok(())
+        })?;
+
+        let samples = if index {
+            self.sample_keys(totals.rows, samples.min(MAX_SAMPLES))?
+        } else {
+            Vec::new()
+        };
+        Ok(TreeStats {
+            row_count: totals.rows,
+            depth: totals.depth,
+            leaf_pages: totals.leaf_pages,
+            interior_pages: totals.interior_pages,
+            overflow_pages: totals.overflow_pages,
+            avg_payload: if totals.rows == 0 {
+                0.0
+            } else {
+                totals.payload as f64 / totals.rows as f64
+            },
+            fill: if totals.capacity == 0 {
+                0.0
+            } else {
+                totals.used as f64 / totals.capacity as f64
+            },
+            distinct_keys: index.then_some(totals.distinct),
+            samples,
+        })
+    }
+
+    /// Walks the index again to pick the histogram samples: the keys of the entries at the middle of
+    /// `count` even slices of the index. A key with many entries can cover several slices, but it is
+    /// only sampled once.
+    ///
+    /// # Errors
+    /// Returns an error if a page cannot be read or a key cannot be parsed.
+    fn sample_keys(&self, rows: u64, count: usize) -> io::Result<Vec<KeySample>> {
+        if rows == 0 || count == 0 {
+            return Ok(Vec::new());
+        }
+        let count = (count as u64).min(rows);
+        let targets: Vec<u64> = (0..count)
+            .map(|i| (2 * i + 1) * rows / (2 * count))
+            .collect();
+
+        let mut samples = Vec::new();
+        let mut next_target = 0;
+        // The key being counted, where its entries start and how many distinct keys came before
+        let mut group: Option<(KeyValue, u64, u64)> = None;
+        let mut position = 0;
+        let mut distinct = 0;
+
+        let mut close_group =
+            |group: (KeyValue, u64, u64), end: u64, samples: &mut Vec<KeySample>| {
+                let (key, start, distinct_less) = group;
+                if next_target < targets.len() && targets[next_target] < end {
+                    samples.push(KeySample {
+                        key,
+                        equal: end - start,
+                        less: start,
+                        distinct_less,
+                    });
+                    while next_target < targets.len() && targets[next_target] < end {
+                        next_target += 1;
+                    }
+                }
+            };
+
+        self.walk(self.root_page(), 1, &mut |_, page| {
+            if !page.leaf {
+                return Ok(());
+            }
+            for cell in &page.cells {
+                let BTreeCell::IndexLeaf(cell) = cell else {
+                    continue;
+                };
+                let key = extract_key_from_payload(&cell.payload)?;
+                match group.take() {
+                    Some(current) if current.0 == key => group = Some(current),
+                    current => {
+                        if let Some(current) = current {
+                            close_group(current, position, &mut samples);
+                        }
+                        group = Some((key, position, distinct));
+                        distinct += 1;
+                    }
+                }
+                position += 1;
+            }
+            Ok(())
+        })?;
+        if let Some(current) = group {
+            close_group(current, position, &mut samples);
+        }
+        Ok(samples)
+    }
+
+    /// Visits the pages of a subtree depth first, left to right, so leaves come in key order.
+    ///
+    /// # Parameters
+    /// * `page_number` - Root of the subtree.
+    /// * `depth` - Level of the root of the subtree, 1 for the root of the tree.
+    /// * `visit` - Called with the level and the content of every page.
+    ///
+    /// # Errors
+    /// Returns an error if a page cannot be read or is not a B-Tree page, or the error of `visit`.
+    fn walk(
+        &self,
+        page_number: u32,
+        depth: u32,
+        visit: &mut dyn FnMut(u32, &PageInfo) -> io::Result<()>,
+    ) -> io::Result<()> {
+        let page = self
+            .pager()
+            .get_page_callback(page_number, None, |page| match page {
+                Page::BTree(btree_page) => Ok(PageInfo {
+                    cells: btree_page.cells.clone(),
+                    right_most: btree_page.header.right_most_page,
+                    free_space: btree_page.free_space(),
+                    leaf: btree_page.header.page_type.is_leaf(),
+                }),
+                _ => Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Page {} is not a B-Tree page", page_number),
+                )),
+            })??;
+        visit(depth, &page)?;
+        if page.leaf {
+            return Ok(());
+        }
+
+        for cell in &page.cells {
+            let left_child = match cell {
+                BTreeCell::TableInterior(cell) => cell.left_child_page,
+                BTreeCell::IndexInterior(cell) => cell.left_child_page,
+                _ => continue,
+            };
+            self.walk(left_child, depth + 1, visit)?;
+        }
+        if let Some(right_most) = page.right_most {
+            self.walk(right_most, depth + 1, visit)?;
+        }
+        Ok(())
+    }
+
+    /// Gets how many overflow pages a payload takes, split the way `create_overflow_chain` does it.
+    fn overflow_page_count(&self, payload_size: u64, local_size: usize) -> u32 {
+        let spilled = payload_size.saturating_sub(local_size as u64);
+        let per_page = (self.usable_page_size() - 13) as u64;
+        spilled.div_ceil(per_page) as u32
+    }
+}
+
+/// Gets the rowid of a row of the stat table. The summary of a tree is sample 0.
+fn stat_rowid(tree_type: TreeType, id: u32, sample: usize) -> i64 {
+    let tree = match tree_type {
+        TreeType::Table => id as i64 * 2,
+        TreeType::Index => id as i64 * 2 + 1,
+    };
+    (tree << 8) | sample as i64
+}
+
+/// Saves the statistics of a tree in the stat table, replacing the ones it had.
+///
+/// The table has one summary row per tree, with the same rowid scheme as the schema table shifted by a byte:
+/// - rowid `(2 * id) << 8` for tables and `(2 * id + 1) << 8` for indexes:
+///   `[kind, id, rows, depth, leaf pages, interior pages, overflow pages, average payload, fill, distinct keys]`
+/// - the next rowids, one per sample: `[key, equal, less, distinct less]`
+///
+/// # Errors
+/// Returns an error if the stat table cannot be written.
+pub(crate) fn save_stats(
+    stat_table: &mut BTree,
+    tree_type: TreeType,
+    id: u32,
+    stats: &TreeStats,
+) -> io::Result<()> {
+    // The old samples are numbered from 1 with no gaps, so the first one missing is the end
+    stat_table.delete(stat_rowid(tree_type, id, 0))?;
+    let mut sample = 1;
+    while sample <= MAX_SAMPLES && stat_table.delete(stat_rowid(tree_type, id, sample))? {
+        sample += 1;
+    }
+
+    let kind = match tree_type {
+        TreeType::Table => "table",
+        TreeType::Index => "index",
+    };
+    let summary = Record::with_values(vec![
+        SqliteValue::String(kind.to_string()),
+        SqliteValue::Integer(id as i64),
+        SqliteValue::Integer(stats.row_count as i64),
+        SqliteValue::Integer(stats.depth as i64),
+        SqliteValue::Integer(stats.leaf_pages as i64),
+        SqliteValue::Integer(stats.interior_pages as i64),
+        SqliteValue::Integer(stats.overflow_pages as i64),
+        SqliteValue::Float(stats.avg_payload),
+        SqliteValue::Float(stats.fill),
+        match stats.distinct_keys {
+            Some(distinct) => SqliteValue::Integer(distinct as i64),
+            None => SqliteValue::Null,
+        },
+    ]);
+    stat_table.insert(stat_rowid(tree_type, id, 0), &summary)?;
+
+    for (n, sample) in stats.samples.iter().take(MAX_SAMPLES).enumerate() {
+        let key = match &sample.key {
+            KeyValue::Null => SqliteValue::Null,
+            KeyValue::Integer(i) => SqliteValue::Integer(*i),
+            KeyValue::Float(f) => SqliteValue::Float(*f),
+            KeyValue::String(s) => SqliteValue::String(s.clone()),
+            KeyValue::Blob(b) => SqliteValue::Blob(b.clone()),
+        };
+        let row = Record::with_values(vec![
+            key,
+            SqliteValue::Integer(sample.equal as i64),
+            SqliteValue::Integer(sample.less as i64),
+            SqliteValue::Integer(sample.distinct_less as i64),
+        ]);
+        stat_table.insert(stat_rowid(tree_type, id, n + 1), &row)?;
+    }
+    Ok(())
+}
+
+/// Loads the statistics of a tree from the stat table.
+///
+/// # Errors
+/// Returns an error if the stat table cannot be read or one of its rows is not valid.
+///
+/// # Returns
+/// The statistics, or `None` if the tree was never analyzed.
+pub(crate) fn load_stats(
+    stat_table: &BTree,
+    tree_type: TreeType,
+    id: u32,
+) -> io::Result<Option<TreeStats>> {
+    let Some(summary) = stat_table.find(stat_rowid(tree_type, id, 0))? else {
+        return Ok(None);
+    };
+
+    let mut samples = Vec::new();
+    for n in 1..=MAX_SAMPLES {
+        let Some(row) = stat_table.find(stat_rowid(tree_type, id, n))? else {
+            break;
+        };
+        let key = match row.get_value(0) {
+            Some(SqliteValue::Null) => KeyValue::Null,
+            Some(SqliteValue::Integer(i)) => KeyValue::Integer(*i),
+            Some(SqliteValue::Float(f)) => KeyValue::Float(*f),
+            Some(SqliteValue::String(s)) => KeyValue::String(s.clone()),
+            Some(SqliteValue::Blob(b)) => KeyValue::Blob(b.clone()),
+            None => return Err(invalid_stat_row()),
+        };
+        samples.push(KeySample {
+            key,
+            equal: stat_integer(&row, 1)?,
+            less: stat_integer(&row, 2)?,
+            distinct_less: stat_integer(&row, 3)?,
+        });
+    }
+
+    Ok(Some(TreeStats {
+        row_count: stat_integer(&summary, 2)?,
+        depth: stat_integer(&summary, 3)? as u32,
+        leaf_pages: stat_integer(&summary, 4)? as u32,
+        interior_pages: stat_integer(&summary, 5)? as u32,
+        overflow_pages: stat_integer(&summary, 6)? as u32,
+        avg_payload: stat_float(&summary, 7)?,
+        fill: stat_float(&summary, 8)?,
+        distinct_keys: match summary.get_value(9) {
+            Some(SqliteValue::Null) => None,
+            _ => Some(stat_integer(&summary, 9)?),
+        },
+        samples,
+    }))
+}
+
+/// Reads a count from a row of the stat table.
+fn stat_integer(row: &Record, index: usize) -> io::Result<u64> {
+    match row.get_value(index) {
+        Some(SqliteValue::Integer(value)) => u64::try_from(*value).map_err(|_| invalid_stat_row()),
+        _ => Err(invalid_stat_row()),
+    }
+}
+
+/// Reads a ratio from a row of the stat table.
+fn stat_float(row: &Record, index: usize) -> io::Result<f64> {
+    match row.get_value(index) {
+        Some(SqliteValue::Float(value)) => Ok(*value),
+        Some(SqliteValue::Integer(value)) => Ok(*value as f64),
+        _ => Err(invalid_stat_row()),
+    }
+}
+
+fn invalid_stat_row() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "Invalid stat table row")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::pager::Pager;
+    use crate::utils::serialization::serialize_values;
+    use std::sync::Arc;
+    use tempfile::tempdir;
+
+    fn create_tree(tree_type: TreeType) -> (tempfile::TempDir, BTree) {
+        let dir = tempdir().unwrap();
+        let pager = Pager::create(dir.path().join("stats_test.db"), 4096, None, 0).unwrap();
+        let btree = BTree::create(tree_type, Arc::new(pager), 4096, 0, 255, 32).unwrap();
+        (dir, btree)
+    }
+
+    fn index_key(value: i64) -> Vec<u8> {
+        let mut key = Vec::new();
+        serialize_values(&[SqliteValue::Integer(value)], &mut key).unwrap();
+        key
+    }
+
+    #[test]
+    fn test_analyze_empty_table() {
+        let (_dir, btree) = create_tree(TreeType::Table);
+        let stats = btree.analyze(DEFAULT_SAMPLES).unwrap();
+        assert_eq!(stats.row_count, 0);
+        assert_eq!(stats.depth, 1);
+        assert_eq!(stats.leaf_pages, 1);
+        assert_eq!(stats.interior_pages, 0);
+        assert_eq!(stats.avg_payload, 0.0);
+        assert_eq!(stats.distinct_keys, None);
+        assert!(stats.samples.is_empty());
+    }
+
+    #[test]
+    fn test_analyze_table() {
+        let (_dir, mut btree) = create_tree(TreeType::Table);
+        for rowid in 1..=2000 {
+            let record = Record::with_values(vec![
+                SqliteValue::Integer(rowid),
+                SqliteValue::String(format!("Row number {:06}", rowid)),
+            ]);
+            btree.insert(rowid, &record).unwrap();
+        }
+        let big = Record::with_values(vec![SqliteValue::Blob(vec![7; 10_000])]);
+        btree.insert(2001, &big).unwrap();
+
+        let stats = btree.analyze(DEFAULT_SAMPLES).unwrap();
+        assert_eq!(stats.row_count, 2001);
+        assert!(stats.depth >= 2);
+        assert!(stats.leaf_pages > 10);
+        assert!(stats.interior_pages >= 1);
+        // 8184 bytes spill over, a few more than two overflow pages hold
+        assert_eq!(stats.overflow_pages, 3);
+        assert!(stats.avg_payload > 20.0 && stats.avg_payload < 30.0);
+        assert!(stats.fill > 0.5 && stats.fill <= 1.0);
+    }
+
+    #[test]
+    fn test_analyze_index() {
+        let (_dir, mut btree) = create_tree(TreeType::Index);
+        // 100 keys with 10 entries each
+        let mut rowid = 0;
+        for _ in 0..10 {
+            for value in 0..100 {
+                rowid += 1;
+                btree.insert_index(&index_key(value * 10), rowid).unwrap();
+            }
+        }
+
+        let stats = btree.analyze(10).unwrap();
+        assert_eq!(stats.row_count, 1000);
+        assert_eq!(stats.distinct_keys, Some(100));
+        assert_eq!(stats.rows_per_key(), Some(10.0));
+        assert_eq!(stats.samples.len(), 10);
+        for (n, sample) in stats.samples.iter().enumerate() {
+            // The middle of slice n is entry 100 * n + 50, the first one of the key number 10 * n + 5
+            let key = 10 * n as i64 + 5;
+            assert_eq!(sample.key, KeyValue::Integer(key * 10));
+            assert_eq!(sample.equal, 10);
+            assert_eq!(sample.less, key as u64 * 10);
+            assert_eq!(sample.distinct_less, key as u64);
+        }
+    }
+
+    #[test]
+    fn test_samples_of_a_dominant_key() {
+        let (_dir, mut btree) = create_tree(TreeType::Index);
+        for rowid in 1..=90 {
+            btree.insert_index(&index_key(42), rowid).unwrap();
+        }
+        for value in 0..10 {
+            btree.insert_index(&index_key(value), 100 + value).unwrap();
+        }
+
+        // The key 42 covers most slices but is only sampled once
+        let stats = btree.analyze(DEFAULT_SAMPLES).unwrap();
+        assert_eq!(stats.distinct_keys, Some(11));
+        let dominant: Vec<_> = stats
+            .samples
+            .iter()
+            .filter(|sample| sample.key == KeyValue::Integer(42))
+            .collect();
+        assert_eq!(dominant.len(), 1);
+        assert_eq!(dominant[0].equal, 90);
+        assert_eq!(dominant[0].less, 10);
+        assert_eq!(dominant[0].distinct_less, 10);
+        assert!(stats
+            .samples
+            .windows(2)
+            .all(|pair| pair[0].less < pair[1].less));
+    }
+
+    #[test]
+    fn test_save_and_load_stats() {
+        let (_dir, mut stat_table) = create_tree(TreeType::Table);
+        let stats = TreeStats {
+            row_count: 1000,
+            depth: 2,
+            leaf_pages: 12,
+            interior_pages: 1,
+            overflow_pages: 0,
+            avg_payload: 17.5,
+            fill: 0.75,
+            distinct_keys: Some(3),
+            samples: vec![
+                KeySample {
+                    key: KeyValue::String("a".to_string()),
+                    equal: 500,
+                    less: 0,
+                    distinct_less: 0,
+                },
+                KeySample {
+                    key: KeyValue::String("c".to_string()),
+                    equal: 400,
+                    less: 600,
+                    distinct_less: 2,
+                },
+            ],
+        };
+        assert_eq!(load_stats(&stat_table, TreeType::Index, 1).unwrap(), None);
+        save_stats(&mut stat_table, TreeType::Index, 1, &stats).unwrap();
+        assert_eq!(
+            load_stats(&stat_table, TreeType::Index, 1).unwrap(),
+            Some(stats.clone())
+        );
+        // A table with the same ID is another tree
+        assert_eq!(load_stats(&stat_table, TreeType::Table, 1).unwrap(), None);
+
+        // Saving again replaces the samples too
+        let fewer = TreeStats {
+            samples: stats.samples[..1].to_vec(),
+            ..stats
+        };
+        save_stats(&mut stat_table, TreeType::Index, 1, &fewer).unwrap();
+        assert_eq!(
+            load_stats(&stat_table, TreeType::Index, 1).unwrap(),
+            Some(fewer)
+        );
+    }
+}
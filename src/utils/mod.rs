@@ -3,6 +3,7 @@
 //! Este módulo contiene utilidades generales que se utilizan en toda la implementación
 //! del motor de almacenamiento SQLite.
 
+pub mod checksum;
 pub mod cmp;
 pub mod serialization;
 pub mod varint;
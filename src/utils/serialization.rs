@@ -68,9 +68,9 @@ pub enum SqliteType {
 
 /// In SQLite, the difference between NUMERIC and INTEGER is only important on a cast expression, but it does not affect how the values are stores under the hood.
 ///
-/// Note that on my implementation I am not using the SQLite rules for type affinity.
-/// Instead, I am using the types as they are defined in the SQLite documentation.
-/// This is because I am not implementing a full SQLite engine, but rather storage backend.
+/// Note that the values themselves do not follow the SQLite rules for type affinity: a value is stored as it comes.
+/// The rules only apply to the tables created with columns (see `Affinity` and `schema.rs`),
+/// which coerce the values of each column before they get here.
 ///
 /// For DateTime values, SQLite does not have a specific type. Instead, it uses built in functions that can convert from REAL, INTEGER or TEXT to DATE.
 /// As I said, I am not implementing that part of the functionality.
@@ -99,7 +99,7 @@ impl From<u8> for SqliteType {
 
 /// Represents a generic SQLite value.
 /// This enum can hold different types of values that SQLite supports.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum SqliteValue {
     /// NULL
     Null,
@@ -144,28 +144,28 @@ impl SqliteValue {
                         writer.write_all(&[SqliteType::Integer1 as u8])?;
                         bytes_written += 1;
                     }
-                    v if v >= -128 && v <= 127 => {
+                    v if (-128..=127).contains(&v) => {
                         writer.write_all(&[SqliteType::Integer8 as u8])?;
                         writer.write_all(&[(v as i8) as u8])?;
                         bytes_written += 2;
                     }
-                    v if v >= -32768 && v <= 32767 => {
+                    v if (-32768..=32767).contains(&v) => {
                         writer.write_all(&[SqliteType::Integer16 as u8])?;
                         writer.write_all(&(v as i16).to_be_bytes())?;
                         bytes_written += 3;
                     }
-                    v if v >= -8388608 && v <= 8388607 => {
+                    v if (-8388608..=8388607).contains(&v) => {
                         writer.write_all(&[SqliteType::Integer24 as u8])?;
                         let bytes = (v as i32).to_be_bytes();
                         writer.write_all(&bytes[1..])?; // Ignorar el byte más significativo
                         bytes_written += 4;
                     }
-                    v if v >= -2147483648 && v <= 2147483647 => {
+                    v if (-2147483648..=2147483647).contains(&v) => {
                         writer.write_all(&[SqliteType::Integer32 as u8])?;
                         writer.write_all(&(v as i32).to_be_bytes())?;
                         bytes_written += 5;
                     }
-                    v if v >= -140737488355328 && v <= 140737488355327 => {
+                    v if (-140737488355328..=140737488355327).contains(&v) => {
                         writer.write_all(&[SqliteType::Integer48 as u8])?;
                         let bytes = v.to_be_bytes();
                         writer.write_all(&bytes[2..])?; // Ignorar los 2 bytes más significativos
@@ -413,6 +413,117 @@ impl SqliteValue {
     }
 }
 
+/// Type affinity of a column: the type its values are converted to when possible.
+/// See the rules at the top of this module, or https://www.sqlite.org/datatype3.html#type_affinity.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Affinity {
+    /// Numbers are converted to text.
+    Text,
+    /// Text that looks like a number is converted to an integer if it can be, or to a float.
+    /// Floats without a fractional part are converted to integers.
+    Numeric,
+    /// Same as `Numeric`. The difference only matters for CAST expressions.
+    Integer,
+    /// Same as `Numeric`, but integers are converted to floats.
+    Real,
+    /// Values are stored as they come.
+    Blob,
+}
+
+impl Affinity {
+    /// Works out the affinity of a column from its declared type, checking the rules in order.
+    ///
+    /// # Parameters
+    /// * `declared_type` - The type in the column definition, like `VARCHAR(255)`. Empty if there is none.
+    pub fn from_declared_type(declared_type: &str) -> Self {
+        let declared_type = declared_type.to_ascii_uppercase();
+        let contains = |pattern: &str| declared_type.contains(pattern);
+        if contains("INT") {
+            Affinity::Integer
+        } else if contains("CHAR") || contains("CLOB") || contains("TEXT") {
+            Affinity::Text
+        } else if contains("BLOB") || declared_type.trim().is_empty() {
+            Affinity::Blob
+        } else if contains("REAL") || contains("FLOA") || contains("DOUB") {
+            Affinity::Real
+        } else {
+            Affinity::Numeric
+        }
+    }
+
+    /// Converts a value to the affinity, if it can be done without losing information.
+    /// NULLs and blobs are never converted.
+    pub fn apply(self, value: SqliteValue) -> SqliteValue {
+        match (self, value) {
+            (Affinity::Blob, value) => value,
+            (Affinity::Text, SqliteValue::Integer(i)) => SqliteValue::String(i.to_string()),
+            (Affinity::Text, SqliteValue::Float(f)) => SqliteValue::String(real_to_text(f)),
+            (Affinity::Real, SqliteValue::Integer(i)) => SqliteValue::Float(i as f64),
+            (Affinity::Real, SqliteValue::String(text)) => match parse_numeric(&text) {
+                Some(SqliteValue::Integer(i)) => SqliteValue::Float(i as f64),
+                Some(number) => number,
+                None => SqliteValue::String(text),
+            },
+            (Affinity::Numeric | Affinity::Integer, SqliteValue::String(text)) => {
+                match parse_numeric(&text) {
+                    Some(number) => number,
+                    None => SqliteValue::String(text),
+                }
+            }
+            (Affinity::Numeric | Affinity::Integer, SqliteValue::Float(f)) => {
+                match exact_integer(f) {
+                    Some(i) => SqliteValue::Integer(i),
+                    None => SqliteValue::Float(f),
+                }
+            }
+            (_, value) => value,
+        }
+    }
+}
+
+/// Parses text that is a well-formed integer or real literal, surrounding spaces allowed, like SQLite does
+/// before storing it in a NUMERIC column. Reals without a fractional part, like `3.0e+5`, become integers.
+/// Hexadecimal literals, `inf` and `NaN` are left alone, as in SQLite.
+pub(crate) fn parse_numeric(text: &str) -> Option<SqliteValue> {
+    let text = text.trim();
+    if let Ok(i) = text.parse::<i64>() {
+        return Some(SqliteValue::Integer(i));
+    }
+
+    let literal = text.chars().any(|c| c.is_ascii_digit())
+        && text
+            .chars()
+            .all(|c| c.is_ascii_digit() || matches!(c, '+' | '-' | '.' | 'e' | 'E'));
+    if !literal {
+        return None;
+    }
+    let f = text.parse::<f64>().ok()?;
+    match exact_integer(f) {
+        Some(i) => Some(SqliteValue::Integer(i)),
+        None => Some(SqliteValue::Float(f)),
+    }
+}
+
+/// Gets the integer a float is equal to, if it has no fractional part and fits in an i64.
+fn exact_integer(f: f64) -> Option<i64> {
+    // 2^63 is the first float that does not fit
+    let limit = -(i64::MIN as f64);
+    if f.fract() == 0.0 && (-limit..limit).contains(&f) {
+        Some(f as i64)
+    } else {
+        None
+    }
+}
+
+/// Renders a float the way SQLite does, keeping the `.0` of whole numbers so they still read as reals.
+pub(crate) fn real_to_text(f: f64) -> String {
+    if f.is_finite() && f.fract() == 0.0 && f.abs() < 1e15 {
+        format!("{:.1}", f)
+    } else {
+        format!("{}", f)
+    }
+}
+
 /// Serializes an slice of `SqliteValue` to the specified writer.
 ///
 /// # Parameters
@@ -684,4 +795,68 @@ mod tests {
             }
         }
     }
+
+    #[test]
+    fn test_affinity_from_declared_type() {
+        let cases = [
+            ("INT", Affinity::Integer),
+            ("BIGINT", Affinity::Integer),
+            ("VARCHAR(255)", Affinity::Text),
+            ("clob", Affinity::Text),
+            ("BLOB", Affinity::Blob),
+            ("", Affinity::Blob),
+            ("DOUBLE PRECISION", Affinity::Real),
+            ("FLOAT", Affinity::Real),
+            ("DECIMAL(10,5)", Affinity::Numeric),
+            ("BOOLEAN", Affinity::Numeric),
+            // INT is checked first, so the rule order matters
+            ("CHARINT", Affinity::Integer),
+            // "FLOATING POINT" has INT in it too
+            ("FLOATING POINT", Affinity::Integer),
+        ];
+        for (declared_type, affinity) in cases {
+            assert_eq!(
+                Affinity::from_declared_type(declared_type),
+                affinity,
+                "{}",
+                declared_type
+            );
+        }
+    }
+
+    #[test]
+    fn test_affinity_apply() {
+        let text = |s: &str| SqliteValue::String(s.to_string());
+
+        let integer = SqliteValue::Integer;
+        let real = SqliteValue::Float;
+        assert_eq!(Affinity::Numeric.apply(text(" 42 ")), integer(42));
+        assert_eq!(Affinity::Numeric.apply(text("3.0e+5")), integer(300000));
+        assert_eq!(Affinity::Numeric.apply(text("2.5")), real(2.5));
+        assert_eq!(Affinity::Integer.apply(real(7.0)), integer(7));
+        assert_eq!(Affinity::Integer.apply(real(7.5)), real(7.5));
+        assert_eq!(Affinity::Numeric.apply(text("0x10")), text("0x10"));
+        assert_eq!(Affinity::Numeric.apply(text("inf")), text("inf"));
+        assert_eq!(
+            Affinity::Numeric.apply(text("9223372036854775808")),
+            real(9223372036854775808.0)
+        );
+
+        assert_eq!(Affinity::Real.apply(integer(3)), real(3.0));
+        assert_eq!(Affinity::Real.apply(text("12")), real(12.0));
+
+        assert_eq!(Affinity::Text.apply(SqliteValue::Integer(-5)), text("-5"));
+        assert_eq!(Affinity::Text.apply(real(1.0)), text("1.0"));
+        assert_eq!(Affinity::Text.apply(real(0.25)), text("0.25"));
+
+        use Affinity::{Blob, Numeric, Real, Text};
+        for affinity in [Text, Numeric, Real, Blob] {
+            assert_eq!(affinity.apply(SqliteValue::Null), SqliteValue::Null);
+            assert_eq!(
+                affinity.apply(SqliteValue::Blob(vec![1, 2])),
+                SqliteValue::Blob(vec![1, 2])
+            );
+        }
+        assert_eq!(Affinity::Blob.apply(text("42")), text("42"));
+    }
 }
@@ -82,8 +82,8 @@ pub fn encode_varint<W: Write>(value: i64, writer: &mut W) -> io::Result<usize>
 
         // The first 8 bytes will have the continuation bit set.
         // The first 7 bits are stored in the first byte, the next 7 bits in the second byte, and so on.
-        for i in 0..8 {
-            buffer[i] = 0x80 | ((uvalue >> (7 * i)) & 0x7F) as u8;
+        for (i, byte) in buffer.iter_mut().take(8).enumerate() {
+            *byte = 0x80 | ((uvalue >> (7 * i)) & 0x7F) as u8;
         }
 
         // The last byte will not have the continuation bit set.
@@ -170,7 +170,6 @@ pub fn decode_varint<R: Read>(reader: &mut R) -> io::Result<(i64, usize)> {
 ///
 /// # Returns
 /// The size of the varint in bytes.
-
 pub fn varint_size(value: i64) -> usize {
     let uvalue = value as u64;
 
@@ -0,0 +1,519 @@
+//! # Crash Recovery Tests
+//!
+//! A randomized suite that kills the database in the middle of its workloads and checks that,
+//! once reopened, it has every committed row and nothing else.
+//!
+//! The crashes are simulated by `FaultVfs`, a VFS that keeps its files in memory and can:
+//!
+//! - fail the Nth I/O operation, after which every other one fails too, like a process that died right there;
+//! - tear a page write in half, leaving only the first half of the buffer in the file;
+//! - return a short read;
+//! - lose power, dropping a random part of what was written since the last sync of each file.
+//!
+//! What survives is copied to real files and opened with `RQLite::open`, so the recovery runs exactly as it would
+//! after a real crash. Every workload is driven by a seed, and a failure prints it to replay the run.
+use rqlite_engine::storage::lock::LockKind;
+use rqlite_engine::storage::{Vfs, VfsFile};
+use rqlite_engine::{RQLite, RQLiteConfig, Record, SqliteValue};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard};
+use tempfile::tempdir;
+
+/// Small and fast xorshift generator, so a seed always replays the same run.
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        Rng(seed.wrapping_mul(0x9E37_79B9_7F4A_7C15) | 1)
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    /// Random number in `0..bound`.
+    fn below(&mut self, bound: u64) -> u64 {
+        self.next() % bound
+    }
+
+    /// `true` once every `n` times, on average.
+    fn one_in(&mut self, n: u64) -> bool {
+        self.below(n) == 0
+    }
+}
+
+/// A change made to a file since its last sync.
+enum Unsynced {
+    Write(u64, Vec<u8>),
+    Truncate(u64),
+}
+
+/// Contents of a file of the `FaultVfs`.
+#[derive(Default)]
+struct FileImage {
+    /// What the process sees.
+    current: Vec<u8>,
+    /// What is on stable storage.
+    durable: Vec<u8>,
+    /// Changes since the last sync, in order.
+    unsynced: Vec<Unsynced>,
+}
+
+fn write_image(image: &mut Vec<u8>, buffer: &[u8], offset: u64) {
+    let end = offset as usize + buffer.len();
+    if image.len() < end {
+        image.resize(end, 0);
+    }
+    image[offset as usize..end].copy_from_slice(buffer);
+}
+
+/// Faults of a `FaultVfs`, shared by all its files.
+#[derive(Default)]
+struct Faults {
+    /// I/O operations done so far.
+    operations: u64,
+    /// Operation that fails, counting from 1.
+    fail_at: Option<u64>,
+    /// Whether the process "died": every operation fails from then on.
+    crashed: bool,
+}
+
+/// A VFS that keeps its files in memory and fails when told to.
+#[derive(Clone, Default)]
+struct FaultVfs {
+    files: Arc<Mutex<HashMap<PathBuf, Arc<Mutex<FileImage>>>>>,
+    faults: Arc<Mutex<Faults>>,
+}
+
+/// What a faulty operation does before failing.
+enum Fault {
+    /// Nothing: the operation fails.
+    Fail,
+    /// Half of the buffer reaches the file.
+    TornWrite,
+    /// Part of the buffer is read.
+    ShortRead,
+}
+
+impl FaultVfs {
+    /// Makes the Nth operation from now fail.
+    fn fail_at(&self, operation: u64) {
+        let mut faults = lock(&self.faults);
+        faults.fail_at = Some(faults.operations + operation);
+    }
+
+    /// Number of I/O operations done so far.
+    fn operations(&self) -> u64 {
+        lock(&self.faults).operations
+    }
+
+    /// Simulates a power loss: every file goes back to its durable image, plus a random part of its unsynced changes.
+    /// Without `power_loss`, only the process dies and the OS keeps everything it was given.
+    ///
+    /// # Returns
+    /// The surviving contents of every file.
+    fn crash(&self, rng: &mut Rng, power_loss: bool) -> HashMap<PathBuf, Vec<u8>> {
+        lock(&self.faults).crashed = true;
+        let files = lock(&self.files);
+        files
+            .iter()
+            .map(|(path, image)| {
+                let image = lock(image);
+                if !power_loss {
+                    return (path.clone(), image.current.clone());
+                }
+                let mut contents = image.durable.clone();
+                for change in &image.unsynced {
+                    if rng.one_in(2) {
+                        continue;
+                    }
+                    match change {
+                        Unsynced::Write(offset, buffer) if rng.one_in(4) => {
+                            write_image(&mut contents, &buffer[..buffer.len() / 2], *offset)
+                        }
+                        Unsynced::Write(offset, buffer) => {
+                            write_image(&mut contents, buffer, *offset)
+                        }
+                        Unsynced::Truncate(size) => contents.resize(*size as usize, 0),
+                    }
+                }
+                (path.clone(), contents)
+            })
+            .collect()
+    }
+
+    /// Counts an operation, and tells if it has to fail.
+    fn operation(&self) -> io::Result<bool> {
+        let mut faults = lock(&self.faults);
+        if faults.crashed {
+            return Err(io::Error::other("simulated crash"));
+        }
+        faults.operations += 1;
+        if faults.fail_at == Some(faults.operations) {
+            faults.crashed = true;
+            return Ok(true);
+        }
+        Ok(false)
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+impl Vfs for FaultVfs {
+    fn open(&self, path: &Path, create: bool) -> io::Result<Arc<dyn VfsFile>> {
+        if self.operation()? {
+            return Err(io::Error::other("injected open failure"));
+        }
+        let mut files = lock(&self.files);
+        let image = if create {
+            // Creating a file is durable right away, as OsVfs syncs the directory
+            let image = Arc::new(Mutex::new(FileImage::default()));
+            files.insert(path.to_path_buf(), Arc::clone(&image));
+            image
+        } else {
+            files
+                .get(path)
+                .cloned()
+                .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no such file"))?
+        };
+        Ok(Arc::new(FaultFile {
+            vfs: self.clone(),
+            image,
+        }))
+    }
+
+    fn delete(&self, path: &Path) -> io::Result<()> {
+        if self.operation()? {
+            return Err(io::Error::other("injected delete failure"));
+        }
+        lock(&self.files)
+            .remove(path)
+            .map(|_| ())
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no such file"))
+    }
+
+    fn exists(&self, path: &Path) -> io::Result<bool> {
+        self.operation()?;
+        Ok(lock(&self.files).contains_key(path))
+    }
+}
+
+/// A file of the `FaultVfs`.
+struct FaultFile {
+    vfs: FaultVfs,
+    image: Arc<Mutex<FileImage>>,
+}
+
+impl FaultFile {
+    /// Counts an operation and picks what goes wrong with it, if anything.
+    fn fault(&self, fault: Fault) -> io::Result<Option<Fault>> {
+        Ok(self.vfs.operation()?.then_some(fault))
+    }
+}
+
+impl VfsFile for FaultFile {
+    fn read_at(&self, buffer: &mut [u8], offset: u64) -> io::Result<()> {
+        let fault = self.fault(Fault::ShortRead)?;
+        let image = lock(&self.image);
+        let start = (offset as usize).min(image.current.len());
+        let mut available = (image.current.len() - start).min(buffer.len());
+        if fault.is_some() {
+            available /= 2;
+        }
+        buffer[..available].copy_from_slice(&image.current[start..start + available]);
+        if available < buffer.len() {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "short read"));
+        }
+        Ok(())
+    }
+
+    fn write_at(&self, buffer: &[u8], offset: u64) -> io::Result<()> {
+        let fault = self.fault(Fault::TornWrite)?;
+        let mut image = lock(&self.image);
+        let buffer = match fault {
+            Some(_) => &buffer[..buffer.len() / 2],
+            None => buffer,
+        };
+        write_image(&mut image.current, buffer, offset);
+        image
+            .unsynced
+            .push(Unsynced::Write(offset, buffer.to_vec()));
+        match fault {
+            Some(_) => Err(io::Error::other("injected torn write")),
+            None => Ok(()),
+        }
+    }
+
+    fn sync(&self, _data_only: bool) -> io::Result<()> {
+        if self.fault(Fault::Fail)?.is_some() {
+            return Err(io::Error::other("injected sync failure"));
+        }
+        let mut image = lock(&self.image);
+        image.durable = image.current.clone();
+        image.unsynced.clear();
+        Ok(())
+    }
+
+    fn truncate(&self, size: u64) -> io::Result<()> {
+        if self.fault(Fault::Fail)?.is_some() {
+            return Err(io::Error::other("injected truncate failure"));
+        }
+        let mut image = lock(&self.image);
+        image.current.resize(size as usize, 0);
+        image.unsynced.push(Unsynced::Truncate(size));
+        Ok(())
+    }
+
+    fn size(&self) -> io::Result<u64> {
+        self.vfs.operation()?;
+        Ok(lock(&self.image).current.len() as u64)
+    }
+
+    fn lock(&self, _kind: LockKind, _start: u64, _len: u64) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Configuration of the crash tests: small pages with checksums, and a tiny cache so transactions spill pages.
+fn config() -> RQLiteConfig {
+    RQLiteConfig {
+        page_size: 1024,
+        buffer_pool_size: 8,
+        reserved_space: 8,
+        checksums: true,
+        ..Default::default()
+    }
+}
+
+/// Rows of the test table, by rowid.
+type Rows = BTreeMap<i64, Vec<u8>>;
+
+fn row(payload: &[u8]) -> Record {
+    Record::with_values(vec![SqliteValue::Blob(payload.to_vec())])
+}
+
+/// Result of a workload that may have crashed.
+struct Outcome {
+    /// Rows as of the last commit that returned.
+    committed: Rows,
+    /// Rows of the transaction that was committing when the crash happened, if any.
+    /// It may or may not have made it.
+    in_doubt: Option<Rows>,
+    /// Every rowid the workload touched.
+    rowids: BTreeSet<i64>,
+}
+
+/// Runs random transactions of inserts and deletes, some of them rolled back, until the workload ends or an operation fails.
+fn run_workload(db: &RQLite, table_id: u32, rng: &mut Rng, transactions: usize) -> Outcome {
+    let mut outcome = Outcome {
+        committed: Rows::new(),
+        in_doubt: None,
+        rowids: BTreeSet::new(),
+    };
+    let mut next_rowid = 1;
+
+    for _ in 0..transactions {
+        let mut rows = outcome.committed.clone();
+        let autocommit = rng.one_in(4);
+        if !autocommit && db.begin_transaction().is_err() {
+            return outcome;
+        }
+
+        let operations = if autocommit { 1 } else { 1 + rng.below(40) };
+        for _ in 0..operations {
+            let result = if rows.is_empty() || rng.below(3) > 0 {
+                let rowid = next_rowid;
+                next_rowid += 1;
+                // Some rows are bigger than a page, so they need an overflow chain
+                let size = if rng.one_in(8) {
+                    1000 + rng.below(3000)
+                } else {
+                    10 + rng.below(200)
+                };
+                let payload = vec![rng.below(256) as u8; size as usize];
+                outcome.rowids.insert(rowid);
+                rows.insert(rowid, payload.clone());
+                db.table_insert(table_id, rowid, &row(&payload)).map(|_| ())
+            } else {
+                let keys = rows.keys().copied().collect::<Vec<_>>();
+                let rowid = keys[rng.below(keys.len() as u64) as usize];
+                rows.remove(&rowid);
+                db.table_delete(table_id, rowid).map(|_| ())
+            };
+            if result.is_err() {
+                // An autocommit statement fails as a whole, so it may have been committing too
+                if autocommit {
+                    outcome.in_doubt = Some(rows);
+                }
+                return outcome;
+            }
+        }
+
+        if autocommit {
+            outcome.committed = rows;
+        } else if rng.one_in(4) {
+            if db.rollback_transaction().is_err() {
+                return outcome;
+            }
+        } else {
+            match db.commit_transaction() {
+                Ok(()) => outcome.committed = rows,
+                Err(_) => {
+                    outcome.in_doubt = Some(rows);
+                    return outcome;
+                }
+            }
+        }
+    }
+    outcome
+}
+
+/// Checks that the table has exactly the expected rows among the ones the workload touched.
+fn table_matches(
+    db: &RQLite,
+    table_id: u32,
+    rows: &Rows,
+    rowids: &BTreeSet<i64>,
+) -> io::Result<bool> {
+    for rowid in rowids {
+        let found = db
+            .table_find(table_id, *rowid)?
+            .map(|record| match record.get_value(0) {
+                Some(SqliteValue::Blob(payload)) => payload.clone(),
+                _ => Vec::new(),
+            });
+        if found.as_ref() != rows.get(rowid) {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+/// Creates a database with an empty table in a `FaultVfs`, synced, so that the crashes only hit the workload.
+fn setup(vfs: &FaultVfs) -> (RQLite, u32) {
+    let db = RQLite::create_with_vfs(Arc::new(vfs.clone()), "crash.db", Some(config())).unwrap();
+    let table_id = db.create_table().unwrap();
+    (db, table_id)
+}
+
+/// Runs a workload of a seed, crashes it at a random point and checks what is left after recovery.
+fn crash_and_recover(seed: u64, transactions: usize) {
+    // A first run without faults tells how many operations the workload does
+    let total = {
+        let vfs = FaultVfs::default();
+        let (db, table_id) = setup(&vfs);
+        let start = vfs.operations();
+        run_workload(&db, table_id, &mut Rng::new(seed), transactions);
+        vfs.operations() - start
+    };
+
+    let mut faults = Rng::new(seed ^ 0xDEAD_BEEF);
+    let vfs = FaultVfs::default();
+    let (db, table_id) = setup(&vfs);
+    vfs.fail_at(1 + faults.below(total));
+    let outcome = run_workload(&db, table_id, &mut Rng::new(seed), transactions);
+    let power_loss = !faults.one_in(4);
+    let files = vfs.crash(&mut faults, power_loss);
+    drop(db);
+
+    // Put what survived in real files, as a real crash would leave them
+    let dir = tempdir().unwrap();
+    for (path, contents) in &files {
+        fs::write(dir.path().join(path), contents).unwrap();
+    }
+    let db = RQLite::open(dir.path().join("crash.db"), Some(config()))
+        .unwrap_or_else(|e| panic!("seed {}: cannot reopen: {}", seed, e));
+
+    let committed = table_matches(&db, table_id, &outcome.committed, &outcome.rowids);
+    let in_doubt = outcome
+        .in_doubt
+        .as_ref()
+        .map(|rows| table_matches(&db, table_id, rows, &outcome.rowids));
+    match (committed, in_doubt) {
+        (Ok(true), _) | (_, Some(Ok(true))) => {}
+        (Err(e), _) => panic!("seed {}: cannot read the recovered database: {}", seed, e),
+        _ => panic!(
+            "seed {}: the recovered database does not match the committed rows (power loss: {})",
+            seed, power_loss
+        ),
+    }
+    assert!(
+        !dir.path().join("crash.db-journal").exists(),
+        "seed {}: the journal was not deleted",
+        seed
+    );
+
+    // The recovered database is fully usable
+    db.table_insert(table_id, 1_000_000, &row(b"after"))
+        .unwrap();
+    assert!(db.table_find(table_id, 1_000_000).unwrap().is_some());
+}
+
+#[test]
+fn test_crash_recovery_short_workloads() {
+    for seed in 1..=60 {
+        crash_and_recover(seed, 4);
+    }
+}
+
+#[test]
+fn test_crash_recovery_long_workloads() {
+    for seed in 1000..1020 {
+        crash_and_recover(seed, 25);
+    }
+}
+
+#[test]
+fn test_fault_vfs_power_loss() {
+    let vfs = FaultVfs::default();
+    let file = vfs.open(Path::new("file"), true).unwrap();
+    file.write_at(b"durable", 0).unwrap();
+    file.sync(false).unwrap();
+    file.write_at(b"lost", 7).unwrap();
+
+    // Without a power loss the OS keeps the unsynced write, with one it may or may not
+    assert_eq!(
+        vfs.crash(&mut Rng::new(1), false)[Path::new("file")],
+        b"durablelost"
+    );
+    assert!(file.read_at(&mut [0u8; 4], 0).is_err());
+    let mut seen = BTreeSet::new();
+    for seed in 0..32 {
+        let files = vfs.crash(&mut Rng::new(seed), true);
+        seen.insert(files[Path::new("file")].clone());
+    }
+    assert!(seen.contains(b"durable".as_slice()));
+    assert!(seen.iter().all(|contents| contents.starts_with(b"durable")));
+}
+
+#[test]
+fn test_fault_vfs_injected_faults() {
+    let vfs = FaultVfs::default();
+    let file = vfs.open(Path::new("file"), true).unwrap();
+    file.write_at(&[7u8; 8], 0).unwrap();
+
+    // A torn write leaves half of the buffer behind, and the "process" is dead afterwards
+    vfs.fail_at(1);
+    assert!(file.write_at(&[9u8; 8], 0).is_err());
+    let files = vfs.crash(&mut Rng::new(1), false);
+    assert_eq!(files[Path::new("file")], [9, 9, 9, 9, 7, 7, 7, 7]);
+    assert!(file.size().is_err());
+
+    // A short read fills only part of the buffer
+    let vfs = FaultVfs::default();
+    let file = vfs.open(Path::new("file"), true).unwrap();
+    file.write_at(&[7u8; 8], 0).unwrap();
+    vfs.fail_at(1);
+    let mut buffer = [0u8; 8];
+    let error = file.read_at(&mut buffer, 0).unwrap_err();
+    assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+    assert_eq!(buffer, [7, 7, 7, 7, 0, 0, 0, 0]);
+}
@@ -12,16 +12,23 @@
 //! 
 //! *  Although it has been designed to be thread-safe, the current implementation is not completely transaction-serializable. A more robust transaction management system is needed to ensure that concurrent transactions do not interfere with each other.
 //! 
-//! * WAL logging and journal recovery are not yet implemented, which means that the database is not durable against crashes or power failures.
-//! 
-//! * The catalog is also not durable yet, which means that the database schema (tables, indexes, etc.) is not persisted across restarts.
+//! * There is a rollback journal now (crash_recovery.rs hammers it), but no WAL yet, so readers and the writer still block each other.
 //! 
 //! I am not still a database or Rust expert so I know this can be done much better. 
 //! Anyway, I am happy with the current state of the engine and I will continue to improve it in the future.
 
-use rqlite_engine::{RQLite, RQLiteConfig, Record, SqliteValue, KeyValue};
+// The float literals below are arbitrary test data, not attempts at PI or E.
+#![allow(clippy::approx_constant)]
+
+use rqlite_engine::{Column, RQLite, RQLiteConfig, Record, SqliteValue, KeyValue};
+use rqlite_engine::page::{BTreeCell, Page, PageType, TableLeafCell};
+use rqlite_engine::storage::lock::{is_busy, timeout_handler};
+use rqlite_engine::storage::{LockLevel, Pager};
 use rqlite_engine::utils::serialization::{serialize_values};
 use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command};
+use std::time::{Duration, Instant};
 use tempfile::tempdir;
 
 /// Test basic database lifecycle - create, use, close, reopen
@@ -33,7 +40,7 @@ fn test_database_lifecycle() {
 
     // Phase 1: Create database and populate with data
     let table_id = {
-        let mut db = RQLite::create(&db_path, None).unwrap();
+        let db = RQLite::create(&db_path, None).unwrap();
         
         let table_id = db.create_table().unwrap();
         
@@ -85,7 +92,7 @@ fn test_database_lifecycle() {
 fn test_multi_table_complex_scenario() {
     let dir = tempdir().unwrap();
     let db_path = dir.path().join("multi_table_test.db");
-    let mut db = RQLite::create(db_path, None).unwrap();
+    let db = RQLite::create(db_path, None).unwrap();
 
     // Create tables for a simple e-commerce scenario
     let users_table = db.create_table().unwrap();
@@ -204,7 +211,7 @@ fn test_multi_table_complex_scenario() {
 fn test_transaction_scenarios() {
     let dir = tempdir().unwrap();
     let db_path = dir.path().join("transaction_test.db");
-    let mut db = RQLite::create(db_path, None).unwrap();
+    let db = RQLite::create(db_path, None).unwrap();
 
     let table_id = db.create_table().unwrap();
     
@@ -279,12 +286,68 @@ fn test_transaction_scenarios() {
     }
 }
 
+/// Test that rolling back a large transaction restores every page, including the ones spilled from a small cache
+#[test]
+fn test_rollback_large_transaction() {
+    let dir = tempdir().unwrap();
+    let db_path = dir.path().join("rollback_test.db");
+    let config = RQLiteConfig {
+        buffer_pool_size: 8,
+        ..Default::default()
+    };
+    let db = RQLite::create(db_path, Some(config)).unwrap();
+    let table_id = db.create_table().unwrap();
+
+    let make_record = |rowid: i64, label: &str| {
+        Record::with_values(vec![
+            SqliteValue::Integer(rowid),
+            SqliteValue::String(format!("{} record {} with some padding to fill the pages", label, rowid)),
+        ])
+    };
+
+    for rowid in 1..=100 {
+        db.table_insert(table_id, rowid, &make_record(rowid, "Committed")).unwrap();
+    }
+    db.flush().unwrap();
+    let page_count = db.page_count().unwrap();
+    let root_page = db.table_root_page(table_id).unwrap();
+
+    // Enough rows to split the tree several times and overflow the cache
+    db.begin_transaction().unwrap();
+    for rowid in 101..=1000 {
+        db.table_insert(table_id, rowid, &make_record(rowid, "Uncommitted")).unwrap();
+    }
+    for rowid in 1..=50 {
+        db.table_delete(table_id, rowid).unwrap();
+    }
+    assert!(db.page_count().unwrap() > page_count);
+    db.rollback_transaction().unwrap();
+
+    assert_eq!(db.page_count().unwrap(), page_count);
+    assert_eq!(db.table_root_page(table_id).unwrap(), root_page);
+    for rowid in 1..=100 {
+        let record = db.table_find(table_id, rowid).unwrap().unwrap();
+        match &record.values[1] {
+            SqliteValue::String(s) => assert!(s.starts_with("Committed")),
+            _ => panic!("Expected string"),
+        }
+    }
+    for rowid in 101..=1000 {
+        assert!(db.table_find(table_id, rowid).unwrap().is_none());
+    }
+
+    // The table keeps working after the rollback
+    db.table_insert(table_id, 101, &make_record(101, "Committed")).unwrap();
+    assert!(db.table_find(table_id, 101).unwrap().is_some());
+    assert_eq!(db.page_count().unwrap(), page_count);
+}
+
 /// Test large dataset operations and B-Tree splitting
 #[test]
 fn test_large_dataset_operations() {
     let dir = tempdir().unwrap();
     let db_path = dir.path().join("large_dataset_test.db");
-    let mut db = RQLite::create(db_path, None).unwrap();
+    let db = RQLite::create(db_path, None).unwrap();
 
     let table_id = db.create_table().unwrap();
     let index_id = db.create_index(table_id).unwrap();
@@ -382,7 +445,7 @@ fn test_large_dataset_operations() {
 fn test_error_handling_scenarios() {
     let dir = tempdir().unwrap();
     let db_path = dir.path().join("error_test.db");
-    let mut db = RQLite::create(db_path, None).unwrap();
+    let db = RQLite::create(db_path, None).unwrap();
 
     // Test operations on non-existent tables/indexes
     let non_existent_table = 999;
@@ -432,7 +495,7 @@ fn test_error_handling_scenarios() {
 fn test_data_types_and_serialization() {
     let dir = tempdir().unwrap();
     let db_path = dir.path().join("data_types_test.db");
-    let mut db = RQLite::create(db_path, None).unwrap();
+    let db = RQLite::create(db_path, None).unwrap();
 
     let table_id = db.create_table().unwrap();
 
@@ -525,9 +588,10 @@ fn test_configuration_options() {
         reserved_space: 128,
         max_payload_fraction: 200,
         min_payload_fraction: 64,
+        ..Default::default()
     };
 
-    let mut db = RQLite::create(db_path, Some(config.clone())).unwrap();
+    let db = RQLite::create(db_path, Some(config.clone())).unwrap();
 
     // Verify configuration was applied
     assert_eq!(db.config().page_size, 8192);
@@ -560,7 +624,7 @@ fn test_memory_pressure() {
         ..Default::default()
     };
     
-    let mut db = RQLite::create(db_path, Some(config)).unwrap();
+    let db = RQLite::create(db_path, Some(config)).unwrap();
     let table_id = db.create_table().unwrap();
 
     // Insert many records with large payloads to force buffer evictions
@@ -600,6 +664,38 @@ fn test_memory_pressure() {
     }
 }
 
+/// Rows with an overflow chain whose leaves are evicted dirty, written back and read again from the file.
+#[test]
+fn test_memory_pressure_overflow_rows() {
+    for page_size in [512, 1024] {
+        let dir = tempdir().unwrap();
+        let config = RQLiteConfig {
+            page_size,
+            buffer_pool_size: 64,
+            ..Default::default()
+        };
+        let db = RQLite::create(dir.path().join("evict_overflow.db"), Some(config)).unwrap();
+        let table_id = db.create_table().unwrap();
+
+        let blob = |rowid: i64| vec![rowid as u8; 1500 + (rowid as usize * 53) % 1500];
+        for rowid in 1..=30 {
+            let record = Record::with_values(vec![SqliteValue::Blob(blob(rowid))]);
+            db.table_insert(table_id, rowid, &record).unwrap();
+        }
+
+        for rowid in 1..=30 {
+            let found = db.table_find(table_id, rowid).unwrap();
+            assert_eq!(
+                found.map(|record| record.values),
+                Some(vec![SqliteValue::Blob(blob(rowid))]),
+                "row {} with {}-byte pages",
+                rowid,
+                page_size
+            );
+        }
+    }
+}
+
 /// Test overflow page handling. This test simulates inserting records that exceed the page size,
 /// There seems to be issues in the current btree splitting implementaton for overflow pages, because if a 
 /// page exceeds the page size with a single record, it cannot be split and a new overflow page should be created.
@@ -607,7 +703,7 @@ fn test_memory_pressure() {
 fn test_overflow_pages() {
     let dir = tempdir().unwrap();
     let db_path = dir.path().join("overflow_test.db");
-    let mut db = RQLite::create(db_path, None).unwrap();
+    let db = RQLite::create(db_path, None).unwrap();
 
     let table_id = db.create_table().unwrap();
 
@@ -652,7 +748,7 @@ fn test_overflow_pages() {
 fn test_complex_index_operations() {
     let dir = tempdir().unwrap();
     let db_path = dir.path().join("complex_index_test.db");
-    let mut db = RQLite::create(db_path, None).unwrap();
+    let db = RQLite::create(db_path, None).unwrap();
 
     let table_id = db.create_table().unwrap();
     let index_id = db.create_index(table_id).unwrap();
@@ -722,7 +818,7 @@ fn test_recovery_scenarios() {
 
     // Phase 1: Create database and start transaction
     {
-        let mut db = RQLite::create(&db_path, None).unwrap();
+        let db = RQLite::create(&db_path, None).unwrap();
         let table_id = db.create_table().unwrap();
 
         // Insert some committed data
@@ -768,9 +864,10 @@ fn test_comprehensive_integration() {
         reserved_space: 32,
         max_payload_fraction: 255,
         min_payload_fraction: 32,
+        ..Default::default()
     };
     
-    let mut db = RQLite::create(db_path, Some(config)).unwrap();
+    let db = RQLite::create(db_path, Some(config)).unwrap();
 
     // Create multiple tables and indexes
     let customers_table = db.create_table().unwrap();
@@ -888,4 +985,185 @@ fn test_comprehensive_integration() {
     println!("Comprehensive integration test completed successfully!");
     println!("Final database size: {} pages", page_count);
     println!("Total orders created: {}", order_id - 1);
-}
\ No newline at end of file
+}
+/// Spawns this same test binary to run `lock_child_process` in a separate process.
+fn spawn_lock_child(role: &str, db_path: &Path, iterations: u32) -> Child {
+    Command::new(std::env::current_exe().unwrap())
+        .args(["lock_child_process", "--exact", "--nocapture", "--test-threads=1"])
+        .env("RQLITE_LOCK_CHILD", role)
+        .env("RQLITE_LOCK_DB", db_path)
+        .env("RQLITE_LOCK_ITERATIONS", iterations.to_string())
+        .spawn()
+        .unwrap()
+}
+
+/// Waits until a file used as a signal between processes exists.
+fn wait_for_file(path: &Path) {
+    let start = Instant::now();
+    while !path.exists() {
+        assert!(start.elapsed() < Duration::from_secs(10), "Timed out waiting for {:?}", path);
+        std::thread::sleep(Duration::from_millis(5));
+    }
+}
+
+/// Counts the cells of a B-Tree page.
+fn cell_count(pager: &Pager, page_number: u32) -> u16 {
+    pager.begin_read().unwrap();
+    let count = pager
+        .get_page_callback(page_number, None, |page| match page {
+            Page::BTree(btree_page) => btree_page.header.cell_count,
+            _ => panic!("Expected BTree page"),
+        })
+        .unwrap();
+    pager.end_read().unwrap();
+    count
+}
+
+/// Child side of the multi-process tests. It does nothing unless spawned by `spawn_lock_child`.
+#[test]
+fn lock_child_process() {
+    let Ok(role) = std::env::var("RQLITE_LOCK_CHILD") else {
+        return;
+    };
+    let db_path = PathBuf::from(std::env::var("RQLITE_LOCK_DB").unwrap());
+    let iterations: u32 = std::env::var("RQLITE_LOCK_ITERATIONS").unwrap().parse().unwrap();
+
+    let pager = Pager::open(&db_path, None).unwrap();
+    pager
+        .set_busy_handler(Some(timeout_handler(Duration::from_secs(10))))
+        .unwrap();
+
+    match role.as_str() {
+        // Keeps a write transaction open until the parent tells us to commit it
+        "hold_reserved" => {
+            pager.begin_transaction().unwrap();
+            pager
+                .get_page_mut_callback(2, None, |page| match page {
+                    Page::BTree(btree_page) => btree_page.add_cell(BTreeCell::TableLeaf(TableLeafCell {
+                        payload_size: 4,
+                        row_id: 1,
+                        payload: vec![1, 2, 3, 4],
+                        overflow_page: None,
+                    })),
+                    _ => panic!("Expected BTree page"),
+                })
+                .unwrap();
+
+            std::fs::write(db_path.with_extension("ready"), b"").unwrap();
+            wait_for_file(&db_path.with_extension("release"));
+            pager.commit_transaction().unwrap();
+        }
+        // Increments the user version of the header, one transaction at a time
+        "increment" => {
+            for _ in 0..iterations {
+                pager.begin_transaction().unwrap();
+                let mut header = pager.get_header().unwrap();
+                header.user_version += 1;
+                pager.update_header(&header).unwrap();
+                pager.commit_transaction().unwrap();
+            }
+        }
+        // Adds a column to the first table through another connection
+        "add_column" => {
+            drop(pager);
+            let db = RQLite::open(&db_path, None).unwrap();
+            let score = Column::new("score", "INTEGER").default_value(SqliteValue::Integer(5));
+            db.add_column(1, score).unwrap();
+            db.close().unwrap();
+        }
+        _ => panic!("Unknown child role {}", role),
+    }
+}
+
+/// Test that the lock states are honoured across processes, and that readers see the changes of other processes
+#[test]
+fn test_multi_process_locking() {
+    let dir = tempdir().unwrap();
+    let db_path = dir.path().join("locking_test.db");
+
+    let pager = Pager::create(&db_path, 4096, None, 0).unwrap();
+    let page_number = pager.create_btree_page(PageType::TableLeaf, None).unwrap();
+    assert_eq!(page_number, 2);
+    pager.flush().unwrap();
+    assert_eq!(pager.lock_level().unwrap(), LockLevel::Unlocked);
+
+    // Cache the empty page before the child changes it
+    assert_eq!(cell_count(&pager, page_number), 0);
+
+    let mut child = spawn_lock_child("hold_reserved", &db_path, 0);
+    wait_for_file(&db_path.with_extension("ready"));
+
+    // The child is the only allowed writer, but we can still read the committed state
+    let error = pager.begin_transaction().unwrap_err();
+    assert!(is_busy(&error), "Expected a busy error, got {:?}", error);
+    assert_eq!(cell_count(&pager, page_number), 0);
+    assert_eq!(pager.lock_level().unwrap(), LockLevel::Unlocked);
+
+    std::fs::write(db_path.with_extension("release"), b"").unwrap();
+    assert!(child.wait().unwrap().success());
+
+    // The change counter tells us our cached copy of the page is stale
+    assert_eq!(cell_count(&pager, page_number), 1);
+
+    // The file is free again
+    pager.begin_transaction().unwrap();
+    pager.commit_transaction().unwrap();
+}
+
+/// Test that concurrent writer processes are serialized by the locks and the busy handler
+#[test]
+fn test_multi_process_writers() {
+    let dir = tempdir().unwrap();
+    let db_path = dir.path().join("writers_test.db");
+    let processes = 4;
+    let iterations = 25;
+
+    let pager = Pager::create(&db_path, 4096, None, 0).unwrap();
+    pager.flush().unwrap();
+
+    let children: Vec<_> = (0..processes)
+        .map(|_| spawn_lock_child("increment", &db_path, iterations))
+        .collect();
+    for mut child in children {
+        assert!(child.wait().unwrap().success());
+    }
+
+    // No increment can be lost if the writers never overlapped
+    assert_eq!(pager.get_header().unwrap().user_version, processes * iterations);
+}
+
+#[test]
+fn test_schema_change_from_another_process() {
+    let dir = tempdir().unwrap();
+    let db_path = dir.path().join("schema_cookie_test.db");
+
+    let db = RQLite::create(&db_path, None).unwrap();
+    let table_id = db
+        .create_table_with_columns(vec![Column::new("name", "TEXT")])
+        .unwrap();
+    assert_eq!(table_id, 1);
+    let first = Record::with_values(vec![SqliteValue::String("ann".to_string())]);
+    db.table_insert(table_id, 1, &first).unwrap();
+
+    let mut child = spawn_lock_child("add_column", &db_path, 0);
+    assert!(child.wait().unwrap().success());
+
+    // The schema cookie changed, so the next transaction reloads the catalog with the new column
+    let second = Record::with_values(vec![
+        SqliteValue::String("bob".to_string()),
+        SqliteValue::Integer(9),
+    ]);
+    db.table_insert(table_id, 2, &second).unwrap();
+    assert_eq!(
+        db.table_find(table_id, 1).unwrap().unwrap().values,
+        vec![
+            SqliteValue::String("ann".to_string()),
+            SqliteValue::Integer(5)
+        ]
+    );
+    assert_eq!(db.table_find(table_id, 2).unwrap().unwrap(), second);
+    assert_eq!(
+        db.table_schema(table_id).unwrap().unwrap().columns().len(),
+        2
+    );
+}